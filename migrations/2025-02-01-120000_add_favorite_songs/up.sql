@@ -0,0 +1,10 @@
+CREATE TABLE
+    player_favorite_songs (
+        player_id INTEGER NOT NULL REFERENCES players (id) ON DELETE CASCADE,
+        song_id INTEGER NOT NULL REFERENCES songs (id) ON DELETE CASCADE,
+        position SMALLINT NOT NULL CHECK (position >= 0 AND position < 5),
+        created_at TIMESTAMPTZ(3) NOT NULL DEFAULT now(),
+        PRIMARY KEY (player_id, song_id)
+    );
+
+CREATE UNIQUE INDEX favorite_songs_unique_position ON player_favorite_songs (player_id, position);
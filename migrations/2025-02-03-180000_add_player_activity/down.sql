@@ -0,0 +1,2 @@
+DROP INDEX players_last_played_at;
+ALTER TABLE players DROP last_played_at, DROP last_seen_at;
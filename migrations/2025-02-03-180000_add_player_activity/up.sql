@@ -0,0 +1,14 @@
+ALTER TABLE players
+ADD last_played_at TIMESTAMPTZ(3),
+ADD last_seen_at TIMESTAMPTZ(3);
+
+UPDATE players
+SET last_played_at = latest.submitted_at
+FROM (
+    SELECT player_id, MAX(submitted_at) AS submitted_at
+    FROM scores
+    GROUP BY player_id
+) AS latest
+WHERE players.id = latest.player_id;
+
+CREATE INDEX players_last_played_at ON players (last_played_at);
@@ -0,0 +1,8 @@
+-- Skill point changes that couldn't be applied to the Redis leaderboard yet
+CREATE TABLE
+    pending_leaderboard_deltas (
+        id SERIAL PRIMARY KEY,
+        player_id INTEGER NOT NULL REFERENCES players (id) ON DELETE CASCADE,
+        delta INTEGER NOT NULL,
+        created_at TIMESTAMPTZ(3) NOT NULL DEFAULT now()
+    );
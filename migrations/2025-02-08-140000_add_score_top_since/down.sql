@@ -0,0 +1 @@
+ALTER TABLE scores DROP top_since;
@@ -0,0 +1,6 @@
+-- When the score's player took the top spot of the song/league, kept through self-improvements.
+-- For existing scores, the best approximation we have is when they were submitted.
+ALTER TABLE scores
+ADD top_since TIMESTAMPTZ(3) NOT NULL DEFAULT now();
+
+UPDATE scores SET top_since = submitted_at;
@@ -0,0 +1,10 @@
+CREATE TABLE
+    song_tag_samples (
+        id SERIAL PRIMARY KEY,
+        song_id INTEGER NOT NULL REFERENCES songs (id) ON DELETE CASCADE,
+        player_id INTEGER NOT NULL REFERENCES players (id) ON DELETE CASCADE,
+        title TEXT NOT NULL,
+        artist TEXT NOT NULL,
+        created_at TIMESTAMPTZ(3) NOT NULL DEFAULT now(),
+        UNIQUE (song_id, title, artist)
+    );
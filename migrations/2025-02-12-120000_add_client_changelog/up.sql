@@ -0,0 +1,7 @@
+CREATE TABLE
+    client_changelog (
+        id SERIAL PRIMARY KEY,
+        version TEXT NOT NULL,
+        body TEXT NOT NULL,
+        published_at TIMESTAMPTZ(3) NOT NULL DEFAULT now()
+    );
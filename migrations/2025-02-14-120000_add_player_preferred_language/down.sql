@@ -0,0 +1 @@
+ALTER TABLE players DROP COLUMN preferred_language;
@@ -0,0 +1,2 @@
+-- NULL means the player hasn't picked a language and the game didn't tell us theirs either
+ALTER TABLE players ADD COLUMN preferred_language SMALLINT;
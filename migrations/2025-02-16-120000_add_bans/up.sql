@@ -0,0 +1,12 @@
+CREATE TABLE
+    bans (
+        id SERIAL PRIMARY KEY,
+        player_id INTEGER NOT NULL REFERENCES players (id) ON DELETE CASCADE,
+        reason TEXT NOT NULL,
+        issued_by INTEGER REFERENCES players (id) ON DELETE SET NULL,
+        issued_at TIMESTAMPTZ(3) NOT NULL DEFAULT now(),
+        -- NULL means the ban is permanent
+        expires_at TIMESTAMPTZ(3)
+    );
+
+CREATE INDEX bans_player_id_idx ON bans (player_id);
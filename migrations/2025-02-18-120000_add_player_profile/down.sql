@@ -0,0 +1,3 @@
+ALTER TABLE players
+    DROP COLUMN bio,
+    DROP COLUMN profile_links;
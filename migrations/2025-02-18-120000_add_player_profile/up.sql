@@ -0,0 +1,3 @@
+ALTER TABLE players
+    ADD COLUMN bio TEXT,
+    ADD COLUMN profile_links TEXT[];
@@ -0,0 +1,10 @@
+CREATE TABLE
+    player_name_history (
+        id SERIAL PRIMARY KEY,
+        player_id INTEGER NOT NULL REFERENCES players (id) ON DELETE CASCADE,
+        old_name VARCHAR(32) NOT NULL,
+        new_name VARCHAR(32) NOT NULL,
+        changed_at TIMESTAMPTZ(3) NOT NULL DEFAULT now()
+    );
+
+CREATE INDEX player_name_history_player_id_idx ON player_name_history (player_id, changed_at DESC);
@@ -0,0 +1 @@
+DROP INDEX rivalries_rival_id_idx;
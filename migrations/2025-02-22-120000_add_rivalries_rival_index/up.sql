@@ -0,0 +1,2 @@
+-- challenger_id is already covered by the primary key
+CREATE INDEX rivalries_rival_id_idx ON rivalries (rival_id);
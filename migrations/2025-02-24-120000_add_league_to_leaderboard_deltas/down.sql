@@ -0,0 +1 @@
+ALTER TABLE pending_leaderboard_deltas DROP COLUMN league;
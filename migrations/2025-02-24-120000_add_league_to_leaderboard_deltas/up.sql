@@ -0,0 +1,2 @@
+-- NULL for deltas queued before per-league leaderboards, which only apply to the global one
+ALTER TABLE pending_leaderboard_deltas ADD COLUMN league SMALLINT;
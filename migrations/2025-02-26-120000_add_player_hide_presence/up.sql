@@ -0,0 +1,2 @@
+ALTER TABLE players
+    ADD COLUMN hide_presence BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,2 @@
+ALTER TABLE songs
+    DROP COLUMN excluded_from_rankings;
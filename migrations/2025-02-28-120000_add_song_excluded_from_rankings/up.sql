@@ -0,0 +1,2 @@
+ALTER TABLE songs
+    ADD COLUMN excluded_from_rankings BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,19 @@
+CREATE TABLE
+    score_history (
+        id SERIAL PRIMARY KEY,
+        score_id INTEGER NOT NULL REFERENCES scores (id) ON DELETE CASCADE,
+        score INTEGER NOT NULL,
+        submitted_at TIMESTAMPTZ(3) NOT NULL,
+        track_shape INTEGER[] NOT NULL,
+        xstats INTEGER[] NOT NULL,
+        density INTEGER NOT NULL,
+        vehicle SMALLINT NOT NULL,
+        feats TEXT[] NOT NULL,
+        song_length INTEGER NOT NULL,
+        gold_threshold INTEGER NOT NULL,
+        iss INTEGER NOT NULL,
+        isj INTEGER NOT NULL,
+        archived_at TIMESTAMPTZ(3) NOT NULL DEFAULT now()
+    );
+
+CREATE INDEX score_history_score_id_idx ON score_history (score_id, submitted_at);
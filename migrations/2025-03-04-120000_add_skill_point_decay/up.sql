@@ -0,0 +1,6 @@
+CREATE TABLE
+    skill_point_decay (
+        player_id INTEGER PRIMARY KEY REFERENCES players (id) ON DELETE CASCADE,
+        multiplier DOUBLE PRECISION NOT NULL,
+        applied_at TIMESTAMPTZ(3) NOT NULL DEFAULT now()
+    );
@@ -0,0 +1,11 @@
+CREATE TABLE
+    notifications (
+        id SERIAL PRIMARY KEY,
+        player_id INTEGER NOT NULL REFERENCES players (id) ON DELETE CASCADE,
+        kind SMALLINT NOT NULL,
+        payload JSONB NOT NULL,
+        created_at TIMESTAMPTZ(3) NOT NULL DEFAULT now(),
+        read_at TIMESTAMPTZ(3)
+    );
+
+CREATE INDEX notifications_player_id_idx ON notifications (player_id, created_at DESC);
@@ -0,0 +1 @@
+DROP INDEX notifications_dethroned_song_idx;
@@ -0,0 +1,8 @@
+-- For finding out in-game whether a rival recently took the player's record on a song
+CREATE INDEX notifications_dethroned_song_idx ON notifications (
+    player_id,
+    ((payload ->> 'songId')::INTEGER),
+    created_at DESC
+)
+WHERE
+    kind = 0;
@@ -0,0 +1,17 @@
+CREATE TABLE
+    rejected_scores (
+        id SERIAL PRIMARY KEY,
+        player_id INTEGER NOT NULL REFERENCES players (id) ON DELETE CASCADE,
+        song_id INTEGER NOT NULL REFERENCES songs (id) ON DELETE CASCADE,
+        league SMALLINT NOT NULL,
+        score INTEGER NOT NULL,
+        vehicle SMALLINT NOT NULL,
+        gold_threshold INTEGER NOT NULL,
+        song_length INTEGER NOT NULL,
+        track_shape_length INTEGER NOT NULL,
+        feats TEXT[] NOT NULL,
+        reason TEXT NOT NULL,
+        rejected_at TIMESTAMPTZ(3) NOT NULL DEFAULT now()
+    );
+
+CREATE INDEX rejected_scores_rejected_at_idx ON rejected_scores (rejected_at DESC);
@@ -0,0 +1,17 @@
+CREATE TABLE
+    score_reports (
+        id SERIAL PRIMARY KEY,
+        -- Kept when the score is deleted, so the resolution stays on record
+        score_id INTEGER REFERENCES scores (id) ON DELETE SET NULL,
+        reporter_id INTEGER NOT NULL REFERENCES players (id) ON DELETE CASCADE,
+        reason VARCHAR(500) NOT NULL,
+        created_at TIMESTAMPTZ(3) NOT NULL DEFAULT now(),
+        resolved_at TIMESTAMPTZ(3),
+        resolved_by INTEGER REFERENCES players (id) ON DELETE SET NULL,
+        score_deleted BOOLEAN NOT NULL DEFAULT FALSE,
+        UNIQUE (score_id, reporter_id)
+    );
+
+CREATE INDEX score_reports_open_idx ON score_reports (created_at)
+WHERE
+    resolved_at IS NULL;
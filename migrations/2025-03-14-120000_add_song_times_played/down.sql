@@ -0,0 +1,3 @@
+DROP INDEX songs_times_played_idx;
+ALTER TABLE songs
+    DROP COLUMN times_played;
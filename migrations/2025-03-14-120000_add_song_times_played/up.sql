@@ -0,0 +1,10 @@
+-- Sum of the play counts of the song's scores, kept up to date on submission and deletion
+ALTER TABLE songs
+    ADD COLUMN times_played BIGINT NOT NULL DEFAULT 0;
+
+UPDATE songs
+SET times_played = counted.plays
+FROM (SELECT song_id, SUM(play_count) AS plays FROM scores GROUP BY song_id) counted
+WHERE songs.id = counted.song_id;
+
+CREATE INDEX songs_times_played_idx ON songs (times_played DESC, id);
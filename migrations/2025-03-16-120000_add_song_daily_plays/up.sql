@@ -0,0 +1,11 @@
+-- Plays per song and UTC day, for rankings over the last week or month.
+-- Rows older than the longest ranking period are pruned.
+CREATE TABLE
+    song_daily_plays (
+        song_id INTEGER NOT NULL REFERENCES songs (id) ON DELETE CASCADE,
+        day DATE NOT NULL,
+        plays INTEGER NOT NULL DEFAULT 1,
+        PRIMARY KEY (song_id, day)
+    );
+
+CREATE INDEX song_daily_plays_day_idx ON song_daily_plays (day);
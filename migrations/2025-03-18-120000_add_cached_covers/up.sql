@@ -0,0 +1,4 @@
+-- File names of our copies of the covers, in the [media] cover directory
+ALTER TABLE extra_song_info
+    ADD COLUMN cover_file TEXT,
+    ADD COLUMN cover_file_small TEXT;
@@ -0,0 +1,10 @@
+-- Aliases stay normalized, they were lowercased before and still match the same way
+DROP INDEX ext_info_musicbrainz_normalized_idx;
+ALTER TABLE extra_song_info
+    DROP COLUMN musicbrainz_title_normalized,
+    DROP COLUMN musicbrainz_artist_normalized;
+
+DROP INDEX songs_normalized_idx;
+ALTER TABLE songs
+    DROP COLUMN title_normalized,
+    DROP COLUMN artist_normalized;
@@ -0,0 +1,47 @@
+-- Titles and artists the way songs are matched, see util::normalize.
+-- This mirrors it closely enough for existing rows, `normalize-songs` makes them exact.
+CREATE FUNCTION pg_temp.normalize_tag(tag TEXT) RETURNS TEXT AS $$
+    SELECT trim(regexp_replace(
+        replace(
+            translate(
+                lower(normalize(tag, NFC)),
+                '‘’‚‛′´`“”„‟″‐‑‒–—―−',
+                repeat(chr(39), 7) || repeat('"', 5) || repeat('-', 7)
+            ),
+            '&', ' and '
+        ),
+        '\s+', ' ', 'g'
+    ))
+$$ LANGUAGE SQL IMMUTABLE;
+
+ALTER TABLE songs
+    ADD COLUMN title_normalized TEXT,
+    ADD COLUMN artist_normalized TEXT;
+UPDATE songs
+SET title_normalized = pg_temp.normalize_tag(title),
+    artist_normalized = pg_temp.normalize_tag(artist);
+ALTER TABLE songs
+    ALTER COLUMN title_normalized SET NOT NULL,
+    ALTER COLUMN artist_normalized SET NOT NULL;
+CREATE INDEX songs_normalized_idx ON songs (title_normalized, artist_normalized);
+
+ALTER TABLE extra_song_info
+    ADD COLUMN musicbrainz_title_normalized TEXT,
+    ADD COLUMN musicbrainz_artist_normalized TEXT;
+UPDATE extra_song_info
+SET musicbrainz_title_normalized = pg_temp.normalize_tag(musicbrainz_title),
+    musicbrainz_artist_normalized = pg_temp.normalize_tag(musicbrainz_artist);
+CREATE INDEX ext_info_musicbrainz_normalized_idx
+    ON extra_song_info (musicbrainz_title_normalized, musicbrainz_artist_normalized);
+
+-- Aliases are only used for matching, so they're stored normalized
+UPDATE extra_song_info
+SET aliases_title = ARRAY(
+    SELECT pg_temp.normalize_tag(alias) FROM unnest(aliases_title) WITH ORDINALITY AS a(alias, n) ORDER BY n
+)
+WHERE aliases_title IS NOT NULL;
+UPDATE extra_song_info
+SET aliases_artist = ARRAY(
+    SELECT pg_temp.normalize_tag(alias) FROM unnest(aliases_artist) WITH ORDINALITY AS a(alias, n) ORDER BY n
+)
+WHERE aliases_artist IS NOT NULL;
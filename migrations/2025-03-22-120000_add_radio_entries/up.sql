@@ -0,0 +1,17 @@
+-- Audiosurf Radio lineup, used to be read from WavebreakerRadio.toml.
+-- Title and artist are what the game shows, they don't have to match the song's tags.
+CREATE TABLE
+    radio_entries (
+        id SERIAL PRIMARY KEY,
+        song_id INTEGER NOT NULL REFERENCES songs (id) ON DELETE CASCADE,
+        title TEXT NOT NULL,
+        artist TEXT NOT NULL,
+        cgr_url TEXT NOT NULL,
+        external_url TEXT NOT NULL,
+        sort_order INTEGER NOT NULL DEFAULT 0,
+        active BOOLEAN NOT NULL DEFAULT TRUE,
+        created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+    );
+
+CREATE INDEX radio_entries_sort_order_idx ON radio_entries (sort_order, id);
+CREATE INDEX radio_entries_song_id_idx ON radio_entries (song_id);
@@ -0,0 +1,4 @@
+ALTER TABLE radio_entries
+    DROP CONSTRAINT radio_entries_availability_check,
+    DROP COLUMN available_from,
+    DROP COLUMN available_until;
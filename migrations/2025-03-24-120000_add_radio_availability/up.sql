@@ -0,0 +1,5 @@
+-- Entries are only on the radio between these, either can be left out
+ALTER TABLE radio_entries
+    ADD COLUMN available_from TIMESTAMPTZ,
+    ADD COLUMN available_until TIMESTAMPTZ,
+    ADD CONSTRAINT radio_entries_availability_check CHECK (available_from < available_until);
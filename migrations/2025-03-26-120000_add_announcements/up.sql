@@ -0,0 +1,13 @@
+-- Server news for the website, the newest one is also shown in game before playing a song.
+CREATE TABLE
+    announcements (
+        id SERIAL PRIMARY KEY,
+        title TEXT NOT NULL,
+        body TEXT NOT NULL,
+        published_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+        expires_at TIMESTAMPTZ,
+        pinned BOOLEAN NOT NULL DEFAULT FALSE,
+        CONSTRAINT announcements_expiry_check CHECK (published_at < expires_at)
+    );
+
+CREATE INDEX announcements_published_at_idx ON announcements (published_at DESC);
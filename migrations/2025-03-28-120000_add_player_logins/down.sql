@@ -0,0 +1,3 @@
+ALTER TABLE players
+    DROP COLUMN last_login_at,
+    DROP COLUMN previous_login_at;
@@ -0,0 +1,4 @@
+-- Unlike last_seen_at, only game logins count, so the news can say what's new since the one before
+ALTER TABLE players
+    ADD COLUMN last_login_at TIMESTAMPTZ,
+    ADD COLUMN previous_login_at TIMESTAMPTZ;
@@ -0,0 +1,4 @@
+ALTER TABLE players
+    DROP COLUMN disable_profile_shouts;
+
+DROP TABLE profile_shouts;
@@ -0,0 +1,13 @@
+CREATE TABLE profile_shouts (
+    id SERIAL PRIMARY KEY,
+    profile_owner_id INTEGER NOT NULL REFERENCES players (id) ON DELETE CASCADE,
+    author_id INTEGER NOT NULL REFERENCES players (id) ON DELETE CASCADE,
+    posted_at TIMESTAMPTZ(3) NOT NULL DEFAULT now(),
+    content VARCHAR(240) NOT NULL
+);
+
+CREATE INDEX profile_shouts_profile_owner_id_idx ON profile_shouts (profile_owner_id, posted_at DESC);
+CREATE INDEX profile_shouts_author_id_idx ON profile_shouts (author_id);
+
+ALTER TABLE players
+    ADD COLUMN disable_profile_shouts BOOLEAN NOT NULL DEFAULT false;
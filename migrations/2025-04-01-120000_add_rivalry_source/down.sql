@@ -0,0 +1,2 @@
+ALTER TABLE rivalries
+    DROP COLUMN source;
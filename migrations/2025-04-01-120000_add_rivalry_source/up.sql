@@ -0,0 +1,4 @@
+-- 0 = added by the player, 1 = added by Steam friend sync
+-- Existing rivalries count as added by the player, so they're never pruned
+ALTER TABLE rivalries
+    ADD COLUMN source SMALLINT NOT NULL DEFAULT 0;
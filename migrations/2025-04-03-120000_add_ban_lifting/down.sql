@@ -0,0 +1,7 @@
+DELETE FROM bans
+WHERE
+    lifted_at IS NOT NULL;
+
+ALTER TABLE bans
+    DROP COLUMN lifted_by,
+    DROP COLUMN lifted_at;
@@ -0,0 +1,4 @@
+-- Lifted bans are kept, so moderators can see who was banned before
+ALTER TABLE bans
+    ADD COLUMN lifted_at TIMESTAMPTZ(3),
+    ADD COLUMN lifted_by INTEGER REFERENCES players (id) ON DELETE SET NULL;
@@ -0,0 +1,6 @@
+DROP TRIGGER touch_song ON extra_song_info;
+DROP FUNCTION touch_song_of_extra_info();
+
+DROP TRIGGER set_updated_at ON songs;
+ALTER TABLE songs
+    DROP COLUMN updated_at;
@@ -0,0 +1,26 @@
+-- When anything the search index holds about a song last changed, so the scheduled search sync can skip the rest.
+-- Play counts change with every score and aren't searched, so they don't count.
+ALTER TABLE songs
+    ADD COLUMN updated_at TIMESTAMPTZ(3) NOT NULL DEFAULT now();
+CREATE INDEX songs_updated_at_idx ON songs (updated_at);
+CREATE TRIGGER set_updated_at
+    BEFORE UPDATE OF title, artist, modifiers, excluded_from_rankings ON songs
+    FOR EACH ROW EXECUTE PROCEDURE diesel_set_updated_at();
+
+-- MusicBrainz names and aliases are searched too, so changing them counts as a change of the song
+CREATE FUNCTION touch_song_of_extra_info() RETURNS trigger AS $$
+BEGIN
+    IF TG_OP <> 'INSERT' THEN
+        UPDATE songs SET updated_at = current_timestamp WHERE id = OLD.song_id;
+    END IF;
+    IF TG_OP <> 'DELETE' THEN
+        UPDATE songs SET updated_at = current_timestamp WHERE id = NEW.song_id;
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+CREATE TRIGGER touch_song
+    AFTER INSERT OR DELETE
+        OR UPDATE OF song_id, musicbrainz_title, musicbrainz_artist, aliases_title, aliases_artist
+    ON extra_song_info
+    FOR EACH ROW EXECUTE PROCEDURE touch_song_of_extra_info();
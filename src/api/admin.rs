@@ -0,0 +1,538 @@
+use std::{collections::HashMap, fmt::Display, sync::Arc};
+
+use axum::{extract::State, Json};
+use diesel::{dsl::count_star, prelude::*};
+use diesel_async::RunQueryDsl;
+use fred::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_inline_default::serde_inline_default;
+use time::{Date, Duration, OffsetDateTime};
+use tracing::{error, info};
+use utoipa::ToSchema;
+use utoipa_axum::{router::OpenApiRouter, routes};
+use validator::Validate;
+
+use crate::{
+    models::{players::AccountType, rejected_scores::RejectedScore},
+    tasks,
+    util::{
+        errors::{RouteError, SimpleRouteErrorOutput},
+        jwt::Claims,
+        registrations,
+        usage::{usage_key, usage_top_key, USAGE_RETENTION_DAYS},
+        validator::ValidatedQuery,
+    },
+    AppState, Cache, Config, Db,
+};
+
+pub fn routes() -> OpenApiRouter<AppState> {
+    OpenApiRouter::new()
+        .routes(routes!(get_usage))
+        .routes(routes!(get_top_usage))
+        .routes(routes!(get_summary))
+        .routes(routes!(get_rejected_scores))
+        .routes(routes!(get_registrations, set_registrations))
+}
+
+/// The last `days` days, oldest first
+fn recent_days(days: i64) -> Vec<Date> {
+    let today = OffsetDateTime::now_utc().date();
+    (0..days)
+        .rev()
+        .map(|ago| today - Duration::days(ago))
+        .collect()
+}
+
+#[serde_inline_default]
+#[derive(Deserialize, Validate)]
+#[serde(rename_all = "camelCase")]
+struct GetUsageParams {
+    player_id: i32,
+    #[validate(range(min = 1, max = USAGE_RETENTION_DAYS))]
+    #[serde_inline_default(7)]
+    days: i64,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct DailyUsage {
+    /// Day in UTC, formatted as `YYYY-MM-DD`
+    date: String,
+    total: u64,
+    /// Requests per route group (e.g. `songs`)
+    groups: HashMap<String, u64>,
+}
+
+/// Get API usage of a player
+///
+/// Counts requests made with the player's tokens per day, oldest day first.
+/// Only available to team members.
+#[utoipa::path(
+    method(get),
+    path = "/usage",
+    params(
+        ("playerId" = i32, Query, description = "ID of player to get usage of"),
+        ("days" = Option<i64>, Query, description = "Number of days to include, counting today", minimum = 1, maximum = 30),
+    ),
+    responses(
+        (status = OK, description = "Success", body = Vec<DailyUsage>, content_type = "application/json"),
+        (status = BAD_REQUEST, description = "Invalid query", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = UNAUTHORIZED, description = "No permission", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = INTERNAL_SERVER_ERROR, description = "Miscellaneous error", body = SimpleRouteErrorOutput)
+    ),
+    security(
+        ("token_jwt" = [])
+    )
+)]
+async fn get_usage(
+    State(redis): State<Cache>,
+    claims: Claims,
+    ValidatedQuery(query): ValidatedQuery<GetUsageParams>,
+) -> Result<Json<Vec<DailyUsage>>, RouteError> {
+    if claims.profile.account_type != AccountType::Team {
+        return Err(RouteError::new_unauthorized());
+    }
+
+    let days = recent_days(query.days);
+    let pipeline = redis.next().pipeline();
+    for date in &days {
+        let _: () = pipeline.hgetall(usage_key(*date, query.player_id)).await?;
+    }
+    let counts: Vec<HashMap<String, u64>> = pipeline.all().await?;
+
+    let series = days
+        .into_iter()
+        .zip(counts)
+        .map(|(date, groups)| DailyUsage {
+            date: date.to_string(),
+            total: groups.values().sum(),
+            groups,
+        })
+        .collect();
+
+    Ok(Json(series))
+}
+
+#[serde_inline_default]
+#[derive(Deserialize, Validate)]
+#[serde(rename_all = "camelCase")]
+struct GetTopUsageParams {
+    #[validate(range(min = 1, max = USAGE_RETENTION_DAYS))]
+    #[serde_inline_default(1)]
+    days: i64,
+    #[validate(range(min = 1, max = 100))]
+    #[serde_inline_default(20)]
+    limit: usize,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct TopConsumer {
+    player_id: i32,
+    requests: u64,
+}
+
+/// Get top API consumers
+///
+/// Lists the players who made the most requests in the last days, most requests first.
+/// Only available to team members.
+#[utoipa::path(
+    method(get),
+    path = "/usage/top",
+    params(
+        ("days" = Option<i64>, Query, description = "Number of days to add up, counting today", minimum = 1, maximum = 30),
+        ("limit" = Option<usize>, Query, description = "Number of players to list", minimum = 1, maximum = 100),
+    ),
+    responses(
+        (status = OK, description = "Success", body = Vec<TopConsumer>, content_type = "application/json"),
+        (status = BAD_REQUEST, description = "Invalid query", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = UNAUTHORIZED, description = "No permission", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = INTERNAL_SERVER_ERROR, description = "Miscellaneous error", body = SimpleRouteErrorOutput)
+    ),
+    security(
+        ("token_jwt" = [])
+    )
+)]
+async fn get_top_usage(
+    State(redis): State<Cache>,
+    claims: Claims,
+    ValidatedQuery(query): ValidatedQuery<GetTopUsageParams>,
+) -> Result<Json<Vec<TopConsumer>>, RouteError> {
+    if claims.profile.account_type != AccountType::Team {
+        return Err(RouteError::new_unauthorized());
+    }
+
+    let pipeline = redis.next().pipeline();
+    for date in recent_days(query.days) {
+        let _: () = pipeline.zrevrange(usage_top_key(date), 0, -1, true).await?;
+    }
+    let daily: Vec<Vec<(i32, f64)>> = pipeline.all().await?;
+
+    let mut totals: HashMap<i32, u64> = HashMap::new();
+    for (player_id, requests) in daily.into_iter().flatten() {
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        let requests = requests as u64;
+        *totals.entry(player_id).or_default() += requests;
+    }
+
+    let mut top: Vec<TopConsumer> = totals
+        .into_iter()
+        .map(|(player_id, requests)| TopConsumer {
+            player_id,
+            requests,
+        })
+        .collect();
+    top.sort_by(|a, b| b.requests.cmp(&a.requests));
+    top.truncate(query.limit);
+
+    Ok(Json(top))
+}
+
+/// One part of the admin summary. A part that fails to load doesn't fail the others.
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+enum SummaryBlock<T> {
+    Ok(T),
+    /// What failed to load, details are in the server log
+    Error(String),
+}
+
+impl<T> SummaryBlock<T> {
+    fn from_result<E: Display>(block: &str, result: Result<T, E>) -> Self {
+        match result {
+            Ok(value) => Self::Ok(value),
+            Err(e) => {
+                error!("Failed to load {} for admin summary: {}", block, e);
+                Self::Error(format!("Failed to load {block}"))
+            }
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct PendingDeltasSummary {
+    count: i64,
+    /// When the oldest queued delta was created
+    #[serde(serialize_with = "time::serde::iso8601::option::serialize")]
+    oldest: Option<OffsetDateTime>,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct UsageSummary {
+    /// Authenticated API requests today (UTC), not counting the last few seconds
+    requests: u64,
+    /// Players who made those requests
+    players: usize,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct BackgroundJob {
+    name: &'static str,
+    interval_seconds: u64,
+    /// Whether the job runs with the current config
+    enabled: bool,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct AdminSummary {
+    /// Score reports nobody handled yet
+    open_score_reports: SummaryBlock<i64>,
+    /// Skill point deltas waiting to be applied to the leaderboards
+    pending_leaderboard_deltas: SummaryBlock<PendingDeltasSummary>,
+    /// Songs without a MusicBrainz ID
+    songs_missing_metadata: SummaryBlock<i64>,
+    api_usage_today: SummaryBlock<UsageSummary>,
+    registrations: SummaryBlock<RegistrationStatus>,
+    background_jobs: Vec<BackgroundJob>,
+}
+
+/// Get admin dashboard summary
+///
+/// Collects what the admin dashboard shows in one request.
+/// Each part is loaded on its own, so if one fails, it is replaced with an error and the rest is still returned.
+/// Only available to moderators and team members.
+#[utoipa::path(
+    method(get),
+    path = "/summary",
+    responses(
+        (status = OK, description = "Success", body = AdminSummary, content_type = "application/json"),
+        (status = UNAUTHORIZED, description = "No permission", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = INTERNAL_SERVER_ERROR, description = "Miscellaneous error", body = SimpleRouteErrorOutput)
+    ),
+    security(
+        ("token_jwt" = [])
+    )
+)]
+async fn get_summary(
+    State(config): State<Arc<Config>>,
+    State(db): State<Db>,
+    State(redis): State<Cache>,
+    claims: Claims,
+) -> Result<Json<AdminSummary>, RouteError> {
+    if claims.profile.account_type != AccountType::Moderator
+        && claims.profile.account_type != AccountType::Team
+    {
+        return Err(RouteError::new_unauthorized());
+    }
+
+    let (
+        open_score_reports,
+        pending_leaderboard_deltas,
+        songs_missing_metadata,
+        api_usage_today,
+        registrations,
+    ) = tokio::join!(
+        open_score_reports(&db),
+        pending_deltas_summary(&db),
+        songs_missing_metadata(&db),
+        usage_summary(&redis),
+        registration_status(&redis),
+    );
+
+    let background_jobs = tasks::job_list(&config)
+        .into_iter()
+        .map(|job| BackgroundJob {
+            name: job.name,
+            interval_seconds: job.interval.as_secs(),
+            enabled: job.enabled,
+        })
+        .collect();
+
+    Ok(Json(AdminSummary {
+        open_score_reports: SummaryBlock::from_result("open score reports", open_score_reports),
+        pending_leaderboard_deltas: SummaryBlock::from_result(
+            "pending leaderboard deltas",
+            pending_leaderboard_deltas,
+        ),
+        songs_missing_metadata: SummaryBlock::from_result(
+            "songs missing metadata",
+            songs_missing_metadata,
+        ),
+        api_usage_today: SummaryBlock::from_result("API usage", api_usage_today),
+        registrations: SummaryBlock::from_result("registration status", registrations),
+        background_jobs,
+    }))
+}
+
+async fn registration_status(redis: &Cache) -> anyhow::Result<RegistrationStatus> {
+    let frozen_since = registrations::frozen_since(redis).await?;
+    Ok(RegistrationStatus {
+        frozen: frozen_since.is_some(),
+        frozen_since,
+    })
+}
+
+async fn open_score_reports(db: &Db) -> anyhow::Result<i64> {
+    use crate::schema::{score_reports, scores};
+
+    let mut conn = db.read_conn().await?;
+    // Counted like the moderation queue lists them, without reports of deleted scores
+    Ok(score_reports::table
+        .inner_join(scores::table)
+        .filter(score_reports::resolved_at.is_null())
+        .count()
+        .get_result(&mut conn)
+        .await?)
+}
+
+async fn pending_deltas_summary(db: &Db) -> anyhow::Result<PendingDeltasSummary> {
+    use crate::schema::pending_leaderboard_deltas;
+
+    let mut conn = db.read_conn().await?;
+    let (count, oldest) = pending_leaderboard_deltas::table
+        .select((
+            count_star(),
+            diesel::dsl::min(pending_leaderboard_deltas::created_at),
+        ))
+        .first(&mut conn)
+        .await?;
+
+    Ok(PendingDeltasSummary { count, oldest })
+}
+
+async fn songs_missing_metadata(db: &Db) -> anyhow::Result<i64> {
+    use crate::schema::{extra_song_info, songs};
+
+    let mut conn = db.read_conn().await?;
+    Ok(songs::table
+        .left_join(extra_song_info::table)
+        .filter(extra_song_info::mbid.is_null())
+        .count()
+        .get_result(&mut conn)
+        .await?)
+}
+
+async fn usage_summary(redis: &Cache) -> anyhow::Result<UsageSummary> {
+    let today = OffsetDateTime::now_utc().date();
+    let consumers: Vec<(i32, f64)> = redis
+        .zrange(usage_top_key(today), 0, -1, None, false, None, true)
+        .await?;
+
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let requests = consumers.iter().map(|&(_, requests)| requests as u64).sum();
+    Ok(UsageSummary {
+        requests,
+        players: consumers.len(),
+    })
+}
+
+#[serde_inline_default]
+#[derive(Deserialize, Validate)]
+#[serde(rename_all = "camelCase")]
+struct GetRejectedScoresParams {
+    player_id: Option<i32>,
+    #[validate(range(min = 1))]
+    #[serde_inline_default(1)]
+    page: i64,
+    #[validate(range(min = 1, max = 50))]
+    #[serde_inline_default(20)]
+    page_size: i64,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct RejectedScoresResponse {
+    results: Vec<RejectedScore>,
+    total: i64,
+}
+
+/// List rejected scores
+///
+/// Lists score submissions that failed the plausibility checks, newest first.
+/// Only available to moderators and team members.
+#[utoipa::path(
+    method(get),
+    path = "/rejectedScores",
+    params(
+        ("playerId" = Option<i32>, Query, description = "Only include submissions of this player"),
+        ("page" = Option<i64>, Query, description = "Page number", minimum = 1),
+        ("pageSize" = Option<i64>, Query, description = "Page size", minimum = 1, maximum = 50),
+    ),
+    responses(
+        (status = OK, description = "Success", body = RejectedScoresResponse, content_type = "application/json"),
+        (status = BAD_REQUEST, description = "Invalid query", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = UNAUTHORIZED, description = "No permission", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = INTERNAL_SERVER_ERROR, description = "Miscellaneous error", body = SimpleRouteErrorOutput)
+    ),
+    security(
+        ("token_jwt" = [])
+    )
+)]
+async fn get_rejected_scores(
+    State(db): State<Db>,
+    claims: Claims,
+    ValidatedQuery(query): ValidatedQuery<GetRejectedScoresParams>,
+) -> Result<Json<RejectedScoresResponse>, RouteError> {
+    use crate::schema::rejected_scores;
+
+    if claims.profile.account_type != AccountType::Moderator
+        && claims.profile.account_type != AccountType::Team
+    {
+        return Err(RouteError::new_unauthorized());
+    }
+
+    let mut conn = db.read_conn().await?;
+
+    let mut total_query = rejected_scores::table.into_boxed();
+    let mut results_query = rejected_scores::table.into_boxed();
+    if let Some(player_id) = query.player_id {
+        total_query = total_query.filter(rejected_scores::player_id.eq(player_id));
+        results_query = results_query.filter(rejected_scores::player_id.eq(player_id));
+    }
+
+    let total: i64 = total_query.count().get_result(&mut conn).await?;
+    let results = results_query
+        .order((
+            rejected_scores::rejected_at.desc(),
+            rejected_scores::id.desc(),
+        ))
+        .limit(query.page_size)
+        .offset((query.page - 1) * query.page_size)
+        .select(RejectedScore::as_select())
+        .load(&mut conn)
+        .await?;
+
+    Ok(Json(RejectedScoresResponse { results, total }))
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct RegistrationStatus {
+    /// While frozen, players without an account can't create one, everyone else plays as usual
+    frozen: bool,
+    #[serde(serialize_with = "time::serde::iso8601::option::serialize")]
+    frozen_since: Option<OffsetDateTime>,
+}
+
+/// Get registration status
+///
+/// Only available to team members.
+#[utoipa::path(
+    method(get),
+    path = "/registrations",
+    responses(
+        (status = OK, description = "Success", body = RegistrationStatus, content_type = "application/json"),
+        (status = UNAUTHORIZED, description = "No permission", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = INTERNAL_SERVER_ERROR, description = "Miscellaneous error", body = SimpleRouteErrorOutput)
+    ),
+    security(
+        ("token_jwt" = [])
+    )
+)]
+async fn get_registrations(
+    State(redis): State<Cache>,
+    claims: Claims,
+) -> Result<Json<RegistrationStatus>, RouteError> {
+    if claims.profile.account_type != AccountType::Team {
+        return Err(RouteError::new_unauthorized());
+    }
+
+    Ok(Json(registration_status(&redis).await?))
+}
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct SetRegistrationsBody {
+    frozen: bool,
+}
+
+/// Freeze or open registrations
+///
+/// Stops players without an account from creating one, e.g. during an abuse wave, or allows it again.
+/// Players who already have an account aren't affected. Takes effect immediately, without a restart.
+/// Only available to team members.
+#[utoipa::path(
+    method(put),
+    path = "/registrations",
+    request_body = SetRegistrationsBody,
+    responses(
+        (status = OK, description = "Success", body = RegistrationStatus, content_type = "application/json"),
+        (status = UNAUTHORIZED, description = "No permission", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = INTERNAL_SERVER_ERROR, description = "Miscellaneous error", body = SimpleRouteErrorOutput)
+    ),
+    security(
+        ("token_jwt" = [])
+    )
+)]
+async fn set_registrations(
+    State(redis): State<Cache>,
+    claims: Claims,
+    Json(body): Json<SetRegistrationsBody>,
+) -> Result<Json<RegistrationStatus>, RouteError> {
+    if claims.profile.account_type != AccountType::Team {
+        return Err(RouteError::new_unauthorized());
+    }
+
+    registrations::set_frozen(body.frozen, &redis).await?;
+    info!(
+        "Player {} {} registrations",
+        claims.profile.id,
+        if body.frozen { "froze" } else { "opened" }
+    );
+
+    Ok(Json(registration_status(&redis).await?))
+}
@@ -0,0 +1,242 @@
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use serde::Deserialize;
+use serde_inline_default::serde_inline_default;
+use time::OffsetDateTime;
+use tracing::info;
+use utoipa_axum::{router::OpenApiRouter, routes};
+use validator::Validate;
+
+use crate::{
+    models::{
+        announcements::{validate_expiry, Announcement, AnnouncementUpdate, NewAnnouncement},
+        players::AccountType,
+    },
+    util::{
+        errors::{RouteError, SimpleRouteErrorOutput},
+        jwt::Claims,
+        validator::{ValidatedJson, ValidatedQuery},
+    },
+    AppState, Db,
+};
+
+pub fn routes() -> OpenApiRouter<AppState> {
+    OpenApiRouter::new()
+        .routes(routes!(get_announcements, add_announcement))
+        .routes(routes!(update_announcement, delete_announcement))
+}
+
+const fn is_moderator(claims: &Claims) -> bool {
+    matches!(
+        claims.profile.account_type,
+        AccountType::Moderator | AccountType::Team
+    )
+}
+
+#[serde_inline_default]
+#[derive(Deserialize, Validate)]
+#[serde(rename_all = "camelCase")]
+struct GetAnnouncementsParams {
+    #[validate(range(min = 1, max = 50))]
+    #[serde_inline_default(10)]
+    limit: i64,
+    #[serde_inline_default(false)]
+    all: bool,
+}
+
+/// List announcements
+///
+/// Lists the announcements that are shown right now, pinned ones first, then the newest.
+/// With `all`, moderators and team members also get expired and upcoming ones, newest first.
+#[utoipa::path(
+    method(get),
+    path = "/",
+    params(
+        ("limit" = Option<i64>, Query, description = "How many announcements to list", minimum = 1, maximum = 50),
+        ("all" = Option<bool>, Query, description = "Include expired and upcoming announcements, only for moderators"),
+    ),
+    responses(
+        (status = OK, description = "Success", body = Vec<Announcement>, content_type = "application/json"),
+        (status = BAD_REQUEST, description = "Invalid query", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = UNAUTHORIZED, description = "No permission to list all announcements", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = INTERNAL_SERVER_ERROR, description = "Miscellaneous error", body = SimpleRouteErrorOutput)
+    ),
+    security(
+        (),
+        ("token_jwt" = [])
+    )
+)]
+async fn get_announcements(
+    State(db): State<Db>,
+    claims: Result<Claims, RouteError>,
+    ValidatedQuery(query): ValidatedQuery<GetAnnouncementsParams>,
+) -> Result<Json<Vec<Announcement>>, RouteError> {
+    if query.all {
+        if !claims.is_ok_and(|claims| is_moderator(&claims)) {
+            return Err(RouteError::new_unauthorized());
+        }
+
+        let mut conn = db.get().await?;
+        return Ok(Json(Announcement::all(query.limit, &mut conn).await?));
+    }
+
+    let mut conn = db.read_conn().await?;
+    let announcements =
+        Announcement::shown_at(OffsetDateTime::now_utc(), query.limit, &mut conn).await?;
+
+    Ok(Json(announcements))
+}
+
+/// Add announcement
+///
+/// Publishes an announcement on the website, and in game if it's the newest one (or pinned).
+/// `publishedAt` can be in the future to prepare it in advance. Only available to moderators and team members.
+#[utoipa::path(
+    method(post),
+    path = "/",
+    request_body = NewAnnouncement,
+    responses(
+        (status = OK, description = "Success", body = Announcement, content_type = "application/json"),
+        (status = BAD_REQUEST, description = "Invalid announcement", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = UNAUTHORIZED, description = "No permission", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = INTERNAL_SERVER_ERROR, description = "Miscellaneous error", body = SimpleRouteErrorOutput)
+    ),
+    security(
+        ("token_jwt" = [])
+    )
+)]
+async fn add_announcement(
+    State(db): State<Db>,
+    claims: Claims,
+    ValidatedJson(new_announcement): ValidatedJson<NewAnnouncement>,
+) -> Result<Json<Announcement>, RouteError> {
+    use crate::schema::announcements;
+
+    if !is_moderator(&claims) {
+        return Err(RouteError::new_unauthorized());
+    }
+
+    let mut conn = db.get().await?;
+    let announcement: Announcement = diesel::insert_into(announcements::table)
+        .values(&new_announcement)
+        .returning(Announcement::as_returning())
+        .get_result(&mut conn)
+        .await?;
+
+    info!(
+        "Player {} added announcement {}",
+        claims.profile.id, announcement.id
+    );
+    Ok(Json(announcement))
+}
+
+/// Update announcement
+///
+/// Fields that are left out stay as they are, `expiresAt: null` makes it stay up.
+/// Only available to moderators and team members.
+#[utoipa::path(
+    method(patch),
+    path = "/{id}",
+    params(
+        ("id" = i32, Path, description = "ID of announcement to update"),
+    ),
+    request_body = AnnouncementUpdate,
+    responses(
+        (status = OK, description = "Success", body = Announcement, content_type = "application/json"),
+        (status = BAD_REQUEST, description = "Invalid changes", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = UNAUTHORIZED, description = "No permission", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = NOT_FOUND, description = "Announcement not found", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = INTERNAL_SERVER_ERROR, description = "Miscellaneous error", body = SimpleRouteErrorOutput)
+    ),
+    security(
+        ("token_jwt" = [])
+    )
+)]
+async fn update_announcement(
+    State(db): State<Db>,
+    claims: Claims,
+    Path(id): Path<i32>,
+    ValidatedJson(update): ValidatedJson<AnnouncementUpdate>,
+) -> Result<Json<Announcement>, RouteError> {
+    use crate::schema::announcements;
+
+    if !is_moderator(&claims) {
+        return Err(RouteError::new_unauthorized());
+    }
+
+    let mut conn = db.get().await?;
+    let announcement: Announcement = announcements::table
+        .find(id)
+        .select(Announcement::as_select())
+        .first(&mut conn)
+        .await
+        .optional()?
+        .ok_or_else(RouteError::new_not_found)?;
+    let (published_at, expires_at) = update.schedule_of(&announcement);
+    if validate_expiry(published_at, expires_at).is_err() {
+        return Err(RouteError::new_bad_request()
+            .set_public_error_message("expiresAt has to be after publishedAt"));
+    }
+
+    let announcement = if update.is_empty() {
+        announcement
+    } else {
+        diesel::update(&announcement)
+            .set(&update)
+            .returning(Announcement::as_returning())
+            .get_result(&mut conn)
+            .await?
+    };
+
+    info!(
+        "Player {} updated announcement {}",
+        claims.profile.id, announcement.id
+    );
+    Ok(Json(announcement))
+}
+
+/// Delete announcement by ID
+///
+/// Only available to moderators and team members.
+#[utoipa::path(
+    method(delete),
+    path = "/{id}",
+    params(
+        ("id" = i32, Path, description = "ID of announcement to delete"),
+    ),
+    responses(
+        (status = OK, description = "Success"),
+        (status = NOT_FOUND, description = "Announcement not found", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = UNAUTHORIZED, description = "No permission", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = INTERNAL_SERVER_ERROR, description = "Miscellaneous error", body = SimpleRouteErrorOutput)
+    ),
+    security(
+        ("token_jwt" = [])
+    )
+)]
+async fn delete_announcement(
+    State(db): State<Db>,
+    claims: Claims,
+    Path(id): Path<i32>,
+) -> Result<(), RouteError> {
+    use crate::schema::announcements;
+
+    if !is_moderator(&claims) {
+        return Err(RouteError::new_unauthorized());
+    }
+
+    let mut conn = db.get().await?;
+    let deleted = diesel::delete(announcements::table.find(id))
+        .execute(&mut conn)
+        .await?;
+    if deleted == 0 {
+        return Err(RouteError::new_not_found());
+    }
+
+    info!("Player {} deleted announcement {id}", claims.profile.id);
+    Ok(())
+}
@@ -1,13 +1,17 @@
+use std::sync::Arc;
+
 use anyhow::anyhow;
 use axum::{
-    extract::{RawQuery, State},
+    extract::{Path, RawQuery, State},
     http::StatusCode,
     response::Redirect,
     Json,
 };
+use diesel::OptionalExtension;
 use diesel_async::RunQueryDsl;
 use jsonwebtoken::{encode, Header};
-use tracing::info;
+use serde::Serialize;
+use tracing::{info, warn};
 use utoipa::ToSchema;
 use utoipa_axum::{router::OpenApiRouter, routes};
 
@@ -15,15 +19,21 @@ use crate::{
     models::players::Player,
     util::{
         errors::{IntoRouteError, RouteError, SimpleRouteErrorOutput},
-        jwt::{AuthBody, Claims},
+        jwt::{AuthBody, Claims, Keys},
+        openid::{self, LoginError},
+        registrations,
+        session::{create_session, list_sessions, revoke_session, SESSION_LIFETIME_SECS},
     },
-    AppState,
+    AppState, Cache, Config, Db, SteamAuth,
 };
 
 pub fn routes() -> OpenApiRouter<AppState> {
     OpenApiRouter::new()
         .routes(routes!(auth_login))
         .routes(routes!(auth_return))
+        .routes(routes!(logout))
+        .routes(routes!(get_sessions))
+        .routes(routes!(delete_session))
 }
 
 /// Start login
@@ -34,16 +44,8 @@ pub fn routes() -> OpenApiRouter<AppState> {
         (status = 308, description = "Redirect to Steam", body = ())
     )
 )]
-async fn auth_login(State(state): State<AppState>) -> Result<Redirect, RouteError> {
-    Ok(Redirect::permanent(state.steam_openid.get_redirect_url()))
-}
-
-/// Wrapper for jwt crate's AuthBody because it doesn't implement ToSchema
-#[derive(ToSchema)]
-#[allow(dead_code)]
-pub struct AuthBodySchema {
-    access_token: String,
-    token_type: String,
+async fn auth_login(State(steam): State<SteamAuth>) -> Result<Redirect, RouteError> {
+    Ok(Redirect::permanent(steam.openid.get_redirect_url()))
 }
 
 /// Return after Steam login
@@ -51,49 +53,196 @@ pub struct AuthBodySchema {
     method(get),
     path = "/return",
     responses(
-        (status = OK, description = "Success", body = AuthBodySchema),
+        (status = OK, description = "Success", body = AuthBody),
         (status = BAD_REQUEST, description = "OpenID verification failed", body = SimpleRouteErrorOutput),
+        (status = FORBIDDEN, description = "Account is banned, or registrations are frozen and the player has no account yet", body = SimpleRouteErrorOutput),
         (status = NOT_FOUND, description = "Profile not found", body = SimpleRouteErrorOutput),
         (status = INTERNAL_SERVER_ERROR, description = "Miscellaneous error", body = SimpleRouteErrorOutput)
     )
 )]
 async fn auth_return(
-    State(state): State<AppState>,
+    State(db): State<Db>,
+    State(redis): State<Cache>,
+    State(steam): State<SteamAuth>,
+    State(jwt_keys): State<Keys>,
+    State(config): State<Arc<Config>>,
     RawQuery(query): RawQuery,
 ) -> Result<Json<AuthBody>, RouteError> {
-    let steamid64 = state
-        .steam_openid
-        .verify(
-            &query
-                .ok_or_else(|| anyhow!("No query string to verify!"))
-                .http_error("Query string is empty", StatusCode::BAD_REQUEST)?,
-        )
-        .await
-        .map_err(|e| anyhow!("OpenID verification failed: {e:?}"))
-        .http_error(
-            "Couldn't verify Steam OpenID return",
-            StatusCode::BAD_REQUEST,
-        )?;
+    let query = query
+        .ok_or_else(|| anyhow!("No query string to verify!"))
+        .http_error("Query string is empty", StatusCode::BAD_REQUEST)?;
+
+    let expected_return_to = format!(
+        "{}{}",
+        config.external.steam_realm.trim_end_matches('/'),
+        config.external.steam_return_path
+    );
+    let steamid64 = match openid::verify_login(
+        &query,
+        &expected_return_to,
+        time::OffsetDateTime::now_utc(),
+        steam.openid.as_ref(),
+        &redis,
+    )
+    .await
+    {
+        Ok(steamid64) => steamid64,
+        Err(LoginError::Replayed(nonce)) => {
+            warn!("Refused replayed Steam OpenID return with nonce {}", nonce);
+            return Err(RouteError::new_bad_request().set_public_error_message(
+                "This login link was already used, please log in again",
+            ));
+        }
+        Err(LoginError::Redis(e)) => return Err(e.into()),
+        Err(e) => {
+            return Err(anyhow!(e)).http_error(
+                "Couldn't verify Steam OpenID return",
+                StatusCode::BAD_REQUEST,
+            )
+        }
+    };
 
-    let mut conn = state.db.get().await?;
+    let mut conn = db.get().await?;
 
-    let player = Player::find_by_steam_id(steamid64.into())
+    let player: Option<Player> = Player::find_by_steam_id(steamid64.into())
         .first(&mut conn)
         .await
-        .http_error("Profile not found", StatusCode::NOT_FOUND)?;
+        .optional()?;
+    let Some(player) = player else {
+        // Players are created by logging in through the game, the message just has to be clear about why that won't work
+        if !registrations::are_open(&redis).await {
+            warn!(
+                "Refused registration of {} (Steam), registrations are frozen",
+                steamid64
+            );
+            return Err(RouteError::new_forbidden().set_public_error_message(
+                "New registrations are closed right now, please try again later",
+            ));
+        }
+        return Err(RouteError::new_not_found().set_public_error_message("Profile not found"));
+    };
+    if let Some(message) = player.active_ban(&mut conn).await? {
+        return Err(RouteError::new_forbidden().set_public_error_message(&message));
+    }
 
     info!("Player {} logged in via Steam OpenID", player.id);
 
-    // expiry in 7 days
-    let exp = time::OffsetDateTime::now_utc().unix_timestamp() + 60 * 60 * 24 * 7;
+    // the token expires together with its session
+    let exp = time::OffsetDateTime::now_utc().unix_timestamp() + SESSION_LIFETIME_SECS;
+    let sid = create_session(player.id, &redis).await?;
 
     let claims = Claims {
         profile: player,
         exp,
+        sid,
     };
     // Create the authorization token
-    let token = encode(&Header::default(), &claims, &state.jwt_keys.encoding)
+    let token = encode(&Header::default(), &claims, &jwt_keys.encoding)
         .http_internal_error("Failed to create token")?;
 
     Ok(Json(AuthBody::new(token)))
 }
+
+/// Log out
+///
+/// Ends the session of the token used for this request, so it can't be used anymore.
+#[utoipa::path(
+    method(delete),
+    path = "/session",
+    responses(
+        (status = OK, description = "Success"),
+        (status = UNAUTHORIZED, description = "Not logged in or token already invalid", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = INTERNAL_SERVER_ERROR, description = "Miscellaneous error", body = SimpleRouteErrorOutput)
+    ),
+    security(
+        ("token_jwt" = [])
+    )
+)]
+async fn logout(State(redis): State<Cache>, claims: Claims) -> Result<(), RouteError> {
+    // Someone else might have revoked the session since the extractor checked it
+    if !revoke_session(claims.sid, claims.profile.id, &redis).await? {
+        return Err(RouteError::new_unauthorized());
+    }
+
+    info!("Player {} logged out", claims.profile.id);
+
+    Ok(())
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct SessionResponse {
+    id: u64,
+    #[serde(serialize_with = "time::serde::iso8601::serialize")]
+    created_at: time::OffsetDateTime,
+    #[serde(serialize_with = "time::serde::iso8601::serialize")]
+    last_used_at: time::OffsetDateTime,
+    /// Whether this is the session of the token used for this request
+    current: bool,
+}
+
+/// List own sessions
+///
+/// Lists everywhere the player is currently logged in, oldest first.
+#[utoipa::path(
+    method(get),
+    path = "/sessions",
+    responses(
+        (status = OK, description = "Success", body = Vec<SessionResponse>, content_type = "application/json"),
+        (status = UNAUTHORIZED, description = "Not logged in or invalid token", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = INTERNAL_SERVER_ERROR, description = "Miscellaneous error", body = SimpleRouteErrorOutput)
+    ),
+    security(
+        ("token_jwt" = [])
+    )
+)]
+async fn get_sessions(
+    State(redis): State<Cache>,
+    claims: Claims,
+) -> Result<Json<Vec<SessionResponse>>, RouteError> {
+    let sessions = list_sessions(claims.profile.id, &redis)
+        .await?
+        .into_iter()
+        .map(|session| SessionResponse {
+            id: session.id,
+            created_at: session.created_at,
+            last_used_at: session.last_used_at,
+            current: session.id == claims.sid,
+        })
+        .collect();
+
+    Ok(Json(sessions))
+}
+
+/// Revoke own session by ID
+///
+/// The token belonging to the session can't be used anymore afterwards.
+#[utoipa::path(
+    method(delete),
+    path = "/sessions/{id}",
+    params(
+        ("id" = u64, Path, description = "ID of session to revoke"),
+    ),
+    responses(
+        (status = OK, description = "Success"),
+        (status = NOT_FOUND, description = "Session not found", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = UNAUTHORIZED, description = "Not logged in or invalid token", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = INTERNAL_SERVER_ERROR, description = "Miscellaneous error", body = SimpleRouteErrorOutput)
+    ),
+    security(
+        ("token_jwt" = [])
+    )
+)]
+async fn delete_session(
+    State(redis): State<Cache>,
+    claims: Claims,
+    Path(id): Path<u64>,
+) -> Result<(), RouteError> {
+    if !revoke_session(id, claims.profile.id, &redis).await? {
+        return Err(RouteError::new_not_found());
+    }
+
+    info!("Player {} revoked their session {}", claims.profile.id, id);
+
+    Ok(())
+}
@@ -0,0 +1,203 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, State},
+    http::header,
+    response::IntoResponse,
+    Json,
+};
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use serde::Serialize;
+use utoipa::ToSchema;
+use utoipa_axum::{router::OpenApiRouter, routes};
+
+use crate::{
+    models::{
+        client_changelog::{ChangelogEntry, NewChangelogEntry},
+        players::AccountType,
+    },
+    util::{
+        errors::{RouteError, SimpleRouteErrorOutput},
+        jwt::Claims,
+        validator::ValidatedJson,
+        version::ClientVersion,
+    },
+    AppState, Config, Db,
+};
+
+/// How many changelog entries are included in the client info
+const CHANGELOG_ENTRIES: i64 = 10;
+
+pub fn routes() -> OpenApiRouter<AppState> {
+    OpenApiRouter::new()
+        .routes(routes!(get_server_info))
+        .routes(routes!(get_client_info))
+        .routes(routes!(add_changelog_entry))
+        .routes(routes!(delete_changelog_entry))
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct ServerInfoResponse {
+    server_name: String,
+    support_url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    discord_invite: Option<String>,
+}
+
+/// Get server info
+///
+/// Name and links of this server, e.g. for the website's footer.
+/// Responses may be cached for up to 5 minutes.
+#[utoipa::path(
+    method(get),
+    path = "/server",
+    responses(
+        (status = OK, description = "Success", body = ServerInfoResponse, content_type = "application/json")
+    )
+)]
+async fn get_server_info(State(config): State<Arc<Config>>) -> impl IntoResponse {
+    let branding = &config.branding;
+
+    (
+        [(header::CACHE_CONTROL, "public, max-age=300")],
+        Json(ServerInfoResponse {
+            server_name: branding.server_name.clone(),
+            support_url: branding.support_url.clone(),
+            discord_invite: branding.discord_invite.clone(),
+        }),
+    )
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct ClientInfoResponse {
+    /// Oldest client mod version that is still supported
+    min_version: ClientVersion,
+    /// Version players should update to
+    recommended_version: ClientVersion,
+    download_url: String,
+    /// Most recent changelog entries, newest first
+    changelog: Vec<ChangelogEntry>,
+}
+
+/// Get client mod version info
+///
+/// Polled by the client mod at startup to decide whether to show an update prompt.
+/// Responses may be cached for up to 5 minutes.
+#[utoipa::path(
+    method(get),
+    path = "/client",
+    responses(
+        (status = OK, description = "Success", body = ClientInfoResponse, content_type = "application/json"),
+        (status = NOT_FOUND, description = "Server has no client info configured", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = INTERNAL_SERVER_ERROR, description = "Miscellaneous error", body = SimpleRouteErrorOutput)
+    )
+)]
+async fn get_client_info(
+    State(db): State<Db>,
+    State(config): State<Arc<Config>>,
+) -> Result<impl IntoResponse, RouteError> {
+    use crate::schema::client_changelog;
+
+    let client_config = config
+        .client
+        .as_ref()
+        .ok_or_else(RouteError::new_not_found)?;
+
+    let mut conn = db.read_conn().await?;
+    let changelog: Vec<ChangelogEntry> = client_changelog::table
+        .order(client_changelog::published_at.desc())
+        .limit(CHANGELOG_ENTRIES)
+        .load(&mut conn)
+        .await?;
+
+    Ok((
+        [(header::CACHE_CONTROL, "public, max-age=300")],
+        Json(ClientInfoResponse {
+            min_version: client_config.min_version,
+            recommended_version: client_config.recommended_version,
+            download_url: client_config.download_url.clone(),
+            changelog,
+        }),
+    ))
+}
+
+/// Add client mod changelog entry
+///
+/// Only available to team members.
+#[utoipa::path(
+    method(post),
+    path = "/client/changelog",
+    request_body = NewChangelogEntry,
+    responses(
+        (status = OK, description = "Success", body = ChangelogEntry, content_type = "application/json"),
+        (status = BAD_REQUEST, description = "Invalid version or body", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = UNAUTHORIZED, description = "No permission", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = INTERNAL_SERVER_ERROR, description = "Miscellaneous error", body = SimpleRouteErrorOutput)
+    ),
+    security(
+        ("token_jwt" = [])
+    )
+)]
+async fn add_changelog_entry(
+    State(db): State<Db>,
+    claims: Claims,
+    ValidatedJson(new_entry): ValidatedJson<NewChangelogEntry>,
+) -> Result<Json<ChangelogEntry>, RouteError> {
+    use crate::schema::client_changelog;
+
+    if claims.profile.account_type != AccountType::Team {
+        return Err(RouteError::new_unauthorized());
+    }
+
+    let mut conn = db.get().await?;
+    let entry: ChangelogEntry = diesel::insert_into(client_changelog::table)
+        .values(&new_entry)
+        .get_result(&mut conn)
+        .await?;
+
+    Ok(Json(entry))
+}
+
+/// Delete client mod changelog entry by ID
+///
+/// Only available to team members.
+#[utoipa::path(
+    method(delete),
+    path = "/client/changelog/{id}",
+    params(
+        ("id" = i32, Path, description = "ID of changelog entry to delete"),
+    ),
+    responses(
+        (status = OK, description = "Success"),
+        (status = NOT_FOUND, description = "Changelog entry not found", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = UNAUTHORIZED, description = "No permission", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = INTERNAL_SERVER_ERROR, description = "Miscellaneous error", body = SimpleRouteErrorOutput)
+    ),
+    security(
+        ("token_jwt" = [])
+    )
+)]
+async fn delete_changelog_entry(
+    State(db): State<Db>,
+    claims: Claims,
+    Path(id): Path<i32>,
+) -> Result<(), RouteError> {
+    use crate::schema::client_changelog;
+
+    if claims.profile.account_type != AccountType::Team {
+        return Err(RouteError::new_unauthorized());
+    }
+
+    let mut conn = db.get().await?;
+    let deleted = diesel::delete(client_changelog::table.find(id))
+        .execute(&mut conn)
+        .await?;
+    if deleted == 0 {
+        return Err(RouteError::new_not_found());
+    }
+
+    Ok(())
+}
@@ -1,7 +1,13 @@
-use axum::{extract::State, Json, Router};
+use std::collections::HashMap;
+
+use axum::{
+    extract::{Query, State},
+    Json, Router,
+};
 use diesel::prelude::*;
 use diesel_async::RunQueryDsl;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use serde_inline_default::serde_inline_default;
 use utoipa::{
     openapi::{
         security::{HttpAuthScheme, HttpBuilder, SecurityScheme},
@@ -12,15 +18,29 @@ use utoipa::{
 use utoipa_axum::{router::OpenApiRouter, routes};
 
 use crate::{
+    models::{
+        players::{Player, PlayerPublic},
+        songs::Song,
+    },
     util::{
         errors::{RouteError, SimpleRouteErrorOutput},
+        presence::online_players,
         query::SortType,
     },
-    AppState,
+    AppState, Cache, Db,
 };
 
+/// Most players listed by `/stats/online`
+const MAX_ONLINE_LISTED: usize = 100;
+
+mod admin;
+mod announcements;
 mod auth;
+mod meta;
+mod notifications;
 mod players;
+mod radio;
+mod reports;
 mod rivals;
 mod scores;
 mod shouts;
@@ -47,6 +67,11 @@ impl Modify for SecurityAddon {
                     HttpBuilder::new()
                         .scheme(HttpAuthScheme::Bearer)
                         .bearer_format("JWT")
+                        .description(Some(
+                            "Token returned by `/auth/return` after logging in through Steam. \
+                            It expires 7 days after login or when logging out through `DELETE /auth/session`, \
+                            after which the player has to log in again.",
+                        ))
                         .build(),
                 ),
             );
@@ -57,12 +82,19 @@ impl Modify for SecurityAddon {
 pub fn routes() -> (Router<AppState>, OpenApi) {
     OpenApiRouter::with_openapi(ApiDoc::openapi())
         .routes(routes!(stats))
+        .routes(routes!(online))
         .nest("/songs", songs::routes())
         .nest("/players", players::routes())
         .nest("/auth", auth::routes())
         .nest("/rivals", rivals::routes())
         .nest("/scores", scores::routes())
         .nest("/shouts", shouts::routes())
+        .nest("/meta", meta::routes())
+        .nest("/radio", radio::routes())
+        .nest("/notifications", notifications::routes())
+        .nest("/reports", reports::routes())
+        .nest("/admin", admin::routes())
+        .nest("/announcements", announcements::routes())
         .split_for_parts()
 }
 
@@ -70,6 +102,7 @@ pub fn routes() -> (Router<AppState>, OpenApi) {
 #[serde(rename_all = "camelCase")]
 struct ServerStats {
     user_count: i64,
+    /// Songs that are excluded from rankings don't count
     song_count: i64,
     score_count: i64,
 }
@@ -83,13 +116,13 @@ struct ServerStats {
         (status = INTERNAL_SERVER_ERROR, description = "Miscellaneous error", body = SimpleRouteErrorOutput)
     )
 )]
-async fn stats(State(state): State<AppState>) -> Result<Json<ServerStats>, RouteError> {
-    use crate::schema::{players, scores, songs};
+async fn stats(State(db): State<Db>) -> Result<Json<ServerStats>, RouteError> {
+    use crate::schema::{players, scores};
 
-    let mut conn = state.db.get().await?;
+    let mut conn = db.read_conn().await?;
 
     let user_count: i64 = players::table.count().get_result(&mut conn).await?;
-    let song_count: i64 = songs::table.count().get_result(&mut conn).await?;
+    let song_count: i64 = Song::ranked().count().get_result(&mut conn).await?;
     let score_count: i64 = scores::table.count().get_result(&mut conn).await?;
 
     Ok(Json(ServerStats {
@@ -98,3 +131,72 @@ async fn stats(State(state): State<AppState>) -> Result<Json<ServerStats>, Route
         score_count,
     }))
 }
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct OnlineStats {
+    /// Players who made a game request in the last 10 minutes
+    count: i64,
+    /// The most recently active of them, without those who hide their online status
+    #[serde(skip_serializing_if = "Option::is_none")]
+    players: Option<Vec<PlayerPublic>>,
+}
+
+#[serde_inline_default]
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct OnlineParams {
+    #[serde_inline_default(false)]
+    with_players: bool,
+}
+
+/// Get players online
+///
+/// Counts players who are in the game right now, going by their game requests in the last 10 minutes.
+#[utoipa::path(
+    method(get),
+    path = "/stats/online",
+    params(
+        ("withPlayers" = Option<bool>, Query, description = "Include up to 100 of the players who are online"),
+    ),
+    responses(
+        (status = OK, description = "Success", body = OnlineStats, content_type = "application/json"),
+        (status = INTERNAL_SERVER_ERROR, description = "Miscellaneous error", body = SimpleRouteErrorOutput)
+    )
+)]
+async fn online(
+    State(db): State<Db>,
+    State(redis): State<Cache>,
+    query: Query<OnlineParams>,
+) -> Result<Json<OnlineStats>, RouteError> {
+    use crate::schema::players;
+
+    let online = online_players(&redis).await?;
+
+    let players = if query.with_players {
+        let mut conn = db.read_conn().await?;
+
+        let mut players: Vec<Player> = players::table
+            .filter(players::steam_account_num.eq_any(&online))
+            .filter(players::hide_presence.eq(false))
+            .load(&mut conn)
+            .await?;
+        // Most recently active first, like the presence set
+        let positions: HashMap<i32, usize> = online
+            .iter()
+            .enumerate()
+            .map(|(position, &account_num)| (account_num, position))
+            .collect();
+        players.sort_by_key(|player| positions.get(&player.steam_account_num).copied());
+        players.truncate(MAX_ONLINE_LISTED);
+
+        Some(players.into_iter().map(PlayerPublic::from).collect())
+    } else {
+        None
+    };
+
+    Ok(Json(OnlineStats {
+        count: i64::try_from(online.len())?,
+        players,
+    }))
+}
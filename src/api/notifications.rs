@@ -0,0 +1,137 @@
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use serde::{Deserialize, Serialize};
+use serde_inline_default::serde_inline_default;
+use utoipa::ToSchema;
+use utoipa_axum::{router::OpenApiRouter, routes};
+use validator::Validate;
+
+use crate::{
+    models::notifications::Notification,
+    util::{
+        errors::{RouteError, SimpleRouteErrorOutput},
+        jwt::Claims,
+        validator::ValidatedQuery,
+    },
+    AppState, Db,
+};
+
+pub fn routes() -> OpenApiRouter<AppState> {
+    OpenApiRouter::new()
+        .routes(routes!(get_notifications))
+        .routes(routes!(mark_notification_read))
+}
+
+#[serde_inline_default]
+#[derive(Deserialize, Validate)]
+#[serde(rename_all = "camelCase")]
+struct GetNotificationsParams {
+    #[validate(range(min = 1))]
+    #[serde_inline_default(1)]
+    page: i64,
+    #[validate(range(min = 1, max = 50))]
+    #[serde_inline_default(10)]
+    page_size: i64,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct NotificationsResponse {
+    results: Vec<Notification>,
+    total: i64,
+    /// Number of notifications that haven't been read yet
+    unread: i64,
+}
+
+/// List own notifications
+///
+/// Lists the notifications of the player that is logged in, newest first.
+#[utoipa::path(
+    method(get),
+    path = "/",
+    params(
+        ("page" = Option<i64>, Query, description = "Page number", minimum = 1),
+        ("pageSize" = Option<i64>, Query, description = "Page size", minimum = 1, maximum = 50),
+    ),
+    responses(
+        (status = OK, description = "Success", body = NotificationsResponse, content_type = "application/json"),
+        (status = BAD_REQUEST, description = "Invalid query parameters", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = UNAUTHORIZED, description = "Not logged in or invalid token", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = INTERNAL_SERVER_ERROR, description = "Miscellaneous error", body = SimpleRouteErrorOutput)
+    ),
+    security(
+        ("token_jwt" = [])
+    )
+)]
+async fn get_notifications(
+    State(db): State<Db>,
+    claims: Claims,
+    ValidatedQuery(query): ValidatedQuery<GetNotificationsParams>,
+) -> Result<Json<NotificationsResponse>, RouteError> {
+    use crate::schema::notifications;
+
+    let mut conn = db.get().await?;
+
+    let total: i64 = notifications::table
+        .filter(notifications::player_id.eq(claims.profile.id))
+        .count()
+        .get_result(&mut conn)
+        .await?;
+    let unread: i64 = notifications::table
+        .filter(notifications::player_id.eq(claims.profile.id))
+        .filter(notifications::read_at.is_null())
+        .count()
+        .get_result(&mut conn)
+        .await?;
+    let results = notifications::table
+        .filter(notifications::player_id.eq(claims.profile.id))
+        .order((notifications::created_at.desc(), notifications::id.desc()))
+        .limit(query.page_size)
+        .offset((query.page - 1) * query.page_size)
+        .select(Notification::as_select())
+        .load(&mut conn)
+        .await?;
+
+    Ok(Json(NotificationsResponse {
+        results,
+        total,
+        unread,
+    }))
+}
+
+/// Mark own notification as read
+///
+/// Marking a notification that was already read does nothing.
+#[utoipa::path(
+    method(post),
+    path = "/{id}/read",
+    params(
+        ("id" = i32, Path, description = "ID of notification to mark as read"),
+    ),
+    responses(
+        (status = OK, description = "Success"),
+        (status = NOT_FOUND, description = "Notification not found", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = UNAUTHORIZED, description = "Not logged in or invalid token", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = INTERNAL_SERVER_ERROR, description = "Miscellaneous error", body = SimpleRouteErrorOutput)
+    ),
+    security(
+        ("token_jwt" = [])
+    )
+)]
+async fn mark_notification_read(
+    State(db): State<Db>,
+    claims: Claims,
+    Path(id): Path<i32>,
+) -> Result<(), RouteError> {
+    let mut conn = db.get().await?;
+
+    if !Notification::mark_read(id, claims.profile.id, &mut conn).await? {
+        return Err(RouteError::new_not_found());
+    }
+
+    Ok(())
+}
@@ -1,5 +1,8 @@
+use std::{collections::HashSet, sync::Arc};
+
 use axum::{
     extract::{Path, Query, State},
+    http::StatusCode,
     Json,
 };
 use diesel::prelude::*;
@@ -7,24 +10,64 @@ use diesel_async::RunQueryDsl;
 use fred::prelude::*;
 use serde::{Deserialize, Serialize};
 use serde_inline_default::serde_inline_default;
+use tracing::info;
 use utoipa::ToSchema;
 use utoipa_axum::{router::OpenApiRouter, routes};
 use validator::Validate;
 
+use super::{
+    rivals::{rivalry_response, GetRivalsParams, RivalryResponse},
+    songs::SongResponse,
+};
 use crate::{
-    models::players::{FavoriteCharacter, Player, PlayerPublic},
+    models::{
+        bans::{Ban, NewBan},
+        extra_song_info::ExtraSongInfo,
+        favorite_songs::{FavoriteSong, MAX_FAVORITE_SONGS},
+        player_name_history::NameChange,
+        players::{
+            AccountType, FavoriteCharacter, LeagueMedals, Player, PlayerProfileUpdate, PlayerPublic,
+        },
+        profile_shouts::{NewProfileShout, ProfileShout},
+        scores::Score,
+        shouts::{shout_rate_limit_key, ShoutError, SHOUT_RATE_LIMIT_WINDOW_SECS},
+        skill_point_decay::SkillPointDecay,
+        songs::Song,
+    },
     util::{
+        access,
         errors::{RouteError, SimpleRouteErrorOutput},
+        game_types::{Character, League},
+        i18n::Language,
         jwt::Claims,
+        leaderboard::leaderboard_key,
+        modifiers::{validate_modifier_filter, ModifierFilter},
+        query::SortType,
+        rate_limit,
+        session::revoke_all_sessions,
+        skill_points::SkillPointPolicy,
+        validator::{ValidatedJson, ValidatedQuery},
     },
-    AppState,
+    AppState, Cache, Config, Db,
 };
 
 pub fn routes() -> OpenApiRouter<AppState> {
     OpenApiRouter::new()
-        .routes(routes!(get_player))
-        .routes(routes!(get_self))
+        .routes(routes!(get_player, update_player))
+        .routes(routes!(get_self, update_self))
         .routes(routes!(get_player_rankings))
+        .routes(routes!(get_player_scores))
+        .routes(routes!(get_personal_bests))
+        .routes(routes!(get_character_stats))
+        .routes(routes!(get_medals))
+        .routes(routes!(get_name_history))
+        .routes(routes!(get_profile_shouts, add_profile_shout))
+        .routes(routes!(delete_profile_shout))
+        .routes(routes!(get_player_rivalries))
+        .routes(routes!(get_favorites))
+        .routes(routes!(update_own_favorites))
+        .routes(routes!(revoke_player_sessions))
+        .routes(routes!(ban_player, unban_player))
 }
 
 #[derive(Serialize, ToSchema)]
@@ -34,6 +77,20 @@ struct PlayerResponse {
     player: PlayerPublic,
     #[serde(skip_serializing_if = "Option::is_none")]
     stats: Option<PlayerStats>,
+    /// Only included for the player that is logged in
+    #[serde(skip_serializing_if = "Option::is_none")]
+    settings: Option<PlayerSettings>,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct PlayerSettings {
+    /// Language of in-game text, English if not set
+    preferred_language: Option<Language>,
+    /// Whether the player is left out of the list of players who are online and their last seen time is hidden
+    hide_presence: bool,
+    /// Whether others can't shout on the player's profile
+    disable_profile_shouts: bool,
 }
 
 #[derive(Serialize, ToSchema)]
@@ -42,7 +99,35 @@ struct PlayerStats {
     rank: i32,
     skill_points: i32,
     total_plays: i32,
+    /// Number of distinct songs the player has set a score on
+    songs_played: i64,
     favorite_character: Option<FavoriteCharacter>,
+    /// Whether the player's skill points are decaying on the leaderboards because they haven't played in a while
+    skill_points_decayed: bool,
+    /// The skill points without decay, same as `skillPoints` if they aren't decaying
+    undecayed_skill_points: i32,
+}
+
+/// Whether the player's skill points are decayed, and what they are without decay.
+async fn undecayed_skill_points(
+    player: &Player,
+    skill_points: i32,
+    policy: &SkillPointPolicy,
+    conn: &mut diesel_async::AsyncPgConnection,
+) -> Result<(bool, i32), RouteError> {
+    if !SkillPointDecay::multipliers(&[player.id], conn)
+        .await?
+        .contains_key(&player.id)
+    {
+        return Ok((false, skill_points));
+    }
+
+    let total: i64 = Player::calc_skill_points_batch(&[player.id], policy, conn)
+        .await?
+        .iter()
+        .map(|(_, _, points)| points)
+        .sum();
+    Ok((true, i32::try_from(total)?))
 }
 
 #[serde_inline_default]
@@ -68,13 +153,15 @@ struct GetPlayerParams {
     )
 )]
 async fn get_player(
-    State(state): State<AppState>,
+    State(config): State<Arc<Config>>,
+    State(db): State<Db>,
+    State(redis): State<Cache>,
     Path(id): Path<i32>,
     query: Query<GetPlayerParams>,
 ) -> Result<Json<PlayerResponse>, RouteError> {
     use crate::schema::players;
 
-    let mut conn = state.db.get().await?;
+    let mut conn = db.read_conn().await?;
 
     let player: Player = players::table
         .find(id)
@@ -84,11 +171,18 @@ async fn get_player(
         .ok_or_else(RouteError::new_not_found)?;
 
     let stats = if query.with_stats {
+        let (rank, skill_points) = player.get_rank_and_skill_points(None, &redis).await?;
+        let score_stats = player.get_score_stats(&mut conn).await?;
+        let (skill_points_decayed, undecayed_skill_points) =
+            undecayed_skill_points(&player, skill_points, &config.skill_points, &mut conn).await?;
         Some(PlayerStats {
-            rank: player.get_rank(&state.redis).await?,
-            skill_points: player.get_skill_points(&state.redis).await?,
-            total_plays: player.get_total_plays(&mut conn).await?,
-            favorite_character: player.get_favorite_character(&mut conn).await?,
+            rank,
+            skill_points,
+            total_plays: score_stats.total_plays,
+            songs_played: score_stats.songs_played,
+            favorite_character: score_stats.favorite_character,
+            skill_points_decayed,
+            undecayed_skill_points,
         })
     } else {
         None
@@ -97,6 +191,7 @@ async fn get_player(
     Ok(Json(PlayerResponse {
         player: player.into(),
         stats,
+        settings: None,
     }))
 }
 
@@ -117,13 +212,15 @@ async fn get_player(
     )
 )]
 async fn get_self(
-    State(state): State<AppState>,
+    State(config): State<Arc<Config>>,
+    State(db): State<Db>,
+    State(redis): State<Cache>,
     claims: Claims,
     query: Query<GetPlayerParams>,
 ) -> Result<Json<PlayerResponse>, RouteError> {
     use crate::schema::players;
 
-    let mut conn = state.db.get().await?;
+    let mut conn = db.get().await?;
 
     let player: Player = players::table
         .find(claims.profile.id)
@@ -131,21 +228,173 @@ async fn get_self(
         .await?;
 
     let stats = if query.with_stats {
+        let (rank, skill_points) = player.get_rank_and_skill_points(None, &redis).await?;
+        let score_stats = player.get_score_stats(&mut conn).await?;
+        let (skill_points_decayed, undecayed_skill_points) =
+            undecayed_skill_points(&player, skill_points, &config.skill_points, &mut conn).await?;
         Some(PlayerStats {
-            rank: player.get_rank(&state.redis).await?,
-            skill_points: player.get_skill_points(&state.redis).await?,
-            total_plays: player.get_total_plays(&mut conn).await?,
-            favorite_character: player.get_favorite_character(&mut conn).await?,
+            rank,
+            skill_points,
+            total_plays: score_stats.total_plays,
+            songs_played: score_stats.songs_played,
+            favorite_character: score_stats.favorite_character,
+            skill_points_decayed,
+            undecayed_skill_points,
         })
     } else {
         None
     };
     Ok(Json(PlayerResponse {
+        settings: Some(PlayerSettings {
+            preferred_language: player.preferred_language,
+            hide_presence: player.hide_presence,
+            disable_profile_shouts: player.disable_profile_shouts,
+        }),
         player: player.into(),
         stats,
     }))
 }
 
+#[derive(Deserialize, Validate, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct UpdateSelfRequest {
+    /// Language of in-game text. Left as it is if not given
+    preferred_language: Option<Language>,
+    /// Leave the player out of the list of players who are online and hide their last seen time. Left as it is if not given
+    hide_presence: Option<bool>,
+    /// Refuse new shouts on the player's profile. Left as it is if not given
+    disable_profile_shouts: Option<bool>,
+    #[serde(flatten)]
+    #[validate(nested)]
+    profile: PlayerProfileUpdate,
+}
+
+/// Update own profile and settings
+///
+/// Only the fields that are given are changed. Setting `bio` or `profileLinks` to `null` clears them.
+#[utoipa::path(
+    method(patch),
+    path = "/self",
+    request_body = UpdateSelfRequest,
+    responses(
+        (status = OK, description = "Success", body = PlayerResponse, content_type = "application/json"),
+        (status = BAD_REQUEST, description = "Invalid bio or links", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = UNAUTHORIZED, description = "Not logged in or invalid token", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = INTERNAL_SERVER_ERROR, description = "Miscellaneous error", body = SimpleRouteErrorOutput)
+    ),
+    security(
+        ("token_jwt" = [])
+    )
+)]
+async fn update_self(
+    State(db): State<Db>,
+    claims: Claims,
+    ValidatedJson(payload): ValidatedJson<UpdateSelfRequest>,
+) -> Result<Json<PlayerResponse>, RouteError> {
+    use crate::schema::players;
+
+    let mut conn = db.get().await?;
+
+    if let Some(language) = payload.preferred_language {
+        diesel::update(players::table.find(claims.profile.id))
+            .set(players::preferred_language.eq(language))
+            .execute(&mut conn)
+            .await?;
+    }
+    if let Some(hide_presence) = payload.hide_presence {
+        diesel::update(players::table.find(claims.profile.id))
+            .set(players::hide_presence.eq(hide_presence))
+            .execute(&mut conn)
+            .await?;
+    }
+    if let Some(disable_profile_shouts) = payload.disable_profile_shouts {
+        diesel::update(players::table.find(claims.profile.id))
+            .set(players::disable_profile_shouts.eq(disable_profile_shouts))
+            .execute(&mut conn)
+            .await?;
+    }
+    if !payload.profile.is_empty() {
+        diesel::update(players::table.find(claims.profile.id))
+            .set(&payload.profile)
+            .execute(&mut conn)
+            .await?;
+    }
+
+    let player: Player = players::table
+        .find(claims.profile.id)
+        .first(&mut conn)
+        .await?;
+
+    Ok(Json(PlayerResponse {
+        settings: Some(PlayerSettings {
+            preferred_language: player.preferred_language,
+            hide_presence: player.hide_presence,
+            disable_profile_shouts: player.disable_profile_shouts,
+        }),
+        player: player.into(),
+        stats: None,
+    }))
+}
+
+/// Update another player's profile
+///
+/// Lets moderators clean up bios and links. Only the fields that are given are changed.
+#[utoipa::path(
+    method(patch),
+    path = "/{id}",
+    params(
+        ("id" = i32, Path, description = "ID of player to update"),
+    ),
+    request_body = PlayerProfileUpdate,
+    responses(
+        (status = OK, description = "Success", body = PlayerPublic, content_type = "application/json"),
+        (status = BAD_REQUEST, description = "Invalid bio or links", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = NOT_FOUND, description = "Player not found", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = UNAUTHORIZED, description = "Not logged in, invalid token or not a moderator", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = INTERNAL_SERVER_ERROR, description = "Miscellaneous error", body = SimpleRouteErrorOutput)
+    ),
+    security(
+        ("token_jwt" = [])
+    )
+)]
+async fn update_player(
+    State(db): State<Db>,
+    claims: Claims,
+    Path(id): Path<i32>,
+    ValidatedJson(payload): ValidatedJson<PlayerProfileUpdate>,
+) -> Result<Json<PlayerPublic>, RouteError> {
+    use crate::schema::players;
+
+    if claims.profile.account_type != AccountType::Moderator
+        && claims.profile.account_type != AccountType::Team
+    {
+        return Err(RouteError::new_unauthorized());
+    }
+
+    let mut conn = db.get().await?;
+
+    if !payload.is_empty() {
+        diesel::update(players::table.find(id))
+            .set(&payload)
+            .execute(&mut conn)
+            .await?;
+    }
+
+    let player: Player = players::table
+        .find(id)
+        .first(&mut conn)
+        .await
+        .optional()?
+        .ok_or_else(RouteError::new_not_found)?;
+
+    info!(
+        "Player {} updated the profile of player {}",
+        claims.profile.id, player.id
+    );
+
+    Ok(Json(player.into()))
+}
+
 #[derive(Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 struct PlayerRankingResponse {
@@ -170,15 +419,190 @@ struct GetRankingsParams {
     #[validate(range(min = 1, max = 50))]
     #[serde_inline_default(10)]
     page_size: i64,
+    #[serde(default, with = "time::serde::iso8601::option")]
+    active_since: Option<time::OffsetDateTime>,
+    league: Option<League>,
+    location_id: Option<i32>,
+}
+
+/// How many leaderboard entries are checked against the rankings filters at once
+const FILTER_CHUNK_SIZE: i64 = 500;
+
+/// Fewest skill points a player needs to be ranked on the leaderboard of `league`.
+/// League leaderboards only rank players who earned points there,
+/// the global one also has players who haven't yet.
+const fn min_ranked_points(league: Option<League>) -> f64 {
+    if league.is_some() {
+        1.0
+    } else {
+        f64::NEG_INFINITY
+    }
+}
+
+/// Puts leaderboard entries in ranking order: most skill points first, and the lower ID first among equals.
+/// Redis orders equal points by comparing the IDs as strings, which can put them on different pages.
+fn rank_entries(entries: &mut [(i32, f64)]) {
+    entries.sort_by(|(id, points), (other_id, other_points)| {
+        other_points.total_cmp(points).then(id.cmp(other_id))
+    });
+}
+
+/// Whether a walk down the leaderboard that found `matching` and last saw `last_seen_points` can stop.
+/// It needs `wanted` entries, and all entries tied with the last wanted one,
+/// since Redis might have put one of them after it that ranks before it.
+fn walk_complete(matching: &[(i32, f64)], wanted: usize, last_seen_points: f64) -> bool {
+    wanted
+        .checked_sub(1)
+        .and_then(|last_wanted| matching.get(last_wanted))
+        .is_some_and(|&(_, points)| last_seen_points < points)
+}
+
+/// Players matching the activity and location filters in `query`, in SQL since the leaderboard doesn't know about them
+fn filtered_players(
+    query: &GetRankingsParams,
+    policy: &SkillPointPolicy,
+) -> crate::schema::players::BoxedQuery<'static, diesel::pg::Pg> {
+    use diesel::{dsl::sql, sql_types::Bool};
+
+    use crate::schema::{players, scores};
+
+    let mut filtered = players::table.into_boxed();
+    if let Some(active_since) = query.active_since {
+        filtered = filtered.filter(players::last_played_at.ge(active_since));
+    }
+    if let Some(location_id) = query.location_id {
+        filtered = filtered.filter(players::location_id.eq(location_id));
+    }
+    // Only players with scores are on the leaderboard, and only those with points on a league's
+    let mut with_scores = scores::table.select(scores::player_id).into_boxed();
+    if let Some(league) = query.league {
+        with_scores = with_scores
+            .filter(scores::league.eq(league))
+            .filter(sql::<Bool>(&format!("ROUND({}) > 0", policy.sql_points())));
+    }
+    filtered.filter(players::id.eq_any(with_scores))
+}
+
+/// A page of the leaderboard in `key`, in the order of `rank_entries`.
+async fn leaderboard_page(
+    key: &str,
+    query: &GetRankingsParams,
+    redis: &Cache,
+) -> Result<(Vec<i32>, i64), RouteError> {
+    let min_points = min_ranked_points(query.league);
+    let total: i64 = redis.zcount(key, min_points, f64::INFINITY).await?;
+
+    let start = (query.page - 1) * query.page_size;
+    let slice: Vec<(i32, f64)> = redis
+        .zrevrangebyscore(
+            key,
+            f64::INFINITY,
+            min_points,
+            true,
+            Some((start, query.page_size)),
+        )
+        .await?;
+    let (Some(&(_, top)), Some(&(_, bottom))) = (slice.first(), slice.last()) else {
+        return Ok((vec![], total));
+    };
+
+    // The ties at both ends of the page might be split up differently, so all of them are needed
+    let mut candidates: Vec<(i32, f64)> =
+        redis.zrevrangebyscore(key, top, bottom, true, None).await?;
+    let Some(&(first_candidate, _)) = candidates.first() else {
+        return Ok((vec![], total));
+    };
+    let candidates_start: Option<i64> = redis.zrevrank(key, first_candidate, false).await?;
+    rank_entries(&mut candidates);
+
+    let page = candidates
+        .into_iter()
+        .skip(usize::try_from(start - candidates_start.unwrap_or(start))?)
+        .take(usize::try_from(query.page_size)?)
+        .map(|(id, _)| id)
+        .collect();
+    Ok((page, total))
+}
+
+/// A page of the leaderboard in `key`, only counting the players matching the filters in `query`.
+/// The leaderboard is walked from the top in chunks until the page is full,
+/// so pages near the top don't need to look at every player.
+async fn filtered_leaderboard_page(
+    key: &str,
+    query: &GetRankingsParams,
+    policy: &SkillPointPolicy,
+    conn: &mut diesel_async::AsyncPgConnection,
+    redis: &Cache,
+) -> Result<(Vec<i32>, i64), RouteError> {
+    use crate::schema::players;
+
+    let total: i64 = filtered_players(query, policy)
+        .count()
+        .get_result(conn)
+        .await?;
+
+    let min_points = min_ranked_points(query.league);
+    let wanted = usize::try_from(query.page * query.page_size)?;
+    let mut matching: Vec<(i32, f64)> = Vec::new();
+    let mut start = 0;
+    loop {
+        let chunk: Vec<(i32, f64)> = redis
+            .zrevrangebyscore(
+                key,
+                f64::INFINITY,
+                min_points,
+                true,
+                Some((start, FILTER_CHUNK_SIZE)),
+            )
+            .await?;
+        let Some(&(_, last_seen_points)) = chunk.last() else {
+            break;
+        };
+        let chunk_ids: Vec<i32> = chunk.iter().map(|&(id, _)| id).collect();
+        let chunk_matches: HashSet<i32> = filtered_players(query, policy)
+            .filter(players::id.eq_any(&chunk_ids))
+            .select(players::id)
+            .load::<i32>(conn)
+            .await?
+            .into_iter()
+            .collect();
+        let chunk_len = i64::try_from(chunk.len())?;
+        matching.extend(
+            chunk
+                .into_iter()
+                .filter(|(id, _)| chunk_matches.contains(id)),
+        );
+
+        if walk_complete(&matching, wanted, last_seen_points) || chunk_len < FILTER_CHUNK_SIZE {
+            break;
+        }
+        start += FILTER_CHUNK_SIZE;
+    }
+
+    rank_entries(&mut matching);
+    let page = matching
+        .into_iter()
+        .skip(usize::try_from((query.page - 1) * query.page_size)?)
+        .take(usize::try_from(query.page_size)?)
+        .map(|(id, _)| id)
+        .collect();
+    Ok((page, total))
 }
 
 /// Get player rankings
+///
+/// Players with equal skill points are ordered by ID. When ranking by a league, players without points there are left out.
+///
+/// Served from the read replica if one is configured, so very recent scores might not show up yet.
 #[utoipa::path(
     method(get),
     path = "/rankings",
     params(
         ("page" = Option<i64>, Query, description = "Page number", minimum = 1),
         ("pageSize" = Option<i64>, Query, description = "Page size", minimum = 1, maximum = 50),
+        ("activeSince" = Option<time::OffsetDateTime>, Query, description = "Only include players who played since this time"),
+        ("league" = Option<League>, Query, description = "Rank by skill points in this league only, instead of across all leagues"),
+        ("locationId" = Option<i32>, Query, description = "Only include players from this location"),
     ),
     responses(
         (status = OK, description = "Success", body = PlayerRankingResponse, content_type = "application/json"),
@@ -186,22 +610,22 @@ struct GetRankingsParams {
     )
 )]
 async fn get_player_rankings(
-    State(state): State<AppState>,
+    State(config): State<Arc<Config>>,
+    State(db): State<Db>,
+    State(redis): State<Cache>,
     query: Query<GetRankingsParams>,
 ) -> Result<Json<PlayerRankingResponse>, RouteError> {
     use crate::schema::players;
 
-    let mut conn = state.db.get().await?;
+    let mut conn = db.read_conn().await?;
+    let key = leaderboard_key(query.league);
 
-    let leaderboard: Vec<i32> = state
-        .redis
-        .zrevrange(
-            "leaderboard",
-            (query.page - 1) * query.page_size,
-            query.page * query.page_size - 1,
-            false,
-        )
-        .await?;
+    let (leaderboard, total): (Vec<i32>, i64) =
+        if query.active_since.is_some() || query.location_id.is_some() {
+            filtered_leaderboard_page(key, &query, &config.skill_points, &mut conn, &redis).await?
+        } else {
+            leaderboard_page(key, &query, &redis).await?
+        };
 
     let mut players = players::table
         .filter(players::id.eq_any(&leaderboard))
@@ -213,12 +637,1344 @@ async fn get_player_rankings(
     for player in players {
         results.push(PlayerWithRanking {
             player: player.clone().into(),
-            skill_points: player.get_skill_points(&state.redis).await?,
+            skill_points: player.get_skill_points(query.league, &redis).await?,
         });
     }
 
-    Ok(Json(PlayerRankingResponse {
-        results,
-        total: state.redis.zcard("leaderboard").await?,
-    }))
+    Ok(Json(PlayerRankingResponse { results, total }))
+}
+
+#[serde_inline_default]
+#[derive(Deserialize, Validate)]
+#[serde(rename_all = "camelCase")]
+struct GetPlayerScoresParams {
+    #[serde_inline_default(true)]
+    with_song: bool,
+    #[serde_inline_default(false)]
+    with_extra_info: bool,
+    #[validate(range(min = 1))]
+    #[serde_inline_default(1)]
+    page: i64,
+    #[validate(range(min = 1, max = 50))]
+    #[serde_inline_default(10)]
+    page_size: i64,
+    time_sort: Option<SortType>,
+    score_sort: Option<SortType>,
+    league: Option<League>,
+    character: Option<Character>,
+    #[validate(custom(function = "validate_modifier_filter"))]
+    modifier: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct PlayerScore {
+    #[serde(flatten)]
+    score: Score,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    song: Option<Song>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    extra_info: Option<ExtraSongInfo>,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct PlayerScoresResponse {
+    results: Vec<PlayerScore>,
+    total: i64,
+}
+
+/// All of a player's scores matching the filters in `query`, without ordering or pagination.
+fn filtered_player_scores(
+    player_id: i32,
+    query: &GetPlayerScoresParams,
+) -> crate::schema::scores::BoxedQuery<'static, diesel::pg::Pg> {
+    use crate::schema::scores;
+
+    let mut db_query = scores::table
+        .filter(scores::player_id.eq(player_id))
+        .into_boxed();
+    if let Some(league) = query.league {
+        db_query = db_query.filter(scores::league.eq(league));
+    }
+    if let Some(character) = query.character {
+        db_query = db_query.filter(scores::vehicle.eq(character));
+    }
+    if let Some(modifier_filter) = query.modifier.as_deref().and_then(ModifierFilter::parse) {
+        db_query = db_query.filter(scores::song_id.eq_any(modifier_filter.matching_song_ids()));
+    }
+
+    db_query
+}
+
+/// Get player's scores
+///
+/// Served from the read replica if one is configured, so very recent scores might not show up yet.
+#[utoipa::path(
+    method(get),
+    path = "/{id}/scores",
+    params(
+        ("id" = i32, Path, description = "ID of player to get scores of"),
+        ("withSong" = Option<bool>, Query, description = "Include song info"),
+        ("withExtraInfo" = Option<bool>, Query, description = "Include extra song info, only if `withSong` is set"),
+        ("page" = Option<i64>, Query, description = "Page number", minimum = 1),
+        ("pageSize" = Option<i64>, Query, description = "Page size", minimum = 1, maximum = 50),
+        ("timeSort" = Option<SortType>, Query, description = "Sort by submission time"),
+        ("scoreSort" = Option<SortType>, Query, description = "Sort by score"),
+        ("league" = Option<League>, Query, description = "League to filter by"),
+        ("character" = Option<Character>, Query, description = "Character to filter by"),
+        ("modifier" = Option<String>, Query, description = "Only include scores on songs with all of these comma-separated modifiers (`steep` or `flat`, in any case), or `none` for songs without any"),
+    ),
+    responses(
+        (status = OK, description = "Success", body = PlayerScoresResponse, content_type = "application/json"),
+        (status = BAD_REQUEST, description = "Invalid query parameters", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = NOT_FOUND, description = "Player not found", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = INTERNAL_SERVER_ERROR, description = "Miscellaneous error", body = SimpleRouteErrorOutput)
+    )
+)]
+async fn get_player_scores(
+    State(db): State<Db>,
+    Path(id): Path<i32>,
+    ValidatedQuery(query): ValidatedQuery<GetPlayerScoresParams>,
+) -> Result<Json<PlayerScoresResponse>, RouteError> {
+    use crate::schema::{extra_song_info, players, scores, songs};
+
+    let mut conn = db.read_conn().await?;
+
+    let exists: bool = diesel::select(diesel::dsl::exists(players::table.find(id)))
+        .get_result(&mut conn)
+        .await?;
+    if !exists {
+        return Err(RouteError::new_not_found());
+    }
+
+    let total: i64 = filtered_player_scores(id, &query)
+        .count()
+        .get_result(&mut conn)
+        .await?;
+
+    let mut db_query = filtered_player_scores(id, &query);
+    if let Some(time_sort) = &query.time_sort {
+        match time_sort {
+            SortType::Asc => db_query = db_query.then_order_by(scores::submitted_at.asc()),
+            SortType::Desc => db_query = db_query.then_order_by(scores::submitted_at.desc()),
+        }
+    }
+    if let Some(score_sort) = &query.score_sort {
+        match score_sort {
+            SortType::Asc => db_query = db_query.then_order_by(scores::score.asc()),
+            SortType::Desc => db_query = db_query.then_order_by(scores::score.desc()),
+        }
+    }
+    db_query = db_query
+        .offset((query.page - 1) * query.page_size)
+        .limit(query.page_size);
+
+    let results = match (query.with_song, query.with_extra_info) {
+        (true, true) => {
+            let items: Vec<(Score, Song, Option<ExtraSongInfo>)> = db_query
+                .inner_join(songs::table.left_join(extra_song_info::table))
+                .select((
+                    Score::as_select(),
+                    Song::as_select(),
+                    Option::<ExtraSongInfo>::as_select(),
+                ))
+                .load(&mut conn)
+                .await?;
+
+            items
+                .into_iter()
+                .map(|(score, song, extra_info)| PlayerScore {
+                    score,
+                    song: Some(song),
+                    extra_info,
+                })
+                .collect()
+        }
+        (true, false) => {
+            let items: Vec<(Score, Song)> = db_query
+                .inner_join(songs::table)
+                .select((Score::as_select(), Song::as_select()))
+                .load(&mut conn)
+                .await?;
+
+            items
+                .into_iter()
+                .map(|(score, song)| PlayerScore {
+                    score,
+                    song: Some(song),
+                    extra_info: None,
+                })
+                .collect()
+        }
+        (false, _) => {
+            let items: Vec<Score> = db_query.load(&mut conn).await?;
+
+            items
+                .into_iter()
+                .map(|score| PlayerScore {
+                    score,
+                    song: None,
+                    extra_info: None,
+                })
+                .collect()
+        }
+    };
+
+    Ok(Json(PlayerScoresResponse { results, total }))
+}
+
+#[serde_inline_default]
+#[derive(Deserialize, Validate)]
+#[serde(rename_all = "camelCase")]
+struct GetNameHistoryParams {
+    #[validate(range(min = 1))]
+    #[serde_inline_default(1)]
+    page: i64,
+    #[validate(range(min = 1, max = 50))]
+    #[serde_inline_default(10)]
+    page_size: i64,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct NameHistoryResponse {
+    results: Vec<NameChange>,
+    total: i64,
+}
+
+/// Get player's name history
+///
+/// Lists the player's changes of their Steam name, newest first.
+#[utoipa::path(
+    method(get),
+    path = "/{id}/nameHistory",
+    params(
+        ("id" = i32, Path, description = "ID of player to get name history of"),
+        ("page" = Option<i64>, Query, description = "Page number", minimum = 1),
+        ("pageSize" = Option<i64>, Query, description = "Page size", minimum = 1, maximum = 50),
+    ),
+    responses(
+        (status = OK, description = "Success", body = NameHistoryResponse, content_type = "application/json"),
+        (status = BAD_REQUEST, description = "Invalid query parameters", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = NOT_FOUND, description = "Player not found", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = INTERNAL_SERVER_ERROR, description = "Miscellaneous error", body = SimpleRouteErrorOutput)
+    )
+)]
+async fn get_name_history(
+    State(db): State<Db>,
+    Path(id): Path<i32>,
+    ValidatedQuery(query): ValidatedQuery<GetNameHistoryParams>,
+) -> Result<Json<NameHistoryResponse>, RouteError> {
+    use crate::schema::{player_name_history, players};
+
+    let mut conn = db.read_conn().await?;
+
+    let exists: bool = diesel::select(diesel::dsl::exists(players::table.find(id)))
+        .get_result(&mut conn)
+        .await?;
+    if !exists {
+        return Err(RouteError::new_not_found());
+    }
+
+    let total: i64 = player_name_history::table
+        .filter(player_name_history::player_id.eq(id))
+        .count()
+        .get_result(&mut conn)
+        .await?;
+    let results = player_name_history::table
+        .filter(player_name_history::player_id.eq(id))
+        .order(player_name_history::changed_at.desc())
+        .limit(query.page_size)
+        .offset((query.page - 1) * query.page_size)
+        .select(NameChange::as_select())
+        .load(&mut conn)
+        .await?;
+
+    Ok(Json(NameHistoryResponse { results, total }))
+}
+
+#[serde_inline_default]
+#[derive(Deserialize, Validate)]
+#[serde(rename_all = "camelCase")]
+struct GetProfileShoutsParams {
+    #[validate(range(min = 1))]
+    #[serde_inline_default(1)]
+    page: i64,
+    #[validate(range(min = 1, max = 50))]
+    #[serde_inline_default(10)]
+    page_size: i64,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct ProfileShoutsResult {
+    shout: ProfileShout,
+    author: PlayerPublic,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct ProfileShoutsResponse {
+    results: Vec<ProfileShoutsResult>,
+    total: i64,
+}
+
+/// Get player's profile shouts
+///
+/// Lists the shouts on the player's profile, newest first.
+#[utoipa::path(
+    method(get),
+    path = "/{id}/shouts",
+    params(
+        ("id" = i32, Path, description = "ID of player to get profile shouts of"),
+        ("page" = Option<i64>, Query, description = "Page number", minimum = 1),
+        ("pageSize" = Option<i64>, Query, description = "Page size", minimum = 1, maximum = 50),
+    ),
+    responses(
+        (status = OK, description = "Success", body = ProfileShoutsResponse, content_type = "application/json"),
+        (status = BAD_REQUEST, description = "Invalid query parameters", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = NOT_FOUND, description = "Player not found", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = INTERNAL_SERVER_ERROR, description = "Miscellaneous error", body = SimpleRouteErrorOutput)
+    )
+)]
+async fn get_profile_shouts(
+    State(db): State<Db>,
+    Path(id): Path<i32>,
+    ValidatedQuery(query): ValidatedQuery<GetProfileShoutsParams>,
+) -> Result<Json<ProfileShoutsResponse>, RouteError> {
+    use crate::schema::{players, profile_shouts};
+
+    let mut conn = db.read_conn().await?;
+
+    let exists: bool = diesel::select(diesel::dsl::exists(players::table.find(id)))
+        .get_result(&mut conn)
+        .await?;
+    if !exists {
+        return Err(RouteError::new_not_found());
+    }
+
+    let total: i64 = profile_shouts::table
+        .filter(profile_shouts::profile_owner_id.eq(id))
+        .count()
+        .get_result(&mut conn)
+        .await?;
+    let results = profile_shouts::table
+        .filter(profile_shouts::profile_owner_id.eq(id))
+        .inner_join(players::table)
+        .order((profile_shouts::posted_at.desc(), profile_shouts::id.desc()))
+        .limit(query.page_size)
+        .offset((query.page - 1) * query.page_size)
+        .select((ProfileShout::as_select(), Player::as_select()))
+        .load::<(ProfileShout, Player)>(&mut conn)
+        .await?
+        .into_iter()
+        .map(|(shout, author)| ProfileShoutsResult {
+            shout,
+            author: author.into(),
+        })
+        .collect();
+
+    Ok(Json(ProfileShoutsResponse { results, total }))
+}
+
+#[derive(Deserialize, ToSchema)]
+struct AddProfileShoutRequest {
+    /// Whitespace around it is trimmed off
+    content: String,
+}
+
+/// Post a shout on a player's profile
+///
+/// Checked like song shouts: they can't be empty, too long, the same as the author's
+/// previous shout on the profile, or posted too often. Refused if the player disabled profile shouts.
+#[utoipa::path(
+    method(post),
+    path = "/{id}/shouts",
+    params(
+        ("id" = i32, Path, description = "ID of player to shout on"),
+    ),
+    request_body = AddProfileShoutRequest,
+    responses(
+        (status = OK, description = "Success", body = ProfileShoutsResult, content_type = "application/json"),
+        (status = BAD_REQUEST, description = "Shout isn't allowed", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = UNAUTHORIZED, description = "Not logged in", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = FORBIDDEN, description = "Player disabled profile shouts", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = NOT_FOUND, description = "Player not found", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = TOO_MANY_REQUESTS, description = "Too many shouts in a short time", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = INTERNAL_SERVER_ERROR, description = "Miscellaneous error", body = SimpleRouteErrorOutput)
+    ),
+    security(
+        ("token_jwt" = [])
+    )
+)]
+async fn add_profile_shout(
+    State(db): State<Db>,
+    State(redis): State<Cache>,
+    State(config): State<Arc<Config>>,
+    claims: Claims,
+    Path(id): Path<i32>,
+    Json(request): Json<AddProfileShoutRequest>,
+) -> Result<Json<ProfileShoutsResult>, RouteError> {
+    use crate::schema::players;
+
+    let mut conn = db.get().await?;
+
+    let disabled: bool = players::table
+        .find(id)
+        .select(players::disable_profile_shouts)
+        .first(&mut conn)
+        .await
+        .optional()?
+        .ok_or_else(RouteError::new_not_found)?;
+    if disabled {
+        return Err(RouteError::new_forbidden()
+            .set_public_error_message("This player doesn't allow shouts on their profile"));
+    }
+
+    let max_per_minute = config.shouts.max_per_minute;
+    if !rate_limit::allows(
+        &shout_rate_limit_key(claims.profile.id),
+        max_per_minute,
+        SHOUT_RATE_LIMIT_WINDOW_SECS,
+        &redis,
+    )
+    .await
+    {
+        return Err(RouteError::from_status(StatusCode::TOO_MANY_REQUESTS)
+            .set_public_error_message(&format!(
+                "You can only post {max_per_minute} shouts per minute"
+            )));
+    }
+
+    let shout = match NewProfileShout::new(id, claims.profile.id, &request.content)
+        .insert(&mut conn)
+        .await
+    {
+        Ok(shout) => shout,
+        Err(ShoutError::Database(e)) => return Err(e.into()),
+        Err(e) => {
+            return Err(RouteError::new_bad_request().set_public_error_message(&e.to_string()))
+        }
+    };
+
+    // The profile in the token might be outdated, e.g. after a name change
+    let author: Player = players::table
+        .find(claims.profile.id)
+        .first(&mut conn)
+        .await?;
+
+    info!(
+        "Player {} shouted {} on the profile of player {id}",
+        author.id, shout.id
+    );
+    Ok(Json(ProfileShoutsResult {
+        shout,
+        author: author.into(),
+    }))
+}
+
+/// Delete a shout on a player's profile
+///
+/// Only the author, the profile's owner, moderators and team members can delete it.
+#[utoipa::path(
+    method(delete),
+    path = "/{id}/shouts/{shout_id}",
+    params(
+        ("id" = i32, Path, description = "ID of player the shout is on"),
+        ("shout_id" = i32, Path, description = "ID of shout to delete"),
+    ),
+    responses(
+        (status = OK, description = "Success"),
+        (status = UNAUTHORIZED, description = "Not logged in or no permission", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = NOT_FOUND, description = "Shout not found", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = INTERNAL_SERVER_ERROR, description = "Miscellaneous error", body = SimpleRouteErrorOutput)
+    ),
+    security(
+        ("token_jwt" = [])
+    )
+)]
+async fn delete_profile_shout(
+    State(db): State<Db>,
+    claims: Claims,
+    Path((id, shout_id)): Path<(i32, i32)>,
+) -> Result<(), RouteError> {
+    use crate::schema::profile_shouts;
+
+    let mut conn = db.get().await?;
+
+    let shout: ProfileShout = profile_shouts::table
+        .find(shout_id)
+        .filter(profile_shouts::profile_owner_id.eq(id))
+        .select(ProfileShout::as_select())
+        .first(&mut conn)
+        .await
+        .optional()?
+        .ok_or_else(RouteError::new_not_found)?;
+    if !shout.can_be_deleted_by(claims.profile.id, claims.profile.account_type) {
+        return Err(RouteError::new_unauthorized());
+    }
+
+    diesel::delete(&shout).execute(&mut conn).await?;
+
+    info!(
+        "Player {} deleted profile shout {shout_id} of player {id}",
+        claims.profile.id
+    );
+    Ok(())
+}
+
+/// Get player's rivalries
+///
+/// Lists who the player has added as a rival, and who has added them, newest first.
+/// Paginated like `/rivals/self`.
+#[utoipa::path(
+    method(get),
+    path = "/{id}/rivals",
+    params(
+        ("id" = i32, Path, description = "ID of player to get rivalries of"),
+        ("page" = Option<i64>, Query, description = "Page number", minimum = 1),
+        ("pageSize" = Option<i64>, Query, description = "Page size", minimum = 1, maximum = 50),
+    ),
+    responses(
+        (status = OK, description = "Success", body = RivalryResponse, content_type = "application/json"),
+        (status = BAD_REQUEST, description = "Invalid query parameters", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = NOT_FOUND, description = "Player not found", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = INTERNAL_SERVER_ERROR, description = "Miscellaneous error", body = SimpleRouteErrorOutput)
+    )
+)]
+async fn get_player_rivalries(
+    State(db): State<Db>,
+    Path(id): Path<i32>,
+    ValidatedQuery(query): ValidatedQuery<GetRivalsParams>,
+) -> Result<Json<RivalryResponse>, RouteError> {
+    let mut conn = db.read_conn().await?;
+
+    let player: Player = crate::schema::players::table
+        .find(id)
+        .first(&mut conn)
+        .await
+        .optional()?
+        .ok_or_else(RouteError::new_not_found)?;
+
+    Ok(Json(rivalry_response(&player, &query, &mut conn).await?))
+}
+
+#[serde_inline_default]
+#[derive(Deserialize, Validate)]
+#[serde(rename_all = "camelCase")]
+struct GetPersonalBestsParams {
+    song_id: Option<i32>,
+    #[validate(range(min = 1, max = 50))]
+    #[serde_inline_default(10)]
+    limit: i64,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct PersonalBest {
+    #[serde(flatten)]
+    score: Score,
+    skill_points: i32,
+    song: Song,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    extra_info: Option<ExtraSongInfo>,
+}
+
+/// Get player's personal bests
+///
+/// With `songId`, this returns the player's best score in every league on that song.
+/// Without it, this returns the player's top scores across all songs, ordered by skill points.
+/// Served from the read replica if one is configured, so very recent scores might not show up yet.
+#[utoipa::path(
+    method(get),
+    path = "/{id}/personalBests",
+    params(
+        ("id" = i32, Path, description = "ID of player to get personal bests of"),
+        ("songId" = Option<i32>, Query, description = "Only get personal bests on this song"),
+        ("limit" = Option<i64>, Query, description = "Maximum number of scores when not filtering by song", minimum = 1, maximum = 50),
+    ),
+    responses(
+        (status = OK, description = "Success", body = Vec<PersonalBest>, content_type = "application/json"),
+        (status = BAD_REQUEST, description = "Invalid query parameters", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = NOT_FOUND, description = "Player not found", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = INTERNAL_SERVER_ERROR, description = "Miscellaneous error", body = SimpleRouteErrorOutput)
+    )
+)]
+async fn get_personal_bests(
+    State(config): State<Arc<Config>>,
+    State(db): State<Db>,
+    Path(id): Path<i32>,
+    ValidatedQuery(query): ValidatedQuery<GetPersonalBestsParams>,
+) -> Result<Json<Vec<PersonalBest>>, RouteError> {
+    use diesel::{dsl::sql, sql_types::Numeric};
+
+    use crate::schema::{extra_song_info, players, scores, songs};
+
+    let mut conn = db.read_conn().await?;
+
+    let player: Player = players::table
+        .find(id)
+        .first(&mut conn)
+        .await
+        .optional()?
+        .ok_or_else(RouteError::new_not_found)?;
+
+    let items: Vec<(Score, Song, Option<ExtraSongInfo>)> = if let Some(song_id) = query.song_id {
+        scores::table
+            .inner_join(songs::table.left_join(extra_song_info::table))
+            .filter(scores::player_id.eq(player.id))
+            .filter(scores::song_id.eq(song_id))
+            .distinct_on(scores::league)
+            .order_by((scores::league.asc(), scores::score.desc()))
+            .select((
+                Score::as_select(),
+                Song::as_select(),
+                Option::<ExtraSongInfo>::as_select(),
+            ))
+            .load(&mut conn)
+            .await?
+    } else {
+        // Same policy as Score::calc_skill_points, so the order matches the returned values
+        scores::table
+            .inner_join(songs::table.left_join(extra_song_info::table))
+            .filter(scores::player_id.eq(player.id))
+            .order_by(sql::<Numeric>(&format!(
+                "{} DESC NULLS LAST",
+                config.skill_points.sql_points()
+            )))
+            .limit(query.limit)
+            .select((
+                Score::as_select(),
+                Song::as_select(),
+                Option::<ExtraSongInfo>::as_select(),
+            ))
+            .load(&mut conn)
+            .await?
+    };
+
+    let results = items
+        .into_iter()
+        .map(|(score, song, extra_info)| PersonalBest {
+            skill_points: score.calc_skill_points(&config.skill_points),
+            score,
+            song,
+            extra_info,
+        })
+        .collect();
+
+    Ok(Json(results))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GetMedalsParams {
+    #[serde(default)] // default to false
+    with_songs: bool,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct MedalsResponse {
+    /// One entry per league, Casual first
+    leagues: Vec<LeagueMedals>,
+    /// Songs the player holds #1 on, only if `withSongs` is set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    gold_songs: Option<Vec<GoldSong>>,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct GoldSong {
+    league: League,
+    song: Song,
+}
+
+/// Get player's medals
+///
+/// Counts the songs where the player has the #1 (gold), #2 (silver) or #3 (bronze) score in each league.
+/// Equal scores are ordered by submission time, like on the leaderboard.
+#[utoipa::path(
+    method(get),
+    path = "/{id}/medals",
+    params(
+        ("id" = i32, Path, description = "ID of player to get medals of"),
+        ("withSongs" = Option<bool>, Query, description = "Include the songs the player holds #1 on"),
+    ),
+    responses(
+        (status = OK, description = "Success", body = MedalsResponse, content_type = "application/json"),
+        (status = NOT_FOUND, description = "Player not found", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = INTERNAL_SERVER_ERROR, description = "Miscellaneous error", body = SimpleRouteErrorOutput)
+    )
+)]
+async fn get_medals(
+    State(db): State<Db>,
+    Path(id): Path<i32>,
+    Query(query): Query<GetMedalsParams>,
+) -> Result<Json<MedalsResponse>, RouteError> {
+    use std::collections::HashMap;
+
+    use crate::schema::{players, songs};
+
+    let mut conn = db.read_conn().await?;
+
+    let player: Player = players::table
+        .find(id)
+        .first(&mut conn)
+        .await
+        .optional()?
+        .ok_or_else(RouteError::new_not_found)?;
+
+    let leagues = player.medal_counts(&mut conn).await?;
+
+    let gold_songs = if query.with_songs {
+        let first_places = player.first_places(&mut conn).await?;
+        let song_ids: Vec<i32> = first_places.iter().map(|(_, song_id)| *song_id).collect();
+        let songs: HashMap<i32, Song> = songs::table
+            .filter(songs::id.eq_any(song_ids))
+            .select(Song::as_select())
+            .load::<Song>(&mut conn)
+            .await?
+            .into_iter()
+            .map(|song| (song.id, song))
+            .collect();
+        Some(
+            first_places
+                .into_iter()
+                .filter_map(|(league, song_id)| {
+                    Some(GoldSong {
+                        league,
+                        song: songs.get(&song_id)?.clone(),
+                    })
+                })
+                .collect(),
+        )
+    } else {
+        None
+    };
+
+    Ok(Json(MedalsResponse {
+        leagues,
+        gold_songs,
+    }))
+}
+
+#[derive(Deserialize, Validate)]
+#[serde(rename_all = "camelCase")]
+struct GetCharacterStatsParams {
+    league: Option<League>,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct CharacterStats {
+    character: Character,
+    score_count: i64,
+    skill_points: i64,
+    /// The player's highest score with this character
+    best_score: Score,
+    /// Song of the best score
+    song: Song,
+}
+
+/// Get player's stats per character
+///
+/// For every character the player has set a score with, returns how many scores they have with it,
+/// the skill points those are worth, and their highest one. Ordered by number of scores, descending.
+#[utoipa::path(
+    method(get),
+    path = "/{id}/characterStats",
+    params(
+        ("id" = i32, Path, description = "ID of player to get character stats of"),
+        ("league" = Option<League>, Query, description = "Only count scores in this league"),
+    ),
+    responses(
+        (status = OK, description = "Success", body = Vec<CharacterStats>, content_type = "application/json"),
+        (status = BAD_REQUEST, description = "Invalid query parameters", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = NOT_FOUND, description = "Player not found", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = INTERNAL_SERVER_ERROR, description = "Miscellaneous error", body = SimpleRouteErrorOutput)
+    )
+)]
+async fn get_character_stats(
+    State(config): State<Arc<Config>>,
+    State(db): State<Db>,
+    Path(id): Path<i32>,
+    ValidatedQuery(query): ValidatedQuery<GetCharacterStatsParams>,
+) -> Result<Json<Vec<CharacterStats>>, RouteError> {
+    use diesel::{dsl::sql, sql_types::BigInt};
+
+    use crate::schema::{players, scores, songs};
+
+    let mut conn = db.read_conn().await?;
+
+    let exists: bool = diesel::select(diesel::dsl::exists(players::table.find(id)))
+        .get_result(&mut conn)
+        .await?;
+    if !exists {
+        return Err(RouteError::new_not_found());
+    }
+
+    let leagues = query
+        .league
+        .map_or(League::ALL.to_vec(), |league| vec![league]);
+
+    let totals: Vec<(Character, i64, i64)> = scores::table
+        .filter(scores::player_id.eq(id))
+        .filter(scores::league.eq_any(&leagues))
+        .group_by(scores::vehicle)
+        .select((
+            scores::vehicle,
+            diesel::dsl::count_star(),
+            sql::<BigInt>(&config.skill_points.sql_sum()),
+        ))
+        .order_by(diesel::dsl::count_star().desc())
+        .load(&mut conn)
+        .await?;
+
+    let mut best_scores: Vec<(Score, Song)> = scores::table
+        .inner_join(songs::table)
+        .filter(scores::player_id.eq(id))
+        .filter(scores::league.eq_any(&leagues))
+        .distinct_on(scores::vehicle)
+        .order_by((
+            scores::vehicle.asc(),
+            scores::score.desc(),
+            scores::submitted_at.asc(),
+        ))
+        .select((Score::as_select(), Song::as_select()))
+        .load(&mut conn)
+        .await?;
+
+    let results = totals
+        .into_iter()
+        .filter_map(|(character, score_count, skill_points)| {
+            let position = best_scores
+                .iter()
+                .position(|(score, _)| score.vehicle == character)?;
+            let (best_score, song) = best_scores.swap_remove(position);
+            Some(CharacterStats {
+                character,
+                score_count,
+                skill_points,
+                best_score,
+                song,
+            })
+        })
+        .collect();
+
+    Ok(Json(results))
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct FavoriteSongResponse {
+    position: i16,
+    #[serde(flatten)]
+    song_data: SongResponse,
+    /// The player's best score on the song, in any league
+    best_score: Option<Score>,
+}
+
+/// Loads a player's favorite songs in order, along with their best score on each.
+async fn load_favorites(
+    player_id: i32,
+    conn: &mut diesel_async::AsyncPgConnection,
+) -> Result<Vec<FavoriteSongResponse>, RouteError> {
+    use crate::schema::{extra_song_info, player_favorite_songs, scores, songs};
+
+    let favorites: Vec<(FavoriteSong, Song, Option<ExtraSongInfo>)> = player_favorite_songs::table
+        .inner_join(songs::table.left_join(extra_song_info::table))
+        .filter(player_favorite_songs::player_id.eq(player_id))
+        .order(player_favorite_songs::position.asc())
+        .select((
+            FavoriteSong::as_select(),
+            Song::as_select(),
+            Option::<ExtraSongInfo>::as_select(),
+        ))
+        .load(conn)
+        .await?;
+
+    let song_ids: Vec<i32> = favorites.iter().map(|(_, song, _)| song.id).collect();
+    let mut best_scores: Vec<Score> = scores::table
+        .filter(scores::player_id.eq(player_id))
+        .filter(scores::song_id.eq_any(&song_ids))
+        .distinct_on(scores::song_id)
+        .order_by((scores::song_id.asc(), scores::score.desc()))
+        .select(Score::as_select())
+        .load(conn)
+        .await?;
+
+    Ok(favorites
+        .into_iter()
+        .map(|(favorite, song, extra_info)| {
+            let best_score = best_scores
+                .iter()
+                .position(|score| score.song_id == song.id)
+                .map(|index| best_scores.swap_remove(index));
+            FavoriteSongResponse {
+                position: favorite.position,
+                song_data: SongResponse { song, extra_info },
+                best_score,
+            }
+        })
+        .collect())
+}
+
+/// Get player's favorite songs
+#[utoipa::path(
+    method(get),
+    path = "/{id}/favorites",
+    params(
+        ("id" = i32, Path, description = "ID of player to get favorites of"),
+    ),
+    responses(
+        (status = OK, description = "Success", body = Vec<FavoriteSongResponse>, content_type = "application/json"),
+        (status = NOT_FOUND, description = "Player not found", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = INTERNAL_SERVER_ERROR, description = "Miscellaneous error", body = SimpleRouteErrorOutput)
+    )
+)]
+async fn get_favorites(
+    State(db): State<Db>,
+    Path(id): Path<i32>,
+) -> Result<Json<Vec<FavoriteSongResponse>>, RouteError> {
+    use crate::schema::players;
+
+    let mut conn = db.read_conn().await?;
+
+    let player: Player = players::table
+        .find(id)
+        .first(&mut conn)
+        .await
+        .optional()?
+        .ok_or_else(RouteError::new_not_found)?;
+
+    Ok(Json(load_favorites(player.id, &mut conn).await?))
+}
+
+#[derive(Deserialize, Validate, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct UpdateFavoritesRequest {
+    /// IDs of the songs to pin, in the order they should be shown
+    #[validate(length(max = MAX_FAVORITE_SONGS))]
+    song_ids: Vec<i32>,
+}
+
+/// Replace own favorite songs
+#[utoipa::path(
+    method(put),
+    path = "/self/favorites",
+    request_body = UpdateFavoritesRequest,
+    responses(
+        (status = OK, description = "Success", body = Vec<FavoriteSongResponse>, content_type = "application/json"),
+        (status = BAD_REQUEST, description = "Invalid song list", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = NOT_FOUND, description = "Song not found", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = UNAUTHORIZED, description = "Not logged in or invalid token", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = INTERNAL_SERVER_ERROR, description = "Miscellaneous error", body = SimpleRouteErrorOutput)
+    ),
+    security(
+        ("token_jwt" = [])
+    )
+)]
+async fn update_own_favorites(
+    State(db): State<Db>,
+    claims: Claims,
+    ValidatedJson(payload): ValidatedJson<UpdateFavoritesRequest>,
+) -> Result<Json<Vec<FavoriteSongResponse>>, RouteError> {
+    use crate::schema::songs;
+
+    let mut unique_ids = payload.song_ids.clone();
+    unique_ids.sort_unstable();
+    unique_ids.dedup();
+    if unique_ids.len() != payload.song_ids.len() {
+        return Err(RouteError::new_bad_request()
+            .set_public_error_message("Each song can only be pinned once"));
+    }
+
+    let mut conn = db.get().await?;
+
+    let existing_count: i64 = songs::table
+        .filter(songs::id.eq_any(&unique_ids))
+        .count()
+        .get_result(&mut conn)
+        .await?;
+    if usize::try_from(existing_count)? != unique_ids.len() {
+        return Err(RouteError::new_not_found().set_public_error_message("Song not found"));
+    }
+
+    FavoriteSong::replace_for_player(claims.profile.id, &payload.song_ids, &mut conn).await?;
+
+    Ok(Json(load_favorites(claims.profile.id, &mut conn).await?))
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct RevokeSessionsResponse {
+    /// Number of sessions that were still active
+    revoked: u64,
+}
+
+/// Revoke all sessions of a player
+///
+/// Logs the player out everywhere, e.g. after their account was compromised or banned.
+/// Only available to moderators and team members.
+#[utoipa::path(
+    method(post),
+    path = "/{id}/revokeSessions",
+    params(
+        ("id" = i32, Path, description = "ID of player whose sessions to revoke"),
+    ),
+    responses(
+        (status = OK, description = "Success", body = RevokeSessionsResponse, content_type = "application/json"),
+        (status = NOT_FOUND, description = "Player not found", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = UNAUTHORIZED, description = "No permission", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = INTERNAL_SERVER_ERROR, description = "Miscellaneous error", body = SimpleRouteErrorOutput)
+    ),
+    security(
+        ("token_jwt" = [])
+    )
+)]
+async fn revoke_player_sessions(
+    State(db): State<Db>,
+    State(redis): State<Cache>,
+    Path(id): Path<i32>,
+    claims: Claims,
+) -> Result<Json<RevokeSessionsResponse>, RouteError> {
+    use crate::schema::players;
+
+    if claims.profile.account_type != AccountType::Moderator
+        && claims.profile.account_type != AccountType::Team
+    {
+        return Err(RouteError::new_unauthorized());
+    }
+
+    let mut conn = db.get().await?;
+    let player: Player = players::table
+        .find(id)
+        .first(&mut conn)
+        .await
+        .optional()?
+        .ok_or_else(RouteError::new_not_found)?;
+
+    let revoked = revoke_all_sessions(player.id, &redis).await?;
+
+    info!(
+        "Player {} revoked all {} sessions of player {}",
+        claims.profile.id, revoked, player.id
+    );
+
+    Ok(Json(RevokeSessionsResponse { revoked }))
+}
+
+/// Ban a player
+///
+/// Keeps the player from logging in, submitting scores and using the API until the ban expires.
+/// Only available to moderators and team members, and they can't ban each other.
+#[utoipa::path(
+    method(post),
+    path = "/{id}/ban",
+    params(
+        ("id" = i32, Path, description = "ID of player to ban"),
+    ),
+    request_body = NewBan,
+    responses(
+        (status = OK, description = "Success", body = Ban, content_type = "application/json"),
+        (status = BAD_REQUEST, description = "Invalid reason or expiry", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = NOT_FOUND, description = "Player not found", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = UNAUTHORIZED, description = "No permission", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = FORBIDDEN, description = "Player is a moderator or team member", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = INTERNAL_SERVER_ERROR, description = "Miscellaneous error", body = SimpleRouteErrorOutput)
+    ),
+    security(
+        ("token_jwt" = [])
+    )
+)]
+async fn ban_player(
+    State(db): State<Db>,
+    State(redis): State<Cache>,
+    Path(id): Path<i32>,
+    claims: Claims,
+    ValidatedJson(new_ban): ValidatedJson<NewBan>,
+) -> Result<Json<Ban>, RouteError> {
+    use crate::schema::players;
+
+    if claims.profile.account_type != AccountType::Moderator
+        && claims.profile.account_type != AccountType::Team
+    {
+        return Err(RouteError::new_unauthorized());
+    }
+
+    let mut conn = db.get().await?;
+    let player: Player = players::table
+        .find(id)
+        .first(&mut conn)
+        .await
+        .optional()?
+        .ok_or_else(RouteError::new_not_found)?;
+    if player.account_type == AccountType::Moderator || player.account_type == AccountType::Team {
+        return Err(RouteError::new_forbidden()
+            .set_public_error_message("Moderators and team members can't be banned"));
+    }
+
+    let ban = new_ban
+        .insert(player.id, Some(claims.profile.id), &mut conn)
+        .await?;
+    access::forget(player.id, &redis).await;
+
+    info!(
+        "Player {} banned player {} until {:?}: {}",
+        claims.profile.id, player.id, ban.expires_at, ban.reason
+    );
+
+    Ok(Json(ban))
+}
+
+/// Unban a player
+///
+/// Lifts all bans of the player that haven't expired yet, and makes a player
+/// banned through their account type a regular user again. Lifted bans are kept for the record.
+/// Only available to moderators and team members.
+#[utoipa::path(
+    method(delete),
+    path = "/{id}/ban",
+    params(
+        ("id" = i32, Path, description = "ID of player to unban"),
+    ),
+    responses(
+        (status = OK, description = "Success"),
+        (status = NOT_FOUND, description = "Player not found or isn't banned", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = UNAUTHORIZED, description = "No permission", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = INTERNAL_SERVER_ERROR, description = "Miscellaneous error", body = SimpleRouteErrorOutput)
+    ),
+    security(
+        ("token_jwt" = [])
+    )
+)]
+async fn unban_player(
+    State(db): State<Db>,
+    State(redis): State<Cache>,
+    Path(id): Path<i32>,
+    claims: Claims,
+) -> Result<(), RouteError> {
+    use crate::schema::players;
+
+    if claims.profile.account_type != AccountType::Moderator
+        && claims.profile.account_type != AccountType::Team
+    {
+        return Err(RouteError::new_unauthorized());
+    }
+
+    let mut conn = db.get().await?;
+    let player: Player = players::table
+        .find(id)
+        .first(&mut conn)
+        .await
+        .optional()?
+        .ok_or_else(RouteError::new_not_found)?;
+    if !player.lift_bans(Some(claims.profile.id), &mut conn).await? {
+        return Err(RouteError::new_not_found().set_public_error_message("Player isn't banned"));
+    }
+    access::forget(player.id, &redis).await;
+
+    info!("Player {} unbanned player {}", claims.profile.id, id);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, http::Request};
+    use time::{Duration, OffsetDateTime};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        models::songs::NewSong,
+        schema::players,
+        util::testing::{self, RecordedRedis},
+    };
+
+    /// Which of `ids` the rankings filters let through
+    async fn matching_players(
+        league: Option<League>,
+        active_since: OffsetDateTime,
+        ids: &[i32],
+        conn: &mut diesel_async::AsyncPgConnection,
+    ) -> Vec<i32> {
+        let query = GetRankingsParams {
+            page: 1,
+            page_size: 10,
+            active_since: Some(active_since),
+            league,
+            location_id: None,
+        };
+        let mut matching: Vec<i32> = filtered_players(&query, &SkillPointPolicy::ORIGINAL)
+            .filter(players::id.eq_any(ids))
+            .select(players::id)
+            .load(conn)
+            .await
+            .unwrap();
+        matching.sort_unstable();
+        matching
+    }
+
+    #[tokio::test]
+    async fn ranking_filters_run_in_sql() {
+        let Some(mut conn) = testing::db().await else {
+            return;
+        };
+        let redis_conn = testing::redis(Arc::new(RecordedRedis::default())).await;
+        let now = OffsetDateTime::now_utc();
+        let song_id = testing::insert_song("Dear Music", "A4.", &mut conn).await;
+
+        let mut ids = vec![];
+        for (steam_account_num, last_played_days_ago, league) in [
+            (1, Some(1), Some(League::Elite)),
+            (2, Some(1), Some(League::Casual)),
+            (3, Some(30), Some(League::Elite)),
+            (4, None, Some(League::Elite)),
+            // Active, but without scores
+            (5, Some(1), None),
+        ] {
+            let player_id = testing::insert_player(steam_account_num, &mut conn).await;
+            if let Some(league) = league {
+                testing::insert_score(player_id, song_id, league, 100_000, &mut conn, &redis_conn)
+                    .await;
+            }
+            diesel::update(players::table.find(player_id))
+                .set(
+                    players::last_played_at
+                        .eq(last_played_days_ago.map(|days| now - Duration::days(days))),
+                )
+                .execute(&mut conn)
+                .await
+                .unwrap();
+            ids.push(player_id);
+        }
+
+        let active_since = now - Duration::days(7);
+        assert_eq!(
+            matching_players(None, active_since, &ids, &mut conn).await,
+            [ids[0], ids[1]]
+        );
+        assert_eq!(
+            matching_players(Some(League::Elite), active_since, &ids, &mut conn).await,
+            [ids[0]]
+        );
+
+        // A score worth no points doesn't put a player on the league's leaderboard
+        testing::insert_score(ids[1], song_id, League::Elite, 0, &mut conn, &redis_conn).await;
+        assert_eq!(
+            matching_players(Some(League::Elite), active_since, &ids, &mut conn).await,
+            [ids[0]]
+        );
+    }
+
+    /// Status, number of results and total of `GET /api/players/{player_id}/scores` with `filters`
+    async fn player_scores(
+        state: &AppState,
+        player_id: i32,
+        filters: &str,
+    ) -> (StatusCode, usize, i64) {
+        let request = Request::builder()
+            .uri(format!(
+                "/api/players/{player_id}/scores?withSong=false&{filters}"
+            ))
+            .body(Body::empty())
+            .unwrap();
+        let response = crate::make_router(state.clone())
+            .oneshot(request)
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        (
+            status,
+            body["results"].as_array().map_or(0, Vec::len),
+            body["total"].as_i64().unwrap_or_default(),
+        )
+    }
+
+    #[tokio::test]
+    async fn modifier_filters_count_what_they_return() {
+        let Some(state) = testing::state(Arc::new(RecordedRedis::default())).await else {
+            return;
+        };
+        let player_id = {
+            let mut conn = state.db.get().await.unwrap();
+            let redis_conn = testing::redis(Arc::new(RecordedRedis::default())).await;
+            let player_id = testing::insert_player(1, &mut conn).await;
+            for (modifiers, leagues) in [
+                (None, &[League::Casual][..]),
+                (Some(vec!["steep"]), &[League::Casual, League::Pro][..]),
+                (Some(vec!["steep", "flat"]), &[League::Elite][..]),
+            ] {
+                let song_id = NewSong::new("Dear Music", "A4.", modifiers)
+                    .find_or_create(&mut conn)
+                    .await
+                    .unwrap()
+                    .id;
+                for league in leagues {
+                    testing::insert_score(
+                        player_id,
+                        song_id,
+                        *league,
+                        1000,
+                        &mut conn,
+                        &redis_conn,
+                    )
+                    .await;
+                }
+            }
+            player_id
+        };
+
+        for (filters, matching) in [
+            ("modifier=none", 1),
+            ("modifier=STEEP", 3),
+            ("modifier=Steep,flat", 1),
+            ("modifier=steep&league=1", 1),
+        ] {
+            assert_eq!(
+                player_scores(&state, player_id, &format!("{filters}&pageSize=50")).await,
+                (StatusCode::OK, matching, i64::try_from(matching).unwrap()),
+                "{filters}"
+            );
+            // The total doesn't depend on the page
+            assert_eq!(
+                player_scores(&state, player_id, &format!("{filters}&pageSize=1")).await,
+                (StatusCode::OK, 1, i64::try_from(matching).unwrap()),
+                "{filters}"
+            );
+        }
+
+        assert_eq!(
+            player_scores(&state, player_id, "modifier=tag1").await.0,
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[test]
+    fn ties_are_ranked_by_id() {
+        // As Redis returns them, comparing the IDs as strings
+        let mut entries = [(9, 100.0), (10, 200.0), (12, 100.0), (100, 100.0)];
+        rank_entries(&mut entries);
+        assert_eq!(
+            entries,
+            [(10, 200.0), (9, 100.0), (12, 100.0), (100, 100.0)]
+        );
+    }
+
+    #[test]
+    fn walks_finish_the_last_tie() {
+        let matching = [(10, 200.0), (100, 100.0)];
+        assert!(!walk_complete(&matching, 3, 100.0));
+        // Another player with 100 points might still come and rank before 100
+        assert!(!walk_complete(&matching, 2, 100.0));
+        assert!(walk_complete(&matching, 2, 50.0));
+        assert!(walk_complete(&matching, 1, 100.0));
+        assert!(!walk_complete(&matching, 0, 0.0));
+    }
 }
@@ -0,0 +1,495 @@
+use std::{io::ErrorKind, path::Path as FsPath, sync::Arc};
+
+use axum::{
+    extract::{DefaultBodyLimit, Multipart, Path, State},
+    http::StatusCode,
+    Json,
+};
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use tokio::{fs, io::AsyncWriteExt};
+use tracing::info;
+use utoipa::ToSchema;
+use utoipa_axum::{router::OpenApiRouter, routes};
+use validator::Validate;
+
+use crate::{
+    models::{
+        players::AccountType,
+        radio_entries::{validate_availability, NewRadioEntry, RadioEntry, RadioEntryUpdate},
+    },
+    util::{
+        cgr::{self, MAX_CGR_SIZE},
+        errors::{RouteError, SimpleRouteErrorOutput},
+        jwt::Claims,
+        radio::{RadioLineup, RadioStatus},
+        validator::ValidatedJson,
+    },
+    AppState, Config, Db,
+};
+
+/// Most entries that can be reordered at once
+const MAX_REORDERED: usize = 500;
+/// Room for the multipart boundaries and headers around an uploaded CGR
+const UPLOAD_OVERHEAD: usize = 64 * 1024;
+
+pub fn routes() -> OpenApiRouter<AppState> {
+    OpenApiRouter::new()
+        .routes(routes!(get_radio_entries, add_radio_entry))
+        .routes(routes!(update_radio_entry))
+        .routes(routes!(reorder_radio_entries))
+        .routes(routes!(get_radio_status))
+        .routes(routes!(reload_radio))
+        .merge(
+            OpenApiRouter::new()
+                .routes(routes!(upload_cgr))
+                .layer(DefaultBodyLimit::max(MAX_CGR_SIZE + UPLOAD_OVERHEAD)),
+        )
+}
+
+const fn is_moderator(claims: &Claims) -> bool {
+    matches!(
+        claims.profile.account_type,
+        AccountType::Moderator | AccountType::Team
+    )
+}
+
+async fn check_song_exists(song_id: i32, conn: &mut AsyncPgConnection) -> Result<(), RouteError> {
+    use crate::schema::songs;
+
+    let exists: bool = diesel::select(diesel::dsl::exists(songs::table.find(song_id)))
+        .get_result(conn)
+        .await?;
+    if !exists {
+        return Err(RouteError::new_not_found().set_public_error_message("Song not found"));
+    }
+
+    Ok(())
+}
+
+/// List radio entries
+///
+/// Lists the whole Audiosurf Radio lineup in order, including inactive entries.
+/// `GET /songs/radio` only lists the active ones. Only available to moderators and team members.
+#[utoipa::path(
+    method(get),
+    path = "/",
+    responses(
+        (status = OK, description = "Success", body = Vec<RadioEntry>, content_type = "application/json"),
+        (status = UNAUTHORIZED, description = "No permission", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = INTERNAL_SERVER_ERROR, description = "Miscellaneous error", body = SimpleRouteErrorOutput)
+    ),
+    security(
+        ("token_jwt" = [])
+    )
+)]
+async fn get_radio_entries(
+    State(db): State<Db>,
+    claims: Claims,
+) -> Result<Json<Vec<RadioEntry>>, RouteError> {
+    if !is_moderator(&claims) {
+        return Err(RouteError::new_unauthorized());
+    }
+
+    let mut conn = db.get().await?;
+    let entries = RadioEntry::all(&mut conn).await?;
+
+    Ok(Json(entries))
+}
+
+/// Add radio entry
+///
+/// Adds a song to the Audiosurf Radio lineup, at the end unless `sortOrder` is given.
+/// The song has to exist already. Only available to moderators and team members.
+#[utoipa::path(
+    method(post),
+    path = "/",
+    request_body = NewRadioEntry,
+    responses(
+        (status = OK, description = "Success", body = RadioEntry, content_type = "application/json"),
+        (status = BAD_REQUEST, description = "Invalid entry", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = UNAUTHORIZED, description = "No permission", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = NOT_FOUND, description = "Song not found", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = INTERNAL_SERVER_ERROR, description = "Miscellaneous error", body = SimpleRouteErrorOutput)
+    ),
+    security(
+        ("token_jwt" = [])
+    )
+)]
+async fn add_radio_entry(
+    State(db): State<Db>,
+    State(radio): State<RadioLineup>,
+    claims: Claims,
+    ValidatedJson(mut new_entry): ValidatedJson<NewRadioEntry>,
+) -> Result<Json<RadioEntry>, RouteError> {
+    use diesel::dsl::max;
+
+    use crate::schema::radio_entries;
+
+    if !is_moderator(&claims) {
+        return Err(RouteError::new_unauthorized());
+    }
+
+    let mut conn = db.get().await?;
+    check_song_exists(new_entry.song_id, &mut conn).await?;
+
+    if new_entry.sort_order.is_none() {
+        let last: Option<i32> = radio_entries::table
+            .select(max(radio_entries::sort_order))
+            .first(&mut conn)
+            .await?;
+        new_entry.sort_order = Some(last.map_or(0, |last| last.saturating_add(1)));
+    }
+    let entry: RadioEntry = diesel::insert_into(radio_entries::table)
+        .values(&new_entry)
+        .returning(RadioEntry::as_returning())
+        .get_result(&mut conn)
+        .await?;
+
+    info!(
+        "Player {} added song {} to the radio as entry {}",
+        claims.profile.id, entry.song_id, entry.id
+    );
+    // The change is saved either way, a failed reload is logged and retried by the next refresh
+    let _ = radio.reload(&db).await;
+    Ok(Json(entry))
+}
+
+/// Update radio entry
+///
+/// Changes an entry of the Audiosurf Radio lineup, e.g. setting `active` to `false` takes it off the radio,
+/// and `availableFrom`/`availableUntil` schedule it. Fields that are left out stay as they are. Only available to moderators and team members.
+#[utoipa::path(
+    method(patch),
+    path = "/{id}",
+    params(
+        ("id" = i32, Path, description = "ID of radio entry to update"),
+    ),
+    request_body = RadioEntryUpdate,
+    responses(
+        (status = OK, description = "Success", body = RadioEntry, content_type = "application/json"),
+        (status = BAD_REQUEST, description = "Invalid changes", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = UNAUTHORIZED, description = "No permission", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = NOT_FOUND, description = "Radio entry or song not found", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = INTERNAL_SERVER_ERROR, description = "Miscellaneous error", body = SimpleRouteErrorOutput)
+    ),
+    security(
+        ("token_jwt" = [])
+    )
+)]
+async fn update_radio_entry(
+    State(db): State<Db>,
+    State(radio): State<RadioLineup>,
+    claims: Claims,
+    Path(id): Path<i32>,
+    ValidatedJson(update): ValidatedJson<RadioEntryUpdate>,
+) -> Result<Json<RadioEntry>, RouteError> {
+    use crate::schema::radio_entries;
+
+    if !is_moderator(&claims) {
+        return Err(RouteError::new_unauthorized());
+    }
+
+    let mut conn = db.get().await?;
+    if let Some(song_id) = update.song_id {
+        check_song_exists(song_id, &mut conn).await?;
+    }
+
+    let entry: RadioEntry = radio_entries::table
+        .find(id)
+        .select(RadioEntry::as_select())
+        .first(&mut conn)
+        .await
+        .optional()?
+        .ok_or_else(RouteError::new_not_found)?;
+    let (available_from, available_until) = update.availability_of(&entry);
+    if validate_availability(available_from, available_until).is_err() {
+        return Err(RouteError::new_bad_request()
+            .set_public_error_message("availableFrom has to be before availableUntil"));
+    }
+
+    let entry = if update.is_empty() {
+        entry
+    } else {
+        diesel::update(&entry)
+            .set(&update)
+            .returning(RadioEntry::as_returning())
+            .get_result(&mut conn)
+            .await?
+    };
+
+    info!(
+        "Player {} updated radio entry {} (active: {})",
+        claims.profile.id, entry.id, entry.active
+    );
+    let _ = radio.reload(&db).await;
+    Ok(Json(entry))
+}
+
+#[derive(Deserialize, Validate, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct ReorderRadioEntriesBody {
+    /// Entry IDs in their new order. Entries that are left out keep their sort order
+    #[validate(length(min = 1, max = MAX_REORDERED))]
+    ids: Vec<i32>,
+}
+
+/// Reorder radio entries
+///
+/// Sets the sort order of the given entries to their position in the list, in one go.
+/// Only available to moderators and team members.
+#[utoipa::path(
+    method(put),
+    path = "/order",
+    request_body = ReorderRadioEntriesBody,
+    responses(
+        (status = OK, description = "Success, returns the whole lineup", body = Vec<RadioEntry>, content_type = "application/json"),
+        (status = BAD_REQUEST, description = "Invalid list", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = UNAUTHORIZED, description = "No permission", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = INTERNAL_SERVER_ERROR, description = "Miscellaneous error", body = SimpleRouteErrorOutput)
+    ),
+    security(
+        ("token_jwt" = [])
+    )
+)]
+async fn reorder_radio_entries(
+    State(db): State<Db>,
+    State(radio): State<RadioLineup>,
+    claims: Claims,
+    ValidatedJson(body): ValidatedJson<ReorderRadioEntriesBody>,
+) -> Result<Json<Vec<RadioEntry>>, RouteError> {
+    if !is_moderator(&claims) {
+        return Err(RouteError::new_unauthorized());
+    }
+
+    let mut conn = db.get().await?;
+    let found = RadioEntry::reorder(&body.ids, &mut conn).await?;
+    info!(
+        "Player {} reordered {found} radio entries",
+        claims.profile.id
+    );
+
+    let _ = radio.reload(&db).await;
+
+    let entries = RadioEntry::all(&mut conn).await?;
+    Ok(Json(entries))
+}
+
+/// Get radio status
+///
+/// Whether the lineup this instance serves loaded fine, has no active songs, has songs whose CGR files
+/// can't be downloaded, or failed to reload (in which case the lineup from before is still served).
+/// Each song's CGR file has to be in `cgr_location` or answer at its URL, which is checked every few minutes.
+/// Only available to moderators and team members.
+#[utoipa::path(
+    method(get),
+    path = "/status",
+    responses(
+        (status = OK, description = "Success", body = RadioStatus, content_type = "application/json"),
+        (status = UNAUTHORIZED, description = "No permission", body = SimpleRouteErrorOutput, content_type = "application/json"),
+    ),
+    security(
+        ("token_jwt" = [])
+    )
+)]
+async fn get_radio_status(
+    State(radio): State<RadioLineup>,
+    State(config): State<Arc<Config>>,
+    claims: Claims,
+) -> Result<Json<RadioStatus>, RouteError> {
+    if !is_moderator(&claims) {
+        return Err(RouteError::new_unauthorized());
+    }
+
+    let status = radio.status(FsPath::new(&config.radio.cgr_location)).await;
+    Ok(Json(status))
+}
+
+/// Reload radio lineup
+///
+/// Loads the lineup from the database again, without waiting for the periodic refresh.
+/// Changes made through the API are loaded right away anyway, this is for changes made directly in the database.
+/// If loading fails, the lineup from before is kept and the status says why.
+/// Only available to moderators and team members.
+#[utoipa::path(
+    method(post),
+    path = "/reload",
+    responses(
+        (status = OK, description = "Reloaded or not, returns the status", body = RadioStatus, content_type = "application/json"),
+        (status = UNAUTHORIZED, description = "No permission", body = SimpleRouteErrorOutput, content_type = "application/json"),
+    ),
+    security(
+        ("token_jwt" = [])
+    )
+)]
+async fn reload_radio(
+    State(db): State<Db>,
+    State(radio): State<RadioLineup>,
+    State(config): State<Arc<Config>>,
+    claims: Claims,
+) -> Result<Json<RadioStatus>, RouteError> {
+    if !is_moderator(&claims) {
+        return Err(RouteError::new_unauthorized());
+    }
+
+    let _ = radio.reload(&db).await;
+    info!("Player {} reloaded the radio lineup", claims.profile.id);
+    let status = radio.status(FsPath::new(&config.radio.cgr_location)).await;
+    Ok(Json(status))
+}
+
+#[derive(ToSchema)]
+#[allow(dead_code)]
+struct UploadCgrForm {
+    /// The `.cgr` file. Its name is kept, so it has to end in `.cgr` and only contain letters, digits, `-`, `_` and `.`
+    #[schema(value_type = String, format = Binary)]
+    file: Vec<u8>,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct UploadedCgr {
+    /// Where the game downloads the file from, to be used as a radio entry's `cgrUrl`
+    url: String,
+    file_name: String,
+    size: usize,
+    /// Whether the file was uploaded compressed
+    compressed: bool,
+}
+
+fn conflict(message: &str) -> RouteError {
+    RouteError::new_conflict().set_public_error_message(message)
+}
+
+/// Writes the file next to its final place first and only moves it there once it's complete,
+/// so a failed upload never leaves a partial file where the game would find it.
+async fn store_cgr(dir: &FsPath, file_name: &str, data: &[u8]) -> Result<(), RouteError> {
+    let target = dir.join(file_name);
+    if fs::try_exists(&target).await? {
+        return Err(conflict("A CGR file with that name exists already"));
+    }
+
+    let temp = dir.join(format!(".{file_name}.upload"));
+    let mut file = match fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&temp)
+        .await
+    {
+        Ok(file) => file,
+        Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+            return Err(conflict(
+                "A CGR file with that name is being uploaded right now",
+            ));
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    let written = async {
+        file.write_all(data).await?;
+        file.sync_all().await?;
+        drop(file);
+        fs::rename(&temp, &target).await
+    }
+    .await;
+    if let Err(e) = written {
+        let _ = fs::remove_file(&temp).await;
+        return Err(e.into());
+    }
+
+    Ok(())
+}
+
+/// Upload radio CGR
+///
+/// Stores a `.cgr` file in `cgr_location`, where it's served under `/as/asradio/` for radio entries to point to.
+/// It has to be a valid CGR file (compressed ones work too) of at most 64 MiB, and there can't be a file with that name yet.
+/// Only available to moderators and team members.
+#[utoipa::path(
+    method(post),
+    path = "/cgr",
+    request_body(content = UploadCgrForm, content_type = "multipart/form-data"),
+    responses(
+        (status = OK, description = "Success", body = UploadedCgr, content_type = "application/json"),
+        (status = BAD_REQUEST, description = "Invalid upload or not a CGR file", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = UNAUTHORIZED, description = "No permission", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = CONFLICT, description = "File exists already", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = PAYLOAD_TOO_LARGE, description = "File too large", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = INTERNAL_SERVER_ERROR, description = "Miscellaneous error", body = SimpleRouteErrorOutput)
+    ),
+    security(
+        ("token_jwt" = [])
+    )
+)]
+async fn upload_cgr(
+    State(config): State<Arc<Config>>,
+    claims: Claims,
+    mut multipart: Multipart,
+) -> Result<Json<UploadedCgr>, RouteError> {
+    if !is_moderator(&claims) {
+        return Err(RouteError::new_unauthorized());
+    }
+
+    let invalid_upload = |e: axum::extract::multipart::MultipartError| {
+        RouteError::new_bad_request()
+            .set_status_code(e.status())
+            .set_public_error_message(&e.body_text())
+    };
+    let mut field = loop {
+        match multipart.next_field().await.map_err(invalid_upload)? {
+            Some(field) if field.name() == Some("file") => break field,
+            Some(_) => {}
+            None => {
+                return Err(RouteError::new_bad_request()
+                    .set_public_error_message("The file has to be sent as `file`"))
+            }
+        }
+    };
+    let file_name = field
+        .file_name()
+        .filter(|file_name| cgr::is_valid_file_name(file_name))
+        .ok_or_else(|| {
+            RouteError::new_bad_request().set_public_error_message(
+                "The file name has to end in .cgr and only contain letters, digits, -, _ and .",
+            )
+        })?
+        .to_owned();
+
+    let mut data = Vec::new();
+    while let Some(chunk) = field.chunk().await.map_err(invalid_upload)? {
+        if data.len() + chunk.len() > MAX_CGR_SIZE {
+            return Err(RouteError::new_bad_request()
+                .set_status_code(StatusCode::PAYLOAD_TOO_LARGE)
+                .set_public_error_message("CGR files can be 64 MiB at most"));
+        }
+        data.extend_from_slice(&chunk);
+    }
+    // Inflating up to 64 MiB would hold up the executor thread
+    let (data, parsed) = tokio::task::spawn_blocking(move || {
+        let parsed = cgr::CgrFile::parse(&data);
+        (data, parsed)
+    })
+    .await?;
+    let parsed = parsed.map_err(|e| {
+        RouteError::new_bad_request()
+            .set_public_error_message(&format!("Not a valid CGR file: {e}"))
+    })?;
+
+    store_cgr(FsPath::new(&config.radio.cgr_location), &file_name, &data).await?;
+    info!(
+        "Player {} uploaded radio CGR {file_name} ({} bytes)",
+        claims.profile.id,
+        data.len()
+    );
+
+    Ok(Json(UploadedCgr {
+        url: format!(
+            "{}/as/asradio/{file_name}",
+            config.external.steam_realm.trim_end_matches('/')
+        ),
+        file_name,
+        size: data.len(),
+        compressed: parsed.is_compressed(),
+    }))
+}
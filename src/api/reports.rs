@@ -0,0 +1,229 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use diesel::prelude::*;
+use diesel_async::{scoped_futures::ScopedFutureExt, AsyncConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use serde_inline_default::serde_inline_default;
+use utoipa::ToSchema;
+use utoipa_axum::{router::OpenApiRouter, routes};
+use validator::Validate;
+
+use crate::{
+    models::{
+        players::{AccountType, PlayerPublic},
+        score_reports::ScoreReport,
+        scores::Score,
+        songs::Song,
+    },
+    util::{
+        errors::{RouteError, SimpleRouteErrorOutput},
+        jwt::Claims,
+        validator::ValidatedQuery,
+    },
+    AppState, Cache, Config, Db,
+};
+
+pub fn routes() -> OpenApiRouter<AppState> {
+    OpenApiRouter::new()
+        .routes(routes!(get_score_reports))
+        .routes(routes!(resolve_score_report))
+}
+
+const fn is_moderator(claims: &Claims) -> bool {
+    matches!(
+        claims.profile.account_type,
+        AccountType::Moderator | AccountType::Team
+    )
+}
+
+#[serde_inline_default]
+#[derive(Deserialize, Validate)]
+#[serde(rename_all = "camelCase")]
+struct GetScoreReportsParams {
+    #[validate(range(min = 1))]
+    #[serde_inline_default(1)]
+    page: i64,
+    #[validate(range(min = 1, max = 50))]
+    #[serde_inline_default(20)]
+    page_size: i64,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct OpenScoreReport {
+    #[serde(flatten)]
+    report: ScoreReport,
+    score: Score,
+    song: Song,
+    reporter: PlayerPublic,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct ScoreReportsResponse {
+    results: Vec<OpenScoreReport>,
+    total: i64,
+}
+
+/// List open score reports
+///
+/// Lists the reports nobody handled yet, oldest first.
+/// Only available to moderators and team members.
+#[utoipa::path(
+    method(get),
+    path = "/scores",
+    params(
+        ("page" = Option<i64>, Query, description = "Page number", minimum = 1),
+        ("pageSize" = Option<i64>, Query, description = "Page size", minimum = 1, maximum = 50),
+    ),
+    responses(
+        (status = OK, description = "Success", body = ScoreReportsResponse, content_type = "application/json"),
+        (status = BAD_REQUEST, description = "Invalid query", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = UNAUTHORIZED, description = "No permission", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = INTERNAL_SERVER_ERROR, description = "Miscellaneous error", body = SimpleRouteErrorOutput)
+    ),
+    security(
+        ("token_jwt" = [])
+    )
+)]
+async fn get_score_reports(
+    State(db): State<Db>,
+    claims: Claims,
+    ValidatedQuery(query): ValidatedQuery<GetScoreReportsParams>,
+) -> Result<Json<ScoreReportsResponse>, RouteError> {
+    use crate::schema::{players, score_reports, scores, songs};
+
+    if !is_moderator(&claims) {
+        return Err(RouteError::new_unauthorized());
+    }
+
+    let mut conn = db.get().await?;
+
+    // Reports of scores deleted some other way can't be acted on anymore, so they aren't listed
+    let total: i64 = score_reports::table
+        .inner_join(scores::table)
+        .filter(score_reports::resolved_at.is_null())
+        .count()
+        .get_result(&mut conn)
+        .await?;
+    let results = score_reports::table
+        .inner_join(scores::table.inner_join(songs::table))
+        .inner_join(players::table)
+        .filter(score_reports::resolved_at.is_null())
+        .order((score_reports::created_at.asc(), score_reports::id.asc()))
+        .limit(query.page_size)
+        .offset((query.page - 1) * query.page_size)
+        .select((
+            ScoreReport::as_select(),
+            Score::as_select(),
+            Song::as_select(),
+            PlayerPublic::as_select(),
+        ))
+        .load::<(ScoreReport, Score, Song, PlayerPublic)>(&mut conn)
+        .await?
+        .into_iter()
+        .map(|(report, score, song, reporter)| OpenScoreReport {
+            report,
+            score,
+            song,
+            reporter,
+        })
+        .collect();
+
+    Ok(Json(ScoreReportsResponse { results, total }))
+}
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct ResolveScoreReportBody {
+    /// Deletes the reported score, which also resolves all other open reports of it
+    #[serde(default)]
+    delete_score: bool,
+}
+
+/// Resolve score report
+///
+/// Marks a report as handled by the moderator who is logged in, optionally deleting the reported score.
+/// Only available to moderators and team members.
+#[utoipa::path(
+    method(post),
+    path = "/scores/{id}/resolve",
+    params(
+        ("id" = i32, Path, description = "ID of report to resolve"),
+    ),
+    request_body = ResolveScoreReportBody,
+    responses(
+        (status = OK, description = "Success"),
+        (status = NOT_FOUND, description = "Report not found", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = CONFLICT, description = "Report was already resolved", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = UNAUTHORIZED, description = "No permission", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = INTERNAL_SERVER_ERROR, description = "Miscellaneous error", body = SimpleRouteErrorOutput)
+    ),
+    security(
+        ("token_jwt" = [])
+    )
+)]
+async fn resolve_score_report(
+    State(config): State<Arc<Config>>,
+    State(db): State<Db>,
+    State(redis): State<Cache>,
+    Path(id): Path<i32>,
+    claims: Claims,
+    Json(body): Json<ResolveScoreReportBody>,
+) -> Result<(), RouteError> {
+    use crate::schema::{score_reports, scores};
+
+    if !is_moderator(&claims) {
+        return Err(RouteError::new_unauthorized());
+    }
+
+    let already_resolved =
+        || RouteError::new_conflict().set_public_error_message("Report was already resolved");
+    let mut conn = db.get().await?;
+
+    let report: ScoreReport = score_reports::table
+        .find(id)
+        .select(ScoreReport::as_select())
+        .first(&mut conn)
+        .await
+        .optional()?
+        .ok_or_else(RouteError::new_not_found)?;
+    if report.resolved_at.is_some() {
+        return Err(already_resolved());
+    }
+
+    let score: Option<Score> = match (body.delete_score, report.score_id) {
+        (true, Some(score_id)) => scores::table
+            .find(score_id)
+            .first(&mut conn)
+            .await
+            .optional()?,
+        _ => None,
+    };
+    if let Some(score) = score {
+        let moderator_id = claims.profile.id;
+        conn.transaction::<_, RouteError, _>(|conn| {
+            async move {
+                // Resolved before deleting, while the reports still point to the score
+                let resolved =
+                    ScoreReport::resolve_all_for_score(score.id, moderator_id, true, conn).await?;
+                // Another moderator resolved it since it was loaded
+                if resolved == 0 {
+                    return Err(already_resolved());
+                }
+                score.delete(&config.skill_points, conn, &redis).await?;
+                Ok(())
+            }
+            .scope_boxed()
+        })
+        .await?;
+    } else if report.resolve(claims.profile.id, &mut conn).await? == 0 {
+        return Err(already_resolved());
+    }
+
+    Ok(())
+}
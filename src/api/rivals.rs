@@ -1,41 +1,114 @@
-use axum::{extract::State, Json};
+use std::collections::{HashMap, HashSet};
+
+use axum::{
+    extract::{Query, State},
+    Json,
+};
 use diesel::prelude::*;
-use diesel_async::RunQueryDsl;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use fred::prelude::*;
 use serde::{Deserialize, Serialize};
+use tracing::warn;
 use utoipa::ToSchema;
 use utoipa_axum::{router::OpenApiRouter, routes};
+use validator::Validate;
 
 use crate::{
     models::{
-        players::Player,
-        rivalries::{NewRivalry, Rivalry, RivalryView},
+        players::{Player, PlayerPublic, RivalryPage},
+        rivalries::{NewRivalry, Rivalry, RivalrySource, RivalryView},
     },
     util::{
         errors::{RouteError, SimpleRouteErrorOutput},
+        game_types::League,
         jwt::Claims,
+        leaderboard::leaderboard_key,
+        validator::ValidatedQuery,
     },
-    AppState,
+    AppState, Cache, Db,
 };
 
 pub fn routes() -> OpenApiRouter<AppState> {
     OpenApiRouter::new()
         .routes(routes!(get_own_rivals))
+        .routes(routes!(get_rival_suggestions))
         .routes(routes!(add_rival))
         .routes(routes!(remove_rival))
 }
 
 #[derive(Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
-struct RivalryResponse {
+pub(super) struct RivalryResponse {
+    /// Players this player has added as rivals
     rivalries: Vec<RivalryView>,
+    rivalries_total: i64,
+    /// Players who have added this player as a rival
+    challengers: Vec<RivalryView>,
+    challengers_total: i64,
+}
+
+#[derive(Deserialize, Validate)]
+#[serde(rename_all = "camelCase")]
+pub(super) struct GetRivalsParams {
+    #[validate(range(min = 1))]
+    page: Option<i64>,
+    #[validate(range(min = 1, max = 50))]
+    page_size: Option<i64>,
+}
+
+impl GetRivalsParams {
+    /// The page to load, or `None` for everything if neither parameter was given.
+    fn rivalry_page(&self) -> Option<RivalryPage> {
+        if self.page.is_none() && self.page_size.is_none() {
+            return None;
+        }
+
+        let page = self.page.unwrap_or(1);
+        let page_size = self.page_size.unwrap_or(10);
+        Some(RivalryPage {
+            limit: page_size,
+            offset: (page - 1) * page_size,
+        })
+    }
+}
+
+pub(super) async fn rivalry_response(
+    player: &Player,
+    query: &GetRivalsParams,
+    conn: &mut AsyncPgConnection,
+) -> Result<RivalryResponse, RouteError> {
+    let page = query.rivalry_page();
+    if page.is_none() {
+        // TODO: default to the first page in the next release
+        warn!(
+            "Rivalries of player {} requested without pagination, which is deprecated",
+            player.id
+        );
+    }
+
+    let (rivalries_total, challengers_total) = player.count_rivalries(conn).await?;
+    Ok(RivalryResponse {
+        rivalries: player.get_rivalry_views(page, conn).await?,
+        rivalries_total,
+        challengers: player.get_challenger_views(page, conn).await?,
+        challengers_total,
+    })
 }
 
 /// Get own rivals
+///
+/// Both lists are paginated the same way, newest first.
+/// Requesting them without `page` and `pageSize` returns everything, but is deprecated and will return the first page in the future.
 #[utoipa::path(
     method(get),
     path = "/self",
+    params(
+        ("page" = Option<i64>, Query, description = "Page number", minimum = 1),
+        ("pageSize" = Option<i64>, Query, description = "Page size", minimum = 1, maximum = 50),
+    ),
     responses(
         (status = OK, description = "Success", body = RivalryResponse, content_type = "application/json"),
+        (status = BAD_REQUEST, description = "Invalid query parameters", body = SimpleRouteErrorOutput, content_type = "application/json"),
         (status = INTERNAL_SERVER_ERROR, description = "Miscellaneous error", body = SimpleRouteErrorOutput)
     ),
     security(
@@ -43,17 +116,176 @@ struct RivalryResponse {
     ))
 ]
 async fn get_own_rivals(
-    State(state): State<AppState>,
+    State(db): State<Db>,
     claims: Claims,
+    ValidatedQuery(query): ValidatedQuery<GetRivalsParams>,
 ) -> Result<Json<RivalryResponse>, RouteError> {
     use crate::schema::players::dsl::*;
 
-    let mut conn = state.db.get().await?;
+    let mut conn = db.get().await?;
 
     let player: Player = players.find(claims.profile.id).first(&mut conn).await?;
-    let rivalries: Vec<RivalryView> = player.get_rivalry_views(&mut conn).await?;
 
-    Ok(Json(RivalryResponse { rivalries }))
+    Ok(Json(rivalry_response(&player, &query, &mut conn).await?))
+}
+
+/// Number of players suggested as rivals
+const SUGGESTION_COUNT: usize = 10;
+/// How many places above and below the player on the leaderboard are considered for suggestions
+const SUGGESTION_WINDOW: i64 = 50;
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct RivalSuggestion {
+    player: PlayerPublic,
+    skill_points: i32,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GetSuggestionsParams {
+    league: Option<League>,
+    location_id: Option<i32>,
+}
+
+/// Picks up to `count` of the candidates whose skill points are closest to `own_points`, closest first.
+fn closest_candidates(
+    own_points: f64,
+    mut candidates: Vec<(i32, f64)>,
+    excluded: &HashSet<i32>,
+    count: usize,
+) -> Vec<(i32, f64)> {
+    candidates.retain(|(id, _)| !excluded.contains(id));
+    candidates.sort_by(|a, b| {
+        (a.1 - own_points)
+            .abs()
+            .total_cmp(&(b.1 - own_points).abs())
+    });
+    candidates.truncate(count);
+    candidates
+}
+
+/// Get rival suggestions
+///
+/// Suggests players with about as many skill points as the player, who aren't their rivals yet.
+/// Players who aren't on the leaderboard yet get the players at the bottom of it.
+#[utoipa::path(
+    method(get),
+    path = "/suggestions",
+    params(
+        ("league" = Option<League>, Query, description = "Compare skill points in this league only, instead of across all leagues"),
+        ("locationId" = Option<i32>, Query, description = "Only suggest players from this location"),
+    ),
+    responses(
+        (status = OK, description = "Success", body = Vec<RivalSuggestion>, content_type = "application/json"),
+        (status = BAD_REQUEST, description = "Invalid query parameters", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = UNAUTHORIZED, description = "Unauthorized", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = INTERNAL_SERVER_ERROR, description = "Miscellaneous error", body = SimpleRouteErrorOutput)
+    ),
+    security(
+        ("token_jwt" = [])
+    ))
+]
+async fn get_rival_suggestions(
+    State(db): State<Db>,
+    State(redis): State<Cache>,
+    claims: Claims,
+    Query(query): Query<GetSuggestionsParams>,
+) -> Result<Json<Vec<RivalSuggestion>>, RouteError> {
+    use crate::schema::{players, rivalries};
+
+    let mut conn = db.read_conn().await?;
+
+    let player: Player = players::table
+        .find(claims.profile.id)
+        .first(&mut conn)
+        .await?;
+    let key = leaderboard_key(query.league);
+    let (rank, own_points) = player
+        .get_rank_and_skill_points(query.league, &redis)
+        .await?;
+
+    // Ranks start at 1, players without one would be placed below the last one
+    let position = if rank > 0 {
+        i64::from(rank - 1)
+    } else {
+        redis.zcard(key).await?
+    };
+    let neighbours: Vec<(i32, f64)> = redis
+        .zrevrange(
+            key,
+            (position - SUGGESTION_WINDOW).max(0),
+            position + SUGGESTION_WINDOW,
+            true,
+        )
+        .await?;
+    let neighbour_ids: Vec<i32> = neighbours.iter().map(|(id, _)| *id).collect();
+
+    let mut excluded: HashSet<i32> = rivalries::table
+        .filter(rivalries::challenger_id.eq(player.id))
+        .filter(rivalries::rival_id.eq_any(&neighbour_ids))
+        .select(rivalries::rival_id)
+        .load::<i32>(&mut conn)
+        .await?
+        .into_iter()
+        .collect();
+    excluded.insert(player.id);
+    if let Some(location_id) = query.location_id {
+        let elsewhere: Vec<i32> = players::table
+            .filter(players::id.eq_any(&neighbour_ids))
+            // Players without a location aren't from this one either
+            .filter(
+                players::location_id
+                    .ne(location_id)
+                    .or(players::location_id.is_null()),
+            )
+            .select(players::id)
+            .load(&mut conn)
+            .await?;
+        excluded.extend(elsewhere);
+    }
+
+    let suggested = closest_candidates(
+        f64::from(own_points),
+        neighbours,
+        &excluded,
+        SUGGESTION_COUNT,
+    );
+    let suggested_ids: Vec<i32> = suggested.iter().map(|(id, _)| *id).collect();
+    let mut profiles: HashMap<i32, PlayerPublic> = players::table
+        .filter(players::id.eq_any(&suggested_ids))
+        .select(PlayerPublic::as_select())
+        .load::<PlayerPublic>(&mut conn)
+        .await?
+        .into_iter()
+        .map(|profile| (profile.id, profile))
+        .collect();
+
+    #[allow(clippy::cast_possible_truncation)]
+    let suggestions = suggested
+        .into_iter()
+        .filter_map(|(id, points)| {
+            profiles.remove(&id).map(|player| RivalSuggestion {
+                player,
+                skill_points: points as i32,
+            })
+        })
+        .collect();
+
+    Ok(Json(suggestions))
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct AddRivalResponse {
+    #[serde(flatten)]
+    rivalry: RivalryView,
+    /// When the rival added the player, only set if the rivalry is mutual
+    #[serde(
+        serialize_with = "time::serde::iso8601::option::serialize",
+        skip_serializing_if = "Option::is_none"
+    )]
+    rival_established_at: Option<time::OffsetDateTime>,
 }
 
 #[derive(Deserialize, ToSchema)]
@@ -63,12 +295,15 @@ struct ModifyRivalRequest {
 }
 
 /// Add rival
+///
+/// If Steam friend sync already added the rival, they're kept as a rival from now on,
+/// even once the players aren't Steam friends anymore.
 #[utoipa::path(
     method(post),
     path = "/add",
     responses(
-        (status = OK, body = RivalryView, description = "Success", content_type = "application/json"),
-        (status = NOT_FOUND, description = "Couldn't find player to rival", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = OK, body = AddRivalResponse, description = "Success", content_type = "application/json"),
+        (status = NOT_FOUND, description = "Couldn't find player to rival, or it's the player themself", body = SimpleRouteErrorOutput, content_type = "application/json"),
         (status = BAD_REQUEST, description = "Invalid parameters", body = SimpleRouteErrorOutput, content_type = "application/json"),
         (status = CONFLICT, description = "Rivalry already exists", body = SimpleRouteErrorOutput, content_type = "application/json"),
         (status = UNAUTHORIZED, description = "Unauthorized", body = SimpleRouteErrorOutput, content_type = "application/json"),
@@ -79,13 +314,19 @@ struct ModifyRivalRequest {
     ))
 ]
 async fn add_rival(
-    State(state): State<AppState>,
+    State(db): State<Db>,
     claims: Claims,
     Json(payload): Json<ModifyRivalRequest>,
-) -> Result<Json<RivalryView>, RouteError> {
+) -> Result<Json<AddRivalResponse>, RouteError> {
     use crate::schema::{players::dsl::*, rivalries::dsl::*};
 
-    let mut conn = state.db.get().await?;
+    if payload.rival_id == claims.profile.id {
+        return Err(
+            RouteError::new_not_found().set_public_error_message("You can't rival yourself")
+        );
+    }
+
+    let mut conn = db.get().await?;
 
     let player: Player = players.find(claims.profile.id).first(&mut conn).await?;
     let rival: Player = players
@@ -102,20 +343,46 @@ async fn add_rival(
         .await
         .optional()?;
 
-    if rivalry.is_some() {
-        Err(RouteError::new_conflict().set_public_error_message("Rivalry already exists"))
-    } else {
-        let new_rivalry = NewRivalry {
-            challenger_id: player.id,
-            rival_id: rival.id,
+    let established_at = match rivalry {
+        Some(rivalry) if rivalry.source == RivalrySource::Manual => {
+            return Err(
+                RouteError::new_conflict().set_public_error_message("Rivalry already exists")
+            );
         }
-        .create(&mut conn)
-        .await?;
+        // Steam friend sync added it, adding it by hand makes sure it's never removed again
+        Some(rivalry) => {
+            diesel::update(&rivalry)
+                .set(source.eq(RivalrySource::Manual))
+                .execute(&mut conn)
+                .await?;
+            rivalry.established_at
+        }
+        None => {
+            NewRivalry {
+                challenger_id: player.id,
+                rival_id: rival.id,
+            }
+            .create(&mut conn)
+            .await?
+            .established_at
+        }
+    };
 
-        Ok(Json(
-            RivalryView::from_rivalry(new_rivalry, &mut conn).await?,
-        ))
-    }
+    let reverse_rivalry = rivalries
+        .filter(challenger_id.eq(rival.id))
+        .filter(rival_id.eq(player.id))
+        .first::<Rivalry>(&mut conn)
+        .await
+        .optional()?;
+
+    Ok(Json(AddRivalResponse {
+        rivalry: RivalryView {
+            established_at,
+            rival: rival.into(),
+            mutual: reverse_rivalry.is_some(),
+        },
+        rival_established_at: reverse_rivalry.map(|reverse| reverse.established_at),
+    }))
 }
 
 /// Remove rival
@@ -137,13 +404,13 @@ async fn add_rival(
     ))
 ]
 async fn remove_rival(
-    State(state): State<AppState>,
+    State(db): State<Db>,
     claims: Claims,
     Json(payload): Json<ModifyRivalRequest>,
 ) -> Result<(), RouteError> {
     use crate::schema::{players::dsl::*, rivalries::dsl::*};
 
-    let mut conn = state.db.get().await?;
+    let mut conn = db.get().await?;
 
     let player: Player = players.find(claims.profile.id).first(&mut conn).await?;
     let rival: Player = players
@@ -173,3 +440,24 @@ async fn remove_rival(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn suggestions_are_the_closest_players_that_are_not_excluded() {
+        let candidates = vec![
+            (1, 5000.0),
+            (2, 4200.0),
+            (3, 4000.0),
+            (4, 3900.0),
+            (5, 1000.0),
+        ];
+        let excluded = HashSet::from([3, 9]);
+
+        let suggested = closest_candidates(4000.0, candidates, &excluded, 3);
+
+        assert_eq!(suggested, vec![(4, 3900.0), (2, 4200.0), (1, 5000.0)]);
+    }
+}
@@ -1,36 +1,51 @@
+use std::sync::Arc;
+
 use axum::{
     extract::{Path, Query, State},
+    http::header,
+    response::{IntoResponse, Response},
     Json,
 };
 use diesel::prelude::*;
 use diesel_async::RunQueryDsl;
+use fred::prelude::*;
 use serde::Deserialize;
 use serde_inline_default::serde_inline_default;
+use time::OffsetDateTime;
+use tracing::warn;
 use utoipa_axum::{router::OpenApiRouter, routes};
-use validator::Validate;
+use validator::{Validate, ValidationError};
 
 use crate::{
     models::{
         extra_song_info::ExtraSongInfo,
         players::{AccountType, Player, PlayerPublic},
-        scores::Score,
+        score_history::ArchivedScore,
+        score_reports::{NewScoreReport, ScoreReport},
+        scores::{Score, RECENT_SCORES_CACHE_KEY},
         songs::Song,
     },
-    schema::extra_song_info,
+    schema::{extra_song_info, score_history},
     util::{
         errors::{RouteError, SimpleRouteErrorOutput},
         game_types::{Character, League},
         jwt::Claims,
+        modifiers::{validate_modifier_filter, ModifierFilter},
         query::SortType,
+        validator::{ValidatedJson, ValidatedQuery},
+        xstats::DecodedXstats,
     },
-    AppState,
+    AppState, Cache, Config, Db,
 };
 
 pub fn routes() -> OpenApiRouter<AppState> {
     OpenApiRouter::new()
         .routes(routes!(get_score, delete_score))
+        .routes(routes!(get_score_history))
+        .routes(routes!(report_score))
         .routes(routes!(get_scores))
         .routes(routes!(get_rival_scores))
+        .routes(routes!(get_recent_scores))
 }
 
 #[serde_inline_default]
@@ -41,6 +56,23 @@ struct GetScoreParams {
     with_player: bool,
     #[serde_inline_default(true)]
     with_song: bool,
+    #[serde_inline_default(false)]
+    with_rank: bool,
+    #[serde_inline_default(false)]
+    with_decoded_stats: bool,
+}
+
+/// How long the first page of recent scores is cached, unless a new score comes in first
+const RECENT_SCORES_CACHE_SECS: i64 = 30;
+
+/// Picks the response out of a cached first page of recent scores, unless it's older than `RECENT_SCORES_CACHE_SECS`.
+///
+/// The pages are stored as `<unix time cached>:<response>`,
+/// since the hash they're in only has one expiry, which each newly cached page pushes back.
+fn fresh_cached_page(cached: &str, now: i64) -> Option<&str> {
+    let (cached_at, body) = cached.split_once(':')?;
+    let cached_at: i64 = cached_at.parse().ok()?;
+    (now - cached_at < RECENT_SCORES_CACHE_SECS).then_some(body)
 }
 
 #[derive(serde::Serialize, utoipa::ToSchema)]
@@ -61,6 +93,22 @@ struct ScoreSearchResult {
     song: Option<Song>,
     #[serde(skip_serializing_if = "Option::is_none")]
     extra_info: Option<ExtraSongInfo>,
+    /// Position on the song's leaderboard in the score's league, starting at 1
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rank: Option<i64>,
+    /// `xstats` decoded according to the character
+    #[serde(skip_serializing_if = "Option::is_none")]
+    decoded_xstats: Option<DecodedXstats>,
+}
+
+impl ScoreSearchResult {
+    /// Fills in `decoded_xstats` if `decode` is set.
+    fn with_decoded_xstats(mut self, decode: bool) -> Self {
+        if decode {
+            self.decoded_xstats = Some(self.score.decoded_xstats());
+        }
+        self
+    }
 }
 
 /// Get score by ID
@@ -71,6 +119,8 @@ struct ScoreSearchResult {
         ("id" = i32, Path, description = "ID of score to get"),
         ("withPlayer" = Option<bool>, Query, description = "Include player info"),
         ("withSong" = Option<bool>, Query, description = "Include song info"),
+        ("withRank" = Option<bool>, Query, description = "Include the score's position on the song's leaderboard in its league"),
+        ("withDecodedStats" = Option<bool>, Query, description = "Include `xstats` decoded according to the character"),
     ),
     responses(
         (status = OK, description = "Success", body = ScoreSearchResult, content_type = "application/json"),
@@ -79,13 +129,13 @@ struct ScoreSearchResult {
     )
 )]
 async fn get_score(
-    State(state): State<AppState>,
+    State(db): State<Db>,
     Path(id): Path<i32>,
     query: Query<GetScoreParams>,
 ) -> Result<Json<ScoreSearchResult>, RouteError> {
     use crate::schema::{players, scores, songs};
 
-    let mut conn = state.db.get().await?;
+    let mut conn = db.read_conn().await?;
 
     let score: Score = scores::table
         .find(id)
@@ -118,12 +168,26 @@ async fn get_score(
         (None, None)
     };
 
-    Ok(Json(ScoreSearchResult {
-        score,
-        player,
-        song: query_result.0,
-        extra_info: query_result.1,
-    }))
+    let rank = if query.with_rank {
+        Score::league_ranks(&[score.id], &mut conn)
+            .await?
+            .first()
+            .map(|&(_, rank)| rank)
+    } else {
+        None
+    };
+
+    Ok(Json(
+        ScoreSearchResult {
+            score,
+            player,
+            song: query_result.0,
+            extra_info: query_result.1,
+            rank,
+            decoded_xstats: None,
+        }
+        .with_decoded_xstats(query.with_decoded_stats),
+    ))
 }
 
 ///Delete score by ID
@@ -144,7 +208,9 @@ async fn get_score(
     )
 )]
 async fn delete_score(
-    State(state): State<AppState>,
+    State(config): State<Arc<Config>>,
+    State(db): State<Db>,
+    State(redis): State<Cache>,
     Path(id): Path<i32>,
     claims: Claims,
 ) -> Result<(), RouteError> {
@@ -153,7 +219,7 @@ async fn delete_score(
     if claims.profile.account_type == AccountType::Moderator
         || claims.profile.account_type == AccountType::Team
     {
-        let mut conn = state.db.get().await?;
+        let mut conn = db.get().await?;
 
         let score: Score = scores::table
             .find(id)
@@ -161,7 +227,9 @@ async fn delete_score(
             .await
             .optional()?
             .ok_or_else(RouteError::new_not_found)?;
-        score.delete(&mut conn, &state.redis).await?;
+        score
+            .delete(&config.skill_points, &mut conn, &redis)
+            .await?;
 
         Ok(())
     } else {
@@ -169,9 +237,99 @@ async fn delete_score(
     }
 }
 
+/// Report score
+///
+/// Reports a score that looks cheated to the moderators.
+/// Each player can only report a score once.
+#[utoipa::path(
+    method(post),
+    path = "/{id}/report",
+    params(
+        ("id" = i32, Path, description = "ID of score to report"),
+    ),
+    request_body = NewScoreReport,
+    responses(
+        (status = OK, description = "Success"),
+        (status = BAD_REQUEST, description = "Invalid body", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = NOT_FOUND, description = "Score not found", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = CONFLICT, description = "Score was already reported by this player", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = UNAUTHORIZED, description = "Not logged in or invalid token", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = INTERNAL_SERVER_ERROR, description = "Miscellaneous error", body = SimpleRouteErrorOutput)
+    ),
+    security(
+        ("token_jwt" = [])
+    )
+)]
+async fn report_score(
+    State(db): State<Db>,
+    Path(id): Path<i32>,
+    claims: Claims,
+    ValidatedJson(report): ValidatedJson<NewScoreReport>,
+) -> Result<(), RouteError> {
+    use crate::schema::scores;
+
+    let mut conn = db.get().await?;
+
+    let score_exists: bool = diesel::select(diesel::dsl::exists(scores::table.find(id)))
+        .get_result(&mut conn)
+        .await?;
+    if !score_exists {
+        return Err(RouteError::new_not_found());
+    }
+
+    if !ScoreReport::create(id, claims.profile.id, &report.reason, &mut conn).await? {
+        return Err(
+            RouteError::new_conflict().set_public_error_message("You already reported this score")
+        );
+    }
+
+    Ok(())
+}
+
+/// Get score history
+///
+/// Lists the personal bests this score replaced, oldest first.
+/// The current one is the score itself.
+#[utoipa::path(
+    method(get),
+    path = "/{id}/history",
+    params(
+        ("id" = i32, Path, description = "ID of score to get the history of"),
+    ),
+    responses(
+        (status = OK, description = "Success", body = Vec<ArchivedScore>, content_type = "application/json"),
+        (status = NOT_FOUND, description = "Score not found", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = INTERNAL_SERVER_ERROR, description = "Miscellaneous error", body = SimpleRouteErrorOutput)
+    )
+)]
+async fn get_score_history(
+    State(db): State<Db>,
+    Path(id): Path<i32>,
+) -> Result<Json<Vec<ArchivedScore>>, RouteError> {
+    use crate::schema::scores;
+
+    let mut conn = db.read_conn().await?;
+
+    let score: Score = scores::table
+        .find(id)
+        .first(&mut conn)
+        .await
+        .optional()?
+        .ok_or_else(RouteError::new_not_found)?;
+
+    let history = ArchivedScore::belonging_to(&score)
+        .order(score_history::submitted_at.asc())
+        .select(ArchivedScore::as_select())
+        .load(&mut conn)
+        .await?;
+
+    Ok(Json(history))
+}
+
 #[serde_inline_default]
 #[derive(Deserialize, Validate)]
 #[serde(rename_all = "camelCase")]
+#[validate(schema(function = "validate_score_range"))]
 struct GetScoresParams {
     #[serde_inline_default(false)]
     with_player: bool,
@@ -188,9 +346,26 @@ struct GetScoresParams {
     league: Option<League>,
     character: Option<Character>,
     player_id: Option<i32>,
+    min_score: Option<i32>,
+    max_score: Option<i32>,
+    has_feats: Option<bool>,
+    #[validate(custom(function = "validate_modifier_filter"))]
+    modifier: Option<String>,
+    #[serde_inline_default(false)]
+    with_decoded_stats: bool,
+}
+
+fn validate_score_range(params: &GetScoresParams) -> Result<(), ValidationError> {
+    match (params.min_score, params.max_score) {
+        (Some(min), Some(max)) if min > max => Err(ValidationError::new("score_range")
+            .with_message("minScore can't be greater than maxScore".into())),
+        _ => Ok(()),
+    }
 }
 
 /// Search for scores
+///
+/// Served from the read replica if one is configured, so very recent scores might not show up yet.
 #[utoipa::path(
     method(get),
     path = "/",
@@ -204,20 +379,38 @@ struct GetScoresParams {
         ("league" = Option<League>, Query, description = "League to filter by"),
         ("character" = Option<Character>, Query, description = "Character to filter by"),
         ("playerId" = Option<i32>, Query, description = "Player ID to filter by"),
+        ("minScore" = Option<i32>, Query, description = "Only include scores at or above this value"),
+        ("maxScore" = Option<i32>, Query, description = "Only include scores at or below this value, can't be lower than `minScore`"),
+        ("hasFeats" = Option<bool>, Query, description = "Only include scores with (`true`) or without (`false`) any feats"),
+        ("modifier" = Option<String>, Query, description = "Only include scores on songs with all of these comma-separated modifiers (`steep` or `flat`, in any case), or `none` for songs without any"),
+        ("withDecodedStats" = Option<bool>, Query, description = "Include `xstats` decoded according to the character"),
     ),
     responses(
         (status = OK, description = "Success", body = ScoreSearchResponse, content_type = "application/json"),
+        (status = BAD_REQUEST, description = "Invalid query parameters", body = SimpleRouteErrorOutput, content_type = "application/json"),
         (status = NOT_FOUND, description = "Song not found", body = SimpleRouteErrorOutput, content_type = "application/json"),
         (status = INTERNAL_SERVER_ERROR, description = "Miscellaneous error", body = SimpleRouteErrorOutput)
     )
 )]
 async fn get_scores(
-    State(state): State<AppState>,
-    query: Query<GetScoresParams>,
+    State(db): State<Db>,
+    ValidatedQuery(query): ValidatedQuery<GetScoresParams>,
 ) -> Result<Json<ScoreSearchResponse>, RouteError> {
+    use diesel::{dsl::sql, sql_types::Bool};
+
     use crate::schema::{players, scores, songs};
 
-    let mut conn = state.db.get().await?;
+    // feats is an array column, so presence is checked by its length
+    let feats_filter = query.has_feats.map(|has_feats| {
+        if has_feats {
+            "cardinality(scores.feats) > 0"
+        } else {
+            "cardinality(scores.feats) = 0"
+        }
+    });
+    let modifier_filter = query.modifier.as_deref().and_then(ModifierFilter::parse);
+
+    let mut conn = db.read_conn().await?;
 
     let mut db_query = scores::table.into_boxed();
     if let Some(league) = query.league {
@@ -229,6 +422,18 @@ async fn get_scores(
     if let Some(player_id) = query.player_id {
         db_query = db_query.filter(scores::player_id.eq(player_id));
     }
+    if let Some(min_score) = query.min_score {
+        db_query = db_query.filter(scores::score.ge(min_score));
+    }
+    if let Some(max_score) = query.max_score {
+        db_query = db_query.filter(scores::score.le(max_score));
+    }
+    if let Some(feats_filter) = feats_filter {
+        db_query = db_query.filter(sql::<Bool>(feats_filter));
+    }
+    if let Some(modifier_filter) = &modifier_filter {
+        db_query = db_query.filter(scores::song_id.eq_any(modifier_filter.matching_song_ids()));
+    }
 
     if let Some(time_sort) = &query.time_sort {
         match time_sort {
@@ -256,6 +461,19 @@ async fn get_scores(
     if let Some(player_id) = query.player_id {
         total_count_query = total_count_query.filter(scores::player_id.eq(player_id));
     }
+    if let Some(min_score) = query.min_score {
+        total_count_query = total_count_query.filter(scores::score.ge(min_score));
+    }
+    if let Some(max_score) = query.max_score {
+        total_count_query = total_count_query.filter(scores::score.le(max_score));
+    }
+    if let Some(feats_filter) = feats_filter {
+        total_count_query = total_count_query.filter(sql::<Bool>(feats_filter));
+    }
+    if let Some(modifier_filter) = &modifier_filter {
+        total_count_query =
+            total_count_query.filter(scores::song_id.eq_any(modifier_filter.matching_song_ids()));
+    }
     let total: i64 = total_count_query.count().get_result(&mut conn).await?;
 
     //FIXME This is messed up. What. Is there a better way to do this???
@@ -276,11 +494,16 @@ async fn get_scores(
 
             let results = items
                 .into_iter()
-                .map(|(score, player, song, extra_info)| ScoreSearchResult {
-                    score,
-                    player: Some(player.into()),
-                    song: Some(song),
-                    extra_info,
+                .map(|(score, player, song, extra_info)| {
+                    ScoreSearchResult {
+                        score,
+                        player: Some(player.into()),
+                        song: Some(song),
+                        extra_info,
+                        rank: None,
+                        decoded_xstats: None,
+                    }
+                    .with_decoded_xstats(query.with_decoded_stats)
                 })
                 .collect();
             Ok(Json(ScoreSearchResponse { results, total }))
@@ -294,11 +517,16 @@ async fn get_scores(
 
             let results = items
                 .into_iter()
-                .map(|(score, player)| ScoreSearchResult {
-                    score,
-                    player: Some(player.into()),
-                    song: None,
-                    extra_info: None,
+                .map(|(score, player)| {
+                    ScoreSearchResult {
+                        score,
+                        player: Some(player.into()),
+                        song: None,
+                        extra_info: None,
+                        rank: None,
+                        decoded_xstats: None,
+                    }
+                    .with_decoded_xstats(query.with_decoded_stats)
                 })
                 .collect();
             Ok(Json(ScoreSearchResponse { results, total }))
@@ -316,11 +544,16 @@ async fn get_scores(
 
             let results = items
                 .into_iter()
-                .map(|(score, song, extra_info)| ScoreSearchResult {
-                    score,
-                    player: None,
-                    song: Some(song),
-                    extra_info,
+                .map(|(score, song, extra_info)| {
+                    ScoreSearchResult {
+                        score,
+                        player: None,
+                        song: Some(song),
+                        extra_info,
+                        rank: None,
+                        decoded_xstats: None,
+                    }
+                    .with_decoded_xstats(query.with_decoded_stats)
                 })
                 .collect();
             Ok(Json(ScoreSearchResponse { results, total }))
@@ -329,11 +562,16 @@ async fn get_scores(
             let scores_only: Vec<Score> = db_query.load(&mut conn).await?;
             let results = scores_only
                 .into_iter()
-                .map(|score| ScoreSearchResult {
-                    score,
-                    player: None,
-                    song: None,
-                    extra_info: None,
+                .map(|score| {
+                    ScoreSearchResult {
+                        score,
+                        player: None,
+                        song: None,
+                        extra_info: None,
+                        rank: None,
+                        decoded_xstats: None,
+                    }
+                    .with_decoded_xstats(query.with_decoded_stats)
                 })
                 .collect();
             Ok(Json(ScoreSearchResponse { results, total }))
@@ -359,10 +597,14 @@ struct GetRivalScoresParams {
     score_sort: Option<SortType>,
     league: Option<League>,
     character: Option<Character>,
+    #[serde_inline_default(false)]
+    with_decoded_stats: bool,
 }
 
 //FIXME: maybe duplicating all the code from the other route is not the best idea?
 /// Get rivals' scores
+///
+/// Served from the read replica if one is configured, so very recent scores might not show up yet.
 #[utoipa::path(
     method(get),
     path = "/rivals",
@@ -375,6 +617,7 @@ struct GetRivalScoresParams {
         ("scoreSort" = Option<SortType>, Query, description = "Sort by score"),
         ("league" = Option<League>, Query, description = "League to filter by"),
         ("character" = Option<Character>, Query, description = "Character to filter by"),
+        ("withDecodedStats" = Option<bool>, Query, description = "Include `xstats` decoded according to the character"),
     ),
     responses(
         (status = OK, description = "Success", body = ScoreSearchResponse, content_type = "application/json"),
@@ -384,13 +627,13 @@ struct GetRivalScoresParams {
     )
 )]
 async fn get_rival_scores(
-    State(state): State<AppState>,
+    State(db): State<Db>,
     query: Query<GetRivalScoresParams>,
     claims: Claims,
 ) -> Result<Json<ScoreSearchResponse>, RouteError> {
     use crate::schema::{players, scores, songs};
 
-    let mut conn = state.db.get().await?;
+    let mut conn = db.read_conn().await?;
 
     let player: Player = players::table
         .find(claims.profile.id)
@@ -456,11 +699,16 @@ async fn get_rival_scores(
 
             let results = items
                 .into_iter()
-                .map(|(score, player, song, extra_info)| ScoreSearchResult {
-                    score,
-                    player: Some(player.into()),
-                    song: Some(song),
-                    extra_info,
+                .map(|(score, player, song, extra_info)| {
+                    ScoreSearchResult {
+                        score,
+                        player: Some(player.into()),
+                        song: Some(song),
+                        extra_info,
+                        rank: None,
+                        decoded_xstats: None,
+                    }
+                    .with_decoded_xstats(query.with_decoded_stats)
                 })
                 .collect();
             Ok(Json(ScoreSearchResponse { results, total }))
@@ -474,11 +722,16 @@ async fn get_rival_scores(
 
             let results = items
                 .into_iter()
-                .map(|(score, player)| ScoreSearchResult {
-                    score,
-                    player: Some(player.into()),
-                    song: None,
-                    extra_info: None,
+                .map(|(score, player)| {
+                    ScoreSearchResult {
+                        score,
+                        player: Some(player.into()),
+                        song: None,
+                        extra_info: None,
+                        rank: None,
+                        decoded_xstats: None,
+                    }
+                    .with_decoded_xstats(query.with_decoded_stats)
                 })
                 .collect();
             Ok(Json(ScoreSearchResponse { results, total }))
@@ -496,11 +749,16 @@ async fn get_rival_scores(
 
             let results = items
                 .into_iter()
-                .map(|(score, song, extra_info)| ScoreSearchResult {
-                    score,
-                    player: None,
-                    song: Some(song),
-                    extra_info,
+                .map(|(score, song, extra_info)| {
+                    ScoreSearchResult {
+                        score,
+                        player: None,
+                        song: Some(song),
+                        extra_info,
+                        rank: None,
+                        decoded_xstats: None,
+                    }
+                    .with_decoded_xstats(query.with_decoded_stats)
                 })
                 .collect();
             Ok(Json(ScoreSearchResponse { results, total }))
@@ -509,14 +767,394 @@ async fn get_rival_scores(
             let scores_only: Vec<Score> = db_query.load(&mut conn).await?;
             let results = scores_only
                 .into_iter()
-                .map(|score| ScoreSearchResult {
-                    score,
-                    player: None,
-                    song: None,
-                    extra_info: None,
+                .map(|score| {
+                    ScoreSearchResult {
+                        score,
+                        player: None,
+                        song: None,
+                        extra_info: None,
+                        rank: None,
+                        decoded_xstats: None,
+                    }
+                    .with_decoded_xstats(query.with_decoded_stats)
                 })
                 .collect();
             Ok(Json(ScoreSearchResponse { results, total }))
         }
     }
 }
+
+#[serde_inline_default]
+#[derive(Deserialize, Validate)]
+#[serde(rename_all = "camelCase")]
+struct GetRecentScoresParams {
+    #[validate(range(min = 1))]
+    #[serde_inline_default(1)]
+    page: i64,
+    #[validate(range(min = 1, max = 50))]
+    #[serde_inline_default(10)]
+    page_size: i64,
+    league: Option<League>,
+}
+
+/// Get recent scores
+///
+/// Lists the scores that were set or improved most recently, newest first, with their player and song.
+/// The first page is cached for up to 30 seconds, but new scores show up immediately.
+#[utoipa::path(
+    method(get),
+    path = "/recent",
+    params(
+        ("page" = Option<i64>, Query, description = "Page number", minimum = 1),
+        ("pageSize" = Option<i64>, Query, description = "Page size", minimum = 1, maximum = 50),
+        ("league" = Option<League>, Query, description = "League to filter by"),
+    ),
+    responses(
+        (status = OK, description = "Success", body = ScoreSearchResponse, content_type = "application/json"),
+        (status = BAD_REQUEST, description = "Invalid query parameters", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = INTERNAL_SERVER_ERROR, description = "Miscellaneous error", body = SimpleRouteErrorOutput)
+    )
+)]
+async fn get_recent_scores(
+    State(db): State<Db>,
+    State(redis): State<Cache>,
+    ValidatedQuery(query): ValidatedQuery<GetRecentScoresParams>,
+) -> Result<Response, RouteError> {
+    use crate::schema::{players, scores, songs};
+
+    // Only the first page is requested often enough to be worth caching
+    let cache_field = (query.page == 1).then(|| {
+        format!(
+            "{}:{}",
+            query
+                .league
+                .map_or_else(|| "all".to_owned(), |league| format!("{league:?}")),
+            query.page_size
+        )
+    });
+    if let Some(cache_field) = &cache_field {
+        match redis
+            .hget::<Option<String>, _, _>(RECENT_SCORES_CACHE_KEY, cache_field)
+            .await
+        {
+            Ok(Some(cached)) => {
+                if let Some(body) =
+                    fresh_cached_page(&cached, OffsetDateTime::now_utc().unix_timestamp())
+                {
+                    return Ok((
+                        [(header::CONTENT_TYPE, "application/json")],
+                        body.to_owned(),
+                    )
+                        .into_response());
+                }
+            }
+            Ok(None) => {}
+            Err(e) => warn!("Failed to get cached recent scores: {}", e),
+        }
+    }
+
+    // New scores invalidate the cache on the primary, so it's only filled from there.
+    // The replica might not have the new score yet.
+    let mut conn = if cache_field.is_some() {
+        db.get().await?
+    } else {
+        db.read_conn().await?
+    };
+
+    let mut total_query = scores::table.into_boxed();
+    let mut results_query = scores::table
+        .inner_join(players::table)
+        .inner_join(songs::table.left_join(extra_song_info::table))
+        .into_boxed();
+    if let Some(league) = query.league {
+        total_query = total_query.filter(scores::league.eq(league));
+        results_query = results_query.filter(scores::league.eq(league));
+    }
+
+    let total: i64 = total_query.count().get_result(&mut conn).await?;
+    let results = results_query
+        .order((scores::submitted_at.desc(), scores::id.desc()))
+        .limit(query.page_size)
+        .offset((query.page - 1) * query.page_size)
+        .select((
+            Score::as_select(),
+            Player::as_select(),
+            Song::as_select(),
+            Option::<ExtraSongInfo>::as_select(),
+        ))
+        .load::<(Score, Player, Song, Option<ExtraSongInfo>)>(&mut conn)
+        .await?
+        .into_iter()
+        .map(|(score, player, song, extra_info)| ScoreSearchResult {
+            score,
+            player: Some(player.into()),
+            song: Some(song),
+            extra_info,
+            rank: None,
+            decoded_xstats: None,
+        })
+        .collect();
+    let body = serde_json::to_string(&ScoreSearchResponse { results, total })?;
+
+    if let Some(cache_field) = cache_field {
+        let cached: Result<(), fred::error::Error> = async {
+            let cached_at = OffsetDateTime::now_utc().unix_timestamp();
+            let _: () = redis
+                .hset(
+                    RECENT_SCORES_CACHE_KEY,
+                    (cache_field, format!("{cached_at}:{body}")),
+                )
+                .await?;
+            // Only so the hash goes away eventually, pages that are too old are ignored anyway
+            redis
+                .expire(RECENT_SCORES_CACHE_KEY, RECENT_SCORES_CACHE_SECS, None)
+                .await
+        }
+        .await;
+        if let Err(e) = cached {
+            warn!("Failed to cache recent scores: {}", e);
+        }
+    }
+
+    Ok(([(header::CONTENT_TYPE, "application/json")], body).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+    };
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        models::scores::NewScore,
+        util::{
+            skill_points::SkillPointPolicy,
+            testing::{self, KeyStore, RecordedRedis},
+        },
+    };
+
+    /// Total of `GET /api/scores/recent`
+    async fn recent_total(state: &AppState) -> i64 {
+        let request = Request::builder()
+            .uri("/api/scores/recent")
+            .body(Body::empty())
+            .unwrap();
+        let response = crate::make_router(state.clone())
+            .oneshot(request)
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        serde_json::from_slice::<serde_json::Value>(&body).unwrap()["total"]
+            .as_i64()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn new_scores_replace_the_cached_page() {
+        let Some(state) = testing::state(Arc::new(KeyStore::default())).await else {
+            return;
+        };
+        let before = recent_total(&state).await;
+
+        {
+            let mut conn = state.db.get().await.unwrap();
+            let player_id = testing::insert_player(1, &mut conn).await;
+            let song_id = testing::insert_song("Dear Music", "Sumijun", &mut conn).await;
+            // KeyStore has no leaderboards, so the skill points get queued instead, which doesn't matter here
+            testing::insert_score(
+                player_id,
+                song_id,
+                League::Casual,
+                1000,
+                &mut conn,
+                &state.redis,
+            )
+            .await;
+        }
+        assert_eq!(recent_total(&state).await, before + 1);
+    }
+
+    /// Scores (lowest first) and total of `GET /api/scores` with `filters`
+    async fn search(state: &AppState, filters: &str) -> (Vec<i64>, i64) {
+        let request = Request::builder()
+            .uri(format!(
+                "/api/scores?withSong=false&scoreSort=asc&{filters}"
+            ))
+            .body(Body::empty())
+            .unwrap();
+        let response = crate::make_router(state.clone())
+            .oneshot(request)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK, "{filters}");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let scores = body["results"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|result| result["score"].as_i64().unwrap())
+            .collect();
+        (scores, body["total"].as_i64().unwrap())
+    }
+
+    #[tokio::test]
+    async fn score_and_feat_filters_apply_to_results_and_total() {
+        let Some(state) = testing::state(Arc::new(RecordedRedis::default())).await else {
+            return;
+        };
+        let player_id = {
+            let mut conn = state.db.get().await.unwrap();
+            let player_id = testing::insert_player(1, &mut conn).await;
+            for (title, league, score, vehicle, feats) in [
+                (
+                    "Song A",
+                    League::Casual,
+                    1000,
+                    Character::PointmanPro,
+                    &[][..],
+                ),
+                (
+                    "Song B",
+                    League::Casual,
+                    5000,
+                    Character::MonoPro,
+                    &["Clean Finish"][..],
+                ),
+                (
+                    "Song C",
+                    League::Pro,
+                    3000,
+                    Character::PointmanPro,
+                    &["Seeing Red"][..],
+                ),
+                ("Song D", League::Elite, 8000, Character::MonoPro, &[][..]),
+            ] {
+                let song_id = testing::insert_song(title, "Artist", &mut conn).await;
+                NewScore::new(
+                    player_id,
+                    song_id,
+                    league,
+                    score,
+                    &[],
+                    &[],
+                    0,
+                    vehicle,
+                    feats,
+                    180,
+                    score,
+                    0,
+                    0,
+                )
+                .create_or_update(&SkillPointPolicy::ORIGINAL, &mut conn, &state.redis)
+                .await
+                .unwrap();
+            }
+            player_id
+        };
+        let only = |filters: &str| format!("playerId={player_id}&{filters}");
+
+        // Each filter alone
+        assert_eq!(
+            search(&state, &only("minScore=3000")).await,
+            (vec![3000, 5000, 8000], 3)
+        );
+        assert_eq!(
+            search(&state, &only("maxScore=3000")).await,
+            (vec![1000, 3000], 2)
+        );
+        assert_eq!(
+            search(&state, &only("minScore=3000&maxScore=5000")).await,
+            (vec![3000, 5000], 2)
+        );
+        assert_eq!(
+            search(&state, &only("hasFeats=true")).await,
+            (vec![3000, 5000], 2)
+        );
+        assert_eq!(
+            search(&state, &only("hasFeats=false")).await,
+            (vec![1000, 8000], 2)
+        );
+
+        // Combined with league and character
+        assert_eq!(
+            search(&state, &only("minScore=2000&league=0")).await,
+            (vec![5000], 1)
+        );
+        assert_eq!(
+            search(&state, &only("maxScore=5000&character=11")).await,
+            (vec![5000], 1)
+        );
+        assert_eq!(
+            search(&state, &only("hasFeats=true&league=1")).await,
+            (vec![3000], 1)
+        );
+        assert_eq!(
+            search(&state, &only("hasFeats=false&character=11")).await,
+            (vec![8000], 1)
+        );
+        assert_eq!(
+            search(
+                &state,
+                &only("minScore=2000&maxScore=6000&hasFeats=true&character=0")
+            )
+            .await,
+            (vec![3000], 1)
+        );
+
+        // The total counts every match, not just the page
+        assert_eq!(
+            search(&state, &only("minScore=3000&pageSize=1")).await,
+            (vec![3000], 3)
+        );
+    }
+
+    #[test]
+    fn cached_pages_expire_on_their_own() {
+        assert_eq!(
+            fresh_cached_page("100:{\"total\":1}", 110),
+            Some("{\"total\":1}")
+        );
+        assert_eq!(
+            fresh_cached_page("100:{}", 100 + RECENT_SCORES_CACHE_SECS),
+            None
+        );
+        assert_eq!(fresh_cached_page("{}", 100), None);
+    }
+
+    fn params(min_score: Option<i32>, max_score: Option<i32>) -> GetScoresParams {
+        GetScoresParams {
+            with_player: false,
+            with_song: true,
+            page: 1,
+            page_size: 10,
+            time_sort: None,
+            score_sort: None,
+            league: None,
+            character: None,
+            player_id: None,
+            min_score,
+            max_score,
+            has_feats: None,
+            modifier: None,
+            with_decoded_stats: false,
+        }
+    }
+
+    #[test]
+    fn score_range_validation() {
+        assert!(params(None, None).validate().is_ok());
+        assert!(params(Some(5_000_000), None).validate().is_ok());
+        assert!(params(None, Some(100)).validate().is_ok());
+        assert!(params(Some(100), Some(100)).validate().is_ok());
+        assert!(params(Some(100), Some(200)).validate().is_ok());
+        assert!(params(Some(200), Some(100)).validate().is_err());
+    }
+}
@@ -1,22 +1,80 @@
-use axum::extract::{Path, State};
+use axum::{
+    extract::{Path, State},
+    Json,
+};
 use diesel::prelude::*;
 use diesel_async::RunQueryDsl;
+use serde::Serialize;
+use utoipa::ToSchema;
 use utoipa_axum::{router::OpenApiRouter, routes};
 
 use crate::{
-    models::shouts::Shout,
+    models::{
+        players::{Player, PlayerPublic},
+        shouts::Shout,
+        songs::Song,
+    },
     util::{
         errors::{RouteError, SimpleRouteErrorOutput},
         jwt::Claims,
     },
-    AppState,
+    AppState, Db,
 };
 
 pub fn routes() -> OpenApiRouter<AppState> {
-    OpenApiRouter::new().routes(routes!(delete_shout))
+    OpenApiRouter::new().routes(routes!(get_shout, delete_shout))
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct ShoutResponse {
+    shout: Shout,
+    author: PlayerPublic,
+    /// The song the shout is on
+    song: Song,
+}
+
+/// Get shout by ID
+#[utoipa::path(
+    method(get),
+    path = "/{id}",
+    params(
+        ("id" = i32, Path, description = "ID of shout to get"),
+    ),
+    responses(
+        (status = OK, description = "Success", body = ShoutResponse, content_type = "application/json"),
+        (status = NOT_FOUND, description = "Shout not found", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = INTERNAL_SERVER_ERROR, description = "Miscellaneous error", body = SimpleRouteErrorOutput)
+    )
+)]
+async fn get_shout(
+    State(db): State<Db>,
+    Path(id): Path<i32>,
+) -> Result<Json<ShoutResponse>, RouteError> {
+    use crate::schema::{players, shouts, songs};
+
+    let mut conn = db.read_conn().await?;
+
+    let (shout, author, song) = shouts::table
+        .find(id)
+        .inner_join(players::table)
+        .inner_join(songs::table)
+        .select((Shout::as_select(), Player::as_select(), Song::as_select()))
+        .first::<(Shout, Player, Song)>(&mut conn)
+        .await
+        .optional()?
+        .ok_or_else(RouteError::new_not_found)?;
+
+    Ok(Json(ShoutResponse {
+        shout,
+        author: author.into(),
+        song,
+    }))
 }
 
-///Delete shout by ID
+/// Delete shout by ID
+///
+/// Only the author, moderators and team members can delete a shout.
 #[utoipa::path(
     method(delete),
     path = "/{id}",
@@ -34,13 +92,13 @@ pub fn routes() -> OpenApiRouter<AppState> {
     )
 )]
 async fn delete_shout(
-    State(state): State<AppState>,
+    State(db): State<Db>,
     claims: Claims,
     Path(id): Path<i32>,
 ) -> Result<(), RouteError> {
     use crate::schema::shouts;
 
-    let mut conn = state.db.get().await?;
+    let mut conn = db.get().await?;
 
     let shout = shouts::table
         .filter(shouts::id.eq(id))
@@ -1,53 +1,75 @@
+use std::{collections::HashMap, sync::Arc};
+
 use axum::{
     extract::{Path, Query, State},
+    http::{header, StatusCode},
+    response::IntoResponse,
     Json,
 };
 use diesel::prelude::*;
 use diesel_async::RunQueryDsl;
+use fred::{prelude::*, types::Expiration};
 use serde::{Deserialize, Serialize};
 use serde_inline_default::serde_inline_default;
+use time::OffsetDateTime;
+use tracing::{info, warn};
 use utoipa::ToSchema;
 use utoipa_axum::{router::OpenApiRouter, routes};
-use validator::Validate;
+use validator::{Validate, ValidationError};
 
 use crate::{
     models::{
-        extra_song_info::{ExtraSongInfo, NewExtraSongInfo},
-        players::{Player, PlayerPublic},
+        extra_song_info::{AliasKind, ExtraSongInfo, NewExtraSongInfo},
+        players::{AccountType, Player, PlayerPublic},
+        radio_entries::RadioEntry,
         scores::Score,
-        shouts::Shout,
-        songs::Song,
+        shouts::{shout_rate_limit_key, NewShout, Shout, ShoutError, SHOUT_RATE_LIMIT_WINDOW_SECS},
+        song_daily_plays::SongDailyPlays,
+        song_tag_samples::SongTagSample,
+        songs::{DuplicateSong, Song},
     },
     schema,
     util::{
+        covers::CachedCovers,
         errors::{RouteError, SimpleRouteErrorOutput},
         game_types::{Character, League},
         jwt::Claims,
+        meilisearch::{self, SongHighlight},
+        modifiers::{validate_modifier_filter, ModifierFilter},
         musicbrainz,
-        radio::get_radio_songs as get_radio_songs_util,
-        validator::ValidatedQuery,
+        normalize::normalize,
+        radio::RadioLineup,
+        rate_limit,
+        validator::{ValidatedJson, ValidatedQuery},
     },
-    AppState,
+    AppState, Cache, Config, Db, Metadata,
 };
 
 pub fn routes() -> OpenApiRouter<AppState> {
     OpenApiRouter::new()
-        .routes(routes!(get_song, delete_song))
+        .routes(routes!(get_song, update_song, delete_song))
+        .routes(routes!(get_song_preview))
+        .routes(routes!(get_song_variants))
+        .routes(routes!(search_songs))
         .routes(routes!(get_top_songs))
         .routes(routes!(get_song_scores))
         .routes(routes!(get_radio_songs))
-        .routes(routes!(get_song_shouts))
+        .routes(routes!(get_song_shouts, add_song_shout))
         .routes(routes!(update_song_extra_info))
+        .routes(routes!(get_song_tag_samples))
         .routes(routes!(update_song_extra_info_mbid))
+        .routes(routes!(merge_song))
+        .routes(routes!(get_duplicate_songs))
+        .routes(routes!(add_song_alias, remove_song_alias))
 }
 
 #[derive(Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
-struct SongResponse {
+pub(super) struct SongResponse {
     #[serde(flatten)]
-    song: Song,
+    pub(super) song: Song,
     #[serde(skip_serializing_if = "Option::is_none")]
-    extra_info: Option<ExtraSongInfo>,
+    pub(super) extra_info: Option<ExtraSongInfo>,
 }
 
 #[derive(Deserialize)]
@@ -72,13 +94,13 @@ struct GetSongParams {
     )
 )]
 async fn get_song(
-    State(state): State<AppState>,
+    State(db): State<Db>,
     Path(id): Path<i32>,
     query: Query<GetSongParams>,
 ) -> Result<Json<SongResponse>, RouteError> {
     use crate::schema::songs;
 
-    let mut conn = state.db.get().await?;
+    let mut conn = db.read_conn().await?;
 
     let song: Song = songs::table
         .find(id)
@@ -100,6 +122,536 @@ async fn get_song(
     }))
 }
 
+/// Get song variants
+///
+/// Lists the other songs with the same title and artist, but different modifiers (e.g. the `[as-steep]` chart of a song), oldest first.
+#[utoipa::path(
+    method(get),
+    path = "/{id}/variants",
+    params(
+        ("id" = i32, Path, description = "ID of song to get the variants of")
+    ),
+    responses(
+        (status = OK, description = "Success", body = Vec<Song>, content_type = "application/json"),
+        (status = NOT_FOUND, description = "Song not found", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = INTERNAL_SERVER_ERROR, description = "Miscellaneous error", body = SimpleRouteErrorOutput)
+    )
+)]
+async fn get_song_variants(
+    State(db): State<Db>,
+    Path(id): Path<i32>,
+) -> Result<Json<Vec<Song>>, RouteError> {
+    use crate::schema::songs;
+
+    let mut conn = db.read_conn().await?;
+
+    let song: Song = songs::table
+        .find(id)
+        .first(&mut conn)
+        .await
+        .optional()?
+        .ok_or_else(RouteError::new_not_found)?;
+
+    let variants: Vec<Song> = songs::table
+        .filter(songs::title.eq(&song.title))
+        .filter(songs::artist.eq(&song.artist))
+        .filter(songs::modifiers.is_distinct_from(&song.modifiers))
+        .order(songs::id.asc())
+        .select(Song::as_select())
+        .load(&mut conn)
+        .await?;
+
+    Ok(Json(variants))
+}
+
+#[serde_inline_default]
+#[derive(Deserialize, Validate)]
+#[serde(rename_all = "camelCase")]
+struct SearchSongsParams {
+    #[validate(length(min = 1, max = 100))]
+    q: String,
+    #[validate(range(min = 1))]
+    #[serde_inline_default(1)]
+    page: i64,
+    #[validate(range(min = 1, max = 50))]
+    #[serde_inline_default(10)]
+    page_size: i64,
+    #[serde_inline_default(false)]
+    highlight: bool,
+    #[validate(custom(function = "validate_modifier_filter"))]
+    modifiers: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct SongSearchResult {
+    #[serde(flatten)]
+    song: SongResponse,
+    /// Only if `highlight` is set and the search is done by Meilisearch
+    #[serde(skip_serializing_if = "Option::is_none")]
+    highlight: Option<SongHighlight>,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct SongSearchResponse {
+    results: Vec<SongSearchResult>,
+    /// Estimated if the search is done by Meilisearch
+    total: i64,
+}
+
+/// For the `modifiers` parameter of routes that list songs
+const MODIFIERS_PARAM_DESCRIPTION: &str = "Only songs with exactly these modifiers (from `[as-...]` tags), comma-separated in the order they were tagged, like `steep,flat` (in any case). `none` for songs without modifiers";
+
+/// Makes user input safe to use in a `LIKE` pattern, matching it anywhere in the text.
+fn contains_pattern(text: &str) -> String {
+    let escaped = text
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    format!("%{escaped}%")
+}
+
+/// Search songs
+///
+/// Searches titles, artists, MusicBrainz metadata and aliases with Meilisearch, best match first.
+/// If Meilisearch isn't configured, titles and artists are searched in the database instead, sorted by title.
+/// Songs excluded from rankings aren't included.
+#[utoipa::path(
+    method(get),
+    path = "/search",
+    params(
+        ("q" = String, Query, description = "What to search for", min_length = 1, max_length = 100),
+        ("page" = Option<i64>, Query, description = "Page number", minimum = 1),
+        ("pageSize" = Option<i64>, Query, description = "Page size", minimum = 1, maximum = 50),
+        ("highlight" = Option<bool>, Query, description = "Include title and artist with the matches wrapped in `<em>` tags"),
+        ("modifiers" = Option<String>, Query, description = MODIFIERS_PARAM_DESCRIPTION),
+    ),
+    responses(
+        (status = OK, description = "Success", body = SongSearchResponse, content_type = "application/json"),
+        (status = BAD_REQUEST, description = "Invalid query parameters", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = INTERNAL_SERVER_ERROR, description = "Miscellaneous error", body = SimpleRouteErrorOutput)
+    )
+)]
+async fn search_songs(
+    State(db): State<Db>,
+    State(metadata): State<Metadata>,
+    ValidatedQuery(query): ValidatedQuery<SearchSongsParams>,
+) -> Result<Json<SongSearchResponse>, RouteError> {
+    use crate::schema::{extra_song_info, songs};
+
+    let mut conn = db.read_conn().await?;
+
+    let modifier_filter = query.modifiers.as_deref().and_then(ModifierFilter::parse);
+
+    let Some(meili) = &metadata.meili else {
+        let pattern = contains_pattern(&query.q);
+        let mut total_query = Song::ranked()
+            .left_join(extra_song_info::table)
+            .filter(
+                songs::title
+                    .ilike(&pattern)
+                    .or(songs::artist.ilike(&pattern))
+                    .nullable()
+                    .or(extra_song_info::musicbrainz_title.ilike(&pattern))
+                    .or(extra_song_info::musicbrainz_artist.ilike(&pattern)),
+            )
+            .into_boxed();
+        let mut results_query = Song::ranked()
+            .left_join(extra_song_info::table)
+            .filter(
+                songs::title
+                    .ilike(&pattern)
+                    .or(songs::artist.ilike(&pattern))
+                    .nullable()
+                    .or(extra_song_info::musicbrainz_title.ilike(&pattern))
+                    .or(extra_song_info::musicbrainz_artist.ilike(&pattern)),
+            )
+            .into_boxed();
+        if let Some(modifier_filter) = &modifier_filter {
+            total_query =
+                total_query.filter(songs::id.eq_any(modifier_filter.exactly_matching_song_ids()));
+            results_query =
+                results_query.filter(songs::id.eq_any(modifier_filter.exactly_matching_song_ids()));
+        }
+
+        let total: i64 = total_query.count().get_result(&mut conn).await?;
+        let results = results_query
+            .order((songs::title.asc(), songs::id.asc()))
+            .limit(query.page_size)
+            .offset((query.page - 1) * query.page_size)
+            .select((Song::as_select(), Option::<ExtraSongInfo>::as_select()))
+            .load::<(Song, Option<ExtraSongInfo>)>(&mut conn)
+            .await?
+            .into_iter()
+            .map(|(song, extra_info)| SongSearchResult {
+                song: SongResponse { song, extra_info },
+                highlight: None,
+            })
+            .collect();
+
+        return Ok(Json(SongSearchResponse { results, total }));
+    };
+
+    // Both are validated to be positive
+    let limit = usize::try_from(query.page_size).unwrap_or_default();
+    let offset = usize::try_from((query.page - 1) * query.page_size).unwrap_or_default();
+    let hits = meilisearch::search_songs(
+        meili,
+        &query.q,
+        limit,
+        offset,
+        query.highlight,
+        modifier_filter.as_ref(),
+    )
+    .await?;
+
+    let ids: Vec<i32> = hits.hits.iter().map(|(id, _)| *id).collect();
+    let mut found: HashMap<i32, (Song, Option<ExtraSongInfo>)> = Song::ranked()
+        .left_join(extra_song_info::table)
+        .filter(songs::id.eq_any(&ids))
+        .select((Song::as_select(), Option::<ExtraSongInfo>::as_select()))
+        .load::<(Song, Option<ExtraSongInfo>)>(&mut conn)
+        .await?
+        .into_iter()
+        .map(|(song, extra_info)| (song.id, (song, extra_info)))
+        .collect();
+    // The index can be behind, songs that are gone or excluded by now are skipped
+    let results = hits
+        .hits
+        .into_iter()
+        .filter_map(|(id, highlight)| {
+            found
+                .remove(&id)
+                .map(|(song, extra_info)| SongSearchResult {
+                    song: SongResponse { song, extra_info },
+                    highlight,
+                })
+        })
+        .collect();
+
+    Ok(Json(SongSearchResponse {
+        results,
+        total: hits.total,
+    }))
+}
+
+/// How long song previews are cached, in Redis and by clients
+const PREVIEW_CACHE_SECS: i64 = 5 * 60;
+/// Title and artist are cut off after this many bytes (UTF-8), so previews stay under 1 KB
+const PREVIEW_MAX_TEXT_BYTES: usize = 150;
+/// Player names in previews are cut off after this many bytes (UTF-8)
+const PREVIEW_MAX_NAME_BYTES: usize = 64;
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct SongPreview {
+    id: i32,
+    /// From MusicBrainz if the song has metadata, otherwise as tagged
+    title: String,
+    /// From MusicBrainz if the song has metadata, otherwise as tagged
+    artist: String,
+    cover_url: Option<String>,
+    /// Plays by all players together
+    play_count: i64,
+    /// The #1 of each league that has scores, Casual first
+    top_scores: Vec<PreviewTopScore>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct PreviewTopScore {
+    league: League,
+    player_name: String,
+    score: i32,
+}
+
+/// Cuts `text` off so it's at most `max_bytes` bytes long, ellipsis included, without splitting characters.
+fn truncate_bytes(text: &str, max_bytes: usize) -> String {
+    const ELLIPSIS: &str = "…";
+
+    if text.len() <= max_bytes {
+        return text.to_owned();
+    }
+    let mut end = max_bytes.saturating_sub(ELLIPSIS.len());
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}{ELLIPSIS}", &text[..end])
+}
+
+/// Get song preview
+///
+/// A small summary of a song for link previews (e.g. OpenGraph tags) and bots.
+/// The shape of this response is kept stable, and it's cached for five minutes, so it can be requested often.
+#[utoipa::path(
+    method(get),
+    path = "/{id}/preview",
+    params(
+        ("id" = i32, Path, description = "ID of song to preview"),
+    ),
+    responses(
+        (status = OK, description = "Success", body = SongPreview, content_type = "application/json"),
+        (status = NOT_FOUND, description = "Song not found", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = INTERNAL_SERVER_ERROR, description = "Miscellaneous error", body = SimpleRouteErrorOutput)
+    )
+)]
+async fn get_song_preview(
+    State(db): State<Db>,
+    State(redis): State<Cache>,
+    Path(id): Path<i32>,
+) -> Result<impl IntoResponse, RouteError> {
+    use crate::schema::{extra_song_info, players, scores, songs};
+
+    let cache_headers = [(
+        header::CACHE_CONTROL,
+        format!("public, max-age={PREVIEW_CACHE_SECS}"),
+    )];
+    let cache_key = format!("song_preview:{id}");
+    // The preview can always be built without Redis, so failing to use the cache is only logged
+    match redis.get::<Option<String>, _>(&cache_key).await {
+        Ok(Some(cached)) => match serde_json::from_str::<SongPreview>(&cached) {
+            Ok(preview) => return Ok((cache_headers, Json(preview))),
+            Err(e) => warn!("Cached preview of song {} is invalid: {}", id, e),
+        },
+        Ok(None) => {}
+        Err(e) => warn!("Failed to get cached preview of song {}: {}", id, e),
+    }
+
+    let mut conn = db.read_conn().await?;
+
+    let (song, extra_info): (Song, Option<ExtraSongInfo>) = songs::table
+        .find(id)
+        .left_join(extra_song_info::table)
+        .select((Song::as_select(), Option::<ExtraSongInfo>::as_select()))
+        .first(&mut conn)
+        .await
+        .optional()?
+        .ok_or_else(RouteError::new_not_found)?;
+
+    let top_scores = scores::table
+        .inner_join(players::table)
+        .filter(scores::song_id.eq(id))
+        .distinct_on(scores::league)
+        .order((
+            scores::league.asc(),
+            scores::score.desc(),
+            scores::submitted_at.asc(),
+        ))
+        .select((scores::league, players::username, scores::score))
+        .load::<(League, String, i32)>(&mut conn)
+        .await?
+        .into_iter()
+        .map(|(league, player_name, score)| PreviewTopScore {
+            league,
+            player_name: truncate_bytes(&player_name, PREVIEW_MAX_NAME_BYTES),
+            score,
+        })
+        .collect();
+
+    let play_count = song.times_played;
+    let (title, artist, cover_url) = match extra_info {
+        Some(extra_info) => {
+            let cover_url = extra_info
+                .public_cover_url()
+                .or_else(|| extra_info.public_cover_url_small());
+            (
+                extra_info.musicbrainz_title.unwrap_or(song.title),
+                extra_info.musicbrainz_artist.unwrap_or(song.artist),
+                cover_url,
+            )
+        }
+        None => (song.title, song.artist, None),
+    };
+    let preview = SongPreview {
+        id,
+        title: truncate_bytes(&title, PREVIEW_MAX_TEXT_BYTES),
+        artist: truncate_bytes(&artist, PREVIEW_MAX_TEXT_BYTES),
+        cover_url,
+        play_count,
+        top_scores,
+    };
+
+    let cached: Result<(), _> = redis
+        .set(
+            &cache_key,
+            serde_json::to_string(&preview)?,
+            Some(Expiration::EX(PREVIEW_CACHE_SECS)),
+            None,
+            false,
+        )
+        .await;
+    if let Err(e) = cached {
+        warn!("Failed to cache preview of song {}: {}", id, e);
+    }
+
+    Ok((cache_headers, Json(preview)))
+}
+
+#[derive(Deserialize, Validate, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct UpdateSongRequest {
+    /// Fix a typo in the title. The old title becomes an alias, so the game still finds the song under it
+    #[validate(length(min = 1, max = 200), custom(function = "validate_not_blank"))]
+    title: Option<String>,
+    /// Fix a typo in the artist. The old artist becomes an alias, so the game still finds the song under it
+    #[validate(length(min = 1, max = 200), custom(function = "validate_not_blank"))]
+    artist: Option<String>,
+    /// Hide the song from charts and stats, without affecting its page and leaderboard.
+    /// Moderators only
+    excluded_from_rankings: Option<bool>,
+}
+
+fn validate_not_blank(text: &str) -> Result<(), ValidationError> {
+    if text.trim().is_empty() {
+        return Err(ValidationError::new("blank").with_message("Must not be blank".into()));
+    }
+
+    Ok(())
+}
+
+/// Song with its extra info, and the fields only moderators get to see
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct UpdatedSongResponse {
+    #[serde(flatten)]
+    song: SongResponse,
+    /// Only for moderators
+    #[serde(skip_serializing_if = "Option::is_none")]
+    excluded_from_rankings: Option<bool>,
+}
+
+/// Update song by ID
+///
+/// Only the fields that are given are changed.
+/// Title and artist can be changed by anyone who can edit the song's metadata, excluding it from rankings is for moderators only.
+#[utoipa::path(
+    method(patch),
+    path = "/{id}",
+    params(
+        ("id" = i32, Path, description = "ID of song to update")
+    ),
+    request_body = UpdateSongRequest,
+    responses(
+        (status = OK, description = "Success", body = UpdatedSongResponse, content_type = "application/json"),
+        (status = BAD_REQUEST, description = "Empty or too long title or artist", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = UNAUTHORIZED, description = "Not logged in, invalid token or no permission", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = NOT_FOUND, description = "Song not found", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = INTERNAL_SERVER_ERROR, description = "Miscellaneous error", body = SimpleRouteErrorOutput)
+    ),
+    security(
+        ("token_jwt" = [])
+    )
+)]
+async fn update_song(
+    State(db): State<Db>,
+    State(metadata): State<Metadata>,
+    claims: Claims,
+    Path(id): Path<i32>,
+    ValidatedJson(payload): ValidatedJson<UpdateSongRequest>,
+) -> Result<Json<UpdatedSongResponse>, RouteError> {
+    use diesel_async::{scoped_futures::ScopedFutureExt, AsyncConnection};
+
+    use crate::schema::songs;
+
+    let is_moderator = claims.profile.account_type == AccountType::Moderator
+        || claims.profile.account_type == AccountType::Team;
+
+    let mut conn = db.get().await?;
+
+    let song: Song = songs::table
+        .find(id)
+        .first(&mut conn)
+        .await
+        .optional()?
+        .ok_or_else(RouteError::new_not_found)?;
+
+    let excluded_from_rankings = payload.excluded_from_rankings;
+    if excluded_from_rankings.is_some() && !is_moderator {
+        return Err(RouteError::new_unauthorized());
+    }
+    let renamed = payload.title.is_some() || payload.artist.is_some();
+    if renamed && !song.user_can_edit(claims.profile.id, &mut conn).await? {
+        return Err(RouteError::new_unauthorized());
+    }
+
+    let (song, extra_info) = conn
+        .transaction::<_, diesel::result::Error, _>(|conn| {
+            async move {
+                let mut song = song;
+                if let Some(excluded) = excluded_from_rankings {
+                    song = Song::set_excluded_from_rankings(song.id, excluded, conn).await?;
+                }
+
+                // Only the parts that actually change become aliases
+                let old_title = payload
+                    .title
+                    .as_ref()
+                    .filter(|title| **title != song.title)
+                    .map(|_| song.title.clone());
+                let old_artist = payload
+                    .artist
+                    .as_ref()
+                    .filter(|artist| **artist != song.artist)
+                    .map(|_| song.artist.clone());
+                if old_title.is_some() || old_artist.is_some() {
+                    ExtraSongInfo::add_aliases(
+                        song.id,
+                        old_title.as_deref(),
+                        old_artist.as_deref(),
+                        conn,
+                    )
+                    .await?;
+                    song = diesel::update(songs::table.find(song.id))
+                        .set((
+                            payload.title.as_deref().map(|title| {
+                                (
+                                    songs::title.eq(title),
+                                    songs::title_normalized.eq(normalize(title)),
+                                )
+                            }),
+                            payload.artist.as_deref().map(|artist| {
+                                (
+                                    songs::artist.eq(artist),
+                                    songs::artist_normalized.eq(normalize(artist)),
+                                )
+                            }),
+                        ))
+                        .get_result(conn)
+                        .await?;
+                }
+
+                let extra_info: Option<ExtraSongInfo> = ExtraSongInfo::belonging_to(&song)
+                    .first(conn)
+                    .await
+                    .optional()?;
+                Ok((song, extra_info))
+            }
+            .scope_boxed()
+        })
+        .await?;
+
+    if renamed {
+        info!(
+            "Player {} renamed song {} to {} - {}",
+            claims.profile.id, song.id, song.artist, song.title
+        );
+        meilisearch::reindex_song(metadata.song_index(), song.id, &mut conn).await;
+    }
+    if let Some(excluded) = excluded_from_rankings {
+        info!(
+            "Player {} set excluded_from_rankings of song {} to {}",
+            claims.profile.id, song.id, excluded
+        );
+        meilisearch::apply_exclusion(metadata.song_index(), song.id, excluded, &mut conn).await;
+    }
+
+    Ok(Json(UpdatedSongResponse {
+        excluded_from_rankings: is_moderator.then_some(song.excluded_from_rankings),
+        song: SongResponse { song, extra_info },
+    }))
+}
+
 /// Delete song by ID
 #[utoipa::path(
     method(delete),
@@ -118,13 +670,16 @@ async fn get_song(
     )
 )]
 async fn delete_song(
-    State(state): State<AppState>,
+    State(config): State<Arc<Config>>,
+    State(db): State<Db>,
+    State(redis): State<Cache>,
+    State(metadata): State<Metadata>,
     Path(id): Path<i32>,
     claims: Claims,
 ) -> Result<(), RouteError> {
     use crate::schema::songs;
 
-    let mut conn = state.db.get().await?;
+    let mut conn = db.get().await?;
 
     let song: Song = songs::table
         .find(id)
@@ -134,7 +689,13 @@ async fn delete_song(
         .ok_or_else(RouteError::new_not_found)?;
 
     if song.user_can_delete(claims.profile.id, &mut conn).await? {
-        song.delete(&mut conn, &state.redis).await?;
+        song.delete(
+            &config.skill_points,
+            &mut conn,
+            &redis,
+            metadata.song_index(),
+        )
+        .await?;
 
         Ok(())
     } else {
@@ -142,6 +703,192 @@ async fn delete_song(
     }
 }
 
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct MergeSongRequest {
+    /// ID of the song to merge into
+    target_id: i32,
+    /// Add the merged song's title and artist to the target's aliases, so the game finds the target under them
+    #[serde(default)]
+    add_alias: bool,
+}
+
+/// Merge song into another one
+///
+/// Moves the song's scores and favorites to the target and deletes it. Moderators only.
+/// Where both songs have a score of the same player and league, the higher one is kept with the play counts of both.
+#[utoipa::path(
+    method(post),
+    path = "/{id}/merge",
+    params(
+        ("id" = i32, Path, description = "ID of song to merge (and delete)")
+    ),
+    request_body = MergeSongRequest,
+    responses(
+        (status = OK, description = "Success, returns the target", body = SongResponse, content_type = "application/json"),
+        (status = UNAUTHORIZED, description = "Not logged in, invalid token or not a moderator", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = NOT_FOUND, description = "Song or target not found", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = CONFLICT, description = "Song and target are the same", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = INTERNAL_SERVER_ERROR, description = "Miscellaneous error", body = SimpleRouteErrorOutput)
+    ),
+    security(
+        ("token_jwt" = [])
+    )
+)]
+async fn merge_song(
+    State(config): State<Arc<Config>>,
+    State(db): State<Db>,
+    State(redis): State<Cache>,
+    State(metadata): State<Metadata>,
+    claims: Claims,
+    Path(id): Path<i32>,
+    Json(payload): Json<MergeSongRequest>,
+) -> Result<Json<SongResponse>, RouteError> {
+    use crate::schema::songs;
+
+    if claims.profile.account_type != AccountType::Moderator
+        && claims.profile.account_type != AccountType::Team
+    {
+        return Err(RouteError::new_unauthorized());
+    }
+    if id == payload.target_id {
+        return Err(
+            RouteError::new_conflict().set_public_error_message("Can't merge a song into itself")
+        );
+    }
+
+    let mut conn = db.get().await?;
+
+    let song: Song = songs::table
+        .find(id)
+        .first(&mut conn)
+        .await
+        .optional()?
+        .ok_or_else(RouteError::new_not_found)?;
+    let target_exists: bool =
+        diesel::select(diesel::dsl::exists(songs::table.find(payload.target_id)))
+            .get_result(&mut conn)
+            .await?;
+    if !target_exists {
+        return Err(RouteError::new_not_found().set_public_error_message("Target song not found"));
+    }
+
+    // Runs in a transaction, so a failed merge doesn't leave anything half-moved
+    song.merge_into(
+        payload.target_id,
+        payload.add_alias,
+        &config.skill_points,
+        &mut conn,
+        &redis,
+        metadata.song_index(),
+    )
+    .await?;
+
+    info!(
+        "Player {} merged song {} ({} - {}) into {} (alias: {})",
+        claims.profile.id, song.id, song.artist, song.title, payload.target_id, payload.add_alias
+    );
+
+    let target: Song = songs::table
+        .find(payload.target_id)
+        .first(&mut conn)
+        .await?;
+    let extra_info: Option<ExtraSongInfo> = ExtraSongInfo::belonging_to(&target)
+        .first(&mut conn)
+        .await
+        .optional()?;
+
+    Ok(Json(SongResponse {
+        song: target,
+        extra_info,
+    }))
+}
+
+#[serde_inline_default]
+#[derive(Deserialize, Validate)]
+#[serde(rename_all = "camelCase")]
+struct GetDuplicateSongsParams {
+    #[validate(range(min = 1))]
+    #[serde_inline_default(1)]
+    page: i64,
+    #[validate(range(min = 1, max = 50))]
+    #[serde_inline_default(20)]
+    page_size: i64,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct DuplicateGroupResponse {
+    /// The song with the most scores (or the oldest one if that's a tie), use it as `targetId` when merging the others
+    target_id: i32,
+    /// All songs of the group, starting with the target
+    songs: Vec<DuplicateSong>,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct DuplicateSongsResponse {
+    results: Vec<DuplicateGroupResponse>,
+    total: i64,
+}
+
+/// List duplicate songs
+///
+/// Groups songs whose title, artist and modifiers only differ in case, whitespace, "&" vs. "and" and the like,
+/// with how many scores each has. Merge them with `POST /{id}/merge`. Moderators only.
+#[utoipa::path(
+    method(get),
+    path = "/duplicates",
+    params(
+        ("page" = Option<i64>, Query, description = "Page number", minimum = 1),
+        ("pageSize" = Option<i64>, Query, description = "Number of groups per page", minimum = 1, maximum = 50),
+    ),
+    responses(
+        (status = OK, description = "Success", body = DuplicateSongsResponse, content_type = "application/json"),
+        (status = BAD_REQUEST, description = "Invalid query parameters", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = UNAUTHORIZED, description = "Not logged in, invalid token or not a moderator", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = INTERNAL_SERVER_ERROR, description = "Miscellaneous error", body = SimpleRouteErrorOutput)
+    ),
+    security(
+        ("token_jwt" = [])
+    )
+)]
+async fn get_duplicate_songs(
+    State(db): State<Db>,
+    claims: Claims,
+    ValidatedQuery(query): ValidatedQuery<GetDuplicateSongsParams>,
+) -> Result<Json<DuplicateSongsResponse>, RouteError> {
+    if claims.profile.account_type != AccountType::Moderator
+        && claims.profile.account_type != AccountType::Team
+    {
+        return Err(RouteError::new_unauthorized());
+    }
+
+    // Not from the read replica, songs that were just merged shouldn't show up again
+    let mut conn = db.get().await?;
+
+    let total = Song::count_duplicate_groups(&mut conn).await?;
+    let groups = Song::duplicate_groups(
+        Some(query.page_size),
+        (query.page - 1) * query.page_size,
+        &mut conn,
+    )
+    .await?;
+
+    Ok(Json(DuplicateSongsResponse {
+        results: groups
+            .into_iter()
+            .filter_map(|songs| {
+                Some(DuplicateGroupResponse {
+                    target_id: songs.first()?.song.id,
+                    songs,
+                })
+            })
+            .collect(),
+        total,
+    }))
+}
+
 #[serde_inline_default]
 #[derive(Deserialize, Validate)]
 #[serde(rename_all = "camelCase")]
@@ -154,6 +901,31 @@ struct GetTopSongParams {
     #[validate(range(min = 1, max = 50))]
     #[serde_inline_default(10)]
     page_size: i64,
+    #[serde(default)]
+    period: RankingPeriod,
+    #[validate(custom(function = "validate_modifier_filter"))]
+    modifiers: Option<String>,
+}
+
+/// Time span that plays count for in the song rankings
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+enum RankingPeriod {
+    #[default]
+    All,
+    Week,
+    Month,
+}
+
+impl RankingPeriod {
+    /// Length of the period in days, `None` for all time
+    const fn days(self) -> Option<i64> {
+        match self {
+            Self::All => None,
+            Self::Week => Some(7),
+            Self::Month => Some(30),
+        }
+    }
 }
 
 #[derive(Serialize, ToSchema)]
@@ -163,33 +935,18 @@ struct TopSongResponse {
     times_played: i64,
 }
 
-allow_columns_to_appear_in_same_group_by_clause!(
-    schema::songs::id,
-    schema::songs::title,
-    schema::songs::artist,
-    schema::songs::created_at,
-    schema::songs::modifiers,
-    schema::extra_song_info::id,
-    schema::extra_song_info::song_id,
-    schema::extra_song_info::cover_url,
-    schema::extra_song_info::cover_url_small,
-    schema::extra_song_info::mbid,
-    schema::extra_song_info::musicbrainz_title,
-    schema::extra_song_info::musicbrainz_artist,
-    schema::extra_song_info::musicbrainz_length,
-    schema::extra_song_info::mistag_lock,
-    schema::extra_song_info::aliases_artist,
-    schema::extra_song_info::aliases_title,
-);
-
 /// Get global most played songs
+///
+/// Served from the read replica if one is configured, so very recent scores might not show up yet.
 #[utoipa::path(
     method(get),
     path = "/rankings",
     params(
         ("withExtraInfo" = Option<bool>, Query, description = "Include extra info"),
         ("page" = Option<i64>, Query, description = "Page number", minimum = 1),
-        ("pageSize" = Option<i64>, Query, description = "Page size", minimum = 1, maximum = 50)
+        ("pageSize" = Option<i64>, Query, description = "Page size", minimum = 1, maximum = 50),
+        ("modifiers" = Option<String>, Query, description = MODIFIERS_PARAM_DESCRIPTION),
+        ("period" = Option<String>, Query, description = "`all` (default), `week` or `month`. For the last week or month, only plays in that time count, played songs that aren't in the period don't show up")
     ),
     responses(
         (status = OK, description = "Success", body = Vec<TopSongResponse>, content_type = "application/json"),
@@ -198,84 +955,84 @@ allow_columns_to_appear_in_same_group_by_clause!(
     )
 )]
 async fn get_top_songs(
-    State(state): State<AppState>,
+    State(db): State<Db>,
     ValidatedQuery(query): ValidatedQuery<GetTopSongParams>,
 ) -> Result<Json<Vec<TopSongResponse>>, RouteError> {
-    use diesel::{dsl::sql, sql_types::BigInt};
+    use diesel::dsl::sum;
 
-    use crate::schema::{extra_song_info, scores, songs};
+    use crate::schema::{extra_song_info, song_daily_plays, songs};
 
-    let mut conn = state.db.get().await?;
+    let mut conn = db.read_conn().await?;
 
-    if query.with_extra_info {
-        let songs_with_extra: Vec<(Song, i64, Option<ExtraSongInfo>)> = songs::table
-            .left_join(scores::table)
-            .left_join(extra_song_info::table)
-            .group_by((
-                songs::id,
-                songs::title,
-                songs::artist,
-                songs::created_at,
-                songs::modifiers,
-                schema::extra_song_info::id,
-                schema::extra_song_info::song_id,
-                schema::extra_song_info::cover_url,
-                schema::extra_song_info::cover_url_small,
-                schema::extra_song_info::mbid,
-                schema::extra_song_info::musicbrainz_title,
-                schema::extra_song_info::musicbrainz_artist,
-                schema::extra_song_info::musicbrainz_length,
-                schema::extra_song_info::mistag_lock,
-                schema::extra_song_info::aliases_artist,
-                schema::extra_song_info::aliases_title,
-            ))
-            .select((
-                Song::as_select(),
-                sql::<BigInt>("COUNT(scores.song_id) AS score_count"),
-                extra_song_info::all_columns.nullable(),
-            ))
-            .order_by(sql::<BigInt>("score_count DESC"))
+    let modifier_filter = query.modifiers.as_deref().and_then(ModifierFilter::parse);
+
+    let songs: Vec<(Song, i64)> = if let Some(days) = query.period.days() {
+        // Only plays in the period count, so they have to be added up
+        let mut top_songs = Song::ranked()
+            .inner_join(song_daily_plays::table)
+            .filter(song_daily_plays::day.ge(SongDailyPlays::window_start(days)))
+            .group_by(songs::id)
+            .select((Song::as_select(), sum(song_daily_plays::plays)))
+            .into_boxed();
+        if let Some(modifier_filter) = &modifier_filter {
+            top_songs =
+                top_songs.filter(songs::id.eq_any(modifier_filter.exactly_matching_song_ids()));
+        }
+        top_songs
+            .order_by((sum(song_daily_plays::plays).desc(), songs::id.asc()))
             .offset((query.page - 1) * query.page_size)
             .limit(query.page_size)
-            .load::<(Song, i64, Option<ExtraSongInfo>)>(&mut conn)
-            .await?;
-
-        let songs: Vec<TopSongResponse> = songs_with_extra
+            .load::<(Song, Option<i64>)>(&mut conn)
+            .await?
             .into_iter()
-            .map(|(song, times_played, extra_info)| TopSongResponse {
-                song_data: SongResponse { song, extra_info },
-                times_played,
-            })
-            .collect();
-
-        Ok(Json(songs))
+            .map(|(song, plays)| (song, plays.unwrap_or_default()))
+            .collect()
     } else {
-        let songs: Vec<(Song, i64)> = songs::table
-            .left_join(scores::table)
-            .select((
-                Song::as_select(),
-                sql::<BigInt>("COUNT(scores.song_id) AS score_count"),
-            ))
-            .group_by(songs::id)
-            .order_by(sql::<BigInt>("score_count DESC"))
+        let mut top_songs = Song::ranked().select(Song::as_select()).into_boxed();
+        if let Some(modifier_filter) = &modifier_filter {
+            top_songs =
+                top_songs.filter(songs::id.eq_any(modifier_filter.exactly_matching_song_ids()));
+        }
+        top_songs
+            .order_by((songs::times_played.desc(), songs::id.asc()))
             .offset((query.page - 1) * query.page_size)
             .limit(query.page_size)
-            .load::<(Song, i64)>(&mut conn)
-            .await?;
+            .load::<Song>(&mut conn)
+            .await?
+            .into_iter()
+            .map(|song| {
+                let plays = song.times_played;
+                (song, plays)
+            })
+            .collect()
+    };
 
-        let songs: Vec<TopSongResponse> = songs
+    let mut extra_infos: HashMap<i32, ExtraSongInfo> = if query.with_extra_info {
+        let song_ids: Vec<i32> = songs.iter().map(|(song, _)| song.id).collect();
+        extra_song_info::table
+            .filter(extra_song_info::song_id.eq_any(song_ids))
+            .select(ExtraSongInfo::as_select())
+            .load::<ExtraSongInfo>(&mut conn)
+            .await?
+            .into_iter()
+            .map(|extra_info| (extra_info.song_id, extra_info))
+            .collect()
+    } else {
+        HashMap::new()
+    };
+
+    Ok(Json(
+        songs
             .into_iter()
             .map(|(song, times_played)| TopSongResponse {
+                times_played,
                 song_data: SongResponse {
+                    extra_info: extra_infos.remove(&song.id),
                     song,
-                    extra_info: None,
                 },
-                times_played,
             })
-            .collect();
-
-        Ok(Json(songs))
-    }
+            .collect(),
+    ))
 }
 
 #[serde_inline_default]
@@ -293,6 +1050,11 @@ struct GetSongScoresParams {
     league: Option<League>,
     character: Option<Character>,
     player_id: Option<i32>,
+    #[serde(default)] // default to false
+    all_leagues: bool,
+    include_player_id: Option<i32>,
+    #[serde(default)] // default to false
+    with_rank: bool,
 }
 
 #[derive(Serialize, ToSchema)]
@@ -300,11 +1062,79 @@ struct GetSongScoresParams {
 struct ScoreResponse {
     #[serde(flatten)]
     score: Score,
+    /// Position on this leaderboard with the requested filters, starting at 1
+    rank: i64,
+    /// Position on the song's leaderboard in the score's league, starting at 1, regardless of the other filters
+    #[serde(skip_serializing_if = "Option::is_none")]
+    league_rank: Option<i64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     player: Option<PlayerPublic>,
 }
 
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct SongScoresResponse {
+    results: Vec<ScoreResponse>,
+    total: i64,
+}
+
+/// All of a song's scores matching the filters in `query`, without ordering or pagination.
+///
+/// Without a league (and without `allLeagues`), only each player's best score among the ones matching
+/// the other filters is kept, so filtering by character gives every player's best with that character.
+fn filtered_song_scores(
+    song_id: i32,
+    query: &GetSongScoresParams,
+) -> crate::schema::scores::BoxedQuery<'static, diesel::pg::Pg> {
+    use crate::schema::scores;
+
+    let mut db_query = scores::table
+        .filter(scores::song_id.eq(song_id))
+        .into_boxed();
+    let mut best_per_player = scores::table
+        .filter(scores::song_id.eq(song_id))
+        .select(scores::id)
+        .distinct_on(scores::player_id)
+        .order((
+            scores::player_id,
+            scores::score.desc(),
+            scores::submitted_at.asc(),
+        ))
+        .into_boxed();
+    if let Some(league) = query.league {
+        db_query = db_query.filter(scores::league.eq(league));
+    }
+    if let Some(character) = query.character {
+        db_query = db_query.filter(scores::vehicle.eq(character));
+        best_per_player = best_per_player.filter(scores::vehicle.eq(character));
+    }
+    if let Some(player_id) = query.player_id {
+        db_query = db_query.filter(scores::player_id.eq(player_id));
+        best_per_player = best_per_player.filter(scores::player_id.eq(player_id));
+    }
+    if query.league.is_none() && !query.all_leagues {
+        db_query = db_query.filter(scores::id.eq_any(best_per_player));
+    }
+
+    db_query
+}
+
 /// Get song's scores
+///
+/// Returns a page of scores along with the total number of scores matching the filters.
+/// This used to be a plain array of scores, clients reading it as one need to read `results` instead.
+///
+/// Without a league filter, only each player's best score across all leagues is returned,
+/// unless `allLeagues` is set. In that case, players can show up once per league.
+/// With a character or player filter, it's their best score matching those.
+///
+/// Scores are ordered by score, and equal scores by submission time (earlier wins).
+/// Scores that are equal in both share a rank, which in practice doesn't happen.
+///
+/// If `includePlayerId` is given and that player's score isn't on the requested page,
+/// it's appended to the results with its actual rank.
+///
+/// Served from the read replica if one is configured, so very recent scores might not show up yet.
 #[utoipa::path(
     method(get),
     path = "/{id}/scores",
@@ -316,21 +1146,24 @@ struct ScoreResponse {
         ("league" = Option<League>, Query, description = "League to filter by"),
         ("character" = Option<Character>, Query, description = "Character to filter by"),
         ("playerId" = Option<i32>, Query, description = "Player ID to filter by"),
+        ("allLeagues" = Option<bool>, Query, description = "Without a league filter, return every league's score instead of only each player's best"),
+        ("includePlayerId" = Option<i32>, Query, description = "Always include this player's score, even if it's not on the requested page"),
+        ("withRank" = Option<bool>, Query, description = "Include each score's position on the song's leaderboard in its league"),
     ),
     responses(
-        (status = OK, description = "Success", body = Vec<ScoreResponse>, content_type = "application/json"),
+        (status = OK, description = "Success", body = SongScoresResponse, content_type = "application/json"),
         (status = NOT_FOUND, description = "Song not found", body = SimpleRouteErrorOutput, content_type = "application/json"),
         (status = INTERNAL_SERVER_ERROR, description = "Miscellaneous error", body = SimpleRouteErrorOutput)
     )
 )]
 async fn get_song_scores(
-    State(state): State<AppState>,
+    State(db): State<Db>,
     Path(id): Path<i32>,
     ValidatedQuery(query): ValidatedQuery<GetSongScoresParams>,
-) -> Result<Json<Vec<ScoreResponse>>, RouteError> {
+) -> Result<Json<SongScoresResponse>, RouteError> {
     use crate::schema::{players, scores, songs};
 
-    let mut conn = state.db.get().await?;
+    let mut conn = db.read_conn().await?;
 
     let song: Song = songs::table
         .find(id)
@@ -339,51 +1172,85 @@ async fn get_song_scores(
         .optional()?
         .ok_or_else(RouteError::new_not_found)?;
 
-    let mut db_query = scores::table
-        .filter(scores::song_id.eq(song.id))
-        .into_boxed();
-    if let Some(league) = query.league {
-        db_query = db_query.filter(scores::league.eq(league));
-    }
-    if let Some(character) = query.character {
-        db_query = db_query.filter(scores::vehicle.eq(character));
-    }
-    if let Some(player_id) = query.player_id {
-        db_query = db_query.filter(scores::player_id.eq(player_id));
+    let offset = (query.page - 1) * query.page_size;
+    let page: Vec<(Score, Player)> = filtered_song_scores(song.id, &query)
+        .inner_join(players::table)
+        .select((Score::as_select(), Player::as_select()))
+        .order((scores::score.desc(), scores::submitted_at.asc()))
+        .offset(offset)
+        .limit(query.page_size)
+        .load(&mut conn)
+        .await?;
+
+    let total: i64 = filtered_song_scores(song.id, &query)
+        .count()
+        .get_result(&mut conn)
+        .await?;
+
+    let mut ranked: Vec<(Score, Player, i64)> = Vec::with_capacity(page.len() + 1);
+    for (index, (score, player)) in (0..).zip(page) {
+        // Exact ties share the rank of the first score they're tied with
+        let rank = match ranked.last() {
+            Some((previous, _, previous_rank))
+                if previous.score == score.score && previous.submitted_at == score.submitted_at =>
+            {
+                *previous_rank
+            }
+            _ => offset + index + 1,
+        };
+        ranked.push((score, player, rank));
     }
-    db_query = db_query
-        .order(scores::score.desc())
-        .offset((query.page - 1) * query.page_size)
-        .limit(query.page_size);
-    if query.with_player {
-        let scores_with_player: Vec<(Score, Player)> = db_query
-            .inner_join(players::table)
-            .select((Score::as_select(), Player::as_select()))
-            .load::<(Score, Player)>(&mut conn)
-            .await?;
 
-        let scores: Vec<ScoreResponse> = scores_with_player
-            .into_iter()
-            .map(|(score, player)| ScoreResponse {
-                score,
-                player: Some(player.into()),
-            })
-            .collect();
+    if let Some(include_player_id) = query.include_player_id {
+        if !ranked
+            .iter()
+            .any(|(score, _, _)| score.player_id == include_player_id)
+        {
+            let included: Option<(Score, Player)> = filtered_song_scores(song.id, &query)
+                .filter(scores::player_id.eq(include_player_id))
+                .inner_join(players::table)
+                .select((Score::as_select(), Player::as_select()))
+                .order((scores::score.desc(), scores::submitted_at.asc()))
+                .first(&mut conn)
+                .await
+                .optional()?;
 
-        Ok(Json(scores))
+            if let Some((score, player)) = included {
+                let ahead: i64 = filtered_song_scores(song.id, &query)
+                    .filter(
+                        scores::score.gt(score.score).or(scores::score
+                            .eq(score.score)
+                            .and(scores::submitted_at.lt(score.submitted_at))),
+                    )
+                    .count()
+                    .get_result(&mut conn)
+                    .await?;
+                ranked.push((score, player, ahead + 1));
+            }
+        }
+    }
+
+    let league_ranks = if query.with_rank {
+        let score_ids: Vec<i32> = ranked.iter().map(|(score, _, _)| score.id).collect();
+        Score::league_ranks(&score_ids, &mut conn).await?
     } else {
-        let scores: Vec<Score> = db_query.load::<Score>(&mut conn).await?;
+        Vec::new()
+    };
 
-        let scores: Vec<ScoreResponse> = scores
-            .into_iter()
-            .map(|score| ScoreResponse {
-                score,
-                player: None,
-            })
-            .collect();
+    let results: Vec<ScoreResponse> = ranked
+        .into_iter()
+        .map(|(score, player, rank)| ScoreResponse {
+            league_rank: league_ranks
+                .iter()
+                .find(|(score_id, _)| *score_id == score.id)
+                .map(|&(_, league_rank)| league_rank),
+            score,
+            rank,
+            player: query.with_player.then(|| player.into()),
+        })
+        .collect();
 
-        Ok(Json(scores))
-    }
+    Ok(Json(SongScoresResponse { results, total }))
 }
 
 #[derive(Serialize, ToSchema)]
@@ -392,73 +1259,113 @@ struct RadioSongResponse {
     song: Song,
     extra_info: Option<ExtraSongInfo>,
     external_url: String,
+    /// Only set for songs with an availability window
+    #[serde(
+        serialize_with = "time::serde::iso8601::option::serialize",
+        skip_serializing_if = "Option::is_none"
+    )]
+    available_from: Option<OffsetDateTime>,
+    #[serde(
+        serialize_with = "time::serde::iso8601::option::serialize",
+        skip_serializing_if = "Option::is_none"
+    )]
+    available_until: Option<OffsetDateTime>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GetRadioSongsParams {
+    #[serde(default)] // default to false
+    with_extra_info: bool,
+    #[serde(default)] // default to false
+    include_scheduled: bool,
 }
 
 /// Get radio songs
+///
+/// Lists the songs that are on Audiosurf Radio right now, in the order the game shows them.
 #[utoipa::path(
     method(get),
     path = "/radio",
     params(
-        ("withExtraInfo" = Option<bool>, Query, description = "Include extra info")
+        ("withExtraInfo" = Option<bool>, Query, description = "Include extra info"),
+        ("includeScheduled" = Option<bool>, Query, description = "Also include songs that only come on the radio later. Moderators only")
     ),
     responses(
         (status = OK, description = "Success", body = Vec<RadioSongResponse>, content_type = "application/json"),
+        (status = UNAUTHORIZED, description = "`includeScheduled` without being a moderator", body = SimpleRouteErrorOutput, content_type = "application/json"),
         (status = INTERNAL_SERVER_ERROR, description = "Miscellaneous error", body = SimpleRouteErrorOutput)
     )
 )]
 async fn get_radio_songs(
-    State(state): State<AppState>,
-    query: Query<GetSongParams>,
+    State(db): State<Db>,
+    State(radio): State<RadioLineup>,
+    claims: Result<Claims, RouteError>,
+    query: Query<GetRadioSongsParams>,
 ) -> Result<Json<Vec<RadioSongResponse>>, RouteError> {
     use crate::schema::{extra_song_info, songs};
 
-    let mut conn = state.db.get().await?;
-
-    let radio_songs = get_radio_songs_util()?;
-    match radio_songs {
-        Some(radio_songs) => {
-            let ids = radio_songs.iter().map(|song| song.id).collect::<Vec<_>>();
-            let external_urls = radio_songs
-                .iter()
-                .map(|song| song.external_url.clone())
-                .collect::<Vec<_>>();
-            if query.with_extra_info {
-                let songs_with_extra: Vec<(Song, Option<ExtraSongInfo>)> = songs::table
-                    .filter(songs::id.eq_any(ids))
-                    .left_join(extra_song_info::table)
-                    .select((Song::as_select(), extra_song_info::all_columns.nullable()))
-                    .load::<(Song, Option<ExtraSongInfo>)>(&mut conn)
-                    .await?;
-                let radio_song_responses: Vec<RadioSongResponse> = songs_with_extra
-                    .into_iter()
-                    .zip(external_urls)
-                    .map(|((song, extra_info), external_url)| RadioSongResponse {
-                        song,
-                        extra_info,
-                        external_url,
-                    })
-                    .collect();
-                Ok(Json(radio_song_responses))
-            } else {
-                let songs: Vec<Song> = songs::table
-                    .filter(songs::id.eq_any(ids))
-                    .load::<Song>(&mut conn)
-                    .await?;
-                let radio_song_responses: Vec<RadioSongResponse> = songs
-                    .into_iter()
-                    .zip(external_urls)
-                    .map(|(song, external_url)| RadioSongResponse {
-                        song,
-                        extra_info: None,
-                        external_url,
-                    })
-                    .collect();
-
-                Ok(Json(radio_song_responses))
-            }
+    if query.include_scheduled {
+        let claims = claims?;
+        if claims.profile.account_type != AccountType::Moderator
+            && claims.profile.account_type != AccountType::Team
+        {
+            return Err(RouteError::new_unauthorized());
         }
-        None => Ok(Json(vec![])),
     }
+
+    let mut conn = db.read_conn().await?;
+
+    let now = OffsetDateTime::now_utc();
+    let lineup = radio.entries();
+    let radio_songs: Vec<&RadioEntry> = lineup
+        .iter()
+        .filter(|radio_song| {
+            radio_song.is_available_at(now)
+                || (query.include_scheduled && radio_song.is_upcoming_at(now))
+        })
+        .collect();
+    let ids = radio_songs
+        .iter()
+        .map(|radio_song| radio_song.song_id)
+        .collect::<Vec<_>>();
+    let songs: HashMap<i32, (Song, Option<ExtraSongInfo>)> = if query.with_extra_info {
+        songs::table
+            .filter(songs::id.eq_any(ids))
+            .left_join(extra_song_info::table)
+            .select((Song::as_select(), extra_song_info::all_columns.nullable()))
+            .load::<(Song, Option<ExtraSongInfo>)>(&mut conn)
+            .await?
+            .into_iter()
+            .map(|(song, extra_info)| (song.id, (song, extra_info)))
+            .collect()
+    } else {
+        songs::table
+            .filter(songs::id.eq_any(ids))
+            .select(Song::as_select())
+            .load::<Song>(&mut conn)
+            .await?
+            .into_iter()
+            .map(|song| (song.id, (song, None)))
+            .collect()
+    };
+
+    // In the order of the lineup, which the songs query doesn't keep
+    Ok(Json(
+        radio_songs
+            .into_iter()
+            .filter_map(|radio_song| {
+                let (song, extra_info) = songs.get(&radio_song.song_id).cloned()?;
+                Some(RadioSongResponse {
+                    song,
+                    extra_info,
+                    external_url: radio_song.external_url.clone(),
+                    available_from: radio_song.available_from,
+                    available_until: radio_song.available_until,
+                })
+            })
+            .collect(),
+    ))
 }
 
 #[serde_inline_default]
@@ -502,13 +1409,13 @@ struct SongShoutsResponse {
     )
 )]
 async fn get_song_shouts(
-    State(state): State<AppState>,
+    State(db): State<Db>,
     Path(id): Path<i32>,
     ValidatedQuery(query): ValidatedQuery<GetSongScoresParams>,
 ) -> Result<Json<SongShoutsResponse>, RouteError> {
     use crate::schema::{players, shouts, songs};
 
-    let mut conn = state.db.get().await?;
+    let mut conn = db.read_conn().await?;
 
     let song: Song = songs::table
         .find(id)
@@ -543,6 +1450,170 @@ async fn get_song_shouts(
     Ok(Json(SongShoutsResponse { results, total }))
 }
 
+#[derive(Deserialize, ToSchema)]
+struct AddSongShoutRequest {
+    /// Whitespace around it is trimmed off
+    content: String,
+}
+
+/// Post a shout on a song
+///
+/// Shouts from the website are checked like the ones from the game: they can't be empty, too long,
+/// the same as the player's previous shout on the song, or posted too often.
+#[utoipa::path(
+    method(post),
+    path = "/{id}/shouts",
+    params(
+        ("id" = i32, Path, description = "ID of song to shout on"),
+    ),
+    request_body = AddSongShoutRequest,
+    responses(
+        (status = OK, description = "Success", body = SongShoutsResult, content_type = "application/json"),
+        (status = BAD_REQUEST, description = "Shout isn't allowed", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = UNAUTHORIZED, description = "Not logged in", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = NOT_FOUND, description = "Song not found", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = TOO_MANY_REQUESTS, description = "Too many shouts in a short time", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = INTERNAL_SERVER_ERROR, description = "Miscellaneous error", body = SimpleRouteErrorOutput)
+    ),
+    security(
+        ("token_jwt" = [])
+    )
+)]
+async fn add_song_shout(
+    State(db): State<Db>,
+    State(redis): State<Cache>,
+    State(config): State<Arc<Config>>,
+    claims: Claims,
+    Path(id): Path<i32>,
+    Json(request): Json<AddSongShoutRequest>,
+) -> Result<Json<SongShoutsResult>, RouteError> {
+    use crate::schema::{players, songs};
+
+    let mut conn = db.get().await?;
+
+    let song_exists: bool = diesel::select(diesel::dsl::exists(songs::table.find(id)))
+        .get_result(&mut conn)
+        .await?;
+    if !song_exists {
+        return Err(RouteError::new_not_found());
+    }
+
+    let max_per_minute = config.shouts.max_per_minute;
+    if !rate_limit::allows(
+        &shout_rate_limit_key(claims.profile.id),
+        max_per_minute,
+        SHOUT_RATE_LIMIT_WINDOW_SECS,
+        &redis,
+    )
+    .await
+    {
+        return Err(RouteError::from_status(StatusCode::TOO_MANY_REQUESTS)
+            .set_public_error_message(&format!(
+                "You can only post {max_per_minute} shouts per minute"
+            )));
+    }
+
+    let shout = match NewShout::new(id, claims.profile.id, &request.content)
+        .insert(&mut conn)
+        .await
+    {
+        Ok(shout) => shout,
+        Err(ShoutError::Database(e)) => return Err(e.into()),
+        Err(e) => {
+            return Err(RouteError::new_bad_request().set_public_error_message(&e.to_string()))
+        }
+    };
+
+    // The profile in the token might be outdated, e.g. after a name change
+    let author: Player = players::table
+        .find(claims.profile.id)
+        .first(&mut conn)
+        .await?;
+
+    info!(
+        "Player {} shouted {} on song {id} from the website",
+        author.id, shout.id
+    );
+    Ok(Json(SongShoutsResult {
+        shout,
+        author: author.into(),
+    }))
+}
+
+/// Get the raw titles and artists clients submitted for a song
+///
+/// Only the first few distinct variants are stored per song. Requires permission to edit the song.
+#[utoipa::path(
+    method(get),
+    path = "/{id}/tagSamples",
+    params(
+        ("id" = i32, Path, description = "ID of song to get tag samples for")
+    ),
+    responses(
+        (status = OK, description = "Success", body = Vec<SongTagSample>, content_type = "application/json"),
+        (status = UNAUTHORIZED, description = "No permission", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = NOT_FOUND, description = "Song not found", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = INTERNAL_SERVER_ERROR, description = "Miscellaneous error", body = SimpleRouteErrorOutput)
+    ),
+    security(
+        ("token_jwt" = [])
+    )
+)]
+async fn get_song_tag_samples(
+    State(db): State<Db>,
+    Path(id): Path<i32>,
+    claims: Claims,
+) -> Result<Json<Vec<SongTagSample>>, RouteError> {
+    use crate::schema::{song_tag_samples, songs};
+
+    let mut conn = db.get().await?;
+
+    let song: Song = songs::table
+        .find(id)
+        .first(&mut conn)
+        .await
+        .optional()?
+        .ok_or_else(RouteError::new_not_found)?;
+
+    if !song.user_can_edit(claims.profile.id, &mut conn).await? {
+        return Err(RouteError::new_unauthorized());
+    }
+
+    let samples: Vec<SongTagSample> = SongTagSample::belonging_to(&song)
+        .select(SongTagSample::as_select())
+        .order(song_tag_samples::created_at.asc())
+        .load(&mut conn)
+        .await?;
+
+    Ok(Json(samples))
+}
+
+/// Rejects edits to extra info that a moderator locked, unless the editor is a moderator themselves
+async fn check_mistag_lock(
+    song: &Song,
+    claims: &Claims,
+    conn: &mut diesel_async::AsyncPgConnection,
+) -> Result<(), RouteError> {
+    if claims.profile.account_type == AccountType::Moderator
+        || claims.profile.account_type == AccountType::Team
+    {
+        return Ok(());
+    }
+
+    let locked = ExtraSongInfo::belonging_to(song)
+        .select(schema::extra_song_info::mistag_lock)
+        .first::<bool>(conn)
+        .await
+        .optional()?
+        .unwrap_or(false);
+    if locked {
+        return Err(RouteError::new_conflict()
+            .set_public_error_message("This song's extra info is locked by a moderator"));
+    }
+
+    Ok(())
+}
+
 /// Manually update song extra info
 #[utoipa::path(
     method(put),
@@ -551,9 +1622,10 @@ async fn get_song_shouts(
         ("id" = i32, Path, description = "ID of song to update")
     ),
     responses(
-        (status = OK, description = "Success"),
+        (status = OK, description = "Success", body = ExtraSongInfo, content_type = "application/json"),
         (status = UNAUTHORIZED, description = "No permission", body = SimpleRouteErrorOutput, content_type = "application/json"),
         (status = NOT_FOUND, description = "Song not found", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = CONFLICT, description = "Extra info is locked", body = SimpleRouteErrorOutput, content_type = "application/json"),
         (status = INTERNAL_SERVER_ERROR, description = "Miscellaneous error", body = SimpleRouteErrorOutput)
     ),
     security(
@@ -561,16 +1633,16 @@ async fn get_song_shouts(
     )
 )]
 async fn update_song_extra_info(
-    State(state): State<AppState>,
+    State(db): State<Db>,
     Path(id): Path<i32>,
     claims: Claims,
     Json(extra_info): Json<NewExtraSongInfo>,
-) -> Result<(), RouteError> {
+) -> Result<Json<ExtraSongInfo>, RouteError> {
     use diesel::insert_into;
 
     use crate::schema::{extra_song_info, songs};
 
-    let mut conn = state.db.get().await?;
+    let mut conn = db.get().await?;
 
     let song: Song = songs::table
         .find(id)
@@ -580,6 +1652,8 @@ async fn update_song_extra_info(
         .ok_or_else(RouteError::new_not_found)?;
 
     if song.user_can_edit(claims.profile.id, &mut conn).await? {
+        check_mistag_lock(&song, &claims, &mut conn).await?;
+
         let new_extra_song_info = NewExtraSongInfo::new(
             id,
             extra_info.cover_url,
@@ -592,19 +1666,187 @@ async fn update_song_extra_info(
             extra_info.aliases_artist,
         );
 
-        insert_into(extra_song_info::table)
+        let existing_info = ExtraSongInfo::belonging_to(&song)
+            .select(ExtraSongInfo::as_select())
+            .first::<ExtraSongInfo>(&mut conn)
+            .await
+            .optional()?;
+        let covers = CachedCovers::fetch_changed(
+            new_extra_song_info.cover_url.as_deref(),
+            new_extra_song_info.cover_url_small.as_deref(),
+            existing_info.as_ref(),
+        )
+        .await;
+
+        let extra_info = insert_into(extra_song_info::table)
             .values(&new_extra_song_info)
             .on_conflict(extra_song_info::song_id)
             .do_update()
             .set(&new_extra_song_info)
-            .execute(&mut conn)
+            .get_result::<ExtraSongInfo>(&mut conn)
             .await?;
-        Ok(())
+        let extra_info = covers.save(extra_info, &mut conn).await?;
+        Ok(Json(extra_info))
     } else {
         Err(RouteError::new_unauthorized())
     }
 }
 
+#[derive(Deserialize, Validate, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct SongAliasRequest {
+    /// Whether this is an alias of the title or the artist
+    #[serde(rename = "type")]
+    kind: AliasKind,
+    /// Stored lowercased, like the game sends tags
+    #[validate(length(min = 1, max = 200), custom(function = "validate_not_blank"))]
+    alias: String,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct SongAliasesResponse {
+    aliases_title: Vec<String>,
+    aliases_artist: Vec<String>,
+}
+
+impl From<Option<ExtraSongInfo>> for SongAliasesResponse {
+    fn from(extra_info: Option<ExtraSongInfo>) -> Self {
+        let extra_info = extra_info.unwrap_or_default();
+        Self {
+            aliases_title: extra_info
+                .aliases_title
+                .into_iter()
+                .flatten()
+                .flatten()
+                .collect(),
+            aliases_artist: extra_info
+                .aliases_artist
+                .into_iter()
+                .flatten()
+                .flatten()
+                .collect(),
+        }
+    }
+}
+
+/// Loads a song and checks that the player may edit its extra info.
+async fn editable_song(
+    id: i32,
+    claims: &Claims,
+    conn: &mut diesel_async::AsyncPgConnection,
+) -> Result<Song, RouteError> {
+    let song: Song = schema::songs::table
+        .find(id)
+        .first(conn)
+        .await
+        .optional()?
+        .ok_or_else(RouteError::new_not_found)?;
+
+    if !song.user_can_edit(claims.profile.id, conn).await? {
+        return Err(RouteError::new_unauthorized());
+    }
+    check_mistag_lock(&song, claims, conn).await?;
+
+    Ok(song)
+}
+
+/// Add song alias
+///
+/// Lets the game match the song by another title or artist. Adding an alias the song already has does nothing.
+#[utoipa::path(
+    method(post),
+    path = "/{id}/aliases",
+    params(
+        ("id" = i32, Path, description = "ID of song to add the alias to")
+    ),
+    request_body = SongAliasRequest,
+    responses(
+        (status = OK, description = "Success, returns all aliases", body = SongAliasesResponse, content_type = "application/json"),
+        (status = BAD_REQUEST, description = "Empty or too long alias", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = UNAUTHORIZED, description = "No permission", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = NOT_FOUND, description = "Song not found", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = CONFLICT, description = "Extra info is locked", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = INTERNAL_SERVER_ERROR, description = "Miscellaneous error", body = SimpleRouteErrorOutput)
+    ),
+    security(
+        ("token_jwt" = [])
+    )
+)]
+async fn add_song_alias(
+    State(db): State<Db>,
+    State(metadata): State<Metadata>,
+    Path(id): Path<i32>,
+    claims: Claims,
+    ValidatedJson(payload): ValidatedJson<SongAliasRequest>,
+) -> Result<Json<SongAliasesResponse>, RouteError> {
+    use diesel_async::{scoped_futures::ScopedFutureExt, AsyncConnection};
+
+    let mut conn = db.get().await?;
+
+    let song = editable_song(id, &claims, &mut conn).await?;
+
+    let (title, artist) = match payload.kind {
+        AliasKind::Title => (Some(payload.alias.as_str()), None),
+        AliasKind::Artist => (None, Some(payload.alias.as_str())),
+    };
+    // In a transaction, so the row lock holds until the aliases are written
+    let extra_info = conn
+        .transaction::<_, diesel::result::Error, _>(|conn| {
+            ExtraSongInfo::add_aliases(song.id, title, artist, conn).scope_boxed()
+        })
+        .await?;
+    meilisearch::reindex_song(metadata.song_index(), song.id, &mut conn).await;
+
+    Ok(Json(Some(extra_info).into()))
+}
+
+/// Remove song alias
+///
+/// Removing an alias the song doesn't have does nothing.
+#[utoipa::path(
+    method(delete),
+    path = "/{id}/aliases",
+    params(
+        ("id" = i32, Path, description = "ID of song to remove the alias from")
+    ),
+    request_body = SongAliasRequest,
+    responses(
+        (status = OK, description = "Success, returns the remaining aliases", body = SongAliasesResponse, content_type = "application/json"),
+        (status = BAD_REQUEST, description = "Empty or too long alias", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = UNAUTHORIZED, description = "No permission", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = NOT_FOUND, description = "Song not found", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = CONFLICT, description = "Extra info is locked", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = INTERNAL_SERVER_ERROR, description = "Miscellaneous error", body = SimpleRouteErrorOutput)
+    ),
+    security(
+        ("token_jwt" = [])
+    )
+)]
+async fn remove_song_alias(
+    State(db): State<Db>,
+    State(metadata): State<Metadata>,
+    Path(id): Path<i32>,
+    claims: Claims,
+    ValidatedJson(payload): ValidatedJson<SongAliasRequest>,
+) -> Result<Json<SongAliasesResponse>, RouteError> {
+    use diesel_async::{scoped_futures::ScopedFutureExt, AsyncConnection};
+
+    let mut conn = db.get().await?;
+
+    let song = editable_song(id, &claims, &mut conn).await?;
+
+    // In a transaction, so the row lock holds until the aliases are written
+    let extra_info = conn
+        .transaction::<_, diesel::result::Error, _>(|conn| {
+            ExtraSongInfo::remove_alias(song.id, payload.kind, &payload.alias, conn).scope_boxed()
+        })
+        .await?;
+    meilisearch::reindex_song(metadata.song_index(), song.id, &mut conn).await;
+
+    Ok(Json(extra_info.into()))
+}
+
 #[derive(Deserialize, ToSchema)]
 struct MbidRefreshBody {
     recording_mbid: String,
@@ -620,26 +1862,29 @@ struct MbidRefreshBody {
         ("id" = i32, Path, description = "ID of song to update")
     ),
     responses(
-        (status = OK, description = "Success"),
+        (status = OK, description = "Success", body = ExtraSongInfo, content_type = "application/json"),
         (status = UNAUTHORIZED, description = "No permission", body = SimpleRouteErrorOutput, content_type = "application/json"),
-        (status = NOT_FOUND, description = "Song not found", body = SimpleRouteErrorOutput, content_type = "application/json"),
-        (status = INTERNAL_SERVER_ERROR, description = "Miscellaneous error", body = SimpleRouteErrorOutput)
+        (status = NOT_FOUND, description = "Song or MBID not found, or the recording has no release or artist credit", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = CONFLICT, description = "Extra info is locked", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = INTERNAL_SERVER_ERROR, description = "Miscellaneous error", body = SimpleRouteErrorOutput),
+        (status = BAD_GATEWAY, description = "MusicBrainz is unreachable or answered unexpectedly", body = SimpleRouteErrorOutput, content_type = "application/json"),
+        (status = SERVICE_UNAVAILABLE, description = "MusicBrainz is rate limiting requests, try again later", body = SimpleRouteErrorOutput, content_type = "application/json")
     ),
     security(
         ("token_jwt" = [])
     )
 )]
 async fn update_song_extra_info_mbid(
-    State(state): State<AppState>,
+    State(db): State<Db>,
     Path(id): Path<i32>,
     claims: Claims,
     Json(payload): Json<MbidRefreshBody>,
-) -> Result<(), RouteError> {
+) -> Result<Json<ExtraSongInfo>, RouteError> {
     use diesel::insert_into;
 
     use crate::schema::{extra_song_info, songs};
 
-    let mut conn = state.db.get().await?;
+    let mut conn = db.get().await?;
 
     let song: Song = songs::table
         .find(id)
@@ -649,20 +1894,238 @@ async fn update_song_extra_info_mbid(
         .ok_or_else(RouteError::new_not_found)?;
 
     if song.user_can_edit(claims.profile.id, &mut conn).await? {
+        check_mistag_lock(&song, &claims, &mut conn).await?;
+
         let mb_info =
             musicbrainz::lookup_mbid(&payload.recording_mbid, payload.release_mbid.as_deref())
-                .await?;
+                .await
+                .map_err(|e| {
+                    let status_code = e.status_code();
+                    let message = e.public_message();
+                    RouteError::from(e)
+                        .set_status_code(status_code)
+                        .set_public_error_message(message)
+                })?;
+
+        let existing_info = ExtraSongInfo::belonging_to(&song)
+            .select(ExtraSongInfo::as_select())
+            .first::<ExtraSongInfo>(&mut conn)
+            .await
+            .optional()?;
+        let covers = CachedCovers::fetch_changed(
+            mb_info.cover_url.as_deref(),
+            mb_info.cover_url_small.as_deref(),
+            existing_info.as_ref(),
+        )
+        .await;
 
-        insert_into(extra_song_info::table)
-            .values(&mb_info)
+        let extra_info = insert_into(extra_song_info::table)
+            .values((&mb_info, extra_song_info::song_id.eq(id)))
             .on_conflict(extra_song_info::song_id)
             .do_update()
             .set(&mb_info)
-            .execute(&mut conn)
+            .get_result::<ExtraSongInfo>(&mut conn)
             .await?;
+        let extra_info = covers.save(extra_info, &mut conn).await?;
 
-        Ok(())
+        Ok(Json(extra_info))
     } else {
         Err(RouteError::new_unauthorized())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, http::Request};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::util::testing;
+
+    /// Status and JSON body of `GET {uri}`
+    async fn get_json(state: &AppState, uri: &str) -> (StatusCode, serde_json::Value) {
+        let request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+        let response = crate::make_router(state.clone())
+            .oneshot(request)
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn rankings_skip_excluded_songs_but_lookups_dont() {
+        use crate::schema::songs;
+
+        let Some(state) = testing::state(Arc::new(testing::RecordedRedis::default())).await else {
+            return;
+        };
+        let (ranked, excluded) = {
+            let mut conn = state.db.get().await.unwrap();
+            let ranked = testing::insert_song("Dear Music", "Sumijun", &mut conn).await;
+            let excluded = testing::insert_song("Test Tone", "Nobody", &mut conn).await;
+            // Played the most, so it would come first if it weren't excluded
+            for (song_id, times_played) in [(ranked, 10), (excluded, 20)] {
+                diesel::update(songs::table.find(song_id))
+                    .set(songs::times_played.eq(times_played))
+                    .execute(&mut conn)
+                    .await
+                    .unwrap();
+            }
+            Song::set_excluded_from_rankings(excluded, true, &mut conn)
+                .await
+                .unwrap();
+            (ranked, excluded)
+        };
+
+        let (status, rankings) = get_json(&state, "/api/songs/rankings?pageSize=50").await;
+        assert_eq!(status, StatusCode::OK);
+        let ranked_ids: Vec<i64> = rankings
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|entry| entry["songData"]["id"].as_i64().unwrap())
+            .collect();
+        assert_eq!(ranked_ids.first(), Some(&i64::from(ranked)));
+        assert!(!ranked_ids.contains(&i64::from(excluded)));
+
+        let (status, song) = get_json(&state, &format!("/api/songs/{excluded}")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(song["id"], excluded);
+    }
+
+    #[tokio::test]
+    async fn best_scores_respect_the_character_filter() {
+        use crate::schema::scores;
+
+        let Some(mut conn) = testing::db().await else {
+            return;
+        };
+        let redis = testing::redis(Arc::new(testing::RecordedRedis::default())).await;
+        let player_id = testing::insert_player(1, &mut conn).await;
+        let song_id = testing::insert_song("Dear Music", "Sumijun", &mut conn).await;
+        let pointman =
+            testing::insert_score(player_id, song_id, League::Casual, 1000, &mut conn, &redis)
+                .await;
+        let mono =
+            testing::insert_score(player_id, song_id, League::Pro, 2000, &mut conn, &redis).await;
+        diesel::update(scores::table.find(mono.id))
+            .set(scores::vehicle.eq(Character::Mono))
+            .execute(&mut conn)
+            .await
+            .unwrap();
+
+        let matching = |query: serde_json::Value| {
+            let query: GetSongScoresParams = serde_json::from_value(query).unwrap();
+            filtered_song_scores(song_id, &query).select(scores::id)
+        };
+        let best: Vec<i32> = matching(serde_json::json!({}))
+            .load(&mut conn)
+            .await
+            .unwrap();
+        assert_eq!(best, vec![mono.id]);
+        // The Pro score is better, but it wasn't played with Pointman Pro
+        let best: Vec<i32> = matching(serde_json::json!({ "character": 0 }))
+            .load(&mut conn)
+            .await
+            .unwrap();
+        assert_eq!(best, vec![pointman.id]);
+        let best: Vec<i32> =
+            matching(serde_json::json!({ "character": 0, "playerId": player_id + 1 }))
+                .load(&mut conn)
+                .await
+                .unwrap();
+        assert!(best.is_empty());
+    }
+
+    #[tokio::test]
+    async fn previews_stay_small_and_get_cached() {
+        use crate::schema::{players, songs};
+
+        let Some(state) = testing::state(Arc::new(testing::KeyStore::default())).await else {
+            return;
+        };
+        let song_id = {
+            let mut conn = state.db.get().await.unwrap();
+            // Multi-byte characters, so cutting off by characters wouldn't be enough
+            let song_id =
+                testing::insert_song(&"Ä".repeat(500), &"🎵".repeat(500), &mut conn).await;
+            for (n, league) in [(1, League::Casual), (2, League::Pro), (3, League::Elite)] {
+                let player_id = testing::insert_player(n, &mut conn).await;
+                diesel::update(players::table.find(player_id))
+                    .set(players::username.eq("🏁".repeat(100)))
+                    .execute(&mut conn)
+                    .await
+                    .unwrap();
+                testing::insert_score(player_id, song_id, league, 1000, &mut conn, &state.redis)
+                    .await;
+            }
+            song_id
+        };
+
+        let preview = || async {
+            let request = Request::builder()
+                .uri(format!("/api/songs/{song_id}/preview"))
+                .body(Body::empty())
+                .unwrap();
+            let response = crate::make_router(state.clone())
+                .oneshot(request)
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap()
+        };
+
+        let queries_before = testing::queries_run();
+        let body = preview().await;
+        assert!(testing::queries_run() - queries_before <= 2);
+        assert!(body.len() < 1024, "preview is {} bytes", body.len());
+        let parsed: SongPreview = serde_json::from_slice(&body).unwrap();
+        assert!(parsed.title.ends_with('…'));
+        assert_eq!(parsed.top_scores.len(), 3);
+
+        // Served from Redis, so the rename only shows up once the preview expires
+        {
+            let mut conn = state.db.get().await.unwrap();
+            diesel::update(songs::table.find(song_id))
+                .set(songs::title.eq("Dear Music"))
+                .execute(&mut conn)
+                .await
+                .unwrap();
+        }
+        let queries_before = testing::queries_run();
+        assert_eq!(preview().await, body);
+        assert_eq!(testing::queries_run(), queries_before);
+    }
+
+    #[test]
+    fn search_input_is_escaped() {
+        assert_eq!(contains_pattern("dear music"), "%dear music%");
+        assert_eq!(contains_pattern("100%_\\"), "%100\\%\\_\\\\%");
+    }
+
+    #[test]
+    fn only_known_ranking_periods_are_accepted() {
+        let period = |input: &str| serde_json::from_value::<RankingPeriod>(input.into());
+        assert_eq!(period("week").unwrap(), RankingPeriod::Week);
+        assert_eq!(period("month").unwrap().days(), Some(30));
+        assert!(period("year").is_err());
+        assert_eq!(RankingPeriod::default().days(), None);
+    }
+
+    #[test]
+    fn long_text_is_cut_off() {
+        assert_eq!(truncate_bytes("Dear Music", 100), "Dear Music");
+        assert_eq!(truncate_bytes("Dear Music", 7), "Dear…");
+        assert_eq!(truncate_bytes("abc", 3), "abc");
+        // Counts bytes, and doesn't split the two-byte umlauts
+        assert_eq!(truncate_bytes("ÄÖÜäöü", 11), "ÄÖÜä…");
+        assert_eq!(truncate_bytes("ÄÖÜäöü", 10), "ÄÖÜ…");
+        assert!(truncate_bytes(&"🎵".repeat(100), 150).len() <= 150);
+    }
+}
@@ -1,25 +1,34 @@
+use std::sync::Arc;
+
 use axum::{extract::State, http::StatusCode, Form};
 use axum_serde::Xml;
 use diesel::{associations::HasTable, prelude::*};
-use diesel_async::RunQueryDsl;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
 use serde::{Deserialize, Serialize};
 use time::OffsetDateTime;
-use tracing::{error, info, instrument};
+use tracing::{error, info};
 
 use super::helpers::ticket_auth;
 use crate::{
     models::{
         extra_song_info::ExtraSongInfo,
+        notifications::{DethronedPayload, NewNotification, Notification},
         players::Player,
+        rejected_scores::NewRejectedScore,
         rivalries::Rivalry,
         scores::{NewScore, Score, ScoreWithPlayer},
+        skill_point_decay::SkillPointDecay,
+        song_tag_samples::SongTagSample,
         songs::{NewSong, Song},
     },
     util::{
+        discord::{self, TopScoreAnnouncement},
         errors::{IntoRouteError, RouteError},
         game_types::{split_x_separated, Character, Leaderboard, League},
+        i18n,
+        lookup_queue::{self, LookupJob, LookupKind},
     },
-    AppState,
+    Cache, Config, Db, SteamAuth,
 };
 
 #[derive(Deserialize)]
@@ -51,107 +60,111 @@ pub struct SongIdResponse {
 
 /// Attempts to get a song ID from the server.
 /// If the song isn't registered on the server yet, it will be created.
+/// Banned players get a `failed` status instead.
 ///
 /// # Errors
 ///
 /// This fails if:
 /// - The response fails to serialize
 /// - The song fails to be created/retrieved
-#[instrument(skip_all)]
 pub async fn fetch_song_id(
-    State(state): State<AppState>,
+    State(db): State<Db>,
+    State(redis): State<Cache>,
+    State(steam): State<SteamAuth>,
     Form(payload): Form<SongIdRequest>,
 ) -> Result<Xml<SongIdResponse>, RouteError> {
-    use crate::{
-        schema::{extra_song_info::dsl::*, songs::dsl::*},
-        util::modifiers::{parse_from_title, remove_from_title},
-    };
+    use crate::util::modifiers::{parse_from_title, remove_from_title};
 
-    let steam_player =
-        ticket_auth(&payload.wavebreaker.ticket, &state.steam_api, &state.redis).await?;
+    let steam_player = ticket_auth(&payload.wavebreaker.ticket, &steam.api, &redis).await?;
 
-    let mut conn = state.db.get().await?;
-    let parsed_modifiers = parse_from_title(&payload.song);
+    let mut conn = db.get().await?;
+    let player = Player::find_by_steam_id(steam_player)
+        .first::<Player>(&mut conn)
+        .await?;
+    if player.active_ban(&mut conn).await?.is_some() {
+        info!("Refused song lookup by banned player {}", player.id);
+        return Ok(Xml(SongIdResponse {
+            status: "failed".to_owned(),
+            song_id: 0,
+        }));
+    }
 
-    // if recording MBID is provided, look it up using that + modifiers from the title
-    // else, look up the song by title and artist
-    if let Some(recording_mbid) = &payload.wavebreaker.mbid {
-        let song = songs
-            .inner_join(extra_song_info)
-            .filter(
-                mbid.eq(recording_mbid)
-                    .and(modifiers.is_not_distinct_from(&parsed_modifiers)),
-            )
-            .first::<(Song, ExtraSongInfo)>(&mut conn)
-            .await
-            .optional()?;
+    // A song with the MBID the client sent wins over one that only matches by title and artist,
+    // and its extra info comes along, so this is one query either way
+    let (song, extra_info) = NewSong::new(
+        &remove_from_title(&payload.song),
+        &payload.artist,
+        parse_from_title(&payload.song),
+    )
+    .find_or_create_with_mbid(payload.wavebreaker.mbid.as_deref(), &mut conn)
+    .await?;
+    record_tag_sample(
+        song.id,
+        player.id,
+        &payload.song,
+        &payload.artist,
+        &mut conn,
+    )
+    .await;
 
-        // if song with MBID and modifiers exists, return its ID
-        // else create a new song, attach the MBID to it and get metadata from MusicBrainz
-        if let Some((song, _)) = song {
+    let known_mbid = extra_info.and_then(|extra_info| extra_info.mbid);
+    match &payload.wavebreaker.mbid {
+        Some(recording_mbid) if known_mbid.as_ref() == Some(recording_mbid) => {
             info!(
                 "Song {} - {} looked up by {} (Steam), league {:?}, MBID {:?}, release MBID {:?} (successful existing MBID lookup)",
                 song.artist, song.title, steam_player, payload.league, payload.wavebreaker.mbid, payload.wavebreaker.release_mbid
             );
-
-            Ok(Xml(SongIdResponse {
-                status: "allgood".to_owned(),
-                song_id: song.id,
-            }))
-        } else {
+        }
+        Some(recording_mbid) => {
             info!(
                 "Song {} - {} looked up by {} (Steam), league {:?}, MBID {:?}, release MBID {:?} (new MBID lookup)",
                 payload.artist, payload.song, steam_player, payload.league, payload.wavebreaker.mbid, payload.wavebreaker.release_mbid
             );
 
-            let song = NewSong::new(
-                &remove_from_title(&payload.song),
-                &payload.artist,
-                parsed_modifiers,
-            )
-            .find_or_create(&mut conn)
-            .await?;
-
-            let song_clone = song.clone();
-            let recording_mbid = recording_mbid.clone();
-            tokio::spawn(async move {
-                let _ = song_clone
-                    .add_metadata_mbid(
-                        &recording_mbid,
-                        payload.wavebreaker.release_mbid.as_deref(),
-                        &mut conn,
-                    )
-                    .await;
-            });
-
-            Ok(Xml(SongIdResponse {
-                status: "allgood".to_owned(),
-                song_id: song.id,
-            }))
+            let job = LookupJob::new(
+                song.id,
+                LookupKind::Mbid {
+                    recording: recording_mbid.clone(),
+                    release: payload.wavebreaker.release_mbid.clone(),
+                },
+            );
+            if let Err(e) = lookup_queue::enqueue(&job, &redis).await {
+                error!(
+                    "Failed to queue metadata lookup for song {}: {}",
+                    song.id, e
+                );
+            }
         }
-    } else {
-        let song = NewSong::new(
-            &remove_from_title(&payload.song),
-            &payload.artist,
-            parsed_modifiers,
-        )
-        .find_or_create(&mut conn)
-        .await?;
+        None => {
+            info!(
+                "Song {} - {} looked up by {} (Steam), league {:?}, MBID {:?}, release MBID {:?}",
+                song.artist,
+                song.title,
+                steam_player,
+                payload.league,
+                payload.wavebreaker.mbid,
+                payload.wavebreaker.release_mbid
+            );
+        }
+    }
 
-        info!(
-            "Song {} - {} looked up by {} (Steam), league {:?}, MBID {:?}, release MBID {:?}",
-            song.artist,
-            song.title,
-            steam_player,
-            payload.league,
-            payload.wavebreaker.mbid,
-            payload.wavebreaker.release_mbid
-        );
+    Ok(Xml(SongIdResponse {
+        status: "allgood".to_owned(),
+        song_id: song.id,
+    }))
+}
 
-        Ok(Xml(SongIdResponse {
-            status: "allgood".to_owned(),
-            song_id: song.id,
-        }))
+/// Keeps the title and artist exactly as the client sent them, for figuring out why songs got split later.
+/// Failing to do so shouldn't fail the lookup, so errors are only logged.
+async fn record_tag_sample(
+    song_id: i32,
+    player_id: i32,
+    title: &str,
+    artist: &str,
+    conn: &mut AsyncPgConnection,
+) {
+    if let Err(e) = SongTagSample::record(song_id, player_id, title, artist, conn).await {
+        error!("Failed to record tag sample for song {}: {}", song_id, e);
     }
 }
 
@@ -197,7 +210,7 @@ pub struct SendRideResponse {
     beat_score: BeatScore,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Default, Serialize, Deserialize)]
 #[serde(rename = "RESULT")]
 struct BeatScore {
     #[serde(rename = "@dethroned")]
@@ -215,52 +228,130 @@ struct BeatScore {
 }
 
 /// Accepts score submissions by the client.
+/// Scores of banned players, and ones failing the plausibility checks of the `[gameplay]` config, are refused with a `failed` status.
+/// Refused implausible scores are kept in `rejected_scores`.
+/// The score limit is based on the gold thresholds of the scores already accepted on the song, see `Score::reference_gold_threshold`.
 ///
 /// # Errors
 /// This fails if:
 /// - The response fails to serialize
 /// - Authenticating with Steam fails
 /// - The score fails to be inserted
-#[instrument(skip_all)]
 pub async fn send_ride(
-    State(state): State<AppState>,
+    State(config): State<Arc<Config>>,
+    State(db): State<Db>,
+    State(redis): State<Cache>,
+    State(steam): State<SteamAuth>,
     Form(payload): Form<SendRideRequest>,
 ) -> Result<Xml<SendRideResponse>, RouteError> {
-    use crate::schema::{players::dsl::*, rivalries::dsl::*, scores::dsl::*, songs::dsl::songs};
+    use crate::schema::{
+        extra_song_info, players::dsl::*, rivalries::dsl::*, scores::dsl::*, songs::dsl::songs,
+    };
 
-    let steam_player = ticket_auth(&payload.ticket, &state.steam_api, &state.redis).await?;
+    let steam_player = ticket_auth(&payload.ticket, &steam.api, &redis).await?;
 
     info!(
         "Score received on {} from {} (Steam) with score {}, using {:?}. MBID {:?}, release MBID {:?}",
         &payload.song_id, &steam_player, &payload.score, &payload.vehicle, &payload.wavebreaker.mbid, &payload.wavebreaker.release_mbid
     );
 
-    let mut conn = state.db.get().await?;
+    let mut conn = db.get().await?;
     let player: Player = Player::find_by_steam_id(steam_player)
         .first::<Player>(&mut conn)
         .await?;
+    if player.active_ban(&mut conn).await?.is_some() {
+        info!("Refused score of banned player {}", player.id);
+        return Ok(Xml(SendRideResponse {
+            status: "failed".to_owned(),
+            song_id: payload.song_id,
+            beat_score: BeatScore::default(),
+        }));
+    }
 
-    let song = songs
+    // Extra info is loaded along with the song, so adding metadata later doesn't have to look it up again
+    let (song, extra_info) = songs
         .find(payload.song_id)
-        .first::<Song>(&mut conn)
+        .left_join(extra_song_info::table)
+        .first::<(Song, Option<ExtraSongInfo>)>(&mut conn)
         .await
         .http_error("Song not found", StatusCode::NOT_FOUND)?;
 
+    let track_shape = split_x_separated::<i32>(&payload.track_shape)?;
+    let feats: Vec<&str> = payload.feats.split(", ").collect();
+    // The client's own gold threshold only counts where it's lower, sending a higher one would raise the limit
+    let gold_reference = Score::reference_gold_threshold(song.id, payload.league, &mut conn)
+        .await?
+        .map_or(payload.gold_threshold, |stored| {
+            stored.min(payload.gold_threshold)
+        });
+    if let Err(implausibility) = config.gameplay.check(
+        payload.score,
+        gold_reference,
+        payload.song_length,
+        track_shape.len(),
+        &feats,
+    ) {
+        info!(
+            "Refused implausible score of player {} on song {}: {}",
+            player.id, song.id, implausibility
+        );
+        let rejected = NewRejectedScore {
+            player_id: player.id,
+            song_id: song.id,
+            league: payload.league,
+            score: payload.score,
+            vehicle: payload.vehicle,
+            gold_threshold: payload.gold_threshold,
+            song_length: payload.song_length,
+            track_shape_length: i32::try_from(track_shape.len()).unwrap_or(i32::MAX),
+            feats: &feats,
+            reason: implausibility.to_string(),
+        };
+        if let Err(e) = rejected.insert(&mut conn).await {
+            error!(
+                "Failed to record rejected score of player {}: {}",
+                player.id, e
+            );
+        }
+        return Ok(Xml(SendRideResponse {
+            status: "failed".to_owned(),
+            song_id: payload.song_id,
+            beat_score: BeatScore::default(),
+        }));
+    }
+
     // Check the song for a top score by another player
     let current_top: Option<(Score, Player)> = scores
         .inner_join(players::table())
         .filter(song_id.eq(payload.song_id))
         .filter(league.eq(payload.league))
         .filter(player_id.ne(player.id))
-        .order(score.desc())
+        .order((score.desc(), submitted_at.asc()))
         .first::<(Score, Player)>(&mut conn)
         .await
         .optional()?;
+    // A #1 who replays and beats #2 without beating their own record doesn't dethrone anyone
+    let own_best: Option<i32> = scores
+        .filter(song_id.eq(payload.song_id))
+        .filter(league.eq(payload.league))
+        .filter(player_id.eq(player.id))
+        .select(score)
+        .first(&mut conn)
+        .await
+        .optional()?;
+    let dethroned_player_id = current_top
+        .as_ref()
+        .filter(|(top_score, _)| dethrones(payload.score, own_best, top_score.score))
+        .map(|(_, top_player)| top_player.id);
+    let previous_top = current_top
+        .as_ref()
+        .map(|(top_score, top_player)| (top_player.username.clone(), top_score.score));
 
     // construct part of the response that's for dethroning
     let beat_score = if let Some(current_top) = current_top {
         // Check if the player dethroned the current top score
-        if current_top.0.score < payload.score {
+        let dethroned = dethroned_player_id.is_some();
+        if dethroned {
             info!(
                 "Player {} (Steam) dethroned {} on {} with score {}",
                 steam_player, current_top.1.id, current_top.0.song_id, payload.score
@@ -268,7 +359,8 @@ pub async fn send_ride(
         }
 
         // Calculate how long the current top score has been at the top before being mercilessly dethroned (part of the Brutus achievement condition!)
-        let reign_duration = OffsetDateTime::now_utc() - current_top.0.submitted_at;
+        // top_since instead of submitted_at, so improving your own top score doesn't reset the reign
+        let reign_duration = OffsetDateTime::now_utc() - current_top.0.top_since;
 
         // Check if the player has a rivalry with the top score holder (part of the Brutus achievement condition!)
         let rivalry = rivalries
@@ -283,7 +375,7 @@ pub async fn send_ride(
         };
 
         BeatScore {
-            dethroned: current_top.0.score < payload.score,
+            dethroned,
             friend: mutual,
             rival_name: current_top.1.username,
             rival_score: current_top.0.score,
@@ -310,7 +402,7 @@ pub async fn send_ride(
         song.id,
         payload.league,
         payload.score,
-        &split_x_separated::<i32>(&payload.track_shape)?,
+        &track_shape,
         &payload
             .xstats
             .split(',')
@@ -318,25 +410,89 @@ pub async fn send_ride(
             .collect::<Result<Vec<_>, _>>()?,
         payload.density,
         payload.vehicle,
-        &payload.feats.split(", ").collect::<Vec<&str>>(),
+        &feats,
         payload.song_length,
         payload.gold_threshold,
         payload.iss,
         payload.isj,
     )
-    .create_or_update(&mut conn, &state.redis)
+    .create_or_update(&config.skill_points, &mut conn, &redis)
     .await?;
 
-    // Add MusicBrainz metadata, if no extra metadata exists already
-    // we're doing this here because we need the song length to search for the recording
-    if let Err(e) = song
-        .auto_add_metadata(payload.song_length * 10, &mut conn)
-        .await
+    Player::touch_last_played(player.id, &mut conn).await?;
+    if let Err(e) =
+        SkillPointDecay::lift_and_restore(player.id, &config.skill_points, &mut conn, &redis).await
     {
-        error!("Failed to add metadata for song {}: {}", song.id, e);
+        error!(
+            "Failed to restore decayed skill points of player {}: {}",
+            player.id, e
+        );
+    }
+
+    if let (Some(webhook_url), Some(_), Some((previous_holder, previous_score))) = (
+        &config.external.discord_webhook_url,
+        dethroned_player_id,
+        previous_top,
+    ) {
+        if !config.external.discord_elite_only || payload.league == League::Elite {
+            discord::announce_top_score(
+                webhook_url.clone(),
+                config.branding.server_name.clone(),
+                TopScoreAnnouncement {
+                    song_title: song.title.clone(),
+                    song_artist: song.artist.clone(),
+                    league: payload.league,
+                    player_name: player.username.clone(),
+                    new_score: payload.score,
+                    previous_holder,
+                    previous_score,
+                },
+            );
+        }
+    }
+
+    if let Some(recipient_id) = dethroned_player_id {
+        let dethroned = DethronedPayload {
+            song_id: song.id,
+            league: payload.league,
+            score: payload.score,
+            dethroned_by: player.id,
+        };
+        // Not awaited, so the notification doesn't hold up the response
+        tokio::spawn(async move {
+            let result = async {
+                let notification = NewNotification::dethroned(recipient_id, &dethroned)?;
+                let mut conn = db.get().await?;
+                notification.insert(&mut conn).await?;
+                anyhow::Ok(())
+            }
+            .await;
+            if let Err(e) = result {
+                error!(
+                    "Failed to notify player {} of being dethroned on song {}: {}",
+                    recipient_id, dethroned.song_id, e
+                );
+            }
+        });
+    }
+
+    // Queue a MusicBrainz lookup, if no extra metadata exists already
+    // we're doing this here because we need the song length to search for the recording
+    if extra_info.is_none() {
+        let job = LookupJob::new(
+            song.id,
+            LookupKind::Search {
+                duration: payload.song_length * 10,
+            },
+        );
+        if let Err(e) = lookup_queue::enqueue(&job, &redis).await {
+            error!(
+                "Failed to queue metadata lookup for song {}: {}",
+                song.id, e
+            );
+        }
     }
 
-    // TODO: Implement dethrone notifications
     Ok(Xml(SendRideResponse {
         status: "allgood".to_owned(),
         song_id: new_score.song_id,
@@ -344,11 +500,27 @@ pub async fn send_ride(
     }))
 }
 
+/// Whether a new score takes the top spot from the best other player's `others_top`.
+/// If the player's own previous best was already above that, they were on top before.
+fn dethrones(new_score: i32, own_best: Option<i32>, others_top: i32) -> bool {
+    new_score > others_top && own_best.is_none_or(|own_best| own_best <= others_top)
+}
+
 #[derive(Deserialize)]
 pub struct GetRidesRequest {
     #[serde(rename = "songid")]
     song_id: i32,
     ticket: String,
+    #[serde(flatten)]
+    wavebreaker: WavebreakerGetRidesSection,
+}
+
+/// Wavebreaker-specific rides request information
+#[derive(Deserialize)]
+pub struct WavebreakerGetRidesSection {
+    /// Only sent by the Wavebreaker client mod
+    #[serde(rename = "wvbrclientversion")]
+    client_version: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -356,9 +528,24 @@ pub struct GetRidesRequest {
 pub struct GetRidesResponse {
     #[serde(rename = "@status")]
     status: String,
+    /// Only sent to the Wavebreaker client mod, if a rival recently took the player's record on the song
+    #[serde(rename = "wvbrnotice", skip_serializing_if = "Option::is_none")]
+    notice: Option<String>,
     scores: Vec<ResponseScore>,
     #[serde(rename = "servertime")]
     server_time: u64,
+    /// Only sent to the Wavebreaker client mod
+    #[serde(rename = "wvbrsong", skip_serializing_if = "Option::is_none")]
+    song: Option<RidesSongInfo>,
+}
+
+/// The song the rides are for, so modifier variants can be told apart from the base song
+#[derive(Debug, Serialize, Deserialize)]
+struct RidesSongInfo {
+    title: String,
+    artist: String,
+    #[serde(rename = "modifier", default)]
+    modifiers: Vec<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -418,37 +605,81 @@ fn create_league_rides(league: League, scores: Vec<ScoreWithPlayer>) -> LeagueRi
     league_rides
 }
 
+/// How long after a rival took the player's record they're told about it in-game
+const RECENT_DETHRONE: time::Duration = time::Duration::days(7);
+
 /// Returns scores for a given song.
+/// Reads from the read replica if one is configured, so a score that was just submitted might be missing.
+/// The Wavebreaker client mod also gets the song's title, artist and modifiers,
+/// and a notice if one of the player's rivals took their record on it recently.
+/// If the song doesn't exist, the status is `failed`.
 ///
 /// # Errors
 /// This fails if:
 /// - The response fails to serialize
 /// - Authenticating with Steam fails
-#[instrument(skip_all)]
 pub async fn get_rides(
-    State(state): State<AppState>,
+    State(db): State<Db>,
+    State(redis): State<Cache>,
+    State(steam): State<SteamAuth>,
     Form(payload): Form<GetRidesRequest>,
 ) -> Result<Xml<GetRidesResponse>, RouteError> {
     const ALL_LEAGUES: [League; 3] = [League::Casual, League::Pro, League::Elite];
 
-    let steam_player = ticket_auth(&payload.ticket, &state.steam_api, &state.redis).await?;
+    let steam_player = ticket_auth(&payload.ticket, &steam.api, &redis).await?;
     info!(
         "Player {} (Steam) requesting rides of song {}",
         steam_player, payload.song_id
     );
 
-    let mut conn = state.db.get().await?;
+    let mut conn = db.read_conn().await?;
+
+    let Some(song) = crate::schema::songs::table
+        .find(payload.song_id)
+        .first::<Song>(&mut conn)
+        .await
+        .optional()?
+    else {
+        // The game handles a failed status fine, unlike an HTTP error
+        info!("Rides requested for nonexistent song {}", payload.song_id);
+        return Ok(Xml(GetRidesResponse {
+            status: "failed".to_owned(),
+            notice: None,
+            scores: vec![],
+            server_time: 143,
+            song: None,
+        }));
+    };
 
     let player: Player = Player::find_by_steam_id(steam_player)
         .first::<Player>(&mut conn)
         .await?;
 
-    let mut rival_ids: Vec<i32> = player
-        .get_rivals(&mut conn)
+    let rivals = player.get_rivals(&mut conn).await?;
+    let mut rival_ids: Vec<i32> = rivals.iter().map(|r| r.id).collect();
+
+    let is_mod_client = payload.wavebreaker.client_version.is_some();
+    let notice = if is_mod_client && !rival_ids.is_empty() {
+        Notification::latest_dethrone(
+            player.id,
+            song.id,
+            &rival_ids,
+            OffsetDateTime::now_utc() - RECENT_DETHRONE,
+            &mut conn,
+        )
         .await?
-        .iter()
-        .map(|r| r.id)
-        .collect();
+        .and_then(|(rival_id, dethroned_at)| {
+            let rival = rivals.iter().find(|rival| rival.id == rival_id)?;
+            let ago = i18n::time_ago(player.language(), OffsetDateTime::now_utc() - dethroned_at);
+            Some(i18n::text(
+                player.language(),
+                "rides.dethroned",
+                &[("username", &rival.username), ("ago", &ago)],
+            ))
+        })
+    } else {
+        None
+    };
 
     // Add the player to the rivals list so they're visible in rival scores
     rival_ids.push(player.id);
@@ -474,6 +705,7 @@ pub async fn get_rides(
 
     Ok(Xml(GetRidesResponse {
         status: "allgood".to_owned(),
+        notice,
         scores: vec![
             ResponseScore {
                 score_type: Leaderboard::Global,
@@ -489,5 +721,120 @@ pub async fn get_rides(
             },
         ],
         server_time: 143,
+        song: is_mod_client.then(|| RidesSongInfo {
+            title: song.title,
+            artist: song.artist,
+            modifiers: song.modifiers.into_iter().flatten().flatten().collect(),
+        }),
     }))
 }
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{header, Method, Request},
+    };
+    use fred::prelude::*;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        models::rejected_scores::RejectedScore,
+        schema::{rejected_scores, scores},
+        util::testing::{self, KeyStore, RecordedRedis},
+    };
+
+    #[test]
+    fn beating_the_top_score_dethrones() {
+        assert!(dethrones(2000, None, 1500));
+        assert!(dethrones(2000, Some(1000), 1500));
+        assert!(dethrones(2000, Some(1500), 1500));
+        assert!(!dethrones(1500, Some(1000), 1500));
+    }
+
+    #[test]
+    fn replaying_top_player_doesnt_dethrone() {
+        // #1 has 3000, #2 has 1500, #1 replays and gets 2000
+        assert!(!dethrones(2000, Some(3000), 1500));
+    }
+
+    #[tokio::test]
+    async fn raised_gold_thresholds_dont_raise_the_limit() {
+        let Some(state) = testing::state(Arc::new(KeyStore::default())).await else {
+            return;
+        };
+        let (player_id, song_id) = {
+            let mut conn = state.db.get().await.unwrap();
+            let player_id = testing::insert_player(1, &mut conn).await;
+            let other_player_id = testing::insert_player(2, &mut conn).await;
+            let song_id = testing::insert_song("Dear Music", "A4.", &mut conn).await;
+            // Accepted earlier with a gold threshold of 1000, so the limit is 10000
+            let skill_points = testing::redis(Arc::new(RecordedRedis::default())).await;
+            testing::insert_score(
+                other_player_id,
+                song_id,
+                League::Casual,
+                1000,
+                &mut conn,
+                &skill_points,
+            )
+            .await;
+            (player_id, song_id)
+        };
+        // Tickets Steam already verified are cached, so this doesn't ask Steam
+        let _: () = state
+            .redis
+            .set(
+                "steamticket:cheater",
+                testing::steam_id(1),
+                None,
+                None,
+                false,
+            )
+            .await
+            .unwrap();
+
+        // Within 10 times the threshold the client claims, but not the stored one
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("/as_steamlogin/game_SendRideSteamVerified.php")
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from(format!(
+                "ticket=cheater&songid={song_id}&score=50000&vehicle=0&league=0&feats=\
+                &songlength=18000&trackshape=1x2x3&density=0&xstats=1,2\
+                &goldthreshold=100000&iss=0&isj=0"
+            )))
+            .unwrap();
+        let response = crate::make_router(state.clone())
+            .oneshot(request)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let response: SendRideResponse =
+            quick_xml::de::from_str(std::str::from_utf8(&body).unwrap()).unwrap();
+        assert_eq!(response.status, "failed");
+
+        let mut conn = state.db.get().await.unwrap();
+        let rejected: Vec<RejectedScore> = rejected_scores::table
+            .filter(rejected_scores::player_id.eq(player_id))
+            .select(RejectedScore::as_select())
+            .load(&mut conn)
+            .await
+            .unwrap();
+        assert_eq!(rejected.len(), 1);
+        assert_eq!(rejected[0].score, 50000);
+        assert_eq!(rejected[0].gold_threshold, 100_000);
+        assert_eq!(rejected[0].reason, "Score is above the limit of 10000");
+        let accepted: i64 = scores::table
+            .filter(scores::player_id.eq(player_id))
+            .count()
+            .get_result(&mut conn)
+            .await
+            .unwrap();
+        assert_eq!(accepted, 0);
+    }
+}
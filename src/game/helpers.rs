@@ -1,16 +1,66 @@
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
 use anyhow::{Context, Error};
+use axum::{
+    extract::{MatchedPath, Request},
+    http::StatusCode,
+    middleware::Next,
+    response::Response,
+};
 use fred::prelude::{Pool as RedisPool, *};
 use steam_rs::{steam_id::SteamId, Steam};
+use tracing::{field::Empty, info, info_span, warn, Instrument, Span};
+
+use crate::util::presence::record_presence;
+
+/// Middleware that runs a game route inside its own span.
+///
+/// The span is named after the route and records the player (see `ticket_auth`),
+/// whether stored player info had to be used because Steam was down (`steam_fallback`, login only),
+/// how many invalid friend entries were ignored (`skipped_friends`, rival sync only),
+/// the result (`ok`, `unauthorized` or `failed`) and `duration_ms` once the request is done.
+/// Handlers shouldn't add their own `#[instrument]` span, or the player can't be recorded here.
+pub async fn game_route_span(req: Request, next: Next) -> Response {
+    let route = req.extensions().get::<MatchedPath>().map_or_else(
+        || req.uri().path().to_owned(),
+        |path| path.as_str().to_owned(),
+    );
+    let span = info_span!(
+        "game_route",
+        otel.name = %format!("{} {}", req.method(), route),
+        player = Empty,
+        steam_fallback = Empty,
+        skipped_friends = Empty,
+        result = Empty,
+        duration_ms = Empty,
+    );
+
+    let start = Instant::now();
+    let response = next.run(req).instrument(span.clone()).await;
+
+    let result = match response.status() {
+        status if status.is_success() => "ok",
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => "unauthorized",
+        _ => "failed",
+    };
+    span.record("result", result);
+    #[allow(clippy::cast_possible_truncation)]
+    span.record("duration_ms", start.elapsed().as_millis() as u64);
+    span.in_scope(|| info!("Game request finished"));
+
+    response
+}
 
 /// Validates Steam game auth tickets. Returns a `SteamId` struct representing for user who the ticket belongs to.
 /// Checks if the ticket is cached in Redis, if not, it will authenticate with Steam and cache the ticket.
+/// Also marks the player as online (see `util::presence`), so every game route counts towards that.
 ///
 /// # Errors
 /// This function will return an error if it fails to authenticate with Steam or something goes wrong with Redis.
 pub async fn ticket_auth(ticket: &str, steam: &Steam, redis: &RedisPool) -> Result<SteamId, Error> {
     let steam_id: Option<String> = redis.get(format!("steamticket:{}", ticket)).await?;
-    match steam_id {
-        Some(steam_id) => Ok(SteamId::from(steam_id)),
+    let steam_id = match steam_id {
+        Some(steam_id) => SteamId::from(steam_id),
         None => {
             let steam_result = steam
                 .authenticate_user_ticket(12900, ticket)
@@ -26,7 +76,57 @@ pub async fn ticket_auth(ticket: &str, steam: &Steam, redis: &RedisPool) -> Resu
                 )
                 .await?;
 
-            Ok(SteamId::from(steam_result.steam_id))
+            SteamId::from(steam_result.steam_id)
+        }
+    };
+
+    // Shows up on the span created by game_route_span
+    Span::current().record("player", tracing::field::display(steam_id));
+
+    // Not being counted as online is no reason to fail the request
+    if let Ok(steam_account_num) = i32::try_from(steam_id.get_account_id()) {
+        if let Err(e) = record_presence(steam_account_num, redis).await {
+            warn!("Failed to record presence of {}: {}", steam_id, e);
+        }
+    }
+
+    Ok(steam_id)
+}
+
+/// How often fetching a player summary from Steam is attempted
+const SUMMARY_ATTEMPTS: u32 = 3;
+
+/// Fetches a player's Steam name and full-size avatar URL, retrying with jittered backoff if the Steam Web API fails.
+///
+/// # Errors
+/// This function will return an error if all attempts fail or Steam returns no summary for the player.
+pub async fn get_player_summary(
+    steam: &Steam,
+    steam_id: SteamId,
+) -> Result<(String, String), Error> {
+    let mut attempt = 1;
+    loop {
+        match steam.get_player_summaries(vec![steam_id]).await {
+            Ok(summaries) => {
+                let summary = summaries
+                    .into_iter()
+                    .next()
+                    .context("Steam returned no player summary")?;
+                return Ok((summary.persona_name, summary.avatar_full));
+            }
+            Err(e) if attempt < SUMMARY_ATTEMPTS => {
+                warn!(
+                    "Failed to get player summary for {} (attempt {}/{}): {}",
+                    steam_id, attempt, SUMMARY_ATTEMPTS, e
+                );
+                // Jitter keeps every client that failed at the same time from retrying at the same time, too
+                let jitter = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |time| u64::from(time.subsec_millis() % 100));
+                tokio::time::sleep(Duration::from_millis(200 * 2u64.pow(attempt) + jitter)).await;
+                attempt += 1;
+            }
+            Err(e) => return Err(Error::from(e).context("Failed to get player summary from Steam")),
         }
     }
 }
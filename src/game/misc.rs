@@ -1,22 +1,37 @@
+use std::sync::Arc;
+
 use axum::{extract::State, Form};
 use axum_extra::extract::Form as ExtraForm;
 use axum_serde::Xml;
 use diesel::{ExpressionMethods, QueryDsl, SelectableHelper};
 use diesel_async::{AsyncPgConnection, RunQueryDsl};
 use serde::{Deserialize, Serialize};
-use tracing::instrument;
+use time::OffsetDateTime;
+use tracing::warn;
 
 use super::helpers::ticket_auth;
 use crate::{
     models::{
+        announcements::Announcement,
         players::Player,
         scores::Score,
-        shouts::{NewShout, Shout},
+        shouts::{
+            shout_rate_limit_key, NewShout, Shout, ShoutError, MAX_SHOUT_LENGTH,
+            SHOUT_RATE_LIMIT_WINDOW_SECS,
+        },
+    },
+    util::{
+        errors::RouteError,
+        game_types::join_x_separated,
+        i18n::{self, Language},
+        rate_limit,
     },
-    util::{errors::RouteError, game_types::join_x_separated},
-    AppState,
+    Cache, Config, Db, SteamAuth,
 };
 
+/// Longest news text the game's text box fits
+const NEWS_MAX_CHARS: usize = 300;
+
 #[derive(Deserialize)]
 pub struct CustomNewsRequest {
     ticket: String,
@@ -29,29 +44,116 @@ pub struct CustomNewsResponse {
     text: String,
 }
 
-/// Sends text to the game, shown before playing a song
+/// Sends text to the game, shown before playing a song.
+/// That's the newest announcement (or a pinned one), or a greeting if there is none,
+/// followed by the player's rank and new challengers if we can get them.
 ///
 /// # Errors
 /// This fails if the response fails to serialize
-#[instrument(skip_all)]
 pub async fn get_custom_news(
-    State(state): State<AppState>,
+    State(db): State<Db>,
+    State(redis): State<Cache>,
+    State(steam): State<SteamAuth>,
+    State(config): State<Arc<Config>>,
     Form(payload): Form<CustomNewsRequest>,
 ) -> Result<Xml<CustomNewsResponse>, RouteError> {
-    let steam_player = ticket_auth(&payload.ticket, &state.steam_api, &state.redis).await?;
+    let steam_player = ticket_auth(&payload.ticket, &steam.api, &redis).await?;
 
-    let mut conn = state.db.get().await?;
+    let mut conn = db.get().await?;
 
     let player: Player = Player::find_by_steam_id(steam_player)
         .first::<Player>(&mut conn)
         .await?;
 
-    Ok(Xml(CustomNewsResponse {
-        text: format!(
-            "Hi, {}!\n\nWelcome to wavebreaker-rs,\nthe next generation of Wavebreaker!",
-            player.username
-        ),
-    }))
+    // The status is a nice extra, the game should still get its news without it
+    let status = match player_status(&player, &redis, &mut conn).await {
+        Ok(status) => status,
+        Err(e) => {
+            warn!("Failed to get news status of player {}: {}", player.id, e);
+            String::new()
+        }
+    };
+    // Leave room for the status under the announcement
+    let max_chars = if status.is_empty() {
+        NEWS_MAX_CHARS
+    } else {
+        NEWS_MAX_CHARS.saturating_sub(status.chars().count() + 2)
+    };
+
+    let announcement = Announcement::shown_at(OffsetDateTime::now_utc(), 1, &mut conn)
+        .await?
+        .pop();
+    let mut text = if let Some(announcement) = announcement {
+        announcement.game_text(max_chars)
+    } else {
+        let args = [
+            ("username", player.username.as_str()),
+            ("server_name", config.branding.server_name.as_str()),
+        ];
+        match &config.branding.welcome_message {
+            Some(template) => i18n::fill(template, &args),
+            None => i18n::text(player.language(), "news.welcome", &args),
+        }
+    };
+
+    if !status.is_empty() {
+        text.push_str("\n\n");
+        text.push_str(&status);
+    }
+
+    Ok(Xml(CustomNewsResponse { text }))
+}
+
+/// A few lines on how the player is doing: their rank and skill points,
+/// and how many players made them their rival since their previous login.
+/// Empty if there's nothing to say, e.g. for new players.
+async fn player_status(
+    player: &Player,
+    redis: &Cache,
+    conn: &mut AsyncPgConnection,
+) -> anyhow::Result<String> {
+    use crate::schema::rivalries;
+
+    let language = player.language();
+    let mut lines = Vec::new();
+
+    let (rank, skill_points) = player.get_rank_and_skill_points(None, redis).await?;
+    // Rank 0 means they're not on the leaderboard yet
+    if rank > 0 {
+        let rank = i18n::number(language, rank.into());
+        let skill_points = i18n::number(language, skill_points.into());
+        lines.push(i18n::text(
+            language,
+            "news.rank",
+            &[
+                ("rank", rank.as_str()),
+                ("skill_points", skill_points.as_str()),
+            ],
+        ));
+    }
+
+    if let Some(previous_login_at) = player.previous_login_at {
+        let challengers: i64 = rivalries::table
+            .filter(rivalries::rival_id.eq(player.id))
+            .filter(rivalries::established_at.gt(previous_login_at))
+            .count()
+            .get_result(conn)
+            .await?;
+        match challengers {
+            0 => {}
+            1 => lines.push(i18n::text(language, "news.challenger", &[])),
+            count => {
+                let count = i18n::number(language, count);
+                lines.push(i18n::text(
+                    language,
+                    "news.challengers",
+                    &[("count", count.as_str())],
+                ));
+            }
+        }
+    }
+
+    Ok(lines.join("\n"))
 }
 
 #[derive(Deserialize)]
@@ -66,14 +168,13 @@ pub struct GetShoutsRequest {
 /// This fails if:
 /// - The response fails to serialize
 /// - Something goes wrong with the database
-#[instrument(skip_all)]
 pub async fn fetch_track_shape(
-    State(state): State<AppState>,
+    State(db): State<Db>,
     Form(payload): Form<GetShoutsRequest>,
 ) -> Result<String, RouteError> {
     use crate::schema::scores::dsl::*;
 
-    let mut conn = state.db.get().await?;
+    let mut conn = db.get().await?;
 
     let ride = scores.find(payload.ridd).first::<Score>(&mut conn).await?;
     let track_shape_string =
@@ -93,31 +194,59 @@ where
     Ok(vec.into_iter().next().unwrap_or_default())
 }
 
+/// Formats a shout for display in-game, e.g. `Dan (3 days ago): Hi`
+fn format_shout(language: Language, username: &str, shout: &Shout, now: OffsetDateTime) -> String {
+    i18n::text(
+        language,
+        "shouts.entry",
+        &[
+            ("username", username),
+            ("time", &i18n::time_ago(language, now - shout.posted_at)),
+            ("content", &shout.content),
+        ],
+    )
+}
+
+/// Lists the newest shouts of a song for the game, which can't show many of them anyway.
+/// If there are more, the list ends with a pointer to the website.
 async fn shouts_to_string(
     conn: &mut AsyncPgConnection,
     target_song_id: i32,
+    language: Language,
+    config: &Config,
 ) -> diesel::QueryResult<String> {
     use crate::schema::shouts::dsl::*;
 
-    let shouts_with_player = Shout::find_by_song_id(target_song_id)
-        .order(posted_at.desc())
+    let limit = config.shouts.in_game_limit;
+    // One more than shown, to know if there are any left
+    let mut shouts_with_player = Shout::find_by_song_id(target_song_id)
+        .order((posted_at.desc(), id.desc()))
+        .limit(i64::from(limit) + 1)
         .inner_join(crate::schema::players::table)
         .select((Shout::as_select(), Player::as_select()))
         .load::<(Shout, Player)>(conn)
         .await?;
     if shouts_with_player.is_empty() {
-        return Ok(
-            "This song has no shouts yet. Let's change that!\n'Cause we're gonna shout it loud!"
-                .to_owned(),
-        );
+        return Ok(i18n::text(language, "shouts.none", &[]));
+    }
+    let has_more = shouts_with_player.len() > usize::try_from(limit).unwrap_or(usize::MAX);
+    if has_more {
+        shouts_with_player.pop();
     }
 
+    let now = OffsetDateTime::now_utc();
     let mut shout_string = String::new();
-    for shout in shouts_with_player {
-        shout_string.push_str(&format!(
-            "{} (at {}): {}\n",
-            shout.1.username, shout.0.posted_at, shout.0.content
+    for (shout, player) in &shouts_with_player {
+        shout_string.push_str(&format_shout(language, &player.username, shout, now));
+        shout_string.push('\n');
+    }
+    if has_more {
+        shout_string.push_str(&i18n::text(
+            language,
+            "shouts.more",
+            &[("website", config.branding.website_url(&config.external))],
         ));
+        shout_string.push('\n');
     }
 
     Ok(shout_string)
@@ -133,14 +262,15 @@ pub struct FetchShoutsRequest {
 ///
 /// # Errors
 /// This fails if the response can't serialize or something is wrong with the database
-#[instrument(skip_all)]
 pub async fn fetch_shouts(
-    State(state): State<AppState>,
+    State(db): State<Db>,
+    State(config): State<Arc<Config>>,
     ExtraForm(payload): ExtraForm<FetchShoutsRequest>,
 ) -> Result<String, RouteError> {
-    let mut conn = state.db.get().await?;
+    let mut conn = db.get().await?;
 
-    Ok(shouts_to_string(&mut conn, payload.song_id).await?)
+    // The game doesn't say who's asking, so the language is unknown
+    Ok(shouts_to_string(&mut conn, payload.song_id, Language::default(), &config).await?)
 }
 
 #[derive(Deserialize)]
@@ -151,27 +281,173 @@ pub struct SendShoutRequest {
     shout: String,
 }
 
-/// Sends a shout to the game
+/// Posts a shout from the game and sends back the song's shouts.
+/// If the shout isn't allowed, e.g. because it's empty or the player shouts too often,
+/// the list starts with a message saying why.
 ///
 /// # Errors
 /// This fails if:
 /// - The response fails to serialize
 /// - Something is wrong with the database
-#[instrument(skip_all)]
 pub async fn send_shout(
-    State(state): State<AppState>,
+    State(db): State<Db>,
+    State(redis): State<Cache>,
+    State(steam): State<SteamAuth>,
+    State(config): State<Arc<Config>>,
     Form(payload): Form<SendShoutRequest>,
 ) -> Result<String, RouteError> {
-    let steam_player = ticket_auth(&payload.ticket, &state.steam_api, &state.redis).await?;
+    let steam_player = ticket_auth(&payload.ticket, &steam.api, &redis).await?;
 
-    let mut conn = state.db.get().await?;
+    let mut conn = db.get().await?;
 
     let player: Player = Player::find_by_steam_id(steam_player)
         .first::<Player>(&mut conn)
         .await?;
 
+    let language = player.language();
+    // Banned players still see the shouts, theirs just isn't posted
+    let notice = if player.active_ban(&mut conn).await?.is_none() {
+        post_shout(&player, &payload, &config, &redis, &mut conn).await?
+    } else {
+        None
+    };
+
+    let shouts = shouts_to_string(&mut conn, payload.song_id, language, &config).await?;
+    Ok(match notice {
+        Some(notice) => format!("{notice}\n\n{shouts}"),
+        None => shouts,
+    })
+}
+
+/// Posts the player's shout, or says politely why it wasn't posted.
+async fn post_shout(
+    player: &Player,
+    payload: &SendShoutRequest,
+    config: &Config,
+    redis: &Cache,
+    conn: &mut AsyncPgConnection,
+) -> diesel::QueryResult<Option<String>> {
+    let language = player.language();
+
+    let max_per_minute = config.shouts.max_per_minute;
+    if !rate_limit::allows(
+        &shout_rate_limit_key(player.id),
+        max_per_minute,
+        SHOUT_RATE_LIMIT_WINDOW_SECS,
+        redis,
+    )
+    .await
+    {
+        return Ok(Some(i18n::text(
+            language,
+            "shouts.rate_limited",
+            &[("count", &max_per_minute.to_string())],
+        )));
+    }
+
     let shout = NewShout::new(payload.song_id, player.id, &payload.shout);
-    shout.insert(&mut conn).await?;
+    let key = match shout.insert(conn).await {
+        Ok(_) => return Ok(None),
+        Err(ShoutError::Database(e)) => return Err(e),
+        Err(ShoutError::Empty) => "shouts.empty",
+        Err(ShoutError::TooLong) => "shouts.too_long",
+        Err(ShoutError::Duplicate) => "shouts.duplicate",
+    };
+
+    Ok(Some(i18n::text(
+        language,
+        key,
+        &[("max", &MAX_SHOUT_LENGTH.to_string())],
+    )))
+}
 
-    Ok(shouts_to_string(&mut conn, payload.song_id).await?)
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{header, Method, Request, StatusCode},
+    };
+    use fred::prelude::*;
+    use time::Duration;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        models::bans::NewBan,
+        schema::shouts,
+        util::testing::{self, KeyStore},
+    };
+
+    #[test]
+    fn shout_time_is_relative() {
+        let now = OffsetDateTime::UNIX_EPOCH + Duration::days(10);
+        let shout = Shout {
+            id: 1,
+            song_id: 1,
+            author_id: 1,
+            posted_at: now - Duration::days(3) - Duration::hours(5),
+            content: "Hi".to_owned(),
+        };
+
+        assert_eq!(
+            format_shout(Language::English, "Dan", &shout, now),
+            "Dan (3 days ago): Hi"
+        );
+        assert_eq!(
+            format_shout(Language::German, "Dan", &shout, now),
+            "Dan (vor 3 Tagen): Hi"
+        );
+    }
+
+    #[tokio::test]
+    async fn banned_players_shouts_arent_posted() {
+        let Some(state) = testing::state(Arc::new(KeyStore::default())).await else {
+            return;
+        };
+        let song_id = {
+            let mut conn = state.db.get().await.unwrap();
+            let player_id = testing::insert_player(1, &mut conn).await;
+            let new_ban = NewBan {
+                reason: "Spam".to_owned(),
+                expires_at: None,
+            };
+            new_ban.insert(player_id, None, &mut conn).await.unwrap();
+            testing::insert_song("Dear Music", "A4.", &mut conn).await
+        };
+        // Tickets Steam already verified are cached, so this doesn't ask Steam
+        let _: () = state
+            .redis
+            .set(
+                "steamticket:banned",
+                testing::steam_id(1),
+                None,
+                None,
+                false,
+            )
+            .await
+            .unwrap();
+
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("/as_steamlogin/game_sendShoutSteamVerified.php")
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from(format!(
+                "ticket=banned&songid={song_id}&shout=Hi"
+            )))
+            .unwrap();
+        let response = crate::make_router(state.clone())
+            .oneshot(request)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let mut conn = state.db.get().await.unwrap();
+        let shout_count: i64 = shouts::table
+            .filter(shouts::song_id.eq(song_id))
+            .count()
+            .get_result(&mut conn)
+            .await
+            .unwrap();
+        assert_eq!(shout_count, 0);
+    }
 }
@@ -4,11 +4,12 @@ mod misc;
 mod radio;
 mod user;
 
-use axum::{routing::post, Router};
+use axum::{middleware::from_fn, routing::post, Router};
 use tower_http::services::ServeDir;
 
 use self::{
     gameplay::{fetch_song_id, get_rides, send_ride},
+    helpers::game_route_span,
     misc::{fetch_shouts, fetch_track_shape, get_custom_news, send_shout},
     radio::get_radio_list,
     user::{login_steam, steam_sync},
@@ -25,13 +26,16 @@ pub fn routes_steam() -> Router<AppState> {
         .route("/game_GetRidesSteamVerified.php", post(get_rides))
         .route("/game_fetchshouts_unicode.php", post(fetch_shouts))
         .route("/game_sendShoutSteamVerified.php", post(send_shout))
+        .route_layer(from_fn(game_route_span))
 }
 
 /// Returns all routes used for everything under ``//as_steamlogin``
 ///
 /// **beware the double slash**
 pub fn routes_steam_doubleslash() -> Router<AppState> {
-    Router::new().route("/game_CustomNews.php", post(get_custom_news))
+    Router::new()
+        .route("/game_CustomNews.php", post(get_custom_news))
+        .route_layer(from_fn(game_route_span))
 }
 
 /// Returns all routes used for everything under ``/as``
@@ -39,5 +43,6 @@ pub fn routes_as(cgr_path: &str) -> Router<AppState> {
     Router::new()
         .route("/game_fetchtrackshape2.php", post(fetch_track_shape))
         .route("/asradio/game_asradiolist5.php", post(get_radio_list))
+        .route_layer(from_fn(game_route_span))
         .nest_service("/asradio", ServeDir::new(cgr_path))
 }
@@ -1,22 +1,31 @@
-use tracing::instrument;
+use std::sync::Arc;
 
-use crate::util::{errors::RouteError, radio::get_radio_songs};
+use axum::extract::State;
+
+use crate::{
+    util::{errors::RouteError, radio::RadioLineup},
+    Config,
+};
 
 /// Returns a list of all Audiosurf Radio songs.
 /// Only works with clients using an old version of `RadioBrowser.cgr`
 /// That version is included with the Wavebreaker mod.
-#[instrument]
-pub async fn get_radio_list() -> Result<String, RouteError> {
-    let radio_songs = match get_radio_songs() {
-        Ok(Some(songs)) => songs,
-        Ok(None) => {
-            return Ok("no radio songs-:*x-This server has-:*x-none-:*x-https://github.com/AudiosurfResearch-:*x-".to_owned());
-        }
-        Err(e) => {
-            tracing::error!("Failed to get radio songs: {}", e);
-            return Err(RouteError::new_internal_server());
-        }
-    };
+pub async fn get_radio_list(
+    State(radio): State<RadioLineup>,
+    State(config): State<Arc<Config>>,
+) -> Result<String, RouteError> {
+    let now = time::OffsetDateTime::now_utc();
+    let lineup = radio.entries();
+    let radio_songs: Vec<_> = lineup
+        .iter()
+        .filter(|song| song.is_available_at(now))
+        .collect();
+    if radio_songs.is_empty() {
+        return Ok(format!(
+            "no radio songs-:*x-{} has-:*x-none-:*x-{}-:*x-",
+            config.branding.server_name, config.branding.support_url
+        ));
+    }
 
     // join all songs into a single string with -:*x- as separator
     // ignore the id, we don't need it
@@ -1,20 +1,25 @@
-use axum::{extract::State, Form};
+use axum::{extract::State, http::StatusCode, Form};
 use axum_serde::Xml;
-use diesel::{ExpressionMethods, QueryDsl};
-use diesel_async::RunQueryDsl;
+use diesel::{ExpressionMethods, OptionalExtension, QueryDsl};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
 use serde::{Deserialize, Serialize};
-use tracing::{info, instrument};
+use steam_rs::steam_id::SteamId;
+use tracing::{error, info, warn, Span};
 
 #[allow(clippy::wildcard_imports)]
 use crate::schema::players::dsl::*;
 use crate::{
-    game::helpers::ticket_auth,
-    models::players::{NewPlayer, Player},
+    game::helpers::{get_player_summary, ticket_auth},
+    models::{
+        players::{NewPlayer, Player},
+        rivalries::{Rivalry, RivalrySource},
+    },
     util::{
         errors::{IntoRouteError, RouteError},
-        game_types::split_x_separated,
+        game_types::FriendList,
+        registrations,
     },
-    AppState,
+    Cache, Db, SteamAuth,
 };
 
 #[derive(Deserialize)]
@@ -22,6 +27,9 @@ pub struct LoginSteamRequest {
     ticket: String,
     #[serde(rename = "wvbrclientversion")]
     client_version: String,
+    /// The game's locale (e.g. `de_DE`), if the client mod sends it
+    #[serde(rename = "wvbrlocale")]
+    locale: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -39,18 +47,21 @@ pub struct LoginSteamResponse {
 }
 
 /// Attempts to authenticate a user through Steam.
+/// Banned players get a `failed` status, and so do new players while registrations are frozen.
 ///
 /// # Errors
 /// This fails if:
 /// - The response fails to serialize
 /// - Authenticating with Steam fails
+/// - Steam player info can't be fetched and the player doesn't exist yet
 /// - Something goes wrong with the database
-#[instrument(skip_all)]
 pub async fn login_steam(
-    State(state): State<AppState>,
+    State(db): State<Db>,
+    State(redis): State<Cache>,
+    State(steam): State<SteamAuth>,
     Form(payload): Form<LoginSteamRequest>,
 ) -> Result<Xml<LoginSteamResponse>, RouteError> {
-    let steam_player = ticket_auth(&payload.ticket, &state.steam_api, &state.redis)
+    let steam_player = ticket_auth(&payload.ticket, &steam.api, &redis)
         .await
         .http_internal_error("Failed to authenticate with Steam")?;
 
@@ -59,21 +70,73 @@ pub async fn login_steam(
         steam_player, &payload.client_version
     );
 
-    let summary = &state
-        .steam_api
-        .get_player_summaries(vec![steam_player])
-        .await?;
+    let mut conn = db.get().await?;
 
-    let mut conn = state.db.get().await?;
+    if !registrations::are_open(&redis).await {
+        let existing_player = Player::find_by_steam_id(steam_player)
+            .first::<Player>(&mut conn)
+            .await
+            .optional()?;
+        if existing_player.is_none() {
+            warn!(
+                "Refused registration of {} (Steam), registrations are frozen",
+                steam_player
+            );
+            return Ok(Xml(LoginSteamResponse {
+                status: "failed".to_owned(),
+                user_id: 0,
+                username: String::new(),
+                location_id: 0,
+                steam_id: i32::try_from(steam_player.get_account_id())?,
+            }));
+        }
+    }
 
-    let player = NewPlayer::new(
-        &summary[0].persona_name,
-        steam_player,
-        i32::try_from(steam_player.get_account_id())?,
-        &summary[0].avatar_full,
-    )
-    .create_or_update(&mut conn, &state.redis)
-    .await?;
+    let player = match get_player_summary(&steam.api, steam_player).await {
+        Ok((persona_name, avatar_full)) => {
+            Span::current().record("steam_fallback", false);
+            NewPlayer::new(
+                &persona_name,
+                steam_player,
+                i32::try_from(steam_player.get_account_id())?,
+                &avatar_full,
+            )
+            .create_or_update(&mut conn, &redis)
+            .await?
+        }
+        Err(e) => {
+            let existing_player = stored_player(steam_player, e, &mut conn).await?;
+            Span::current().record("steam_fallback", true);
+            existing_player
+        }
+    };
+
+    if player.active_ban(&mut conn).await?.is_some() {
+        info!("Refused login of banned player {}", player.id);
+        return Ok(Xml(LoginSteamResponse {
+            status: "failed".to_owned(),
+            user_id: player.id,
+            username: player.username,
+            location_id: player.location_id,
+            steam_id: player.steam_account_num,
+        }));
+    }
+
+    if let Some(locale) = &payload.locale {
+        if let Err(e) = player.infer_language(locale, &mut conn).await {
+            error!("Failed to store language of player {}: {}", player.id, e);
+        }
+    }
+
+    if let Err(e) = Player::touch_last_seen(player.id, &mut conn, &redis).await {
+        error!(
+            "Failed to update last seen time of player {}: {}",
+            player.id, e
+        );
+    }
+    if let Err(e) = Player::record_login(player.id, &mut conn).await {
+        error!("Failed to record login of player {}: {}", player.id, e);
+    }
 
     Ok(Xml(LoginSteamResponse {
         status: "allgood".to_owned(),
@@ -84,10 +147,56 @@ pub async fn login_steam(
     }))
 }
 
+/// Finds the player to log in when their Steam player summary couldn't be fetched (`summary_error`).
+/// The ticket is valid, so known players can still log in with their stored name and avatar.
+///
+/// # Errors
+/// Fails with `503 Service Unavailable` for new players, since they can't be created without their username.
+async fn stored_player(
+    steam_player: SteamId,
+    summary_error: anyhow::Error,
+    conn: &mut AsyncPgConnection,
+) -> Result<Player, RouteError> {
+    let existing_player = Player::find_by_steam_id(steam_player)
+        .first::<Player>(conn)
+        .await
+        .optional()?;
+    let Some(existing_player) = existing_player else {
+        return Err(RouteError::from(summary_error)
+            .set_status_code(StatusCode::SERVICE_UNAVAILABLE)
+            .set_public_error_message("Steam is unavailable, please try again later"));
+    };
+
+    warn!(
+        "Steam player summary unavailable for {}, using stored info: {}",
+        steam_player, summary_error
+    );
+    Ok(existing_player)
+}
+
 #[derive(Deserialize)]
 pub struct SteamSyncRequest {
     ticket: String,
     snums: String,
+    /// Sent as `1` by the Wavebreaker client mod to remove rivals who aren't Steam friends anymore
+    #[serde(default, rename = "removestale", deserialize_with = "deserialize_flag")]
+    remove_stale: bool,
+}
+
+/// Reads a form flag sent as `1`/`0` or `true`/`false`
+fn deserialize_flag<'de, D>(deserializer: D) -> Result<bool, D::Error>
+where
+    D: serde::de::Deserializer<'de>,
+{
+    let value = String::deserialize(deserializer)?;
+    match value.to_ascii_lowercase().as_str() {
+        "1" | "true" => Ok(true),
+        "0" | "false" | "" => Ok(false),
+        _ => Err(serde::de::Error::invalid_value(
+            serde::de::Unexpected::Str(&value),
+            &"1, 0, true or false",
+        )),
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -104,29 +213,31 @@ pub struct SteamSyncResponse {
 /// - The response fails to serialize
 /// - Authenticating with Steam fails
 /// - Something goes wrong with the database
-#[instrument(skip_all)]
 pub async fn steam_sync(
-    State(state): State<AppState>,
+    State(db): State<Db>,
+    State(redis): State<Cache>,
+    State(steam): State<SteamAuth>,
     Form(payload): Form<SteamSyncRequest>,
 ) -> Result<Xml<SteamSyncResponse>, RouteError> {
     //Split the string of steam account numbers into a vector
-    //Validating before the steam auth request, because if this is invalid anyway then we don't care about the request
-    //This way we have one less Steam API request on the daily limit
-    let friend_nums: Vec<i32> =
-        split_x_separated(&payload.snums).http_status_error(axum::http::StatusCode::BAD_REQUEST)?;
+    //One odd entry shouldn't keep the player from syncing all the other friends, so invalid ones are just skipped
+    let friend_list = FriendList::parse(&payload.snums);
+    // Shows up on the span created by game_route_span
+    Span::current().record("skipped_friends", friend_list.skipped);
 
-    let steam_player = ticket_auth(&payload.ticket, &state.steam_api, &state.redis)
+    let steam_player = ticket_auth(&payload.ticket, &steam.api, &redis)
         .await
         .http_internal_error("Failed to authenticate with Steam")?;
-    let mut conn = state.db.get().await?;
+    let mut conn = db.get().await?;
 
     let player: Player = Player::find_by_steam_id(steam_player)
         .first::<Player>(&mut conn)
         .await?;
 
-    //Get all friends
+    //Get all friends, without the player in case the client sent their own account too
     let friends = players
-        .filter(steam_account_num.eq_any(&friend_nums))
+        .filter(steam_account_num.eq_any(&friend_list.account_nums))
+        .filter(id.ne(player.id))
         .load::<Player>(&mut conn)
         .await?;
 
@@ -136,14 +247,85 @@ pub async fn steam_sync(
             .values((
                 crate::schema::rivalries::challenger_id.eq(player.id),
                 crate::schema::rivalries::rival_id.eq(friend.id),
+                crate::schema::rivalries::source.eq(RivalrySource::SteamSync),
             ))
             .on_conflict_do_nothing()
             .execute(&mut conn)
             .await?;
     }
 
+    // Only what the sync added itself is removed, rivals the player added stay.
+    // A list with entries that couldn't be read (or none at all) might be missing friends, so nobody is removed then.
+    let complete_list = friend_list.skipped == 0 && !friend_list.account_nums.is_empty();
+    if payload.remove_stale && !complete_list {
+        info!(
+            "Not removing stale rivals of player {}, their friend list is empty or had invalid entries",
+            player.id
+        );
+    } else if payload.remove_stale {
+        let friend_ids: Vec<i32> = friends.iter().map(|friend| friend.id).collect();
+        let removed = Rivalry::prune_steam_sync(player.id, &friend_ids, &mut conn).await?;
+        if removed > 0 {
+            info!(
+                "Removed {removed} rivals of player {} who aren't Steam friends anymore",
+                player.id
+            );
+        }
+    }
+
     Ok(Xml(SteamSyncResponse {
         //This technically doesn't return the number of friends added
-        status: format!("added {} of {} friends", friends.len(), friend_nums.len()),
+        status: format!(
+            "added {} of {} friends, skipped {} invalid",
+            friends.len(),
+            friend_list.account_nums.len(),
+            friend_list.skipped
+        ),
     }))
 }
+
+#[cfg(test)]
+mod tests {
+    use serde::de::value::{Error, StrDeserializer};
+
+    use super::*;
+    use crate::util::testing;
+
+    #[test]
+    fn remove_stale_accepts_numbers_and_bools() {
+        let flag = |input| deserialize_flag(StrDeserializer::<Error>::new(input));
+        assert!(flag("1").unwrap());
+        assert!(flag("true").unwrap());
+        assert!(flag("True").unwrap());
+        assert!(!flag("0").unwrap());
+        assert!(!flag("false").unwrap());
+        assert!(flag("yes").is_err());
+    }
+
+    #[tokio::test]
+    async fn only_known_players_log_in_without_steam() {
+        let Some(mut conn) = testing::db().await else {
+            return;
+        };
+        let known_id = testing::insert_player(1, &mut conn).await;
+
+        let known = stored_player(
+            SteamId::from(testing::steam_id(1)),
+            anyhow::anyhow!("Steam is down"),
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        assert_eq!(known.id, known_id);
+        assert_eq!(known.username, "Player 1");
+
+        let unknown = stored_player(
+            SteamId::from(testing::steam_id(2)),
+            anyhow::anyhow!("Steam is down"),
+            &mut conn,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(unknown.status_code(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+}
@@ -17,20 +17,31 @@ mod game;
 mod manager;
 pub mod models;
 pub mod schema;
+mod tasks;
 mod util;
 
-use std::{io::stdout, sync::Arc};
+use std::{
+    io::stdout,
+    ops::Deref,
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use anyhow::{anyhow, Context};
 use axum::{
-    extract::{MatchedPath, Request},
+    extract::{FromRef, MatchedPath, Request},
+    middleware,
+    routing::get,
     Router,
 };
 use clap::Parser;
-use diesel::pg::Pg;
 use diesel_async::{
     async_connection_wrapper::AsyncConnectionWrapper,
-    pooled_connection::{deadpool::Pool, AsyncDieselConnectionManager},
+    pooled_connection::{
+        deadpool::{Object, Pool, PoolError},
+        AsyncDieselConnectionManager,
+    },
 };
 use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
 use figment::{
@@ -40,17 +51,22 @@ use figment::{
 use fred::{clients::Pool as RedisPool, prelude::*, types::config::Config as RedisConfig};
 use meilisearch_sdk::client::Client as MeiliClient;
 use serde::Deserialize;
+use serde_inline_default::serde_inline_default;
 use steam_openid::SteamOpenId;
 use steam_rs::Steam;
-use tower_http::trace::TraceLayer;
-use tracing::{debug, info};
+use tower_http::{services::ServeDir, trace::TraceLayer};
+use tracing::{debug, error, info, warn};
 use tracing_appender::rolling::{RollingFileAppender, Rotation};
 use tracing_subscriber::{
     fmt::writer::MakeWriterExt, layer::SubscriberExt, util::SubscriberInitExt,
 };
 use utoipa_scalar::{Scalar, Servable};
 
-use crate::game::{routes_as, routes_steam, routes_steam_doubleslash};
+use crate::{
+    game::{routes_as, routes_steam, routes_steam_doubleslash},
+    util::meilisearch::SongIndex,
+};
+
 pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!();
 
 /// Wavebreaker-specific user agent
@@ -67,14 +83,157 @@ struct Config {
     main: Main,
     radio: Radio,
     external: External,
+    /// Info for the client mod's update prompt, served by `/api/meta/client`
+    client: Option<Client>,
+    #[serde(default)]
+    logging: Logging,
+    #[serde(default)]
+    branding: Branding,
+    /// Skill point decay for inactive players, off if not set
+    decay: Option<Decay>,
+    #[serde(default)]
+    skill_points: util::skill_points::SkillPointPolicy,
+    /// Plausibility checks for score submissions
+    #[serde(default)]
+    gameplay: util::plausibility::PlausibilityRules,
+    /// Where cover art is cached
+    #[serde(default)]
+    media: util::covers::MediaConfig,
+    #[serde(default)]
+    shouts: Shouts,
 }
 
+#[serde_inline_default]
 #[derive(Deserialize, Clone)]
 struct Main {
     address: String,
     database: String,
+    /// Optional read replica, used by read-only endpoints
+    database_replica: Option<String>,
     redis: String,
     jwt_secret: String,
+    /// Queries taking at least this long (in milliseconds) are logged as slow
+    #[serde_inline_default(250)]
+    slow_query_threshold_ms: u64,
+    /// For operators who run migrations separately, e.g. as part of deploying
+    #[serde_inline_default(false)]
+    skip_migrations: bool,
+    /// Startup fails if migrations take longer than this
+    #[serde_inline_default(300)]
+    migration_timeout_secs: u64,
+    /// How crawlers requesting expensive endpoints are treated
+    #[serde(default)]
+    bot_policy: util::bots::BotPolicy,
+    /// For mirrors pointed at a replica: refuses everything that writes, and never migrates or runs background writers
+    #[serde_inline_default(false)]
+    read_only: bool,
+}
+
+#[serde_inline_default]
+#[derive(Deserialize, Clone)]
+struct Logging {
+    #[serde_inline_default("./logs".to_owned())]
+    directory: String,
+    /// Log files older than this many days are deleted
+    max_days: Option<u64>,
+    /// The oldest log files are deleted when all of them together are bigger than this
+    max_total_mb: Option<u64>,
+}
+
+impl Default for Logging {
+    fn default() -> Self {
+        Self {
+            directory: "./logs".to_owned(),
+            max_days: None,
+            max_total_mb: None,
+        }
+    }
+}
+
+impl Logging {
+    const fn retention_limits(&self) -> util::log_retention::RetentionLimits {
+        util::log_retention::RetentionLimits {
+            max_days: self.max_days,
+            max_total_bytes: match self.max_total_mb {
+                Some(max_total_mb) => Some(max_total_mb.saturating_mul(1024 * 1024)),
+                None => None,
+            },
+        }
+    }
+}
+
+/// How the server presents itself to players
+#[serde_inline_default]
+#[derive(Deserialize, Clone)]
+struct Branding {
+    #[serde_inline_default("wavebreaker-rs".to_owned())]
+    server_name: String,
+    /// Replaces the built-in greeting shown before songs. `{username}` and `{server_name}` are filled in
+    welcome_message: Option<String>,
+    #[serde_inline_default("https://github.com/AudiosurfResearch".to_owned())]
+    support_url: String,
+    discord_invite: Option<String>,
+    /// Where players find the website, e.g. in game when there are more shouts than it shows.
+    /// `steam_realm` is used if not set, for servers that host the website and the API together.
+    website_url: Option<String>,
+}
+
+impl Branding {
+    /// See `website_url`, without a trailing slash
+    fn website_url<'a>(&'a self, external: &'a External) -> &'a str {
+        self.website_url
+            .as_deref()
+            .unwrap_or(&external.steam_realm)
+            .trim_end_matches('/')
+    }
+}
+
+impl Default for Branding {
+    fn default() -> Self {
+        Self {
+            server_name: "wavebreaker-rs".to_owned(),
+            welcome_message: None,
+            support_url: "https://github.com/AudiosurfResearch".to_owned(),
+            discord_invite: None,
+            website_url: None,
+        }
+    }
+}
+
+/// How shouts are shown in game
+#[serde_inline_default]
+#[derive(Deserialize, Clone)]
+struct Shouts {
+    /// Only this many of the newest shouts are sent to the game, the rest are on the website
+    #[serde_inline_default(20)]
+    in_game_limit: u32,
+    /// How many shouts a player can post per minute
+    #[serde_inline_default(5)]
+    max_per_minute: u32,
+}
+
+impl Default for Shouts {
+    fn default() -> Self {
+        Self {
+            in_game_limit: 20,
+            max_per_minute: 5,
+        }
+    }
+}
+
+/// Lowers the leaderboard skill points of players who stopped playing, see `SkillPointDecay`
+#[serde_inline_default]
+#[derive(Deserialize, Clone)]
+struct Decay {
+    /// Players who haven't played for this long start decaying
+    #[serde_inline_default(60)]
+    inactive_days: u32,
+    /// What the skill points are multiplied with every week
+    #[serde_inline_default(0.9)]
+    factor: f64,
+    /// Skill points never decay below this percentage of their true value
+    #[serde_inline_default(50)]
+    floor_percent: u8,
 }
 
 #[derive(Deserialize, Clone)]
@@ -82,51 +241,278 @@ struct Radio {
     cgr_location: String,
 }
 
+#[derive(Deserialize, Clone)]
+struct Client {
+    /// Oldest client mod version that is still supported
+    min_version: util::version::ClientVersion,
+    /// Version players should update to
+    recommended_version: util::version::ClientVersion,
+    download_url: String,
+}
+
+#[serde_inline_default]
 #[derive(Deserialize, Clone)]
 struct External {
     steam_key: String,
     steam_realm: String,
     steam_return_path: String,
-    meilisearch_url: String,
-    meilisearch_key: String,
+    /// Song search falls back to the database if not set
+    meilisearch_url: Option<String>,
+    meilisearch_key: Option<String>,
+    /// How often all songs are sent to Meilisearch, a bit of jitter is added
+    #[serde_inline_default(60)]
+    meilisearch_sync_interval_mins: u64,
+    /// How long the metadata lookup queue waits between songs
+    #[serde_inline_default(1000)]
+    musicbrainz_lookup_interval_ms: u64,
+    /// Discord webhook that new #1 scores are announced to, off if not set
+    discord_webhook_url: Option<String>,
+    /// Only announce new #1 scores in Elite
+    #[serde(default)]
+    discord_elite_only: bool,
 }
 
-#[derive(Clone)]
+/// Shared state of all routes.
+///
+/// Handlers should only extract the parts they need (e.g. `State<Db>`), so they can be tested without building all of this.
+#[derive(Clone, FromRef)]
 pub struct AppState {
-    steam_api: Arc<Steam>,
-    steam_openid: Arc<SteamOpenId>,
     config: Arc<Config>,
-    db: Pool<diesel_async::AsyncPgConnection>,
-    redis: Arc<RedisPool>,
+    db: Db,
+    redis: Cache,
+    steam: SteamAuth,
+    metadata: Metadata,
     jwt_keys: util::jwt::Keys,
-    meili: Arc<MeiliClient>,
+    usage: util::usage::UsageCounter,
+    radio: util::radio::RadioLineup,
+}
+
+/// Postgres connection pools.
+#[derive(Clone)]
+pub struct Db {
+    primary: Pool<diesel_async::AsyncPgConnection>,
+    replica: Option<Pool<diesel_async::AsyncPgConnection>>,
 }
 
-fn run_migrations(
-    connection: &mut impl MigrationHarness<Pg>,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
-    // This will run the necessary migrations.
-    //
-    // See the documentation for `MigrationHarness` for
-    // all available methods.
-    connection.run_pending_migrations(MIGRATIONS)?;
+impl Db {
+    /// Gets a connection to the primary database.
+    ///
+    /// # Errors
+    /// Fails if no connection can be established.
+    pub async fn get(&self) -> Result<Object<diesel_async::AsyncPgConnection>, PoolError> {
+        self.primary.get().await
+    }
 
-    Ok(())
+    /// Gets a connection for read-only queries.
+    ///
+    /// This uses the read replica if one is configured, otherwise (or if the replica is unavailable) the primary database.
+    /// Data read this way might lag behind the primary a bit, so never use it for reads that decide what to write!
+    ///
+    /// # Errors
+    /// Fails if no connection to the primary database can be established either.
+    pub async fn read_conn(&self) -> Result<Object<diesel_async::AsyncPgConnection>, PoolError> {
+        if let Some(replica) = &self.replica {
+            match replica.get().await {
+                Ok(conn) => return Ok(conn),
+                Err(e) => warn!("Read replica unavailable, falling back to primary: {e}"),
+            }
+        }
+
+        self.primary.get().await
+    }
+}
+
+/// Redis pool, which holds the leaderboard and other cached data.
+/// Derefs to the pool, so it can be passed wherever a `&RedisPool` is expected.
+#[derive(Clone)]
+pub struct Cache(Arc<RedisPool>);
+
+impl Deref for Cache {
+    type Target = RedisPool;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// Everything needed to authenticate players through Steam.
+#[derive(Clone)]
+pub struct SteamAuth {
+    /// Steam Web API client
+    api: Arc<Steam>,
+    /// For logging in on the website
+    openid: Arc<SteamOpenId>,
+}
+
+/// Clients for song metadata services.
+#[derive(Clone)]
+pub struct Metadata {
+    /// Not set if Meilisearch isn't configured
+    meili: Option<Arc<MeiliClient>>,
 }
 
-/// Reads the config, initializes database connections and the Steam API client
+impl Metadata {
+    /// The index to keep in sync when songs change, if Meilisearch is configured
+    #[must_use]
+    pub fn song_index(&self) -> Option<&dyn SongIndex> {
+        self.meili.as_deref().map(|meili| meili as &dyn SongIndex)
+    }
+}
+
+/// Key of the advisory lock held while migrating, so only one instance runs migrations at a time
+const MIGRATION_LOCK_KEY: i64 = 0x5741_5645_4252_4B52;
+
+/// Migrations run on a blocking connection, since `diesel_migrations` isn't async
+type MigrationConnection = AsyncConnectionWrapper<diesel_async::AsyncPgConnection>;
+
+/// Runs all pending migrations while holding an advisory lock.
+/// If another instance is migrating at the same time, this waits for it to finish first.
 ///
-/// # Returns
-/// An `AppState` struct with all the necessary members
+/// Postgres enforces `timeout` through `lock_timeout` and `statement_timeout`,
+/// so once it passes, nothing is left waiting for the lock or migrating in the background.
+///
+/// # Errors
+/// Fails if the lock can't be taken or a migration fails, including when `timeout` passes.
+fn run_migrations(connection: &mut MigrationConnection, timeout: Duration) -> anyhow::Result<()> {
+    use diesel::{sql_types::BigInt, RunQueryDsl};
+
+    let deadline = Instant::now() + timeout;
+    info!("Waiting for migration lock");
+    set_timeout_until(connection, "lock_timeout", deadline)?;
+    diesel::sql_query("SELECT pg_advisory_lock($1)")
+        .bind::<BigInt, _>(MIGRATION_LOCK_KEY)
+        .execute(connection)
+        .context("Failed to take the migration lock! Another instance might be migrating")?;
+
+    let result = run_pending_migrations(connection, deadline);
+
+    diesel::sql_query("RESET statement_timeout").execute(connection)?;
+    diesel::sql_query("SELECT pg_advisory_unlock($1)")
+        .bind::<BigInt, _>(MIGRATION_LOCK_KEY)
+        .execute(connection)?;
+
+    result
+}
+
+/// Sets the Postgres timeout `setting` to the time left until `deadline`.
+///
+/// # Errors
+/// Fails if the deadline already passed or the setting can't be changed.
+fn set_timeout_until(
+    connection: &mut MigrationConnection,
+    setting: &str,
+    deadline: Instant,
+) -> anyhow::Result<()> {
+    use diesel::RunQueryDsl;
+
+    let remaining = deadline.saturating_duration_since(Instant::now());
+    if remaining.is_zero() {
+        return Err(anyhow!("Ran out of time for migrations"));
+    }
+    // SET doesn't take bind parameters, and 0 would turn the timeout off
+    diesel::sql_query(format!("SET {setting} = {}", remaining.as_millis().max(1)))
+        .execute(connection)?;
+
+    Ok(())
+}
+
+fn run_pending_migrations(
+    connection: &mut MigrationConnection,
+    deadline: Instant,
+) -> anyhow::Result<()> {
+    // Checked after taking the lock, so migrations another instance just ran aren't run again
+    let pending = connection
+        .pending_migrations(MIGRATIONS)
+        .map_err(|e| anyhow!(e))?;
+    if pending.is_empty() {
+        info!("No pending migrations");
+    }
+
+    for migration in pending {
+        set_timeout_until(connection, "statement_timeout", deadline)?;
+        let start = Instant::now();
+        connection
+            .run_migration(&*migration)
+            .map_err(|e| anyhow!(e))
+            .with_context(|| format!("Failed to run migration {}", migration.name()))?;
+        info!(
+            "Applied migration {} in {} ms",
+            migration.name(),
+            start.elapsed().as_millis()
+        );
+    }
+
+    Ok(())
+}
+
+/// Reads and validates the config
 ///
 /// # Errors
-/// This function can fail if the config file is missing or invalid, the connection to Postgres or Redis fails, or the Steam API key is invalid
-async fn init_state() -> anyhow::Result<AppState> {
+/// This function fails if the config file is missing or invalid
+fn load_config() -> anyhow::Result<Config> {
     let wavebreaker_config: Config = Figment::new()
         .merge(Toml::file("Wavebreaker.toml"))
         .merge(Env::prefixed("WAVEBREAKER_"))
         .extract()
         .context("Config should be valid!")?;
+    if let Some(client) = &wavebreaker_config.client {
+        if client.min_version > client.recommended_version {
+            return Err(anyhow!(
+                "Minimum client version {} is newer than the recommended version {}",
+                client.min_version,
+                client.recommended_version
+            ));
+        }
+    }
+
+    wavebreaker_config
+        .skill_points
+        .validate()
+        .context("Invalid skill point policy")?;
+    wavebreaker_config
+        .gameplay
+        .validate()
+        .context("Invalid gameplay config")?;
+    if wavebreaker_config.external.meilisearch_sync_interval_mins == 0 {
+        return Err(anyhow!(
+            "Meilisearch sync interval has to be at least a minute"
+        ));
+    }
+    if wavebreaker_config.external.musicbrainz_lookup_interval_ms < 1000 {
+        return Err(anyhow!(
+            "MusicBrainz lookup interval has to be at least 1000 ms, MusicBrainz only allows a request per second"
+        ));
+    }
+    if let Some(decay) = &wavebreaker_config.decay {
+        if !(decay.factor > 0.0 && decay.factor <= 1.0) {
+            return Err(anyhow!(
+                "Decay factor {} has to be above 0 and at most 1",
+                decay.factor
+            ));
+        }
+        if decay.floor_percent > 100 {
+            return Err(anyhow!(
+                "Decay floor of {}% is above 100%",
+                decay.floor_percent
+            ));
+        }
+    }
+
+    Ok(wavebreaker_config)
+}
+
+/// Initializes database connections and the Steam API client, and runs migrations unless `skip_migrations` is set
+///
+/// # Returns
+/// An `AppState` struct with all the necessary members
+///
+/// # Errors
+/// This function can fail if the connection to Postgres or Redis fails, migrations fail, or the Steam API key is invalid
+async fn init_state(wavebreaker_config: Config, skip_migrations: bool) -> anyhow::Result<AppState> {
+    util::instrumentation::install_slow_query_logger(
+        wavebreaker_config.main.slow_query_threshold_ms,
+    )
+    .context("Failed to install slow query logger!")?;
 
     let diesel_manager = AsyncDieselConnectionManager::<diesel_async::AsyncPgConnection>::new(
         &wavebreaker_config.main.database,
@@ -135,17 +521,41 @@ async fn init_state() -> anyhow::Result<AppState> {
         .build()
         .context("Failed to build DB pool!")?;
 
-    // clone the url because moving the value will screw things up
-    let pg_url = wavebreaker_config.main.database.clone();
-    tokio::task::spawn_blocking(move || {
-        use diesel::prelude::Connection;
-        use diesel_async::pg::AsyncPgConnection;
-        let mut conn = AsyncConnectionWrapper::<AsyncPgConnection>::establish(&pg_url)
-            .expect("Failed to establish DB connection for migrations!");
-
-        run_migrations(&mut conn).expect("Failed to run migrations!");
-    })
-    .await?;
+    let replica_pool = match &wavebreaker_config.main.database_replica {
+        Some(replica_url) => {
+            let replica_manager =
+                AsyncDieselConnectionManager::<diesel_async::AsyncPgConnection>::new(replica_url);
+            Some(
+                Pool::builder(replica_manager)
+                    .build()
+                    .context("Failed to build replica DB pool!")?,
+            )
+        }
+        None => None,
+    };
+
+    if skip_migrations {
+        info!("Skipping migrations");
+    } else {
+        // clone the url because moving the value will screw things up
+        let pg_url = wavebreaker_config.main.database.clone();
+        let migration_timeout = Duration::from_secs(wavebreaker_config.main.migration_timeout_secs);
+        tokio::task::spawn_blocking(move || {
+            use diesel::prelude::Connection;
+            let mut conn = MigrationConnection::establish(&pg_url)
+                .context("Failed to establish DB connection for migrations!")?;
+
+            run_migrations(&mut conn, migration_timeout)
+        })
+        .await?
+        .with_context(|| {
+            format!(
+                "Failed to run migrations! If they timed out after {} seconds, another instance might be holding \
+                the migration lock, or a migration is taking very long. Raise migration_timeout_secs or run migrations separately.",
+                migration_timeout.as_secs()
+            )
+        })?;
+    }
 
     let redis_cfg = RedisConfig::from_url(&wavebreaker_config.main.redis)?;
     let redis_builder = Builder::from_config(redis_cfg);
@@ -167,19 +577,30 @@ async fn init_state() -> anyhow::Result<AppState> {
     )
     .map_err(|e| anyhow!("Failed to construct SteamOpenId: {e:?}"))?;
 
-    let meilisearch_client = MeiliClient::new(
-        &wavebreaker_config.external.meilisearch_url,
-        Some(&wavebreaker_config.external.meilisearch_key),
-    )?;
+    let meilisearch_client = wavebreaker_config
+        .external
+        .meilisearch_url
+        .as_deref()
+        .map(|url| MeiliClient::new(url, wavebreaker_config.external.meilisearch_key.as_deref()))
+        .transpose()?;
 
     Ok(AppState {
-        steam_api: Arc::new(Steam::new(&wavebreaker_config.external.steam_key)),
-        steam_openid: Arc::new(steam_openid),
-        db: pool,
-        redis: Arc::new(redis_pool),
+        db: Db {
+            primary: pool,
+            replica: replica_pool,
+        },
+        redis: Cache(Arc::new(redis_pool)),
+        steam: SteamAuth {
+            api: Arc::new(Steam::new(&wavebreaker_config.external.steam_key)),
+            openid: Arc::new(steam_openid),
+        },
+        metadata: Metadata {
+            meili: meilisearch_client.map(Arc::new),
+        },
         jwt_keys: util::jwt::Keys::new(wavebreaker_config.main.jwt_secret.as_bytes()),
+        usage: util::usage::UsageCounter::default(),
+        radio: util::radio::RadioLineup::default(),
         config: Arc::new(wavebreaker_config),
-        meili: Arc::new(meilisearch_client),
     })
 }
 
@@ -190,8 +611,28 @@ fn make_router(state: AppState) -> Router {
         .nest("/as_steamlogin", routes_steam())
         .nest("//as_steamlogin", routes_steam_doubleslash()) // for that one edge case
         .nest("/as", routes_as(&state.config.radio.cgr_location))
-        .nest("/api", api_router)
+        .nest(
+            "/api",
+            api_router
+                .route_layer(middleware::from_fn_with_state(
+                    state.clone(),
+                    util::usage::count_usage,
+                ))
+                .route_layer(middleware::from_fn_with_state(
+                    state.clone(),
+                    util::bots::block_bots,
+                ))
+                .nest_service(
+                    "/media/covers",
+                    ServeDir::new(&state.config.media.cover_directory),
+                ),
+        )
+        .route("/robots.txt", get(|| async { util::bots::ROBOTS_TXT }))
         .merge(Scalar::with_url("/api/docs", openapi))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            util::read_only::reject_writes,
+        ))
         .layer(
             // TAKEN FROM: https://github.com/tokio-rs/axum/blob/d1fb14ead1063efe31ae3202e947ffd569875c0b/examples/error-handling/src/main.rs#L60-L77
             TraceLayer::new_for_http() // Create our own span for the request and include the matched path. The matched
@@ -217,11 +658,16 @@ fn make_router(state: AppState) -> Router {
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    // Logging isn't set up yet, so config errors only end up on stderr
+    let wavebreaker_config = load_config()?;
+
+    let log_dir = Path::new(&wavebreaker_config.logging.directory);
+    util::log_retention::create_log_dir(log_dir).context("Failed to create log directory!")?;
     let file_appender = RollingFileAppender::builder()
-        .filename_suffix("wavebreaker.log")
+        .filename_suffix(util::log_retention::LOG_FILE_SUFFIX)
         .rotation(Rotation::DAILY)
-        .build("./logs")
-        .expect("Initializing logging failed");
+        .build(log_dir)
+        .context("Initializing logging failed")?;
     let (non_blocking, _guard) = tracing_appender::non_blocking(file_appender);
 
     tracing_subscriber::registry()
@@ -237,22 +683,68 @@ async fn main() -> anyhow::Result<()> {
 
     debug!("Start init");
 
-    let state = init_state().await?;
+    if let Err(e) =
+        util::log_retention::prune_logs(log_dir, wavebreaker_config.logging.retention_limits())
+    {
+        warn!("Failed to prune old log files: {e}");
+    }
 
     // Parse CLI arguments
     // and if we have a management command, don't spin up a server
     let args = manager::Args::parse();
+    util::covers::install(wavebreaker_config.media.clone());
+    // Showing the migration status shouldn't change it, and inspecting a file has nothing to do with it
+    let skip_migrations = wavebreaker_config.main.skip_migrations
+        || wavebreaker_config.main.read_only
+        || args.skip_migrations
+        || matches!(
+            args.command,
+            Some(
+                manager::Command::MigrationStatus
+                    | manager::Command::Doctor
+                    | manager::Command::InspectCgr { .. }
+            )
+        );
+
+    let state = init_state(wavebreaker_config, skip_migrations).await?;
+
     if args.command.is_some() {
         return manager::parse_command(&args.command.unwrap(), state).await;
     }
 
     info!("Wavebreaker starting...");
+    if state.config.main.read_only {
+        info!("Read-only mode, everything that writes will be refused");
+    }
 
     let listener = tokio::net::TcpListener::bind(&state.config.main.address)
         .await
         .context("Listener should always be able to listen!")?;
     info!("Listening on {}", &state.config.main.address);
 
+    if let Err(e) =
+        util::skill_points::check_version(&state.config.skill_points, &state.redis).await
+    {
+        warn!("Failed to check which skill point policy the leaderboards were built with: {e}");
+    }
+
+    if !state.config.main.read_only {
+        if let Err(e) = util::radio::import_legacy_config(&state.db).await {
+            warn!("Failed to import the old radio song list: {e:#}");
+        }
+
+        let backfill_state = state.clone();
+        tokio::spawn(async move {
+            if let Err(e) = manager::backfill_league_leaderboards(&backfill_state).await {
+                error!("Failed to build the per-league leaderboards: {e:#}");
+            }
+        });
+    }
+    // A failure is logged, and the lineup is loaded again by the next refresh
+    let _ = state.radio.reload(&state.db).await;
+
+    tasks::spawn_all(&state);
+
     let app = make_router(state);
 
     axum::serve(listener, app)
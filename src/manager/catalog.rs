@@ -0,0 +1,455 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::Path,
+};
+
+use anyhow::Context;
+use diesel::prelude::*;
+use diesel_async::{
+    scoped_futures::ScopedFutureExt, AsyncConnection, AsyncPgConnection, RunQueryDsl,
+};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::{
+    models::{
+        extra_song_info::{ExtraSongInfo, NewExtraSongInfo},
+        songs::Song,
+    },
+    schema::{extra_song_info, songs},
+    util::{covers::CachedCovers, normalize::normalize},
+    AppState,
+};
+
+/// Songs loaded at once when exporting
+const EXPORT_BATCH_SIZE: i64 = 1000;
+/// Lines imported per transaction
+const IMPORT_BATCH_SIZE: usize = 500;
+
+/// One line of a catalog export
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct CatalogEntry {
+    title: String,
+    artist: String,
+    modifiers: Option<Vec<Option<String>>>,
+    /// What songs are matched by on import, if the MBID doesn't match anything
+    normalized_title: String,
+    normalized_artist: String,
+    #[serde(default)]
+    extra_info: Option<CatalogExtraInfo>,
+}
+
+/// The parts of `ExtraSongInfo` that are the same on every instance
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+struct CatalogExtraInfo {
+    mbid: Option<String>,
+    cover_url: Option<String>,
+    cover_url_small: Option<String>,
+    musicbrainz_title: Option<String>,
+    musicbrainz_artist: Option<String>,
+    musicbrainz_length: Option<i32>,
+    aliases_title: Option<Vec<String>>,
+    aliases_artist: Option<Vec<String>>,
+}
+
+impl CatalogExtraInfo {
+    fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    fn into_new(self, song_id: i32) -> NewExtraSongInfo {
+        NewExtraSongInfo::new(
+            song_id,
+            self.cover_url,
+            self.cover_url_small,
+            self.mbid,
+            self.musicbrainz_title,
+            self.musicbrainz_artist,
+            self.musicbrainz_length,
+            self.aliases_title,
+            self.aliases_artist,
+        )
+    }
+}
+
+impl From<ExtraSongInfo> for CatalogExtraInfo {
+    fn from(info: ExtraSongInfo) -> Self {
+        Self {
+            mbid: info.mbid,
+            cover_url: info.cover_url,
+            cover_url_small: info.cover_url_small,
+            musicbrainz_title: info.musicbrainz_title,
+            musicbrainz_artist: info.musicbrainz_artist,
+            musicbrainz_length: info.musicbrainz_length,
+            aliases_title: info
+                .aliases_title
+                .map(|aliases| aliases.into_iter().flatten().collect()),
+            aliases_artist: info
+                .aliases_artist
+                .map(|aliases| aliases.into_iter().flatten().collect()),
+        }
+    }
+}
+
+/// Drops the imported fields the song already has, unless they should be overwritten.
+fn fill_missing(
+    existing: &ExtraSongInfo,
+    imported: CatalogExtraInfo,
+    overwrite: bool,
+) -> CatalogExtraInfo {
+    if overwrite {
+        return imported;
+    }
+
+    CatalogExtraInfo {
+        mbid: imported.mbid.filter(|_| existing.mbid.is_none()),
+        cover_url: imported.cover_url.filter(|_| existing.cover_url.is_none()),
+        cover_url_small: imported
+            .cover_url_small
+            .filter(|_| existing.cover_url_small.is_none()),
+        musicbrainz_title: imported
+            .musicbrainz_title
+            .filter(|_| existing.musicbrainz_title.is_none()),
+        musicbrainz_artist: imported
+            .musicbrainz_artist
+            .filter(|_| existing.musicbrainz_artist.is_none()),
+        musicbrainz_length: imported
+            .musicbrainz_length
+            .filter(|_| existing.musicbrainz_length.is_none()),
+        aliases_title: imported
+            .aliases_title
+            .filter(|_| existing.aliases_title.is_none()),
+        aliases_artist: imported
+            .aliases_artist
+            .filter(|_| existing.aliases_artist.is_none()),
+    }
+}
+
+/// Writes every song with its extra info to `path`, one JSON object per line.
+///
+/// # Errors
+/// Fails if the file can't be written or something is wrong with the DB.
+pub async fn export_songs(state: &AppState, path: &Path) -> anyhow::Result<()> {
+    let mut conn = state.db.read_conn().await?;
+    let mut writer = BufWriter::new(
+        File::create(path).with_context(|| format!("Failed to create {}", path.display()))?,
+    );
+
+    let mut last_id = 0;
+    let mut exported = 0;
+    loop {
+        let batch: Vec<(Song, Option<ExtraSongInfo>)> = songs::table
+            .left_join(extra_song_info::table)
+            .filter(songs::id.gt(last_id))
+            .order(songs::id.asc())
+            .limit(EXPORT_BATCH_SIZE)
+            .select((Song::as_select(), Option::<ExtraSongInfo>::as_select()))
+            .load(&mut conn)
+            .await?;
+        let Some((last_song, _)) = batch.last() else {
+            break;
+        };
+        last_id = last_song.id;
+
+        for (song, extra_info) in batch {
+            let entry = CatalogEntry {
+                normalized_title: song.title_normalized,
+                normalized_artist: song.artist_normalized,
+                title: song.title,
+                artist: song.artist,
+                modifiers: song.modifiers,
+                extra_info: extra_info.map(CatalogExtraInfo::from),
+            };
+            serde_json::to_writer(&mut writer, &entry)?;
+            writeln!(writer)?;
+            exported += 1;
+        }
+    }
+    writer.flush()?;
+
+    info!("Exported {exported} songs to {}", path.display());
+    Ok(())
+}
+
+#[derive(Debug, Default)]
+struct ImportReport {
+    /// Entries that matched a song on this instance
+    matched: usize,
+    /// Songs that got extra info for the first time
+    created: usize,
+    /// Songs whose extra info got new fields
+    updated: usize,
+    /// Entries without a matching song, or that couldn't be read
+    skipped: usize,
+}
+
+/// Adds the extra info of an export to the matching songs on this instance.
+/// Songs are matched by MBID first, then by normalized title, artist and modifiers, and never created.
+///
+/// # Errors
+/// Fails if the file can't be read or something is wrong with the DB.
+pub async fn import_songs(state: &AppState, path: &Path, overwrite: bool) -> anyhow::Result<()> {
+    let reader = BufReader::new(
+        File::open(path).with_context(|| format!("Failed to open {}", path.display()))?,
+    );
+    let mut conn = state.db.get().await?;
+
+    let mut report = ImportReport::default();
+    let mut batch = Vec::with_capacity(IMPORT_BATCH_SIZE);
+    for (line_number, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        match serde_json::from_str::<CatalogEntry>(&line) {
+            Ok(entry) => batch.push(entry),
+            Err(e) => {
+                warn!("Skipping line {}: {e}", line_number + 1);
+                report.skipped += 1;
+            }
+        }
+
+        if batch.len() >= IMPORT_BATCH_SIZE {
+            import_batch(
+                std::mem::take(&mut batch),
+                overwrite,
+                &mut report,
+                &mut conn,
+            )
+            .await?;
+        }
+    }
+    if !batch.is_empty() {
+        import_batch(batch, overwrite, &mut report, &mut conn).await?;
+    }
+
+    info!(
+        "Imported {}: {} matched ({} with new extra info, {} updated), {} skipped",
+        path.display(),
+        report.matched,
+        report.created,
+        report.updated,
+        report.skipped
+    );
+    Ok(())
+}
+
+/// Imports a batch of entries in one transaction.
+async fn import_batch(
+    batch: Vec<CatalogEntry>,
+    overwrite: bool,
+    report: &mut ImportReport,
+    conn: &mut AsyncPgConnection,
+) -> anyhow::Result<()> {
+    let batch_report = conn
+        .transaction::<_, anyhow::Error, _>(|conn| {
+            async move {
+                let mut report = ImportReport::default();
+                for entry in batch {
+                    import_entry(entry, overwrite, &mut report, conn).await?;
+                }
+                Ok(report)
+            }
+            .scope_boxed()
+        })
+        .await?;
+
+    report.matched += batch_report.matched;
+    report.created += batch_report.created;
+    report.updated += batch_report.updated;
+    report.skipped += batch_report.skipped;
+    Ok(())
+}
+
+async fn import_entry(
+    entry: CatalogEntry,
+    overwrite: bool,
+    report: &mut ImportReport,
+    conn: &mut AsyncPgConnection,
+) -> anyhow::Result<()> {
+    let mbid = entry
+        .extra_info
+        .as_ref()
+        .and_then(|info| info.mbid.as_deref());
+    let mut song_id: Option<i32> = match mbid {
+        Some(mbid) => extra_song_info::table
+            .filter(extra_song_info::mbid.eq(mbid))
+            .select(extra_song_info::song_id)
+            .first(conn)
+            .await
+            .optional()?,
+        None => None,
+    };
+    if song_id.is_none() {
+        song_id = songs::table
+            .filter(songs::title_normalized.eq(normalize(&entry.title)))
+            .filter(songs::artist_normalized.eq(normalize(&entry.artist)))
+            .filter(songs::modifiers.is_not_distinct_from(entry.modifiers))
+            .select(songs::id)
+            .first(conn)
+            .await
+            .optional()?;
+    }
+    let Some(song_id) = song_id else {
+        report.skipped += 1;
+        return Ok(());
+    };
+    report.matched += 1;
+
+    let Some(imported) = entry.extra_info else {
+        return Ok(());
+    };
+    let existing: Option<ExtraSongInfo> = extra_song_info::table
+        .filter(extra_song_info::song_id.eq(song_id))
+        .select(ExtraSongInfo::as_select())
+        .first(conn)
+        .await
+        .optional()?;
+
+    match existing {
+        Some(existing) => {
+            let changes = fill_missing(&existing, imported, overwrite);
+            if !changes.is_empty() {
+                // Covers of replaced URLs aren't ours to show anymore, `cache-covers` gets the new ones
+                let stale_covers = CachedCovers {
+                    cover_file: changes.cover_url.as_ref().map(|_| None),
+                    cover_file_small: changes.cover_url_small.as_ref().map(|_| None),
+                };
+                diesel::update(&existing)
+                    .set((changes.into_new(song_id), stale_covers))
+                    .execute(conn)
+                    .await?;
+                report.updated += 1;
+            }
+        }
+        None if !imported.is_empty() => {
+            imported.into_new(song_id).insert(conn).await?;
+            report.created += 1;
+        }
+        None => {}
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::util::testing;
+
+    #[test]
+    fn import_only_fills_missing_fields() {
+        let existing = ExtraSongInfo {
+            mbid: Some("local".to_owned()),
+            cover_url: Some("https://example.com/local.jpg".to_owned()),
+            ..Default::default()
+        };
+        let imported = || CatalogExtraInfo {
+            mbid: Some("imported".to_owned()),
+            cover_url: None,
+            musicbrainz_title: Some("Title".to_owned()),
+            musicbrainz_length: Some(180_000),
+            ..Default::default()
+        };
+
+        let filled = fill_missing(&existing, imported(), false);
+        assert_eq!(
+            filled,
+            CatalogExtraInfo {
+                musicbrainz_title: Some("Title".to_owned()),
+                musicbrainz_length: Some(180_000),
+                ..Default::default()
+            }
+        );
+
+        assert_eq!(fill_missing(&existing, imported(), true), imported());
+    }
+
+    #[test]
+    fn nothing_to_fill_is_empty() {
+        let existing = ExtraSongInfo {
+            mbid: Some("local".to_owned()),
+            ..Default::default()
+        };
+        let imported = CatalogExtraInfo {
+            mbid: Some("imported".to_owned()),
+            ..Default::default()
+        };
+
+        assert!(fill_missing(&existing, imported, false).is_empty());
+    }
+
+    #[tokio::test]
+    async fn exports_import_into_songs_tagged_in_another_case() {
+        let Some(state) = testing::state(Arc::new(testing::RecordedRedis::default())).await else {
+            return;
+        };
+        let (song_id, bare_id) = {
+            let mut conn = state.db.get().await.unwrap();
+            let song_id = testing::insert_song("Dear Music", "Sumijun", &mut conn).await;
+            let bare_id = testing::insert_song("Doppelganger", "A4.", &mut conn).await;
+            NewExtraSongInfo::new(
+                song_id,
+                Some("https://coverartarchive.org/release/release/front-500.jpg".to_owned()),
+                None,
+                Some("recording".to_owned()),
+                Some("Dear Music".to_owned()),
+                Some("Sumijun".to_owned()),
+                Some(180_000),
+                Some(vec!["Dear Music.".to_owned()]),
+                None,
+            )
+            .insert(&mut conn)
+            .await
+            .unwrap();
+            (song_id, bare_id)
+        };
+        let extra_info = |song_id: i32| {
+            extra_song_info::table
+                .filter(extra_song_info::song_id.eq(song_id))
+                .select(ExtraSongInfo::as_select())
+        };
+
+        let path =
+            std::env::temp_dir().join(format!("wavebreaker-catalog-{}.jsonl", std::process::id()));
+        export_songs(&state, &path).await.unwrap();
+
+        // Like another instance, where the song was tagged in another case and has no extra info yet
+        let exported = std::fs::read_to_string(&path).unwrap();
+        std::fs::write(
+            &path,
+            exported.replace(r#""title":"Dear Music""#, r#""title":"DEAR MUSIC""#),
+        )
+        .unwrap();
+        let expected = {
+            let mut conn = state.db.get().await.unwrap();
+            let expected =
+                CatalogExtraInfo::from(extra_info(song_id).first(&mut conn).await.unwrap());
+            diesel::delete(extra_song_info::table.filter(extra_song_info::song_id.eq(song_id)))
+                .execute(&mut conn)
+                .await
+                .unwrap();
+            expected
+        };
+
+        import_songs(&state, &path, false).await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let mut conn = state.db.get().await.unwrap();
+        let imported = extra_info(song_id).first(&mut conn).await.unwrap();
+        assert_eq!(CatalogExtraInfo::from(imported), expected);
+        // Songs without extra info stay that way
+        let bare = extra_info(bare_id)
+            .first(&mut conn)
+            .await
+            .optional()
+            .unwrap();
+        assert!(bare.is_none());
+    }
+}
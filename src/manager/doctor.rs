@@ -0,0 +1,349 @@
+use std::{fs, path::Path};
+
+use anyhow::anyhow;
+use diesel_async::RunQueryDsl;
+use fred::prelude::*;
+use steam_rs::steam_id::SteamId;
+use url::Url;
+
+use super::migration_versions;
+use crate::{
+    models::radio_entries::RadioEntry,
+    util::{
+        cgr::CgrFile,
+        radio::{self, cgr_file_name},
+        registrations, skill_points,
+    },
+    AppState,
+};
+
+/// Where Steam has to send players back to after logging in, relative to the realm
+const OPENID_RETURN_ROUTE: &str = "/api/auth/return";
+/// Any public profile works for checking the API key, this is Steam's own test account
+const STEAM_TEST_ACCOUNT: &str = "76561197960287930";
+/// Key that is written and read back to check Redis
+const REDIS_CHECK_KEY: &str = "doctor:roundtrip";
+
+/// What a check found
+enum Outcome {
+    Ok(String),
+    Failed {
+        error: String,
+        /// What the operator can do about it
+        hint: &'static str,
+    },
+}
+
+struct Check {
+    name: &'static str,
+    /// The server doesn't work at all if this fails
+    critical: bool,
+    outcome: Outcome,
+}
+
+impl Check {
+    fn new(name: &'static str, critical: bool, outcome: Outcome) -> Self {
+        Self {
+            name,
+            critical,
+            outcome,
+        }
+    }
+}
+
+/// Runs all checks and prints their results as a table.
+///
+/// The database and Redis already have to be reachable for the manager to start at all,
+/// so if they aren't, the error from startup is what tells the operator.
+///
+/// # Errors
+/// Fails if any critical check failed.
+pub async fn run(state: &AppState) -> anyhow::Result<()> {
+    let checks = vec![
+        Check::new("Database", true, check_database(state).await),
+        Check::new("Migrations", false, check_migrations(state).await),
+        Check::new("Redis", true, check_redis(state).await),
+        Check::new("Skill point policy", false, check_policy(state).await),
+        Check::new("Registrations", false, check_registrations(state).await),
+        Check::new("Steam Web API key", true, check_steam_key(state).await),
+        Check::new(
+            "Steam OpenID",
+            true,
+            check_openid(
+                &state.config.external.steam_realm,
+                &state.config.external.steam_return_path,
+            ),
+        ),
+        Check::new("Radio", false, check_radio(state).await),
+    ];
+
+    let name_width = checks
+        .iter()
+        .map(|check| check.name.len())
+        .max()
+        .unwrap_or(0);
+    let mut critical_failures = 0;
+    for check in &checks {
+        match &check.outcome {
+            Outcome::Ok(details) => println!("✅ {:name_width$}  {details}", check.name),
+            Outcome::Failed { error, hint } => {
+                println!("❌ {:name_width$}  {error}", check.name);
+                println!("   {:name_width$}  → {hint}", "");
+                if check.critical {
+                    critical_failures += 1;
+                }
+            }
+        }
+    }
+
+    if critical_failures > 0 {
+        return Err(anyhow!("{critical_failures} critical check(s) failed"));
+    }
+    Ok(())
+}
+
+async fn check_database(state: &AppState) -> Outcome {
+    let result = async {
+        let mut conn = state.db.get().await?;
+        diesel::sql_query("SELECT 1").execute(&mut conn).await?;
+        anyhow::Ok(())
+    }
+    .await;
+
+    match result {
+        Ok(()) => Outcome::Ok("Connected".to_owned()),
+        Err(e) => Outcome::Failed {
+            error: format!("{e:#}"),
+            hint: "Check `database` in the config and that Postgres is running",
+        },
+    }
+}
+
+async fn check_migrations(state: &AppState) -> Outcome {
+    match migration_versions(state).await {
+        Ok((applied, pending)) if pending.is_empty() => {
+            Outcome::Ok(format!("{} applied, none pending", applied.len()))
+        }
+        Ok((_, pending)) => Outcome::Failed {
+            error: format!("{} pending: {}", pending.len(), pending.join(", ")),
+            hint: "Start the server without skip_migrations to run them",
+        },
+        Err(e) => Outcome::Failed {
+            error: format!("{e:#}"),
+            hint: "Check `database` in the config and that Postgres is running",
+        },
+    }
+}
+
+async fn check_redis(state: &AppState) -> Outcome {
+    let value = time::OffsetDateTime::now_utc().unix_timestamp().to_string();
+    let result = async {
+        let _: () = state
+            .redis
+            .set(
+                REDIS_CHECK_KEY,
+                value.as_str(),
+                Some(Expiration::EX(10)),
+                None,
+                false,
+            )
+            .await?;
+        let read: Option<String> = state.redis.get(REDIS_CHECK_KEY).await?;
+        anyhow::Ok(read)
+    }
+    .await;
+
+    match result {
+        Ok(Some(read)) if read == value => Outcome::Ok("Write and read back".to_owned()),
+        Ok(_) => Outcome::Failed {
+            error: "Read back something else than was written".to_owned(),
+            hint: "Make sure nothing else uses the same Redis database, or that it's not a read-only replica",
+        },
+        Err(e) => Outcome::Failed {
+            error: format!("{e:#}"),
+            hint: "Check `redis` in the config and that Redis is running",
+        },
+    }
+}
+
+async fn check_policy(state: &AppState) -> Outcome {
+    let configured = state.config.skill_points.version;
+    match skill_points::stored_version(&state.redis).await {
+        Ok(stored) if stored == configured => {
+            Outcome::Ok(format!("Leaderboards built with version {configured}"))
+        }
+        Ok(stored) => Outcome::Failed {
+            error: format!("Version {configured} is configured, but the leaderboards were built with version {stored}"),
+            hint: "Run refresh-all-skill-points to rebuild the leaderboards with the configured policy",
+        },
+        Err(e) => Outcome::Failed {
+            error: format!("{e:#}"),
+            hint: "Check `redis` in the config and that Redis is running",
+        },
+    }
+}
+
+async fn check_registrations(state: &AppState) -> Outcome {
+    match registrations::frozen_since(&state.redis).await {
+        Ok(None) => Outcome::Ok("Open".to_owned()),
+        Ok(Some(frozen_since)) => Outcome::Failed {
+            error: format!(
+                "Frozen since {frozen_since}, players without an account can't create one"
+            ),
+            hint: "Run `freeze-registrations false` once new players should be able to join again",
+        },
+        Err(e) => Outcome::Failed {
+            error: format!("{e:#}"),
+            hint: "Check `redis` in the config and that Redis is running",
+        },
+    }
+}
+
+async fn check_steam_key(state: &AppState) -> Outcome {
+    let test_account = SteamId::from(STEAM_TEST_ACCOUNT.to_owned());
+    match state
+        .steam
+        .api
+        .get_player_summaries(vec![test_account])
+        .await
+    {
+        Ok(_) => Outcome::Ok("Accepted by Steam".to_owned()),
+        Err(e) => Outcome::Failed {
+            error: e.to_string(),
+            hint: "Check `steam_key`, you can get a key at https://steamcommunity.com/dev/apikey",
+        },
+    }
+}
+
+/// Steam sends players back to the realm with the return path appended, which has to end up at our return route.
+fn check_openid(realm: &str, return_path: &str) -> Outcome {
+    let realm_url = match Url::parse(realm) {
+        Ok(url) => url,
+        Err(e) => return Outcome::Failed {
+            error: format!("Realm {realm:?} isn't a URL: {e}"),
+            hint:
+                "Set `steam_realm` to the address of the site, e.g. https://wavebreaker.example.com",
+        },
+    };
+
+    if !matches!(realm_url.scheme(), "http" | "https") {
+        return Outcome::Failed {
+            error: format!("Realm {realm:?} isn't an HTTP(S) URL"),
+            hint:
+                "Set `steam_realm` to the address of the site, e.g. https://wavebreaker.example.com",
+        };
+    }
+    if realm_url.path() != "/" || realm_url.query().is_some() {
+        return Outcome::Failed {
+            error: format!("Realm {realm:?} has a path"),
+            hint: "`steam_realm` should only be the scheme and host, put paths into `steam_return_path`",
+        };
+    }
+    if return_path.trim_end_matches('/') != OPENID_RETURN_ROUTE {
+        return Outcome::Failed {
+            error: format!("Return path {return_path:?} isn't {OPENID_RETURN_ROUTE:?}"),
+            hint:
+                "Set `steam_return_path` to /api/auth/return, or logging in on the site won't work",
+        };
+    }
+
+    Outcome::Ok(format!(
+        "Returning to {}{return_path}",
+        realm.trim_end_matches('/')
+    ))
+}
+
+/// The radio lineup has to load, and every active song's CGR file has to be in `cgr_location` (and parse)
+/// or answer at its URL.
+async fn check_radio(state: &AppState) -> Outcome {
+    let cgr_location = &state.config.radio.cgr_location;
+    let cgr_dir = Path::new(cgr_location);
+    if let Err(e) = fs::read_dir(cgr_dir) {
+        return Outcome::Failed {
+            error: format!("Can't read {cgr_location:?}: {e}"),
+            hint: "Point `cgr_location` at the directory with the radio's .cgr files",
+        };
+    }
+
+    let lineup = async {
+        let mut conn = state.db.get().await?;
+        anyhow::Ok(RadioEntry::lineup(&mut conn).await?)
+    }
+    .await;
+    let songs = match lineup {
+        Ok(songs) => songs,
+        Err(e) => {
+            return Outcome::Failed {
+                error: format!("Can't load the radio lineup: {e:#}"),
+                hint: "Check `database` in the config and that Postgres is running",
+            }
+        }
+    };
+
+    let entries: Vec<&RadioEntry> = songs.iter().collect();
+    let unreachable: Vec<String> = radio::check_files(&entries, cgr_dir)
+        .await
+        .into_iter()
+        .filter_map(|file| Some(format!("{} ({})", file.cgr_url, file.problem?)))
+        .collect();
+    if !unreachable.is_empty() {
+        return Outcome::Failed {
+            error: format!(
+                "{} of {} songs broken: {}",
+                unreachable.len(),
+                songs.len(),
+                unreachable.join(", ")
+            ),
+            hint: "Put the files into `cgr_location`, or fix the cgr_url of those songs",
+        };
+    }
+
+    // Files that aren't in cgr_location are served from elsewhere
+    let broken: Vec<String> = songs
+        .iter()
+        .filter_map(|song| cgr_file_name(&song.cgr_url))
+        .filter_map(|file_name| {
+            let data = fs::read(cgr_dir.join(file_name)).ok()?;
+            CgrFile::parse(&data)
+                .err()
+                .map(|e| format!("{file_name} ({e})"))
+        })
+        .collect();
+    if !broken.is_empty() {
+        return Outcome::Failed {
+            error: format!("Broken CGR files: {}", broken.join(", ")),
+            hint: "Replace those files, `inspect-cgr` shows what's wrong with them",
+        };
+    }
+
+    Outcome::Ok(format!("{} songs, all CGR files available", songs.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn openid_needs_bare_realm_and_return_route() {
+        assert!(matches!(
+            check_openid("https://wavebreaker.example.com", "/api/auth/return"),
+            Outcome::Ok(_)
+        ));
+        assert!(matches!(
+            check_openid("http://localhost:1337/", "/api/auth/return"),
+            Outcome::Ok(_)
+        ));
+        assert!(matches!(
+            check_openid("wavebreaker.example.com", "/api/auth/return"),
+            Outcome::Failed { .. }
+        ));
+        assert!(matches!(
+            check_openid("https://wavebreaker.example.com/api", "/auth/return"),
+            Outcome::Failed { .. }
+        ));
+        assert!(matches!(
+            check_openid("https://wavebreaker.example.com", "/auth/return"),
+            Outcome::Failed { .. }
+        ));
+    }
+}
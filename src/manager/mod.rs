@@ -1,16 +1,32 @@
+mod catalog;
+mod doctor;
+
+use std::path::{Path, PathBuf};
+
 use clap::{ArgAction, Parser, Subcommand};
 use diesel::prelude::*;
 use diesel_async::RunQueryDsl;
 use fred::prelude::*;
-use tracing::instrument;
+use tracing::{info, instrument, warn};
 
-use crate::AppState;
+use crate::{
+    models::players::AccountType,
+    util::{
+        game_types::League,
+        leaderboard::{leaderboard_key, set_skill_points},
+        skill_points,
+    },
+    AppState,
+};
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 pub struct Args {
     #[clap(subcommand)]
     pub command: Option<Command>,
+    /// Don't run pending migrations on startup
+    #[arg(long)]
+    pub skip_migrations: bool,
 }
 
 #[derive(Debug, Subcommand)]
@@ -27,10 +43,103 @@ pub enum Command {
     DeleteScore {
         id_to_delete: i32,
     },
+    /// Writes all songs with their extra info to a JSON Lines file, for importing on another instance
+    ExportSongs {
+        path: PathBuf,
+    },
+    /// Adds the extra info from an export to matching songs, without creating any songs.
+    /// Only fills in what's missing, unless `--overwrite` is given
+    ImportSongs {
+        path: PathBuf,
+        #[arg(long)]
+        overwrite: bool,
+    },
+    /// Hides a song from charts and stats (or shows it again), its page and leaderboard stay
+    ExcludeSongFromRankings {
+        song_id: i32,
+        #[clap(action=ArgAction::Set)]
+        excluded: bool,
+    },
+    /// Looks up metadata on MusicBrainz for songs that have none, e.g. because MusicBrainz was down when they were created.
+    /// Uses the song length of each song's best score, songs without scores are skipped
+    BackfillMetadata {
+        /// Stop after this many songs
+        limit: Option<u32>,
+        /// Wait between songs, on top of the MusicBrainz rate limit
+        #[arg(long, default_value_t = 1000)]
+        delay_ms: u64,
+    },
+    /// Lists the MusicBrainz lookups that were given up on after failing, newest first.
+    /// `--requeue` queues all of them again
+    FailedMetadataLookups {
+        #[arg(long)]
+        requeue: bool,
+    },
+    /// Recounts how often each song was played from its scores, in case the counters drifted
+    RecountSongPlays,
+    /// Recomputes the normalized titles, artists and aliases songs are matched by, e.g. after the rules changed,
+    /// and counts the songs that turn out to be duplicates. `find-duplicates` lists them
+    NormalizeSongs,
+    /// Lists songs with the same normalized title, artist and modifiers, with how many scores each has
+    /// and the `merge-songs` commands that merge them into the one with the most scores
+    FindDuplicates {
+        /// Only list this many groups
+        limit: Option<u32>,
+    },
+    /// Downloads the covers that aren't cached yet into the `[media]` cover directory.
+    /// Waits `--delay-ms` after every song, so the Cover Art Archive doesn't start refusing
+    CacheCovers {
+        #[arg(long, default_value_t = 1000)]
+        delay_ms: u64,
+    },
+    /// Recalculates a player's skill points. If they are decaying, the decay is applied again,
+    /// unless `--lift-decay` is given
     RefreshSkillPoints {
         player_to_refresh: i32,
+        #[arg(long)]
+        lift_decay: bool,
+    },
+    /// Recalculates everyone's skill points. Decay is applied again, unless `--lift-decay` is given
+    RefreshAllSkillPoints {
+        #[arg(long)]
+        lift_decay: bool,
+    },
+    /// Takes effect on the player's next request, no restart or new login needed
+    ChangeAccountType {
+        player_id: i32,
+        #[clap(value_enum)]
+        account_type: AccountType,
     },
-    RefreshAllSkillPoints,
+    /// Bans a player for the given number of days, or permanently, and ends their sessions
+    BanPlayer {
+        player_id: i32,
+        reason: String,
+        #[arg(long)]
+        days: Option<u32>,
+    },
+    /// Lifts all bans of a player that haven't expired yet, including one through their account type
+    UnbanPlayer {
+        player_id: i32,
+    },
+    /// Stops players without an account from creating one (or allows it again), without a restart.
+    /// Existing players aren't affected
+    FreezeRegistrations {
+        #[clap(action=ArgAction::Set)]
+        frozen: bool,
+    },
+    /// Sends all songs to Meilisearch for the song search once, without waiting for the server's periodic sync.
+    /// The periodic sync only sends songs that changed, this also repairs an index that lost documents.
+    SyncSearch,
+    /// Lists applied and pending migrations without running any
+    MigrationStatus,
+    /// Lists the chunks of a `.cgr` file and what it says about its song,
+    /// and checks that it would be written back unchanged
+    InspectCgr {
+        path: PathBuf,
+    },
+    /// Checks the setup (database, Redis, Steam, radio) and explains how to fix what's wrong.
+    /// Fails if anything the server can't run without is broken
+    Doctor,
 }
 
 //skip state because it has members that don't implement Debug
@@ -48,7 +157,14 @@ pub async fn parse_command(command: &Command, state: AppState) -> anyhow::Result
 
             let to_merge = songs.find(*id_to_merge).first::<Song>(&mut conn).await?;
             to_merge
-                .merge_into(*target, *new_alias, &mut conn, &state.redis)
+                .merge_into(
+                    *target,
+                    *new_alias,
+                    &state.config.skill_points,
+                    &mut conn,
+                    &state.redis,
+                    state.metadata.song_index(),
+                )
                 .await
         }
         Command::DeleteSong { id_to_delete } => {
@@ -60,7 +176,13 @@ pub async fn parse_command(command: &Command, state: AppState) -> anyhow::Result
                 .find(*id_to_delete)
                 .first::<crate::models::songs::Song>(&mut conn)
                 .await?;
-            song.delete(&mut conn, &state.redis).await
+            song.delete(
+                &state.config.skill_points,
+                &mut conn,
+                &state.redis,
+                state.metadata.song_index(),
+            )
+            .await
         }
         Command::DeleteScore { id_to_delete } => {
             use crate::schema::scores::dsl::*;
@@ -71,53 +193,817 @@ pub async fn parse_command(command: &Command, state: AppState) -> anyhow::Result
                 .find(*id_to_delete)
                 .first::<crate::models::scores::Score>(&mut conn)
                 .await?;
-            score_to_delete.delete(&mut conn, &state.redis).await
+            score_to_delete
+                .delete(&state.config.skill_points, &mut conn, &state.redis)
+                .await
+        }
+        Command::ExportSongs { path } => catalog::export_songs(&state, path).await,
+        Command::ImportSongs { path, overwrite } => {
+            catalog::import_songs(&state, path, *overwrite).await
+        }
+        Command::ExcludeSongFromRankings { song_id, excluded } => {
+            use crate::models::songs::Song;
+
+            let mut conn = state.db.get().await?;
+
+            Song::set_excluded_from_rankings(*song_id, *excluded, &mut conn)
+                .await
+                .optional()?
+                .ok_or_else(|| anyhow::anyhow!("Song {song_id} not found"))?;
+
+            info!("Set excluded_from_rankings of song {song_id} to {excluded}");
+            crate::util::meilisearch::apply_exclusion(
+                state.metadata.song_index(),
+                *song_id,
+                *excluded,
+                &mut conn,
+            )
+            .await;
+            Ok(())
+        }
+        Command::BackfillMetadata { limit, delay_ms } => {
+            backfill_metadata(&state, *limit, std::time::Duration::from_millis(*delay_ms)).await
         }
-        Command::RefreshSkillPoints { player_to_refresh } => {
-            use crate::{models::players::Player, schema::players::dsl::*};
+        Command::FailedMetadataLookups { requeue } => {
+            use crate::util::lookup_queue;
+
+            if *requeue {
+                let queued = lookup_queue::requeue_dead(&state.redis).await?;
+                info!("Queued {queued} failed metadata lookup(s) again");
+                return Ok(());
+            }
+
+            let dead = lookup_queue::dead_lookups(&state.redis).await?;
+            info!("{} failed metadata lookup(s):", dead.len());
+            for lookup in dead {
+                info!(
+                    "  song {} ({:?}), {} attempt(s), last at {}: {}",
+                    lookup.job.song_id,
+                    lookup.job.kind,
+                    lookup.job.attempts,
+                    lookup.failed_at,
+                    lookup.error
+                );
+            }
+            Ok(())
+        }
+        Command::RecountSongPlays => {
+            use crate::models::songs::Song;
+
+            let mut conn = state.db.get().await?;
+
+            let fixed = Song::recount_plays(&mut conn).await?;
+            info!("Recounted song plays, {fixed} song(s) were off");
+            Ok(())
+        }
+        Command::NormalizeSongs => normalize_songs(&state).await,
+        Command::FindDuplicates { limit } => find_duplicates(&state, *limit).await,
+        Command::CacheCovers { delay_ms } => {
+            cache_covers(&state, std::time::Duration::from_millis(*delay_ms)).await
+        }
+        Command::RefreshSkillPoints {
+            player_to_refresh,
+            lift_decay,
+        } => {
+            use crate::{
+                models::{
+                    players::Player,
+                    skill_point_decay::{apply_multiplier, league_points_of, SkillPointDecay},
+                },
+                schema::players::dsl::*,
+            };
 
             let mut conn = state.db.get().await?;
 
             let player: Player = players.find(player_to_refresh).first(&mut conn).await?;
 
-            let skill_points = player.calc_skill_points(&mut conn).await?;
-            let _: () = state
-                .redis
-                .zadd(
-                    "leaderboard",
-                    None,
-                    None,
-                    false,
-                    false,
-                    (skill_points.into(), player_to_refresh.to_owned()),
-                )
+            let calculated = Player::calc_skill_points_batch(
+                &[player.id],
+                &state.config.skill_points,
+                &mut conn,
+            )
+            .await?;
+            let multiplier = if *lift_decay {
+                if SkillPointDecay::lift(player.id, &mut conn).await? {
+                    info!("Lifted the skill point decay of player {}", player.id);
+                }
+                None
+            } else {
+                SkillPointDecay::multipliers(&[player.id], &mut conn)
+                    .await?
+                    .get(&player.id)
+                    .copied()
+            };
+
+            set_skill_points(
+                player.id,
+                &apply_multiplier(&league_points_of(&calculated, player.id), multiplier),
+                &state.redis,
+            )
+            .await
+        }
+        Command::RefreshAllSkillPoints { lift_decay } => {
+            refresh_all_skill_points(&state, *lift_decay).await
+        }
+        Command::ChangeAccountType {
+            player_id,
+            account_type,
+        } => {
+            use crate::schema::players;
+
+            let mut conn = state.db.get().await?;
+
+            let updated = diesel::update(players::table.find(player_id))
+                .set(players::account_type.eq(account_type))
+                .execute(&mut conn)
                 .await?;
+            if updated == 0 {
+                return Err(anyhow::anyhow!("Player {player_id} not found"));
+            }
+            crate::util::access::forget(*player_id, &state.redis).await;
 
+            info!("Changed account type of player {player_id} to {account_type:?}");
             Ok(())
         }
-        Command::RefreshAllSkillPoints => {
+        Command::BanPlayer {
+            player_id,
+            reason,
+            days,
+        } => ban_player(&state, *player_id, reason, *days).await,
+        Command::UnbanPlayer { player_id } => {
             use crate::{models::players::Player, schema::players};
 
             let mut conn = state.db.get().await?;
 
-            let all_players = players::table.load::<Player>(&mut conn).await?;
-
-            for player in all_players {
-                let skill_points = player.calc_skill_points(&mut conn).await?;
-                let _: () = state
-                    .redis
-                    .zadd(
-                        "leaderboard",
-                        None,
-                        None,
-                        false,
-                        false,
-                        (skill_points.into(), player.id),
-                    )
-                    .await?;
+            let player: Player = players::table
+                .find(*player_id)
+                .first(&mut conn)
+                .await
+                .optional()?
+                .ok_or_else(|| anyhow::anyhow!("Player {player_id} not found"))?;
+            if !player.lift_bans(None, &mut conn).await? {
+                return Err(anyhow::anyhow!("Player {player_id} isn't banned"));
             }
+            crate::util::access::forget(player.id, &state.redis).await;
+
+            info!("Lifted the bans of player {player_id}");
+            Ok(())
+        }
+        Command::FreezeRegistrations { frozen } => {
+            crate::util::registrations::set_frozen(*frozen, &state.redis).await?;
 
+            info!("{} registrations", if *frozen { "Froze" } else { "Opened" });
             Ok(())
         }
+        Command::SyncSearch => {
+            let meili = state
+                .metadata
+                .meili
+                .as_ref()
+                .ok_or_else(|| anyhow::anyhow!("Meilisearch isn't configured"))?;
+
+            let mut conn = state.db.get().await?;
+
+            let started_at = time::OffsetDateTime::now_utc();
+            let synced = crate::util::meilisearch::sync_songs(meili, &mut conn, None).await?;
+            info!("Sent {synced} songs to Meilisearch");
+            crate::util::meilisearch::record_sync(started_at, &state.redis).await?;
+            Ok(())
+        }
+        Command::MigrationStatus => migration_status(&state).await,
+        Command::InspectCgr { path } => inspect_cgr(path),
+        Command::Doctor => doctor::run(&state).await,
+    }
+}
+
+/// Logs a CGR file's chunk tree and metadata.
+fn inspect_cgr(path: &Path) -> anyhow::Result<()> {
+    use anyhow::Context;
+
+    use crate::util::cgr::CgrFile;
+
+    let data = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let cgr = CgrFile::parse(&data)
+        .with_context(|| format!("{} isn't a valid CGR file", path.display()))?;
+
+    let metadata = cgr.metadata();
+    info!(
+        "{}: {} bytes{}",
+        path.display(),
+        data.len(),
+        if cgr.is_compressed() {
+            ", compressed"
+        } else {
+            ""
+        }
+    );
+    info!(
+        "Title: {}, artist: {}, length: {}",
+        metadata.title.as_deref().unwrap_or("unknown"),
+        metadata.artist.as_deref().unwrap_or("unknown"),
+        metadata
+            .length
+            .map_or_else(|| "unknown".to_owned(), |length| format!("{length:.1?}"))
+    );
+    info!("Chunks:\n{}", cgr.root());
+
+    if cgr.to_bytes() != data {
+        warn!("Writing the file back wouldn't give the same bytes, editing it would break it");
+    }
+    Ok(())
+}
+
+/// Bans a player, ends all their sessions and logs what was done.
+async fn ban_player(
+    state: &AppState,
+    player_id: i32,
+    reason: &str,
+    days: Option<u32>,
+) -> anyhow::Result<()> {
+    use anyhow::anyhow;
+    use validator::Validate;
+
+    use crate::{
+        models::{bans::NewBan, players::Player},
+        schema::players,
+        util::{access, session::revoke_all_sessions},
+    };
+
+    let mut conn = state.db.get().await?;
+
+    let player: Player = players::table
+        .find(player_id)
+        .first(&mut conn)
+        .await
+        .optional()?
+        .ok_or_else(|| anyhow!("Player {player_id} not found"))?;
+
+    let new_ban = NewBan {
+        reason: reason.to_owned(),
+        expires_at: days
+            .map(|days| time::OffsetDateTime::now_utc() + time::Duration::days(days.into())),
+    };
+    new_ban.validate()?;
+    let ban = new_ban.insert(player.id, None, &mut conn).await?;
+    access::forget(player.id, &state.redis).await;
+    let revoked = revoke_all_sessions(player.id, &state.redis).await?;
+
+    match ban.expires_at {
+        Some(expires_at) => info!(
+            "Banned player {} ({}) until {}, revoked {} session(s). Reason: {}",
+            player.id, player.username, expires_at, revoked, ban.reason
+        ),
+        None => info!(
+            "Banned player {} ({}) permanently, revoked {} session(s). Reason: {}",
+            player.id, player.username, revoked, ban.reason
+        ),
+    }
+
+    Ok(())
+}
+
+/// How a song fared in `backfill_metadata`
+#[derive(Debug, Default, PartialEq, Eq)]
+struct BackfillReport {
+    matched: u32,
+    unmatched: u32,
+    failed: u32,
+    /// Songs without scores, so there's no song length to search with
+    skipped: u32,
+}
+
+impl BackfillReport {
+    fn rows(&self) -> [(&'static str, u32); 5] {
+        [
+            ("Matched", self.matched),
+            ("Not on MusicBrainz", self.unmatched),
+            ("Failed", self.failed),
+            ("Skipped (no scores)", self.skipped),
+            (
+                "Total",
+                self.matched + self.unmatched + self.failed + self.skipped,
+            ),
+        ]
+    }
+}
+
+/// Looks up metadata for songs without extra info, oldest first, and logs a summary.
+/// Failed lookups are logged and skipped. Running this again retries them.
+async fn backfill_metadata(
+    state: &AppState,
+    limit: Option<u32>,
+    delay: std::time::Duration,
+) -> anyhow::Result<()> {
+    use crate::{
+        models::songs::Song,
+        schema::{extra_song_info, scores, songs},
+    };
+
+    let mut conn = state.db.get().await?;
+
+    let mut query = songs::table
+        .left_join(extra_song_info::table)
+        .filter(extra_song_info::id.is_null())
+        .select(Song::as_select())
+        .order(songs::id.asc())
+        .into_boxed();
+    if let Some(limit) = limit {
+        query = query.limit(i64::from(limit));
+    }
+    let missing: Vec<Song> = query.load(&mut conn).await?;
+    info!("{} song(s) without metadata", missing.len());
+
+    let mut report = BackfillReport::default();
+    for song in missing {
+        let song_length: Option<i32> = scores::table
+            .filter(scores::song_id.eq(song.id))
+            .order(scores::score.desc())
+            .select(scores::song_length)
+            .first(&mut conn)
+            .await
+            .optional()?;
+        let Some(song_length) = song_length else {
+            report.skipped += 1;
+            continue;
+        };
+
+        // Scores store the length in centiseconds, MusicBrainz wants milliseconds
+        match song
+            .auto_add_metadata(song_length * 10, None, &mut conn)
+            .await
+        {
+            Ok(true) => report.matched += 1,
+            Ok(false) => report.unmatched += 1,
+            Err(e) => {
+                warn!(
+                    "Metadata lookup for song {} ({} - {}) failed: {:#}",
+                    song.id, song.artist, song.title, e
+                );
+                report.failed += 1;
+            }
+        }
+
+        tokio::time::sleep(delay).await;
+    }
+
+    info!("Metadata backfill done:");
+    for (outcome, count) in report.rows() {
+        info!("  {outcome:<20} {count:>6}");
+    }
+    Ok(())
+}
+
+/// Rows normalized at once
+const NORMALIZE_BATCH_SIZE: i64 = 1000;
+
+/// Recomputes everything that's stored normalized and counts duplicate songs.
+async fn normalize_songs(state: &AppState) -> anyhow::Result<()> {
+    use crate::{
+        models::{extra_song_info::ExtraSongInfo, songs::Song},
+        schema::{extra_song_info, songs},
+        util::normalize::normalize,
+    };
+
+    let mut conn = state.db.get().await?;
+
+    let mut last_id = 0;
+    let mut songs_changed = 0;
+    loop {
+        let batch: Vec<Song> = songs::table
+            .filter(songs::id.gt(last_id))
+            .select(Song::as_select())
+            .order(songs::id.asc())
+            .limit(NORMALIZE_BATCH_SIZE)
+            .load(&mut conn)
+            .await?;
+        let Some(last) = batch.last() else {
+            break;
+        };
+        last_id = last.id;
+
+        for song in batch {
+            let title = normalize(&song.title);
+            let artist = normalize(&song.artist);
+            if title != song.title_normalized || artist != song.artist_normalized {
+                diesel::update(&song)
+                    .set((
+                        songs::title_normalized.eq(title),
+                        songs::artist_normalized.eq(artist),
+                    ))
+                    .execute(&mut conn)
+                    .await?;
+                songs_changed += 1;
+            }
+        }
+    }
+    info!("Normalized {songs_changed} song(s)");
+
+    let mut last_id = 0;
+    let mut extra_infos_changed = 0;
+    loop {
+        let batch: Vec<ExtraSongInfo> = extra_song_info::table
+            .filter(extra_song_info::id.gt(last_id))
+            .select(ExtraSongInfo::as_select())
+            .order(extra_song_info::id.asc())
+            .limit(NORMALIZE_BATCH_SIZE)
+            .load(&mut conn)
+            .await?;
+        let Some(last) = batch.last() else {
+            break;
+        };
+        last_id = last.id;
+
+        for extra_info in batch {
+            let title = extra_info.musicbrainz_title.as_deref().map(normalize);
+            let artist = extra_info.musicbrainz_artist.as_deref().map(normalize);
+            let aliases_title = extra_info.aliases_title.as_deref().map(normalize_aliases);
+            let aliases_artist = extra_info.aliases_artist.as_deref().map(normalize_aliases);
+            if title != extra_info.musicbrainz_title_normalized
+                || artist != extra_info.musicbrainz_artist_normalized
+                || aliases_title != extra_info.aliases_title
+                || aliases_artist != extra_info.aliases_artist
+            {
+                diesel::update(&extra_info)
+                    .set((
+                        extra_song_info::musicbrainz_title_normalized.eq(title),
+                        extra_song_info::musicbrainz_artist_normalized.eq(artist),
+                        extra_song_info::aliases_title.eq(aliases_title),
+                        extra_song_info::aliases_artist.eq(aliases_artist),
+                    ))
+                    .execute(&mut conn)
+                    .await?;
+                extra_infos_changed += 1;
+            }
+        }
+    }
+    info!("Normalized the extra info of {extra_infos_changed} song(s)");
+
+    let duplicates = Song::count_duplicate_groups(&mut conn).await?;
+    if duplicates > 0 {
+        info!("{duplicates} group(s) of duplicate songs, list them with find-duplicates");
+    } else {
+        info!("No duplicate songs");
+    }
+    Ok(())
+}
+
+/// Logs groups of duplicate songs, each with the commands that merge it.
+async fn find_duplicates(state: &AppState, limit: Option<u32>) -> anyhow::Result<()> {
+    use crate::models::songs::Song;
+
+    let mut conn = state.db.get().await?;
+
+    let total = Song::count_duplicate_groups(&mut conn).await?;
+    let groups = Song::duplicate_groups(limit.map(i64::from), 0, &mut conn).await?;
+    info!(
+        "{total} group(s) of duplicate songs, showing {}:",
+        groups.len()
+    );
+    for group in groups {
+        let Some((target, others)) = group.split_first() else {
+            continue;
+        };
+        info!(
+            "  {} - {}, modifiers {:?}",
+            target.song.artist, target.song.title, target.song.modifiers
+        );
+        for member in &group {
+            info!(
+                "    song {:>6}: {:>5} score(s), created {}",
+                member.song.id, member.score_count, member.song.created_at
+            );
+        }
+        for other in others {
+            info!("    merge-songs {} {} false", other.song.id, target.song.id);
+        }
     }
+    Ok(())
+}
+
+/// Normalizes aliases, dropping the ones that become the same.
+fn normalize_aliases(aliases: &[Option<String>]) -> Vec<Option<String>> {
+    use crate::util::normalize::normalize;
+
+    let mut normalized: Vec<Option<String>> = Vec::with_capacity(aliases.len());
+    for alias in aliases {
+        let alias = alias.as_deref().map(normalize);
+        if !normalized.contains(&alias) {
+            normalized.push(alias);
+        }
+    }
+    normalized
+}
+
+/// Songs whose covers are cached at once
+const COVER_BATCH_SIZE: i64 = 100;
+
+/// Caches the covers of all songs that have cover URLs without a cached file.
+/// Covers that fail to download are skipped, running this again retries them.
+async fn cache_covers(state: &AppState, delay: std::time::Duration) -> anyhow::Result<()> {
+    use crate::{
+        models::extra_song_info::ExtraSongInfo,
+        schema::extra_song_info,
+        util::covers::{self, CachedCovers},
+    };
+
+    if !covers::caching_enabled() {
+        return Err(anyhow::anyhow!(
+            "Cover caching is turned off with cache_covers in [media]"
+        ));
+    }
+
+    let mut conn = state.db.get().await?;
+
+    let mut last_id = 0;
+    let (mut cached, mut failed) = (0, 0);
+    loop {
+        let batch: Vec<ExtraSongInfo> = extra_song_info::table
+            .filter(extra_song_info::id.gt(last_id))
+            .filter(
+                extra_song_info::cover_url
+                    .is_not_null()
+                    .and(extra_song_info::cover_file.is_null())
+                    .or(extra_song_info::cover_url_small
+                        .is_not_null()
+                        .and(extra_song_info::cover_file_small.is_null())),
+            )
+            .order(extra_song_info::id.asc())
+            .limit(COVER_BATCH_SIZE)
+            .load(&mut conn)
+            .await?;
+        let Some(last) = batch.last() else {
+            break;
+        };
+        last_id = last.id;
+
+        for extra_info in batch {
+            let song_id = extra_info.song_id;
+            // Only the missing ones, the others are cached already
+            let new_covers = CachedCovers::fetch(
+                extra_info
+                    .cover_url
+                    .as_deref()
+                    .filter(|_| extra_info.cover_file.is_none()),
+                extra_info
+                    .cover_url_small
+                    .as_deref()
+                    .filter(|_| extra_info.cover_file_small.is_none()),
+            )
+            .await;
+            let saved = new_covers.save(extra_info, &mut conn).await?;
+            if saved.cover_url.is_some() == saved.cover_file.is_some()
+                && saved.cover_url_small.is_some() == saved.cover_file_small.is_some()
+            {
+                cached += 1;
+            } else {
+                warn!("Couldn't cache all covers of song {song_id}");
+                failed += 1;
+            }
+
+            tokio::time::sleep(delay).await;
+        }
+        info!("Cached covers of {cached} song(s) so far, {failed} failed");
+    }
+
+    info!("Cached covers of {cached} song(s), {failed} failed");
+    Ok(())
+}
+
+/// Logs which migrations were already applied and which are still pending.
+async fn migration_status(state: &AppState) -> anyhow::Result<()> {
+    let (applied, pending) = migration_versions(state).await?;
+
+    info!("{} applied migrations:", applied.len());
+    for version in applied {
+        info!("  {version}");
+    }
+    info!("{} pending migrations:", pending.len());
+    for name in pending {
+        info!("  {name}");
+    }
+
+    Ok(())
+}
+
+/// Versions of the applied migrations and names of the pending ones, in that order.
+async fn migration_versions(state: &AppState) -> anyhow::Result<(Vec<String>, Vec<String>)> {
+    use anyhow::{anyhow, Context};
+    use diesel::Connection;
+    use diesel_async::{async_connection_wrapper::AsyncConnectionWrapper, AsyncPgConnection};
+    use diesel_migrations::MigrationHarness;
+
+    let pg_url = state.config.main.database.clone();
+    tokio::task::spawn_blocking(move || {
+        let mut conn = AsyncConnectionWrapper::<AsyncPgConnection>::establish(&pg_url)
+            .context("Failed to establish DB connection for migrations!")?;
+
+        let applied = conn
+            .applied_migrations()
+            .map_err(|e| anyhow!(e))?
+            .into_iter()
+            .map(|version| version.to_string())
+            .collect();
+        let pending = conn
+            .pending_migrations(crate::MIGRATIONS)
+            .map_err(|e| anyhow!(e))?
+            .into_iter()
+            .map(|migration| migration.name().to_string())
+            .collect();
+
+        Ok((applied, pending))
+    })
+    .await?
+}
+
+/// Number of players whose skill points are recalculated at once
+const REFRESH_BATCH_SIZE: i64 = 500;
+/// Sorted set that is filled during a refresh, before replacing the real leaderboard of `league`
+fn refresh_temp_key(league: Option<League>) -> String {
+    format!("{}:refresh:tmp", leaderboard_key(league))
+}
+
+/// The global leaderboard and the one of every league
+fn all_leaderboards() -> impl Iterator<Item = Option<League>> {
+    [None].into_iter().chain(League::ALL.map(Some))
+}
+/// Hash with the progress of an unfinished refresh (last player ID, players and points so far)
+const REFRESH_PROGRESS_KEY: &str = "leaderboard:refresh:progress";
+/// Set by the first refresh that built the per-league leaderboards, which older versions didn't keep
+const LEAGUE_LEADERBOARDS_BUILT_KEY: &str = "leaderboard:leagues_built";
+/// Held by the instance that builds the per-league leaderboards on startup, so only one of them does
+const LEAGUE_BACKFILL_LOCK_KEY: &str = "leaderboard:leagues_backfill:lock";
+/// Long enough for a refresh of a big database, short enough that a crashed instance doesn't block it for long
+const LEAGUE_BACKFILL_LOCK_SECS: i64 = 60 * 60;
+
+/// Rebuilds the leaderboards if the per-league ones were never built,
+/// since they'd otherwise only have the players who submitted a score since upgrading.
+/// Meant to run in the background on startup.
+///
+/// # Errors
+/// Fails if something goes wrong with the DB or Redis.
+pub async fn backfill_league_leaderboards(state: &AppState) -> anyhow::Result<()> {
+    let built: bool = state.redis.exists(LEAGUE_LEADERBOARDS_BUILT_KEY).await?;
+    if built {
+        return Ok(());
+    }
+    let locked: Option<String> = state
+        .redis
+        .set(
+            LEAGUE_BACKFILL_LOCK_KEY,
+            1,
+            Some(Expiration::EX(LEAGUE_BACKFILL_LOCK_SECS)),
+            Some(SetOptions::NX),
+            false,
+        )
+        .await?;
+    if locked.is_none() {
+        info!("Another instance is building the per-league leaderboards");
+        return Ok(());
+    }
+
+    info!("Per-league leaderboards were never built, rebuilding all leaderboards");
+    let result = refresh_all_skill_points(state, false).await;
+    let _: () = state.redis.del(LEAGUE_BACKFILL_LOCK_KEY).await?;
+    result
+}
+
+/// Recalculates every player's skill points in batches and rebuilds the global and per-league leaderboards from them.
+///
+/// The new values are written to temporary sorted sets, which only replace the leaderboards once every player is done.
+/// Afterwards, the leaderboards count as built with the configured `SkillPointPolicy`.
+/// Progress is stored in Redis, so if this gets interrupted, running it again continues where it stopped.
+/// Scores submitted while this runs may not be reflected in the result.
+///
+/// Decayed players get their decay applied to the new values, unless `lift_decay` is set, which ends everyone's decay first.
+async fn refresh_all_skill_points(state: &AppState, lift_decay: bool) -> anyhow::Result<()> {
+    use std::collections::HashMap;
+
+    use crate::{
+        models::{
+            players::Player,
+            skill_point_decay::{decay_points, SkillPointDecay},
+        },
+        schema::players,
+    };
+
+    let mut conn = state.db.get().await?;
+
+    if lift_decay {
+        let lifted = SkillPointDecay::lift_all(&mut conn).await?;
+        info!("Lifted the skill point decay of {lifted} players");
+    }
+
+    let progress: HashMap<String, i64> = state.redis.hgetall(REFRESH_PROGRESS_KEY).await?;
+    let mut last_id = progress.get("last_id").copied().unwrap_or(0);
+    let mut players_done = progress.get("players").copied().unwrap_or(0);
+    let mut points_total = progress.get("points").copied().unwrap_or(0);
+    if last_id > 0 {
+        info!("Resuming skill point refresh after player {last_id} ({players_done} players done)");
+    } else {
+        // Leftovers from an earlier run without progress info would poison the new leaderboards
+        for league in all_leaderboards() {
+            let _: () = state.redis.del(refresh_temp_key(league)).await?;
+        }
+    }
+
+    let total_players: i64 = players::table.count().get_result(&mut conn).await?;
+
+    loop {
+        let batch: Vec<i32> = players::table
+            .filter(players::id.gt(i32::try_from(last_id)?))
+            .order(players::id.asc())
+            .limit(REFRESH_BATCH_SIZE)
+            .select(players::id)
+            .load(&mut conn)
+            .await?;
+        let Some(&batch_last_id) = batch.last() else {
+            break;
+        };
+
+        let calculated =
+            Player::calc_skill_points_batch(&batch, &state.config.skill_points, &mut conn).await?;
+        let multipliers = SkillPointDecay::multipliers(&batch, &mut conn).await?;
+        let mut totals: HashMap<i32, i64> = HashMap::new();
+        let mut league_entries: HashMap<League, Vec<(f64, i32)>> = HashMap::new();
+        for (player_id, league, points) in calculated {
+            // Same as the decay task would put on the leaderboards
+            let points = decay_points(points, multipliers.get(&player_id).copied());
+            *totals.entry(player_id).or_default() += points;
+            #[allow(clippy::cast_precision_loss)]
+            league_entries
+                .entry(league)
+                .or_default()
+                .push((points as f64, player_id));
+        }
+        // Players without scores still need to be on the global leaderboard
+        #[allow(clippy::cast_precision_loss)]
+        let entries: Vec<(f64, i32)> = batch
+            .iter()
+            .map(|id| (totals.get(id).copied().unwrap_or(0) as f64, *id))
+            .collect();
+        let batch_points: i64 = totals.values().sum();
+
+        // Write the batch and the progress together, so a rerun never counts a batch twice
+        let trx = state.redis.next().multi();
+        let _: () = trx
+            .zadd(refresh_temp_key(None), None, None, false, false, entries)
+            .await?;
+        for (league, entries) in league_entries {
+            let _: () = trx
+                .zadd(
+                    refresh_temp_key(Some(league)),
+                    None,
+                    None,
+                    false,
+                    false,
+                    entries,
+                )
+                .await?;
+        }
+        let _: () = trx
+            .hset(
+                REFRESH_PROGRESS_KEY,
+                [("last_id", i64::from(batch_last_id))],
+            )
+            .await?;
+        let _: () = trx
+            .hincrby(REFRESH_PROGRESS_KEY, "players", i64::try_from(batch.len())?)
+            .await?;
+        let _: () = trx
+            .hincrby(REFRESH_PROGRESS_KEY, "points", batch_points)
+            .await?;
+        let _: () = trx.exec(true).await?;
+
+        last_id = i64::from(batch_last_id);
+        players_done += i64::try_from(batch.len())?;
+        points_total += batch_points;
+        info!("Refreshed skill points of {players_done}/{total_players} players");
+    }
+
+    for league in all_leaderboards() {
+        // RENAME fails if the temporary set doesn't exist, which happens when nobody has points there
+        let filled: bool = state.redis.exists(refresh_temp_key(league)).await?;
+        if filled {
+            let _: () = state
+                .redis
+                .rename(refresh_temp_key(league), leaderboard_key(league))
+                .await?;
+        } else {
+            let _: () = state.redis.del(leaderboard_key(league)).await?;
+        }
+    }
+    let _: () = state.redis.del(REFRESH_PROGRESS_KEY).await?;
+    let _: () = state
+        .redis
+        .set(LEAGUE_LEADERBOARDS_BUILT_KEY, 1, None, None, false)
+        .await?;
+    skill_points::store_version(&state.config.skill_points, &state.redis).await?;
+
+    info!(
+        "Skill point refresh done: {points_total} points distributed across {players_done} players, \
+        using skill point policy version {}",
+        state.config.skill_points.version
+    );
+
+    Ok(())
 }
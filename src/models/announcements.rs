@@ -0,0 +1,208 @@
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use utoipa::ToSchema;
+use validator::{Validate, ValidationError};
+
+use super::radio_entries::deserialize_changed_time;
+use crate::schema::announcements;
+
+/// Server news, shown on the website and, the newest one, in game before playing a song.
+#[derive(Identifiable, Selectable, Queryable, Debug, Serialize, ToSchema)]
+#[diesel(table_name = announcements, check_for_backend(diesel::pg::Pg))]
+#[diesel(primary_key(id))]
+#[serde(rename_all = "camelCase")]
+pub struct Announcement {
+    pub id: i32,
+    pub title: String,
+    pub body: String,
+    /// Not shown before this, so announcements can be prepared in advance
+    #[serde(serialize_with = "time::serde::iso8601::serialize")]
+    pub published_at: OffsetDateTime,
+    /// Not shown anymore from this on
+    #[serde(with = "time::serde::iso8601::option")]
+    pub expires_at: Option<OffsetDateTime>,
+    /// Pinned announcements come before all others
+    pub pinned: bool,
+}
+
+impl Announcement {
+    /// The announcements that are shown at `now`, pinned ones first, then the newest.
+    ///
+    /// # Errors
+    /// Fails if something is wrong with the DB.
+    pub async fn shown_at(
+        now: OffsetDateTime,
+        limit: i64,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Vec<Self>> {
+        announcements::table
+            .filter(announcements::published_at.le(now))
+            .filter(
+                announcements::expires_at
+                    .is_null()
+                    .or(announcements::expires_at.gt(now)),
+            )
+            .order((
+                announcements::pinned.desc(),
+                announcements::published_at.desc(),
+                announcements::id.desc(),
+            ))
+            .limit(limit)
+            .select(Self::as_select())
+            .load(conn)
+            .await
+    }
+
+    /// All announcements, including expired and upcoming ones, newest first.
+    ///
+    /// # Errors
+    /// Fails if something is wrong with the DB.
+    pub async fn all(limit: i64, conn: &mut AsyncPgConnection) -> QueryResult<Vec<Self>> {
+        announcements::table
+            .order((announcements::published_at.desc(), announcements::id.desc()))
+            .limit(limit)
+            .select(Self::as_select())
+            .load(conn)
+            .await
+    }
+
+    /// The announcement as the game shows it, cut off after `max_chars` characters
+    /// so it fits into the text box.
+    #[must_use]
+    pub fn game_text(&self, max_chars: usize) -> String {
+        let text = format!("{}\n\n{}", self.title, self.body);
+        if text.chars().count() <= max_chars {
+            return text;
+        }
+
+        // The game can't show an ellipsis character
+        let end = text
+            .char_indices()
+            .nth(max_chars.saturating_sub(3))
+            .map_or(text.len(), |(end, _)| end);
+        format!("{}...", text[..end].trim_end())
+    }
+}
+
+#[derive(Insertable, Deserialize, Validate, ToSchema)]
+#[diesel(table_name = announcements)]
+#[serde(rename_all = "camelCase")]
+#[validate(schema(function = "validate_new_expiry"))]
+pub struct NewAnnouncement {
+    #[validate(length(min = 1, max = 200))]
+    pub title: String,
+    #[validate(length(min = 1, max = 5000))]
+    pub body: String,
+    /// Defaults to now
+    #[serde(default, with = "time::serde::iso8601::option")]
+    pub published_at: Option<OffsetDateTime>,
+    #[serde(default, with = "time::serde::iso8601::option")]
+    pub expires_at: Option<OffsetDateTime>,
+    pub pinned: Option<bool>,
+}
+
+/// Changes to an announcement. Fields that are left out stay as they are, `null` clears the expiry.
+#[derive(AsChangeset, Deserialize, Validate, ToSchema, Default)]
+#[diesel(table_name = announcements)]
+#[serde(rename_all = "camelCase")]
+pub struct AnnouncementUpdate {
+    #[validate(length(min = 1, max = 200))]
+    pub title: Option<String>,
+    #[validate(length(min = 1, max = 5000))]
+    pub body: Option<String>,
+    #[serde(default, with = "time::serde::iso8601::option")]
+    pub published_at: Option<OffsetDateTime>,
+    #[serde(default, deserialize_with = "deserialize_changed_time")]
+    pub expires_at: Option<Option<OffsetDateTime>>,
+    pub pinned: Option<bool>,
+}
+
+impl AnnouncementUpdate {
+    /// Whether there's nothing to change, since Diesel refuses to run an empty update.
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.title.is_none()
+            && self.body.is_none()
+            && self.published_at.is_none()
+            && self.expires_at.is_none()
+            && self.pinned.is_none()
+    }
+
+    /// When the announcement is shown from and until after this change.
+    #[must_use]
+    pub fn schedule_of(
+        &self,
+        announcement: &Announcement,
+    ) -> (OffsetDateTime, Option<OffsetDateTime>) {
+        (
+            self.published_at.unwrap_or(announcement.published_at),
+            self.expires_at.unwrap_or(announcement.expires_at),
+        )
+    }
+}
+
+/// An announcement can't expire before it's published.
+pub fn validate_expiry(
+    published_at: OffsetDateTime,
+    expires_at: Option<OffsetDateTime>,
+) -> Result<(), ValidationError> {
+    if expires_at.is_some_and(|expires_at| expires_at <= published_at) {
+        return Err(ValidationError::new("expiry")
+            .with_message("expiresAt has to be after publishedAt".into()));
+    }
+
+    Ok(())
+}
+
+fn validate_new_expiry(announcement: &NewAnnouncement) -> Result<(), ValidationError> {
+    validate_expiry(
+        announcement
+            .published_at
+            .unwrap_or_else(OffsetDateTime::now_utc),
+        announcement.expires_at,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn announcement(body: &str) -> Announcement {
+        Announcement {
+            id: 1,
+            title: "Maintenance".to_owned(),
+            body: body.to_owned(),
+            published_at: OffsetDateTime::UNIX_EPOCH,
+            expires_at: None,
+            pinned: false,
+        }
+    }
+
+    #[test]
+    fn game_text_fits_into_the_text_box() {
+        let short = announcement("Back soon!");
+        assert_eq!(short.game_text(100), "Maintenance\n\nBack soon!");
+        assert_eq!(short.game_text(23), "Maintenance\n\nBack soon!");
+
+        let long = announcement("The server is down for maintenance tonight.");
+        assert_eq!(long.game_text(30), "Maintenance\n\nThe server is...");
+
+        let umlauts = announcement("Wartungsarbeiten f\u{00FC}r alle \u{00DC}bertragungen");
+        assert_eq!(
+            umlauts.game_text(35),
+            "Maintenance\n\nWartungsarbeiten f\u{00FC}..."
+        );
+    }
+
+    #[test]
+    fn announcements_expire_after_they_are_published() {
+        let now = OffsetDateTime::UNIX_EPOCH;
+        let day = time::Duration::days(1);
+        assert!(validate_expiry(now, None).is_ok());
+        assert!(validate_expiry(now, Some(now + day)).is_ok());
+        assert!(validate_expiry(now, Some(now)).is_err());
+        assert!(validate_expiry(now, Some(now - day)).is_err());
+    }
+}
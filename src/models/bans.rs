@@ -0,0 +1,253 @@
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use utoipa::ToSchema;
+use validator::{Validate, ValidationError};
+
+use crate::schema::bans;
+
+/// A ban keeping a player from using the game and API routes until it expires.
+#[derive(Identifiable, Selectable, Queryable, Debug, Clone, Serialize, ToSchema)]
+#[diesel(table_name = bans, check_for_backend(diesel::pg::Pg))]
+#[diesel(primary_key(id))]
+#[serde(rename_all = "camelCase")]
+pub struct Ban {
+    pub id: i32,
+    pub player_id: i32,
+    /// Shown to the banned player
+    pub reason: String,
+    /// ID of the moderator who issued the ban, not set for bans from the command line
+    pub issued_by: Option<i32>,
+    #[serde(serialize_with = "time::serde::iso8601::serialize")]
+    pub issued_at: OffsetDateTime,
+    /// The ban is permanent if this isn't set
+    #[serde(serialize_with = "time::serde::iso8601::option::serialize")]
+    pub expires_at: Option<OffsetDateTime>,
+    /// When the ban was lifted before it expired, lifted bans are kept for the record
+    #[serde(serialize_with = "time::serde::iso8601::option::serialize")]
+    pub lifted_at: Option<OffsetDateTime>,
+    /// ID of the moderator who lifted the ban, not set if it was lifted from the command line
+    pub lifted_by: Option<i32>,
+}
+
+impl Ban {
+    /// Gets the ban currently applying to a player, if any.
+    /// Expired and lifted bans are ignored, so they don't need to be cleaned up.
+    /// If several bans apply, the one lasting the longest is returned.
+    ///
+    /// # Errors
+    /// Fails if something is wrong with the database.
+    pub async fn find_active(
+        player_id: i32,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Option<Self>> {
+        bans::table
+            .filter(bans::player_id.eq(player_id))
+            .filter(bans::lifted_at.is_null())
+            .filter(
+                bans::expires_at
+                    .is_null()
+                    .or(bans::expires_at.gt(diesel::dsl::now)),
+            )
+            .order(bans::expires_at.desc().nulls_first())
+            .select(Self::as_select())
+            .first(conn)
+            .await
+            .optional()
+    }
+
+    /// Lifts all bans currently applying to a player. They're marked as lifted, not deleted.
+    /// `lifted_by` is the moderator's ID, or `None` if they're lifted from the command line.
+    ///
+    /// # Returns
+    /// The number of bans that were lifted.
+    ///
+    /// # Errors
+    /// Fails if something is wrong with the database.
+    pub async fn lift_all(
+        player_id: i32,
+        lifted_by: Option<i32>,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<usize> {
+        diesel::update(
+            bans::table
+                .filter(bans::player_id.eq(player_id))
+                .filter(bans::lifted_at.is_null())
+                .filter(
+                    bans::expires_at
+                        .is_null()
+                        .or(bans::expires_at.gt(diesel::dsl::now)),
+                ),
+        )
+        .set((
+            bans::lifted_at.eq(diesel::dsl::now),
+            bans::lifted_by.eq(lifted_by),
+        ))
+        .execute(conn)
+        .await
+    }
+
+    /// Message shown to the banned player, including the reason and expiry.
+    pub fn public_message(&self) -> String {
+        match self.expires_at {
+            Some(expires_at) => format!(
+                "Account is banned until {}: {}",
+                expires_at
+                    .format(&time::format_description::well_known::Rfc3339)
+                    .unwrap_or_default(),
+                self.reason
+            ),
+            None => format!("Account is banned: {}", self.reason),
+        }
+    }
+}
+
+/// A ban issued by a moderator.
+#[derive(Deserialize, Validate, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct NewBan {
+    #[validate(length(min = 1, max = 500))]
+    pub reason: String,
+    /// Leave out for a permanent ban
+    #[serde(default, with = "time::serde::iso8601::option")]
+    #[validate(custom(function = "validate_expiry"))]
+    pub expires_at: Option<OffsetDateTime>,
+}
+
+fn validate_expiry(expires_at: &OffsetDateTime) -> Result<(), ValidationError> {
+    if *expires_at <= OffsetDateTime::now_utc() {
+        return Err(
+            ValidationError::new("expires_at").with_message("Expiry must be in the future".into())
+        );
+    }
+
+    Ok(())
+}
+
+impl NewBan {
+    /// Bans a player. `issued_by` is the moderator's ID, or `None` if the ban came from the command line.
+    ///
+    /// # Errors
+    /// Fails if something is wrong with the database.
+    pub async fn insert(
+        &self,
+        player_id: i32,
+        issued_by: Option<i32>,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Ban> {
+        diesel::insert_into(bans::table)
+            .values((
+                bans::player_id.eq(player_id),
+                bans::reason.eq(&self.reason),
+                bans::issued_by.eq(issued_by),
+                bans::expires_at.eq(self.expires_at),
+            ))
+            .returning(Ban::as_returning())
+            .get_result(conn)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use time::Duration;
+
+    use super::*;
+    use crate::{
+        models::players::{AccountType, Player},
+        schema::players,
+        util::testing,
+    };
+
+    fn ban(expires_at: Option<OffsetDateTime>) -> Ban {
+        Ban {
+            id: 1,
+            player_id: 2,
+            reason: "Cheating".to_owned(),
+            issued_by: Some(3),
+            issued_at: OffsetDateTime::UNIX_EPOCH,
+            expires_at,
+            lifted_at: None,
+            lifted_by: None,
+        }
+    }
+
+    #[test]
+    fn message_includes_reason_and_expiry() {
+        assert_eq!(
+            ban(Some(OffsetDateTime::UNIX_EPOCH + Duration::hours(36))).public_message(),
+            "Account is banned until 1970-01-02T12:00:00Z: Cheating"
+        );
+        assert_eq!(ban(None).public_message(), "Account is banned: Cheating");
+    }
+
+    #[tokio::test]
+    async fn lifted_bans_are_kept_but_dont_apply() {
+        let Some(mut conn) = testing::db().await else {
+            return;
+        };
+        let player_id = testing::insert_player(1, &mut conn).await;
+        let moderator_id = testing::insert_player(2, &mut conn).await;
+        let new_ban = NewBan {
+            reason: "Cheating".to_owned(),
+            expires_at: None,
+        };
+        new_ban
+            .insert(player_id, Some(moderator_id), &mut conn)
+            .await
+            .unwrap();
+        assert!(Ban::find_active(player_id, &mut conn)
+            .await
+            .unwrap()
+            .is_some());
+
+        let lifted = Ban::lift_all(player_id, Some(moderator_id), &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(lifted, 1);
+        assert!(Ban::find_active(player_id, &mut conn)
+            .await
+            .unwrap()
+            .is_none());
+
+        let kept: Ban = bans::table
+            .filter(bans::player_id.eq(player_id))
+            .select(Ban::as_select())
+            .first(&mut conn)
+            .await
+            .unwrap();
+        assert!(kept.lifted_at.is_some());
+        assert_eq!(kept.lifted_by, Some(moderator_id));
+        // Nothing left to lift
+        assert_eq!(Ban::lift_all(player_id, None, &mut conn).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn lifting_bans_includes_the_account_type() {
+        let Some(mut conn) = testing::db().await else {
+            return;
+        };
+        let player_id = testing::insert_player(1, &mut conn).await;
+        let player: Player = diesel::update(players::table.find(player_id))
+            .set(players::account_type.eq(AccountType::Banned))
+            .returning(Player::as_returning())
+            .get_result(&mut conn)
+            .await
+            .unwrap();
+        assert!(player.active_ban(&mut conn).await.unwrap().is_some());
+
+        assert!(player.lift_bans(None, &mut conn).await.unwrap());
+        let player: Player = players::table
+            .find(player_id)
+            .select(Player::as_select())
+            .first(&mut conn)
+            .await
+            .unwrap();
+        assert_eq!(player.account_type, AccountType::User);
+        assert!(player.active_ban(&mut conn).await.unwrap().is_none());
+
+        // A player who isn't banned has nothing to lift
+        assert!(!player.lift_bans(None, &mut conn).await.unwrap());
+    }
+}
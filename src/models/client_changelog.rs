@@ -0,0 +1,37 @@
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use validator::{Validate, ValidationError};
+
+use crate::{schema::client_changelog, util::version::ClientVersion};
+
+/// A changelog entry for a release of the client mod, shown in the mod's update prompt.
+#[derive(Identifiable, Selectable, Queryable, Debug, Serialize, ToSchema)]
+#[diesel(table_name = client_changelog, check_for_backend(diesel::pg::Pg))]
+#[diesel(primary_key(id))]
+#[serde(rename_all = "camelCase")]
+pub struct ChangelogEntry {
+    pub id: i32,
+    /// Client mod version this entry is about
+    pub version: String,
+    pub body: String,
+    #[serde(serialize_with = "time::serde::iso8601::serialize")]
+    pub published_at: time::OffsetDateTime,
+}
+
+#[derive(Insertable, Deserialize, Validate, ToSchema)]
+#[diesel(table_name = client_changelog)]
+#[serde(rename_all = "camelCase")]
+pub struct NewChangelogEntry {
+    #[validate(custom(function = "validate_version"))]
+    pub version: String,
+    #[validate(length(min = 1, max = 5000))]
+    pub body: String,
+}
+
+fn validate_version(version: &str) -> Result<(), ValidationError> {
+    version
+        .parse::<ClientVersion>()
+        .map(|_| ())
+        .map_err(|_| ValidationError::new("version").with_message("Invalid version".into()))
+}
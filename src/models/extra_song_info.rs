@@ -4,7 +4,10 @@ use serde::{Deserialize, Serialize};
 use serde_with::skip_serializing_none;
 use utoipa::ToSchema;
 
-use crate::schema::extra_song_info;
+use crate::{
+    schema::extra_song_info,
+    util::{covers, normalize::normalize},
+};
 
 /// Used for storing additional metadata from [MusicBrainz](https://musicbrainz.org).
 /// This lets us display fancy stuff™ on the song page.
@@ -15,6 +18,7 @@ use crate::schema::extra_song_info;
     Associations,
     PartialEq,
     Eq,
+    Clone,
     Debug,
     Serialize,
     Default,
@@ -24,7 +28,7 @@ use crate::schema::extra_song_info;
 #[diesel(belongs_to(super::songs::Song))]
 #[diesel(table_name = extra_song_info, check_for_backend(diesel::pg::Pg))]
 #[diesel(primary_key(id))]
-#[serde(rename_all = "camelCase")]
+#[serde(rename_all = "camelCase", into = "PublicExtraSongInfo")]
 pub struct ExtraSongInfo {
     pub id: i32,
     pub song_id: i32,
@@ -37,10 +41,197 @@ pub struct ExtraSongInfo {
     /// For songs that have been mistagged by the automatic lookup.
     /// A value of `true` prevents any new metadata lookups by title
     pub mistag_lock: bool,
-    /// Alternative artist tags that can be matched to this song
+    /// Alternative artist tags that can be matched to this song, normalized
     pub aliases_artist: Option<Vec<Option<String>>>,
-    /// Alternative title tags that can be matched to this song
+    /// Alternative title tags that can be matched to this song, normalized
     pub aliases_title: Option<Vec<Option<String>>>,
+    /// Our copy of the cover, see [`crate::util::covers`]
+    #[serde(skip)]
+    pub cover_file: Option<String>,
+    #[serde(skip)]
+    pub cover_file_small: Option<String>,
+    /// What songs are matched by, see [`crate::util::normalize`]
+    #[serde(skip)]
+    pub musicbrainz_title_normalized: Option<String>,
+    #[serde(skip)]
+    pub musicbrainz_artist_normalized: Option<String>,
+}
+
+/// What `ExtraSongInfo` is serialized as: cover URLs point at our copies where we have them.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PublicExtraSongInfo {
+    id: i32,
+    song_id: i32,
+    cover_url: Option<String>,
+    cover_url_small: Option<String>,
+    mbid: Option<String>,
+    musicbrainz_title: Option<String>,
+    musicbrainz_artist: Option<String>,
+    musicbrainz_length: Option<i32>,
+    mistag_lock: bool,
+    aliases_artist: Option<Vec<Option<String>>>,
+    aliases_title: Option<Vec<Option<String>>>,
+}
+
+impl From<ExtraSongInfo> for PublicExtraSongInfo {
+    fn from(info: ExtraSongInfo) -> Self {
+        Self {
+            id: info.id,
+            song_id: info.song_id,
+            cover_url: info.public_cover_url(),
+            cover_url_small: info.public_cover_url_small(),
+            mbid: info.mbid,
+            musicbrainz_title: info.musicbrainz_title,
+            musicbrainz_artist: info.musicbrainz_artist,
+            musicbrainz_length: info.musicbrainz_length,
+            mistag_lock: info.mistag_lock,
+            aliases_artist: info.aliases_artist,
+            aliases_title: info.aliases_title,
+        }
+    }
+}
+
+impl ExtraSongInfo {
+    /// URL of our copy of the cover if we have one, otherwise the Cover Art Archive's.
+    #[must_use]
+    pub fn public_cover_url(&self) -> Option<String> {
+        prefer_cached(self.cover_file.as_deref(), self.cover_url.as_deref())
+    }
+
+    /// Like [`Self::public_cover_url`], for the small cover.
+    #[must_use]
+    pub fn public_cover_url_small(&self) -> Option<String> {
+        prefer_cached(
+            self.cover_file_small.as_deref(),
+            self.cover_url_small.as_deref(),
+        )
+    }
+
+    /// Adds an alias for the song's title and/or artist, creating its extra info if it has none.
+    /// Aliases are stored normalized like the tags they're matched against, and ones that already exist aren't added again.
+    /// Call it in a transaction, otherwise concurrent edits can lose aliases.
+    ///
+    /// # Errors
+    /// Fails if something is wrong with the database.
+    pub async fn add_aliases(
+        song_id: i32,
+        title: Option<&str>,
+        artist: Option<&str>,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Self> {
+        let existing: Option<Self> = extra_song_info::table
+            .filter(extra_song_info::song_id.eq(song_id))
+            .for_update()
+            .first(conn)
+            .await
+            .optional()?;
+
+        let Some(mut extra_info) = existing else {
+            return NewExtraSongInfo {
+                song_id,
+                aliases_title: title.map(|title| vec![normalize(title)]),
+                aliases_artist: artist.map(|artist| vec![normalize(artist)]),
+                ..Default::default()
+            }
+            .insert(conn)
+            .await;
+        };
+
+        push_alias(&mut extra_info.aliases_title, title);
+        push_alias(&mut extra_info.aliases_artist, artist);
+        diesel::update(&extra_info)
+            .set((
+                extra_song_info::aliases_title.eq(&extra_info.aliases_title),
+                extra_song_info::aliases_artist.eq(&extra_info.aliases_artist),
+            ))
+            .get_result(conn)
+            .await
+    }
+
+    /// Removes an alias of the song's title or artist, compared normalized.
+    /// Does nothing if the song doesn't have that alias.
+    /// Call it in a transaction, otherwise concurrent edits can lose aliases.
+    ///
+    /// # Returns
+    /// The song's extra info, if it has any.
+    ///
+    /// # Errors
+    /// Fails if something is wrong with the database.
+    pub async fn remove_alias(
+        song_id: i32,
+        kind: AliasKind,
+        alias: &str,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Option<Self>> {
+        let existing: Option<Self> = extra_song_info::table
+            .filter(extra_song_info::song_id.eq(song_id))
+            .for_update()
+            .first(conn)
+            .await
+            .optional()?;
+        let Some(mut extra_info) = existing else {
+            return Ok(None);
+        };
+
+        let aliases = match kind {
+            AliasKind::Title => &mut extra_info.aliases_title,
+            AliasKind::Artist => &mut extra_info.aliases_artist,
+        };
+        if !drop_alias(aliases, alias) {
+            return Ok(Some(extra_info));
+        }
+
+        diesel::update(&extra_info)
+            .set((
+                extra_song_info::aliases_title.eq(&extra_info.aliases_title),
+                extra_song_info::aliases_artist.eq(&extra_info.aliases_artist),
+            ))
+            .get_result(conn)
+            .await
+            .map(Some)
+    }
+}
+
+fn prefer_cached(file: Option<&str>, remote_url: Option<&str>) -> Option<String> {
+    file.map_or_else(
+        || remote_url.map(str::to_owned),
+        |file| Some(covers::public_url(file)),
+    )
+}
+
+/// Which of a song's tags an alias stands in for
+#[derive(Deserialize, ToSchema, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum AliasKind {
+    Title,
+    Artist,
+}
+
+/// Removes the alias from the list, compared normalized. Returns whether it was in there.
+fn drop_alias(aliases: &mut Option<Vec<Option<String>>>, alias: &str) -> bool {
+    let Some(aliases) = aliases else {
+        return false;
+    };
+    let alias = normalize(alias);
+    let count = aliases.len();
+    aliases.retain(|existing| {
+        existing
+            .as_deref()
+            .is_none_or(|existing| normalize(existing) != alias)
+    });
+    aliases.len() != count
+}
+
+/// Adds the normalized alias to the list, unless it's already in there.
+fn push_alias(aliases: &mut Option<Vec<Option<String>>>, alias: Option<&str>) {
+    let Some(alias) = alias.map(normalize) else {
+        return;
+    };
+    let aliases = aliases.get_or_insert_with(Vec::new);
+    if !aliases.iter().flatten().any(|existing| *existing == alias) {
+        aliases.push(Some(alias));
+    }
 }
 
 /// Used for inserting additional metadata from [MusicBrainz](https://musicbrainz.org).
@@ -61,12 +252,17 @@ pub struct NewExtraSongInfo {
     pub musicbrainz_length: Option<i32>,
     pub aliases_title: Option<Vec<String>>,
     pub aliases_artist: Option<Vec<String>>,
+    #[serde(skip)]
+    pub musicbrainz_title_normalized: Option<String>,
+    #[serde(skip)]
+    pub musicbrainz_artist_normalized: Option<String>,
 }
 
 impl NewExtraSongInfo {
+    /// Aliases are normalized, and the normalized MusicBrainz title and artist filled in.
     #[must_use]
     #[allow(clippy::too_many_arguments)] // Too bad, I don't care!
-    pub const fn new(
+    pub fn new(
         song_id: i32,
         cover_url: Option<String>,
         cover_url_small: Option<String>,
@@ -77,16 +273,21 @@ impl NewExtraSongInfo {
         aliases_title: Option<Vec<String>>,
         aliases_artist: Option<Vec<String>>,
     ) -> Self {
+        let normalize_all = |aliases: Vec<String>| -> Vec<String> {
+            aliases.iter().map(|alias| normalize(alias)).collect()
+        };
         Self {
             song_id,
             cover_url,
             cover_url_small,
             mbid,
+            musicbrainz_title_normalized: musicbrainz_title.as_deref().map(normalize),
+            musicbrainz_artist_normalized: musicbrainz_artist.as_deref().map(normalize),
             musicbrainz_title,
             musicbrainz_artist,
             musicbrainz_length,
-            aliases_title,
-            aliases_artist,
+            aliases_title: aliases_title.map(normalize_all),
+            aliases_artist: aliases_artist.map(normalize_all),
         }
     }
 
@@ -104,3 +305,57 @@ impl NewExtraSongInfo {
             .await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aliases_are_normalized_and_not_repeated() {
+        let mut aliases = None;
+        push_alias(&mut aliases, Some("Dear Music"));
+        push_alias(&mut aliases, Some("dear music"));
+        push_alias(&mut aliases, None);
+        assert_eq!(aliases, Some(vec![Some("dear music".to_owned())]));
+
+        let mut aliases = Some(vec![None, Some("a4.".to_owned())]);
+        push_alias(&mut aliases, Some("A4"));
+        assert_eq!(
+            aliases,
+            Some(vec![None, Some("a4.".to_owned()), Some("a4".to_owned())])
+        );
+
+        let mut aliases = None;
+        push_alias(&mut aliases, Some("Simon & Garfunkel"));
+        push_alias(&mut aliases, Some("simon and garfunkel"));
+        assert_eq!(aliases, Some(vec![Some("simon and garfunkel".to_owned())]));
+    }
+
+    #[test]
+    fn alias_removal_is_normalized() {
+        let mut aliases = Some(vec![Some("dear music".to_owned()), None]);
+        assert!(drop_alias(&mut aliases, "Dear Music"));
+        assert_eq!(aliases, Some(vec![None]));
+        assert!(!drop_alias(&mut aliases, "dear music"));
+
+        assert!(!drop_alias(&mut None, "dear music"));
+    }
+
+    #[test]
+    fn cached_covers_replace_remote_ones() {
+        let info = ExtraSongInfo {
+            cover_url: Some("https://coverartarchive.org/release/a/1-500.jpg".to_owned()),
+            cover_url_small: Some("https://coverartarchive.org/release/a/1-250.jpg".to_owned()),
+            cover_file: Some("abc.jpg".to_owned()),
+            ..Default::default()
+        };
+        let json = serde_json::to_value(&info).unwrap();
+        assert_eq!(json["coverUrl"], "/api/media/covers/abc.jpg");
+        assert_eq!(
+            json["coverUrlSmall"],
+            "https://coverartarchive.org/release/a/1-250.jpg"
+        );
+        assert!(json.get("coverFile").is_none());
+        assert_eq!(json["mistagLock"], false);
+    }
+}
@@ -0,0 +1,79 @@
+use diesel::prelude::*;
+use diesel_async::{
+    scoped_futures::ScopedFutureExt, AsyncConnection, AsyncPgConnection, RunQueryDsl,
+};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use super::{players::Player, songs::Song};
+use crate::schema::player_favorite_songs;
+
+/// The maximum number of songs a player can pin to their profile.
+pub const MAX_FAVORITE_SONGS: usize = 5;
+
+/// A song pinned to a player's profile.
+#[derive(Identifiable, Selectable, Queryable, Associations, Debug, Serialize, ToSchema)]
+#[diesel(belongs_to(Player))]
+#[diesel(belongs_to(Song))]
+#[diesel(table_name = player_favorite_songs, check_for_backend(diesel::pg::Pg))]
+#[diesel(primary_key(player_id, song_id))]
+#[serde(rename_all = "camelCase")]
+pub struct FavoriteSong {
+    pub player_id: i32,
+    pub song_id: i32,
+    /// Zero-based position on the player's profile
+    pub position: i16,
+    #[serde(serialize_with = "time::serde::iso8601::serialize")]
+    pub created_at: time::OffsetDateTime,
+}
+
+impl FavoriteSong {
+    /// Replaces all of a player's favorite songs with a new list.
+    /// The order of `song_ids` determines the position of each song.
+    ///
+    /// # Errors
+    /// Fails if something is wrong with the database, e.g. if a song doesn't exist.
+    // song_ids is capped at MAX_FAVORITE_SONGS, so the position always fits
+    #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+    pub async fn replace_for_player(
+        player_id: i32,
+        song_ids: &[i32],
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Vec<Self>> {
+        let new_favorites = song_ids
+            .iter()
+            .enumerate()
+            .map(|(position, song_id)| NewFavoriteSong {
+                player_id,
+                song_id: *song_id,
+                position: position as i16,
+            })
+            .collect::<Vec<NewFavoriteSong>>();
+
+        conn.transaction(|conn| {
+            async move {
+                diesel::delete(
+                    player_favorite_songs::table
+                        .filter(player_favorite_songs::player_id.eq(player_id)),
+                )
+                .execute(conn)
+                .await?;
+
+                diesel::insert_into(player_favorite_songs::table)
+                    .values(&new_favorites)
+                    .get_results(conn)
+                    .await
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = player_favorite_songs)]
+pub struct NewFavoriteSong {
+    pub player_id: i32,
+    pub song_id: i32,
+    pub position: i16,
+}
@@ -0,0 +1,217 @@
+use std::time::Duration;
+
+use diesel::prelude::*;
+use diesel_async::{
+    scoped_futures::ScopedFutureExt, AsyncConnection, AsyncPgConnection, RunQueryDsl,
+};
+use fred::{clients::Pool as RedisPool, prelude::*};
+use tracing::{error, info, warn};
+
+use crate::{
+    schema::pending_leaderboard_deltas,
+    util::{
+        game_types::League,
+        leaderboard::{add_skill_points, GLOBAL_LEADERBOARD},
+    },
+};
+
+/// How often applying a delta to Redis is attempted before it gets queued in the database
+const APPLY_ATTEMPTS: u32 = 3;
+
+/// A change to a player's skill points that couldn't be applied to the Redis leaderboard yet,
+/// e.g. because Redis was unreachable when the score was submitted.
+#[derive(Identifiable, Selectable, Queryable, Debug)]
+#[diesel(table_name = pending_leaderboard_deltas, check_for_backend(diesel::pg::Pg))]
+#[diesel(primary_key(id))]
+pub struct PendingLeaderboardDelta {
+    pub id: i32,
+    pub player_id: i32,
+    pub delta: i32,
+    pub created_at: time::OffsetDateTime,
+    /// Not set for deltas queued before there were per-league leaderboards
+    pub league: Option<League>,
+}
+
+impl PendingLeaderboardDelta {
+    /// Adds `delta` to the player's skill points on the global leaderboard and the one of `league`, retrying a few times.
+    /// If Redis still can't be reached, the delta is stored in the database so `drain` can apply it later.
+    ///
+    /// # Errors
+    /// Fails only if Redis fails **and** the delta can't be stored in the database.
+    pub async fn apply_or_enqueue(
+        player_id: i32,
+        league: League,
+        delta: i32,
+        conn: &mut AsyncPgConnection,
+        redis_conn: &RedisPool,
+    ) -> QueryResult<()> {
+        for attempt in 1..=APPLY_ATTEMPTS {
+            match add_skill_points(player_id, league, delta, redis_conn).await {
+                Ok(()) => return Ok(()),
+                Err(e) => {
+                    warn!(
+                        "Failed to apply skill point delta {} to player {} (attempt {}/{}): {}",
+                        delta, player_id, attempt, APPLY_ATTEMPTS, e
+                    );
+                    if attempt < APPLY_ATTEMPTS {
+                        tokio::time::sleep(Duration::from_millis(100 * 2u64.pow(attempt))).await;
+                    }
+                }
+            }
+        }
+
+        error!(
+            "Queueing skill point delta {} of player {} until Redis is reachable again",
+            delta, player_id
+        );
+        diesel::insert_into(pending_leaderboard_deltas::table)
+            .values((
+                pending_leaderboard_deltas::player_id.eq(player_id),
+                pending_leaderboard_deltas::delta.eq(delta),
+                pending_leaderboard_deltas::league.eq(league),
+            ))
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Applies all queued deltas to the leaderboard, oldest first.
+    /// Each delta is only removed from the queue if applying it to Redis worked.
+    /// Deltas another drain already took off the queue are skipped, so none are applied twice.
+    ///
+    /// # Returns
+    /// The number of deltas that were applied.
+    ///
+    /// # Errors
+    /// Fails if something is wrong with the database or Redis. Deltas applied before the failure stay applied.
+    pub async fn drain(
+        conn: &mut AsyncPgConnection,
+        redis_conn: &RedisPool,
+    ) -> anyhow::Result<usize> {
+        let pending: Vec<i32> = pending_leaderboard_deltas::table
+            .select(pending_leaderboard_deltas::id)
+            .order(pending_leaderboard_deltas::id.asc())
+            .load(conn)
+            .await?;
+
+        let mut applied = 0;
+        for id in pending {
+            let was_applied = conn
+                .transaction::<_, anyhow::Error, _>(|conn| {
+                    async move {
+                        // Only the drain whose delete returns the row applies it
+                        let Some(pending_delta) =
+                            diesel::delete(pending_leaderboard_deltas::table.find(id))
+                                .returning(Self::as_returning())
+                                .get_result::<Self>(conn)
+                                .await
+                                .optional()?
+                        else {
+                            return Ok(false);
+                        };
+                        // If this fails, the transaction is rolled back and the delta stays queued
+                        pending_delta.apply(redis_conn).await?;
+                        Ok(true)
+                    }
+                    .scope_boxed()
+                })
+                .await?;
+            if was_applied {
+                applied += 1;
+            }
+        }
+
+        if applied > 0 {
+            info!("Applied {} queued skill point deltas", applied);
+        }
+
+        Ok(applied)
+    }
+
+    /// Adds the delta to the leaderboards it belongs on
+    async fn apply(&self, redis_conn: &RedisPool) -> anyhow::Result<()> {
+        if let Some(league) = self.league {
+            add_skill_points(self.player_id, league, self.delta, redis_conn).await
+        } else {
+            let _: () = redis_conn
+                .zincrby(GLOBAL_LEADERBOARD, self.delta.into(), self.player_id)
+                .await?;
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::util::testing::{self, Outage, RecordedRedis};
+
+    #[tokio::test]
+    async fn deltas_fail_to_apply_while_redis_is_down() {
+        let redis_conn = testing::redis(Arc::new(Outage)).await;
+
+        // A failed apply rolls back the delete in `drain`, so these stay queued
+        for league in [Some(League::Casual), None] {
+            let pending_delta = PendingLeaderboardDelta {
+                id: 1,
+                player_id: 7,
+                delta: 120,
+                created_at: time::OffsetDateTime::UNIX_EPOCH,
+                league,
+            };
+            assert!(pending_delta.apply(&redis_conn).await.is_err());
+        }
+    }
+
+    async fn queued(player_id: i32, conn: &mut AsyncPgConnection) -> i64 {
+        pending_leaderboard_deltas::table
+            .filter(pending_leaderboard_deltas::player_id.eq(player_id))
+            .count()
+            .get_result(conn)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn queued_deltas_survive_an_outage_until_drained() {
+        let Some(mut conn) = testing::db().await else {
+            return;
+        };
+        let player_id = testing::insert_player(1, &mut conn).await;
+        let down = testing::redis(Arc::new(Outage)).await;
+        PendingLeaderboardDelta::apply_or_enqueue(player_id, League::Pro, 120, &mut conn, &down)
+            .await
+            .unwrap();
+        assert_eq!(queued(player_id, &mut conn).await, 1);
+
+        // Still down, so the delta stays queued
+        assert!(PendingLeaderboardDelta::drain(&mut conn, &down)
+            .await
+            .is_err());
+        assert_eq!(queued(player_id, &mut conn).await, 1);
+
+        let recorded = Arc::new(RecordedRedis::default());
+        let up = testing::redis(recorded.clone()).await;
+        assert!(
+            PendingLeaderboardDelta::drain(&mut conn, &up)
+                .await
+                .unwrap()
+                >= 1
+        );
+        assert_eq!(queued(player_id, &mut conn).await, 0);
+        assert!(recorded
+            .commands()
+            .contains(&("ZINCRBY".to_owned(), GLOBAL_LEADERBOARD.to_owned())));
+
+        // Draining again doesn't apply it twice
+        let recorded = Arc::new(RecordedRedis::default());
+        let up = testing::redis(recorded.clone()).await;
+        PendingLeaderboardDelta::drain(&mut conn, &up)
+            .await
+            .unwrap();
+        assert!(recorded.commands().is_empty());
+    }
+}
@@ -1,6 +1,21 @@
+pub mod announcements;
+pub mod bans;
+pub mod client_changelog;
 pub mod extra_song_info;
+pub mod favorite_songs;
+pub mod leaderboard_deltas;
+pub mod notifications;
+pub mod player_name_history;
 pub mod players;
+pub mod profile_shouts;
+pub mod radio_entries;
+pub mod rejected_scores;
 pub mod rivalries;
+pub mod score_history;
+pub mod score_reports;
 pub mod scores;
 pub mod shouts;
+pub mod skill_point_decay;
+pub mod song_daily_plays;
+pub mod song_tag_samples;
 pub mod songs;
@@ -0,0 +1,224 @@
+use diesel::{
+    backend::Backend,
+    deserialize::{self, FromSql, FromSqlRow},
+    dsl::sql,
+    expression::AsExpression,
+    pg::Pg,
+    prelude::*,
+    serialize::{self, Output, ToSql},
+    sql_types::{Array, Bool, Integer, SmallInt},
+};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use num_enum::{IntoPrimitive, TryFromPrimitive};
+use serde::Serialize;
+use serde_repr::{Deserialize_repr, Serialize_repr};
+use time::OffsetDateTime;
+use utoipa::ToSchema;
+
+use crate::{models::players::Player, schema::notifications, util::game_types::League};
+
+/// What a notification is about, which decides what its payload looks like.
+///
+/// 0 = Dethroned
+#[derive(
+    AsExpression,
+    FromSqlRow,
+    Serialize_repr,
+    Deserialize_repr,
+    Debug,
+    Eq,
+    PartialEq,
+    Clone,
+    Copy,
+    TryFromPrimitive,
+    IntoPrimitive,
+    ToSchema,
+)]
+#[diesel(sql_type = diesel::sql_types::SmallInt)]
+#[repr(i16)]
+pub enum NotificationKind {
+    /// Someone beat the player's top score on a song, see `DethronedPayload`
+    Dethroned,
+}
+
+impl ToSql<SmallInt, Pg> for NotificationKind
+where
+    i16: ToSql<SmallInt, Pg>,
+{
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        let v = *self as i16;
+        <i16 as ToSql<SmallInt, Pg>>::to_sql(&v, &mut out.reborrow())
+    }
+}
+
+impl<DB> FromSql<SmallInt, DB> for NotificationKind
+where
+    DB: Backend,
+    i16: FromSql<SmallInt, DB>,
+{
+    fn from_sql(bytes: DB::RawValue<'_>) -> deserialize::Result<Self> {
+        let kind = i16::from_sql(bytes)?;
+        Ok(Self::try_from(kind)?)
+    }
+}
+
+#[derive(Identifiable, Selectable, Queryable, Associations, Debug, Serialize, ToSchema)]
+#[diesel(belongs_to(Player))]
+#[diesel(table_name = notifications, check_for_backend(diesel::pg::Pg))]
+#[diesel(primary_key(id))]
+#[serde(rename_all = "camelCase")]
+pub struct Notification {
+    pub id: i32,
+    #[serde(skip)]
+    pub player_id: i32,
+    pub kind: NotificationKind,
+    /// Details, depending on the kind
+    #[schema(value_type = Object)]
+    pub payload: serde_json::Value,
+    #[serde(serialize_with = "time::serde::iso8601::serialize")]
+    pub created_at: OffsetDateTime,
+    /// Not set while the notification is unread
+    #[serde(serialize_with = "time::serde::iso8601::option::serialize")]
+    pub read_at: Option<OffsetDateTime>,
+}
+
+impl Notification {
+    /// Marks one of the player's notifications as read, if it isn't already.
+    /// Returns whether the player has a notification with that ID.
+    ///
+    /// # Errors
+    /// Fails if something is wrong with the DB.
+    pub async fn mark_read(
+        notification_id: i32,
+        recipient_id: i32,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<bool> {
+        let found: Option<Option<OffsetDateTime>> = notifications::table
+            .find(notification_id)
+            .filter(notifications::player_id.eq(recipient_id))
+            .select(notifications::read_at)
+            .first(conn)
+            .await
+            .optional()?;
+
+        match found {
+            None => Ok(false),
+            Some(Some(_)) => Ok(true),
+            Some(None) => {
+                diesel::update(notifications::table.find(notification_id))
+                    .set(notifications::read_at.eq(diesel::dsl::now))
+                    .execute(conn)
+                    .await?;
+                Ok(true)
+            }
+        }
+    }
+
+    /// Finds the most recent time since `since` that one of `dethroned_by` took the recipient's top score on a song.
+    /// Returns who it was and when.
+    ///
+    /// # Errors
+    /// Fails if something is wrong with the DB.
+    pub async fn latest_dethrone(
+        recipient_id: i32,
+        song_id: i32,
+        dethroned_by: &[i32],
+        since: OffsetDateTime,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Option<(i32, OffsetDateTime)>> {
+        // Matches notifications_dethroned_song_idx
+        notifications::table
+            .filter(notifications::player_id.eq(recipient_id))
+            .filter(notifications::kind.eq(NotificationKind::Dethroned))
+            .filter(sql::<Bool>("(payload ->> 'songId')::INTEGER = ").bind::<Integer, _>(song_id))
+            .filter(
+                sql::<Bool>("(payload ->> 'dethronedBy')::INTEGER = ANY(")
+                    .bind::<Array<Integer>, _>(dethroned_by)
+                    .sql(")"),
+            )
+            .filter(notifications::created_at.ge(since))
+            .order(notifications::created_at.desc())
+            .select((
+                sql::<Integer>("(payload ->> 'dethronedBy')::INTEGER"),
+                notifications::created_at,
+            ))
+            .first(conn)
+            .await
+            .optional()
+    }
+}
+
+/// Payload of `NotificationKind::Dethroned`
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct DethronedPayload {
+    pub song_id: i32,
+    pub league: League,
+    /// The score that beat the recipient's
+    pub score: i32,
+    /// ID of the player who set it
+    pub dethroned_by: i32,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = notifications)]
+pub struct NewNotification {
+    pub player_id: i32,
+    pub kind: NotificationKind,
+    pub payload: serde_json::Value,
+}
+
+impl NewNotification {
+    /// Tells `recipient_id` that their top score was beaten.
+    ///
+    /// # Errors
+    /// Fails if the payload can't be serialized, which shouldn't happen.
+    pub fn dethroned(recipient_id: i32, payload: &DethronedPayload) -> serde_json::Result<Self> {
+        Ok(Self {
+            player_id: recipient_id,
+            kind: NotificationKind::Dethroned,
+            payload: serde_json::to_value(payload)?,
+        })
+    }
+
+    /// Inserts the notification into the database.
+    ///
+    /// # Errors
+    /// Fails if something is wrong with the DB.
+    pub async fn insert(&self, conn: &mut AsyncPgConnection) -> QueryResult<usize> {
+        diesel::insert_into(notifications::table)
+            .values(self)
+            .execute(conn)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dethroned_payload_shape() {
+        let notification = NewNotification::dethroned(
+            1,
+            &DethronedPayload {
+                song_id: 42,
+                league: League::Elite,
+                score: 123_456,
+                dethroned_by: 2,
+            },
+        )
+        .unwrap();
+
+        assert_eq!(notification.kind, NotificationKind::Dethroned);
+        assert_eq!(
+            notification.payload,
+            serde_json::json!({
+                "songId": 42,
+                "league": 2,
+                "score": 123_456,
+                "dethronedBy": 2,
+            })
+        );
+    }
+}
@@ -0,0 +1,44 @@
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::Serialize;
+use time::OffsetDateTime;
+use utoipa::ToSchema;
+
+use crate::schema::player_name_history;
+
+/// A change of a player's Steam persona name, noticed when they logged in.
+#[derive(Identifiable, Selectable, Queryable, Debug, Serialize, ToSchema)]
+#[diesel(table_name = player_name_history, check_for_backend(diesel::pg::Pg))]
+#[diesel(primary_key(id))]
+#[serde(rename_all = "camelCase")]
+pub struct NameChange {
+    #[serde(skip)]
+    pub id: i32,
+    #[serde(skip)]
+    pub player_id: i32,
+    pub old_name: String,
+    pub new_name: String,
+    #[serde(serialize_with = "time::serde::iso8601::serialize")]
+    pub changed_at: OffsetDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = player_name_history)]
+pub struct NewNameChange<'a> {
+    pub player_id: i32,
+    pub old_name: &'a str,
+    pub new_name: &'a str,
+}
+
+impl NewNameChange<'_> {
+    /// Records the name change.
+    ///
+    /// # Errors
+    /// Fails if something is wrong with the database.
+    pub async fn insert(&self, conn: &mut AsyncPgConnection) -> QueryResult<usize> {
+        diesel::insert_into(player_name_history::table)
+            .values(self)
+            .execute(conn)
+            .await
+    }
+}
@@ -2,25 +2,33 @@ use diesel::{
     backend::Backend,
     deserialize::{self, FromSql, FromSqlRow},
     dsl::sql,
-    expression::AsExpression,
+    expression::{AsExpression, SqlLiteral},
     pg::Pg,
     prelude::*,
     serialize::{self, Output, ToSql},
-    sql_types::{BigInt, SmallInt, Text},
+    sql_types::{BigInt, Nullable, SmallInt, Text, Timestamptz},
+};
+use diesel_async::{
+    scoped_futures::ScopedFutureExt, AsyncConnection, AsyncPgConnection, RunQueryDsl,
 };
-use diesel_async::{AsyncPgConnection, RunQueryDsl};
 use fred::{clients::Pool as RedisPool, prelude::*};
 use num_enum::{IntoPrimitive, TryFromPrimitive};
 use serde::{Deserialize, Serialize};
 use serde_repr::{Deserialize_repr, Serialize_repr};
 use steam_rs::steam_id::SteamId;
 use utoipa::ToSchema;
+use validator::{Validate, ValidationError};
 
-use super::rivalries::RivalryView;
+use super::rivalries::{reverse_rivalries, RivalryView};
 use crate::{
-    models::{rivalries::Rivalry, scores::Score},
+    models::{bans::Ban, player_name_history::NewNameChange, rivalries::Rivalry},
     schema::players,
-    util::game_types::Character,
+    util::{
+        game_types::{Character, League},
+        i18n::Language,
+        leaderboard::{leaderboard_key, GLOBAL_LEADERBOARD},
+        skill_points::SkillPointPolicy,
+    },
 };
 
 #[derive(Serialize, Deserialize, AsExpression, FromSqlRow, Debug, PartialEq, Eq, Clone)]
@@ -58,7 +66,7 @@ where
 
 /// Represents the type of account a player has.
 ///
-/// 0 = User, 1 = Moderator, 2 = Wavebreaker Team
+/// 0 = User, 1 = Moderator, 2 = Wavebreaker Team, 3 = Banned
 #[derive(
     AsExpression,
     FromSqlRow,
@@ -72,6 +80,7 @@ where
     TryFromPrimitive,
     IntoPrimitive,
     ToSchema,
+    clap::ValueEnum,
 )]
 #[diesel(sql_type = diesel::sql_types::SmallInt)]
 #[repr(i16)]
@@ -79,6 +88,8 @@ pub enum AccountType {
     User,
     Moderator,
     Team,
+    /// Can't log in, submit scores or use the API
+    Banned,
 }
 
 impl ToSql<SmallInt, Pg> for AccountType
@@ -102,6 +113,27 @@ where
     }
 }
 
+impl ToSql<SmallInt, Pg> for Language
+where
+    i16: ToSql<SmallInt, Pg>,
+{
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        let v = *self as i16;
+        <i16 as ToSql<SmallInt, Pg>>::to_sql(&v, &mut out.reborrow())
+    }
+}
+
+impl<DB> FromSql<SmallInt, DB> for Language
+where
+    DB: Backend,
+    i16: FromSql<SmallInt, DB>,
+{
+    fn from_sql(bytes: DB::RawValue<'_>) -> deserialize::Result<Self> {
+        let language = i16::from_sql(bytes)?;
+        Ok(Self::try_from(language)?)
+    }
+}
+
 #[derive(
     Queryable, Selectable, Identifiable, PartialEq, Eq, Debug, Serialize, Deserialize, Clone,
 )]
@@ -117,6 +149,29 @@ pub struct Player {
     #[serde(deserialize_with = "time::serde::iso8601::deserialize")]
     pub joined_at: time::OffsetDateTime,
     pub avatar_url: String,
+    #[serde(default, with = "time::serde::iso8601::option")]
+    pub last_played_at: Option<time::OffsetDateTime>,
+    #[serde(default, with = "time::serde::iso8601::option")]
+    pub last_seen_at: Option<time::OffsetDateTime>,
+    /// Language of in-game text, English if not set
+    #[serde(default)]
+    pub preferred_language: Option<Language>,
+    #[serde(default)]
+    pub bio: Option<String>,
+    #[serde(default)]
+    pub profile_links: Option<Vec<Option<String>>>,
+    /// Keeps the player out of the list of players who are online, and their last seen time private
+    #[serde(default)]
+    pub hide_presence: bool,
+    /// When the player last logged in through the game
+    #[serde(default, with = "time::serde::iso8601::option")]
+    pub last_login_at: Option<time::OffsetDateTime>,
+    /// When the player logged in through the game before that, to tell what's new since then
+    #[serde(default, with = "time::serde::iso8601::option")]
+    pub previous_login_at: Option<time::OffsetDateTime>,
+    /// Keeps others from shouting on the player's profile
+    #[serde(default)]
+    pub disable_profile_shouts: bool,
 }
 
 // Types for use with functions that return reusable query fragments
@@ -125,78 +180,308 @@ type WithSteamId = diesel::dsl::Eq<players::steam_id, SteamIdWrapper>;
 type BySteamId = diesel::dsl::Filter<All, WithSteamId>;
 
 impl Player {
-    /// Get skill points from Redis.
-    pub async fn get_skill_points(&self, redis_conn: &RedisPool) -> anyhow::Result<i32> {
-        let skill_points: Option<i32> = redis_conn.zscore("leaderboard", self.id).await?;
+    /// Checks whether the player is banned and mustn't use the game or API routes,
+    /// either permanently through their account type or by a ban that hasn't expired yet.
+    ///
+    /// # Returns
+    /// The message explaining the ban to the player, if they're banned.
+    ///
+    /// # Errors
+    /// Fails if something is wrong with the database.
+    pub async fn active_ban(&self, conn: &mut AsyncPgConnection) -> QueryResult<Option<String>> {
+        if self.account_type == AccountType::Banned {
+            return Ok(Some("Account is banned".to_owned()));
+        }
 
-        Ok(skill_points.unwrap_or(0))
+        Ok(Ban::find_active(self.id, conn)
+            .await?
+            .map(|ban| ban.public_message()))
     }
 
-    /// Calculates the total skill points a player has earned with their scores.
-    /// This is not the value stored in the Redis leaderboard, this function calculates it again!
-    pub async fn calc_skill_points(&self, conn: &mut AsyncPgConnection) -> QueryResult<i32> {
-        use crate::schema::scores::dsl::*;
+    /// Lifts the player's bans, including a permanent one through their account type,
+    /// which makes them a regular user again. See [`Ban::lift_all`] for `lifted_by`.
+    ///
+    /// # Returns
+    /// Whether there was anything to lift.
+    ///
+    /// # Errors
+    /// Fails if something is wrong with the database.
+    pub async fn lift_bans(
+        &self,
+        lifted_by: Option<i32>,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<bool> {
+        conn.transaction(|conn| {
+            async move {
+                let lifted = Ban::lift_all(self.id, lifted_by, conn).await?;
+                let unbanned = diesel::update(
+                    players::table
+                        .find(self.id)
+                        .filter(players::account_type.eq(AccountType::Banned)),
+                )
+                .set(players::account_type.eq(AccountType::User))
+                .execute(conn)
+                .await?;
 
-        let player_scores = scores
-            .filter(player_id.eq(self.id))
-            .load::<Score>(conn)
-            .await?;
+                Ok(lifted + unbanned > 0)
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+
+    /// Language to show in-game text in.
+    #[must_use]
+    pub fn language(&self) -> Language {
+        self.preferred_language.unwrap_or_default()
+    }
+
+    /// Remembers the language of the game's locale (e.g. `de_DE`), unless the player already has one set.
+    ///
+    /// # Errors
+    /// Fails if something is wrong with the database.
+    pub async fn infer_language(
+        &self,
+        locale: &str,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<()> {
+        let Some(language) = Language::from_locale(locale) else {
+            return Ok(());
+        };
+        if self.preferred_language.is_some() {
+            return Ok(());
+        }
 
-        let skill_points_sum = player_scores.iter().map(Score::calc_skill_points).sum();
+        diesel::update(players::table.find(self.id))
+            .filter(players::preferred_language.is_null())
+            .set(players::preferred_language.eq(language))
+            .execute(conn)
+            .await?;
 
-        Ok(skill_points_sum)
+        Ok(())
     }
 
-    /// Returns the player's global leaderboard rank
-    pub async fn get_rank(&self, redis_conn: &RedisPool) -> anyhow::Result<i32> {
-        let rank = redis_conn
-            .zrevrank::<i32, _, _>("leaderboard", self.id, false)
-            .await?
-            + 1; // index starts with 0
+    /// Get skill points in `league` (or across all leagues if `None`) from Redis.
+    pub async fn get_skill_points(
+        &self,
+        league: Option<League>,
+        redis_conn: &RedisPool,
+    ) -> anyhow::Result<i32> {
+        let skill_points: Option<i32> = redis_conn.zscore(leaderboard_key(league), self.id).await?;
 
-        Ok(rank)
+        Ok(skill_points.unwrap_or(0))
     }
 
-    /// Returns the total number of the player's plays.
-    /// This is the sum of all `play_count`s across all scores, which increments on every score submission (no matter if high score or not).
-    pub async fn get_total_plays(&self, conn: &mut AsyncPgConnection) -> QueryResult<i32> {
+    /// Calculates the skill points of multiple players in each league with a single query.
+    /// These are not the values stored in the Redis leaderboards, this function calculates them again!
+    /// Leagues a player has no scores in are not included in the result.
+    ///
+    /// This calculates the same as `Score::calc_skill_points` with `policy`, just in SQL.
+    pub async fn calc_skill_points_batch(
+        player_ids: &[i32],
+        policy: &SkillPointPolicy,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Vec<(i32, League, i64)>> {
         use crate::schema::scores::dsl::*;
 
-        let player_scores = scores
-            .filter(player_id.eq(self.id))
-            .load::<Score>(conn)
-            .await?;
+        scores
+            .filter(player_id.eq_any(player_ids))
+            .group_by((player_id, league))
+            .select((player_id, league, sql::<BigInt>(&policy.sql_sum())))
+            .load::<(i32, League, i64)>(conn)
+            .await
+    }
 
-        let play_count_sum = player_scores.iter().map(|s| s.play_count).sum();
+    /// Returns the player's rank and skill points in `league` (or across all leagues if `None`) in a single Redis round trip.
+    /// The rank is 0 if the player isn't on the leaderboard.
+    pub async fn get_rank_and_skill_points(
+        &self,
+        league: Option<League>,
+        redis_conn: &RedisPool,
+    ) -> anyhow::Result<(i32, i32)> {
+        let pipeline = redis_conn.next().pipeline();
+        let _: () = pipeline
+            .zrevrank(leaderboard_key(league), self.id, false)
+            .await?;
+        let _: () = pipeline.zscore(leaderboard_key(league), self.id).await?;
+        let (rank, skill_points): (Option<i32>, Option<i32>) = pipeline.all().await?;
 
-        Ok(play_count_sum)
+        Ok((
+            rank.map_or(0, |rank| rank + 1), // index starts with 0
+            skill_points.unwrap_or(0),
+        ))
     }
 
-    /// Returns the player's favorite character.
-    /// This is the character that they have set the most scores with. Unlike `get_total_plays`, this only counts high scores,
+    /// Returns the player's score-based stats (total plays, songs played and favorite character) with a single query.
+    ///
+    /// Total plays is the sum of all `play_count`s across all scores, which increments on every score submission (no matter if high score or not).
+    /// The favorite character is the character that they have set the most scores with. Unlike total plays, this only counts high scores,
     /// since we do not track the character for submissions that aren't high scores.
-    pub async fn get_favorite_character(
+    pub async fn get_score_stats(&self, conn: &mut AsyncPgConnection) -> QueryResult<ScoreStats> {
+        let row: Option<ScoreStatsRow> = diesel::sql_query(
+            "SELECT \
+                COALESCE(SUM(scores.play_count), 0)::int4 AS total_plays, \
+                COUNT(DISTINCT scores.song_id) AS songs_played, \
+                fav.vehicle AS favorite_character, \
+                fav.times_used AS favorite_character_uses \
+            FROM scores \
+            LEFT JOIN LATERAL ( \
+                SELECT vehicle, COUNT(*) AS times_used FROM scores \
+                WHERE player_id = $1 GROUP BY vehicle ORDER BY times_used DESC LIMIT 1 \
+            ) AS fav ON TRUE \
+            WHERE scores.player_id = $1 \
+            GROUP BY fav.vehicle, fav.times_used",
+        )
+        .bind::<diesel::sql_types::Integer, _>(self.id)
+        .get_result(conn)
+        .await
+        .optional()?;
+
+        // No row means the player has no scores at all
+        Ok(row.map_or(
+            ScoreStats {
+                total_plays: 0,
+                songs_played: 0,
+                favorite_character: None,
+            },
+            |row| ScoreStats {
+                total_plays: row.total_plays,
+                songs_played: row.songs_played,
+                favorite_character: row.favorite_character.zip(row.favorite_character_uses).map(
+                    |(character, times_used)| FavoriteCharacter {
+                        character,
+                        times_used,
+                    },
+                ),
+            },
+        ))
+    }
+
+    /// Counts the songs where the player holds the #1, #2 or #3 score, per league.
+    /// Equal scores are ordered by submission time, like on the leaderboard. Songs excluded from rankings don't count.
+    ///
+    /// # Errors
+    /// Fails if something is wrong with the database.
+    pub async fn medal_counts(
         &self,
         conn: &mut AsyncPgConnection,
-    ) -> QueryResult<Option<FavoriteCharacter>> {
-        use crate::schema::scores::dsl::*;
+    ) -> QueryResult<Vec<LeagueMedals>> {
+        let rows: Vec<MedalCountRow> = diesel::sql_query(format!(
+            "SELECT league, position, COUNT(*) AS songs FROM ({MEDAL_POSITIONS_SQL}) AS ranked \
+            WHERE player_id = $1 AND position <= 3 \
+            GROUP BY league, position"
+        ))
+        .bind::<diesel::sql_types::Integer, _>(self.id)
+        .load(conn)
+        .await?;
 
-        let result: Option<(Character, i64)> = scores
-            .filter(player_id.eq(self.id))
-            .select((
-                vehicle,
-                sql::<BigInt>("COUNT(scores.vehicle) AS play_count"),
+        Ok(tally_medals(
+            rows.into_iter()
+                .map(|row| (row.league, row.position, row.songs)),
+        ))
+    }
+
+    /// Lists the songs where the player holds the #1 score, as `(league, song ID)` pairs.
+    /// Counted like in [`Player::medal_counts`].
+    ///
+    /// # Errors
+    /// Fails if something is wrong with the database.
+    pub async fn first_places(
+        &self,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Vec<(League, i32)>> {
+        let rows: Vec<FirstPlaceRow> = diesel::sql_query(format!(
+            "SELECT league, song_id FROM ({MEDAL_POSITIONS_SQL}) AS ranked \
+            WHERE player_id = $1 AND position = 1 \
+            ORDER BY league, song_id"
+        ))
+        .bind::<diesel::sql_types::Integer, _>(self.id)
+        .load(conn)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| (row.league, row.song_id))
+            .collect())
+    }
+
+    /// Marks the player as having played just now.
+    pub async fn touch_last_played(
+        player_id: i32,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<()> {
+        diesel::update(players::table.find(player_id))
+            .set(players::last_played_at.eq(diesel::dsl::now))
+            .execute(conn)
+            .await?;
+        Ok(())
+    }
+
+    /// Marks the player as seen just now.
+    /// This only writes to the database once per hour per player, since it's called on every authenticated request.
+    pub async fn touch_last_seen(
+        player_id: i32,
+        conn: &mut AsyncPgConnection,
+        redis_conn: &RedisPool,
+    ) -> anyhow::Result<()> {
+        if Self::claim_last_seen_update(player_id, redis_conn).await? {
+            Self::set_last_seen(player_id, conn).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Whether the player's last seen time is due for an update, see [`Self::touch_last_seen`].
+    /// If it is, it's not due again for an hour.
+    ///
+    /// # Errors
+    /// Fails if something goes wrong with Redis.
+    pub async fn claim_last_seen_update(
+        player_id: i32,
+        redis_conn: &RedisPool,
+    ) -> anyhow::Result<bool> {
+        // SET NX only succeeds if the key doesn't exist yet, i.e. the last update is over an hour ago
+        let was_set: Option<String> = redis_conn
+            .set(
+                format!("lastseen:{player_id}"),
+                1,
+                Some(Expiration::EX(60 * 60)),
+                Some(SetOptions::NX),
+                false,
+            )
+            .await?;
+
+        Ok(was_set.is_some())
+    }
+
+    /// Sets the player's last seen time to now, without throttling.
+    ///
+    /// # Errors
+    /// Fails if something is wrong with the DB.
+    pub async fn set_last_seen(player_id: i32, conn: &mut AsyncPgConnection) -> QueryResult<()> {
+        diesel::update(players::table.find(player_id))
+            .set(players::last_seen_at.eq(diesel::dsl::now))
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Remembers that the player logged in through the game just now,
+    /// moving the time of their last login to `previous_login_at`.
+    ///
+    /// # Errors
+    /// Fails if something is wrong with the DB.
+    pub async fn record_login(player_id: i32, conn: &mut AsyncPgConnection) -> QueryResult<()> {
+        diesel::update(players::table.find(player_id))
+            .set((
+                players::previous_login_at.eq(players::last_login_at),
+                players::last_login_at.eq(diesel::dsl::now),
             ))
-            .group_by(vehicle)
-            .order_by(sql::<BigInt>("play_count DESC"))
-            .first::<(Character, i64)>(conn)
-            .await
-            .optional()?;
+            .execute(conn)
+            .await?;
 
-        Ok(result.map(|(character, times_used)| FavoriteCharacter {
-            character,
-            times_used,
-        }))
+        Ok(())
     }
 
     /// Finds a player by their Steam ID.
@@ -239,21 +524,104 @@ impl Player {
 
     /// Retrieves rivalries, with the date they were established, and the profiles of the rivals.
     /// This is **not** like `get_rivals`, which only returns a `Vec<Player>` of the rivals and nothing else.
+    ///
+    /// Newest rivalries come first. Without `page`, all of them are returned.
     pub async fn get_rivalry_views(
         &self,
+        page: Option<RivalryPage>,
         conn: &mut AsyncPgConnection,
     ) -> QueryResult<Vec<RivalryView>> {
         use crate::schema::rivalries::dsl::*;
 
-        rivalries
+        let mut query = rivalries
             .inner_join(
                 crate::schema::players::table.on(rival_id.eq(crate::schema::players::dsl::id)),
             )
+            .left_join(
+                reverse_rivalries.on(reverse_rivalries
+                    .field(challenger_id)
+                    .eq(rival_id)
+                    .and(reverse_rivalries.field(rival_id).eq(challenger_id))),
+            )
             .filter(challenger_id.eq(self.id))
-            .select((established_at, PlayerPublic::as_select()))
-            .load::<RivalryView>(conn)
-            .await
+            .order(established_at.desc())
+            .select((
+                established_at,
+                PlayerPublic::as_select(),
+                reverse_rivalries
+                    .field(challenger_id)
+                    .nullable()
+                    .is_not_null(),
+            ))
+            .into_boxed();
+        if let Some(page) = page {
+            query = query.limit(page.limit).offset(page.offset);
+        }
+
+        query.load::<RivalryView>(conn).await
     }
+
+    /// Like `get_rivalry_views`, but for the players who added **this player** as a rival,
+    /// showing the challengers' profiles.
+    pub async fn get_challenger_views(
+        &self,
+        page: Option<RivalryPage>,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Vec<RivalryView>> {
+        use crate::schema::rivalries::dsl::*;
+
+        let mut query = rivalries
+            .inner_join(
+                crate::schema::players::table.on(challenger_id.eq(crate::schema::players::dsl::id)),
+            )
+            .left_join(
+                reverse_rivalries.on(reverse_rivalries
+                    .field(challenger_id)
+                    .eq(rival_id)
+                    .and(reverse_rivalries.field(rival_id).eq(challenger_id))),
+            )
+            .filter(rival_id.eq(self.id))
+            .order(established_at.desc())
+            .select((
+                established_at,
+                PlayerPublic::as_select(),
+                reverse_rivalries
+                    .field(challenger_id)
+                    .nullable()
+                    .is_not_null(),
+            ))
+            .into_boxed();
+        if let Some(page) = page {
+            query = query.limit(page.limit).offset(page.offset);
+        }
+
+        query.load::<RivalryView>(conn).await
+    }
+
+    /// Counts the player's rivals and the players who have them as a rival, in that order.
+    pub async fn count_rivalries(&self, conn: &mut AsyncPgConnection) -> QueryResult<(i64, i64)> {
+        use crate::schema::rivalries::dsl::*;
+
+        let rivals = rivalries
+            .filter(challenger_id.eq(self.id))
+            .count()
+            .get_result(conn)
+            .await?;
+        let challengers = rivalries
+            .filter(rival_id.eq(self.id))
+            .count()
+            .get_result(conn)
+            .await?;
+
+        Ok((rivals, challengers))
+    }
+}
+
+/// Which part of a player's rivalries to load
+#[derive(Clone, Copy, Debug)]
+pub struct RivalryPage {
+    pub limit: i64,
+    pub offset: i64,
 }
 
 #[derive(Insertable)]
@@ -301,23 +669,51 @@ impl<'a> NewPlayer<'a> {
         conn: &mut AsyncPgConnection,
         redis_conn: &RedisPool,
     ) -> anyhow::Result<Player> {
-        // Register player
-        // Update info if already registered
-        let player_result = diesel::insert_into(players::table)
-            .values(self)
-            .on_conflict(players::steam_account_num)
-            .do_update()
-            .set((
-                players::username.eq(&self.username),
-                players::avatar_url.eq(&self.avatar_url),
-            ))
-            .get_result::<Player>(conn)
+        let player_result = conn
+            .transaction::<_, anyhow::Error, _>(|conn| {
+                async move {
+                    // Lock the row, so concurrent logins can't both record the same name change
+                    let old_name: Option<String> = players::table
+                        .filter(players::steam_account_num.eq(self.steam_account_num))
+                        .select(players::username)
+                        .for_update()
+                        .first(conn)
+                        .await
+                        .optional()?;
+
+                    // Register player
+                    // Update info if already registered
+                    let player = diesel::insert_into(players::table)
+                        .values(self)
+                        .on_conflict(players::steam_account_num)
+                        .do_update()
+                        .set((
+                            players::username.eq(&self.username),
+                            players::avatar_url.eq(&self.avatar_url),
+                        ))
+                        .get_result::<Player>(conn)
+                        .await?;
+
+                    if let Some(old_name) = old_name.filter(|name| *name != self.username) {
+                        NewNameChange {
+                            player_id: player.id,
+                            old_name: &old_name,
+                            new_name: self.username,
+                        }
+                        .insert(conn)
+                        .await?;
+                    }
+
+                    Ok(player)
+                }
+                .scope_boxed()
+            })
             .await?;
 
         // If the player doesn't exist in the Redis sorted set, add them with a score of 0
         redis_conn
             .zadd::<(), _, _>(
-                "leaderboard",
+                GLOBAL_LEADERBOARD,
                 Some(SetOptions::NX),
                 None,
                 false,
@@ -330,6 +726,10 @@ impl<'a> NewPlayer<'a> {
     }
 }
 
+/// `players.last_seen_at`, unless the player hides their presence
+const VISIBLE_LAST_SEEN_SQL: &str =
+    "CASE WHEN players.hide_presence THEN NULL ELSE players.last_seen_at END";
+
 #[derive(Selectable, Queryable, Debug, Serialize, Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 #[diesel(table_name = players, check_for_backend(diesel::pg::Pg))]
@@ -340,6 +740,21 @@ pub struct PlayerPublic {
     #[serde(serialize_with = "time::serde::iso8601::serialize")]
     pub joined_at: time::OffsetDateTime,
     pub avatar_url: String,
+    /// When the player last submitted a score
+    #[serde(serialize_with = "time::serde::iso8601::option::serialize")]
+    pub last_played_at: Option<time::OffsetDateTime>,
+    /// When the player last logged in or used the API while logged in (updated at most once per hour).
+    /// `null` if the player hides their presence.
+    #[serde(serialize_with = "time::serde::iso8601::option::serialize")]
+    #[diesel(select_expression = sql::<Nullable<Timestamptz>>(VISIBLE_LAST_SEEN_SQL))]
+    #[diesel(select_expression_type = SqlLiteral<Nullable<Timestamptz>>)]
+    pub last_seen_at: Option<time::OffsetDateTime>,
+    /// Short "about me" text
+    pub bio: Option<String>,
+    /// Links the player put on their profile, e.g. to their YouTube channel
+    pub profile_links: Option<Vec<Option<String>>>,
+    /// Whether new shouts on the player's profile are refused
+    pub disable_profile_shouts: bool,
 }
 
 impl From<Player> for PlayerPublic {
@@ -350,13 +765,313 @@ impl From<Player> for PlayerPublic {
             account_type: player.account_type,
             joined_at: player.joined_at,
             avatar_url: player.avatar_url,
+            last_played_at: player.last_played_at,
+            last_seen_at: player.last_seen_at.filter(|_| !player.hide_presence),
+            bio: player.bio,
+            profile_links: player.profile_links,
+            disable_profile_shouts: player.disable_profile_shouts,
         }
     }
 }
 
+/// Maximum number of links on a profile
+pub const MAX_PROFILE_LINKS: usize = 5;
+
+/// Changes to a player's profile. Fields that are left out stay as they are, `null` clears them.
+#[derive(AsChangeset, Deserialize, Validate, ToSchema, Default)]
+#[diesel(table_name = players)]
+#[serde(rename_all = "camelCase")]
+pub struct PlayerProfileUpdate {
+    #[serde(default, with = "::serde_with::rust::double_option")]
+    #[validate(length(max = 500))]
+    pub bio: Option<Option<String>>,
+    #[serde(default, with = "::serde_with::rust::double_option")]
+    #[validate(custom(function = "validate_profile_links"))]
+    pub profile_links: Option<Option<Vec<String>>>,
+}
+
+impl PlayerProfileUpdate {
+    /// Whether there's nothing to change, since Diesel refuses to run an empty update.
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.bio.is_none() && self.profile_links.is_none()
+    }
+}
+
+fn validate_profile_links(links: &[String]) -> Result<(), ValidationError> {
+    if links.len() > MAX_PROFILE_LINKS {
+        return Err(ValidationError::new("profile_links")
+            .with_message(format!("At most {MAX_PROFILE_LINKS} links are allowed").into()));
+    }
+    if !links.iter().all(|link| is_web_link(link)) {
+        return Err(
+            ValidationError::new("profile_links").with_message("Links must be http(s) URLs".into())
+        );
+    }
+
+    Ok(())
+}
+
+/// Whether a profile link is a plain http(s) URL, so it can't run scripts when clicked.
+pub(crate) fn is_web_link(link: &str) -> bool {
+    let Some(rest) = link
+        .strip_prefix("https://")
+        .or_else(|| link.strip_prefix("http://"))
+    else {
+        return false;
+    };
+    let host = rest.split(['/', '?', '#']).next().unwrap_or_default();
+
+    link.len() <= 200 && !host.is_empty() && !link.chars().any(char::is_whitespace)
+}
+
 #[derive(Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct FavoriteCharacter {
     pub character: Character,
     pub times_used: i64,
 }
+
+/// Position of every score on the leaderboards of the songs a player (`$1`) has scores on
+const MEDAL_POSITIONS_SQL: &str = "SELECT scores.player_id, scores.song_id, scores.league, \
+    ROW_NUMBER() OVER (PARTITION BY scores.song_id, scores.league \
+        ORDER BY scores.score DESC, scores.submitted_at ASC) AS position \
+    FROM scores JOIN songs ON songs.id = scores.song_id \
+    WHERE NOT songs.excluded_from_rankings \
+    AND scores.song_id IN (SELECT song_id FROM scores WHERE player_id = $1)";
+
+/// How many songs a player holds gold (#1), silver (#2) and bronze (#3) on in a league
+#[derive(Serialize, ToSchema, Debug, PartialEq, Eq)]
+pub struct LeagueMedals {
+    pub league: League,
+    pub gold: i64,
+    pub silver: i64,
+    pub bronze: i64,
+}
+
+/// Sorts `(league, position, song count)` rows into medals, with an entry for every league.
+fn tally_medals(rows: impl IntoIterator<Item = (League, i64, i64)>) -> Vec<LeagueMedals> {
+    let mut medals: Vec<LeagueMedals> = League::ALL
+        .into_iter()
+        .map(|league| LeagueMedals {
+            league,
+            gold: 0,
+            silver: 0,
+            bronze: 0,
+        })
+        .collect();
+    for (league, position, songs) in rows {
+        let Some(entry) = medals.iter_mut().find(|entry| entry.league == league) else {
+            continue;
+        };
+        match position {
+            1 => entry.gold += songs,
+            2 => entry.silver += songs,
+            3 => entry.bronze += songs,
+            _ => {}
+        }
+    }
+    medals
+}
+
+#[derive(QueryableByName)]
+struct MedalCountRow {
+    #[diesel(sql_type = SmallInt)]
+    league: League,
+    #[diesel(sql_type = BigInt)]
+    position: i64,
+    #[diesel(sql_type = BigInt)]
+    songs: i64,
+}
+
+#[derive(QueryableByName)]
+struct FirstPlaceRow {
+    #[diesel(sql_type = SmallInt)]
+    league: League,
+    #[diesel(sql_type = diesel::sql_types::Integer)]
+    song_id: i32,
+}
+
+/// Stats derived from a player's scores, see `Player::get_score_stats`.
+pub struct ScoreStats {
+    pub total_plays: i32,
+    pub songs_played: i64,
+    pub favorite_character: Option<FavoriteCharacter>,
+}
+
+#[derive(QueryableByName)]
+struct ScoreStatsRow {
+    #[diesel(sql_type = diesel::sql_types::Integer)]
+    total_plays: i32,
+    #[diesel(sql_type = BigInt)]
+    songs_played: i64,
+    #[diesel(sql_type = diesel::sql_types::Nullable<SmallInt>)]
+    favorite_character: Option<Character>,
+    #[diesel(sql_type = diesel::sql_types::Nullable<BigInt>)]
+    favorite_character_uses: Option<i64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        models::scores::NewScore,
+        util::testing::{self, RecordedRedis},
+    };
+
+    #[test]
+    fn account_type_values_are_stable() {
+        // These are stored in the database, so existing values must never change
+        assert_eq!(i16::from(AccountType::User), 0);
+        assert_eq!(i16::from(AccountType::Moderator), 1);
+        assert_eq!(i16::from(AccountType::Team), 2);
+        assert_eq!(AccountType::try_from(3i16).ok(), Some(AccountType::Banned));
+    }
+
+    #[test]
+    fn only_web_links_are_allowed() {
+        assert!(is_web_link("https://www.youtube.com/@someone"));
+        assert!(is_web_link("http://example.com"));
+        assert!(!is_web_link("javascript:alert(1)"));
+        assert!(!is_web_link("https://"));
+        assert!(!is_web_link("https://example.com/a b"));
+        assert!(!is_web_link("ftp://example.com"));
+    }
+
+    #[tokio::test]
+    async fn hidden_presence_hides_last_seen() {
+        let Some(mut conn) = testing::db().await else {
+            return;
+        };
+        let visible = testing::insert_player(1, &mut conn).await;
+        let hidden = testing::insert_player(2, &mut conn).await;
+        for player_id in [visible, hidden] {
+            Player::set_last_seen(player_id, &mut conn).await.unwrap();
+        }
+        diesel::update(players::table.find(hidden))
+            .set(players::hide_presence.eq(true))
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        let both = players::table
+            .filter(players::id.eq_any([visible, hidden]))
+            .order(players::id);
+
+        // Selected directly, as in rivalry and score lists
+        let selected: Vec<PlayerPublic> = both
+            .select(PlayerPublic::as_select())
+            .load(&mut conn)
+            .await
+            .unwrap();
+        assert!(selected[0].last_seen_at.is_some());
+        assert_eq!(selected[1].last_seen_at, None);
+
+        // Converted, as on profiles
+        let converted: Vec<PlayerPublic> = both
+            .select(Player::as_select())
+            .load::<Player>(&mut conn)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(PlayerPublic::from)
+            .collect();
+        assert!(converted[0].last_seen_at.is_some());
+        assert_eq!(converted[1].last_seen_at, None);
+    }
+
+    #[tokio::test]
+    async fn score_stats_count_plays_songs_and_characters() {
+        let Some(mut conn) = testing::db().await else {
+            return;
+        };
+        let redis_conn = testing::redis(Arc::new(RecordedRedis::default())).await;
+        let player_id = testing::insert_player(1, &mut conn).await;
+        let idle_id = testing::insert_player(2, &mut conn).await;
+        let first_song = testing::insert_song("Dear Music", "A4.", &mut conn).await;
+        let second_song = testing::insert_song("Future Rewind", "Sumijun", &mut conn).await;
+        for (song_id, league, score, vehicle) in [
+            (first_song, League::Casual, 1000, Character::PointmanPro),
+            // A replay, so one more play but still one score
+            (first_song, League::Casual, 2000, Character::PointmanPro),
+            (first_song, League::Pro, 1000, Character::MonoPro),
+            (second_song, League::Casual, 1000, Character::MonoPro),
+            (second_song, League::Elite, 1000, Character::MonoPro),
+        ] {
+            NewScore::new(
+                player_id,
+                song_id,
+                league,
+                score,
+                &[],
+                &[],
+                0,
+                vehicle,
+                &[],
+                180,
+                score,
+                0,
+                0,
+            )
+            .create_or_update(&SkillPointPolicy::ORIGINAL, &mut conn, &redis_conn)
+            .await
+            .unwrap();
+        }
+        let load = |id: i32| players::table.find(id).select(Player::as_select());
+
+        let player: Player = load(player_id).first(&mut conn).await.unwrap();
+        let stats = player.get_score_stats(&mut conn).await.unwrap();
+        assert_eq!(stats.total_plays, 5);
+        assert_eq!(stats.songs_played, 2);
+        let favorite = stats.favorite_character.unwrap();
+        assert_eq!(favorite.character, Character::MonoPro);
+        assert_eq!(favorite.times_used, 3);
+
+        let idle: Player = load(idle_id).first(&mut conn).await.unwrap();
+        let stats = idle.get_score_stats(&mut conn).await.unwrap();
+        assert_eq!(stats.total_plays, 0);
+        assert_eq!(stats.songs_played, 0);
+        assert!(stats.favorite_character.is_none());
+    }
+
+    #[test]
+    fn left_out_fields_stay_unchanged() {
+        let update: PlayerProfileUpdate = serde_json::from_str(r#"{"bio": null}"#).unwrap();
+        assert_eq!(update.bio, Some(None));
+        assert_eq!(update.profile_links, None);
+        assert!(!update.is_empty());
+    }
+
+    #[test]
+    fn medals_cover_all_leagues() {
+        let medals = tally_medals([
+            (League::Pro, 1, 4),
+            (League::Pro, 3, 2),
+            (League::Elite, 2, 1),
+        ]);
+        assert_eq!(
+            medals,
+            vec![
+                LeagueMedals {
+                    league: League::Casual,
+                    gold: 0,
+                    silver: 0,
+                    bronze: 0
+                },
+                LeagueMedals {
+                    league: League::Pro,
+                    gold: 4,
+                    silver: 0,
+                    bronze: 2
+                },
+                LeagueMedals {
+                    league: League::Elite,
+                    gold: 0,
+                    silver: 1,
+                    bronze: 0
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,125 @@
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::Serialize;
+use time::OffsetDateTime;
+use utoipa::ToSchema;
+
+use super::{
+    players::{AccountType, Player},
+    shouts::{validate_content, ShoutError},
+};
+use crate::schema::profile_shouts;
+
+/// A shout on a player's profile, like the ones on songs.
+#[derive(Identifiable, Selectable, Queryable, Associations, Debug, Serialize, ToSchema)]
+#[diesel(belongs_to(Player, foreign_key = author_id))]
+#[diesel(table_name = profile_shouts, check_for_backend(diesel::pg::Pg))]
+#[diesel(primary_key(id))]
+#[serde(rename_all = "camelCase")]
+pub struct ProfileShout {
+    pub id: i32,
+    /// The player whose profile it's on
+    pub profile_owner_id: i32,
+    pub author_id: i32,
+    #[serde(serialize_with = "time::serde::iso8601::serialize")]
+    pub posted_at: OffsetDateTime,
+    pub content: String,
+}
+
+impl ProfileShout {
+    /// Whether a player with this ID and account type may delete the shout:
+    /// its author and the profile's owner can, and so can moderators and team members.
+    #[must_use]
+    pub fn can_be_deleted_by(&self, player_id: i32, account_type: AccountType) -> bool {
+        player_id == self.author_id
+            || player_id == self.profile_owner_id
+            || matches!(account_type, AccountType::Moderator | AccountType::Team)
+    }
+}
+
+#[derive(Insertable, Debug)]
+#[diesel(table_name = profile_shouts)]
+pub struct NewProfileShout<'a> {
+    pub profile_owner_id: i32,
+    pub author_id: i32,
+    pub content: &'a str,
+}
+
+impl<'a> NewProfileShout<'a> {
+    /// Whitespace around the content is trimmed off.
+    #[must_use]
+    pub fn new(profile_owner_id: i32, author_id: i32, content: &'a str) -> Self {
+        Self {
+            profile_owner_id,
+            author_id,
+            content: content.trim(),
+        }
+    }
+
+    /// Checks that the shout can be posted, like song shouts: the content has to be valid,
+    /// and it can't repeat the author's previous shout on the same profile.
+    ///
+    /// # Errors
+    /// Fails with the reason the shout isn't allowed, or if something goes wrong with the database.
+    pub async fn validate(&self, conn: &mut AsyncPgConnection) -> Result<(), ShoutError> {
+        validate_content(self.content)?;
+
+        let previous_content: Option<String> = profile_shouts::table
+            .filter(profile_shouts::author_id.eq(self.author_id))
+            .filter(profile_shouts::profile_owner_id.eq(self.profile_owner_id))
+            .order((profile_shouts::posted_at.desc(), profile_shouts::id.desc()))
+            .select(profile_shouts::content)
+            .first(conn)
+            .await
+            .optional()?;
+        if previous_content.as_deref() == Some(self.content) {
+            return Err(ShoutError::Duplicate);
+        }
+
+        Ok(())
+    }
+
+    /// Validates the shout and inserts it into the database
+    ///
+    /// # Errors
+    /// Fails if the shout isn't allowed (see [`Self::validate`]) or something goes wrong with the database
+    pub async fn insert(&self, conn: &mut AsyncPgConnection) -> Result<ProfileShout, ShoutError> {
+        self.validate(conn).await?;
+
+        Ok(diesel::insert_into(profile_shouts::table)
+            .values(self)
+            .returning(ProfileShout::as_returning())
+            .get_result(conn)
+            .await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shout_on(profile_owner_id: i32, author_id: i32) -> ProfileShout {
+        ProfileShout {
+            id: 1,
+            profile_owner_id,
+            author_id,
+            posted_at: OffsetDateTime::UNIX_EPOCH,
+            content: "GG!".to_owned(),
+        }
+    }
+
+    #[test]
+    fn author_and_owner_can_delete() {
+        let shout = shout_on(1, 2);
+        assert!(shout.can_be_deleted_by(2, AccountType::User));
+        assert!(shout.can_be_deleted_by(1, AccountType::User));
+    }
+
+    #[test]
+    fn others_need_to_be_moderators() {
+        let shout = shout_on(1, 2);
+        assert!(!shout.can_be_deleted_by(3, AccountType::User));
+        assert!(shout.can_be_deleted_by(3, AccountType::Moderator));
+        assert!(shout.can_be_deleted_by(3, AccountType::Team));
+    }
+}
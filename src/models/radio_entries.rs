@@ -0,0 +1,288 @@
+use diesel::prelude::*;
+use diesel_async::{
+    scoped_futures::ScopedFutureExt, AsyncConnection, AsyncPgConnection, RunQueryDsl,
+};
+use serde::{Deserialize, Deserializer, Serialize};
+use time::OffsetDateTime;
+use utoipa::ToSchema;
+use validator::{Validate, ValidationError};
+
+use super::{players::is_web_link, songs::Song};
+use crate::schema::radio_entries;
+
+/// What separates the fields of the game's radio list, so it can't be part of them
+pub const RADIO_LIST_SEPARATOR: &str = "-:*x-";
+
+/// A song in the Audiosurf Radio lineup.
+#[derive(Identifiable, Selectable, Queryable, Associations, Clone, Debug, Serialize, ToSchema)]
+#[diesel(belongs_to(Song))]
+#[diesel(table_name = radio_entries, check_for_backend(diesel::pg::Pg))]
+#[diesel(primary_key(id))]
+#[serde(rename_all = "camelCase")]
+pub struct RadioEntry {
+    pub id: i32,
+    /// Song the radio song's scores go to, it has to exist already
+    pub song_id: i32,
+    /// What the game shows, doesn't have to match the song's tags
+    pub title: String,
+    pub artist: String,
+    /// URL of the .cgr file with the song, usually served from `cgr_location`
+    pub cgr_url: String,
+    /// Where to buy the song
+    pub external_url: String,
+    /// Entries are listed from the lowest to the highest
+    pub sort_order: i32,
+    /// Inactive entries aren't listed, but can be brought back
+    pub active: bool,
+    #[serde(serialize_with = "time::serde::iso8601::serialize")]
+    pub created_at: OffsetDateTime,
+    /// Not on the radio before this, e.g. to prepare next month's lineup
+    #[serde(with = "time::serde::iso8601::option")]
+    pub available_from: Option<OffsetDateTime>,
+    /// Not on the radio anymore from this on
+    #[serde(with = "time::serde::iso8601::option")]
+    pub available_until: Option<OffsetDateTime>,
+}
+
+impl RadioEntry {
+    /// Whether the entry is on the radio at `now`. Entries without dates always are, as long as they're active.
+    #[must_use]
+    pub fn is_available_at(&self, now: OffsetDateTime) -> bool {
+        self.available_from.is_none_or(|from| from <= now)
+            && self.available_until.is_none_or(|until| now < until)
+    }
+
+    /// Whether the entry only comes on the radio after `now`.
+    #[must_use]
+    pub fn is_upcoming_at(&self, now: OffsetDateTime) -> bool {
+        self.available_from.is_some_and(|from| now < from)
+    }
+
+    /// The active entries in the order the game lists them.
+    ///
+    /// # Errors
+    /// Fails if something is wrong with the DB.
+    pub async fn lineup(conn: &mut AsyncPgConnection) -> QueryResult<Vec<Self>> {
+        radio_entries::table
+            .filter(radio_entries::active.eq(true))
+            .order((radio_entries::sort_order.asc(), radio_entries::id.asc()))
+            .select(Self::as_select())
+            .load(conn)
+            .await
+    }
+
+    /// All entries in order, including inactive ones.
+    ///
+    /// # Errors
+    /// Fails if something is wrong with the DB.
+    pub async fn all(conn: &mut AsyncPgConnection) -> QueryResult<Vec<Self>> {
+        radio_entries::table
+            .order((radio_entries::sort_order.asc(), radio_entries::id.asc()))
+            .select(Self::as_select())
+            .load(conn)
+            .await
+    }
+
+    /// Gives the entries the sort order of their position in `ids`, all at once.
+    /// Entries that aren't in `ids` keep theirs.
+    ///
+    /// # Returns
+    /// How many entries were found.
+    ///
+    /// # Errors
+    /// Fails if something is wrong with the DB.
+    pub async fn reorder(ids: &[i32], conn: &mut AsyncPgConnection) -> QueryResult<usize> {
+        let ids = ids.to_vec();
+        conn.transaction(|conn| {
+            async move {
+                let mut found = 0;
+                for (position, id) in (0..).zip(ids) {
+                    found += diesel::update(radio_entries::table.find(id))
+                        .set(radio_entries::sort_order.eq(position))
+                        .execute(conn)
+                        .await?;
+                }
+                Ok(found)
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+}
+
+#[derive(Insertable, Deserialize, Validate, ToSchema)]
+#[diesel(table_name = radio_entries)]
+#[serde(rename_all = "camelCase")]
+#[validate(schema(function = "validate_new_availability"))]
+pub struct NewRadioEntry {
+    pub song_id: i32,
+    #[validate(length(min = 1, max = 200), custom(function = "validate_game_text"))]
+    pub title: String,
+    #[validate(length(min = 1, max = 200), custom(function = "validate_game_text"))]
+    pub artist: String,
+    #[validate(custom(function = "validate_radio_url"))]
+    pub cgr_url: String,
+    #[validate(custom(function = "validate_radio_url"))]
+    pub external_url: String,
+    /// Defaults to after all other entries
+    pub sort_order: Option<i32>,
+    #[serde(default, with = "time::serde::iso8601::option")]
+    pub available_from: Option<OffsetDateTime>,
+    #[serde(default, with = "time::serde::iso8601::option")]
+    pub available_until: Option<OffsetDateTime>,
+}
+
+/// Changes to a radio entry. Fields that are left out stay as they are, `null` clears the dates.
+#[derive(AsChangeset, Deserialize, Validate, ToSchema, Default)]
+#[diesel(table_name = radio_entries)]
+#[serde(rename_all = "camelCase")]
+pub struct RadioEntryUpdate {
+    pub song_id: Option<i32>,
+    #[validate(length(min = 1, max = 200), custom(function = "validate_game_text"))]
+    pub title: Option<String>,
+    #[validate(length(min = 1, max = 200), custom(function = "validate_game_text"))]
+    pub artist: Option<String>,
+    #[validate(custom(function = "validate_radio_url"))]
+    pub cgr_url: Option<String>,
+    #[validate(custom(function = "validate_radio_url"))]
+    pub external_url: Option<String>,
+    pub sort_order: Option<i32>,
+    pub active: Option<bool>,
+    #[serde(default, deserialize_with = "deserialize_changed_time")]
+    pub available_from: Option<Option<OffsetDateTime>>,
+    #[serde(default, deserialize_with = "deserialize_changed_time")]
+    pub available_until: Option<Option<OffsetDateTime>>,
+}
+
+impl RadioEntryUpdate {
+    /// Whether there's nothing to change, since Diesel refuses to run an empty update.
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.song_id.is_none()
+            && self.title.is_none()
+            && self.artist.is_none()
+            && self.cgr_url.is_none()
+            && self.external_url.is_none()
+            && self.sort_order.is_none()
+            && self.active.is_none()
+            && self.available_from.is_none()
+            && self.available_until.is_none()
+    }
+
+    /// The dates the entry is available between after this change.
+    #[must_use]
+    pub fn availability_of(
+        &self,
+        entry: &RadioEntry,
+    ) -> (Option<OffsetDateTime>, Option<OffsetDateTime>) {
+        (
+            self.available_from.unwrap_or(entry.available_from),
+            self.available_until.unwrap_or(entry.available_until),
+        )
+    }
+}
+
+/// A time that was sent, possibly `null`, as opposed to one that was left out (which `default` takes care of).
+pub(crate) fn deserialize_changed_time<'de, D>(
+    deserializer: D,
+) -> Result<Option<Option<OffsetDateTime>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    time::serde::iso8601::option::deserialize(deserializer).map(Some)
+}
+
+/// An entry can't stop being available before it starts.
+pub fn validate_availability(
+    available_from: Option<OffsetDateTime>,
+    available_until: Option<OffsetDateTime>,
+) -> Result<(), ValidationError> {
+    if let (Some(from), Some(until)) = (available_from, available_until) {
+        if from >= until {
+            return Err(ValidationError::new("availability")
+                .with_message("availableFrom has to be before availableUntil".into()));
+        }
+    }
+
+    Ok(())
+}
+
+fn validate_new_availability(entry: &NewRadioEntry) -> Result<(), ValidationError> {
+    validate_availability(entry.available_from, entry.available_until)
+}
+
+/// The game's radio browser only shows ASCII properly, and the separator would break the list.
+fn validate_game_text(text: &str) -> Result<(), ValidationError> {
+    if !text.is_ascii() || text.contains(RADIO_LIST_SEPARATOR) {
+        return Err(ValidationError::new("game_text").with_message(
+            format!("Only ASCII characters are allowed, and no \"{RADIO_LIST_SEPARATOR}\"").into(),
+        ));
+    }
+
+    Ok(())
+}
+
+fn validate_radio_url(url: &str) -> Result<(), ValidationError> {
+    if !is_web_link(url) || url.contains(RADIO_LIST_SEPARATOR) {
+        return Err(ValidationError::new("url").with_message("Must be an http(s) URL".into()));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_what_the_game_can_show_is_allowed() {
+        assert!(validate_game_text("Dear Music.").is_ok());
+        assert!(validate_game_text("A4.").is_ok());
+        assert!(validate_game_text("Caf\u{00E9}").is_err());
+        assert!(validate_game_text("Title-:*x-Injected").is_err());
+
+        assert!(validate_radio_url("http://localhost/as/asradio/WVBR_A4_DearMusic.cgr").is_ok());
+        assert!(validate_radio_url("https://www.youtube.com/watch?v=XeVrdjZSceA").is_ok());
+        assert!(validate_radio_url("javascript:alert(1)").is_err());
+        assert!(validate_radio_url("https://example.com/-:*x-").is_err());
+    }
+
+    #[test]
+    fn entries_are_only_available_in_their_window() {
+        let now = OffsetDateTime::UNIX_EPOCH + time::Duration::days(30);
+        let day = time::Duration::days(1);
+        let entry = |available_from, available_until| RadioEntry {
+            id: 1,
+            song_id: 1,
+            title: "Dear Music.".to_owned(),
+            artist: "A4.".to_owned(),
+            cgr_url: "http://localhost/as/asradio/WVBR_A4_DearMusic.cgr".to_owned(),
+            external_url: "https://www.youtube.com/watch?v=XeVrdjZSceA".to_owned(),
+            sort_order: 0,
+            active: true,
+            created_at: OffsetDateTime::UNIX_EPOCH,
+            available_from,
+            available_until,
+        };
+
+        let always = entry(None, None);
+        assert!(always.is_available_at(now));
+        assert!(!always.is_upcoming_at(now));
+
+        let upcoming = entry(Some(now + day), None);
+        assert!(!upcoming.is_available_at(now));
+        assert!(upcoming.is_upcoming_at(now));
+
+        let running = entry(Some(now - day), Some(now + day));
+        assert!(running.is_available_at(now));
+        assert!(!running.is_upcoming_at(now));
+
+        let ended = entry(None, Some(now));
+        assert!(!ended.is_available_at(now));
+        assert!(!ended.is_upcoming_at(now));
+
+        assert!(validate_availability(Some(now), Some(now + day)).is_ok());
+        assert!(validate_availability(Some(now), None).is_ok());
+        assert!(validate_availability(Some(now), Some(now)).is_err());
+    }
+}
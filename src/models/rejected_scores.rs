@@ -0,0 +1,63 @@
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use super::{players::Player, songs::Song};
+use crate::{
+    schema::rejected_scores,
+    util::game_types::{Character, League},
+};
+
+/// A score submission that failed the plausibility checks, kept for moderators to review.
+/// Only the values that were checked are stored.
+#[derive(Identifiable, Selectable, Queryable, Associations, Debug, Serialize, ToSchema)]
+#[diesel(belongs_to(Player))]
+#[diesel(belongs_to(Song))]
+#[diesel(table_name = rejected_scores, check_for_backend(diesel::pg::Pg))]
+#[diesel(primary_key(id))]
+#[serde(rename_all = "camelCase")]
+pub struct RejectedScore {
+    pub id: i32,
+    pub player_id: i32,
+    pub song_id: i32,
+    pub league: League,
+    pub score: i32,
+    pub vehicle: Character,
+    pub gold_threshold: i32,
+    pub song_length: i32,
+    pub track_shape_length: i32,
+    pub feats: Vec<Option<String>>,
+    /// Which check the submission failed
+    pub reason: String,
+    #[serde(serialize_with = "time::serde::iso8601::serialize")]
+    pub rejected_at: time::OffsetDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = rejected_scores)]
+pub struct NewRejectedScore<'a> {
+    pub player_id: i32,
+    pub song_id: i32,
+    pub league: League,
+    pub score: i32,
+    pub vehicle: Character,
+    pub gold_threshold: i32,
+    pub song_length: i32,
+    pub track_shape_length: i32,
+    pub feats: &'a [&'a str],
+    pub reason: String,
+}
+
+impl NewRejectedScore<'_> {
+    /// Inserts the rejected submission into the database.
+    ///
+    /// # Errors
+    /// Fails if something is wrong with the DB.
+    pub async fn insert(&self, conn: &mut AsyncPgConnection) -> QueryResult<usize> {
+        diesel::insert_into(rejected_scores::table)
+            .values(self)
+            .execute(conn)
+            .await
+    }
+}
@@ -1,6 +1,16 @@
-use diesel::prelude::*;
+use diesel::{
+    backend::Backend,
+    deserialize::{self, FromSql, FromSqlRow},
+    expression::AsExpression,
+    pg::Pg,
+    prelude::*,
+    serialize::{self, Output, ToSql},
+    sql_types::SmallInt,
+};
 use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use num_enum::{IntoPrimitive, TryFromPrimitive};
 use serde::{Deserialize, Serialize};
+use serde_repr::{Deserialize_repr, Serialize_repr};
 use utoipa::ToSchema;
 
 use super::players::PlayerPublic;
@@ -17,9 +27,82 @@ pub struct Rivalry {
     pub challenger_id: i32,
     pub rival_id: i32,
     pub established_at: time::OffsetDateTime,
+    pub source: RivalrySource,
+}
+
+/// How a rivalry was added.
+///
+/// 0 = by the player, 1 = by Steam friend sync
+#[derive(
+    AsExpression,
+    FromSqlRow,
+    Serialize_repr,
+    Deserialize_repr,
+    Debug,
+    Eq,
+    PartialEq,
+    Clone,
+    Copy,
+    Default,
+    TryFromPrimitive,
+    IntoPrimitive,
+    ToSchema,
+)]
+#[diesel(sql_type = diesel::sql_types::SmallInt)]
+#[repr(i16)]
+pub enum RivalrySource {
+    /// Added on the website or through the API, never removed automatically
+    #[default]
+    Manual,
+    /// Added because the players are Steam friends, can be removed once they aren't anymore
+    SteamSync,
+}
+
+impl ToSql<SmallInt, Pg> for RivalrySource
+where
+    i16: ToSql<SmallInt, Pg>,
+{
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        let v = *self as i16;
+        <i16 as ToSql<SmallInt, Pg>>::to_sql(&v, &mut out.reborrow())
+    }
+}
+
+impl<DB> FromSql<SmallInt, DB> for RivalrySource
+where
+    DB: Backend,
+    i16: FromSql<SmallInt, DB>,
+{
+    fn from_sql(bytes: DB::RawValue<'_>) -> deserialize::Result<Self> {
+        let source = i16::from_sql(bytes)?;
+        Ok(Self::try_from(source)?)
+    }
 }
 
 impl Rivalry {
+    /// Removes the player's rivalries that Steam friend sync added,
+    /// with players who aren't among `friend_ids` anymore. Manual rivalries are kept.
+    ///
+    /// # Returns
+    /// How many rivalries were removed.
+    ///
+    /// # Errors
+    /// Fails if something is wrong with the database.
+    pub async fn prune_steam_sync(
+        challenger_id: i32,
+        friend_ids: &[i32],
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<usize> {
+        diesel::delete(
+            rivalries::table
+                .filter(rivalries::challenger_id.eq(challenger_id))
+                .filter(rivalries::source.eq(RivalrySource::SteamSync))
+                .filter(rivalries::rival_id.ne_all(friend_ids)),
+        )
+        .execute(conn)
+        .await
+    }
+
     /// Find out if the players added *each others* as rivals.
     pub async fn is_mutual(&self, conn: &mut AsyncPgConnection) -> bool {
         use crate::schema::rivalries::dsl::*;
@@ -70,6 +153,9 @@ impl NewRivalry {
     }
 }
 
+// The other direction of a rivalry, for finding out if it's mutual in the same query
+diesel::alias!(rivalries as reverse_rivalries: ReverseRivalries);
+
 #[derive(Queryable, Deserialize, Serialize, ToSchema)]
 #[diesel(table_name = rivalries, check_for_backend(diesel::pg::Pg))]
 #[serde(rename_all = "camelCase")]
@@ -79,6 +165,8 @@ pub struct RivalryView {
     pub established_at: time::OffsetDateTime,
     #[diesel(embed)]
     pub rival: PlayerPublic,
+    /// Whether both players have added each other as rivals
+    pub mutual: bool,
 }
 
 impl RivalryView {
@@ -92,6 +180,7 @@ impl RivalryView {
             .await?
             .into();
         Ok(Self {
+            mutual: rivalry.is_mutual(conn).await,
             established_at: rivalry.established_at,
             rival,
         })
@@ -110,8 +199,124 @@ impl RivalryView {
             .await?
             .into();
         Ok(Self {
+            mutual: rivalry.is_mutual(conn).await,
             established_at: rivalry.established_at,
             rival: challenger,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{models::players::RivalryPage, schema::players, util::testing};
+
+    async fn add_rival(challenger_id: i32, rival_id: i32, conn: &mut AsyncPgConnection) {
+        diesel::insert_into(rivalries::table)
+            .values((
+                rivalries::challenger_id.eq(challenger_id),
+                rivalries::rival_id.eq(rival_id),
+            ))
+            .execute(conn)
+            .await
+            .unwrap();
+    }
+
+    /// Rival IDs and mutual flags of `views`, by rival ID
+    fn mutual_flags(mut views: Vec<RivalryView>) -> Vec<(i32, bool)> {
+        views.sort_by_key(|view| view.rival.id);
+        views
+            .into_iter()
+            .map(|view| (view.rival.id, view.mutual))
+            .collect()
+    }
+
+    #[test]
+    fn sources_keep_their_numbers() {
+        // Stored in the database, so these must never change
+        assert_eq!(i16::from(RivalrySource::Manual), 0);
+        assert_eq!(i16::from(RivalrySource::SteamSync), 1);
+        assert_eq!(RivalrySource::default(), RivalrySource::Manual);
+    }
+
+    #[tokio::test]
+    async fn views_tell_one_sided_and_mutual_rivalries_apart() {
+        let Some(mut conn) = testing::db().await else {
+            return;
+        };
+        let player_id = testing::insert_player(1, &mut conn).await;
+        let friend_id = testing::insert_player(2, &mut conn).await;
+        let stranger_id = testing::insert_player(3, &mut conn).await;
+        add_rival(player_id, friend_id, &mut conn).await;
+        add_rival(friend_id, player_id, &mut conn).await;
+        add_rival(player_id, stranger_id, &mut conn).await;
+        let load = |id: i32| players::table.find(id).select(Player::as_select());
+        let player: Player = load(player_id).first(&mut conn).await.unwrap();
+        let friend: Player = load(friend_id).first(&mut conn).await.unwrap();
+        let stranger: Player = load(stranger_id).first(&mut conn).await.unwrap();
+
+        let rivals = player.get_rivalry_views(None, &mut conn).await.unwrap();
+        assert_eq!(
+            mutual_flags(rivals),
+            [(friend_id, true), (stranger_id, false)]
+        );
+        let challengers = friend.get_challenger_views(None, &mut conn).await.unwrap();
+        assert_eq!(mutual_flags(challengers), [(player_id, true)]);
+        let challengers = stranger
+            .get_challenger_views(None, &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(mutual_flags(challengers), [(player_id, false)]);
+        // Paginated the same way
+        let page = RivalryPage {
+            limit: 1,
+            offset: 0,
+        };
+        let rivals = player
+            .get_rivalry_views(Some(page), &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(rivals.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn pruning_keeps_manual_rivals_and_friends() {
+        let Some(mut conn) = testing::db().await else {
+            return;
+        };
+        let player = testing::insert_player(1, &mut conn).await;
+        let manual = testing::insert_player(2, &mut conn).await;
+        let friend = testing::insert_player(3, &mut conn).await;
+        let ex_friend = testing::insert_player(4, &mut conn).await;
+        for (rival_id, source) in [
+            (manual, RivalrySource::Manual),
+            (friend, RivalrySource::SteamSync),
+            (ex_friend, RivalrySource::SteamSync),
+        ] {
+            diesel::insert_into(rivalries::table)
+                .values((
+                    rivalries::challenger_id.eq(player),
+                    rivalries::rival_id.eq(rival_id),
+                    rivalries::source.eq(source),
+                ))
+                .execute(&mut conn)
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(
+            Rivalry::prune_steam_sync(player, &[friend], &mut conn)
+                .await
+                .unwrap(),
+            1
+        );
+        let mut rivals: Vec<i32> = rivalries::table
+            .filter(rivalries::challenger_id.eq(player))
+            .select(rivalries::rival_id)
+            .load(&mut conn)
+            .await
+            .unwrap();
+        rivals.sort_unstable();
+        assert_eq!(rivals, [manual, friend]);
+    }
+}
@@ -0,0 +1,84 @@
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::Serialize;
+use time::OffsetDateTime;
+use utoipa::ToSchema;
+
+use crate::{models::scores::Score, schema::score_history, util::game_types::Character};
+
+/// A personal best that was beaten later, kept so players can see how they improved.
+#[derive(Identifiable, Selectable, Queryable, Associations, Debug, Serialize, ToSchema)]
+#[diesel(belongs_to(Score))]
+#[diesel(table_name = score_history, check_for_backend(diesel::pg::Pg))]
+#[diesel(primary_key(id))]
+#[serde(rename_all = "camelCase")]
+pub struct ArchivedScore {
+    #[serde(skip)]
+    pub id: i32,
+    /// The score that replaced this one
+    pub score_id: i32,
+    pub score: i32,
+    #[serde(serialize_with = "time::serde::iso8601::serialize")]
+    pub submitted_at: OffsetDateTime,
+    pub track_shape: Vec<Option<i32>>,
+    pub xstats: Vec<Option<i32>>,
+    pub density: i32,
+    pub vehicle: Character,
+    pub feats: Vec<Option<String>>,
+    pub song_length: i32,
+    pub gold_threshold: i32,
+    pub iss: i32,
+    pub isj: i32,
+    /// When this stopped being the personal best
+    #[serde(serialize_with = "time::serde::iso8601::serialize")]
+    pub archived_at: OffsetDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = score_history)]
+pub struct NewArchivedScore<'a> {
+    pub score_id: i32,
+    pub score: i32,
+    pub submitted_at: OffsetDateTime,
+    pub track_shape: &'a [Option<i32>],
+    pub xstats: &'a [Option<i32>],
+    pub density: i32,
+    pub vehicle: Character,
+    pub feats: &'a [Option<String>],
+    pub song_length: i32,
+    pub gold_threshold: i32,
+    pub iss: i32,
+    pub isj: i32,
+}
+
+impl<'a> NewArchivedScore<'a> {
+    /// Snapshot of `score` as it is now, for the history of the score with ID `score_id`.
+    #[must_use]
+    pub fn new(score: &'a Score, score_id: i32) -> Self {
+        Self {
+            score_id,
+            score: score.score,
+            submitted_at: score.submitted_at,
+            track_shape: &score.track_shape,
+            xstats: &score.xstats,
+            density: score.density,
+            vehicle: score.vehicle,
+            feats: &score.feats,
+            song_length: score.song_length,
+            gold_threshold: score.gold_threshold,
+            iss: score.iss,
+            isj: score.isj,
+        }
+    }
+
+    /// Adds the snapshot to the history.
+    ///
+    /// # Errors
+    /// Fails if something is wrong with the database.
+    pub async fn insert(&self, conn: &mut AsyncPgConnection) -> QueryResult<usize> {
+        diesel::insert_into(score_history::table)
+            .values(self)
+            .execute(conn)
+            .await
+    }
+}
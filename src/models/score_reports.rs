@@ -0,0 +1,205 @@
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use validator::Validate;
+
+use crate::schema::score_reports;
+
+/// A player's report of a score they think is cheated, waiting for a moderator to handle it.
+#[derive(Identifiable, Selectable, Queryable, Debug, Serialize, ToSchema)]
+#[diesel(table_name = score_reports, check_for_backend(diesel::pg::Pg))]
+#[diesel(primary_key(id))]
+#[serde(rename_all = "camelCase")]
+pub struct ScoreReport {
+    pub id: i32,
+    /// Not set anymore once the score was deleted
+    pub score_id: Option<i32>,
+    pub reporter_id: i32,
+    pub reason: String,
+    #[serde(serialize_with = "time::serde::iso8601::serialize")]
+    pub created_at: time::OffsetDateTime,
+    /// Not set while the report is open
+    #[serde(serialize_with = "time::serde::iso8601::option::serialize")]
+    pub resolved_at: Option<time::OffsetDateTime>,
+    /// The moderator who handled the report
+    pub resolved_by: Option<i32>,
+    /// Whether the score was deleted when the report was resolved
+    pub score_deleted: bool,
+}
+
+impl ScoreReport {
+    /// Files a report. Returns `false` if the player already reported the score.
+    ///
+    /// # Errors
+    /// Fails if something is wrong with the DB.
+    pub async fn create(
+        score_id: i32,
+        reporter_id: i32,
+        reason: &str,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<bool> {
+        let inserted = diesel::insert_into(score_reports::table)
+            .values((
+                score_reports::score_id.eq(score_id),
+                score_reports::reporter_id.eq(reporter_id),
+                score_reports::reason.eq(reason),
+            ))
+            .on_conflict((score_reports::score_id, score_reports::reporter_id))
+            .do_nothing()
+            .execute(conn)
+            .await?;
+        Ok(inserted > 0)
+    }
+
+    /// Marks all open reports of a score as resolved by `moderator_id`.
+    /// Returns how many reports were resolved.
+    ///
+    /// # Errors
+    /// Fails if something is wrong with the DB.
+    pub async fn resolve_all_for_score(
+        score_id: i32,
+        moderator_id: i32,
+        score_deleted: bool,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<usize> {
+        diesel::update(
+            score_reports::table
+                .filter(score_reports::score_id.eq(score_id))
+                .filter(score_reports::resolved_at.is_null()),
+        )
+        .set((
+            score_reports::resolved_at.eq(diesel::dsl::now),
+            score_reports::resolved_by.eq(moderator_id),
+            score_reports::score_deleted.eq(score_deleted),
+        ))
+        .execute(conn)
+        .await
+    }
+
+    /// Marks this report as resolved by `moderator_id`, keeping the score.
+    /// Returns 0 if it was already resolved.
+    ///
+    /// # Errors
+    /// Fails if something is wrong with the DB.
+    pub async fn resolve(
+        &self,
+        moderator_id: i32,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<usize> {
+        diesel::update(score_reports::table.find(self.id))
+            .filter(score_reports::resolved_at.is_null())
+            .set((
+                score_reports::resolved_at.eq(diesel::dsl::now),
+                score_reports::resolved_by.eq(moderator_id),
+            ))
+            .execute(conn)
+            .await
+    }
+}
+
+#[derive(Deserialize, Validate, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct NewScoreReport {
+    /// Why the score looks cheated
+    #[validate(length(min = 1, max = 500))]
+    pub reason: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::util::{
+        game_types::League,
+        testing::{self, RecordedRedis},
+    };
+
+    /// A score by one player, reported by two others. Returns the score's and the moderator's ID.
+    async fn reported_score(conn: &mut AsyncPgConnection) -> (i32, i32) {
+        let redis_conn = testing::redis(Arc::new(RecordedRedis::default())).await;
+        let player = testing::insert_player(1, conn).await;
+        let moderator = testing::insert_player(2, conn).await;
+        let song = testing::insert_song("Dear Music", "A4.", conn).await;
+        let score =
+            testing::insert_score(player, song, League::Elite, 1_000_000, conn, &redis_conn).await;
+
+        for reporter in [3, 4] {
+            let reporter = testing::insert_player(reporter, conn).await;
+            assert!(ScoreReport::create(score.id, reporter, "Too good", conn)
+                .await
+                .unwrap());
+        }
+        (score.id, moderator)
+    }
+
+    async fn reports(score_id: i32, conn: &mut AsyncPgConnection) -> Vec<ScoreReport> {
+        score_reports::table
+            .filter(score_reports::score_id.eq(score_id))
+            .order(score_reports::id.asc())
+            .select(ScoreReport::as_select())
+            .load(conn)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn reporting_a_score_twice_is_refused() {
+        let Some(mut conn) = testing::db().await else {
+            return;
+        };
+        let (score_id, moderator) = reported_score(&mut conn).await;
+        let reporter_id = reports(score_id, &mut conn).await[0].reporter_id;
+
+        // The route answers this with 409
+        assert!(
+            !ScoreReport::create(score_id, reporter_id, "Again", &mut conn)
+                .await
+                .unwrap()
+        );
+        assert_eq!(reports(score_id, &mut conn).await.len(), 2);
+        // Someone else can still report it
+        assert!(
+            ScoreReport::create(score_id, moderator, "Me too", &mut conn)
+                .await
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn resolving_keeps_the_first_resolution() {
+        let Some(mut conn) = testing::db().await else {
+            return;
+        };
+        let (score_id, moderator) = reported_score(&mut conn).await;
+        let other_moderator = testing::insert_player(5, &mut conn).await;
+
+        let report = reports(score_id, &mut conn).await.remove(0);
+        assert_eq!(report.resolve(moderator, &mut conn).await.unwrap(), 1);
+        assert_eq!(report.resolve(other_moderator, &mut conn).await.unwrap(), 0);
+
+        let after_resolving = reports(score_id, &mut conn).await;
+        assert_eq!(after_resolving[0].resolved_by, Some(moderator));
+        assert!(after_resolving[1].resolved_at.is_none());
+
+        // Only the report that was still open is resolved
+        assert_eq!(
+            ScoreReport::resolve_all_for_score(score_id, other_moderator, true, &mut conn)
+                .await
+                .unwrap(),
+            1
+        );
+        assert_eq!(
+            ScoreReport::resolve_all_for_score(score_id, other_moderator, true, &mut conn)
+                .await
+                .unwrap(),
+            0
+        );
+        let after_resolving_all = reports(score_id, &mut conn).await;
+        assert_eq!(after_resolving_all[0].resolved_by, Some(moderator));
+        assert!(!after_resolving_all[0].score_deleted);
+        assert_eq!(after_resolving_all[1].resolved_by, Some(other_moderator));
+        assert!(after_resolving_all[1].score_deleted);
+    }
+}
@@ -3,22 +3,34 @@ use diesel::{
     associations::HasTable,
     backend::Backend,
     deserialize::{self, FromSql},
+    dsl::{min, sql},
     pg::Pg,
     prelude::*,
     serialize,
     serialize::{Output, ToSql},
-    sql_types::SmallInt,
+    sql_types::{BigInt, SmallInt},
 };
-use diesel_async::{AsyncPgConnection, RunQueryDsl};
-use fred::{clients::Pool as RedisPool, prelude::*};
+use diesel_async::{
+    scoped_futures::ScopedFutureExt, AsyncConnection, AsyncPgConnection, RunQueryDsl,
+};
+use fred::{clients::Pool as RedisPool, interfaces::KeysInterface};
 use serde::Serialize;
 use time::OffsetDateTime;
+use tracing::warn;
 use utoipa::ToSchema;
 
 use crate::{
-    models::{players::Player, songs::Song},
+    models::{
+        leaderboard_deltas::PendingLeaderboardDelta, players::Player,
+        score_history::NewArchivedScore, song_daily_plays::SongDailyPlays, songs::Song,
+    },
     schema::scores,
-    util::game_types::{Character, League},
+    util::{
+        game_types::{Character, League},
+        leaderboard::add_skill_points,
+        skill_points::SkillPointPolicy,
+        xstats::DecodedXstats,
+    },
 };
 
 impl ToSql<SmallInt, Pg> for League
@@ -99,44 +111,108 @@ pub struct Score {
     pub gold_threshold: i32,
     pub iss: i32,
     pub isj: i32,
+    /// When the player took the top spot on the song and league.
+    /// Unlike `submitted_at`, this doesn't change when the player improves their own top score.
+    /// Only meaningful for scores that currently are the top score.
+    #[serde(serialize_with = "time::serde::iso8601::serialize")]
+    pub top_since: time::OffsetDateTime,
 }
 
+/// Hash caching the first page of the recent scores feed, with one field per league filter and page size
+pub const RECENT_SCORES_CACHE_KEY: &str = "recent_scores:first_page";
+
+/// Counts the scores ahead of `scores` on the same song and league
+const LEAGUE_RANK_SQL: &str = "(SELECT COUNT(*) FROM scores AS ahead \
+    WHERE ahead.song_id = scores.song_id AND ahead.league = scores.league \
+    AND (ahead.score > scores.score OR (ahead.score = scores.score AND ahead.submitted_at < scores.submitted_at))) + 1";
+
 impl Score {
-    /// Calculates and returns the skill points the player earned for this score.
+    /// Decodes `xstats` according to the character the score was played with.
+    #[must_use]
+    pub fn decoded_xstats(&self) -> DecodedXstats {
+        DecodedXstats::decode(self.vehicle, &self.xstats)
+    }
+
+    /// Calculates and returns the skill points the player earned for this score under `policy`.
     #[must_use]
-    #[allow(clippy::cast_possible_truncation)]
-    pub fn calc_skill_points(&self) -> i32 {
-        let multiplier = (self.league as u32 + 1) * 100;
-        ((f64::from(self.score) / f64::from(self.gold_threshold)) * f64::from(multiplier)).round()
-            as i32
+    pub fn calc_skill_points(&self, policy: &SkillPointPolicy) -> i32 {
+        policy.points(
+            self.score,
+            self.gold_threshold,
+            self.league,
+            self.song_length,
+        )
     }
 
-    /// Deletes the score from the database.
+    /// Adds the score as it is now to its history, before it gets replaced by a better one.
+    ///
+    /// # Errors
+    /// Fails if something is wrong with the database.
+    pub async fn archive(&self, conn: &mut AsyncPgConnection) -> QueryResult<usize> {
+        NewArchivedScore::new(self, self.id).insert(conn).await
+    }
+
+    /// Gets the 1-based position of each score on its song's leaderboard in its league,
+    /// as `(score ID, rank)` pairs. Equal scores are ordered by submission time, like on the leaderboard itself.
+    ///
+    /// # Errors
+    /// Fails if something is wrong with the database.
+    pub async fn league_ranks(
+        score_ids: &[i32],
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Vec<(i32, i64)>> {
+        scores::table
+            .filter(scores::id.eq_any(score_ids))
+            .select((scores::id, sql::<BigInt>(LEAGUE_RANK_SQL)))
+            .load(conn)
+            .await
+    }
+
+    /// Gets the lowest gold threshold of the accepted scores on a song in a league, or `None` if there are none.
+    /// Submissions are checked against this instead of the threshold the client sends along, which it could just raise.
+    ///
+    /// # Errors
+    /// Fails if something is wrong with the database.
+    pub async fn reference_gold_threshold(
+        find_song_id: i32,
+        find_league: League,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Option<i32>> {
+        scores::table
+            .filter(scores::song_id.eq(find_song_id))
+            .filter(scores::league.eq(find_league))
+            .select(min(scores::gold_threshold))
+            .get_result(conn)
+            .await
+    }
+
+    /// Deletes the score from the database, along with its history.
     ///
     /// # Errors
     /// This fails if the database query fails or something goes wrong with Redis.
     pub async fn delete(
         &self,
+        policy: &SkillPointPolicy,
         conn: &mut AsyncPgConnection,
         redis_pool: &RedisPool,
     ) -> anyhow::Result<()> {
         use crate::schema::scores::dsl::*;
 
         // Subtract the skill points from the player on Redis
-        let sub_amount = 0 - self.calc_skill_points();
-        let _: () = redis_pool
-            .zincrby("leaderboard", sub_amount.into(), self.player_id)
-            .await?;
+        let sub_amount = 0 - self.calc_skill_points(policy);
+        add_skill_points(self.player_id, self.league, sub_amount, redis_pool).await?;
 
         diesel::delete(scores.filter(id.eq(self.id)))
             .execute(conn)
             .await?;
+        Song::add_plays(self.song_id, -i64::from(self.play_count), conn).await?;
         Ok(())
     }
 
     /// Retrieves the scores for a specific song and league, for display in-game.
     /// **ALL OF THE `game_get_*` FUNCTIONS ARE ONLY FOR IN-GAME LEADERBOARDS.**
     ///  Therefore, the score count is limited to 11.
+    ///  Equal scores are ordered by submission time, the earlier score wins.
     ///
     /// # Arguments
     /// * `find_song_id` - The ID of the song to find scores for.
@@ -153,7 +229,7 @@ impl Score {
             .inner_join(players::table())
             .filter(song_id.eq(find_song_id))
             .filter(league.eq(find_league))
-            .order(score.desc())
+            .order((score.desc(), submitted_at.asc()))
             .limit(11)
             .load::<(Self, Player)>(conn)
             .await?
@@ -185,7 +261,7 @@ impl Score {
             .filter(song_id.eq(find_song_id))
             .filter(league.eq(find_league))
             .filter(player_id.eq_any(rival_ids))
-            .order(score.desc())
+            .order((score.desc(), submitted_at.asc()))
             .limit(11)
             .load::<(Self, Player)>(conn)
             .await?
@@ -217,7 +293,7 @@ impl Score {
             .filter(song_id.eq(find_song_id))
             .filter(league.eq(find_league))
             .filter(location_id.eq(find_location_id))
-            .order(score.desc())
+            .order((score.desc(), submitted_at.asc()))
             .limit(11)
             .load::<(Self, Player)>(conn)
             .await?
@@ -308,7 +384,12 @@ impl<'a> NewScore<'a> {
 
     /// Creates or updates a score entry in the database.
     ///
+    /// The score is written in a transaction first. The resulting change in skill points is only applied
+    /// to the Redis leaderboard after that transaction committed. If Redis is unavailable, the change is queued
+    /// (see `PendingLeaderboardDelta`) and the submission still succeeds.
+    ///
     /// # Arguments
+    /// * `policy` - How the score's skill points are calculated.
     /// * `conn` - The database connection.
     ///
     /// # Returns
@@ -320,73 +401,179 @@ impl<'a> NewScore<'a> {
     /// - The score fails to be created/retrieved
     pub async fn create_or_update(
         &self,
+        policy: &SkillPointPolicy,
         conn: &mut AsyncPgConnection,
         redis_conn: &RedisPool,
     ) -> anyhow::Result<Score> {
         use crate::schema::scores::dsl::*;
 
-        let existing_score = scores
-            .filter(player_id.eq(self.player_id))
-            .filter(song_id.eq(self.song_id))
-            .filter(league.eq(self.league))
-            .first::<Score>(conn)
-            .await
-            .optional()?;
-
-        if let Some(existing_score) = existing_score {
-            if existing_score.score < self.score {
-                // Subtract the skill points of the old score from the Redis leaderboard
-                let sub_amount = 0 - existing_score.calc_skill_points();
-                let _: () = redis_conn
-                    .zincrby("leaderboard", sub_amount.into(), existing_score.player_id)
-                    .await?;
-
-                let updated_score = diesel::update(scores)
-                    .filter(player_id.eq(self.player_id))
-                    .filter(song_id.eq(self.song_id))
-                    .filter(league.eq(self.league))
-                    .set((
-                        score.eq(self.score),
-                        track_shape.eq(self.track_shape),
-                        xstats.eq(self.xstats),
-                        density.eq(self.density),
-                        vehicle.eq(self.vehicle),
-                        feats.eq(self.feats),
-                        song_length.eq(self.song_length),
-                        gold_threshold.eq(self.gold_threshold),
-                        iss.eq(self.iss),
-                        isj.eq(self.isj),
-                        play_count.eq(play_count + 1),
-                        submitted_at.eq(OffsetDateTime::now_utc()),
-                    ))
-                    .get_result::<Score>(conn)
-                    .await
-                    .context("Failed to update score")?;
-
-                // Add the skill points of the new score to the Redis leaderboard
-                let add_amount = updated_score.calc_skill_points();
-                let _: () = redis_conn
-                    .zincrby("leaderboard", add_amount.into(), updated_score.player_id)
-                    .await?;
-
-                Ok(updated_score)
-            } else {
-                Ok(existing_score)
-            }
-        } else {
-            let new_score = diesel::insert_into(scores)
-                .values(self)
-                .get_result::<Score>(conn)
-                .await
-                .context("Failed to insert score")?;
-
-            // Add the skill points of the new score to the Redis leaderboard
-            let add_amount = new_score.calc_skill_points();
-            let _: () = redis_conn
-                .zincrby("leaderboard", add_amount.into(), new_score.player_id)
-                .await?;
-
-            Ok(new_score)
+        let (result_score, skill_point_delta) = conn
+            .transaction::<_, anyhow::Error, _>(|conn| {
+                async move {
+                    let existing_score = scores
+                        .filter(player_id.eq(self.player_id))
+                        .filter(song_id.eq(self.song_id))
+                        .filter(league.eq(self.league))
+                        .for_update()
+                        .first::<Score>(conn)
+                        .await
+                        .optional()?;
+
+                    if let Some(existing_score) = existing_score {
+                        if existing_score.score < self.score {
+                            // Check if the player was already holding the top spot before this improvement
+                            let higher_scores: i64 = scores
+                                .filter(song_id.eq(self.song_id))
+                                .filter(league.eq(self.league))
+                                .filter(player_id.ne(self.player_id))
+                                .filter(score.ge(existing_score.score))
+                                .count()
+                                .get_result(conn)
+                                .await?;
+                            let now = OffsetDateTime::now_utc();
+                            let new_top_since = improved_top_since(
+                                higher_scores == 0,
+                                existing_score.top_since,
+                                now,
+                            );
+
+                            existing_score
+                                .archive(conn)
+                                .await
+                                .context("Failed to archive score")?;
+                            let updated_score = diesel::update(scores)
+                                .filter(player_id.eq(self.player_id))
+                                .filter(song_id.eq(self.song_id))
+                                .filter(league.eq(self.league))
+                                .set((
+                                    score.eq(self.score),
+                                    track_shape.eq(self.track_shape),
+                                    xstats.eq(self.xstats),
+                                    density.eq(self.density),
+                                    vehicle.eq(self.vehicle),
+                                    feats.eq(self.feats),
+                                    song_length.eq(self.song_length),
+                                    gold_threshold.eq(self.gold_threshold),
+                                    iss.eq(self.iss),
+                                    isj.eq(self.isj),
+                                    play_count.eq(play_count + 1),
+                                    submitted_at.eq(now),
+                                    top_since.eq(new_top_since),
+                                ))
+                                .get_result::<Score>(conn)
+                                .await
+                                .context("Failed to update score")?;
+                            Song::add_plays(self.song_id, 1, conn).await?;
+                            SongDailyPlays::record(self.song_id, conn).await?;
+
+                            let delta = updated_score.calc_skill_points(policy)
+                                - existing_score.calc_skill_points(policy);
+                            Ok((updated_score, delta))
+                        } else {
+                            Ok((existing_score, 0))
+                        }
+                    } else {
+                        let new_score = diesel::insert_into(scores)
+                            .values(self)
+                            .get_result::<Score>(conn)
+                            .await
+                            .context("Failed to insert score")?;
+                        Song::add_plays(self.song_id, new_score.play_count.into(), conn).await?;
+                        SongDailyPlays::record(self.song_id, conn).await?;
+
+                        let delta = new_score.calc_skill_points(policy);
+                        Ok((new_score, delta))
+                    }
+                }
+                .scope_boxed()
+            })
+            .await?;
+
+        if skill_point_delta != 0 {
+            PendingLeaderboardDelta::apply_or_enqueue(
+                result_score.player_id,
+                result_score.league,
+                skill_point_delta,
+                conn,
+                redis_conn,
+            )
+            .await?;
+        }
+
+        // A stale feed is only a cosmetic problem, so this doesn't fail the submission
+        if let Err(e) = redis_conn.del::<(), _>(RECENT_SCORES_CACHE_KEY).await {
+            warn!("Failed to invalidate cached recent scores: {}", e);
         }
+
+        Ok(result_score)
+    }
+}
+
+/// Decides the `top_since` of a score that was just improved.
+/// If the player already held the top spot, their reign continues. Otherwise, it starts now.
+const fn improved_top_since(
+    was_top: bool,
+    previous_top_since: OffsetDateTime,
+    now: OffsetDateTime,
+) -> OffsetDateTime {
+    if was_top {
+        previous_top_since
+    } else {
+        now
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use time::Duration;
+
+    use super::*;
+    use crate::util::testing::{self, RecordedRedis};
+
+    #[tokio::test]
+    async fn reigns_survive_self_improvement_but_not_dethrones() {
+        let Some(mut conn) = testing::db().await else {
+            return;
+        };
+        let redis_conn = testing::redis(Arc::new(RecordedRedis::default())).await;
+        let song_id = testing::insert_song("Dear Music", "A4.", &mut conn).await;
+        let holder = testing::insert_player(1, &mut conn).await;
+        let challenger = testing::insert_player(2, &mut conn).await;
+
+        let first =
+            testing::insert_score(holder, song_id, League::Elite, 1000, &mut conn, &redis_conn)
+                .await;
+        // Read back, since the database only keeps milliseconds
+        let reign_start: OffsetDateTime = diesel::update(scores::table.find(first.id))
+            .set(scores::top_since.eq(OffsetDateTime::now_utc() - Duration::days(90)))
+            .returning(scores::top_since)
+            .get_result(&mut conn)
+            .await
+            .unwrap();
+
+        // Still on top, so the reign goes on even though the score is new
+        let improved =
+            testing::insert_score(holder, song_id, League::Elite, 2000, &mut conn, &redis_conn)
+                .await;
+        assert_eq!(improved.top_since, reign_start);
+        assert!(improved.submitted_at > reign_start);
+
+        // Dethroned, so improving without getting the top spot back starts over
+        let dethroning = testing::insert_score(
+            challenger,
+            song_id,
+            League::Elite,
+            3000,
+            &mut conn,
+            &redis_conn,
+        )
+        .await;
+        assert!(dethroning.top_since > reign_start);
+        let improved =
+            testing::insert_score(holder, song_id, League::Elite, 2500, &mut conn, &redis_conn)
+                .await;
+        assert!(improved.top_since > reign_start + Duration::days(89));
     }
 }
@@ -27,25 +27,76 @@ impl Shout {
         shouts.filter(song_id.eq(target_id)).into_boxed()
     }
 
+    /// Whether a player with this ID and account type may delete the shout:
+    /// its author can, and so can moderators and team members.
+    #[must_use]
+    pub fn can_be_deleted_by(&self, player_id: i32, account_type: AccountType) -> bool {
+        player_id == self.author_id
+            || matches!(account_type, AccountType::Moderator | AccountType::Team)
+    }
+
+    /// Looks up the player and checks [`Self::can_be_deleted_by`].
+    ///
+    /// # Errors
+    /// Fails if the player doesn't exist or something goes wrong with the database.
     pub async fn user_can_delete(
         &self,
         user_id: i32,
         conn: &mut AsyncPgConnection,
     ) -> anyhow::Result<bool> {
-        use crate::schema::players::dsl::*;
-        let player = players.find(user_id).first::<Player>(conn).await?;
-
-        if player.id == self.author_id
-            || player.account_type == AccountType::Moderator
-            || player.account_type == AccountType::Team
-        {
-            Ok(true)
-        } else {
-            Ok(false)
-        }
+        use crate::schema::players;
+
+        let account_type: AccountType = players::table
+            .find(user_id)
+            .select(players::account_type)
+            .first(conn)
+            .await?;
+
+        Ok(self.can_be_deleted_by(user_id, account_type))
     }
 }
 
+/// Longest shout the database takes, in characters
+pub const MAX_SHOUT_LENGTH: usize = 240;
+
+/// Players can post a limited number of shouts in this many seconds
+pub const SHOUT_RATE_LIMIT_WINDOW_SECS: i64 = 60;
+
+/// Counts a player's shouts for rate limiting
+#[must_use]
+pub fn shout_rate_limit_key(author_id: i32) -> String {
+    format!("shouts:rate:{author_id}")
+}
+
+/// Why a shout wasn't posted
+#[derive(Debug, thiserror::Error)]
+pub enum ShoutError {
+    #[error("Shout is empty")]
+    Empty,
+    #[error("Shout is longer than {MAX_SHOUT_LENGTH} characters")]
+    TooLong,
+    #[error("Shout is the same as the author's previous one here")]
+    Duplicate,
+    #[error(transparent)]
+    Database(#[from] diesel::result::Error),
+}
+
+/// Checks a shout's (already trimmed) content on its own: it can't be empty or too long for the database.
+/// Used for song and profile shouts alike.
+///
+/// # Errors
+/// Fails with the reason the content isn't allowed.
+pub fn validate_content(content: &str) -> Result<(), ShoutError> {
+    if content.is_empty() {
+        return Err(ShoutError::Empty);
+    }
+    if content.chars().count() > MAX_SHOUT_LENGTH {
+        return Err(ShoutError::TooLong);
+    }
+
+    Ok(())
+}
+
 #[derive(Insertable, Debug)]
 #[diesel(table_name = shouts)]
 pub struct NewShout<'a> {
@@ -55,25 +106,106 @@ pub struct NewShout<'a> {
 }
 
 impl<'a> NewShout<'a> {
+    /// Whitespace around the content is trimmed off.
     #[must_use]
-    pub const fn new(song_id: i32, author_id: i32, content: &'a str) -> Self {
+    pub fn new(song_id: i32, author_id: i32, content: &'a str) -> Self {
         Self {
             song_id,
             author_id,
-            content,
+            content: content.trim(),
         }
     }
 
-    /// Inserts the shout into the database
+    /// Checks that the shout can be posted: the content has to be valid,
+    /// and it can't repeat the author's previous shout on the same song.
     ///
     /// # Errors
-    /// Fails if something goes wrong with the database
-    pub async fn insert(&self, conn: &mut AsyncPgConnection) -> QueryResult<()> {
+    /// Fails with the reason the shout isn't allowed, or if something goes wrong with the database.
+    pub async fn validate(&self, conn: &mut AsyncPgConnection) -> Result<(), ShoutError> {
         use crate::schema::shouts::dsl::*;
-        diesel::insert_into(shouts)
-            .values(self)
-            .execute(conn)
-            .await?;
+
+        validate_content(self.content)?;
+
+        let previous_content: Option<String> = shouts
+            .filter(author_id.eq(self.author_id))
+            .filter(song_id.eq(self.song_id))
+            .order((posted_at.desc(), id.desc()))
+            .select(content)
+            .first(conn)
+            .await
+            .optional()?;
+        if previous_content.as_deref() == Some(self.content) {
+            return Err(ShoutError::Duplicate);
+        }
+
         Ok(())
     }
+
+    /// Validates the shout and inserts it into the database
+    ///
+    /// # Errors
+    /// Fails if the shout isn't allowed (see [`Self::validate`]) or something goes wrong with the database
+    pub async fn insert(&self, conn: &mut AsyncPgConnection) -> Result<Shout, ShoutError> {
+        use crate::schema::shouts::dsl::*;
+
+        self.validate(conn).await?;
+
+        Ok(diesel::insert_into(shouts)
+            .values(self)
+            .returning(Shout::as_returning())
+            .get_result(conn)
+            .await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shout_by(author_id: i32) -> Shout {
+        Shout {
+            id: 1,
+            song_id: 1,
+            author_id,
+            posted_at: time::OffsetDateTime::UNIX_EPOCH,
+            content: "Great song!".to_owned(),
+        }
+    }
+
+    #[test]
+    fn authors_can_delete_their_shouts() {
+        assert!(shout_by(7).can_be_deleted_by(7, AccountType::User));
+    }
+
+    #[test]
+    fn users_cant_delete_other_shouts() {
+        assert!(!shout_by(7).can_be_deleted_by(8, AccountType::User));
+        assert!(!shout_by(7).can_be_deleted_by(8, AccountType::Banned));
+    }
+
+    #[test]
+    fn moderators_and_team_can_delete_any_shout() {
+        assert!(shout_by(7).can_be_deleted_by(8, AccountType::Moderator));
+        assert!(shout_by(7).can_be_deleted_by(8, AccountType::Team));
+    }
+
+    #[test]
+    fn content_is_trimmed_and_checked() {
+        let shout = NewShout::new(1, 1, "  Great song!\n");
+        assert_eq!(shout.content, "Great song!");
+        assert!(validate_content(shout.content).is_ok());
+
+        assert!(matches!(
+            validate_content(NewShout::new(1, 1, " \t\n ").content),
+            Err(ShoutError::Empty)
+        ));
+
+        let longest = "\u{00E4}".repeat(MAX_SHOUT_LENGTH);
+        assert!(validate_content(NewShout::new(1, 1, &longest).content).is_ok());
+        let too_long = "a".repeat(MAX_SHOUT_LENGTH + 1);
+        assert!(matches!(
+            validate_content(NewShout::new(1, 1, &too_long).content),
+            Err(ShoutError::TooLong)
+        ));
+    }
 }
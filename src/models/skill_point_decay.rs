@@ -0,0 +1,338 @@
+use std::collections::HashMap;
+
+use diesel::{prelude::*, upsert::excluded};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use fred::clients::Pool as RedisPool;
+use time::{Duration, OffsetDateTime};
+use tracing::info;
+
+use crate::{
+    models::players::Player,
+    schema::{players, skill_point_decay},
+    util::{game_types::League, leaderboard::set_skill_points, skill_points::SkillPointPolicy},
+};
+
+/// Skill points of a player decay at most once per this period
+pub const DECAY_PERIOD: Duration = Duration::weeks(1);
+/// Players whose skill points are decayed at once
+const DECAY_BATCH_SIZE: i64 = 500;
+
+/// How much of their skill points an inactive player keeps on the leaderboards.
+///
+/// Only the Redis leaderboards are decayed, their scores stay untouched,
+/// so the true value can always be calculated again.
+#[derive(Identifiable, Selectable, Queryable, Associations, Debug)]
+#[diesel(belongs_to(Player))]
+#[diesel(table_name = skill_point_decay, check_for_backend(diesel::pg::Pg))]
+#[diesel(primary_key(player_id))]
+pub struct SkillPointDecay {
+    pub player_id: i32,
+    /// Factor the true skill points are multiplied with, between the floor and 1
+    pub multiplier: f64,
+    pub applied_at: OffsetDateTime,
+}
+
+impl SkillPointDecay {
+    /// Gets the multipliers of those of `player_ids` whose skill points are decayed.
+    ///
+    /// # Errors
+    /// Fails if something is wrong with the DB.
+    pub async fn multipliers(
+        player_ids: &[i32],
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<HashMap<i32, f64>> {
+        let rows: Vec<(i32, f64)> = skill_point_decay::table
+            .filter(skill_point_decay::player_id.eq_any(player_ids))
+            .select((skill_point_decay::player_id, skill_point_decay::multiplier))
+            .load(conn)
+            .await?;
+
+        Ok(rows.into_iter().collect())
+    }
+
+    /// Decays the skill points of everyone who hasn't played since `inactive_since`,
+    /// unless their skill points already decayed within the last `DECAY_PERIOD` or are at the floor.
+    /// Returns how many players were decayed.
+    ///
+    /// # Errors
+    /// Fails if something goes wrong with the DB or Redis.
+    pub async fn apply(
+        inactive_since: OffsetDateTime,
+        factor: f64,
+        floor: f64,
+        policy: &SkillPointPolicy,
+        conn: &mut AsyncPgConnection,
+        redis_conn: &RedisPool,
+    ) -> anyhow::Result<usize> {
+        let decayed_before = OffsetDateTime::now_utc() - DECAY_PERIOD;
+
+        let mut last_id = 0;
+        let mut decayed = 0;
+        loop {
+            let due: Vec<(i32, Option<f64>)> = players::table
+                .left_join(skill_point_decay::table)
+                .filter(players::id.gt(last_id))
+                // Players who never played count as inactive since they joined
+                .filter(
+                    players::last_played_at
+                        .lt(inactive_since)
+                        .or(players::last_played_at
+                            .is_null()
+                            .and(players::joined_at.lt(inactive_since))),
+                )
+                .filter(
+                    skill_point_decay::applied_at.nullable().is_null().or(
+                        skill_point_decay::applied_at
+                            .nullable()
+                            .lt(decayed_before)
+                            .and(skill_point_decay::multiplier.nullable().gt(floor)),
+                    ),
+                )
+                .order(players::id.asc())
+                .limit(DECAY_BATCH_SIZE)
+                .select((players::id, skill_point_decay::multiplier.nullable()))
+                .load(conn)
+                .await?;
+            let Some(&(batch_last_id, _)) = due.last() else {
+                break;
+            };
+            last_id = batch_last_id;
+
+            let multipliers: Vec<(i32, f64)> = due
+                .into_iter()
+                .map(|(player_id, current)| {
+                    (
+                        player_id,
+                        next_multiplier(current.unwrap_or(1.0), factor, floor),
+                    )
+                })
+                .collect();
+            diesel::insert_into(skill_point_decay::table)
+                .values(
+                    multipliers
+                        .iter()
+                        .map(|&(player_id, multiplier)| {
+                            (
+                                skill_point_decay::player_id.eq(player_id),
+                                skill_point_decay::multiplier.eq(multiplier),
+                            )
+                        })
+                        .collect::<Vec<_>>(),
+                )
+                .on_conflict(skill_point_decay::player_id)
+                .do_update()
+                .set((
+                    skill_point_decay::multiplier.eq(excluded(skill_point_decay::multiplier)),
+                    skill_point_decay::applied_at.eq(diesel::dsl::now),
+                ))
+                .execute(conn)
+                .await?;
+
+            let player_ids: Vec<i32> = multipliers
+                .iter()
+                .map(|&(player_id, _)| player_id)
+                .collect();
+            let calculated = Player::calc_skill_points_batch(&player_ids, policy, conn).await?;
+            for &(player_id, multiplier) in &multipliers {
+                let league_points = league_points_of(&calculated, player_id);
+                set_skill_points(
+                    player_id,
+                    &apply_multiplier(&league_points, Some(multiplier)),
+                    redis_conn,
+                )
+                .await?;
+            }
+            decayed += multipliers.len();
+        }
+
+        Ok(decayed)
+    }
+
+    /// Ends the decay of a player's skill points. Returns whether they were decayed.
+    ///
+    /// # Errors
+    /// Fails if something is wrong with the DB.
+    pub async fn lift(player_id: i32, conn: &mut AsyncPgConnection) -> QueryResult<bool> {
+        let deleted = diesel::delete(skill_point_decay::table.find(player_id))
+            .execute(conn)
+            .await?;
+        Ok(deleted > 0)
+    }
+
+    /// Ends the decay of everyone's skill points. Returns how many players were decayed.
+    ///
+    /// # Errors
+    /// Fails if something is wrong with the DB.
+    pub async fn lift_all(conn: &mut AsyncPgConnection) -> QueryResult<usize> {
+        diesel::delete(skill_point_decay::table).execute(conn).await
+    }
+
+    /// Ends the decay of a player's skill points and puts their true value back on the leaderboards.
+    /// Meant for when they play again.
+    ///
+    /// The decay is only lifted once the leaderboards are updated,
+    /// so if Redis fails, it's tried again the next time they play.
+    ///
+    /// # Errors
+    /// Fails if something goes wrong with the DB or Redis.
+    pub async fn lift_and_restore(
+        player_id: i32,
+        policy: &SkillPointPolicy,
+        conn: &mut AsyncPgConnection,
+        redis_conn: &RedisPool,
+    ) -> anyhow::Result<()> {
+        let decayed = skill_point_decay::table
+            .find(player_id)
+            .select(skill_point_decay::player_id)
+            .first::<i32>(conn)
+            .await
+            .optional()?
+            .is_some();
+        if !decayed {
+            return Ok(());
+        }
+
+        let calculated = Player::calc_skill_points_batch(&[player_id], policy, conn).await?;
+        set_skill_points(
+            player_id,
+            &league_points_of(&calculated, player_id),
+            redis_conn,
+        )
+        .await?;
+        Self::lift(player_id, conn).await?;
+        info!("Restored skill points of player {player_id}, who is active again");
+
+        Ok(())
+    }
+}
+
+/// Picks a player's entries out of the result of `Player::calc_skill_points_batch`.
+pub fn league_points_of(calculated: &[(i32, League, i64)], player_id: i32) -> Vec<(League, i64)> {
+    calculated
+        .iter()
+        .filter(|(points_player_id, _, _)| *points_player_id == player_id)
+        .map(|&(_, league, points)| (league, points))
+        .collect()
+}
+
+/// The multiplier after another round of decay. It never drops below `floor`, or below zero.
+#[must_use]
+pub fn next_multiplier(current: f64, factor: f64, floor: f64) -> f64 {
+    (current * factor).max(floor).clamp(0.0, 1.0)
+}
+
+/// Skill points as they go on the leaderboard, with the decay `multiplier` if there is one.
+#[must_use]
+#[allow(clippy::cast_precision_loss, clippy::cast_possible_truncation)]
+pub fn decay_points(points: i64, multiplier: Option<f64>) -> i64 {
+    multiplier.map_or(points, |multiplier| {
+        ((points as f64 * multiplier).round() as i64).max(0)
+    })
+}
+
+/// `decay_points` for every league.
+#[must_use]
+pub fn apply_multiplier(
+    league_points: &[(League, i64)],
+    multiplier: Option<f64>,
+) -> Vec<(League, i64)> {
+    league_points
+        .iter()
+        .map(|&(league, points)| (league, decay_points(points, multiplier)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::util::testing::{self, Outage, RecordedRedis};
+
+    #[tokio::test]
+    async fn decay_is_only_lifted_once_restored() {
+        let Some(mut conn) = testing::db().await else {
+            return;
+        };
+        let recorded = Arc::new(RecordedRedis::default());
+        let redis = testing::redis(recorded.clone()).await;
+        let policy = SkillPointPolicy::default();
+        // Never played, so `last_played_at` is NULL
+        let player_id = testing::insert_player(1, &mut conn).await;
+
+        let inactive_since = OffsetDateTime::now_utc() + Duration::days(1);
+        let decayed = SkillPointDecay::apply(inactive_since, 0.5, 0.1, &policy, &mut conn, &redis)
+            .await
+            .unwrap();
+        assert_eq!(decayed, 1);
+        // Not again within the same period
+        let decayed = SkillPointDecay::apply(inactive_since, 0.5, 0.1, &policy, &mut conn, &redis)
+            .await
+            .unwrap();
+        assert_eq!(decayed, 0);
+        let multipliers = SkillPointDecay::multipliers(&[player_id], &mut conn)
+            .await
+            .unwrap();
+        assert!((multipliers[&player_id] - 0.5).abs() < f64::EPSILON);
+
+        let outage = testing::redis(Arc::new(Outage)).await;
+        assert!(
+            SkillPointDecay::lift_and_restore(player_id, &policy, &mut conn, &outage)
+                .await
+                .is_err()
+        );
+        let multipliers = SkillPointDecay::multipliers(&[player_id], &mut conn)
+            .await
+            .unwrap();
+        assert!(multipliers.contains_key(&player_id));
+
+        SkillPointDecay::lift_and_restore(player_id, &policy, &mut conn, &redis)
+            .await
+            .unwrap();
+        let multipliers = SkillPointDecay::multipliers(&[player_id], &mut conn)
+            .await
+            .unwrap();
+        assert!(multipliers.is_empty());
+        assert!(recorded.commands().iter().any(|(cmd, _)| cmd == "ZADD"));
+    }
+
+    #[test]
+    fn decay_stops_at_floor() {
+        let mut multiplier = 1.0;
+        for _ in 0..100 {
+            multiplier = next_multiplier(multiplier, 0.9, 0.5);
+        }
+        assert!((multiplier - 0.5).abs() < f64::EPSILON);
+
+        assert!((next_multiplier(1.0, 0.9, 0.5) - 0.9).abs() < f64::EPSILON);
+        assert!(next_multiplier(1.0, -1.0, -0.5) >= 0.0);
+    }
+
+    #[test]
+    fn refresh_reapplies_or_restores() {
+        let league_points = [(League::Casual, 1000), (League::Elite, 333)];
+
+        // Refreshing a decayed player puts the same decayed values back...
+        assert_eq!(
+            apply_multiplier(&league_points, Some(0.81)),
+            vec![(League::Casual, 810), (League::Elite, 270)]
+        );
+        // ...and lifting the decay puts back the true ones
+        assert_eq!(apply_multiplier(&league_points, None), league_points);
+        assert_eq!(decay_points(0, Some(0.5)), 0);
+    }
+
+    #[test]
+    fn league_points_are_picked_per_player() {
+        let calculated = [
+            (1, League::Casual, 100),
+            (2, League::Pro, 200),
+            (1, League::Elite, 300),
+        ];
+        assert_eq!(
+            league_points_of(&calculated, 1),
+            vec![(League::Casual, 100), (League::Elite, 300)]
+        );
+        assert!(league_points_of(&calculated, 3).is_empty());
+    }
+}
@@ -0,0 +1,122 @@
+use diesel::{prelude::*, sql_types::Integer};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use time::{Date, Duration, OffsetDateTime};
+
+use crate::schema::song_daily_plays;
+
+/// How many days of plays are kept, enough for the longest ranking period
+pub const RETENTION_DAYS: i64 = 31;
+
+/// How often a song was played on one (UTC) day.
+/// Unlike `Song::times_played`, this only covers the last `RETENTION_DAYS` days.
+#[derive(Identifiable, Selectable, Queryable, Associations, Debug)]
+#[diesel(belongs_to(super::songs::Song))]
+#[diesel(table_name = song_daily_plays, check_for_backend(diesel::pg::Pg))]
+#[diesel(primary_key(song_id, day))]
+pub struct SongDailyPlays {
+    pub song_id: i32,
+    pub day: Date,
+    pub plays: i32,
+}
+
+impl SongDailyPlays {
+    /// Counts a play of the song today.
+    ///
+    /// # Errors
+    /// Fails if something is wrong with the database.
+    pub async fn record(song_id: i32, conn: &mut AsyncPgConnection) -> QueryResult<()> {
+        diesel::insert_into(song_daily_plays::table)
+            .values((
+                song_daily_plays::song_id.eq(song_id),
+                song_daily_plays::day.eq(OffsetDateTime::now_utc().date()),
+            ))
+            .on_conflict((song_daily_plays::song_id, song_daily_plays::day))
+            .do_update()
+            .set(song_daily_plays::plays.eq(song_daily_plays::plays + 1))
+            .execute(conn)
+            .await?;
+        Ok(())
+    }
+
+    /// Adds the daily plays of song `from` to the ones of song `to`, for merging songs.
+    /// The rows of `from` are left alone, they're deleted along with it.
+    ///
+    /// # Errors
+    /// Fails if something is wrong with the database.
+    pub async fn merge(from: i32, to: i32, conn: &mut AsyncPgConnection) -> QueryResult<()> {
+        diesel::sql_query(
+            "INSERT INTO song_daily_plays (song_id, day, plays) \
+            SELECT $2, day, plays FROM song_daily_plays WHERE song_id = $1 \
+            ON CONFLICT (song_id, day) DO UPDATE SET plays = song_daily_plays.plays + EXCLUDED.plays",
+        )
+        .bind::<Integer, _>(from)
+        .bind::<Integer, _>(to)
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// First day that counts for a period of `days` days, today included.
+    #[must_use]
+    pub fn window_start(days: i64) -> Date {
+        OffsetDateTime::now_utc().date() - Duration::days(days - 1)
+    }
+
+    /// Deletes plays older than `RETENTION_DAYS`.
+    ///
+    /// # Returns
+    /// The number of deleted rows.
+    ///
+    /// # Errors
+    /// Fails if something is wrong with the database.
+    pub async fn prune(conn: &mut AsyncPgConnection) -> QueryResult<usize> {
+        diesel::delete(
+            song_daily_plays::table
+                .filter(song_daily_plays::day.lt(Self::window_start(RETENTION_DAYS))),
+        )
+        .execute(conn)
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::testing;
+
+    async fn plays_today(song_id: i32, conn: &mut AsyncPgConnection) -> i32 {
+        song_daily_plays::table
+            .find((song_id, OffsetDateTime::now_utc().date()))
+            .select(song_daily_plays::plays)
+            .first(conn)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn merged_plays_are_added_up() {
+        let Some(mut conn) = testing::db().await else {
+            return;
+        };
+        let source = testing::insert_song("Dear Music", "A4", &mut conn).await;
+        let target = testing::insert_song("Dear Music", "A4.", &mut conn).await;
+        let untouched = testing::insert_song("Owaranai", "A4.", &mut conn).await;
+        for song_id in [source, source, target, untouched] {
+            SongDailyPlays::record(song_id, &mut conn).await.unwrap();
+        }
+
+        SongDailyPlays::merge(source, target, &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(plays_today(target, &mut conn).await, 3);
+        assert_eq!(plays_today(untouched, &mut conn).await, 1);
+
+        // Merging a song without recent plays changes nothing
+        let quiet = testing::insert_song("Silence", "Nobody", &mut conn).await;
+        SongDailyPlays::merge(quiet, target, &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(plays_today(target, &mut conn).await, 3);
+    }
+}
@@ -0,0 +1,64 @@
+use diesel::{
+    prelude::*,
+    sql_types::{BigInt, Integer, Text},
+};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use super::{players::Player, songs::Song};
+use crate::schema::song_tag_samples;
+
+/// The maximum number of distinct tag variants stored per song.
+pub const MAX_SAMPLES_PER_SONG: i64 = 10;
+
+/// The title and artist exactly as a client submitted them for a song, before modifiers were stripped.
+/// Only the first few distinct variants per song are kept, so moderators can see why songs ended up split.
+#[derive(Identifiable, Selectable, Queryable, Associations, Debug, Serialize, ToSchema)]
+#[diesel(belongs_to(Player))]
+#[diesel(belongs_to(Song))]
+#[diesel(table_name = song_tag_samples, check_for_backend(diesel::pg::Pg))]
+#[diesel(primary_key(id))]
+#[serde(rename_all = "camelCase")]
+pub struct SongTagSample {
+    pub id: i32,
+    pub song_id: i32,
+    /// The player who first submitted this variant
+    pub player_id: i32,
+    pub title: String,
+    pub artist: String,
+    #[serde(serialize_with = "time::serde::iso8601::serialize")]
+    pub created_at: time::OffsetDateTime,
+}
+
+impl SongTagSample {
+    /// Stores the raw title and artist a player submitted for a song.
+    /// Nothing is stored if the variant was seen before or the song already has `MAX_SAMPLES_PER_SONG` samples.
+    ///
+    /// # Errors
+    /// Fails if something is wrong with the database.
+    pub async fn record(
+        song_id: i32,
+        player_id: i32,
+        title: &str,
+        artist: &str,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<()> {
+        // Checking the cap in the same statement keeps concurrent submissions from going far over it
+        diesel::sql_query(
+            "INSERT INTO song_tag_samples (song_id, player_id, title, artist) \
+            SELECT $1, $2, $3, $4 \
+            WHERE (SELECT COUNT(*) FROM song_tag_samples WHERE song_id = $1) < $5 \
+            ON CONFLICT (song_id, title, artist) DO NOTHING",
+        )
+        .bind::<Integer, _>(song_id)
+        .bind::<Integer, _>(player_id)
+        .bind::<Text, _>(title)
+        .bind::<Text, _>(artist)
+        .bind::<BigInt, _>(MAX_SAMPLES_PER_SONG)
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+}
@@ -1,5 +1,9 @@
+use std::collections::HashMap;
+
 use diesel::prelude::*;
-use diesel_async::{AsyncPgConnection, RunQueryDsl, SaveChangesDsl};
+use diesel_async::{
+    scoped_futures::ScopedFutureExt, AsyncConnection, AsyncPgConnection, RunQueryDsl,
+};
 use fred::clients::Pool as RedisPool;
 use serde::Serialize;
 use tracing::debug;
@@ -7,11 +11,21 @@ use utoipa::ToSchema;
 
 use crate::{
     models::{
-        extra_song_info::{ExtraSongInfo, NewExtraSongInfo},
+        extra_song_info::ExtraSongInfo,
+        leaderboard_deltas::PendingLeaderboardDelta,
         players::{AccountType, Player},
+        score_history::NewArchivedScore,
         scores::Score,
+        song_daily_plays::SongDailyPlays,
     },
     schema::{extra_song_info, songs},
+    util::{
+        covers::CachedCovers,
+        game_types::League,
+        meilisearch::{self, SongIndex},
+        normalize::normalize,
+        skill_points::SkillPointPolicy,
+    },
 };
 
 #[derive(
@@ -27,133 +41,445 @@ pub struct Song {
     pub artist: String,
     #[serde(serialize_with = "time::serde::iso8601::serialize")]
     pub created_at: time::OffsetDateTime,
+    /// Modifiers from `[as-...]` tags at the end of the title (e.g. `["steep"]` for `[as-steep]`), `null` if there were none
     pub modifiers: Option<Vec<Option<String>>>,
+    /// Hides the song from charts and stats (e.g. for test tones), its own page and leaderboard still work.
+    /// Only shown to moderators.
+    #[serde(skip)]
+    pub excluded_from_rankings: bool,
+    /// Sum of the play counts of the song's scores, see [`Song::add_plays`]
+    #[serde(skip)]
+    pub times_played: i64,
+    /// What songs are matched by, see [`crate::util::normalize`]
+    #[serde(skip)]
+    pub title_normalized: String,
+    #[serde(skip)]
+    pub artist_normalized: String,
+    /// When anything the search index holds about the song last changed, including its extra info.
+    /// Kept up to date by the database, see [`crate::util::meilisearch::sync_songs`].
+    #[serde(skip)]
+    pub updated_at: time::OffsetDateTime,
+}
+
+/// Songs that count for charts and stats, see [`Song::excluded_from_rankings`].
+pub type RankedSongs =
+    diesel::dsl::Filter<songs::table, diesel::dsl::Eq<songs::excluded_from_rankings, bool>>;
+
+/// Songs with the same normalized title, artist and modifiers, see [`Song::duplicate_groups`]
+const DUPLICATE_GROUPS: &str = "SELECT array_agg(id ORDER BY id) AS ids FROM songs \
+    GROUP BY title_normalized, artist_normalized, modifiers HAVING count(*) > 1";
+
+/// Row of [`DUPLICATE_GROUPS`]
+#[derive(QueryableByName)]
+struct DuplicateGroupIds {
+    #[diesel(sql_type = diesel::sql_types::Array<diesel::sql_types::Integer>)]
+    ids: Vec<i32>,
+}
+
+/// Row of [`Song::count_duplicate_groups`]
+#[derive(QueryableByName)]
+struct DuplicateGroupCount {
+    #[diesel(sql_type = diesel::sql_types::BigInt)]
+    count: i64,
+}
+
+/// A song that has duplicates, with how many scores it has, see [`Song::duplicate_groups`].
+#[derive(Serialize, ToSchema, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct DuplicateSong {
+    #[serde(flatten)]
+    pub song: Song,
+    pub score_count: i64,
+}
+
+/// Sorts a group of duplicates so the song the others should be merged into comes first:
+/// the one with the most scores, or the oldest if that's a tie.
+fn sort_for_merging(group: &mut [DuplicateSong]) {
+    group.sort_by_key(|member| (std::cmp::Reverse(member.score_count), member.song.id));
 }
 
 impl Song {
-    /// Deletes the song from the database.
+    /// Query of all songs that aren't excluded from rankings.
+    /// Use this instead of `songs::table` for anything that lists songs by popularity or counts them.
+    #[must_use]
+    pub fn ranked() -> RankedSongs {
+        songs::table.filter(songs::excluded_from_rankings.eq(false))
+    }
+
+    /// Excludes the song from rankings or includes it again.
+    ///
+    /// # Errors
+    /// Fails if the song doesn't exist or something is wrong with the DB.
+    pub async fn set_excluded_from_rankings(
+        song_id: i32,
+        excluded: bool,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Self> {
+        diesel::update(songs::table.find(song_id))
+            .set(songs::excluded_from_rankings.eq(excluded))
+            .get_result(conn)
+            .await
+    }
+
+    /// Adds to (or with a negative `plays`, subtracts from) the song's play counter.
+    /// Has to be called wherever the play counts of its scores change, so rankings don't need to add them up.
+    ///
+    /// # Errors
+    /// Fails if something is wrong with the DB.
+    pub async fn add_plays(
+        song_id: i32,
+        plays: i64,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<()> {
+        diesel::update(songs::table.find(song_id))
+            .set(songs::times_played.eq(songs::times_played + plays))
+            .execute(conn)
+            .await?;
+        Ok(())
+    }
+
+    /// Recounts the play counters of all songs from their scores, in case they drifted.
+    ///
+    /// # Returns
+    /// The number of songs whose counter was wrong.
+    ///
+    /// # Errors
+    /// Fails if something is wrong with the DB.
+    pub async fn recount_plays(conn: &mut AsyncPgConnection) -> QueryResult<usize> {
+        diesel::sql_query(
+            "UPDATE songs SET times_played = counted.plays \
+            FROM (SELECT songs.id, COALESCE(SUM(scores.play_count), 0) AS plays \
+                FROM songs LEFT JOIN scores ON scores.song_id = songs.id GROUP BY songs.id) counted \
+            WHERE songs.id = counted.id AND songs.times_played <> counted.plays",
+        )
+        .execute(conn)
+        .await
+    }
+
+    /// Finds songs that only differ in ways normalization ignores, e.g. case or "&" vs. "and",
+    /// and have the same modifiers. They should probably be merged.
+    ///
+    /// # Returns
+    /// The groups of duplicates, ordered by their oldest song. Each group starts with the song
+    /// the others should be merged into: the one with the most scores, or the oldest if that's a tie.
+    ///
+    /// # Errors
+    /// Fails if something is wrong with the DB.
+    pub async fn duplicate_groups(
+        limit: Option<i64>,
+        offset: i64,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<Vec<Vec<DuplicateSong>>> {
+        use diesel::{
+            dsl::count_star,
+            sql_types::{BigInt, Nullable},
+        };
+
+        use crate::schema::scores;
+
+        let groups: Vec<DuplicateGroupIds> = diesel::sql_query(format!(
+            "{DUPLICATE_GROUPS} ORDER BY min(id) LIMIT $1 OFFSET $2"
+        ))
+        .bind::<Nullable<BigInt>, _>(limit)
+        .bind::<BigInt, _>(offset)
+        .load(conn)
+        .await?;
+        let ids: Vec<i32> = groups.iter().flat_map(|group| group.ids.clone()).collect();
+
+        let mut songs_by_id: HashMap<i32, Self> = songs::table
+            .filter(songs::id.eq_any(&ids))
+            .select(Self::as_select())
+            .load::<Self>(conn)
+            .await?
+            .into_iter()
+            .map(|song| (song.id, song))
+            .collect();
+        let score_counts: HashMap<i32, i64> = scores::table
+            .filter(scores::song_id.eq_any(&ids))
+            .group_by(scores::song_id)
+            .select((scores::song_id, count_star()))
+            .load::<(i32, i64)>(conn)
+            .await?
+            .into_iter()
+            .collect();
+
+        Ok(groups
+            .into_iter()
+            .map(|group| {
+                let mut members: Vec<DuplicateSong> = group
+                    .ids
+                    .iter()
+                    .filter_map(|id| songs_by_id.remove(id))
+                    .map(|song| DuplicateSong {
+                        score_count: score_counts.get(&song.id).copied().unwrap_or_default(),
+                        song,
+                    })
+                    .collect();
+                sort_for_merging(&mut members);
+                members
+            })
+            .collect())
+    }
+
+    /// How many groups [`Song::duplicate_groups`] finds in total.
+    ///
+    /// # Errors
+    /// Fails if something is wrong with the DB.
+    pub async fn count_duplicate_groups(conn: &mut AsyncPgConnection) -> QueryResult<i64> {
+        let counted: DuplicateGroupCount = diesel::sql_query(format!(
+            "SELECT count(*) AS count FROM ({DUPLICATE_GROUPS}) duplicates"
+        ))
+        .get_result(conn)
+        .await?;
+        Ok(counted.count)
+    }
+
+    /// Deletes the song from the database and from the search index, if one is passed.
+    ///
+    /// Its scores, shouts, extra info and everything else belonging to it are deleted explicitly in one transaction,
+    /// so this doesn't depend on foreign keys cascading. The scores' skill points are only taken off
+    /// the leaderboard after it committed.
     ///
     /// # Errors
     /// Fails if something is wrong with the DB or with Redis.
     pub async fn delete(
         &self,
+        policy: &SkillPointPolicy,
         conn: &mut AsyncPgConnection,
         redis_conn: &RedisPool,
+        search: Option<&dyn SongIndex>,
     ) -> anyhow::Result<()> {
         use crate::schema::{
-            scores::dsl::{scores, song_id},
-            songs::dsl::{id, songs},
+            player_favorite_songs, radio_entries, rejected_scores, score_history, scores, shouts,
+            song_daily_plays, song_tag_samples,
         };
 
-        // Manually delete all of the song's scores with our own Score::delete().
-        // Necessary because we have to subtract the skill points from Redis
-        // Diesel doesn't provide hooks to do it automatically
-        let ass_scores: Vec<Score> = scores
-            .filter(song_id.eq(self.id))
-            .load::<Score>(conn)
+        let skill_point_deltas = conn
+            .transaction::<_, anyhow::Error, _>(|conn| {
+                async move {
+                    let song_scores: Vec<Score> = Score::belonging_to(self)
+                        .select(Score::as_select())
+                        .for_update()
+                        .load::<Score>(conn)
+                        .await?;
+                    let score_ids: Vec<i32> = song_scores.iter().map(|score| score.id).collect();
+
+                    diesel::delete(
+                        score_history::table.filter(score_history::score_id.eq_any(&score_ids)),
+                    )
+                    .execute(conn)
+                    .await?;
+                    diesel::delete(scores::table.filter(scores::id.eq_any(&score_ids)))
+                        .execute(conn)
+                        .await?;
+                    diesel::delete(shouts::table.filter(shouts::song_id.eq(self.id)))
+                        .execute(conn)
+                        .await?;
+                    diesel::delete(
+                        extra_song_info::table.filter(extra_song_info::song_id.eq(self.id)),
+                    )
+                    .execute(conn)
+                    .await?;
+                    // Unpin the song from everyone's profile
+                    diesel::delete(
+                        player_favorite_songs::table
+                            .filter(player_favorite_songs::song_id.eq(self.id)),
+                    )
+                    .execute(conn)
+                    .await?;
+                    diesel::delete(
+                        song_tag_samples::table.filter(song_tag_samples::song_id.eq(self.id)),
+                    )
+                    .execute(conn)
+                    .await?;
+                    diesel::delete(
+                        song_daily_plays::table.filter(song_daily_plays::song_id.eq(self.id)),
+                    )
+                    .execute(conn)
+                    .await?;
+                    diesel::delete(
+                        rejected_scores::table.filter(rejected_scores::song_id.eq(self.id)),
+                    )
+                    .execute(conn)
+                    .await?;
+                    diesel::delete(radio_entries::table.filter(radio_entries::song_id.eq(self.id)))
+                        .execute(conn)
+                        .await?;
+                    diesel::delete(songs::table.find(self.id))
+                        .execute(conn)
+                        .await?;
+
+                    Ok(lost_skill_points(&song_scores, policy))
+                }
+                .scope_boxed()
+            })
             .await?;
-        for score in ass_scores {
-            score.delete(conn, redis_conn).await?;
+
+        for ((player_id, league), delta) in skill_point_deltas {
+            if delta != 0 {
+                PendingLeaderboardDelta::apply_or_enqueue(
+                    player_id, league, delta, conn, redis_conn,
+                )
+                .await?;
+            }
         }
 
-        diesel::delete(songs.filter(id.eq(self.id)))
-            .execute(conn)
-            .await?;
+        meilisearch::forget_songs(search, &[self.id]).await;
         Ok(())
     }
 
     /// Merges this song into another one. `self` will be deleted when it's done.
     ///
+    /// Where both songs have a score of the same player and league, the higher one is kept
+    /// (the target's on a tie) and gets the play counts of both. See `plan_score_merge`.
+    /// Everything happens in one transaction. The skill points of dropped scores are only
+    /// taken off the leaderboard after it committed, same for updating the search index.
+    ///
     /// # Errors
     /// When the merge fails or something is wrong with the database, this fails.
     pub async fn merge_into(
         &self,
         target: i32,
         should_alias: bool,
+        policy: &SkillPointPolicy,
         conn: &mut AsyncPgConnection,
         redis_pool: &RedisPool,
+        search: Option<&dyn SongIndex>,
     ) -> anyhow::Result<()> {
-        use crate::schema::{scores::dsl::*, songs::dsl::*};
+        use crate::schema::{player_favorite_songs, radio_entries, score_history, scores, shouts};
 
-        let target = songs.find(target).first::<Self>(conn).await?;
-        let mut target_scores: Vec<Score> = Score::belonging_to(&target)
-            .select(Score::as_select())
-            .load::<Score>(conn)
-            .await?;
-        let own_scores: Vec<Score> = Score::belonging_to(&self)
-            .select(Score::as_select())
-            .load::<Score>(conn)
-            .await?;
+        debug!("Merging song {} into {}", self.id, target);
+
+        let skill_point_deltas = conn
+            .transaction::<_, anyhow::Error, _>(|conn| {
+                async move {
+                    let target = songs::table.find(target).first::<Self>(conn).await?;
+                    // Locked, so scores submitted during the merge can't get lost
+                    let target_scores: Vec<Score> = Score::belonging_to(&target)
+                        .select(Score::as_select())
+                        .for_update()
+                        .load::<Score>(conn)
+                        .await?;
+                    let own_scores: Vec<Score> = Score::belonging_to(self)
+                        .select(Score::as_select())
+                        .for_update()
+                        .load::<Score>(conn)
+                        .await?;
+
+                    let plan = plan_score_merge(&own_scores, &target_scores, policy);
+
+                    // Dropped scores become part of the history of the score they lost against,
+                    // along with their own history
+                    for dropped_score in own_scores
+                        .iter()
+                        .chain(&target_scores)
+                        .filter(|score| plan.dropped.contains(&score.id))
+                    {
+                        let survivor_id = plan.replaced_by[&dropped_score.id];
+                        diesel::update(
+                            score_history::table
+                                .filter(score_history::score_id.eq(dropped_score.id)),
+                        )
+                        .set(score_history::score_id.eq(survivor_id))
+                        .execute(conn)
+                        .await?;
+                        NewArchivedScore::new(dropped_score, survivor_id)
+                            .insert(conn)
+                            .await?;
+                    }
 
-        debug!("Merging song {} into {}", self.id, target.id);
-
-        for mut own_score in own_scores {
-            // Find score with same player and league in the target song
-            match target_scores.iter_mut().find(|found_score| {
-                found_score.player_id == own_score.player_id
-                    && found_score.league == own_score.league
-            }) {
-                Some(target_score) => {
-                    // If the score on the song we want to merge into is lower, we delete that score
-                    // then, we add our song's score to the merge target song
-                    if target_score.score < own_score.score {
-                        target_score.delete(conn, redis_pool).await?;
-                        own_score.song_id = target.id;
-                        own_score.play_count += target_score.play_count;
-                        own_score.save_changes::<Score>(conn).await?;
-                    } else {
-                        target_score.play_count += own_score.play_count;
-                        target_score.save_changes::<Score>(conn).await?;
-                        own_score.delete(conn, redis_pool).await?;
+                    // Dropped scores go first, so moved ones don't clash with them
+                    diesel::delete(scores::table.filter(scores::id.eq_any(&plan.dropped)))
+                        .execute(conn)
+                        .await?;
+                    for (score_id, new_play_count) in &plan.moved {
+                        diesel::update(scores::table.find(score_id))
+                            .set((
+                                scores::song_id.eq(target.id),
+                                scores::play_count.eq(new_play_count),
+                            ))
+                            .execute(conn)
+                            .await?;
                     }
-                }
-                None => {
-                    diesel::update(&own_score)
-                        .set(song_id.eq(target.id))
+                    for (score_id, new_play_count) in &plan.kept {
+                        diesel::update(scores::table.find(score_id))
+                            .set(scores::play_count.eq(new_play_count))
+                            .execute(conn)
+                            .await?;
+                    }
+                    // The plan keeps the total play count, so all of ours go to the target
+                    let own_plays: i64 = own_scores
+                        .iter()
+                        .map(|score| i64::from(score.play_count))
+                        .sum();
+                    Self::add_plays(target.id, own_plays, conn).await?;
+                    // Same for the plays of the last days, so the weekly and monthly rankings keep them
+                    SongDailyPlays::merge(self.id, target.id, conn).await?;
+
+                    // Move pinned favorites over to the target song.
+                    // Players who already pinned the target keep that entry, ours gets deleted with this song.
+                    let target_pinned_by: Vec<i32> = player_favorite_songs::table
+                        .filter(player_favorite_songs::song_id.eq(target.id))
+                        .select(player_favorite_songs::player_id)
+                        .load::<i32>(conn)
+                        .await?;
+                    diesel::update(
+                        player_favorite_songs::table
+                            .filter(player_favorite_songs::song_id.eq(self.id))
+                            .filter(player_favorite_songs::player_id.ne_all(target_pinned_by)),
+                    )
+                    .set(player_favorite_songs::song_id.eq(target.id))
+                    .execute(conn)
+                    .await?;
+
+                    diesel::update(shouts::table.filter(shouts::song_id.eq(self.id)))
+                        .set(shouts::song_id.eq(target.id))
+                        .execute(conn)
+                        .await?;
+                    // Radio songs keep playing, their scores just end up on the target
+                    diesel::update(radio_entries::table.filter(radio_entries::song_id.eq(self.id)))
+                        .set(radio_entries::song_id.eq(target.id))
                         .execute(conn)
                         .await?;
+
+                    if should_alias {
+                        self.add_as_alias_of(&target, conn).await?;
+                    }
+
+                    // All of our scores were moved or dropped by now, so this doesn't touch the leaderboard
+                    self.delete(policy, conn, redis_pool, None).await?;
+
+                    Ok(plan.skill_point_deltas)
                 }
+                .scope_boxed()
+            })
+            .await?;
+
+        for ((player_id, league), delta) in skill_point_deltas {
+            if delta != 0 {
+                PendingLeaderboardDelta::apply_or_enqueue(
+                    player_id, league, delta, conn, redis_pool,
+                )
+                .await?;
             }
         }
 
+        meilisearch::forget_songs(search, &[self.id]).await;
         if should_alias {
-            let target_extra_info: Option<ExtraSongInfo> = ExtraSongInfo::belonging_to(&target)
-                .select(ExtraSongInfo::as_select())
-                .first::<ExtraSongInfo>(conn)
-                .await
-                .optional()?;
-
-            if let Some(target_extra_info) = target_extra_info {
-                //This doesn't merge our own alias list into the target's!
-                //*Only our artist and title fields* are added to the target's aliases.
-                target_extra_info
-                    .aliases_artist
-                    .clone()
-                    .unwrap_or_default()
-                    .push(Some(self.artist.clone()));
-                target_extra_info
-                    .aliases_title
-                    .clone()
-                    .unwrap_or_default()
-                    .push(Some(self.title.clone()));
-
-                target_extra_info
-                    .save_changes::<ExtraSongInfo>(conn)
-                    .await?;
-            } else {
-                let new_extra_info = NewExtraSongInfo {
-                    song_id: target.id,
-                    aliases_artist: Some(vec![self.artist.clone()]),
-                    aliases_title: Some(vec![self.title.clone()]),
-                    ..Default::default()
-                };
-                new_extra_info.insert(conn).await?;
-            }
+            meilisearch::reindex_song(search, target, conn).await;
         }
 
-        //Delete this song!
-        self.delete(conn, redis_pool).await?;
+        Ok(())
+    }
 
+    /// Adds our title and artist to the target's aliases.
+    /// This doesn't merge our own alias list into the target's!
+    async fn add_as_alias_of(
+        &self,
+        target: &Self,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<()> {
+        ExtraSongInfo::add_aliases(target.id, Some(&self.title), Some(&self.artist), conn).await?;
         Ok(())
     }
 
@@ -161,36 +487,47 @@ impl Song {
     /// Automatically adds extra metadata from [MusicBrainz](https://musicbrainz.org) to the song if it doesn't have any.
     ///
     /// This function doesn't check if an existing `ExtraSongInfo` struct lacks info.
-    /// It bails if it finds an existing struct *at all.*
+    /// It bails if the caller passes an existing struct *at all.*
+    ///
+    /// # Arguments
+    /// * `extra_info` - The song's current extra info, which the caller is expected to have loaded along with the song.
+    ///
+    /// # Returns
+    /// Whether metadata was added, i.e. MusicBrainz knew the song.
     ///
     /// # Errors
     /// Fails on database error or if the MusicBrainz lookup fails.
     pub async fn auto_add_metadata(
         &self,
         duration: i32,
+        extra_info: Option<&ExtraSongInfo>,
         conn: &mut AsyncPgConnection,
-    ) -> anyhow::Result<()> {
+    ) -> anyhow::Result<bool> {
         use crate::util::musicbrainz::lookup_metadata;
 
-        let extra_info = ExtraSongInfo::belonging_to(self)
-            .select(ExtraSongInfo::as_select())
-            .first::<ExtraSongInfo>(conn)
-            .await
-            .optional()?;
-
         if extra_info.is_none() {
-            let metadata = lookup_metadata(self, duration).await?;
-            if metadata.is_none() {
-                return Ok(());
-            }
+            let Some(metadata) = lookup_metadata(self, duration).await? else {
+                return Ok(false);
+            };
+            let covers = CachedCovers::fetch(
+                metadata.cover_url.as_deref(),
+                metadata.cover_url_small.as_deref(),
+            )
+            .await;
 
             diesel::insert_into(extra_song_info::table)
-                .values((metadata, extra_song_info::song_id.eq(self.id)))
+                .values((
+                    metadata,
+                    extra_song_info::song_id.eq(self.id),
+                    extra_song_info::cover_file.eq(covers.cover_file.flatten()),
+                    extra_song_info::cover_file_small.eq(covers.cover_file_small.flatten()),
+                ))
                 .execute(conn)
                 .await?;
+            return Ok(true);
         }
 
-        Ok(())
+        Ok(false)
     }
 
     #[allow(clippy::doc_markdown)]
@@ -215,15 +552,26 @@ impl Song {
             .optional()?;
 
         let mb_info = lookup_mbid(mbid, release_mbid).await?;
+        let covers = CachedCovers::fetch_changed(
+            mb_info.cover_url.as_deref(),
+            mb_info.cover_url_small.as_deref(),
+            existing_info.as_ref(),
+        )
+        .await;
 
         if let Some(existing_info) = existing_info {
             diesel::update(&existing_info)
-                .set(mb_info)
+                .set((mb_info, covers))
                 .execute(conn)
                 .await?;
         } else {
             diesel::insert_into(extra_song_info::table)
-                .values((mb_info, extra_song_info::song_id.eq(self.id)))
+                .values((
+                    mb_info,
+                    extra_song_info::song_id.eq(self.id),
+                    extra_song_info::cover_file.eq(covers.cover_file.flatten()),
+                    extra_song_info::cover_file_small.eq(covers.cover_file_small.flatten()),
+                ))
                 .execute(conn)
                 .await?;
         }
@@ -321,6 +669,8 @@ pub struct NewSong<'a> {
     pub title: &'a str,
     pub artist: &'a str,
     pub modifiers: Option<Vec<&'a str>>,
+    title_normalized: String,
+    artist_normalized: String,
 }
 
 impl<'a> NewSong<'a> {
@@ -333,11 +683,13 @@ impl<'a> NewSong<'a> {
     /// # Returns
     /// A new `NewSong` instance.
     #[must_use]
-    pub const fn new(title: &'a str, artist: &'a str, modifiers: Option<Vec<&'a str>>) -> Self {
+    pub fn new(title: &'a str, artist: &'a str, modifiers: Option<Vec<&'a str>>) -> Self {
         Self {
             title,
             artist,
             modifiers,
+            title_normalized: normalize(title),
+            artist_normalized: normalize(artist),
         }
     }
 
@@ -352,45 +704,552 @@ impl<'a> NewSong<'a> {
     /// # Errors
     /// This fails if the query or DB connection fail.
     pub async fn find_or_create(&self, conn: &mut AsyncPgConnection) -> QueryResult<Song> {
-        use diesel::sql_types::{Nullable, Text};
+        self.find_or_create_with_mbid(None, conn)
+            .await
+            .map(|(song, _)| song)
+    }
 
-        use crate::schema::{
-            extra_song_info::dsl::{
-                aliases_artist, aliases_title, musicbrainz_artist, musicbrainz_title,
-            },
-            songs::dsl::{artist, title},
+    /// Like [`Self::find_or_create`], but a song with the recording MBID `recording_mbid` and the same modifiers
+    /// is found even if its tags don't match, and preferred over songs that only match by tags.
+    /// It's all one query, so the game's song lookup doesn't need another one for the MBID.
+    ///
+    /// # Returns
+    /// The song, with its extra info if it has some. New songs never have any.
+    ///
+    /// # Errors
+    /// This fails if the query or DB connection fail.
+    pub async fn find_or_create_with_mbid(
+        &self,
+        recording_mbid: Option<&str>,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<(Song, Option<ExtraSongInfo>)> {
+        use crate::schema::extra_song_info::dsl::{
+            aliases_artist, aliases_title, mbid, musicbrainz_artist_normalized,
+            musicbrainz_title_normalized,
         };
 
-        // diesel doesn't have support for the lower function out of the box
-        define_sql_function!(fn lower(x: Nullable<Text> ) -> Nullable<Text>);
-
-        // the alias arrays and the musicbrainz data have to play by the game's rules
-        // or else we can never match them with what the game sends!
-        // for arrays: lowercase (the lower function wont work on arrays)
-        // for all of them: "&" replaced with "and", potentially other changes by the client too!
-        // can we fix this in the hook? what do we do?!
-        let title_predicate = title.eq(self.title).or(lower(musicbrainz_title)
-            .eq(self.title)
-            .or(aliases_title.contains(vec![self.title])));
-        let artist_predicate = artist.eq(self.artist).or(lower(musicbrainz_artist)
-            .eq(self.artist)
-            .or(aliases_artist.contains(vec![self.artist])));
-
-        match songs::table
+        // Everything is compared normalized, since the game, MusicBrainz and whoever entered aliases
+        // all disagree on "&", quotes, dashes and case
+        let title_predicate = songs::title_normalized
+            .eq(&self.title_normalized)
+            .or(musicbrainz_title_normalized.eq(&self.title_normalized))
+            .or(aliases_title.contains(vec![self.title_normalized.as_str()]));
+        let artist_predicate = songs::artist_normalized
+            .eq(&self.artist_normalized)
+            .or(musicbrainz_artist_normalized.eq(&self.artist_normalized))
+            .or(aliases_artist.contains(vec![self.artist_normalized.as_str()]));
+        let tags_predicate = title_predicate.and(artist_predicate);
+
+        let mut query = songs::table
             .left_join(extra_song_info::table)
             .select((Song::as_select(), Option::<ExtraSongInfo>::as_select()))
-            .filter(title_predicate.and(artist_predicate))
+            .filter(songs::modifiers.is_not_distinct_from(&self.modifiers))
+            .into_boxed();
+        query = match recording_mbid {
+            Some(recording_mbid) => query
+                .filter(mbid.eq(recording_mbid).or(tags_predicate))
+                .order(mbid.is_distinct_from(recording_mbid)),
+            None => query.filter(tags_predicate),
+        };
+
+        match query
+            // Prefer the song that really has these tags over one that only matches by alias
+            .then_order_by((
+                songs::title_normalized.ne(&self.title_normalized),
+                songs::artist_normalized.ne(&self.artist_normalized),
+                songs::id,
+            ))
             .first::<(Song, Option<ExtraSongInfo>)>(conn)
             .await
             .optional()?
         {
-            Some(song_extended) => Ok(song_extended.0),
+            Some(found) => Ok(found),
             None => {
-                diesel::insert_into(songs::table)
+                let song = diesel::insert_into(songs::table)
                     .values(self)
                     .get_result(conn)
-                    .await
+                    .await?;
+                Ok((song, None))
+            }
+        }
+    }
+}
+
+/// Skill points each player loses in each league when the scores are deleted.
+fn lost_skill_points(scores: &[Score], policy: &SkillPointPolicy) -> HashMap<(i32, League), i32> {
+    let mut deltas = HashMap::new();
+    for score in scores {
+        *deltas.entry((score.player_id, score.league)).or_default() -=
+            score.calc_skill_points(policy);
+    }
+    deltas
+}
+
+/// What happens to the scores of two songs when one is merged into the other.
+#[derive(Debug, Default)]
+struct ScoreMergePlan {
+    /// Scores of the merged song that move to the target, with their new play count
+    moved: Vec<(i32, i32)>,
+    /// Scores of the target that absorb the play count of a lower score, with their new play count
+    kept: Vec<(i32, i32)>,
+    /// Scores that lost against the other song's score of the same player and league
+    dropped: Vec<i32>,
+    /// The score each dropped score lost against, which takes over its history
+    replaced_by: HashMap<i32, i32>,
+    /// Skill points each player loses in each league through their dropped scores
+    skill_point_deltas: HashMap<(i32, League), i32>,
+}
+
+/// Decides which scores survive merging a song into `target_scores`' song.
+///
+/// Scores without a counterpart (same player and league) on the other song just move over.
+/// Otherwise, the higher score survives with the play counts of both, and the target's wins on a tie.
+/// So play counts always add up to the same total, and skill points only go down by what the dropped scores were worth.
+fn plan_score_merge(
+    own_scores: &[Score],
+    target_scores: &[Score],
+    policy: &SkillPointPolicy,
+) -> ScoreMergePlan {
+    let mut plan = ScoreMergePlan::default();
+
+    for own_score in own_scores {
+        let counterpart = target_scores.iter().find(|target_score| {
+            target_score.player_id == own_score.player_id && target_score.league == own_score.league
+        });
+
+        match counterpart {
+            Some(target_score) if target_score.score < own_score.score => {
+                plan.moved
+                    .push((own_score.id, own_score.play_count + target_score.play_count));
+                plan.dropped.push(target_score.id);
+                plan.replaced_by.insert(target_score.id, own_score.id);
+                *plan
+                    .skill_point_deltas
+                    .entry((target_score.player_id, target_score.league))
+                    .or_default() -= target_score.calc_skill_points(policy);
+            }
+            Some(target_score) => {
+                plan.kept.push((
+                    target_score.id,
+                    target_score.play_count + own_score.play_count,
+                ));
+                plan.dropped.push(own_score.id);
+                plan.replaced_by.insert(own_score.id, target_score.id);
+                *plan
+                    .skill_point_deltas
+                    .entry((own_score.player_id, own_score.league))
+                    .or_default() -= own_score.calc_skill_points(policy);
+            }
+            None => plan.moved.push((own_score.id, own_score.play_count)),
+        }
+    }
+
+    plan
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, sync::Arc};
+
+    use time::OffsetDateTime;
+
+    use super::*;
+    use crate::{
+        models::shouts::NewShout,
+        schema::{score_history, scores, shouts},
+        util::{
+            game_types::{Character, League},
+            testing::{self, RecordedIndex, RecordedRedis},
+        },
+    };
+
+    const LEAGUES: [League; 3] = [League::Casual, League::Pro, League::Elite];
+
+    #[test]
+    fn duplicates_are_merged_into_the_most_played_song() {
+        let duplicate = |id, score_count| DuplicateSong {
+            song: Song {
+                id,
+                title: "Title".to_owned(),
+                artist: "Artist".to_owned(),
+                created_at: OffsetDateTime::UNIX_EPOCH,
+                modifiers: None,
+                excluded_from_rankings: false,
+                times_played: 0,
+                title_normalized: "title".to_owned(),
+                artist_normalized: "artist".to_owned(),
+            },
+            score_count,
+        };
+
+        let mut group = vec![duplicate(1, 2), duplicate(2, 40), duplicate(3, 40)];
+        sort_for_merging(&mut group);
+        let order: Vec<(i32, i64)> = group
+            .iter()
+            .map(|member| (member.song.id, member.score_count))
+            .collect();
+        assert_eq!(order, [(2, 40), (3, 40), (1, 2)]);
+    }
+
+    /// Tiny xorshift generator, so the test is reproducible without extra dependencies
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self, below: u64) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0 % below
+        }
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn random_scores(rng: &mut Rng, song_id: i32, next_id: &mut i32) -> Vec<Score> {
+        let mut scores = vec![];
+        for player_id in 1..=6 {
+            for league in LEAGUES {
+                if rng.next(2) == 0 {
+                    continue;
+                }
+                *next_id += 1;
+                scores.push(Score {
+                    id: *next_id,
+                    song_id,
+                    player_id,
+                    league,
+                    submitted_at: OffsetDateTime::UNIX_EPOCH,
+                    play_count: 1 + rng.next(20) as i32,
+                    // Small range, so ties happen too
+                    score: rng.next(10) as i32 * 1000,
+                    track_shape: vec![],
+                    xstats: vec![],
+                    density: 0,
+                    vehicle: Character::Mono,
+                    feats: vec![],
+                    song_length: 0,
+                    gold_threshold: 1000 + rng.next(5000) as i32,
+                    iss: 0,
+                    isj: 0,
+                    top_since: OffsetDateTime::UNIX_EPOCH,
+                });
             }
         }
+        scores
+    }
+
+    #[test]
+    fn merge_keeps_play_counts_and_skill_points() {
+        for seed in 1..=500 {
+            let mut rng = Rng(seed);
+            let mut next_id = 0;
+            let own_scores = random_scores(&mut rng, 1, &mut next_id);
+            let target_scores = random_scores(&mut rng, 2, &mut next_id);
+
+            let plan = plan_score_merge(&own_scores, &target_scores, &SkillPointPolicy::ORIGINAL);
+
+            // Apply the plan to an in-memory copy of both songs' scores
+            let dropped: HashSet<i32> = plan.dropped.iter().copied().collect();
+            let new_play_counts: HashMap<i32, i32> =
+                plan.moved.iter().chain(&plan.kept).copied().collect();
+            let merged: Vec<&Score> = own_scores
+                .iter()
+                .chain(&target_scores)
+                .filter(|score| !dropped.contains(&score.id))
+                .collect();
+            assert_eq!(
+                merged.len() + dropped.len(),
+                own_scores.len() + target_scores.len()
+            );
+
+            // One score per player and league
+            let keys: HashSet<(i32, i16)> = merged
+                .iter()
+                .map(|score| (score.player_id, score.league.into()))
+                .collect();
+            assert_eq!(keys.len(), merged.len(), "seed {seed}");
+
+            let play_count_before: i32 = own_scores
+                .iter()
+                .chain(&target_scores)
+                .map(|score| score.play_count)
+                .sum();
+            let play_count_after: i32 = merged
+                .iter()
+                .map(|score| {
+                    new_play_counts
+                        .get(&score.id)
+                        .copied()
+                        .unwrap_or(score.play_count)
+                })
+                .sum();
+            assert_eq!(play_count_before, play_count_after, "seed {seed}");
+
+            // Only the dropped scores' points are taken off, per player and league
+            for player_id in 1..=6 {
+                for league in LEAGUES {
+                    let points = |scores: &mut dyn Iterator<Item = &Score>| -> i32 {
+                        scores
+                            .filter(|score| score.player_id == player_id && score.league == league)
+                            .map(|score| score.calc_skill_points(&SkillPointPolicy::ORIGINAL))
+                            .sum()
+                    };
+                    let before = points(&mut own_scores.iter().chain(&target_scores));
+                    let after = points(&mut merged.iter().copied());
+                    let delta = plan
+                        .skill_point_deltas
+                        .get(&(player_id, league))
+                        .copied()
+                        .unwrap_or(0);
+                    assert_eq!(
+                        before + delta,
+                        after,
+                        "seed {seed}, player {player_id}, league {league:?}"
+                    );
+                }
+            }
+
+            // A dropped score is never higher than the one it lost against
+            for dropped_score in own_scores
+                .iter()
+                .chain(&target_scores)
+                .filter(|score| dropped.contains(&score.id))
+            {
+                let winner = merged
+                    .iter()
+                    .find(|score| {
+                        score.player_id == dropped_score.player_id
+                            && score.league == dropped_score.league
+                    })
+                    .expect("dropped score without a winner");
+                assert!(winner.score >= dropped_score.score, "seed {seed}");
+                assert_eq!(
+                    plan.replaced_by.get(&dropped_score.id),
+                    Some(&winner.id),
+                    "seed {seed}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn deletion_takes_off_every_scores_points() {
+        for seed in 1..=100 {
+            let mut rng = Rng(seed);
+            let scores = random_scores(&mut rng, 1, &mut 0);
+            let deltas = lost_skill_points(&scores, &SkillPointPolicy::ORIGINAL);
+
+            for player_id in 1..=6 {
+                for league in LEAGUES {
+                    let points: i32 = scores
+                        .iter()
+                        .filter(|score| score.player_id == player_id && score.league == league)
+                        .map(|score| score.calc_skill_points(&SkillPointPolicy::ORIGINAL))
+                        .sum();
+                    assert_eq!(
+                        deltas.get(&(player_id, league)).copied().unwrap_or(0),
+                        -points,
+                        "seed {seed}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn deleting_a_song_removes_everything_belonging_to_it() {
+        let Some(mut conn) = testing::db().await else {
+            return;
+        };
+        let redis_conn = testing::redis(Arc::new(RecordedRedis::default())).await;
+        let player = testing::insert_player(1, &mut conn).await;
+        let song_id = testing::insert_song("Dear Music", "A4.", &mut conn).await;
+        let other_song = testing::insert_song("Owaranai", "A4.", &mut conn).await;
+
+        // The improvement puts the first score into the history
+        for score in [100_000, 200_000] {
+            testing::insert_score(
+                player,
+                song_id,
+                League::Elite,
+                score,
+                &mut conn,
+                &redis_conn,
+            )
+            .await;
+        }
+        testing::insert_score(
+            player,
+            other_song,
+            League::Elite,
+            100_000,
+            &mut conn,
+            &redis_conn,
+        )
+        .await;
+        for shout_song in [song_id, other_song] {
+            NewShout::new(shout_song, player, "Hi")
+                .insert(&mut conn)
+                .await
+                .unwrap();
+        }
+        ExtraSongInfo::add_aliases(song_id, Some("dear music (remix)"), None, &mut conn)
+            .await
+            .unwrap();
+        let score_ids: Vec<i32> = scores::table
+            .filter(scores::song_id.eq(song_id))
+            .select(scores::id)
+            .load(&mut conn)
+            .await
+            .unwrap();
+        assert!(
+            score_history::table
+                .filter(score_history::score_id.eq_any(&score_ids))
+                .count()
+                .get_result::<i64>(&mut conn)
+                .await
+                .unwrap()
+                > 0
+        );
+
+        let recorded = Arc::new(RecordedRedis::default());
+        let index = RecordedIndex::default();
+        let song: Song = songs::table.find(song_id).first(&mut conn).await.unwrap();
+        song.delete(
+            &SkillPointPolicy::ORIGINAL,
+            &mut conn,
+            &testing::redis(recorded.clone()).await,
+            Some(&index),
+        )
+        .await
+        .unwrap();
+
+        let song_count = songs::table
+            .find(song_id)
+            .count()
+            .get_result::<i64>(&mut conn)
+            .await
+            .unwrap();
+        let score_count = scores::table
+            .filter(scores::song_id.eq_any([song_id, other_song]))
+            .count()
+            .get_result::<i64>(&mut conn)
+            .await
+            .unwrap();
+        let history_count = score_history::table
+            .filter(score_history::score_id.eq_any(&score_ids))
+            .count()
+            .get_result::<i64>(&mut conn)
+            .await
+            .unwrap();
+        let shout_count = shouts::table
+            .filter(shouts::song_id.eq_any([song_id, other_song]))
+            .count()
+            .get_result::<i64>(&mut conn)
+            .await
+            .unwrap();
+        let extra_info_count = extra_song_info::table
+            .filter(extra_song_info::song_id.eq(song_id))
+            .count()
+            .get_result::<i64>(&mut conn)
+            .await
+            .unwrap();
+        assert_eq!(song_count, 0);
+        // Only the other song's are left
+        assert_eq!(score_count, 1);
+        assert_eq!(history_count, 0);
+        assert_eq!(shout_count, 1);
+        assert_eq!(extra_info_count, 0);
+        assert_eq!(index.removed(), [song_id]);
+        assert!(index.indexed().is_empty());
+        // The deleted score's points were taken off the leaderboard
+        assert!(recorded
+            .commands()
+            .iter()
+            .any(|(command, _)| command == "ZINCRBY"));
+    }
+
+    #[tokio::test]
+    async fn mbid_matches_win_over_tag_matches() {
+        use crate::models::extra_song_info::NewExtraSongInfo;
+
+        let Some(mut conn) = testing::db().await else {
+            return;
+        };
+        let tagged_id = testing::insert_song("Dear Music", "A4.", &mut conn).await;
+        let mbid_id = testing::insert_song("Dear Music (Remastered)", "A4.", &mut conn).await;
+        NewExtraSongInfo::new(
+            mbid_id,
+            None,
+            None,
+            Some("recording".to_owned()),
+            None,
+            None,
+            None,
+            None,
+            None,
+        )
+        .insert(&mut conn)
+        .await
+        .unwrap();
+        let lookup = NewSong::new("Dear Music", "A4.", None);
+
+        let (song, extra_info) = lookup
+            .find_or_create_with_mbid(Some("recording"), &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(song.id, mbid_id);
+        assert_eq!(extra_info.unwrap().mbid.as_deref(), Some("recording"));
+
+        let (song, extra_info) = lookup
+            .find_or_create_with_mbid(Some("other recording"), &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(song.id, tagged_id);
+        assert!(extra_info.is_none());
+        assert_eq!(
+            lookup.find_or_create(&mut conn).await.unwrap().id,
+            tagged_id
+        );
+
+        // Modifiers still have to match
+        let (song, extra_info) = NewSong::new("Dear Music", "A4.", Some(vec!["steep"]))
+            .find_or_create_with_mbid(Some("recording"), &mut conn)
+            .await
+            .unwrap();
+        assert!(song.id != tagged_id && song.id != mbid_id);
+        assert!(extra_info.is_none());
+    }
+
+    #[tokio::test]
+    async fn merging_a_song_moves_it_in_the_index() {
+        let Some(mut conn) = testing::db().await else {
+            return;
+        };
+        let redis_conn = testing::redis(Arc::new(RecordedRedis::default())).await;
+        let duplicate_id =
+            testing::insert_song("Dear Music (Album Version)", "A4.", &mut conn).await;
+        let target_id = testing::insert_song("Dear Music", "A4.", &mut conn).await;
+
+        let index = RecordedIndex::default();
+        let duplicate: Song = songs::table
+            .find(duplicate_id)
+            .first(&mut conn)
+            .await
+            .unwrap();
+        duplicate
+            .merge_into(
+                target_id,
+                true,
+                &SkillPointPolicy::ORIGINAL,
+                &mut conn,
+                &redis_conn,
+                Some(&index),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(index.removed(), [duplicate_id]);
+        // The target now has the duplicate as an alias, so its document changed
+        assert_eq!(index.indexed(), [target_id]);
     }
 }
@@ -1,5 +1,38 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    announcements (id) {
+        id -> Int4,
+        title -> Text,
+        body -> Text,
+        published_at -> Timestamptz,
+        expires_at -> Nullable<Timestamptz>,
+        pinned -> Bool,
+    }
+}
+
+diesel::table! {
+    bans (id) {
+        id -> Int4,
+        player_id -> Int4,
+        reason -> Text,
+        issued_by -> Nullable<Int4>,
+        issued_at -> Timestamptz,
+        expires_at -> Nullable<Timestamptz>,
+        lifted_at -> Nullable<Timestamptz>,
+        lifted_by -> Nullable<Int4>,
+    }
+}
+
+diesel::table! {
+    client_changelog (id) {
+        id -> Int4,
+        version -> Text,
+        body -> Text,
+        published_at -> Timestamptz,
+    }
+}
+
 diesel::table! {
     extra_song_info (id) {
         id -> Int4,
@@ -13,6 +46,52 @@ diesel::table! {
         mistag_lock -> Bool,
         aliases_artist -> Nullable<Array<Nullable<Text>>>,
         aliases_title -> Nullable<Array<Nullable<Text>>>,
+        cover_file -> Nullable<Text>,
+        cover_file_small -> Nullable<Text>,
+        musicbrainz_title_normalized -> Nullable<Text>,
+        musicbrainz_artist_normalized -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    notifications (id) {
+        id -> Int4,
+        player_id -> Int4,
+        kind -> Int2,
+        payload -> Jsonb,
+        created_at -> Timestamptz,
+        read_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    pending_leaderboard_deltas (id) {
+        id -> Int4,
+        player_id -> Int4,
+        delta -> Int4,
+        created_at -> Timestamptz,
+        league -> Nullable<Int2>,
+    }
+}
+
+diesel::table! {
+    player_favorite_songs (player_id, song_id) {
+        player_id -> Int4,
+        song_id -> Int4,
+        position -> Int2,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    player_name_history (id) {
+        id -> Int4,
+        player_id -> Int4,
+        #[max_length = 32]
+        old_name -> Varchar,
+        #[max_length = 32]
+        new_name -> Varchar,
+        changed_at -> Timestamptz,
     }
 }
 
@@ -27,6 +106,59 @@ diesel::table! {
         account_type -> Int2,
         joined_at -> Timestamptz,
         avatar_url -> Text,
+        last_played_at -> Nullable<Timestamptz>,
+        last_seen_at -> Nullable<Timestamptz>,
+        preferred_language -> Nullable<Int2>,
+        bio -> Nullable<Text>,
+        profile_links -> Nullable<Array<Nullable<Text>>>,
+        hide_presence -> Bool,
+        last_login_at -> Nullable<Timestamptz>,
+        previous_login_at -> Nullable<Timestamptz>,
+        disable_profile_shouts -> Bool,
+    }
+}
+
+diesel::table! {
+    profile_shouts (id) {
+        id -> Int4,
+        profile_owner_id -> Int4,
+        author_id -> Int4,
+        posted_at -> Timestamptz,
+        #[max_length = 240]
+        content -> Varchar,
+    }
+}
+
+diesel::table! {
+    radio_entries (id) {
+        id -> Int4,
+        song_id -> Int4,
+        title -> Text,
+        artist -> Text,
+        cgr_url -> Text,
+        external_url -> Text,
+        sort_order -> Int4,
+        active -> Bool,
+        created_at -> Timestamptz,
+        available_from -> Nullable<Timestamptz>,
+        available_until -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    rejected_scores (id) {
+        id -> Int4,
+        player_id -> Int4,
+        song_id -> Int4,
+        league -> Int2,
+        score -> Int4,
+        vehicle -> Int2,
+        gold_threshold -> Int4,
+        song_length -> Int4,
+        track_shape_length -> Int4,
+        feats -> Array<Nullable<Text>>,
+        reason -> Text,
+        rejected_at -> Timestamptz,
     }
 }
 
@@ -35,6 +167,40 @@ diesel::table! {
         challenger_id -> Int4,
         rival_id -> Int4,
         established_at -> Timestamptz,
+        source -> Int2,
+    }
+}
+
+diesel::table! {
+    score_history (id) {
+        id -> Int4,
+        score_id -> Int4,
+        score -> Int4,
+        submitted_at -> Timestamptz,
+        track_shape -> Array<Nullable<Int4>>,
+        xstats -> Array<Nullable<Int4>>,
+        density -> Int4,
+        vehicle -> Int2,
+        feats -> Array<Nullable<Text>>,
+        song_length -> Int4,
+        gold_threshold -> Int4,
+        iss -> Int4,
+        isj -> Int4,
+        archived_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    score_reports (id) {
+        id -> Int4,
+        score_id -> Nullable<Int4>,
+        reporter_id -> Int4,
+        #[max_length = 500]
+        reason -> Varchar,
+        created_at -> Timestamptz,
+        resolved_at -> Nullable<Timestamptz>,
+        resolved_by -> Nullable<Int4>,
+        score_deleted -> Bool,
     }
 }
 
@@ -56,6 +222,7 @@ diesel::table! {
         gold_threshold -> Int4,
         iss -> Int4,
         isj -> Int4,
+        top_since -> Timestamptz,
     }
 }
 
@@ -70,6 +237,33 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    skill_point_decay (player_id) {
+        player_id -> Int4,
+        multiplier -> Float8,
+        applied_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    song_daily_plays (song_id, day) {
+        song_id -> Int4,
+        day -> Date,
+        plays -> Int4,
+    }
+}
+
+diesel::table! {
+    song_tag_samples (id) {
+        id -> Int4,
+        song_id -> Int4,
+        player_id -> Int4,
+        title -> Text,
+        artist -> Text,
+        created_at -> Timestamptz,
+    }
+}
+
 diesel::table! {
     songs (id) {
         id -> Int4,
@@ -77,20 +271,56 @@ diesel::table! {
         artist -> Text,
         created_at -> Timestamptz,
         modifiers -> Nullable<Array<Nullable<Text>>>,
+        excluded_from_rankings -> Bool,
+        times_played -> Int8,
+        title_normalized -> Text,
+        artist_normalized -> Text,
+        updated_at -> Timestamptz,
     }
 }
 
 diesel::joinable!(extra_song_info -> songs (song_id));
+diesel::joinable!(notifications -> players (player_id));
+diesel::joinable!(pending_leaderboard_deltas -> players (player_id));
+diesel::joinable!(player_favorite_songs -> players (player_id));
+diesel::joinable!(player_favorite_songs -> songs (song_id));
+diesel::joinable!(player_name_history -> players (player_id));
+diesel::joinable!(profile_shouts -> players (author_id));
+diesel::joinable!(radio_entries -> songs (song_id));
+diesel::joinable!(rejected_scores -> players (player_id));
+diesel::joinable!(rejected_scores -> songs (song_id));
+diesel::joinable!(score_history -> scores (score_id));
+diesel::joinable!(score_reports -> players (reporter_id));
+diesel::joinable!(score_reports -> scores (score_id));
 diesel::joinable!(scores -> players (player_id));
 diesel::joinable!(scores -> songs (song_id));
 diesel::joinable!(shouts -> players (author_id));
 diesel::joinable!(shouts -> songs (song_id));
+diesel::joinable!(skill_point_decay -> players (player_id));
+diesel::joinable!(song_daily_plays -> songs (song_id));
+diesel::joinable!(song_tag_samples -> players (player_id));
+diesel::joinable!(song_tag_samples -> songs (song_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
+    announcements,
+    bans,
+    client_changelog,
     extra_song_info,
+    notifications,
+    pending_leaderboard_deltas,
+    player_favorite_songs,
+    player_name_history,
     players,
+    profile_shouts,
+    radio_entries,
+    rejected_scores,
     rivalries,
+    score_history,
+    score_reports,
     scores,
     shouts,
+    skill_point_decay,
+    song_daily_plays,
+    song_tag_samples,
     songs,
 );
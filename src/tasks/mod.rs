@@ -0,0 +1,291 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
+
+use meilisearch_sdk::client::Client as MeiliClient;
+use time::OffsetDateTime;
+use tracing::{error, info, warn};
+
+use crate::{
+    models::{
+        leaderboard_deltas::PendingLeaderboardDelta, skill_point_decay::SkillPointDecay,
+        song_daily_plays::SongDailyPlays,
+    },
+    util::{log_retention, lookup_queue, meilisearch},
+    AppState, Config, Decay,
+};
+
+/// How often queued skill point deltas are retried
+const LEADERBOARD_DELTA_INTERVAL: Duration = Duration::from_secs(30);
+/// How often buffered API usage counts are written to Redis
+const USAGE_FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+/// How often plays that are too old for any ranking period are pruned
+const SONG_PLAYS_PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60 * 24);
+/// How often the radio lineup is reloaded, for changes made through other instances
+const RADIO_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+/// How often old log files are pruned
+const LOG_PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60 * 24);
+/// How often inactive players are checked for skill point decay.
+/// Each player still only decays once per `DECAY_PERIOD`, no matter how often the server restarts.
+const DECAY_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60 * 24);
+/// At most this fraction of the interval is added to each wait between search syncs,
+/// so instances sharing a Meilisearch don't all sync at once
+const SEARCH_SYNC_JITTER_DIVISOR: u32 = 10;
+
+/// A background task, as listed in the admin summary
+pub struct JobInfo {
+    pub name: &'static str,
+    pub interval: Duration,
+    /// Whether `spawn_all` starts it with this config
+    pub enabled: bool,
+}
+
+/// Lists the background tasks `spawn_all` knows about and whether they run with `config`.
+pub fn job_list(config: &Config) -> Vec<JobInfo> {
+    let writable = !config.main.read_only;
+    vec![
+        JobInfo {
+            name: "drain_leaderboard_deltas",
+            interval: LEADERBOARD_DELTA_INTERVAL,
+            enabled: writable,
+        },
+        JobInfo {
+            name: "decay_skill_points",
+            interval: DECAY_CHECK_INTERVAL,
+            enabled: writable && config.decay.is_some(),
+        },
+        JobInfo {
+            name: "prune_song_plays",
+            interval: SONG_PLAYS_PRUNE_INTERVAL,
+            enabled: writable,
+        },
+        JobInfo {
+            name: "process_metadata_lookups",
+            interval: metadata_lookup_interval(config),
+            enabled: writable,
+        },
+        JobInfo {
+            name: "flush_usage",
+            interval: USAGE_FLUSH_INTERVAL,
+            enabled: true,
+        },
+        JobInfo {
+            name: "refresh_radio_lineup",
+            interval: RADIO_REFRESH_INTERVAL,
+            enabled: true,
+        },
+        JobInfo {
+            name: "prune_logs",
+            interval: LOG_PRUNE_INTERVAL,
+            enabled: true,
+        },
+        JobInfo {
+            name: "sync_search",
+            interval: search_sync_interval(config),
+            enabled: writable && config.external.meilisearch_url.is_some(),
+        },
+    ]
+}
+
+fn search_sync_interval(config: &Config) -> Duration {
+    Duration::from_secs(
+        config
+            .external
+            .meilisearch_sync_interval_mins
+            .saturating_mul(60),
+    )
+}
+
+fn metadata_lookup_interval(config: &Config) -> Duration {
+    Duration::from_millis(config.external.musicbrainz_lookup_interval_ms)
+}
+
+/// Spawns all background tasks that run alongside the server.
+/// In read-only mode, the ones writing to the database aren't spawned.
+pub fn spawn_all(state: &AppState) {
+    if !state.config.main.read_only {
+        tokio::spawn(drain_leaderboard_deltas(state.clone()));
+        tokio::spawn(prune_song_plays(state.clone()));
+        tokio::spawn(process_metadata_lookups(state.clone()));
+        if let Some(decay) = &state.config.decay {
+            tokio::spawn(decay_skill_points(state.clone(), decay.clone()));
+        }
+        if let Some(meili) = &state.metadata.meili {
+            tokio::spawn(sync_search(state.clone(), meili.clone()));
+        }
+    }
+    tokio::spawn(flush_usage(state.clone()));
+    tokio::spawn(refresh_radio_lineup(state.clone()));
+    tokio::spawn(prune_logs(state.clone()));
+}
+
+/// Periodically applies skill point deltas that couldn't be written to Redis when they happened.
+async fn drain_leaderboard_deltas(state: AppState) {
+    let mut interval = tokio::time::interval(LEADERBOARD_DELTA_INTERVAL);
+    loop {
+        interval.tick().await;
+
+        let mut conn = match state.db.get().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                error!(
+                    "Failed to get DB connection for draining leaderboard deltas: {}",
+                    e
+                );
+                continue;
+            }
+        };
+        if let Err(e) = PendingLeaderboardDelta::drain(&mut conn, &state.redis).await {
+            error!("Failed to drain leaderboard deltas: {}", e);
+        }
+    }
+}
+
+/// Periodically decays the leaderboard skill points of players who haven't played in a while.
+async fn decay_skill_points(state: AppState, decay: Decay) {
+    let mut interval = tokio::time::interval(DECAY_CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+
+        let mut conn = match state.db.get().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                error!("Failed to get DB connection for skill point decay: {}", e);
+                continue;
+            }
+        };
+        let inactive_since =
+            time::OffsetDateTime::now_utc() - time::Duration::days(decay.inactive_days.into());
+        match SkillPointDecay::apply(
+            inactive_since,
+            decay.factor,
+            f64::from(decay.floor_percent) / 100.0,
+            &state.config.skill_points,
+            &mut conn,
+            &state.redis,
+        )
+        .await
+        {
+            Ok(0) => {}
+            Ok(decayed) => info!("Decayed skill points of {} inactive players", decayed),
+            Err(e) => error!("Failed to decay skill points: {}", e),
+        }
+    }
+}
+
+/// Deletes daily song plays that no ranking period needs anymore, once a day.
+async fn prune_song_plays(state: AppState) {
+    let mut interval = tokio::time::interval(SONG_PLAYS_PRUNE_INTERVAL);
+    loop {
+        interval.tick().await;
+
+        let mut conn = match state.db.get().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                error!("Failed to get DB connection for pruning song plays: {}", e);
+                continue;
+            }
+        };
+        if let Err(e) = SongDailyPlays::prune(&mut conn).await {
+            error!("Failed to prune old song plays: {}", e);
+        }
+    }
+}
+
+/// Works through the queued MusicBrainz lookups, one per interval so MusicBrainz doesn't rate limit us.
+async fn process_metadata_lookups(state: AppState) {
+    let mut interval = tokio::time::interval(metadata_lookup_interval(&state.config));
+    // Waiting for a full interval after a slow lookup is pointless
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+
+        if let Err(e) = lookup_queue::process_next(&state.db, &state.redis).await {
+            error!("Failed to process metadata lookup: {}", e);
+        }
+    }
+}
+
+/// Periodically writes buffered API usage counts to Redis.
+async fn flush_usage(state: AppState) {
+    let mut interval = tokio::time::interval(USAGE_FLUSH_INTERVAL);
+    loop {
+        interval.tick().await;
+
+        if let Err(e) = state.usage.flush(&state.redis).await {
+            error!("Failed to flush API usage counts: {}", e);
+        }
+    }
+}
+
+/// Periodically reloads the radio lineup. Failures are logged by the reload itself.
+async fn refresh_radio_lineup(state: AppState) {
+    let mut interval = tokio::time::interval(RADIO_REFRESH_INTERVAL);
+    // The first tick completes immediately, but the lineup was already loaded at startup
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+
+        let _ = state.radio.reload(&state.db).await;
+    }
+}
+
+/// Deletes log files beyond the configured retention once a day.
+async fn prune_logs(state: AppState) {
+    let mut interval = tokio::time::interval(LOG_PRUNE_INTERVAL);
+    // The first tick completes immediately, but logs were already pruned at startup
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+
+        let directory = Path::new(&state.config.logging.directory);
+        if let Err(e) =
+            log_retention::prune_logs(directory, state.config.logging.retention_limits())
+        {
+            error!("Failed to prune old log files: {}", e);
+        }
+    }
+}
+
+/// Sends the songs that changed since the last sync to Meilisearch at startup, then again every configured interval.
+/// Everything is sent if no sync is known to have finished before (e.g. on a fresh Redis), `sync-search` does that by hand.
+async fn sync_search(state: AppState, meili: Arc<MeiliClient>) {
+    let interval = search_sync_interval(&state.config);
+    loop {
+        let started_at = OffsetDateTime::now_utc();
+        let changed_since = match meilisearch::last_sync(&state.redis).await {
+            Ok(last_sync) => last_sync,
+            Err(e) => {
+                warn!(
+                    "Failed to get the last search sync, sending all songs: {}",
+                    e
+                );
+                None
+            }
+        };
+        match state.db.get().await {
+            Ok(mut conn) => match meilisearch::sync_songs(&meili, &mut conn, changed_since).await {
+                Ok(synced) => {
+                    info!("Sent {} songs to Meilisearch", synced);
+                    if let Err(e) = meilisearch::record_sync(started_at, &state.redis).await {
+                        error!("Failed to remember the search sync: {}", e);
+                    }
+                }
+                Err(e) => error!("Failed to sync songs to Meilisearch: {}", e),
+            },
+            Err(e) => error!("Failed to get DB connection for syncing search: {}", e),
+        }
+
+        tokio::time::sleep(interval + jitter(interval / SEARCH_SYNC_JITTER_DIVISOR)).await;
+    }
+}
+
+/// A random duration up to `max`. Doesn't need to be good randomness, just different for each call.
+fn jitter(max: Duration) -> Duration {
+    let random = RandomState::new().build_hasher().finish();
+    max.mul_f64(random as f64 / u64::MAX as f64)
+}
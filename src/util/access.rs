@@ -0,0 +1,143 @@
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use fred::{clients::Pool as RedisPool, prelude::*};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::{
+    models::players::{AccountType, Player},
+    schema::players,
+    Db,
+};
+
+/// How long a player's access is cached. Bans and account type changes made elsewhere take effect after this.
+const ACCESS_TTL_SECS: i64 = 60;
+
+fn access_key(player_id: i32) -> String {
+    format!("access:{player_id}")
+}
+
+/// What decides whether a player may use the API, checked on every authenticated request
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Access {
+    pub account_type: AccountType,
+    /// Explains the player's ban to them, if they're banned (see [`Player::active_ban`])
+    pub ban_message: Option<String>,
+}
+
+/// Gets a player's account type and ban, from Redis if it was looked up in the last minute.
+/// Only checks out a database connection if it wasn't.
+/// If Redis can't be asked, the database is, since that's only slower.
+///
+/// # Returns
+/// `None` if the player doesn't exist.
+///
+/// # Errors
+/// Fails if something is wrong with the database.
+pub async fn lookup(
+    player_id: i32,
+    db: &Db,
+    redis_conn: &RedisPool,
+) -> anyhow::Result<Option<Access>> {
+    match redis_conn
+        .get::<Option<String>, _>(access_key(player_id))
+        .await
+    {
+        Ok(Some(cached)) => match serde_json::from_str(&cached) {
+            Ok(access) => return Ok(Some(access)),
+            Err(e) => warn!("Ignoring unreadable cached access of player {player_id}: {e}"),
+        },
+        Ok(None) => {}
+        Err(e) => warn!("Failed to get cached access of player {player_id}: {e}"),
+    }
+
+    let mut conn = db.get().await?;
+    let Some(player) = players::table
+        .find(player_id)
+        .select(Player::as_select())
+        .first::<Player>(&mut conn)
+        .await
+        .optional()?
+    else {
+        return Ok(None);
+    };
+    let access = Access {
+        account_type: player.account_type,
+        ban_message: player.active_ban(&mut conn).await?,
+    };
+
+    if let Err(e) = redis_conn
+        .set::<(), _, _>(
+            access_key(player_id),
+            serde_json::to_string(&access)?,
+            Some(Expiration::EX(ACCESS_TTL_SECS)),
+            None,
+            false,
+        )
+        .await
+    {
+        warn!("Failed to cache access of player {player_id}: {e}");
+    }
+
+    Ok(Some(access))
+}
+
+/// Drops the cached access of a player, so a ban or account type change applies to their next request.
+/// Without this, it applies within a minute anyway, so failures are only logged.
+pub async fn forget(player_id: i32, redis_conn: &RedisPool) {
+    if let Err(e) = redis_conn.del::<(), _>(access_key(player_id)).await {
+        warn!("Failed to drop cached access of player {player_id}: {e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        models::bans::NewBan,
+        util::testing::{self, KeyStore},
+    };
+
+    #[tokio::test]
+    async fn bans_apply_once_forgotten() {
+        let Some(state) = testing::state(Arc::new(KeyStore::default())).await else {
+            return;
+        };
+        let player_id = {
+            let mut conn = state.db.get().await.unwrap();
+            testing::insert_player(1, &mut conn).await
+        };
+
+        let access = lookup(player_id, &state.db, &state.redis).await.unwrap();
+        assert_eq!(
+            access,
+            Some(Access {
+                account_type: AccountType::User,
+                ban_message: None,
+            })
+        );
+
+        {
+            let mut conn = state.db.get().await.unwrap();
+            let new_ban = NewBan {
+                reason: "Cheating".to_owned(),
+                expires_at: None,
+            };
+            new_ban.insert(player_id, None, &mut conn).await.unwrap();
+        }
+        // Still cached
+        let access = lookup(player_id, &state.db, &state.redis).await.unwrap();
+        assert_eq!(access.unwrap().ban_message, None);
+
+        forget(player_id, &state.redis).await;
+        let access = lookup(player_id, &state.db, &state.redis).await.unwrap();
+        assert_eq!(
+            access.unwrap().ban_message.as_deref(),
+            Some("Account is banned: Cheating")
+        );
+
+        assert_eq!(lookup(-1, &state.db, &state.redis).await.unwrap(), None);
+    }
+}
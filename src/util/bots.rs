@@ -0,0 +1,157 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+
+use crate::Config;
+
+/// Served at `/robots.txt`. Crawlers may look at the API docs, but nothing else under `/api/`.
+pub const ROBOTS_TXT: &str = "User-agent: *\nAllow: /api/docs\nDisallow: /api/\n";
+
+/// What bots get when they request an expensive endpoint
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BotPolicy {
+    /// Serve them like everyone else
+    Allow,
+    /// Answer with empty results, without touching the database
+    #[default]
+    Minimal,
+    /// Answer with 403
+    Forbid,
+}
+
+/// An empty page of results
+const EMPTY_PAGE: &str = r#"{"results":[],"total":0}"#;
+/// An empty list, for endpoints that aren't paginated
+const EMPTY_LIST: &str = "[]";
+
+/// Endpoints that are expensive to serve, relative to `/api`, with what they return when there's nothing to show
+const EXPENSIVE_PATHS: &[(&str, &str)] = &[
+    ("/scores", EMPTY_PAGE),
+    ("/scores/", EMPTY_PAGE),
+    ("/scores/rivals", EMPTY_PAGE),
+    ("/players/rankings", EMPTY_PAGE),
+    ("/players/{id}/scores", EMPTY_PAGE),
+    ("/songs/rankings", EMPTY_LIST),
+    ("/songs/{id}/scores", EMPTY_PAGE),
+];
+
+/// Parts of the user agents of known crawlers, lowercase.
+/// Not just "bot", since that also matches clients that merely have it in their name.
+const CRAWLER_MARKERS: &[&str] = &[
+    "googlebot",
+    "google-inspectiontool",
+    "bingbot",
+    "bingpreview",
+    "yandex",
+    "baiduspider",
+    "duckduckbot",
+    "slurp",
+    "applebot",
+    "petalbot",
+    "sogou",
+    "seznambot",
+    "ahrefsbot",
+    "semrushbot",
+    "mj12bot",
+    "dotbot",
+    "blexbot",
+    "dataforseobot",
+    "bytespider",
+    "amazonbot",
+    "gptbot",
+    "ccbot",
+    "facebookexternalhit",
+    "headlesschrome",
+];
+
+fn is_bot(user_agent: &str) -> bool {
+    let user_agent = user_agent.to_ascii_lowercase();
+    CRAWLER_MARKERS
+        .iter()
+        .any(|marker| user_agent.contains(marker))
+}
+
+/// What the endpoint returns when there's nothing to show, if it's an expensive one
+fn empty_response(matched_path: &str) -> Option<&'static str> {
+    let path = matched_path.strip_prefix("/api").unwrap_or(matched_path);
+    EXPENSIVE_PATHS
+        .iter()
+        .find(|(expensive_path, _)| *expensive_path == path)
+        .map(|(_, empty)| *empty)
+}
+
+/// Middleware keeping crawlers away from expensive endpoints, as configured by `bot_policy`.
+///
+/// Everything else (e.g. the docs and `/api/stats`, which uptime monitors use) is left alone.
+pub async fn block_bots(
+    State(config): State<Arc<Config>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let policy = config.main.bot_policy;
+    let empty = request
+        .extensions()
+        .get::<MatchedPath>()
+        .and_then(|path| empty_response(path.as_str()));
+    let from_bot = request
+        .headers()
+        .get(header::USER_AGENT)
+        .and_then(|user_agent| user_agent.to_str().ok())
+        .is_some_and(is_bot);
+
+    let Some(empty) = empty.filter(|_| policy != BotPolicy::Allow && from_bot) else {
+        return next.run(request).await;
+    };
+
+    match policy {
+        BotPolicy::Minimal => (
+            [
+                (header::CONTENT_TYPE, "application/json"),
+                (header::CACHE_CONTROL, "public, max-age=86400"),
+            ],
+            empty,
+        )
+            .into_response(),
+        _ => StatusCode::FORBIDDEN.into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bots_are_detected() {
+        assert!(is_bot(
+            "Mozilla/5.0 (compatible; Googlebot/2.1; +http://www.google.com/bot.html)"
+        ));
+        assert!(is_bot("Mozilla/5.0 (compatible; YandexSpider/3.0)"));
+        assert!(!is_bot(
+            "Mozilla/5.0 (Windows NT 10.0; Win64; x64; rv:133.0) Gecko/20100101 Firefox/133.0"
+        ));
+        // Only known crawlers, not everything with "bot" in its name
+        assert!(!is_bot("RobotRockClient/1.2"));
+    }
+
+    #[test]
+    fn only_expensive_paths_are_blocked() {
+        assert_eq!(empty_response("/api/scores"), Some(EMPTY_PAGE));
+        assert_eq!(empty_response("/players/rankings"), Some(EMPTY_PAGE));
+        assert_eq!(empty_response("/api/songs/{id}/scores"), Some(EMPTY_PAGE));
+        assert_eq!(empty_response("/api/songs/{id}"), None);
+        assert_eq!(empty_response("/api/stats"), None);
+    }
+
+    #[test]
+    fn empty_responses_match_the_endpoint() {
+        // Song rankings are a plain list, not a page
+        assert_eq!(empty_response("/api/songs/rankings"), Some(EMPTY_LIST));
+    }
+}
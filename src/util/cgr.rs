@@ -0,0 +1,509 @@
+//! Audiosurf's `.cgr` channel group files.
+//!
+//! They're RIFF containers: a `RIFF` chunk with a form type, holding chunks that either hold data
+//! or are `LIST` chunks holding more chunks. Some come zlib-compressed as a whole.
+//! Parsed files are written back exactly as they were, so they can be edited without breaking the rest.
+
+use std::{fmt, io::Read, time::Duration};
+
+use flate2::read::ZlibDecoder;
+
+/// Largest CGR file that is accepted, uncompressed. The radio's files are a few MB.
+pub const MAX_CGR_SIZE: usize = 64 * 1024 * 1024;
+
+/// Deepest nesting of lists that is accepted. Real files nest a few levels,
+/// and everything walking the tree recurses once per level.
+pub const MAX_CGR_DEPTH: usize = 32;
+
+/// Why a file isn't a usable CGR
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum CgrError {
+    #[error("Not a RIFF file")]
+    NotRiff,
+    #[error("RIFF header says {declared} bytes, but there are {actual}")]
+    SizeMismatch { declared: usize, actual: usize },
+    #[error("Invalid chunk ID at offset {0}")]
+    InvalidChunkId(usize),
+    #[error("Chunk at offset {0} runs past the end of its parent")]
+    TruncatedChunk(usize),
+    #[error("List chunk at offset {0} has no form type")]
+    MissingFormType(usize),
+    #[error("Compressed data is invalid")]
+    BadCompression,
+    #[error("Larger than {MAX_CGR_SIZE} bytes uncompressed")]
+    TooLarge,
+    #[error("Lists are nested deeper than {MAX_CGR_DEPTH} levels")]
+    TooDeep,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChunkBody {
+    Data(Vec<u8>),
+    /// `RIFF` and `LIST` chunks
+    List {
+        form_type: [u8; 4],
+        chunks: Vec<Chunk>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chunk {
+    pub id: [u8; 4],
+    pub body: ChunkBody,
+    /// The byte after an odd-sized chunk, `None` if its parent ends without one.
+    /// It should be 0, but is kept as it is so files are written back byte for byte
+    pad: Option<u8>,
+}
+
+impl Chunk {
+    /// Size of the chunk's content, as its header says
+    #[must_use]
+    pub fn size(&self) -> usize {
+        match &self.body {
+            ChunkBody::Data(data) => data.len(),
+            ChunkBody::List { chunks, .. } => {
+                4 + chunks.iter().map(Self::size_with_header).sum::<usize>()
+            }
+        }
+    }
+
+    fn size_with_header(&self) -> usize {
+        8 + self.size() + usize::from(self.pad.is_some())
+    }
+
+    /// The chunk itself and all chunks in it, depth first
+    pub fn walk(&self) -> Box<dyn Iterator<Item = &Self> + '_> {
+        let children: Box<dyn Iterator<Item = &Self> + '_> = match &self.body {
+            ChunkBody::Data(_) => Box::new(std::iter::empty()),
+            ChunkBody::List { chunks, .. } => Box::new(chunks.iter().flat_map(Self::walk)),
+        };
+        Box::new(std::iter::once(self).chain(children))
+    }
+
+    /// Data of the first chunk with that ID, looking into all lists
+    fn find_data(&self, id: &[u8; 4]) -> Option<&[u8]> {
+        self.walk().find_map(|chunk| match &chunk.body {
+            ChunkBody::Data(data) if &chunk.id == id => Some(data.as_slice()),
+            _ => None,
+        })
+    }
+
+    fn write(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.id);
+        let size_at = out.len();
+        out.extend_from_slice(&[0; 4]);
+        match &self.body {
+            ChunkBody::Data(data) => out.extend_from_slice(data),
+            ChunkBody::List { form_type, chunks } => {
+                out.extend_from_slice(form_type);
+                for chunk in chunks {
+                    chunk.write(out);
+                }
+            }
+        }
+
+        // Sizes were read from u32s, and MAX_CGR_SIZE keeps them far below that
+        let size = u32::try_from(out.len() - size_at - 4).unwrap_or(u32::MAX);
+        out[size_at..size_at + 4].copy_from_slice(&size.to_le_bytes());
+        out.extend(self.pad);
+    }
+
+    fn describe(&self, depth: usize, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let indent = "  ".repeat(depth);
+        let id = String::from_utf8_lossy(&self.id);
+        match &self.body {
+            ChunkBody::Data(data) => writeln!(f, "{indent}{id} ({} bytes)", data.len()),
+            ChunkBody::List { form_type, chunks } => {
+                writeln!(
+                    f,
+                    "{indent}{id} {} ({} bytes, {} chunks)",
+                    String::from_utf8_lossy(form_type),
+                    self.size(),
+                    chunks.len()
+                )?;
+                chunks
+                    .iter()
+                    .try_for_each(|chunk| chunk.describe(depth + 1, f))
+            }
+        }
+    }
+}
+
+/// Lists the chunk and everything in it, one chunk per line
+impl fmt::Display for Chunk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.describe(0, f)
+    }
+}
+
+/// What a CGR says about its song, where it says anything
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CgrMetadata {
+    /// From the `INAM` chunk of the `INFO` list
+    pub title: Option<String>,
+    /// From the `IART` chunk of the `INFO` list
+    pub artist: Option<String>,
+    /// From the embedded audio's `fmt ` and `data` chunks
+    pub length: Option<Duration>,
+}
+
+/// A parsed CGR file.
+#[derive(Debug, Clone)]
+pub struct CgrFile {
+    root: Chunk,
+    /// What was read if the file was compressed. Compressing the RIFF data again wouldn't necessarily
+    /// give the same bytes, and since the chunks can't be changed yet, this is what gets written back
+    compressed: Option<Vec<u8>>,
+}
+
+impl CgrFile {
+    /// Parses `data`, decompressing it first if needed.
+    ///
+    /// # Errors
+    /// Fails if the data isn't a RIFF container, its sizes don't add up, or it can't be decompressed.
+    pub fn parse(data: &[u8]) -> Result<Self, CgrError> {
+        if is_zlib(data) {
+            let decompressed = decompress(data)?;
+            return Ok(Self {
+                root: parse_riff(&decompressed)?,
+                compressed: Some(data.to_vec()),
+            });
+        }
+
+        Ok(Self {
+            root: parse_riff(data)?,
+            compressed: None,
+        })
+    }
+
+    /// The `RIFF` chunk everything else is in
+    #[must_use]
+    pub const fn root(&self) -> &Chunk {
+        &self.root
+    }
+
+    #[must_use]
+    pub const fn is_compressed(&self) -> bool {
+        self.compressed.is_some()
+    }
+
+    #[must_use]
+    pub fn metadata(&self) -> CgrMetadata {
+        let text = |id| {
+            self.root
+                .find_data(id)
+                .map(|data| {
+                    // Strings are NUL-terminated
+                    let value = data.split(|byte| *byte == 0).next().unwrap_or_default();
+                    String::from_utf8_lossy(value).trim().to_owned()
+                })
+                .filter(|value| !value.is_empty())
+        };
+        let length = self
+            .root
+            .find_data(b"fmt ")
+            .and_then(|format| read_u32(format, 8))
+            .filter(|byte_rate| *byte_rate > 0)
+            .zip(self.root.find_data(b"data"))
+            .map(|(byte_rate, audio)| {
+                Duration::from_secs_f64(audio.len() as f64 / byte_rate as f64)
+            });
+
+        CgrMetadata {
+            title: text(b"INAM"),
+            artist: text(b"IART"),
+            length,
+        }
+    }
+
+    /// The file as it was read.
+    #[must_use]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        if let Some(compressed) = &self.compressed {
+            return compressed.clone();
+        }
+
+        let mut out = Vec::with_capacity(self.root.size_with_header());
+        self.root.write(&mut out);
+        out
+    }
+}
+
+/// Whether the data starts like a zlib stream, which is how compressed CGRs come
+fn is_zlib(data: &[u8]) -> bool {
+    match data {
+        [cmf, flg, ..] => cmf & 0x0F == 8 && u16::from_be_bytes([*cmf, *flg]) % 31 == 0,
+        _ => false,
+    }
+}
+
+fn decompress(data: &[u8]) -> Result<Vec<u8>, CgrError> {
+    let mut decompressed = Vec::new();
+    ZlibDecoder::new(data)
+        .take(MAX_CGR_SIZE as u64 + 1)
+        .read_to_end(&mut decompressed)
+        .map_err(|_| CgrError::BadCompression)?;
+    if decompressed.len() > MAX_CGR_SIZE {
+        return Err(CgrError::TooLarge);
+    }
+
+    Ok(decompressed)
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<usize> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes(bytes.try_into().ok()?) as usize)
+}
+
+/// RIFF IDs are four printable ASCII characters
+fn fourcc(data: &[u8], offset: usize) -> Option<[u8; 4]> {
+    let id: [u8; 4] = data.get(offset..offset + 4)?.try_into().ok()?;
+    id.iter()
+        .all(|byte| (0x20..=0x7E).contains(byte))
+        .then_some(id)
+}
+
+fn parse_riff(data: &[u8]) -> Result<Chunk, CgrError> {
+    if !data.starts_with(b"RIFF") || fourcc(data, 8).is_none() {
+        return Err(CgrError::NotRiff);
+    }
+    let declared = read_u32(data, 4).ok_or(CgrError::NotRiff)?;
+    let actual = data.len() - 8;
+    if declared != actual && declared + declared % 2 != actual {
+        return Err(CgrError::SizeMismatch { declared, actual });
+    }
+
+    // The sizes were checked, so this is exactly the RIFF chunk
+    parse_chunks(data, 0, data.len(), 0)?
+        .into_iter()
+        .next()
+        .ok_or(CgrError::NotRiff)
+}
+
+/// Parses the chunks between `start` and `end`, which have to fill it exactly.
+/// Offsets in errors are from the start of `data`.
+fn parse_chunks(
+    data: &[u8],
+    start: usize,
+    end: usize,
+    depth: usize,
+) -> Result<Vec<Chunk>, CgrError> {
+    if depth > MAX_CGR_DEPTH {
+        return Err(CgrError::TooDeep);
+    }
+
+    let mut chunks = Vec::new();
+    let mut offset = start;
+    while offset < end {
+        if end - offset < 8 {
+            return Err(CgrError::TruncatedChunk(offset));
+        }
+        let id = fourcc(data, offset).ok_or(CgrError::InvalidChunkId(offset))?;
+        let size = read_u32(data, offset + 4).ok_or(CgrError::TruncatedChunk(offset))?;
+        let content_end = (offset + 8)
+            .checked_add(size)
+            .filter(|content_end| *content_end <= end)
+            .ok_or(CgrError::TruncatedChunk(offset))?;
+        // Chunks are padded to an even size, unless their parent ends right after them
+        let pad = (size % 2 == 1 && content_end < end).then(|| data[content_end]);
+
+        let body = if &id == b"RIFF" || &id == b"LIST" {
+            let form_type = fourcc(data, offset + 8)
+                .filter(|_| size >= 4)
+                .ok_or(CgrError::MissingFormType(offset))?;
+            ChunkBody::List {
+                form_type,
+                chunks: parse_chunks(data, offset + 12, content_end, depth + 1)?,
+            }
+        } else {
+            ChunkBody::Data(data[offset + 8..content_end].to_vec())
+        };
+
+        chunks.push(Chunk { id, body, pad });
+        offset = content_end + usize::from(pad.is_some());
+    }
+
+    Ok(chunks)
+}
+
+/// Whether `file_name` is fine to store in `cgr_location` and put into a URL as it is
+#[must_use]
+pub fn is_valid_file_name(file_name: &str) -> bool {
+    file_name.len() <= 128
+        && !file_name.starts_with('.')
+        && file_name
+            .get(file_name.len().saturating_sub(4)..)
+            .is_some_and(|extension| extension.eq_ignore_ascii_case(".cgr"))
+        && file_name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::{write::ZlibEncoder, Compression};
+
+    use super::*;
+
+    fn chunk(id: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut chunk = id.to_vec();
+        chunk.extend_from_slice(&u32::try_from(data.len()).unwrap().to_le_bytes());
+        chunk.extend_from_slice(data);
+        if data.len() % 2 == 1 {
+            chunk.push(0);
+        }
+        chunk
+    }
+
+    fn list(id: &[u8; 4], form_type: &[u8; 4], chunks: &[Vec<u8>]) -> Vec<u8> {
+        let mut content = form_type.to_vec();
+        content.extend(chunks.concat());
+        chunk(id, &content)
+    }
+
+    /// A channel group with a song name and a second of 8 kHz mono audio
+    fn cgr() -> Vec<u8> {
+        let format = [1, 0, 1, 0, 0x40, 0x1F, 0, 0, 0x40, 0x1F, 0, 0, 1, 0, 8, 0];
+        list(
+            b"RIFF",
+            b"CGR ",
+            &[
+                list(
+                    b"LIST",
+                    b"INFO",
+                    &[chunk(b"INAM", b"Dear Music.\0"), chunk(b"IART", b"A4.\0")],
+                ),
+                chunk(b"fmt ", &format),
+                chunk(b"data", &[0x80; 8000]),
+                chunk(b"odd ", b"abc"),
+            ],
+        )
+    }
+
+    #[test]
+    fn files_are_written_back_as_they_were() {
+        let file = cgr();
+        let parsed = CgrFile::parse(&file).unwrap();
+        assert!(!parsed.is_compressed());
+        assert_eq!(parsed.to_bytes(), file);
+
+        // Padding that isn't 0, and a last chunk without any
+        let mut file = list(
+            b"RIFF",
+            b"CGR ",
+            &[chunk(b"odd ", b"abc"), chunk(b"end ", b"x")],
+        );
+        file[23] = 0xFF;
+        file.pop();
+        file[4] -= 1;
+        assert_eq!(CgrFile::parse(&file).unwrap().to_bytes(), file);
+
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&cgr()).unwrap();
+        let compressed = encoder.finish().unwrap();
+        let parsed = CgrFile::parse(&compressed).unwrap();
+        assert!(parsed.is_compressed());
+        assert_eq!(parsed.root(), CgrFile::parse(&cgr()).unwrap().root());
+        assert_eq!(parsed.to_bytes(), compressed);
+    }
+
+    #[test]
+    fn chunks_and_metadata_are_found() {
+        let parsed = CgrFile::parse(&cgr()).unwrap();
+        let ids: Vec<&[u8; 4]> = parsed.root().walk().map(|chunk| &chunk.id).collect();
+        assert_eq!(
+            ids,
+            [b"RIFF", b"LIST", b"INAM", b"IART", b"fmt ", b"data", b"odd "]
+        );
+        assert_eq!(
+            parsed.metadata(),
+            CgrMetadata {
+                title: Some("Dear Music.".to_owned()),
+                artist: Some("A4.".to_owned()),
+                length: Some(Duration::from_secs(1)),
+            }
+        );
+
+        let bare = list(b"RIFF", b"CGR ", &[chunk(b"data", &[1, 2])]);
+        assert_eq!(
+            CgrFile::parse(&bare).unwrap().metadata(),
+            CgrMetadata::default()
+        );
+    }
+
+    #[test]
+    fn broken_files_are_rejected() {
+        let parse = |data: &[u8]| CgrFile::parse(data).map(|_| ()).unwrap_err();
+        assert_eq!(parse(b""), CgrError::NotRiff);
+        assert_eq!(parse(b"<html>Not Found</html>"), CgrError::NotRiff);
+
+        let file = cgr();
+        assert!(matches!(
+            parse(&file[..file.len() - 2]),
+            CgrError::SizeMismatch { .. }
+        ));
+
+        let mut file = list(b"RIFF", b"CGR ", &[chunk(b"data", &[1, 2, 3, 4])]);
+        file[16] = 200;
+        assert_eq!(parse(&file), CgrError::TruncatedChunk(12));
+
+        let mut file = list(b"RIFF", b"CGR ", &[chunk(b"data", &[1, 2, 3, 4])]);
+        file[12] = 0;
+        assert_eq!(parse(&file), CgrError::InvalidChunkId(12));
+
+        let file = list(b"RIFF", b"CGR ", &[chunk(b"LIST", b"")]);
+        assert_eq!(parse(&file), CgrError::MissingFormType(12));
+
+        assert_eq!(parse(&[0x78, 0x9C, 1, 2, 3]), CgrError::BadCompression);
+    }
+
+    #[test]
+    fn deeply_nested_lists_are_rejected() {
+        let nested = |depth: usize| {
+            let mut inner = chunk(b"INAM", b"x");
+            for _ in 0..depth {
+                inner = list(b"LIST", b"INFO", &[inner]);
+            }
+            list(b"RIFF", b"CGR ", &[inner])
+        };
+
+        assert!(CgrFile::parse(&nested(MAX_CGR_DEPTH - 1)).is_ok());
+        assert_eq!(
+            CgrFile::parse(&nested(MAX_CGR_DEPTH)).map(|_| ()),
+            Err(CgrError::TooDeep)
+        );
+
+        // Deep enough to overflow the stack without the limit. Built flat, since
+        // wrapping it one level at a time copies it once per level.
+        let depth = 100_000;
+        let mut headers = Vec::with_capacity(depth);
+        let mut size = 4;
+        for _ in 0..depth {
+            headers.push(size);
+            size += 12;
+        }
+        let mut file = Vec::with_capacity(size + 8);
+        file.extend_from_slice(b"RIFF");
+        file.extend_from_slice(&u32::try_from(size).unwrap().to_le_bytes());
+        file.extend_from_slice(b"CGR ");
+        for size in headers.iter().rev() {
+            file.extend_from_slice(b"LIST");
+            file.extend_from_slice(&u32::try_from(*size).unwrap().to_le_bytes());
+            file.extend_from_slice(b"INFO");
+        }
+        assert_eq!(CgrFile::parse(&file).map(|_| ()), Err(CgrError::TooDeep));
+    }
+
+    #[test]
+    fn only_plain_cgr_file_names_are_stored() {
+        assert!(is_valid_file_name("WVBR_A4_DearMusic.cgr"));
+        assert!(is_valid_file_name("song-2.CGR"));
+        assert!(!is_valid_file_name(".cgr"));
+        assert!(!is_valid_file_name("../Wavebreaker.cgr"));
+        assert!(!is_valid_file_name("song.mp3"));
+        assert!(!is_valid_file_name("sch\u{00F6}n.cgr"));
+        assert!(!is_valid_file_name("a b.cgr"));
+    }
+}
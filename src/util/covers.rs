@@ -0,0 +1,333 @@
+use std::{path::Path, sync::OnceLock, time::Duration};
+
+use anyhow::{anyhow, Context};
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use reqwest::{header, redirect, Url};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tracing::{debug, warn};
+
+use crate::{
+    models::extra_song_info::ExtraSongInfo, schema::extra_song_info, WAVEBREAKER_USER_AGENT,
+};
+
+static MEDIA: OnceLock<MediaConfig> = OnceLock::new();
+
+/// How long the Cover Art Archive gets to send a cover
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(30);
+/// Covers bigger than this aren't cached. The 500px ones are way smaller
+const MAX_COVER_BYTES: usize = 10 * 1024 * 1024;
+/// The Cover Art Archive redirects to the Internet Archive, which stores the images
+const COVER_HOSTS: [&str; 2] = ["coverartarchive.org", "archive.org"];
+/// More redirects than the Cover Art Archive needs
+const MAX_REDIRECTS: usize = 5;
+
+/// Where cover art is cached, so song pages don't hotlink the Cover Art Archive.
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct MediaConfig {
+    /// Directory the covers are stored in
+    pub cover_directory: String,
+    /// Where clients can get the covers from, `/api/media/covers` is served by Wavebreaker itself
+    pub cover_url_prefix: String,
+    /// If off, covers aren't downloaded anymore, but the ones already cached are still used
+    pub cache_covers: bool,
+}
+
+impl Default for MediaConfig {
+    fn default() -> Self {
+        Self {
+            cover_directory: "./media/covers".to_owned(),
+            cover_url_prefix: "/api/media/covers".to_owned(),
+            cache_covers: true,
+        }
+    }
+}
+
+/// Sets the media config used from now on. Only the first call has an effect.
+pub fn install(config: MediaConfig) {
+    if MEDIA.set(config).is_err() {
+        warn!("Media config was already installed, keeping the first one");
+    }
+}
+
+/// Whether new covers are downloaded.
+pub fn caching_enabled() -> bool {
+    current().cache_covers
+}
+
+fn current() -> &'static MediaConfig {
+    MEDIA.get_or_init(MediaConfig::default)
+}
+
+/// URL of a cached cover for clients.
+#[must_use]
+pub fn public_url(file_name: &str) -> String {
+    format!(
+        "{}/{file_name}",
+        current().cover_url_prefix.trim_end_matches('/')
+    )
+}
+
+/// File names of cached covers, to store along with their URLs.
+///
+/// A field is `None` if its cover URL doesn't change, so an existing file stays,
+/// and `Some(None)` if caching the new cover failed, so the old cover's file isn't shown instead.
+#[derive(AsChangeset, Debug, Default, PartialEq, Eq)]
+#[diesel(table_name = extra_song_info)]
+pub struct CachedCovers {
+    pub cover_file: Option<Option<String>>,
+    pub cover_file_small: Option<Option<String>>,
+}
+
+impl CachedCovers {
+    /// Caches the covers behind the URLs that are given. Failures are only logged.
+    pub async fn fetch(cover_url: Option<&str>, cover_url_small: Option<&str>) -> Self {
+        Self {
+            cover_file: match cover_url {
+                Some(url) => Some(try_cache(url).await),
+                None => None,
+            },
+            cover_file_small: match cover_url_small {
+                Some(url) => Some(try_cache(url).await),
+                None => None,
+            },
+        }
+    }
+
+    /// Like [`Self::fetch`], but only caches the covers whose URL differs from the one in `existing`.
+    /// A cover URL that was removed also removes its file.
+    pub async fn fetch_changed(
+        cover_url: Option<&str>,
+        cover_url_small: Option<&str>,
+        existing: Option<&ExtraSongInfo>,
+    ) -> Self {
+        Self {
+            cover_file: cache_changed(
+                cover_url,
+                existing.and_then(|info| info.cover_url.as_deref()),
+            )
+            .await,
+            cover_file_small: cache_changed(
+                cover_url_small,
+                existing.and_then(|info| info.cover_url_small.as_deref()),
+            )
+            .await,
+        }
+    }
+
+    /// Stores the file names on the extra info, if any cover URL changed.
+    ///
+    /// # Errors
+    /// Fails if something is wrong with the database.
+    pub async fn save(
+        self,
+        extra_info: ExtraSongInfo,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<ExtraSongInfo> {
+        if self == Self::default() {
+            return Ok(extra_info);
+        }
+
+        diesel::update(&extra_info).set(self).get_result(conn).await
+    }
+}
+
+async fn cache_changed(url: Option<&str>, old_url: Option<&str>) -> Option<Option<String>> {
+    if url == old_url {
+        return None;
+    }
+
+    match url {
+        Some(url) => Some(try_cache(url).await),
+        None => Some(None),
+    }
+}
+
+/// Like [`cache_cover`], but only logs failures.
+async fn try_cache(url: &str) -> Option<String> {
+    if !caching_enabled() {
+        return None;
+    }
+
+    match cache_cover(url).await {
+        Ok(file_name) => Some(file_name),
+        Err(e) => {
+            warn!("Failed to cache cover {url}: {e:#}");
+            None
+        }
+    }
+}
+
+/// Downloads a cover into the cover directory.
+/// Files are named after a hash of their contents, so releases sharing a cover share the file too.
+///
+/// # Returns
+/// The name of the file in the cover directory.
+///
+/// # Errors
+/// Fails if the cover can't be downloaded, isn't an image or can't be written.
+async fn cache_cover(url: &str) -> anyhow::Result<String> {
+    let url = Url::parse(url)?;
+    if !is_cover_host(&url) {
+        return Err(anyhow!(
+            "Covers are only downloaded from the Cover Art Archive"
+        ));
+    }
+
+    // Player-supplied URLs end up here too, so redirects mustn't lead anywhere else
+    let client = reqwest::Client::builder()
+        .redirect(redirect::Policy::custom(|attempt| {
+            if attempt.previous().len() >= MAX_REDIRECTS {
+                attempt.error("too many redirects")
+            } else if is_cover_host(attempt.url()) {
+                attempt.follow()
+            } else {
+                attempt.error("redirected away from the Cover Art Archive")
+            }
+        }))
+        .build()?;
+    let mut response = client
+        .get(url.as_str())
+        .header(header::USER_AGENT, WAVEBREAKER_USER_AGENT)
+        .timeout(DOWNLOAD_TIMEOUT)
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        return Err(anyhow!(
+            "Cover Art Archive answered with {}",
+            response.status()
+        ));
+    }
+
+    let content_type = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    let extension = image_extension(content_type)
+        .ok_or_else(|| anyhow!("Cover isn't a known image type, but {content_type:?}"))?;
+    // Read bit by bit, so a huge file is given up on without holding all of it
+    let too_big = || anyhow!("Cover is bigger than {MAX_COVER_BYTES} bytes");
+    if response
+        .content_length()
+        .is_some_and(|length| length > MAX_COVER_BYTES as u64)
+    {
+        return Err(too_big());
+    }
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        if body.len() + chunk.len() > MAX_COVER_BYTES {
+            return Err(too_big());
+        }
+        body.extend_from_slice(&chunk);
+    }
+
+    let file_name = file_name(&body, extension);
+    let directory = Path::new(&current().cover_directory);
+    let path = directory.join(&file_name);
+    if tokio::fs::try_exists(&path).await.unwrap_or(false) {
+        debug!("Cover {url} is already cached as {file_name}");
+        return Ok(file_name);
+    }
+
+    tokio::fs::create_dir_all(directory)
+        .await
+        .context("Failed to create cover directory")?;
+    // Written under another name first, so a half-written cover is never served
+    let partial_path = directory.join(format!("{file_name}.part"));
+    tokio::fs::write(&partial_path, &body)
+        .await
+        .context("Failed to write cover")?;
+    tokio::fs::rename(&partial_path, &path)
+        .await
+        .context("Failed to move cover into place")?;
+
+    debug!("Cached cover {url} as {file_name}");
+    Ok(file_name)
+}
+
+/// Whether the URL points at the Cover Art Archive or the Internet Archive.
+fn is_cover_host(url: &Url) -> bool {
+    matches!(url.scheme(), "http" | "https")
+        && url.host_str().is_some_and(|host| {
+            COVER_HOSTS.iter().any(|allowed| {
+                host == *allowed
+                    || host
+                        .strip_suffix(allowed)
+                        .is_some_and(|subdomain| subdomain.ends_with('.'))
+            })
+        })
+}
+
+/// File extension for the image types the Cover Art Archive serves.
+fn image_extension(content_type: &str) -> Option<&'static str> {
+    let mime = content_type.split(';').next()?.trim();
+    match mime.to_ascii_lowercase().as_str() {
+        "image/jpeg" | "image/jpg" => Some("jpg"),
+        "image/png" => Some("png"),
+        "image/gif" => Some("gif"),
+        "image/webp" => Some("webp"),
+        _ => None,
+    }
+}
+
+fn file_name(contents: &[u8], extension: &str) -> String {
+    format!("{:x}.{extension}", Sha256::digest(contents))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_cover_art_archive_urls_are_downloaded() {
+        let allowed = [
+            "https://coverartarchive.org/release/1/2-500.jpg",
+            "http://coverartarchive.org/release/1/2-250.jpg",
+            "https://ia800505.us.archive.org/1/items/mbid-1/mbid-1-2_thumb500.jpg",
+        ];
+        for url in allowed {
+            assert!(is_cover_host(&Url::parse(url).unwrap()), "{url}");
+        }
+
+        let refused = [
+            "https://example.com/cover.jpg",
+            "https://evilarchive.org/cover.jpg",
+            "https://coverartarchive.org.example.com/cover.jpg",
+            "ftp://coverartarchive.org/cover.jpg",
+            "http://127.0.0.1/cover.jpg",
+        ];
+        for url in refused {
+            assert!(!is_cover_host(&Url::parse(url).unwrap()), "{url}");
+        }
+    }
+
+    #[tokio::test]
+    async fn unchanged_covers_are_not_downloaded_again() {
+        let url = Some("https://coverartarchive.org/release/1/2-500.jpg");
+        assert_eq!(cache_changed(url, url).await, None);
+        assert_eq!(cache_changed(None, None).await, None);
+        // The file of a removed cover isn't shown anymore
+        assert_eq!(cache_changed(None, url).await, Some(None));
+    }
+
+    #[test]
+    fn only_images_get_an_extension() {
+        assert_eq!(image_extension("image/jpeg"), Some("jpg"));
+        assert_eq!(image_extension("IMAGE/PNG; charset=binary"), Some("png"));
+        assert_eq!(image_extension("text/html; charset=utf-8"), None);
+        assert_eq!(image_extension(""), None);
+    }
+
+    #[test]
+    fn files_are_named_after_their_contents() {
+        assert_eq!(
+            file_name(b"", "jpg"),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855.jpg"
+        );
+        assert_eq!(file_name(b"cover", "png"), file_name(b"cover", "png"));
+        assert_ne!(file_name(b"cover", "png"), file_name(b"other", "png"));
+    }
+}
@@ -0,0 +1,131 @@
+use std::{sync::OnceLock, time::Duration};
+
+use anyhow::anyhow;
+use reqwest::header;
+use serde_json::{json, Value};
+use tracing::{info, warn};
+
+use super::game_types::League;
+
+/// How long Discord gets to answer a webhook call
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+/// Wait before the one retry of a failed webhook call
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+/// How often a webhook call is tried before giving up
+const ATTEMPTS: u32 = 2;
+/// Sidebar color of the embed
+const EMBED_COLOR: u32 = 0x00_8B_D2;
+
+/// Shared by all webhook calls, so they can reuse connections
+static HTTP_CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+
+/// A global top score that changed hands
+#[derive(Debug, Clone)]
+pub struct TopScoreAnnouncement {
+    pub song_title: String,
+    pub song_artist: String,
+    pub league: League,
+    pub player_name: String,
+    pub new_score: i32,
+    pub previous_holder: String,
+    pub previous_score: i32,
+}
+
+impl TopScoreAnnouncement {
+    /// The webhook payload, with the announcement as a Discord embed
+    fn to_webhook_body(&self, server_name: &str) -> Value {
+        json!({
+            "username": server_name,
+            "embeds": [{
+                "title": format!("New #1 on {} - {}", self.song_artist, self.song_title),
+                "description": format!(
+                    "**{}** took the top spot in {} from **{}**",
+                    self.player_name,
+                    self.league.name(),
+                    self.previous_holder
+                ),
+                "color": EMBED_COLOR,
+                "fields": [
+                    { "name": "New score", "value": self.new_score.to_string(), "inline": true },
+                    { "name": "Previous score", "value": self.previous_score.to_string(), "inline": true },
+                ],
+            }],
+        })
+    }
+}
+
+/// Posts the announcement to the webhook in the background, retrying once if that fails.
+/// Errors are only logged, the caller never waits for Discord.
+pub fn announce_top_score(
+    webhook_url: String,
+    server_name: String,
+    announcement: TopScoreAnnouncement,
+) {
+    tokio::spawn(async move {
+        let body = announcement.to_webhook_body(&server_name).to_string();
+        for attempt in 1..=ATTEMPTS {
+            match post_webhook(&webhook_url, body.clone()).await {
+                Ok(()) => {
+                    info!(
+                        "Announced new #1 of {} on {} - {} to Discord",
+                        announcement.player_name, announcement.song_artist, announcement.song_title
+                    );
+                    return;
+                }
+                Err(e) => {
+                    warn!(
+                        "Failed to announce new #1 to Discord (attempt {attempt}/{ATTEMPTS}): {e:#}"
+                    );
+                    if attempt < ATTEMPTS {
+                        tokio::time::sleep(RETRY_DELAY).await;
+                    }
+                }
+            }
+        }
+    });
+}
+
+async fn post_webhook(webhook_url: &str, body: String) -> anyhow::Result<()> {
+    let response = HTTP_CLIENT
+        .get_or_init(reqwest::Client::new)
+        .post(webhook_url)
+        .header(header::CONTENT_TYPE, "application/json")
+        .timeout(WEBHOOK_TIMEOUT)
+        .body(body)
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        return Err(anyhow!("Discord answered with {}", response.status()));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn announcement_is_an_embed() {
+        let announcement = TopScoreAnnouncement {
+            song_title: "Dear Music".to_owned(),
+            song_artist: "A4.".to_owned(),
+            league: League::Elite,
+            player_name: "Dan".to_owned(),
+            new_score: 250_000,
+            previous_holder: "Duck".to_owned(),
+            previous_score: 240_000,
+        };
+
+        let body = announcement.to_webhook_body("wavebreaker-rs");
+        assert_eq!(body["username"], "wavebreaker-rs");
+        let embed = &body["embeds"][0];
+        assert_eq!(embed["title"], "New #1 on A4. - Dear Music");
+        assert_eq!(
+            embed["description"],
+            "**Dan** took the top spot in Elite from **Duck**"
+        );
+        assert_eq!(embed["fields"][0]["value"], "250000");
+        assert_eq!(embed["fields"][1]["value"], "240000");
+    }
+}
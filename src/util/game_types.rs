@@ -12,6 +12,7 @@ use utoipa::ToSchema;
     Debug,
     Eq,
     PartialEq,
+    Hash,
     Clone,
     Copy,
     TryFromPrimitive,
@@ -26,6 +27,20 @@ pub enum League {
     Elite,
 }
 
+impl League {
+    pub const ALL: [Self; 3] = [Self::Casual, Self::Pro, Self::Elite];
+
+    /// The name the game shows for the league
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Casual => "Casual",
+            Self::Pro => "Pro",
+            Self::Elite => "Elite",
+        }
+    }
+}
+
 /// Represents a character/vehicle in the game.
 #[derive(
     AsExpression,
@@ -86,6 +101,43 @@ where
         .collect::<Result<Vec<T>, T::Err>>()
 }
 
+/// Steam doesn't allow more friends than this, so longer friend lists are cut off here
+pub const MAX_FRIENDS: usize = 2000;
+
+/// Steam account numbers of a player's friends, as sent by the game when syncing rivals.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct FriendList {
+    /// Valid account numbers, without duplicates
+    pub account_nums: Vec<i32>,
+    /// How many entries were left out because they're invalid or over `MAX_FRIENDS`
+    pub skipped: usize,
+}
+
+impl FriendList {
+    /// Parses an x-separated list of account numbers.
+    /// Unlike `split_x_separated`, invalid entries are skipped instead of failing the whole list.
+    pub fn parse(s: &str) -> Self {
+        let mut list = Self::default();
+        let s = s.strip_suffix('x').unwrap_or(s);
+        if s.is_empty() {
+            return list;
+        }
+
+        for (index, entry) in s.split('x').enumerate() {
+            match entry.parse::<i32>() {
+                Ok(account_num) if account_num > 0 && index < MAX_FRIENDS => {
+                    list.account_nums.push(account_num);
+                }
+                _ => list.skipped += 1,
+            }
+        }
+        list.account_nums.sort_unstable();
+        list.account_nums.dedup();
+
+        list
+    }
+}
+
 pub fn join_x_separated<T>(v: &[T]) -> String
 where
     T: std::fmt::Display,
@@ -121,6 +173,30 @@ mod tests {
         assert!(split_x_separated::<i32>(input3).is_err());
     }
 
+    #[test]
+    fn friend_list_skips_garbage() {
+        assert_eq!(
+            FriendList::parse("5x3xAAAxx-1x3x0x"),
+            FriendList {
+                account_nums: vec![3, 5],
+                skipped: 4,
+            }
+        );
+        assert_eq!(FriendList::parse(""), FriendList::default());
+        assert_eq!(FriendList::parse("x"), FriendList::default());
+    }
+
+    #[test]
+    fn friend_list_is_capped() {
+        let input = (1..=MAX_FRIENDS + 5)
+            .map(|num| num.to_string())
+            .collect::<Vec<String>>()
+            .join("x");
+        let list = FriendList::parse(&input);
+        assert_eq!(list.account_nums.len(), MAX_FRIENDS);
+        assert_eq!(list.skipped, 5);
+    }
+
     #[test]
     fn test_join_x_separated() {
         // Test case 1: Valid input
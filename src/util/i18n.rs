@@ -0,0 +1,356 @@
+use diesel::{deserialize::FromSqlRow, expression::AsExpression};
+use num_enum::{IntoPrimitive, TryFromPrimitive};
+use serde_repr::{Deserialize_repr, Serialize_repr};
+use utoipa::ToSchema;
+
+/// Languages in-game text is available in.
+///
+/// 0 = English, 1 = German
+#[derive(
+    AsExpression,
+    FromSqlRow,
+    Serialize_repr,
+    Deserialize_repr,
+    Debug,
+    Default,
+    Eq,
+    PartialEq,
+    Clone,
+    Copy,
+    TryFromPrimitive,
+    IntoPrimitive,
+    ToSchema,
+)]
+#[diesel(sql_type = diesel::sql_types::SmallInt)]
+#[repr(i16)]
+pub enum Language {
+    #[default]
+    English,
+    German,
+}
+
+/// Every shipped language, for checking that the catalogs are complete
+pub const ALL_LANGUAGES: [Language; 2] = [Language::English, Language::German];
+
+const ENGLISH: &[(&str, &str)] = &[
+    (
+        "news.welcome",
+        "Hi, {username}!\n\nWelcome to {server_name},\nthe next generation of Wavebreaker!",
+    ),
+    (
+        "news.rank",
+        "You're rank #{rank} with {skill_points} skill points.",
+    ),
+    ("news.challenger", "1 new challenger since your last login!"),
+    (
+        "news.challengers",
+        "{count} new challengers since your last login!",
+    ),
+    (
+        "shouts.none",
+        "This song has no shouts yet. Let's change that!\n'Cause we're gonna shout it loud!",
+    ),
+    ("shouts.entry", "{username} ({time}): {content}"),
+    ("shouts.more", "Older shouts are on {website}"),
+    ("shouts.empty", "Your shout was empty, so it wasn't posted."),
+    (
+        "shouts.too_long",
+        "Your shout was too long, only {max} characters fit.",
+    ),
+    ("shouts.duplicate", "You already shouted that on this song!"),
+    (
+        "shouts.rate_limited",
+        "Slow down! You can only shout {count} times a minute.",
+    ),
+    ("rides.dethroned", "{username} took your record {ago}!"),
+    ("time.just_now", "just now"),
+    ("time.minute_ago", "a minute ago"),
+    ("time.minutes_ago", "{count} minutes ago"),
+    ("time.hour_ago", "an hour ago"),
+    ("time.hours_ago", "{count} hours ago"),
+    ("time.day_ago", "a day ago"),
+    ("time.days_ago", "{count} days ago"),
+];
+
+const GERMAN: &[(&str, &str)] = &[
+    (
+        "news.welcome",
+        "Hallo, {username}!\n\nWillkommen bei {server_name},\nder nächsten Generation von Wavebreaker!",
+    ),
+    (
+        "news.rank",
+        "Du bist auf Platz {rank} mit {skill_points} Skillpunkten.",
+    ),
+    (
+        "news.challenger",
+        "1 neuer Herausforderer seit deinem letzten Login!",
+    ),
+    (
+        "news.challengers",
+        "{count} neue Herausforderer seit deinem letzten Login!",
+    ),
+    (
+        "shouts.none",
+        "Zu diesem Song gibt es noch keine Shouts. Ändern wir das!\n'Cause we're gonna shout it loud!",
+    ),
+    ("shouts.entry", "{username} ({time}): {content}"),
+    ("shouts.more", "Ältere Shouts findest du auf {website}"),
+    (
+        "shouts.empty",
+        "Dein Shout war leer und wurde deshalb nicht gepostet.",
+    ),
+    (
+        "shouts.too_long",
+        "Dein Shout war zu lang, es passen nur {max} Zeichen.",
+    ),
+    (
+        "shouts.duplicate",
+        "Das hast du zu diesem Song schon geshoutet!",
+    ),
+    (
+        "shouts.rate_limited",
+        "Nicht so schnell! Du kannst nur {count} Mal pro Minute shouten.",
+    ),
+    ("rides.dethroned", "{username} hat dir {ago} den Rekord abgenommen!"),
+    ("time.just_now", "gerade eben"),
+    ("time.minute_ago", "vor einer Minute"),
+    ("time.minutes_ago", "vor {count} Minuten"),
+    ("time.hour_ago", "vor einer Stunde"),
+    ("time.hours_ago", "vor {count} Stunden"),
+    ("time.day_ago", "vor einem Tag"),
+    ("time.days_ago", "vor {count} Tagen"),
+];
+
+impl Language {
+    /// Guesses the language from a locale like `de_DE` or `de-AT`.
+    /// Returns `None` for languages without a catalog.
+    pub fn from_locale(locale: &str) -> Option<Self> {
+        let language = locale
+            .split(['_', '-', '.'])
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        match language.as_str() {
+            "en" => Some(Self::English),
+            "de" => Some(Self::German),
+            _ => None,
+        }
+    }
+
+    const fn catalog(self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Self::English => ENGLISH,
+            Self::German => GERMAN,
+        }
+    }
+}
+
+fn lookup(language: Language, key: &str) -> Option<&'static str> {
+    language
+        .catalog()
+        .iter()
+        .find(|(entry_key, _)| *entry_key == key)
+        .map(|(_, text)| *text)
+}
+
+/// Gets the text for `key` in `language`, filling in `{name}` placeholders from `args`.
+///
+/// Falls back to English if the language doesn't have the key, and to the key itself if English doesn't either.
+pub fn text(language: Language, key: &str, args: &[(&str, &str)]) -> String {
+    let template = lookup(language, key)
+        .or_else(|| lookup(Language::English, key))
+        .unwrap_or(key);
+
+    fill(template, args)
+}
+
+/// Fills in `{name}` placeholders in `template` from `args`. Unknown placeholders are kept as they are.
+pub fn fill(template: &str, args: &[(&str, &str)]) -> String {
+    // Filled in one pass, so placeholders inside values (e.g. a shout containing `{time}`) stay as they are
+    let mut result = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        result.push_str(&rest[..start]);
+        let placeholder = &rest[start..];
+        let value = placeholder.find('}').and_then(|end| {
+            let name = &placeholder[1..end];
+            args.iter()
+                .find(|(arg_name, _)| *arg_name == name)
+                .map(|(_, value)| (*value, end))
+        });
+        if let Some((value, end)) = value {
+            result.push_str(value);
+            rest = &placeholder[end + 1..];
+        } else {
+            result.push('{');
+            rest = &placeholder[1..];
+        }
+    }
+    result.push_str(rest);
+
+    result
+}
+
+/// Writes a number with the language's thousands separators, e.g. "45,300".
+pub fn number(language: Language, number: i64) -> String {
+    let separator = match language {
+        Language::English => ',',
+        Language::German => '.',
+    };
+
+    let digits = number.unsigned_abs().to_string();
+    let mut result = String::with_capacity(digits.len() + digits.len() / 3 + 1);
+    if number < 0 {
+        result.push('-');
+    }
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i) % 3 == 0 {
+            result.push(separator);
+        }
+        result.push(digit);
+    }
+
+    result
+}
+
+/// Says how long ago something happened, e.g. "2 days ago", in the largest unit that fits.
+pub fn time_ago(language: Language, elapsed: time::Duration) -> String {
+    let (count, one, many) = if elapsed.whole_days() > 0 {
+        (elapsed.whole_days(), "time.day_ago", "time.days_ago")
+    } else if elapsed.whole_hours() > 0 {
+        (elapsed.whole_hours(), "time.hour_ago", "time.hours_ago")
+    } else if elapsed.whole_minutes() > 0 {
+        (
+            elapsed.whole_minutes(),
+            "time.minute_ago",
+            "time.minutes_ago",
+        )
+    } else {
+        return text(language, "time.just_now", &[]);
+    };
+
+    if count == 1 {
+        text(language, one, &[])
+    } else {
+        text(language, many, &[("count", &count.to_string())])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_language_has_every_key() {
+        for language in ALL_LANGUAGES {
+            for (key, _) in ENGLISH {
+                assert!(
+                    lookup(language, key).is_some(),
+                    "{language:?} is missing {key}"
+                );
+            }
+            assert_eq!(
+                language.catalog().len(),
+                ENGLISH.len(),
+                "{language:?} has keys English doesn't have"
+            );
+        }
+    }
+
+    #[test]
+    fn numbers_are_grouped() {
+        assert_eq!(number(Language::English, 45_300), "45,300");
+        assert_eq!(number(Language::German, 1_234_567), "1.234.567");
+        assert_eq!(number(Language::English, 999), "999");
+        assert_eq!(number(Language::English, -1000), "-1,000");
+        assert_eq!(number(Language::English, 0), "0");
+    }
+
+    #[test]
+    fn placeholders_are_filled() {
+        assert_eq!(
+            text(
+                Language::German,
+                "shouts.entry",
+                &[
+                    ("username", "Dan"),
+                    ("time", "vor 2 Tagen"),
+                    ("content", "Hi")
+                ]
+            ),
+            "Dan (vor 2 Tagen): Hi"
+        );
+    }
+
+    #[test]
+    fn placeholders_in_values_are_kept() {
+        assert_eq!(
+            text(
+                Language::English,
+                "shouts.entry",
+                &[
+                    ("username", "{time}"),
+                    ("time", "just now"),
+                    ("content", "{")
+                ]
+            ),
+            "{time} (just now): {"
+        );
+    }
+
+    #[test]
+    fn unknown_placeholders_are_kept() {
+        assert_eq!(
+            fill(
+                "Welcome to {server_name}, {player}!",
+                &[("server_name", "Wavebreaker")]
+            ),
+            "Welcome to Wavebreaker, {player}!"
+        );
+    }
+
+    #[test]
+    fn missing_keys_fall_back() {
+        assert_eq!(
+            text(Language::German, "does.not.exist", &[]),
+            "does.not.exist"
+        );
+    }
+
+    #[test]
+    fn time_ago_uses_largest_unit() {
+        use time::Duration;
+
+        assert_eq!(
+            time_ago(Language::English, Duration::seconds(30)),
+            "just now"
+        );
+        assert_eq!(
+            time_ago(Language::English, Duration::minutes(1)),
+            "a minute ago"
+        );
+        assert_eq!(
+            time_ago(
+                Language::English,
+                Duration::hours(5) + Duration::minutes(59)
+            ),
+            "5 hours ago"
+        );
+        assert_eq!(time_ago(Language::English, Duration::days(2)), "2 days ago");
+        assert_eq!(
+            time_ago(Language::German, Duration::days(1)),
+            "vor einem Tag"
+        );
+        assert_eq!(
+            time_ago(Language::German, Duration::minutes(3)),
+            "vor 3 Minuten"
+        );
+    }
+
+    #[test]
+    fn locales() {
+        assert_eq!(Language::from_locale("de_DE"), Some(Language::German));
+        assert_eq!(Language::from_locale("en-US"), Some(Language::English));
+        assert_eq!(Language::from_locale("fr_FR"), None);
+    }
+}
@@ -0,0 +1,94 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use diesel::connection::{set_default_instrumentation, Instrumentation, InstrumentationEvent};
+use tracing::warn;
+
+/// Queries taking at least this many milliseconds get logged.
+/// `set_default_instrumentation` only takes a function pointer, so this can't live in the logger itself.
+static SLOW_QUERY_THRESHOLD_MS: AtomicU64 = AtomicU64::new(250);
+
+/// Logged SQL is cut off after this many characters
+const MAX_LOGGED_QUERY_LENGTH: usize = 500;
+
+/// Makes every new database connection log queries that take longer than `threshold_ms`.
+///
+/// # Errors
+/// Fails if the default instrumentation can't be set.
+pub fn install_slow_query_logger(threshold_ms: u64) -> diesel::QueryResult<()> {
+    SLOW_QUERY_THRESHOLD_MS.store(threshold_ms, Ordering::Relaxed);
+    set_default_instrumentation(|| Some(Box::new(SlowQueryLogger::default())))
+}
+
+/// Times queries on a single connection and warns about slow ones.
+#[derive(Default)]
+struct SlowQueryLogger {
+    query_start: Option<Instant>,
+}
+
+impl Instrumentation for SlowQueryLogger {
+    fn on_connection_event(&mut self, event: InstrumentationEvent<'_>) {
+        match event {
+            InstrumentationEvent::StartQuery { .. } => {
+                self.query_start = Some(Instant::now());
+            }
+            InstrumentationEvent::FinishQuery { query, .. } => {
+                let Some(query_start) = self.query_start.take() else {
+                    return;
+                };
+
+                let elapsed = query_start.elapsed();
+                let threshold =
+                    Duration::from_millis(SLOW_QUERY_THRESHOLD_MS.load(Ordering::Relaxed));
+                if elapsed >= threshold {
+                    #[allow(clippy::cast_possible_truncation)]
+                    let duration_ms = elapsed.as_millis() as u64;
+                    warn!(
+                        duration_ms,
+                        "Slow query: {}",
+                        sql_without_binds(&query.to_string())
+                    );
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Strips the bind values Diesel appends to debug queries and truncates the SQL.
+fn sql_without_binds(debug_query: &str) -> String {
+    let sql = debug_query
+        .split_once(" -- binds:")
+        .map_or(debug_query, |(sql, _)| sql);
+
+    if sql.chars().count() > MAX_LOGGED_QUERY_LENGTH {
+        let truncated: String = sql.chars().take(MAX_LOGGED_QUERY_LENGTH).collect();
+        format!("{truncated}...")
+    } else {
+        sql.to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn binds_are_removed() {
+        let query =
+            r#"SELECT "players"."id" FROM "players" WHERE "players"."id" = $1 -- binds: [1]"#;
+        assert_eq!(
+            sql_without_binds(query),
+            r#"SELECT "players"."id" FROM "players" WHERE "players"."id" = $1"#
+        );
+    }
+
+    #[test]
+    fn long_queries_are_truncated() {
+        let query = "x".repeat(MAX_LOGGED_QUERY_LENGTH + 10);
+        let expected = format!("{}...", "x".repeat(MAX_LOGGED_QUERY_LENGTH));
+        assert_eq!(sql_without_binds(&query), expected);
+    }
+}
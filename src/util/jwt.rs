@@ -11,8 +11,14 @@ use axum_extra::{
 };
 use jsonwebtoken::{decode, DecodingKey, EncodingKey, Validation};
 use serde::{Deserialize, Serialize};
+use tracing::error;
+use utoipa::ToSchema;
 
-use super::errors::{IntoRouteError, RouteError};
+use super::{
+    access,
+    errors::{IntoRouteError, RouteError},
+    session::verify_session,
+};
 use crate::{models::players::Player, AppState};
 #[derive(Clone)]
 pub struct Keys {
@@ -29,9 +35,12 @@ impl Keys {
     }
 }
 
-#[derive(Debug, Serialize)]
+/// Returned after logging in. The token is sent as `Authorization: Bearer <token>` on authenticated routes.
+#[derive(Debug, Serialize, ToSchema)]
 pub struct AuthBody {
+    /// Signed JWT containing the player's profile, valid for 7 days or until logging out
     access_token: String,
+    /// Always `Bearer`
     token_type: String,
 }
 
@@ -48,6 +57,8 @@ impl AuthBody {
 pub struct Claims {
     pub profile: Player,
     pub exp: i64,
+    /// ID of the session this token belongs to, see `util::session`
+    pub sid: u64,
 }
 
 impl<S> FromRequestParts<S> for Claims
@@ -74,6 +85,149 @@ where
         )
         .http_error("Invalid token", StatusCode::UNAUTHORIZED)?;
 
-        Ok(token_data.claims)
+        // The token is only valid as long as its session wasn't ended (e.g. by logging out)
+        if !verify_session(
+            token_data.claims.sid,
+            token_data.claims.profile.id,
+            &state.redis,
+        )
+        .await?
+        {
+            return Err(RouteError::new_unauthorized()
+                .set_public_error_message("Session expired or logged out"));
+        }
+
+        let mut claims = token_data.claims;
+
+        // The profile in the token is from when the player logged in,
+        // so a changed account type (e.g. a ban) only takes effect when it's looked up again.
+        // That's cached for a minute, so most requests don't need the database for it.
+        let access = access::lookup(claims.profile.id, &state.db, &state.redis)
+            .await?
+            .ok_or_else(|| {
+                RouteError::new_unauthorized().set_public_error_message("Player not found")
+            })?;
+        claims.profile.account_type = access.account_type;
+        if let Some(message) = access.ban_message {
+            return Err(RouteError::new_forbidden().set_public_error_message(&message));
+        }
+
+        // A read-only mirror's database can't be written to
+        if !state.config.main.read_only {
+            if let Err(e) = touch_last_seen(claims.profile.id, &state).await {
+                error!(
+                    "Failed to update last seen time of player {}: {}",
+                    claims.profile.id, e
+                );
+            }
+        }
+
+        Ok(claims)
+    }
+}
+
+/// Like [`Player::touch_last_seen`], but only checks out a connection if it's time to write
+async fn touch_last_seen(player_id: i32, state: &AppState) -> anyhow::Result<()> {
+    if Player::claim_last_seen_update(player_id, &state.redis).await? {
+        let mut conn = state.db.get().await?;
+        Player::set_last_seen(player_id, &mut conn).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::{
+        body::Body,
+        http::{header, Request},
+    };
+    use diesel::prelude::*;
+    use diesel_async::RunQueryDsl;
+    use fred::prelude::*;
+    use jsonwebtoken::{encode, Header};
+    use time::{Duration, OffsetDateTime};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        models::{bans::NewBan, players::AccountType},
+        schema::players,
+        util::testing::{self, KeyStore},
+    };
+
+    /// Sends `GET /api/players/self` with a token the player got before, and a session that's still valid
+    async fn get_self_as(player: Player, state: &AppState) -> StatusCode {
+        let sid = u64::try_from(player.id).unwrap();
+        let _: () = state
+            .redis
+            .hset(
+                format!("session:{sid}"),
+                [("player_id", i64::from(player.id))],
+            )
+            .await
+            .unwrap();
+        let claims = Claims {
+            profile: player,
+            exp: (OffsetDateTime::now_utc() + Duration::days(1)).unix_timestamp(),
+            sid,
+        };
+        let token = encode(&Header::default(), &claims, &state.jwt_keys.encoding).unwrap();
+
+        let request = Request::builder()
+            .uri("/api/players/self")
+            .header(header::AUTHORIZATION, format!("Bearer {token}"))
+            .body(Body::empty())
+            .unwrap();
+        crate::make_router(state.clone())
+            .oneshot(request)
+            .await
+            .unwrap()
+            .status()
+    }
+
+    #[tokio::test]
+    async fn banned_players_are_refused_despite_their_token() {
+        let Some(state) = testing::state(Arc::new(KeyStore::default())).await else {
+            return;
+        };
+        let (banned, banned_by_account_type) = {
+            let mut conn = state.db.get().await.unwrap();
+            let banned_id = testing::insert_player(1, &mut conn).await;
+            let new_ban = NewBan {
+                reason: "Cheating".to_owned(),
+                expires_at: None,
+            };
+            new_ban.insert(banned_id, None, &mut conn).await.unwrap();
+
+            // Their token still says they're a regular user
+            let other_id = testing::insert_player(2, &mut conn).await;
+            let other: Player = players::table
+                .find(other_id)
+                .select(Player::as_select())
+                .first(&mut conn)
+                .await
+                .unwrap();
+            diesel::update(players::table.find(other_id))
+                .set(players::account_type.eq(AccountType::Banned))
+                .execute(&mut conn)
+                .await
+                .unwrap();
+
+            let banned: Player = players::table
+                .find(banned_id)
+                .select(Player::as_select())
+                .first(&mut conn)
+                .await
+                .unwrap();
+            (banned, other)
+        };
+
+        assert_eq!(get_self_as(banned, &state).await, StatusCode::FORBIDDEN);
+        assert_eq!(
+            get_self_as(banned_by_account_type, &state).await,
+            StatusCode::FORBIDDEN
+        );
     }
 }
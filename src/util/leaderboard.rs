@@ -0,0 +1,109 @@
+use fred::{clients::Pool as RedisPool, prelude::*};
+
+use super::game_types::League;
+
+/// Sorted set of all players by their skill points across all leagues
+pub const GLOBAL_LEADERBOARD: &str = "leaderboard";
+
+/// Key of the sorted set ranking players by their skill points in `league`, or across all leagues if `None`.
+pub const fn leaderboard_key(league: Option<League>) -> &'static str {
+    match league {
+        None => GLOBAL_LEADERBOARD,
+        Some(League::Casual) => "leaderboard:casual",
+        Some(League::Pro) => "leaderboard:pro",
+        Some(League::Elite) => "leaderboard:elite",
+    }
+}
+
+/// Adds `delta` to a player's skill points on the global leaderboard and the one of `league`.
+/// Both are changed in one transaction, so they can't drift apart.
+///
+/// # Errors
+/// Fails if something goes wrong with Redis.
+pub async fn add_skill_points(
+    player_id: i32,
+    league: League,
+    delta: i32,
+    redis_conn: &RedisPool,
+) -> anyhow::Result<()> {
+    let trx = redis_conn.next().multi();
+    let _: () = trx
+        .zincrby(GLOBAL_LEADERBOARD, delta.into(), player_id)
+        .await?;
+    let _: () = trx
+        .zincrby(leaderboard_key(Some(league)), delta.into(), player_id)
+        .await?;
+    let _: () = trx.exec(true).await?;
+
+    Ok(())
+}
+
+/// Replaces a player's skill points on the global leaderboard with the sum of `league_points`,
+/// and on every league's leaderboard with its entry. Leagues without an entry are left off,
+/// since players only show up on the leaderboards of leagues they have scores in.
+///
+/// # Errors
+/// Fails if something goes wrong with Redis.
+pub async fn set_skill_points(
+    player_id: i32,
+    league_points: &[(League, i64)],
+    redis_conn: &RedisPool,
+) -> anyhow::Result<()> {
+    let total_points: i64 = league_points.iter().map(|(_, points)| points).sum();
+
+    let trx = redis_conn.next().multi();
+    #[allow(clippy::cast_precision_loss)]
+    let _: () = trx
+        .zadd(
+            GLOBAL_LEADERBOARD,
+            None,
+            None,
+            false,
+            false,
+            (total_points as f64, player_id),
+        )
+        .await?;
+    for league in League::ALL {
+        let points = league_points
+            .iter()
+            .find(|(points_league, _)| *points_league == league)
+            .map(|(_, points)| *points);
+        #[allow(clippy::cast_precision_loss)]
+        let _: () = match points {
+            Some(points) => {
+                trx.zadd(
+                    leaderboard_key(Some(league)),
+                    None,
+                    None,
+                    false,
+                    false,
+                    (points as f64, player_id),
+                )
+                .await?
+            }
+            None => trx.zrem(leaderboard_key(Some(league)), player_id).await?,
+        };
+    }
+    let _: () = trx.exec(true).await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn every_leaderboard_has_its_own_key() {
+        let keys: HashSet<&str> = League::ALL
+            .into_iter()
+            .map(Some)
+            .chain([None])
+            .map(leaderboard_key)
+            .collect();
+        assert_eq!(keys.len(), League::ALL.len() + 1);
+        assert_eq!(leaderboard_key(None), "leaderboard");
+    }
+}
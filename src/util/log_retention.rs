@@ -0,0 +1,200 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use tracing::{info, warn};
+
+/// Log files written by the rolling appender end with this
+pub const LOG_FILE_SUFFIX: &str = "wavebreaker.log";
+
+/// Limits for how many old log files are kept. Without any limit set, nothing is deleted.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RetentionLimits {
+    /// Delete log files last written more than this many days ago
+    pub max_days: Option<u64>,
+    /// Delete the oldest log files until all of them together take up at most this many bytes
+    pub max_total_bytes: Option<u64>,
+}
+
+/// A log file on disk
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogFile {
+    pub path: PathBuf,
+    pub modified: SystemTime,
+    pub size: u64,
+}
+
+/// Decides which log files exceed the retention limits.
+/// The newest file is never picked, since the appender is probably still writing to it.
+pub fn files_to_prune(
+    mut files: Vec<LogFile>,
+    limits: RetentionLimits,
+    now: SystemTime,
+) -> Vec<PathBuf> {
+    // Newest first
+    files.sort_by(|a, b| b.modified.cmp(&a.modified));
+
+    let mut total_size = 0u64;
+    let mut pruned = vec![];
+    for (index, file) in files.into_iter().enumerate() {
+        if index == 0 {
+            total_size += file.size;
+            continue;
+        }
+
+        let too_old = limits.max_days.is_some_and(|max_days| {
+            now.duration_since(file.modified)
+                .is_ok_and(|age| age > Duration::from_secs(max_days * 60 * 60 * 24))
+        });
+        let too_big = limits
+            .max_total_bytes
+            .is_some_and(|max_total_bytes| total_size + file.size > max_total_bytes);
+
+        if too_old || too_big {
+            pruned.push(file.path);
+        } else {
+            total_size += file.size;
+        }
+    }
+
+    pruned
+}
+
+/// Deletes log files in `directory` that exceed the retention limits and logs which ones were removed.
+///
+/// # Errors
+/// Fails if the directory can't be read. Files that can't be deleted are only logged.
+pub fn prune_logs(directory: &Path, limits: RetentionLimits) -> io::Result<Vec<PathBuf>> {
+    if limits.max_days.is_none() && limits.max_total_bytes.is_none() {
+        return Ok(vec![]);
+    }
+
+    let mut files = vec![];
+    for entry in fs::read_dir(directory)? {
+        let entry = entry?;
+        if !entry
+            .file_name()
+            .to_string_lossy()
+            .ends_with(LOG_FILE_SUFFIX)
+        {
+            continue;
+        }
+        let metadata = entry.metadata()?;
+        if metadata.is_file() {
+            files.push(LogFile {
+                path: entry.path(),
+                modified: metadata.modified()?,
+                size: metadata.len(),
+            });
+        }
+    }
+
+    let mut removed = vec![];
+    for path in files_to_prune(files, limits, SystemTime::now()) {
+        match fs::remove_file(&path) {
+            Ok(()) => {
+                info!("Removed old log file {}", path.display());
+                removed.push(path);
+            }
+            Err(e) => warn!("Failed to remove old log file {}: {}", path.display(), e),
+        }
+    }
+
+    Ok(removed)
+}
+
+/// Creates the log directory if it doesn't exist yet.
+/// On Unix, it's only accessible by the owner and their group, since logs contain player info.
+///
+/// # Errors
+/// Fails if the directory can't be created.
+pub fn create_log_dir(directory: &Path) -> io::Result<()> {
+    if directory.is_dir() {
+        return Ok(());
+    }
+
+    fs::create_dir_all(directory)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(directory, fs::Permissions::from_mode(0o750))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: Duration = Duration::from_secs(60 * 60 * 24);
+
+    fn file(name: &str, age_days: u32, size: u64, now: SystemTime) -> LogFile {
+        LogFile {
+            path: PathBuf::from(name),
+            modified: now - DAY * age_days,
+            size,
+        }
+    }
+
+    #[test]
+    fn no_limits_keep_everything() {
+        let now = SystemTime::now();
+        let files = vec![file("a", 0, 100, now), file("b", 400, 100, now)];
+
+        assert!(files_to_prune(files, RetentionLimits::default(), now).is_empty());
+    }
+
+    #[test]
+    fn prune_by_age() {
+        let now = SystemTime::now();
+        let files = vec![
+            file("today", 0, 100, now),
+            file("last_week", 7, 100, now),
+            file("last_month", 30, 100, now),
+        ];
+        let limits = RetentionLimits {
+            max_days: Some(14),
+            max_total_bytes: None,
+        };
+
+        assert_eq!(
+            files_to_prune(files, limits, now),
+            vec![PathBuf::from("last_month")]
+        );
+    }
+
+    #[test]
+    fn prune_oldest_by_total_size() {
+        let now = SystemTime::now();
+        let files = vec![
+            file("oldest", 3, 100, now),
+            file("newest", 0, 100, now),
+            file("older", 2, 100, now),
+            file("old", 1, 100, now),
+        ];
+        let limits = RetentionLimits {
+            max_days: None,
+            max_total_bytes: Some(250),
+        };
+
+        assert_eq!(
+            files_to_prune(files, limits, now),
+            vec![PathBuf::from("older"), PathBuf::from("oldest")]
+        );
+    }
+
+    #[test]
+    fn never_prune_newest_file() {
+        let now = SystemTime::now();
+        let files = vec![file("huge", 100, 1000, now)];
+        let limits = RetentionLimits {
+            max_days: Some(1),
+            max_total_bytes: Some(10),
+        };
+
+        assert!(files_to_prune(files, limits, now).is_empty());
+    }
+}
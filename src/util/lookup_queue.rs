@@ -0,0 +1,382 @@
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use fred::{clients::Pool as RedisPool, prelude::*};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use tracing::{info, warn};
+
+use super::musicbrainz::MetadataError;
+use crate::{
+    models::{extra_song_info::ExtraSongInfo, songs::Song},
+    schema::songs,
+    Db,
+};
+
+/// List of lookups waiting to be done, oldest first
+const QUEUE_KEY: &str = "metadata:queue";
+/// Sorted set of failed lookups, scored by the Unix timestamp they're retried at
+const RETRY_KEY: &str = "metadata:retry";
+/// List of lookups that failed for good, newest first
+const DEAD_KEY: &str = "metadata:dead";
+/// Prefix of the keys marking songs with a lookup in the queue or waiting for a retry, so songs aren't queued twice
+const PENDING_KEY_PREFIX: &str = "metadata:pending:";
+/// Pending markers expire after this long, in case the lookup got lost (e.g. the server stopped while doing it).
+/// Way longer than all retries take, so a lookup is only done twice if the queue is a day behind.
+const PENDING_TTL_SECS: i64 = 24 * 60 * 60;
+
+/// Failed lookups are tried this many times in total before they're given up on
+const MAX_ATTEMPTS: u32 = 5;
+/// Wait before the first retry, doubled for every one after
+const RETRY_BASE_SECS: i64 = 60;
+/// Only this many given up lookups are kept
+const MAX_DEAD: i64 = 1000;
+
+/// What to look up on MusicBrainz for a song
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", tag = "type")]
+pub enum LookupKind {
+    /// Search by title, artist and duration (in milliseconds), see `Song::auto_add_metadata`
+    Search { duration: i32 },
+    /// Fetch by the MBIDs the client sent, see `Song::add_metadata_mbid`
+    Mbid {
+        recording: String,
+        release: Option<String>,
+    },
+}
+
+/// A queued MusicBrainz lookup.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct LookupJob {
+    pub song_id: i32,
+    pub kind: LookupKind,
+    /// Failed attempts so far
+    #[serde(default)]
+    pub attempts: u32,
+}
+
+impl LookupJob {
+    #[must_use]
+    pub const fn new(song_id: i32, kind: LookupKind) -> Self {
+        Self {
+            song_id,
+            kind,
+            attempts: 0,
+        }
+    }
+}
+
+/// A lookup that was given up on, with why.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct DeadLookup {
+    pub job: LookupJob,
+    pub error: String,
+    /// Unix timestamp of the last attempt
+    pub failed_at: i64,
+}
+
+/// What happens to a job after it failed.
+#[derive(Debug, PartialEq, Eq)]
+enum AfterFailure {
+    /// Tried again at this Unix timestamp
+    Retry {
+        job: LookupJob,
+        due: i64,
+    },
+    GiveUp(LookupJob),
+}
+
+fn pending_key(song_id: i32) -> String {
+    format!("{PENDING_KEY_PREFIX}{song_id}")
+}
+
+fn after_failure(mut job: LookupJob, retryable: bool, now: i64) -> AfterFailure {
+    job.attempts += 1;
+    if !retryable || job.attempts >= MAX_ATTEMPTS {
+        return AfterFailure::GiveUp(job);
+    }
+
+    let due = now + RETRY_BASE_SECS * 2_i64.pow(job.attempts - 1);
+    AfterFailure::Retry { job, due }
+}
+
+/// Whether a failed lookup could succeed later.
+/// MusicBrainz saying no is final, anything else (rate limits, the database, ...) is worth another try.
+fn is_retryable(error: &anyhow::Error) -> bool {
+    error
+        .downcast_ref::<MetadataError>()
+        .is_none_or(MetadataError::is_retryable)
+}
+
+/// Queues a lookup, unless the song already has one queued.
+///
+/// # Returns
+/// Whether the lookup was queued.
+///
+/// # Errors
+/// Fails if something goes wrong with Redis.
+pub async fn enqueue(job: &LookupJob, redis_conn: &RedisPool) -> anyhow::Result<bool> {
+    let added: Option<String> = redis_conn
+        .set(
+            pending_key(job.song_id),
+            1,
+            Some(Expiration::EX(PENDING_TTL_SECS)),
+            Some(SetOptions::NX),
+            false,
+        )
+        .await?;
+    if added.is_none() {
+        return Ok(false);
+    }
+
+    let _: () = redis_conn
+        .rpush(QUEUE_KEY, serde_json::to_string(job)?)
+        .await?;
+    Ok(true)
+}
+
+/// Moves failed lookups that are due for a retry back into the queue.
+async fn requeue_due(redis_conn: &RedisPool) -> anyhow::Result<()> {
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+    let due: Vec<String> = redis_conn
+        .zrangebyscore(RETRY_KEY, "-inf", now, false, None)
+        .await?;
+    for job in due {
+        // Only whoever removes it requeues it, in case multiple instances share Redis
+        let removed: i64 = redis_conn.zrem(RETRY_KEY, job.as_str()).await?;
+        if removed > 0 {
+            let _: () = redis_conn.rpush(QUEUE_KEY, job).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Does the next queued lookup, if there is one.
+///
+/// # Returns
+/// Whether there was a lookup to do.
+///
+/// # Errors
+/// Fails if something goes wrong with Redis. Failed lookups are retried or given up on instead.
+pub async fn process_next(db: &Db, redis_conn: &RedisPool) -> anyhow::Result<bool> {
+    requeue_due(redis_conn).await?;
+
+    let next: Option<String> = redis_conn.lpop(QUEUE_KEY, None).await?;
+    let Some(next) = next else {
+        return Ok(false);
+    };
+    let job: LookupJob = match serde_json::from_str(&next) {
+        Ok(job) => job,
+        Err(e) => {
+            warn!("Dropping unreadable metadata lookup {next:?}: {e}");
+            // The song ID might still be readable, so the song can be queued again
+            let song_id = serde_json::from_str::<serde_json::Value>(&next)
+                .ok()
+                .and_then(|job| job.get("songId")?.as_i64());
+            if let Some(song_id) = song_id.and_then(|id| i32::try_from(id).ok()) {
+                let _: () = redis_conn.del(pending_key(song_id)).await?;
+            }
+            return Ok(true);
+        }
+    };
+
+    // Only checked out once there is something to do
+    let mut conn = match db.get().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            // Back to the front, to be done first next time
+            let _: () = redis_conn.lpush(QUEUE_KEY, next).await?;
+            return Err(e.into());
+        }
+    };
+    let error = match run(&job, &mut conn).await {
+        Ok(()) => {
+            let _: () = redis_conn.del(pending_key(job.song_id)).await?;
+            return Ok(true);
+        }
+        Err(e) => e,
+    };
+
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+    match after_failure(job, is_retryable(&error), now) {
+        AfterFailure::Retry { job, due } => {
+            warn!(
+                "Metadata lookup for song {} failed (attempt {}/{}), retrying later: {:#}",
+                job.song_id, job.attempts, MAX_ATTEMPTS, error
+            );
+            #[allow(clippy::cast_precision_loss)]
+            let _: () = redis_conn
+                .zadd(
+                    RETRY_KEY,
+                    None,
+                    None,
+                    false,
+                    false,
+                    (due as f64, serde_json::to_string(&job)?),
+                )
+                .await?;
+        }
+        AfterFailure::GiveUp(job) => {
+            warn!(
+                "Giving up on metadata lookup for song {} after {} attempt(s): {:#}",
+                job.song_id, job.attempts, error
+            );
+            let song_id = job.song_id;
+            let dead = DeadLookup {
+                job,
+                error: format!("{error:#}"),
+                failed_at: now,
+            };
+            let trx = redis_conn.next().multi();
+            let _: () = trx.lpush(DEAD_KEY, serde_json::to_string(&dead)?).await?;
+            let _: () = trx.ltrim(DEAD_KEY, 0, MAX_DEAD - 1).await?;
+            let _: () = trx.del(pending_key(song_id)).await?;
+            let _: () = trx.exec(true).await?;
+        }
+    }
+
+    Ok(true)
+}
+
+async fn run(job: &LookupJob, conn: &mut AsyncPgConnection) -> anyhow::Result<()> {
+    let song: Option<Song> = songs::table
+        .find(job.song_id)
+        .first(conn)
+        .await
+        .optional()?;
+    let Some(song) = song else {
+        info!(
+            "Song {} was deleted before its metadata lookup",
+            job.song_id
+        );
+        return Ok(());
+    };
+
+    match &job.kind {
+        LookupKind::Search { duration } => {
+            // Might have gotten metadata some other way while queued
+            let extra_info = ExtraSongInfo::belonging_to(&song)
+                .select(ExtraSongInfo::as_select())
+                .first::<ExtraSongInfo>(conn)
+                .await
+                .optional()?;
+            song.auto_add_metadata(*duration, extra_info.as_ref(), conn)
+                .await
+                .map(|_| ())
+        }
+        LookupKind::Mbid { recording, release } => {
+            song.add_metadata_mbid(recording, release.as_deref(), conn)
+                .await
+        }
+    }
+}
+
+/// The lookups that were given up on, newest first.
+///
+/// # Errors
+/// Fails if something goes wrong with Redis.
+pub async fn dead_lookups(redis_conn: &RedisPool) -> anyhow::Result<Vec<DeadLookup>> {
+    let entries: Vec<String> = redis_conn.lrange(DEAD_KEY, 0, -1).await?;
+    Ok(entries
+        .iter()
+        .filter_map(|entry| serde_json::from_str(entry).ok())
+        .collect())
+}
+
+/// Queues all given up lookups again, with their attempts reset.
+///
+/// # Returns
+/// How many were queued. Songs that have a lookup queued already are skipped.
+///
+/// # Errors
+/// Fails if something goes wrong with Redis.
+pub async fn requeue_dead(redis_conn: &RedisPool) -> anyhow::Result<usize> {
+    let dead = dead_lookups(redis_conn).await?;
+    let _: () = redis_conn.del(DEAD_KEY).await?;
+
+    let mut queued = 0;
+    for dead_lookup in dead {
+        let job = LookupJob::new(dead_lookup.job.song_id, dead_lookup.job.kind);
+        if enqueue(&job, redis_conn).await? {
+            queued += 1;
+        }
+    }
+
+    Ok(queued)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::util::testing::{self, RecordedRedis};
+
+    fn job() -> LookupJob {
+        LookupJob::new(1, LookupKind::Search { duration: 180_000 })
+    }
+
+    #[test]
+    fn retries_back_off_until_given_up() {
+        let mut job = job();
+        let mut waits = vec![];
+        loop {
+            match after_failure(job, true, 0) {
+                AfterFailure::Retry { job: retried, due } => {
+                    waits.push(due);
+                    job = retried;
+                }
+                AfterFailure::GiveUp(given_up) => {
+                    assert_eq!(given_up.attempts, MAX_ATTEMPTS);
+                    break;
+                }
+            }
+        }
+        assert_eq!(waits, [60, 120, 240, 480]);
+    }
+
+    #[test]
+    fn final_errors_are_not_retried() {
+        assert!(matches!(
+            after_failure(job(), false, 0),
+            AfterFailure::GiveUp(LookupJob { attempts: 1, .. })
+        ));
+
+        assert!(!is_retryable(&MetadataError::NoRelease.into()));
+        assert!(is_retryable(&MetadataError::RateLimited.into()));
+        assert!(is_retryable(&anyhow::anyhow!("connection reset")));
+    }
+
+    #[test]
+    fn jobs_survive_the_queue() {
+        let jobs = [
+            job(),
+            LookupJob::new(
+                2,
+                LookupKind::Mbid {
+                    recording: "4d8b4e9c-0d1a-4b9b-9a8e-3f1f4e1c2b3a".to_owned(),
+                    release: None,
+                },
+            ),
+        ];
+        for job in jobs {
+            let json = serde_json::to_string(&job).unwrap();
+            assert_eq!(serde_json::from_str::<LookupJob>(&json).unwrap(), job);
+        }
+    }
+
+    #[tokio::test]
+    async fn songs_with_a_pending_lookup_are_not_queued_again() {
+        // Answers the SET NX with nil, like Redis does when the key exists
+        let recorded = Arc::new(RecordedRedis::default());
+        let redis_conn = testing::redis(recorded.clone()).await;
+
+        assert!(!enqueue(&job(), &redis_conn).await.unwrap());
+        assert_eq!(
+            recorded.commands(),
+            [("SET".to_owned(), "metadata:pending:1".to_owned())]
+        );
+    }
+}
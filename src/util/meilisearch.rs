@@ -0,0 +1,451 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use fred::{clients::Pool as RedisPool, interfaces::KeysInterface};
+use meilisearch_sdk::{client::Client as MeiliClient, search::Selectors};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use time::OffsetDateTime;
+use tracing::{debug, error};
+use utoipa::ToSchema;
+
+use crate::{
+    models::{extra_song_info::ExtraSongInfo, songs::Song},
+    schema::{extra_song_info, songs},
+    util::modifiers::{modifier_key, ModifierFilter},
+};
+
+/// Name of the Meilisearch index songs are kept in
+pub const SONGS_INDEX: &str = "songs";
+/// Songs sent to Meilisearch at once when indexing
+const INDEX_BATCH_SIZE: i64 = 1000;
+/// How long to wait for Meilisearch to process a task while syncing.
+/// Its default of 5 seconds isn't enough for a full batch on a busy instance.
+const TASK_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+/// Holds the Unix timestamp of when the last successful search sync started
+const LAST_SYNC_KEY: &str = "meilisearch:last_sync";
+/// How far before the last sync changes are picked up again.
+/// A transaction that was still running then can have changed songs with an earlier `updated_at`.
+const SYNC_OVERLAP: time::Duration = time::Duration::minutes(10);
+
+/// What Meilisearch knows about a song, everything else is loaded from the database
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct SongDocument {
+    pub id: i32,
+    pub title: String,
+    pub artist: String,
+    pub musicbrainz_title: Option<String>,
+    pub musicbrainz_artist: Option<String>,
+    pub aliases_title: Vec<String>,
+    pub aliases_artist: Vec<String>,
+    /// See [`modifier_key`]
+    pub modifier_key: String,
+}
+
+impl SongDocument {
+    #[must_use]
+    pub fn new(song: Song, extra_info: Option<ExtraSongInfo>) -> Self {
+        let extra_info = extra_info.unwrap_or_default();
+        Self {
+            modifier_key: modifier_key(song.modifiers.as_deref()),
+            id: song.id,
+            title: song.title,
+            artist: song.artist,
+            musicbrainz_title: extra_info.musicbrainz_title,
+            musicbrainz_artist: extra_info.musicbrainz_artist,
+            aliases_title: extra_info
+                .aliases_title
+                .into_iter()
+                .flatten()
+                .flatten()
+                .collect(),
+            aliases_artist: extra_info
+                .aliases_artist
+                .into_iter()
+                .flatten()
+                .flatten()
+                .collect(),
+        }
+    }
+}
+
+/// The index songs are searched in. Implemented by the Meilisearch client, tests can stub it.
+#[async_trait]
+pub trait SongIndex: Send + Sync {
+    /// Removes the documents of the given songs.
+    async fn remove_songs(&self, song_ids: &[i32]) -> anyhow::Result<()>;
+    /// Adds the documents, replacing ones with the same id.
+    async fn index_songs(&self, documents: &[SongDocument]) -> anyhow::Result<()>;
+}
+
+#[async_trait]
+impl SongIndex for MeiliClient {
+    async fn remove_songs(&self, song_ids: &[i32]) -> anyhow::Result<()> {
+        // Not waiting for Meilisearch to process it, nothing depends on it being done
+        self.index(SONGS_INDEX).delete_documents(song_ids).await?;
+        Ok(())
+    }
+
+    async fn index_songs(&self, documents: &[SongDocument]) -> anyhow::Result<()> {
+        self.index(SONGS_INDEX)
+            .add_or_replace(documents, Some("id"))
+            .await?;
+        Ok(())
+    }
+}
+
+/// Removes deleted songs from the search index, if there is one.
+/// Failures are only logged, the songs are gone from the database either way and search skips ids it can't find.
+pub async fn forget_songs(index: Option<&dyn SongIndex>, song_ids: &[i32]) {
+    let Some(index) = index else {
+        return;
+    };
+    if let Err(e) = index.remove_songs(song_ids).await {
+        error!(
+            "Failed to remove songs {:?} from the search index: {}",
+            song_ids, e
+        );
+    }
+}
+
+/// Sends the current state of a song to the search index, if there is one (e.g. after its aliases changed).
+/// Songs excluded from rankings aren't indexed. Failures are only logged, the next sync catches up.
+/// That includes loading the song, since callers have already committed the change by then.
+pub async fn reindex_song(
+    index: Option<&dyn SongIndex>,
+    song_id: i32,
+    conn: &mut AsyncPgConnection,
+) {
+    let Some(index) = index else {
+        return;
+    };
+    let song = Song::ranked()
+        .left_join(extra_song_info::table)
+        .filter(songs::id.eq(song_id))
+        .select((Song::as_select(), Option::<ExtraSongInfo>::as_select()))
+        .first::<(Song, Option<ExtraSongInfo>)>(conn)
+        .await
+        .optional();
+    let (song, extra_info) = match song {
+        Ok(Some(song)) => song,
+        Ok(None) => return,
+        Err(e) => {
+            error!("Failed to load song {} for reindexing: {}", song_id, e);
+            return;
+        }
+    };
+
+    if let Err(e) = index
+        .index_songs(&[SongDocument::new(song, extra_info)])
+        .await
+    {
+        error!("Failed to reindex song {} for search: {}", song_id, e);
+    }
+}
+
+/// Takes a song out of the search index when it's excluded from rankings, and sends it again when it's included again.
+/// Failures are only logged, like in [`forget_songs`] and [`reindex_song`].
+pub async fn apply_exclusion(
+    index: Option<&dyn SongIndex>,
+    song_id: i32,
+    excluded: bool,
+    conn: &mut AsyncPgConnection,
+) {
+    if excluded {
+        forget_songs(index, &[song_id]).await;
+    } else {
+        reindex_song(index, song_id, conn).await;
+    }
+}
+
+/// Title and artist of a search hit, with the matched parts wrapped in `<em>` tags
+#[derive(Serialize, ToSchema, Debug, PartialEq, Eq)]
+pub struct SongHighlight {
+    pub title: String,
+    pub artist: String,
+}
+
+impl SongHighlight {
+    /// Reads the highlighted fields Meilisearch sent for a hit, if it sent both.
+    fn from_formatted(formatted: &Map<String, Value>) -> Option<Self> {
+        let field = |name: &str| formatted.get(name)?.as_str().map(str::to_owned);
+        Some(Self {
+            title: field("title")?,
+            artist: field("artist")?,
+        })
+    }
+}
+
+/// One page of search hits, best match first
+pub struct SongSearchHits {
+    pub hits: Vec<(i32, Option<SongHighlight>)>,
+    /// Meilisearch only estimates this
+    pub total: i64,
+}
+
+/// Searches the songs index, optionally only for songs with exactly the modifiers of `modifiers`.
+///
+/// # Errors
+/// Fails if Meilisearch can't be reached or answers with an error.
+pub async fn search_songs(
+    client: &MeiliClient,
+    query: &str,
+    limit: usize,
+    offset: usize,
+    highlight: bool,
+    modifiers: Option<&ModifierFilter>,
+) -> anyhow::Result<SongSearchHits> {
+    let index = client.index(SONGS_INDEX);
+    // Modifier names are alphanumeric, so they can't break out of the quotes
+    let filter = modifiers.map(|modifiers| format!("modifierKey = \"{}\"", modifiers.key()));
+    let mut search = index.search();
+    search
+        .with_query(query)
+        .with_limit(limit)
+        .with_offset(offset);
+    if let Some(filter) = &filter {
+        search.with_filter(filter);
+    }
+    if highlight {
+        search.with_attributes_to_highlight(Selectors::Some(&["title", "artist"]));
+    }
+    let results = search.execute::<SongDocument>().await?;
+
+    Ok(SongSearchHits {
+        total: results
+            .estimated_total_hits
+            .or(results.total_hits)
+            .map_or(0, |total| i64::try_from(total).unwrap_or(i64::MAX)),
+        hits: results
+            .hits
+            .into_iter()
+            .map(|hit| {
+                let highlight = hit
+                    .formatted_result
+                    .as_ref()
+                    .and_then(SongHighlight::from_formatted);
+                (hit.result.id, highlight)
+            })
+            .collect(),
+    })
+}
+
+/// When the last search sync started, so the next scheduled one only has to send what changed since.
+///
+/// # Errors
+/// Fails if something goes wrong with Redis.
+pub async fn last_sync(redis_conn: &RedisPool) -> anyhow::Result<Option<OffsetDateTime>> {
+    let timestamp: Option<i64> = redis_conn.get(LAST_SYNC_KEY).await?;
+    Ok(timestamp
+        .map(OffsetDateTime::from_unix_timestamp)
+        .transpose()?)
+}
+
+/// Remembers when a successful search sync started, see [`last_sync`].
+///
+/// # Errors
+/// Fails if something goes wrong with Redis.
+pub async fn record_sync(started_at: OffsetDateTime, redis_conn: &RedisPool) -> anyhow::Result<()> {
+    let _: () = redis_conn
+        .set(
+            LAST_SYNC_KEY,
+            started_at.unix_timestamp(),
+            None,
+            None,
+            false,
+        )
+        .await?;
+    Ok(())
+}
+
+/// Brings Meilisearch up to date with the songs changed after `changed_since` (see [`Song::updated_at`]),
+/// or with all songs if it's `None`. Songs that count for rankings are sent, replacing what it had for them,
+/// and songs excluded from rankings are taken out.
+/// Changes are picked up from a bit before `changed_since`, so ones committed while the last sync ran aren't missed.
+/// Deleted songs are taken out of the index when they're deleted, see [`forget_songs`].
+///
+/// # Returns
+/// The number of songs sent.
+///
+/// # Errors
+/// Fails if something goes wrong with the database or Meilisearch.
+pub async fn sync_songs(
+    client: &MeiliClient,
+    conn: &mut AsyncPgConnection,
+    changed_since: Option<OffsetDateTime>,
+) -> anyhow::Result<usize> {
+    let index = client.index(SONGS_INDEX);
+    let changed_since = changed_since.map(|since| since - SYNC_OVERLAP);
+    let mut indexed = 0;
+    let mut last_id = 0;
+
+    index
+        .set_filterable_attributes(["modifierKey"])
+        .await?
+        .wait_for_completion(client, None, Some(TASK_TIMEOUT))
+        .await?;
+
+    let mut excluded_query = songs::table
+        .filter(songs::excluded_from_rankings.eq(true))
+        .select(songs::id)
+        .into_boxed();
+    if let Some(since) = changed_since {
+        excluded_query = excluded_query.filter(songs::updated_at.gt(since));
+    }
+    let excluded: Vec<i32> = excluded_query.load(conn).await?;
+    if !excluded.is_empty() {
+        index
+            .delete_documents(&excluded)
+            .await?
+            .wait_for_completion(client, None, Some(TASK_TIMEOUT))
+            .await?;
+        debug!("Removed {} excluded songs from Meilisearch", excluded.len());
+    }
+
+    loop {
+        let mut batch_query = Song::ranked()
+            .left_join(extra_song_info::table)
+            .select((Song::as_select(), Option::<ExtraSongInfo>::as_select()))
+            .filter(songs::id.gt(last_id))
+            .into_boxed();
+        if let Some(since) = changed_since {
+            batch_query = batch_query.filter(songs::updated_at.gt(since));
+        }
+        let batch: Vec<(Song, Option<ExtraSongInfo>)> = batch_query
+            .order(songs::id.asc())
+            .limit(INDEX_BATCH_SIZE)
+            .load(conn)
+            .await?;
+        let Some((last, _)) = batch.last() else {
+            break;
+        };
+        last_id = last.id;
+
+        let documents: Vec<SongDocument> = batch
+            .into_iter()
+            .map(|(song, extra_info)| SongDocument::new(song, extra_info))
+            .collect();
+        index
+            .add_or_replace(&documents, Some("id"))
+            .await?
+            .wait_for_completion(client, None, Some(TASK_TIMEOUT))
+            .await?;
+        indexed += documents.len();
+        debug!("Sent {indexed} songs to Meilisearch so far");
+    }
+
+    Ok(indexed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::testing::{self, RecordedIndex};
+
+    async fn updated_at(song_id: i32, conn: &mut AsyncPgConnection) -> OffsetDateTime {
+        songs::table
+            .find(song_id)
+            .select(songs::updated_at)
+            .first(conn)
+            .await
+            .unwrap()
+    }
+
+    /// Sets the song's `updated_at` back, since everything in a test transaction happens at the same time
+    async fn backdate(song_id: i32, conn: &mut AsyncPgConnection) {
+        diesel::update(songs::table.find(song_id))
+            .set(songs::updated_at.eq(OffsetDateTime::UNIX_EPOCH))
+            .execute(conn)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn forgetting_songs_removes_them_from_the_index() {
+        let index = RecordedIndex::default();
+        forget_songs(Some(&index), &[3, 7]).await;
+        assert_eq!(index.removed(), vec![3, 7]);
+
+        // Without an index, there's nothing to do
+        forget_songs(None, &[3]).await;
+    }
+
+    #[tokio::test]
+    async fn excluded_songs_leave_the_index_until_included_again() {
+        let Some(mut conn) = testing::db().await else {
+            return;
+        };
+        let index = RecordedIndex::default();
+        let song_id = testing::insert_song("Dear Music", "Sumijun", &mut conn).await;
+
+        Song::set_excluded_from_rankings(song_id, true, &mut conn)
+            .await
+            .unwrap();
+        apply_exclusion(Some(&index), song_id, true, &mut conn).await;
+        assert_eq!(index.removed(), vec![song_id]);
+        assert!(index.indexed().is_empty());
+
+        Song::set_excluded_from_rankings(song_id, false, &mut conn)
+            .await
+            .unwrap();
+        apply_exclusion(Some(&index), song_id, false, &mut conn).await;
+        assert_eq!(index.indexed(), vec![song_id]);
+    }
+
+    #[tokio::test]
+    async fn only_searched_changes_mark_songs_as_updated() {
+        let Some(mut conn) = testing::db().await else {
+            return;
+        };
+        let song_id = testing::insert_song("Dear Music", "Sumijun", &mut conn).await;
+
+        backdate(song_id, &mut conn).await;
+        Song::add_plays(song_id, 1, &mut conn).await.unwrap();
+        assert_eq!(
+            updated_at(song_id, &mut conn).await,
+            OffsetDateTime::UNIX_EPOCH
+        );
+
+        ExtraSongInfo::add_aliases(song_id, Some("Dear Musik"), None, &mut conn)
+            .await
+            .unwrap();
+        assert_ne!(
+            updated_at(song_id, &mut conn).await,
+            OffsetDateTime::UNIX_EPOCH
+        );
+
+        backdate(song_id, &mut conn).await;
+        Song::set_excluded_from_rankings(song_id, true, &mut conn)
+            .await
+            .unwrap();
+        assert_ne!(
+            updated_at(song_id, &mut conn).await,
+            OffsetDateTime::UNIX_EPOCH
+        );
+    }
+
+    #[test]
+    fn highlight_needs_both_fields() {
+        let formatted = serde_json::json!({
+            "id": "1",
+            "title": "<em>Dear</em> Music",
+            "artist": "A4.",
+        });
+        assert_eq!(
+            SongHighlight::from_formatted(formatted.as_object().unwrap()),
+            Some(SongHighlight {
+                title: "<em>Dear</em> Music".to_owned(),
+                artist: "A4.".to_owned()
+            })
+        );
+
+        let formatted = serde_json::json!({ "title": "<em>Dear</em> Music" });
+        assert_eq!(
+            SongHighlight::from_formatted(formatted.as_object().unwrap()),
+            None
+        );
+    }
+}
@@ -1,8 +1,33 @@
+pub mod access;
+pub mod bots;
+pub mod cgr;
+pub mod covers;
+pub mod discord;
 pub mod errors;
 pub mod game_types;
+pub mod i18n;
+pub mod instrumentation;
 pub mod jwt;
+pub mod leaderboard;
+pub mod log_retention;
+pub mod lookup_queue;
+pub mod meilisearch;
 pub mod modifiers;
 pub mod musicbrainz;
+pub mod normalize;
+pub mod openid;
+pub mod plausibility;
+pub mod presence;
 pub mod query;
 pub mod radio;
+pub mod rate_limit;
+pub mod read_only;
+pub mod registrations;
+pub mod session;
+pub mod skill_points;
+#[cfg(test)]
+pub mod testing;
+pub mod usage;
 pub mod validator;
+pub mod version;
+pub mod xstats;
@@ -1,4 +1,14 @@
+use diesel::{pg::Pg, prelude::*, sql_types::Integer};
 use regex::Regex;
+use validator::ValidationError;
+
+use crate::schema::songs;
+
+/// Most modifiers a filter can ask for at once
+const MAX_FILTER_MODIFIERS: usize = 5;
+/// Modifiers filters accept, the `[as-...]` tags the game changes the ride for.
+/// Songs can have any tag, but filtering by a made-up one can't mean anything.
+pub const KNOWN_MODIFIERS: [&str; 2] = ["steep", "flat"];
 
 /// For song titles with modifiers, this function returns a vector of the modifiers, or `None` if no modifiers are found.
 ///
@@ -35,6 +45,92 @@ pub fn remove_from_title(title: &str) -> String {
     )
 }
 
+/// A filter on the modifiers of songs, as given in API queries:
+/// `none` for songs without modifiers, or a comma-separated list like `steep,flat` for songs that have all of them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ModifierFilter {
+    Unmodified,
+    Contains(Vec<String>),
+}
+
+impl ModifierFilter {
+    /// Parses a filter, ignoring case. Only [`KNOWN_MODIFIERS`] are accepted.
+    #[must_use]
+    pub fn parse(input: &str) -> Option<Self> {
+        if input.eq_ignore_ascii_case("none") {
+            return Some(Self::Unmodified);
+        }
+
+        let modifiers: Vec<String> = input
+            .split(',')
+            .map(|m| m.trim().to_ascii_lowercase())
+            .collect();
+        let known = modifiers
+            .iter()
+            .all(|m| KNOWN_MODIFIERS.contains(&m.as_str()));
+        (known && modifiers.len() <= MAX_FILTER_MODIFIERS).then_some(Self::Contains(modifiers))
+    }
+
+    /// IDs of the songs matching the filter, to be used with `eq_any` on a song ID column.
+    #[must_use]
+    pub fn matching_song_ids(&self) -> songs::BoxedQuery<'static, Pg, Integer> {
+        let query = songs::table.select(songs::id).into_boxed();
+        match self {
+            Self::Unmodified => query.filter(songs::modifiers.is_null()),
+            Self::Contains(modifiers) => query.filter(songs::modifiers.contains(modifiers.clone())),
+        }
+    }
+
+    /// IDs of the songs with exactly the modifiers of the filter, in the same order, and no others.
+    /// Matches songs the same way the game's song lookup does.
+    #[must_use]
+    pub fn exactly_matching_song_ids(&self) -> songs::BoxedQuery<'static, Pg, Integer> {
+        let query = songs::table.select(songs::id).into_boxed();
+        match self {
+            Self::Unmodified => query.filter(songs::modifiers.is_null()),
+            Self::Contains(modifiers) => {
+                query.filter(songs::modifiers.is_not_distinct_from(modifiers.clone()))
+            }
+        }
+    }
+
+    /// The modifiers the filter asks for, joined like [`modifier_key`] does.
+    #[must_use]
+    pub fn key(&self) -> String {
+        match self {
+            Self::Unmodified => String::new(),
+            Self::Contains(modifiers) => modifiers.join(","),
+        }
+    }
+}
+
+/// A song's modifiers as one comma-separated string, empty for none. Lets the search index match them exactly.
+#[must_use]
+pub fn modifier_key(modifiers: Option<&[Option<String>]>) -> String {
+    modifiers
+        .unwrap_or_default()
+        .iter()
+        .flatten()
+        .map(String::as_str)
+        .collect::<Vec<&str>>()
+        .join(",")
+}
+
+/// For `#[validate(custom)]` on query parameters that are parsed with `ModifierFilter::parse`.
+pub fn validate_modifier_filter(input: &str) -> Result<(), ValidationError> {
+    if ModifierFilter::parse(input).is_none() {
+        return Err(ValidationError::new("modifier").with_message(
+            format!(
+                "Modifier filter must be \"none\" or up to {MAX_FILTER_MODIFIERS} comma-separated modifiers out of {}",
+                KNOWN_MODIFIERS.join(", ")
+            )
+            .into(),
+        ));
+    }
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -65,4 +161,40 @@ mod tests {
         let expected_result = "マボロシ";
         assert_eq!(remove_from_title(title), expected_result);
     }
+
+    #[test]
+    fn modifier_filters() {
+        assert_eq!(
+            ModifierFilter::parse("None"),
+            Some(ModifierFilter::Unmodified)
+        );
+        assert_eq!(
+            ModifierFilter::parse("Steep, FLAT"),
+            Some(ModifierFilter::Contains(vec![
+                "steep".to_owned(),
+                "flat".to_owned()
+            ]))
+        );
+        assert_eq!(ModifierFilter::parse(""), None);
+        assert_eq!(ModifierFilter::parse("steep,"), None);
+        assert_eq!(ModifierFilter::parse("as-steep"), None);
+        assert_eq!(ModifierFilter::parse("tag1"), None);
+        assert_eq!(
+            ModifierFilter::parse("steep,steep,steep,steep,steep,steep"),
+            None
+        );
+    }
+
+    #[test]
+    fn filter_keys_match_song_keys() {
+        let steep_flat = Some(vec![Some("steep".to_owned()), Some("flat".to_owned())]);
+        assert_eq!(
+            ModifierFilter::parse("steep, flat").unwrap().key(),
+            modifier_key(steep_flat.as_deref())
+        );
+        assert_eq!(
+            ModifierFilter::parse("none").unwrap().key(),
+            modifier_key(None)
+        );
+    }
 }
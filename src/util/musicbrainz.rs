@@ -1,11 +1,162 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
 use diesel::{prelude::Insertable, query_builder::AsChangeset};
 use musicbrainz_rs::{
     entity::{recording::Recording, release::Release, CoverartResponse},
-    Fetch, FetchCoverart, Search,
+    FetchCoverart, Search,
 };
+use reqwest::{header, StatusCode};
+use serde::de::DeserializeOwned;
 use tracing::{error, info};
 
-use crate::models::songs::Song;
+use super::normalize::normalize;
+use crate::{models::songs::Song, WAVEBREAKER_USER_AGENT};
+
+/// MusicBrainz' JSON web service
+const MUSICBRAINZ_API: &str = "https://musicbrainz.org/ws/2";
+/// How long MusicBrainz gets to answer a lookup
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(15);
+/// MusicBrainz allows one request per second per IP
+const MIN_REQUEST_INTERVAL: Duration = Duration::from_secs(1);
+
+/// When the next request to MusicBrainz may be sent
+static NEXT_REQUEST: Mutex<Option<Instant>> = Mutex::new(None);
+
+/// Reserves the next free request slot.
+///
+/// # Returns
+/// How long to wait until the slot.
+fn reserve_slot(next_request: &mut Option<Instant>, now: Instant) -> Duration {
+    let slot = next_request.map_or(now, |next| next.max(now));
+    *next_request = Some(slot + MIN_REQUEST_INTERVAL);
+    slot - now
+}
+
+/// Waits until MusicBrainz can be asked again without getting rate limited.
+/// Every request to MusicBrainz goes through here, whether it's from the lookup queue or a moderator.
+async fn wait_for_turn() {
+    let wait = {
+        let mut next_request = NEXT_REQUEST
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        reserve_slot(&mut next_request, Instant::now())
+    };
+    tokio::time::sleep(wait).await;
+}
+
+/// Why a MusicBrainz lookup failed
+#[derive(Debug, thiserror::Error)]
+pub enum MetadataError {
+    /// MusicBrainz doesn't know the MBID, or sent nothing usable
+    #[error("MusicBrainz has no usable data for this MBID")]
+    NotFound,
+    #[error("MusicBrainz has no release for this recording")]
+    NoRelease,
+    #[error("MusicBrainz has no artist credit for this recording")]
+    NoArtistCredit,
+    /// MusicBrainz answers with 503 when requests come in too fast
+    #[error("MusicBrainz is rate limiting requests")]
+    RateLimited,
+    /// Any other unexpected status
+    #[error("MusicBrainz answered with {0}")]
+    Upstream(StatusCode),
+    /// MusicBrainz answered successfully, but with something that isn't the entity
+    #[error("MusicBrainz sent an unreadable answer: {0}")]
+    Unreadable(serde_json::Error),
+    /// MusicBrainz couldn't be reached
+    #[error("MusicBrainz couldn't be reached: {0}")]
+    Network(anyhow::Error),
+}
+
+impl MetadataError {
+    /// Sorts an unsuccessful answer from MusicBrainz.
+    #[must_use]
+    pub fn from_status(status: StatusCode) -> Self {
+        match status {
+            StatusCode::NOT_FOUND => Self::NotFound,
+            StatusCode::SERVICE_UNAVAILABLE | StatusCode::TOO_MANY_REQUESTS => Self::RateLimited,
+            status => Self::Upstream(status),
+        }
+    }
+
+    /// Sorts a failed request made with `musicbrainz_rs`, which doesn't tell the status.
+    ///
+    /// MusicBrainz answers unknown MBIDs with an error body instead of the entity,
+    /// so a failure to deserialize the response means there was nothing to find.
+    fn classify<E>(error: E) -> Self
+    where
+        E: std::error::Error + Send + Sync + 'static,
+    {
+        let mut source: Option<&(dyn std::error::Error + 'static)> = Some(&error);
+        while let Some(inner) = source {
+            if inner.is::<serde_json::Error>() {
+                return Self::NotFound;
+            }
+            source = inner.source();
+        }
+
+        Self::Network(error.into())
+    }
+
+    /// The status an API route answers with when the lookup fails like this.
+    #[must_use]
+    pub const fn status_code(&self) -> StatusCode {
+        match self {
+            Self::NotFound | Self::NoRelease | Self::NoArtistCredit => StatusCode::NOT_FOUND,
+            Self::RateLimited => StatusCode::SERVICE_UNAVAILABLE,
+            Self::Upstream(_) | Self::Unreadable(_) | Self::Network(_) => StatusCode::BAD_GATEWAY,
+        }
+    }
+
+    /// What an API route tells the client when the lookup fails like this.
+    /// Unlike the `Display` output, this never includes MusicBrainz' URL or what went wrong underneath.
+    #[must_use]
+    pub const fn public_message(&self) -> &'static str {
+        match self {
+            Self::NotFound => "MusicBrainz has no usable data for this MBID",
+            Self::NoRelease => "MusicBrainz has no release for this recording",
+            Self::NoArtistCredit => "MusicBrainz has no artist credit for this recording",
+            Self::RateLimited => "MusicBrainz is rate limiting requests, try again later",
+            Self::Upstream(_) | Self::Unreadable(_) => "MusicBrainz answered unexpectedly",
+            Self::Network(_) => "MusicBrainz couldn't be reached",
+        }
+    }
+
+    /// Whether trying the same lookup again later could succeed.
+    #[must_use]
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::NotFound | Self::NoRelease | Self::NoArtistCredit => false,
+            Self::RateLimited | Self::Unreadable(_) | Self::Network(_) => true,
+            Self::Upstream(status) => status.is_server_error(),
+        }
+    }
+}
+
+/// Fetches an entity from MusicBrainz, keeping the status so failures can be told apart.
+async fn fetch_entity<T: DeserializeOwned>(path: &str, includes: &str) -> Result<T, MetadataError> {
+    wait_for_turn().await;
+    let response = reqwest::Client::new()
+        .get(format!("{MUSICBRAINZ_API}/{path}"))
+        .query(&[("fmt", "json"), ("inc", includes)])
+        .header(header::USER_AGENT, WAVEBREAKER_USER_AGENT)
+        .timeout(LOOKUP_TIMEOUT)
+        .send()
+        .await
+        .map_err(|e| MetadataError::Network(e.into()))?;
+    if !response.status().is_success() {
+        return Err(MetadataError::from_status(response.status()));
+    }
+
+    let body = response
+        .bytes()
+        .await
+        .map_err(|e| MetadataError::Network(e.into()))?;
+    serde_json::from_slice(&body).map_err(MetadataError::Unreadable)
+}
 
 #[derive(Debug, AsChangeset, Insertable)]
 #[diesel(table_name = crate::schema::extra_song_info)]
@@ -16,17 +167,20 @@ pub struct MusicBrainzInfo {
     pub musicbrainz_title: String,
     pub musicbrainz_artist: String,
     pub musicbrainz_length: i32,
+    pub musicbrainz_title_normalized: String,
+    pub musicbrainz_artist_normalized: String,
 }
 
 // TODO: Make this code less bad
 /// Tries automatically finding song on MB with title, artist and duration
 ///
 /// # Errors
-/// Fails if no song is found or lookup errors
+/// [`MetadataError::NoRelease`] or [`MetadataError::NoArtistCredit`] if the recording found lacks them,
+/// [`MetadataError::Network`] if MusicBrainz can't be reached
 pub async fn lookup_metadata(
     song: &Song,
     duration: i32,
-) -> anyhow::Result<Option<MusicBrainzInfo>> {
+) -> Result<Option<MusicBrainzInfo>, MetadataError> {
     let query = format!(
         "query=(recording:\"{}\" OR alias:\"{0}\") AND artist:\"{}\" AND dur:\"[{} TO {}]\"",
         song.title,
@@ -37,7 +191,12 @@ pub async fn lookup_metadata(
 
     info!("Searching for recording with query: {:?}", query);
 
-    let query_result = Recording::search(query).execute().await?.entities;
+    wait_for_turn().await;
+    let query_result = Recording::search(query)
+        .execute()
+        .await
+        .map_err(MetadataError::classify)?
+        .entities;
 
     if query_result.is_empty() {
         info!(
@@ -48,9 +207,9 @@ pub async fn lookup_metadata(
     }
 
     let recording = query_result[0].clone();
-    let release = match recording.releases.clone() {
-        Some(releases) => releases[0].clone(),
-        None => return Err(anyhow::anyhow!("No release found for recording")),
+    let release = match recording.releases.as_deref() {
+        Some([release, ..]) => release.clone(),
+        _ => return Err(MetadataError::NoRelease),
     };
 
     let cover_url = match release.get_coverart().front().res_500().execute().await {
@@ -89,7 +248,7 @@ pub async fn lookup_metadata(
             }
             artist_string
         }
-        None => return Err(anyhow::anyhow!("No artist found for recording")),
+        None => return Err(MetadataError::NoArtistCredit),
     };
 
     //let's be real, we're not gonna see a song be so long it eclipses i32::MAX
@@ -100,6 +259,8 @@ pub async fn lookup_metadata(
         cover_url,
         cover_url_small,
         mbid,
+        musicbrainz_title_normalized: normalize(&musicbrainz_title),
+        musicbrainz_artist_normalized: normalize(&musicbrainz_artist),
         musicbrainz_title,
         musicbrainz_artist,
         musicbrainz_length: musicbrainz_length.unwrap_or_default(),
@@ -109,32 +270,25 @@ pub async fn lookup_metadata(
 /// Fetches song metadata using recording and release MBIDs
 ///
 /// # Errors
-/// Fails if no song is found or lookup fails
+/// [`MetadataError::NotFound`] if MusicBrainz doesn't know the MBIDs,
+/// [`MetadataError::NoRelease`] or [`MetadataError::NoArtistCredit`] if the recording lacks them,
+/// and the other variants if MusicBrainz can't be asked
 pub async fn lookup_mbid(
     mbid: &str,
     release_mbid: Option<&str>,
-) -> anyhow::Result<MusicBrainzInfo> {
-    let recording = Recording::fetch()
-        .id(mbid)
-        .with_releases()
-        .with_artists()
-        .execute()
-        .await?;
+) -> Result<MusicBrainzInfo, MetadataError> {
+    let recording: Recording =
+        fetch_entity(&format!("recording/{mbid}"), "releases+artists").await?;
 
     // get cover from user-supplied release, if present
     let release = match release_mbid {
         Some(release_mbid) => {
             info!("Fetching release from MBID: {:?}", release_mbid);
-            match Release::fetch().id(release_mbid).execute().await {
-                Ok(release_result) => release_result,
-                Err(_) => {
-                    return Err(anyhow::anyhow!("Failed to fetch release from MBID"));
-                }
-            }
+            fetch_entity::<Release>(&format!("release/{release_mbid}"), "").await?
         }
-        None => match recording.releases.clone() {
-            Some(releases) => releases[0].clone(),
-            None => return Err(anyhow::anyhow!("No release found for recording")),
+        None => match recording.releases.as_deref() {
+            Some([release, ..]) => release.clone(),
+            _ => return Err(MetadataError::NoRelease),
         },
     };
 
@@ -174,7 +328,7 @@ pub async fn lookup_mbid(
             }
             artist_string
         }
-        None => return Err(anyhow::anyhow!("No artist found for recording")),
+        None => return Err(MetadataError::NoArtistCredit),
     };
 
     //let's be real, we're not gonna see a song be so long it eclipses i32::MAX
@@ -185,8 +339,99 @@ pub async fn lookup_mbid(
         cover_url,
         cover_url_small,
         mbid,
+        musicbrainz_title_normalized: normalize(&musicbrainz_title),
+        musicbrainz_artist_normalized: normalize(&musicbrainz_artist),
         musicbrainz_title,
         musicbrainz_artist,
         musicbrainz_length: musicbrainz_length.unwrap_or_default(),
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requests_are_spaced_out() {
+        let start = Instant::now();
+        let mut next_request = None;
+
+        assert_eq!(reserve_slot(&mut next_request, start), Duration::ZERO);
+        assert_eq!(reserve_slot(&mut next_request, start), MIN_REQUEST_INTERVAL);
+        assert_eq!(
+            reserve_slot(&mut next_request, start),
+            MIN_REQUEST_INTERVAL * 2
+        );
+        // Slots that passed unused aren't saved up
+        let later = start + Duration::from_secs(10);
+        assert_eq!(reserve_slot(&mut next_request, later), Duration::ZERO);
+    }
+
+    #[test]
+    fn statuses_are_sorted() {
+        assert!(matches!(
+            MetadataError::from_status(StatusCode::NOT_FOUND),
+            MetadataError::NotFound
+        ));
+        assert!(matches!(
+            MetadataError::from_status(StatusCode::SERVICE_UNAVAILABLE),
+            MetadataError::RateLimited
+        ));
+        assert!(matches!(
+            MetadataError::from_status(StatusCode::TOO_MANY_REQUESTS),
+            MetadataError::RateLimited
+        ));
+        assert!(matches!(
+            MetadataError::from_status(StatusCode::INTERNAL_SERVER_ERROR),
+            MetadataError::Upstream(StatusCode::INTERNAL_SERVER_ERROR)
+        ));
+    }
+
+    #[test]
+    fn every_variant_maps_to_a_route_status() {
+        let cases = [
+            (MetadataError::NotFound, StatusCode::NOT_FOUND, false),
+            (MetadataError::NoRelease, StatusCode::NOT_FOUND, false),
+            (MetadataError::NoArtistCredit, StatusCode::NOT_FOUND, false),
+            (
+                MetadataError::RateLimited,
+                StatusCode::SERVICE_UNAVAILABLE,
+                true,
+            ),
+            (
+                MetadataError::Upstream(StatusCode::BAD_GATEWAY),
+                StatusCode::BAD_GATEWAY,
+                true,
+            ),
+            (
+                MetadataError::Upstream(StatusCode::BAD_REQUEST),
+                StatusCode::BAD_GATEWAY,
+                false,
+            ),
+            (
+                MetadataError::Unreadable(serde_json::from_str::<Recording>("{}").unwrap_err()),
+                StatusCode::BAD_GATEWAY,
+                true,
+            ),
+            (
+                MetadataError::Network(anyhow::anyhow!("connection refused")),
+                StatusCode::BAD_GATEWAY,
+                true,
+            ),
+        ];
+        for (error, status, retryable) in cases {
+            assert_eq!(error.status_code(), status, "{error}");
+            assert_eq!(error.is_retryable(), retryable, "{error}");
+            assert!(!error.public_message().contains("musicbrainz.org"));
+        }
+    }
+
+    #[test]
+    fn undecodable_answers_mean_not_found() {
+        let decode_error = serde_json::from_str::<Recording>("{}").unwrap_err();
+        assert!(matches!(
+            MetadataError::classify(decode_error),
+            MetadataError::NotFound
+        ));
+    }
+}
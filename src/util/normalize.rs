@@ -0,0 +1,62 @@
+use unicode_normalization::UnicodeNormalization;
+
+/// Brings a title or artist into the form songs are matched by.
+///
+/// The game and MusicBrainz disagree on a lot of little things, so both sides are normalized the same way:
+/// Unicode NFC, lowercase, "&" spelled out as "and" (like the game sends it),
+/// typographic quotes and dashes straightened and whitespace collapsed.
+///
+/// The first migration adding normalized columns mirrors this in SQL, keep them in line!
+#[must_use]
+pub fn normalize(tag: &str) -> String {
+    let straightened: String =
+        tag.nfc()
+            .flat_map(char::to_lowercase)
+            .map(|c| match c {
+                '\u{2018}' | '\u{2019}' | '\u{201A}' | '\u{201B}' | '\u{2032}' | '\u{00B4}'
+                | '`' => '\'',
+                '\u{201C}' | '\u{201D}' | '\u{201E}' | '\u{201F}' | '\u{2033}' => '"',
+                '\u{2010}' | '\u{2011}' | '\u{2012}' | '\u{2013}' | '\u{2014}' | '\u{2015}'
+                | '\u{2212}' => '-',
+                c => c,
+            })
+            .collect();
+
+    straightened
+        .replace('&', " and ")
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn game_and_musicbrainz_tags_meet() {
+        assert_eq!(normalize("Simon & Garfunkel"), "simon and garfunkel");
+        assert_eq!(normalize("simon and garfunkel"), "simon and garfunkel");
+        assert_eq!(normalize("Rock&Roll"), "rock and roll");
+        assert_eq!(normalize("Don\u{2019}t Stop"), "don't stop");
+        assert_eq!(
+            normalize("\u{201C}Heroes\u{201D} \u{2013} Remastered"),
+            "\"heroes\" - remastered"
+        );
+        assert_eq!(normalize("  Dear   Music.\t"), "dear music.");
+    }
+
+    #[test]
+    fn composed_and_decomposed_are_the_same() {
+        // "é" as one code point and as "e" with a combining accent
+        assert_eq!(normalize("Caf\u{00E9}"), normalize("Cafe\u{0301}"));
+        assert_eq!(normalize("\u{00C9}T\u{00C9}"), "\u{00E9}t\u{00E9}");
+    }
+
+    #[test]
+    fn normalizing_twice_changes_nothing() {
+        for tag in ["A4. & Friends", "\u{201C}Quoted\u{201D}", "Cafe\u{0301}"] {
+            assert_eq!(normalize(&normalize(tag)), normalize(tag));
+        }
+    }
+}
@@ -0,0 +1,536 @@
+use std::collections::HashMap;
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use fred::{clients::Pool as RedisPool, prelude::*};
+use steam_openid::SteamOpenId;
+use time::{Date, Month, OffsetDateTime, PrimitiveDateTime, Time};
+use url::Url;
+
+/// The only OP endpoint Steam logins may come from
+const STEAM_OP_ENDPOINT: &str = "https://steamcommunity.com/openid/login";
+/// Claimed IDs are this, followed by the SteamID64
+const STEAM_CLAIMED_ID_PREFIX: &str = "https://steamcommunity.com/openid/id/";
+/// Fields that have to be covered by Steam's signature, otherwise they could be swapped out
+const REQUIRED_SIGNED_FIELDS: [&str; 5] = [
+    "op_endpoint",
+    "claimed_id",
+    "identity",
+    "return_to",
+    "response_nonce",
+];
+/// How old a response nonce may be. Steam doesn't document how long it accepts them, this is generous.
+const NONCE_MAX_AGE_SECS: i64 = 5 * 60;
+/// How far ahead of our clock Steam's may be
+const NONCE_CLOCK_SKEW_SECS: i64 = 60;
+/// How long used nonces are remembered, longer than they're accepted so a replay is always caught
+const NONCE_TTL_SECS: i64 = NONCE_MAX_AGE_SECS + 2 * NONCE_CLOCK_SKEW_SECS;
+
+/// Why a Steam OpenID return was refused before asking Steam about it
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+pub enum OpenIdReturnError {
+    #[error("Missing parameter openid.{0}")]
+    MissingParameter(&'static str),
+    #[error("openid.{0} isn't signed")]
+    UnsignedField(&'static str),
+    #[error("Unexpected OP endpoint {0}")]
+    WrongEndpoint(String),
+    #[error("return_to {0} doesn't point to this server")]
+    WrongReturnTo(String),
+    #[error("claimed_id {0} isn't a Steam ID")]
+    InvalidClaimedId(String),
+    #[error("claimed_id and identity differ")]
+    IdentityMismatch,
+    #[error("Malformed response nonce")]
+    MalformedNonce,
+    #[error("Response nonce is too old or from the future")]
+    StaleNonce,
+}
+
+/// Why a Steam login was refused
+#[derive(Debug, thiserror::Error)]
+pub enum LoginError {
+    #[error("Refused Steam OpenID return: {0}")]
+    Refused(#[from] OpenIdReturnError),
+    #[error("OpenID verification failed: {0:#}")]
+    NotVerified(anyhow::Error),
+    #[error("Verified Steam ID {verified} differs from claimed {claimed}")]
+    IdMismatch { verified: u64, claimed: u64 },
+    #[error("Response nonce {0} was already used")]
+    Replayed(String),
+    /// Not the player's fault
+    #[error(transparent)]
+    Redis(anyhow::Error),
+}
+
+/// Asks the OP whether a return really is signed by it, OpenID's `check_authentication`.
+/// Implemented by the Steam OpenID client, tests can stub it.
+#[async_trait]
+pub trait CheckAuthentication: Send + Sync {
+    /// Returns the SteamID64 Steam vouches for.
+    async fn check_authentication(&self, query: &str) -> anyhow::Result<u64>;
+}
+
+#[async_trait]
+impl CheckAuthentication for SteamOpenId {
+    async fn check_authentication(&self, query: &str) -> anyhow::Result<u64> {
+        self.verify(query).await.map_err(|e| anyhow!("{e:?}"))
+    }
+}
+
+/// Does every check a Steam OpenID return needs: ours, Steam's signature check and the replay check, in that order.
+/// `expected_return_to` is the URL logins are sent back to.
+///
+/// # Returns
+/// The SteamID64 of the player logging in.
+///
+/// # Errors
+/// Returns the first check that failed.
+pub async fn verify_login(
+    query: &str,
+    expected_return_to: &str,
+    now: OffsetDateTime,
+    op: &dyn CheckAuthentication,
+    redis_conn: &RedisPool,
+) -> Result<u64, LoginError> {
+    // The OP only checks the signature, everything else is up to us
+    let steam_return = SteamReturn::check(query, expected_return_to, now)?;
+
+    let verified = op
+        .check_authentication(query)
+        .await
+        .map_err(LoginError::NotVerified)?;
+    if verified != steam_return.steam_id {
+        return Err(LoginError::IdMismatch {
+            verified,
+            claimed: steam_return.steam_id,
+        });
+    }
+
+    // Only claimed once the signature is verified, so made-up nonces never end up in Redis
+    if !claim_nonce(&steam_return.nonce, redis_conn)
+        .await
+        .map_err(LoginError::Redis)?
+    {
+        return Err(LoginError::Replayed(steam_return.nonce));
+    }
+
+    Ok(verified)
+}
+
+/// A Steam OpenID return that passed the checks `steam_openid` doesn't do itself.
+/// The signature still has to be verified with Steam, and the nonce claimed after that.
+#[derive(Debug, PartialEq, Eq)]
+pub struct SteamReturn {
+    pub steam_id: u64,
+    pub nonce: String,
+}
+
+impl SteamReturn {
+    /// Checks the query string Steam redirected the player back with.
+    /// `expected_return_to` is the URL logins are sent back to, its origin and path have to match exactly.
+    ///
+    /// # Errors
+    /// Returns the first check that failed.
+    pub fn check(
+        query: &str,
+        expected_return_to: &str,
+        now: OffsetDateTime,
+    ) -> Result<Self, OpenIdReturnError> {
+        let params: HashMap<String, String> = url::form_urlencoded::parse(query.as_bytes())
+            .filter_map(|(key, value)| {
+                key.strip_prefix("openid.")
+                    .map(|key| (key.to_owned(), value.into_owned()))
+            })
+            .collect();
+        let param = |name: &'static str| {
+            params
+                .get(name)
+                .map(String::as_str)
+                .ok_or(OpenIdReturnError::MissingParameter(name))
+        };
+
+        let signed: Vec<&str> = param("signed")?.split(',').collect();
+        if let Some(unsigned) = REQUIRED_SIGNED_FIELDS
+            .into_iter()
+            .find(|field| !signed.contains(field))
+        {
+            return Err(OpenIdReturnError::UnsignedField(unsigned));
+        }
+
+        let op_endpoint = param("op_endpoint")?;
+        if op_endpoint != STEAM_OP_ENDPOINT {
+            return Err(OpenIdReturnError::WrongEndpoint(op_endpoint.to_owned()));
+        }
+
+        let return_to = param("return_to")?;
+        if !same_origin_and_path(return_to, expected_return_to) {
+            return Err(OpenIdReturnError::WrongReturnTo(return_to.to_owned()));
+        }
+
+        let claimed_id = param("claimed_id")?;
+        if param("identity")? != claimed_id {
+            return Err(OpenIdReturnError::IdentityMismatch);
+        }
+        let steam_id = claimed_id
+            .strip_prefix(STEAM_CLAIMED_ID_PREFIX)
+            .filter(|id| !id.is_empty() && id.bytes().all(|b| b.is_ascii_digit()))
+            .and_then(|id| id.parse().ok())
+            .ok_or_else(|| OpenIdReturnError::InvalidClaimedId(claimed_id.to_owned()))?;
+
+        let nonce = param("response_nonce")?;
+        let issued_at = nonce_timestamp(nonce).ok_or(OpenIdReturnError::MalformedNonce)?;
+        let age = (now - issued_at).whole_seconds();
+        if !(-NONCE_CLOCK_SKEW_SECS..=NONCE_MAX_AGE_SECS).contains(&age) {
+            return Err(OpenIdReturnError::StaleNonce);
+        }
+
+        Ok(Self {
+            steam_id,
+            nonce: nonce.to_owned(),
+        })
+    }
+}
+
+/// Whether both URLs have the same scheme, host, port and path. The query isn't compared.
+fn same_origin_and_path(url: &str, expected: &str) -> bool {
+    match (Url::parse(url), Url::parse(expected)) {
+        (Ok(url), Ok(expected)) => {
+            url.origin() == expected.origin() && url.path() == expected.path()
+        }
+        _ => false,
+    }
+}
+
+/// Reads the time a nonce was issued at, which OpenID puts at its start, like `2025-03-14T12:00:00Z` followed by anything.
+fn nonce_timestamp(nonce: &str) -> Option<OffsetDateTime> {
+    let timestamp = nonce.get(..20)?;
+    let bytes = timestamp.as_bytes();
+    let separators_ok = [
+        (4, b'-'),
+        (7, b'-'),
+        (10, b'T'),
+        (13, b':'),
+        (16, b':'),
+        (19, b'Z'),
+    ]
+    .into_iter()
+    .all(|(index, separator)| bytes[index] == separator);
+    if !separators_ok {
+        return None;
+    }
+    let number = |range: std::ops::Range<usize>| -> Option<u16> {
+        let digits = timestamp.get(range)?;
+        digits
+            .bytes()
+            .all(|b| b.is_ascii_digit())
+            .then(|| digits.parse().ok())
+            .flatten()
+    };
+    let as_u8 = |value: u16| u8::try_from(value).ok();
+
+    let date = Date::from_calendar_date(
+        i32::from(number(0..4)?),
+        Month::try_from(as_u8(number(5..7)?)?).ok()?,
+        as_u8(number(8..10)?)?,
+    )
+    .ok()?;
+    let time = Time::from_hms(
+        as_u8(number(11..13)?)?,
+        as_u8(number(14..16)?)?,
+        as_u8(number(17..19)?)?,
+    )
+    .ok()?;
+
+    Some(PrimitiveDateTime::new(date, time).assume_utc())
+}
+
+fn nonce_key(nonce: &str) -> String {
+    format!("openid_nonce:{nonce}")
+}
+
+/// Marks a verified nonce as used. Returns `false` if it was used before, so the login is a replay.
+///
+/// # Errors
+/// Fails if something goes wrong with Redis.
+async fn claim_nonce(nonce: &str, redis_conn: &RedisPool) -> anyhow::Result<bool> {
+    let claimed: Option<String> = redis_conn
+        .set(
+            nonce_key(nonce),
+            1,
+            Some(Expiration::EX(NONCE_TTL_SECS)),
+            Some(SetOptions::NX),
+            false,
+        )
+        .await?;
+
+    Ok(claimed.is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use super::*;
+    use crate::util::testing::{self, KeyStore};
+
+    const RETURN_TO: &str = "https://wavebreaker.example.com/api/auth/return";
+    /// 2025-03-14 12:01:00 UTC, a minute after the nonce in `query`
+    const NOW: i64 = 1_741_953_660;
+
+    fn now() -> OffsetDateTime {
+        OffsetDateTime::from_unix_timestamp(NOW).unwrap()
+    }
+
+    fn query(overrides: &[(&str, &str)]) -> String {
+        let mut params = vec![
+            ("openid.ns", "http://specs.openid.net/auth/2.0"),
+            ("openid.mode", "id_res"),
+            ("openid.op_endpoint", STEAM_OP_ENDPOINT),
+            (
+                "openid.claimed_id",
+                "https://steamcommunity.com/openid/id/76561197960287930",
+            ),
+            (
+                "openid.identity",
+                "https://steamcommunity.com/openid/id/76561197960287930",
+            ),
+            ("openid.return_to", RETURN_TO),
+            ("openid.response_nonce", "2025-03-14T12:00:00ZabcDEF123"),
+            ("openid.assoc_handle", "1234567890"),
+            (
+                "openid.signed",
+                "signed,op_endpoint,claimed_id,identity,return_to,response_nonce,assoc_handle",
+            ),
+            ("openid.sig", "c2lnbmF0dXJl"),
+        ];
+        for (key, value) in overrides {
+            match params.iter_mut().find(|(k, _)| k == key) {
+                Some(param) => param.1 = value,
+                None => params.push((key, value)),
+            }
+        }
+        serde_urlencoded::to_string(params).unwrap()
+    }
+
+    #[test]
+    fn valid_return_is_accepted() {
+        assert_eq!(
+            SteamReturn::check(&query(&[]), RETURN_TO, now()),
+            Ok(SteamReturn {
+                steam_id: 76_561_197_960_287_930,
+                nonce: "2025-03-14T12:00:00ZabcDEF123".to_owned()
+            })
+        );
+    }
+
+    #[test]
+    fn tampered_claimed_id_is_refused() {
+        assert_eq!(
+            SteamReturn::check(
+                &query(&[(
+                    "openid.claimed_id",
+                    "https://steamcommunity.com/openid/id/76561197960287931"
+                )]),
+                RETURN_TO,
+                now()
+            ),
+            Err(OpenIdReturnError::IdentityMismatch)
+        );
+        let evil = "https://evil.example.com/openid/id/76561197960287930";
+        assert_eq!(
+            SteamReturn::check(
+                &query(&[("openid.claimed_id", evil), ("openid.identity", evil)]),
+                RETURN_TO,
+                now()
+            ),
+            Err(OpenIdReturnError::InvalidClaimedId(evil.to_owned()))
+        );
+        assert_eq!(
+            SteamReturn::check(
+                &query(&[(
+                    "openid.signed",
+                    "signed,op_endpoint,identity,return_to,response_nonce"
+                )]),
+                RETURN_TO,
+                now()
+            ),
+            Err(OpenIdReturnError::UnsignedField("claimed_id"))
+        );
+    }
+
+    #[test]
+    fn mismatched_return_to_is_refused() {
+        for return_to in [
+            "https://evil.example.com/api/auth/return",
+            "http://wavebreaker.example.com/api/auth/return",
+            "https://wavebreaker.example.com:8443/api/auth/return",
+            "https://wavebreaker.example.com/api/auth/return/",
+            "https://wavebreaker.example.com/api/auth/returned",
+        ] {
+            assert_eq!(
+                SteamReturn::check(&query(&[("openid.return_to", return_to)]), RETURN_TO, now()),
+                Err(OpenIdReturnError::WrongReturnTo(return_to.to_owned()))
+            );
+        }
+        // Only the query may differ
+        assert!(SteamReturn::check(
+            &query(&[("openid.return_to", &format!("{RETURN_TO}?state=1"))]),
+            RETURN_TO,
+            now()
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn wrong_endpoint_is_refused() {
+        assert_eq!(
+            SteamReturn::check(
+                &query(&[(
+                    "openid.op_endpoint",
+                    "https://evil.example.com/openid/login"
+                )]),
+                RETURN_TO,
+                now()
+            ),
+            Err(OpenIdReturnError::WrongEndpoint(
+                "https://evil.example.com/openid/login".to_owned()
+            ))
+        );
+    }
+
+    #[test]
+    fn nonce_has_to_be_fresh() {
+        assert_eq!(
+            SteamReturn::check(
+                &query(&[("openid.response_nonce", "2025-03-14T11:50:00Zabc")]),
+                RETURN_TO,
+                now()
+            ),
+            Err(OpenIdReturnError::StaleNonce)
+        );
+        assert_eq!(
+            SteamReturn::check(
+                &query(&[("openid.response_nonce", "2025-03-14T12:05:00Zabc")]),
+                RETURN_TO,
+                now()
+            ),
+            Err(OpenIdReturnError::StaleNonce)
+        );
+        assert_eq!(
+            SteamReturn::check(
+                &query(&[("openid.response_nonce", "yesterday")]),
+                RETURN_TO,
+                now()
+            ),
+            Err(OpenIdReturnError::MalformedNonce)
+        );
+        assert_eq!(
+            nonce_timestamp("2025-02-30T12:00:00Z"),
+            None,
+            "invalid dates aren't accepted"
+        );
+    }
+
+    /// Steam answering `check_authentication` with `steam_id`, or refusing if there is none
+    #[derive(Default)]
+    struct StubSteam {
+        steam_id: Option<u64>,
+        checks: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl CheckAuthentication for StubSteam {
+        async fn check_authentication(&self, _query: &str) -> anyhow::Result<u64> {
+            self.checks.fetch_add(1, Ordering::Relaxed);
+            self.steam_id.ok_or_else(|| anyhow!("is_valid:false"))
+        }
+    }
+
+    #[tokio::test]
+    async fn replayed_logins_are_refused() {
+        let redis_conn = testing::redis(Arc::new(KeyStore::default())).await;
+        let steam = StubSteam {
+            steam_id: Some(76_561_197_960_287_930),
+            ..StubSteam::default()
+        };
+
+        assert_eq!(
+            verify_login(&query(&[]), RETURN_TO, now(), &steam, &redis_conn)
+                .await
+                .unwrap(),
+            76_561_197_960_287_930
+        );
+        assert!(matches!(
+            verify_login(&query(&[]), RETURN_TO, now(), &steam, &redis_conn).await,
+            Err(LoginError::Replayed(nonce)) if nonce == "2025-03-14T12:00:00ZabcDEF123"
+        ));
+
+        // A new login has a new nonce
+        assert!(verify_login(
+            &query(&[("openid.response_nonce", "2025-03-14T12:00:30Zother")]),
+            RETURN_TO,
+            now(),
+            &steam,
+            &redis_conn
+        )
+        .await
+        .is_ok());
+    }
+
+    #[tokio::test]
+    async fn logins_steam_doesnt_vouch_for_are_refused() {
+        let redis_conn = testing::redis(Arc::new(KeyStore::default())).await;
+
+        let refusing = StubSteam::default();
+        assert!(matches!(
+            verify_login(&query(&[]), RETURN_TO, now(), &refusing, &redis_conn).await,
+            Err(LoginError::NotVerified(_))
+        ));
+
+        let other_player = StubSteam {
+            steam_id: Some(76_561_197_960_287_931),
+            ..StubSteam::default()
+        };
+        assert!(matches!(
+            verify_login(&query(&[]), RETURN_TO, now(), &other_player, &redis_conn).await,
+            Err(LoginError::IdMismatch { .. })
+        ));
+
+        // Neither used up the nonce, so the real login still works
+        let steam = StubSteam {
+            steam_id: Some(76_561_197_960_287_930),
+            ..StubSteam::default()
+        };
+        assert!(
+            verify_login(&query(&[]), RETURN_TO, now(), &steam, &redis_conn)
+                .await
+                .is_ok()
+        );
+    }
+
+    #[tokio::test]
+    async fn steam_is_only_asked_about_plausible_returns() {
+        let redis_conn = testing::redis(Arc::new(KeyStore::default())).await;
+        let steam = StubSteam {
+            steam_id: Some(76_561_197_960_287_930),
+            ..StubSteam::default()
+        };
+
+        assert!(matches!(
+            verify_login(
+                &query(&[("openid.return_to", "https://evil.example.com/")]),
+                RETURN_TO,
+                now(),
+                &steam,
+                &redis_conn
+            )
+            .await,
+            Err(LoginError::Refused(OpenIdReturnError::WrongReturnTo(_)))
+        ));
+        assert_eq!(steam.checks.load(Ordering::Relaxed), 0);
+    }
+}
@@ -0,0 +1,215 @@
+use std::fmt;
+
+use anyhow::anyhow;
+use serde::Deserialize;
+
+/// Limits a score submission has to stay within to be accepted.
+///
+/// Submissions are only sanity-checked, a modified client that stays within these can still cheat.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct PlausibilityRules {
+    /// Scores above this multiple of the song's gold threshold are refused
+    pub max_gold_multiple: f64,
+    /// Track shape points the game sends per second of song. If not set, the track shape isn't checked.
+    pub track_shape_per_second: Option<f64>,
+    /// How far the track shape length may be off from the expected one, as a factor in either direction
+    pub track_shape_tolerance: f64,
+    /// Feats the game can award. If not set, feats aren't checked.
+    pub known_feats: Option<Vec<String>>,
+}
+
+impl Default for PlausibilityRules {
+    fn default() -> Self {
+        Self {
+            max_gold_multiple: 10.0,
+            track_shape_per_second: None,
+            track_shape_tolerance: 4.0,
+            known_feats: None,
+        }
+    }
+}
+
+/// Why a submission was refused
+#[derive(Debug, PartialEq, Eq)]
+pub enum Implausibility {
+    NegativeScore,
+    InvalidGoldThreshold,
+    AboveGoldMultiple { limit: i64 },
+    TrackShapeLength { length: usize, expected: usize },
+    UnknownFeat(String),
+}
+
+impl fmt::Display for Implausibility {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NegativeScore => write!(f, "Score is negative"),
+            Self::InvalidGoldThreshold => write!(f, "Gold threshold isn't positive"),
+            Self::AboveGoldMultiple { limit } => write!(f, "Score is above the limit of {limit}"),
+            Self::TrackShapeLength { length, expected } => write!(
+                f,
+                "Track shape has {length} points, expected about {expected}"
+            ),
+            Self::UnknownFeat(feat) => write!(f, "Unknown feat \"{feat}\""),
+        }
+    }
+}
+
+impl PlausibilityRules {
+    /// Checks that the limits make sense.
+    ///
+    /// # Errors
+    /// Fails if the gold multiple isn't positive, the track shape rate isn't positive or the tolerance is below 1.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if !(self.max_gold_multiple.is_finite() && self.max_gold_multiple > 0.0) {
+            return Err(anyhow!(
+                "Maximum gold multiple {} has to be above 0",
+                self.max_gold_multiple
+            ));
+        }
+        if let Some(rate) = self.track_shape_per_second {
+            if !(rate.is_finite() && rate > 0.0) {
+                return Err(anyhow!(
+                    "Track shape points per second {rate} has to be above 0"
+                ));
+            }
+        }
+        if !(self.track_shape_tolerance.is_finite() && self.track_shape_tolerance >= 1.0) {
+            return Err(anyhow!(
+                "Track shape tolerance {} has to be at least 1",
+                self.track_shape_tolerance
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Checks a score submission. `song_length` is in centiseconds, like the game sends it.
+    ///
+    /// # Errors
+    /// Returns the first rule the submission breaks.
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss,
+        clippy::cast_precision_loss
+    )]
+    pub fn check(
+        &self,
+        score: i32,
+        gold_threshold: i32,
+        song_length: i32,
+        track_shape_length: usize,
+        feats: &[&str],
+    ) -> Result<(), Implausibility> {
+        if score < 0 {
+            return Err(Implausibility::NegativeScore);
+        }
+        if gold_threshold <= 0 {
+            return Err(Implausibility::InvalidGoldThreshold);
+        }
+        let limit = (f64::from(gold_threshold) * self.max_gold_multiple) as i64;
+        if i64::from(score) > limit {
+            return Err(Implausibility::AboveGoldMultiple { limit });
+        }
+
+        if let Some(rate) = self.track_shape_per_second {
+            let expected = (f64::from(song_length.max(0)) / 100.0 * rate).round();
+            let length = track_shape_length as f64;
+            if length * self.track_shape_tolerance < expected
+                || length > expected.max(1.0) * self.track_shape_tolerance
+            {
+                return Err(Implausibility::TrackShapeLength {
+                    length: track_shape_length,
+                    expected: expected as usize,
+                });
+            }
+        }
+
+        if let Some(known_feats) = &self.known_feats {
+            // The game sends an empty string if there are no feats
+            if let Some(unknown) = feats
+                .iter()
+                .filter(|feat| !feat.is_empty())
+                .find(|feat| !known_feats.iter().any(|known| known == *feat))
+            {
+                return Err(Implausibility::UnknownFeat((*unknown).to_owned()));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_rules_check_score_only() {
+        let rules = PlausibilityRules::default();
+        assert!(rules.validate().is_ok());
+        assert_eq!(
+            rules.check(150_000, 100_000, 12_000, 3, &["Whatever"]),
+            Ok(())
+        );
+        assert_eq!(
+            rules.check(-1, 100_000, 12_000, 3, &[]),
+            Err(Implausibility::NegativeScore)
+        );
+        assert_eq!(
+            rules.check(1_000_001, 100_000, 12_000, 3, &[]),
+            Err(Implausibility::AboveGoldMultiple { limit: 1_000_000 })
+        );
+        assert_eq!(
+            rules.check(1, 0, 12_000, 3, &[]),
+            Err(Implausibility::InvalidGoldThreshold)
+        );
+    }
+
+    #[test]
+    fn track_shape_and_feats_are_checked_when_configured() {
+        let rules = PlausibilityRules {
+            track_shape_per_second: Some(1.0),
+            known_feats: Some(vec!["Clean Finish".to_owned(), "Seeing Red".to_owned()]),
+            ..PlausibilityRules::default()
+        };
+        // Two minutes, so 120 points are expected and 30 to 480 are fine
+        assert_eq!(
+            rules.check(1000, 1000, 12_000, 120, &["Clean Finish"]),
+            Ok(())
+        );
+        assert_eq!(rules.check(1000, 1000, 12_000, 30, &[""]), Ok(()));
+        assert_eq!(
+            rules.check(1000, 1000, 12_000, 5, &[]),
+            Err(Implausibility::TrackShapeLength {
+                length: 5,
+                expected: 120
+            })
+        );
+        assert_eq!(
+            rules.check(1000, 1000, 12_000, 481, &[]),
+            Err(Implausibility::TrackShapeLength {
+                length: 481,
+                expected: 120
+            })
+        );
+        assert_eq!(
+            rules.check(1000, 1000, 12_000, 120, &["Seeing Red", "Aimbot"]),
+            Err(Implausibility::UnknownFeat("Aimbot".to_owned()))
+        );
+    }
+
+    #[test]
+    fn invalid_rules_are_refused() {
+        let rules = PlausibilityRules {
+            track_shape_tolerance: 0.5,
+            ..PlausibilityRules::default()
+        };
+        assert!(rules.validate().is_err());
+        let rules = PlausibilityRules {
+            max_gold_multiple: 0.0,
+            ..PlausibilityRules::default()
+        };
+        assert!(rules.validate().is_err());
+    }
+}
@@ -0,0 +1,59 @@
+use fred::{clients::Pool as RedisPool, prelude::*};
+use time::OffsetDateTime;
+
+/// Sorted set of Steam account numbers, scored by the Unix timestamp of their last game request
+pub const PRESENCE_KEY: &str = "presence";
+
+/// Players count as online for this long after their last game request
+pub const ONLINE_WINDOW_SECS: i64 = 10 * 60;
+
+/// Records that a player just made a game request.
+/// This only writes once per minute per player, since it's called on every game request.
+///
+/// # Errors
+/// Fails if something goes wrong with Redis.
+pub async fn record_presence(steam_account_num: i32, redis_conn: &RedisPool) -> anyhow::Result<()> {
+    // SET NX only succeeds if the key doesn't exist yet, i.e. the last write is over a minute ago
+    let was_set: Option<String> = redis_conn
+        .set(
+            format!("presence:throttle:{steam_account_num}"),
+            1,
+            Some(Expiration::EX(60)),
+            Some(SetOptions::NX),
+            false,
+        )
+        .await?;
+
+    if was_set.is_some() {
+        #[allow(clippy::cast_precision_loss)]
+        let now = OffsetDateTime::now_utc().unix_timestamp() as f64;
+        let _: () = redis_conn
+            .zadd(
+                PRESENCE_KEY,
+                None,
+                None,
+                false,
+                false,
+                (now, steam_account_num),
+            )
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Gets the Steam account numbers of everyone who is online, most recently seen first.
+/// Players who haven't been seen in a while are removed from the set on the way.
+///
+/// # Errors
+/// Fails if something goes wrong with Redis.
+pub async fn online_players(redis_conn: &RedisPool) -> anyhow::Result<Vec<i32>> {
+    let cutoff = OffsetDateTime::now_utc().unix_timestamp() - ONLINE_WINDOW_SECS;
+
+    let trx = redis_conn.next().multi();
+    let _: () = trx.zremrangebyscore(PRESENCE_KEY, "-inf", cutoff).await?;
+    let _: () = trx.zrevrange(PRESENCE_KEY, 0, -1, false).await?;
+    let (_, online): (i64, Vec<i32>) = trx.exec(true).await?;
+
+    Ok(online)
+}
@@ -1,24 +1,518 @@
-use std::fs;
+use std::{
+    io::ErrorKind,
+    path::Path,
+    sync::{Arc, OnceLock, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard},
+    time::{Duration, Instant},
+};
 
-use serde::Deserialize;
+use anyhow::Context;
+use diesel::prelude::*;
+use diesel_async::{scoped_futures::ScopedFutureExt, AsyncConnection, RunQueryDsl};
+use futures::future::join_all;
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+use utoipa::ToSchema;
 
-#[derive(Deserialize, Clone)]
-struct RadioConfig {
-    radio_songs: Option<Vec<RadioSong>>,
+use crate::{models::radio_entries::RadioEntry, schema::radio_entries, Db, WAVEBREAKER_USER_AGENT};
+
+/// Where the radio lineup was configured before it moved into the database
+const LEGACY_CONFIG_PATH: &str = "WavebreakerRadio.toml";
+/// How long checking whether the radio's CGR files can be downloaded is good for
+const FILE_CHECK_TTL: Duration = Duration::from_secs(5 * 60);
+/// How long a CGR file that isn't in `cgr_location` gets to answer
+const HEAD_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Shared by all file checks, so they can reuse connections
+static HTTP_CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+
+#[derive(Deserialize)]
+struct LegacyRadioConfig {
+    radio_songs: Option<Vec<LegacyRadioSong>>,
+}
+
+#[derive(Deserialize)]
+struct LegacyRadioSong {
+    id: i32,
+    title: String,
+    artist: String,
+    external_url: String,
+    cgr_url: String,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = radio_entries)]
+struct ImportedRadioEntry {
+    song_id: i32,
+    title: String,
+    artist: String,
+    cgr_url: String,
+    external_url: String,
+    sort_order: i32,
+}
+
+fn parse_legacy_config(config: &str) -> anyhow::Result<Vec<ImportedRadioEntry>> {
+    let config: LegacyRadioConfig = toml::from_str(config)?;
+    Ok((0..)
+        .zip(config.radio_songs.unwrap_or_default())
+        .map(|(sort_order, song)| ImportedRadioEntry {
+            song_id: song.id,
+            title: song.title,
+            artist: song.artist,
+            cgr_url: song.cgr_url,
+            external_url: song.external_url,
+            sort_order,
+        })
+        .collect())
 }
 
-#[derive(Deserialize, Clone)]
-#[allow(clippy::module_name_repetitions)]
-pub struct RadioSong {
-    pub id: i32,
-    pub title: String,
-    pub artist: String,
-    pub external_url: String,
+/// Imports `WavebreakerRadio.toml` if there is one and the radio lineup in the database is still empty,
+/// so deployments from before the lineup moved into the database keep their radio.
+///
+/// # Errors
+/// Fails if the file can't be read or parsed, or something is wrong with the DB.
+pub async fn import_legacy_config(db: &Db) -> anyhow::Result<()> {
+    let config = match tokio::fs::read_to_string(LEGACY_CONFIG_PATH).await {
+        Ok(config) => config,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e).context(format!("Failed to read {LEGACY_CONFIG_PATH}")),
+    };
+    let entries =
+        parse_legacy_config(&config).with_context(|| format!("Invalid {LEGACY_CONFIG_PATH}"))?;
+
+    let mut conn = db.get().await?;
+    let imported = conn
+        .transaction(|conn| {
+            async move {
+                // Locked, so two instances starting at once don't both import
+                diesel::sql_query("LOCK TABLE radio_entries IN EXCLUSIVE MODE")
+                    .execute(conn)
+                    .await?;
+                let existing: i64 = radio_entries::table.count().get_result(conn).await?;
+                if existing > 0 {
+                    return Ok(0);
+                }
+
+                diesel::insert_into(radio_entries::table)
+                    .values(&entries)
+                    .execute(conn)
+                    .await
+            }
+            .scope_boxed()
+        })
+        .await?;
+
+    if imported > 0 {
+        info!(
+            "Imported {imported} radio song(s) from {LEGACY_CONFIG_PATH}, \
+            it isn't used anymore and can be deleted"
+        );
+    }
+    Ok(())
+}
+
+/// How the radio lineup is doing, see [`RadioLineup::status`]
+#[derive(Serialize, ToSchema, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum RadioState {
+    /// Hasn't been loaded since startup, so the radio is empty
+    NotLoaded,
+    Ok,
+    /// Loaded fine, but no entry is active and available right now
+    NoSongs,
+    /// Some songs' CGR files can't be downloaded
+    Broken,
+    /// The last reload failed, the lineup from before is still served
+    ReloadFailed,
+}
+
+#[derive(Serialize, ToSchema, Clone, PartialEq, Eq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RadioStatus {
+    pub state: RadioState,
+    /// The state for humans, e.g. "1 of 2 songs broken"
+    pub summary: String,
+    /// Entries that are on the radio right now
+    pub songs: usize,
+    /// Why the last reload failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Whether each song's CGR file can be downloaded, checked every few minutes
+    pub files: Vec<RadioFileStatus>,
+}
+
+#[derive(Serialize, ToSchema, Clone, PartialEq, Eq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RadioFileStatus {
+    pub entry_id: i32,
     pub cgr_url: String,
+    /// Why the file can't be downloaded, if it can't
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub problem: Option<String>,
+}
+
+/// Results of checking the CGR files, see [`check_files`]
+#[derive(Clone)]
+struct FileCheck {
+    checked_at: Instant,
+    files: Vec<RadioFileStatus>,
+}
+
+impl FileCheck {
+    /// Whether the check is recent and was done for these entries
+    fn is_valid_for(&self, entries: &[&RadioEntry], now: Instant) -> bool {
+        now.duration_since(self.checked_at) < FILE_CHECK_TTL
+            && self.files.len() == entries.len()
+            && self
+                .files
+                .iter()
+                .zip(entries)
+                .all(|(file, entry)| file.entry_id == entry.id && file.cgr_url == entry.cgr_url)
+    }
+}
+
+#[derive(Default)]
+struct Lineup {
+    entries: Arc<Vec<RadioEntry>>,
+    loaded: bool,
+    error: Option<String>,
+    file_check: Option<FileCheck>,
+}
+
+impl Lineup {
+    /// Takes the result of a reload. If it failed, the entries from before stay.
+    fn update(&mut self, result: Result<Vec<RadioEntry>, String>) {
+        match result {
+            Ok(entries) => {
+                self.entries = Arc::new(entries);
+                self.loaded = true;
+                self.error = None;
+            }
+            Err(error) => self.error = Some(error),
+        }
+    }
+
+    /// `files` has to be the result of checking what's available at `now`.
+    fn status(&self, now: OffsetDateTime, files: Vec<RadioFileStatus>) -> RadioStatus {
+        let songs = self
+            .entries
+            .iter()
+            .filter(|entry| entry.is_available_at(now))
+            .count();
+        let broken = files.iter().filter(|file| file.problem.is_some()).count();
+        let (state, summary) = if self.error.is_some() {
+            (
+                RadioState::ReloadFailed,
+                format!("Reload failed, still serving {songs} song(s) from before"),
+            )
+        } else if !self.loaded {
+            (RadioState::NotLoaded, "Not loaded yet".to_owned())
+        } else if songs == 0 {
+            (RadioState::NoSongs, "No songs".to_owned())
+        } else if broken > 0 {
+            (
+                RadioState::Broken,
+                format!("{broken} of {songs} song(s) broken"),
+            )
+        } else {
+            (RadioState::Ok, format!("{songs} song(s)"))
+        };
+
+        RadioStatus {
+            state,
+            summary,
+            songs,
+            error: self.error.clone(),
+            files,
+        }
+    }
+}
+
+/// Name of the file a radio song's `cgr_url` points to, which is served from `cgr_location`
+#[must_use]
+pub fn cgr_file_name(cgr_url: &str) -> Option<&str> {
+    cgr_url
+        .rsplit('/')
+        .next()
+        .filter(|file_name| !file_name.is_empty())
 }
 
-pub fn get_radio_songs() -> anyhow::Result<Option<Vec<RadioSong>>> {
-    let config_string = fs::read_to_string("WavebreakerRadio.toml")?;
-    let radio_config: RadioConfig = toml::from_str(&config_string)?;
-    Ok(radio_config.radio_songs)
+/// Checks that the file is in `cgr_location`, or else that its URL answers.
+async fn check_file(cgr_url: &str, cgr_location: &Path) -> Result<(), String> {
+    if let Some(file_name) = cgr_file_name(cgr_url) {
+        if tokio::fs::metadata(cgr_location.join(file_name))
+            .await
+            .is_ok_and(|metadata| metadata.is_file())
+        {
+            return Ok(());
+        }
+    }
+
+    let response = HTTP_CLIENT
+        .get_or_init(reqwest::Client::new)
+        .head(cgr_url)
+        .header(reqwest::header::USER_AGENT, WAVEBREAKER_USER_AGENT)
+        .timeout(HEAD_TIMEOUT)
+        .send()
+        .await
+        .map_err(|e| format!("Not in cgr_location, and the URL can't be reached: {e}"))?;
+    if !response.status().is_success() {
+        return Err(format!(
+            "Not in cgr_location, and the URL answers with {}",
+            response.status()
+        ));
+    }
+
+    Ok(())
+}
+
+/// Checks whether each entry's CGR file can be downloaded, see [`check_file`].
+/// All files are checked at once, so broken songs only cost one timeout together.
+pub async fn check_files(entries: &[&RadioEntry], cgr_location: &Path) -> Vec<RadioFileStatus> {
+    join_all(entries.iter().map(|entry| async move {
+        RadioFileStatus {
+            entry_id: entry.id,
+            cgr_url: entry.cgr_url.clone(),
+            problem: check_file(&entry.cgr_url, cgr_location).await.err(),
+        }
+    }))
+    .await
+}
+
+/// The active radio entries, kept in memory so the radio doesn't need the database for every request.
+///
+/// Loaded at startup and reloaded whenever moderators change the lineup, and periodically by `tasks`
+/// so changes made through other instances show up too.
+#[derive(Clone, Default)]
+pub struct RadioLineup {
+    lineup: Arc<RwLock<Lineup>>,
+    /// Held while the files are checked, so callers arriving meanwhile wait for that result instead of checking again
+    file_check_lock: Arc<Mutex<()>>,
+}
+
+impl RadioLineup {
+    // The lineup is still fine if a thread panicked while holding the lock
+    fn read(&self) -> RwLockReadGuard<'_, Lineup> {
+        self.lineup.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, Lineup> {
+        self.lineup.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// The cached file check, if it's still good for `entries`
+    fn cached_files(&self, entries: &[&RadioEntry]) -> Option<Vec<RadioFileStatus>> {
+        self.read()
+            .file_check
+            .as_ref()
+            .filter(|cached| cached.is_valid_for(entries, Instant::now()))
+            .map(|cached| cached.files.clone())
+    }
+
+    /// The active entries in the order the game lists them, including those that aren't available right now.
+    /// Check [`RadioEntry::is_available_at`] before listing them!
+    #[must_use]
+    pub fn entries(&self) -> Arc<Vec<RadioEntry>> {
+        Arc::clone(&self.read().entries)
+    }
+
+    /// How the lineup is doing, including whether the CGR files of the songs on the radio
+    /// are in `cgr_location` or can be downloaded. That is only checked every few minutes,
+    /// or when the songs on the radio change.
+    pub async fn status(&self, cgr_location: &Path) -> RadioStatus {
+        let now = OffsetDateTime::now_utc();
+        let entries = self.entries();
+        let available: Vec<&RadioEntry> = entries
+            .iter()
+            .filter(|entry| entry.is_available_at(now))
+            .collect();
+
+        let files = match self.cached_files(&available) {
+            Some(files) => files,
+            None => {
+                let _checking = self.file_check_lock.lock().await;
+                // Someone else might have checked while we waited
+                if let Some(files) = self.cached_files(&available) {
+                    files
+                } else {
+                    let files = check_files(&available, cgr_location).await;
+                    self.write().file_check = Some(FileCheck {
+                        checked_at: Instant::now(),
+                        files: files.clone(),
+                    });
+                    files
+                }
+            }
+        };
+
+        self.read().status(now, files)
+    }
+
+    /// Loads the lineup from the database again. If that fails, the problem is logged
+    /// and the lineup from before is kept.
+    ///
+    /// # Errors
+    /// Fails if the lineup can't be loaded.
+    pub async fn reload(&self, db: &Db) -> anyhow::Result<()> {
+        // Not from the read replica, a change that was just made has to show up
+        let result = async {
+            let mut conn = db.get().await?;
+            anyhow::Ok(RadioEntry::lineup(&mut conn).await?)
+        }
+        .await;
+
+        let mut lineup = self.write();
+        match result {
+            Ok(entries) => {
+                lineup.update(Ok(entries));
+                Ok(())
+            }
+            Err(e) => {
+                warn!("Failed to reload the radio lineup, keeping the old one: {e:#}");
+                lineup.update(Err(format!("{e:#}")));
+                Err(e)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(id: i32) -> RadioEntry {
+        RadioEntry {
+            id,
+            song_id: id,
+            title: "Dear Music.".to_owned(),
+            artist: "A4.".to_owned(),
+            cgr_url: "http://localhost/as/asradio/WVBR_A4_DearMusic.cgr".to_owned(),
+            external_url: "https://www.youtube.com/watch?v=XeVrdjZSceA".to_owned(),
+            sort_order: 0,
+            active: true,
+            created_at: OffsetDateTime::UNIX_EPOCH,
+            available_from: None,
+            available_until: None,
+        }
+    }
+
+    #[test]
+    fn failed_reloads_keep_the_last_lineup() {
+        let now = OffsetDateTime::UNIX_EPOCH;
+        let mut lineup = Lineup::default();
+        assert_eq!(lineup.status(now, vec![]).state, RadioState::NotLoaded);
+
+        lineup.update(Ok(vec![]));
+        assert_eq!(lineup.status(now, vec![]).state, RadioState::NoSongs);
+
+        lineup.update(Ok(vec![entry(1), entry(2)]));
+        assert_eq!(lineup.status(now, vec![]).state, RadioState::Ok);
+
+        lineup.update(Err("connection refused".to_owned()));
+        let status = lineup.status(now, vec![]);
+        assert_eq!(status.state, RadioState::ReloadFailed);
+        assert_eq!(status.songs, 2);
+        assert_eq!(status.error.as_deref(), Some("connection refused"));
+        assert_eq!(lineup.entries.len(), 2);
+
+        lineup.update(Ok(vec![entry(1)]));
+        assert_eq!(
+            lineup.status(now, vec![]),
+            RadioStatus {
+                state: RadioState::Ok,
+                summary: "1 song(s)".to_owned(),
+                songs: 1,
+                error: None,
+                files: vec![],
+            }
+        );
+
+        let mut scheduled = entry(3);
+        scheduled.available_from = Some(now + time::Duration::days(1));
+        lineup.update(Ok(vec![scheduled]));
+        assert_eq!(lineup.status(now, vec![]).state, RadioState::NoSongs);
+    }
+
+    #[test]
+    fn broken_files_are_counted() {
+        let now = OffsetDateTime::UNIX_EPOCH;
+        let mut lineup = Lineup::default();
+        lineup.update(Ok(vec![entry(1), entry(2)]));
+
+        let file = |entry_id, problem: Option<&str>| RadioFileStatus {
+            entry_id,
+            cgr_url: entry(entry_id).cgr_url,
+            problem: problem.map(ToOwned::to_owned),
+        };
+        let status = lineup.status(
+            now,
+            vec![file(1, None), file(2, Some("URL answers with 404"))],
+        );
+        assert_eq!(status.state, RadioState::Broken);
+        assert_eq!(status.summary, "1 of 2 song(s) broken");
+    }
+
+    #[test]
+    fn file_checks_are_redone_when_the_songs_change() {
+        let checked_at = Instant::now();
+        let check = FileCheck {
+            checked_at,
+            files: vec![RadioFileStatus {
+                entry_id: 1,
+                cgr_url: entry(1).cgr_url,
+                problem: None,
+            }],
+        };
+
+        let first = entry(1);
+        assert!(check.is_valid_for(&[&first], checked_at));
+        assert!(!check.is_valid_for(&[&first], checked_at + FILE_CHECK_TTL));
+        assert!(!check.is_valid_for(&[&first, &entry(2)], checked_at));
+
+        let mut moved = entry(1);
+        moved.cgr_url = "https://cdn.example.com/WVBR_A4_DearMusic.cgr".to_owned();
+        assert!(!check.is_valid_for(&[&moved], checked_at));
+    }
+
+    #[test]
+    fn cgr_file_names_come_from_the_url() {
+        assert_eq!(
+            cgr_file_name("http://localhost/as/asradio/WVBR_A4_DearMusic.cgr"),
+            Some("WVBR_A4_DearMusic.cgr")
+        );
+        assert_eq!(cgr_file_name("http://localhost/as/asradio/"), None);
+    }
+
+    #[test]
+    fn legacy_config_keeps_its_order() {
+        let entries = parse_legacy_config(
+            r#"
+            [[radio_songs]]
+            id = 1
+            title = "Dear Music."
+            artist = "A4."
+            external_url = "https://www.youtube.com/watch?v=XeVrdjZSceA"
+            cgr_url = "http://localhost/as/asradio/WVBR_A4_DearMusic.cgr"
+
+            [[radio_songs]]
+            id = 7
+            title = "Second"
+            artist = "Someone"
+            external_url = "https://example.com"
+            cgr_url = "http://localhost/as/asradio/Second.cgr"
+            "#,
+        )
+        .unwrap();
+
+        let imported: Vec<(i32, i32)> = entries
+            .iter()
+            .map(|entry| (entry.song_id, entry.sort_order))
+            .collect();
+        assert_eq!(imported, [(1, 0), (7, 1)]);
+        assert_eq!(entries[0].title, "Dear Music.");
+
+        assert!(parse_legacy_config("").unwrap().is_empty());
+        assert!(parse_legacy_config("radio_songs = 1").is_err());
+    }
 }
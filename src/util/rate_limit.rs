@@ -0,0 +1,38 @@
+use fred::{clients::Pool as RedisPool, prelude::*, types::ExpireOptions};
+use tracing::warn;
+
+/// Counts an attempt at something that's allowed `limit` times per `window_secs`,
+/// and says if this attempt is still within the limit.
+/// The window starts with the first attempt, attempts over the limit count too.
+///
+/// # Errors
+/// Fails if something goes wrong with Redis.
+pub async fn try_acquire(
+    key: &str,
+    limit: u32,
+    window_secs: i64,
+    redis_conn: &RedisPool,
+) -> anyhow::Result<bool> {
+    // INCR counts the attempt, creating the key if the window is over, and EXPIRE NX starts a window for a key without one.
+    // Sent every time, so a key that INCR created without the EXPIRE getting through still gets one on the next attempt.
+    let pipeline = redis_conn.next().pipeline();
+    let _: () = pipeline.incr(key).await?;
+    let _: () = pipeline
+        .expire(key, window_secs, Some(ExpireOptions::NX))
+        .await?;
+    let (attempts, _): (i64, i64) = pipeline.all().await?;
+
+    Ok(attempts <= i64::from(limit))
+}
+
+/// Like [`try_acquire`], but lets the attempt through if Redis can't be asked,
+/// since refusing everyone would be worse.
+pub async fn allows(key: &str, limit: u32, window_secs: i64, redis_conn: &RedisPool) -> bool {
+    match try_acquire(key, limit, window_secs, redis_conn).await {
+        Ok(allowed) => allowed,
+        Err(e) => {
+            warn!("Failed to check rate limit {key}, allowing: {e:#}");
+            true
+        }
+    }
+}
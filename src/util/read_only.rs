@@ -0,0 +1,140 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Request, State},
+    http::{Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+
+use super::errors::SimpleRouteErrorOutput;
+use crate::Config;
+
+/// What every refused request gets told
+const READ_ONLY_MESSAGE: &str =
+    "This server is a read-only mirror, log in and submit scores on the main server instead";
+
+/// Routes that write even though they aren't called with a mutating method.
+/// The game sends everything as POST, so only the game routes that actually write are listed.
+const WRITING_PATHS: &[&str] = &[
+    "/api/auth/login",
+    "/api/auth/return",
+    "/as_steamlogin/game_AttemptLoginSteamVerified.php",
+    "/as_steamlogin/game_SteamSyncSteamVerified.php",
+    "/as_steamlogin/game_fetchsongid_unicode.php",
+    "/as_steamlogin/game_SendRideSteamVerified.php",
+    "/as_steamlogin/game_sendShoutSteamVerified.php",
+];
+
+/// Whether a request to `path` changes anything
+fn is_write(method: &Method, path: &str) -> bool {
+    if WRITING_PATHS.contains(&path) {
+        return true;
+    }
+
+    path.starts_with("/api/") && !matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS)
+}
+
+/// Middleware refusing everything that writes with 503 when `read_only` is set.
+pub async fn reject_writes(
+    State(config): State<Arc<Config>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if !config.main.read_only || !is_write(request.method(), request.uri().path()) {
+        return next.run(request).await;
+    }
+
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        Json(SimpleRouteErrorOutput {
+            error: READ_ONLY_MESSAGE.to_owned(),
+        }),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        middleware,
+        routing::{get, post},
+        Router,
+    };
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::util::testing;
+
+    fn router(read_only: bool) -> Router {
+        let mut config = testing::config();
+        config.main.read_only = read_only;
+
+        Router::new()
+            .route("/api/players/{id}", get(|| async { "player" }))
+            .route("/api/players/{id}/ban", post(|| async { "banned" }))
+            .layer(middleware::from_fn_with_state(
+                Arc::new(config),
+                reject_writes,
+            ))
+    }
+
+    async fn status(router: Router, method: Method, path: &str) -> StatusCode {
+        let request = Request::builder()
+            .method(method)
+            .uri(path)
+            .body(Body::empty())
+            .unwrap();
+        router.oneshot(request).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn write_routes_are_refused_in_read_only_mode() {
+        assert_eq!(
+            status(router(true), Method::POST, "/api/players/1/ban").await,
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(
+            status(router(true), Method::GET, "/api/players/1").await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(router(false), Method::POST, "/api/players/1/ban").await,
+            StatusCode::OK
+        );
+    }
+
+    #[test]
+    fn reads_are_allowed() {
+        assert!(!is_write(&Method::GET, "/api/players/1"));
+        assert!(!is_write(&Method::GET, "/api/songs/1/scores"));
+        assert!(!is_write(&Method::HEAD, "/api/stats"));
+        assert!(!is_write(&Method::GET, "/api/docs"));
+        assert!(!is_write(
+            &Method::POST,
+            "/as_steamlogin/game_GetRidesSteamVerified.php"
+        ));
+        assert!(!is_write(
+            &Method::POST,
+            "/as/asradio/game_asradiolist5.php"
+        ));
+    }
+
+    #[test]
+    fn writes_are_refused() {
+        assert!(is_write(&Method::POST, "/api/players/1/ban"));
+        assert!(is_write(&Method::PATCH, "/api/players/self"));
+        assert!(is_write(&Method::DELETE, "/api/auth/session"));
+        assert!(is_write(&Method::GET, "/api/auth/return"));
+        assert!(is_write(
+            &Method::POST,
+            "/as_steamlogin/game_SendRideSteamVerified.php"
+        ));
+        assert!(is_write(
+            &Method::POST,
+            "/as_steamlogin/game_AttemptLoginSteamVerified.php"
+        ));
+    }
+}
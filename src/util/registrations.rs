@@ -0,0 +1,51 @@
+use fred::{clients::Pool as RedisPool, prelude::*};
+use time::OffsetDateTime;
+use tracing::warn;
+
+/// Set while registrations are frozen, holds the Unix timestamp of when they were frozen
+const FROZEN_SINCE_KEY: &str = "registrations:frozen_since";
+
+/// When new registrations were frozen, if they are.
+///
+/// # Errors
+/// Fails if something goes wrong with Redis.
+pub async fn frozen_since(redis_conn: &RedisPool) -> anyhow::Result<Option<OffsetDateTime>> {
+    let timestamp: Option<i64> = redis_conn.get(FROZEN_SINCE_KEY).await?;
+    Ok(timestamp
+        .map(OffsetDateTime::from_unix_timestamp)
+        .transpose()?)
+}
+
+/// Freezes new registrations, or opens them again. Existing players aren't affected either way.
+/// Freezing them again keeps the original time they were frozen at.
+///
+/// # Errors
+/// Fails if something goes wrong with Redis.
+pub async fn set_frozen(frozen: bool, redis_conn: &RedisPool) -> anyhow::Result<()> {
+    if frozen {
+        let _: Option<String> = redis_conn
+            .set(
+                FROZEN_SINCE_KEY,
+                OffsetDateTime::now_utc().unix_timestamp(),
+                None,
+                Some(SetOptions::NX),
+                false,
+            )
+            .await?;
+    } else {
+        let _: () = redis_conn.del(FROZEN_SINCE_KEY).await?;
+    }
+    Ok(())
+}
+
+/// Whether new players may be created right now.
+/// If Redis can't be asked, registrations stay open, since refusing everyone would be worse.
+pub async fn are_open(redis_conn: &RedisPool) -> bool {
+    match frozen_since(redis_conn).await {
+        Ok(frozen_since) => frozen_since.is_none(),
+        Err(e) => {
+            warn!("Failed to check if registrations are frozen, treating them as open: {e:#}");
+            true
+        }
+    }
+}
@@ -0,0 +1,182 @@
+use fred::{clients::Pool as RedisPool, prelude::*};
+use time::OffsetDateTime;
+
+/// How long a session (and the token belonging to it) stays valid after logging in
+pub const SESSION_LIFETIME_SECS: i64 = 60 * 60 * 24 * 7;
+
+fn session_key(session_id: u64) -> String {
+    format!("session:{session_id}")
+}
+
+fn player_sessions_key(player_id: i32) -> String {
+    format!("player_sessions:{player_id}")
+}
+
+/// Updates `last_used_at` of the session in `KEYS[1]` if it belongs to the player in `ARGV[1]`, and returns whether it does.
+/// In one script, so a session that ends in the meantime isn't brought back without its expiry.
+const TOUCH_SESSION_SCRIPT: &str = r"
+if redis.call('HGET', KEYS[1], 'player_id') ~= ARGV[1] then
+    return 0
+end
+redis.call('HSET', KEYS[1], 'last_used_at', ARGV[2])
+return 1
+";
+
+/// Creates a new session for a player, valid for `SESSION_LIFETIME_SECS`.
+///
+/// Sessions are stored in Redis as `session:{id}` hashes, and each player's session IDs in the set `player_sessions:{player_id}`.
+/// The set expires along with the player's newest session.
+/// The ID doesn't have to be unguessable, since it's only ever handed out inside a signed token.
+///
+/// # Returns
+/// The ID of the new session.
+///
+/// # Errors
+/// Fails if something goes wrong with Redis.
+pub async fn create_session(player_id: i32, redis_conn: &RedisPool) -> anyhow::Result<u64> {
+    let session_id: u64 = redis_conn.incr("session:next_id").await?;
+    let now = time::OffsetDateTime::now_utc().unix_timestamp();
+
+    let trx = redis_conn.next().multi();
+    let _: () = trx
+        .hset(
+            session_key(session_id),
+            [
+                ("player_id", i64::from(player_id)),
+                ("created_at", now),
+                ("last_used_at", now),
+            ],
+        )
+        .await?;
+    let _: () = trx
+        .expire(session_key(session_id), SESSION_LIFETIME_SECS, None)
+        .await?;
+    let _: () = trx.sadd(player_sessions_key(player_id), session_id).await?;
+    let _: () = trx
+        .expire(player_sessions_key(player_id), SESSION_LIFETIME_SECS, None)
+        .await?;
+    let _: () = trx.exec(true).await?;
+
+    Ok(session_id)
+}
+
+/// Checks that a session still exists and belongs to the player, and marks it as used just now.
+///
+/// # Errors
+/// Fails if something goes wrong with Redis.
+pub async fn verify_session(
+    session_id: u64,
+    player_id: i32,
+    redis_conn: &RedisPool,
+) -> anyhow::Result<bool> {
+    let touched: i64 = redis_conn
+        .eval(
+            TOUCH_SESSION_SCRIPT,
+            session_key(session_id),
+            vec![
+                i64::from(player_id),
+                time::OffsetDateTime::now_utc().unix_timestamp(),
+            ],
+        )
+        .await?;
+
+    Ok(touched == 1)
+}
+
+/// Ends a player's session, invalidating the token belonging to it.
+/// Sessions of other players are left alone.
+///
+/// # Returns
+/// Whether the session still existed and belonged to the player.
+///
+/// # Errors
+/// Fails if something goes wrong with Redis.
+pub async fn revoke_session(
+    session_id: u64,
+    player_id: i32,
+    redis_conn: &RedisPool,
+) -> anyhow::Result<bool> {
+    let owner: Option<i32> = redis_conn
+        .hget(session_key(session_id), "player_id")
+        .await?;
+    if owner != Some(player_id) {
+        return Ok(false);
+    }
+
+    let trx = redis_conn.next().multi();
+    let _: () = trx.del(session_key(session_id)).await?;
+    let _: () = trx.srem(player_sessions_key(player_id), session_id).await?;
+    let (deleted, _): (i64, i64) = trx.exec(true).await?;
+
+    Ok(deleted > 0)
+}
+
+/// Ends all of a player's sessions, e.g. because their account was compromised.
+///
+/// # Returns
+/// The number of sessions that were still active.
+///
+/// # Errors
+/// Fails if something goes wrong with Redis.
+pub async fn revoke_all_sessions(player_id: i32, redis_conn: &RedisPool) -> anyhow::Result<u64> {
+    let session_ids: Vec<u64> = redis_conn.smembers(player_sessions_key(player_id)).await?;
+    if session_ids.is_empty() {
+        return Ok(0);
+    }
+
+    let trx = redis_conn.next().multi();
+    let _: () = trx
+        .del(
+            session_ids
+                .into_iter()
+                .map(session_key)
+                .collect::<Vec<String>>(),
+        )
+        .await?;
+    let _: () = trx.del(player_sessions_key(player_id)).await?;
+    let (revoked, _): (u64, u64) = trx.exec(true).await?;
+
+    Ok(revoked)
+}
+
+/// An active session of a player.
+#[derive(Debug)]
+pub struct Session {
+    pub id: u64,
+    pub created_at: OffsetDateTime,
+    pub last_used_at: OffsetDateTime,
+}
+
+/// Lists a player's active sessions, oldest first.
+/// Expired sessions are cleaned up from the player's session set along the way.
+///
+/// # Errors
+/// Fails if something goes wrong with Redis.
+pub async fn list_sessions(player_id: i32, redis_conn: &RedisPool) -> anyhow::Result<Vec<Session>> {
+    let session_ids: Vec<u64> = redis_conn.smembers(player_sessions_key(player_id)).await?;
+
+    let mut sessions = vec![];
+    let mut expired = vec![];
+    for session_id in session_ids {
+        let (created_at, last_used_at): (Option<i64>, Option<i64>) = redis_conn
+            .hmget(session_key(session_id), vec!["created_at", "last_used_at"])
+            .await?;
+        match (created_at, last_used_at) {
+            (Some(created_at), Some(last_used_at)) => sessions.push(Session {
+                id: session_id,
+                created_at: OffsetDateTime::from_unix_timestamp(created_at)?,
+                last_used_at: OffsetDateTime::from_unix_timestamp(last_used_at)?,
+            }),
+            _ => expired.push(session_id),
+        }
+    }
+
+    if !expired.is_empty() {
+        let _: () = redis_conn
+            .srem(player_sessions_key(player_id), expired)
+            .await?;
+    }
+
+    sessions.sort_by_key(|session| session.created_at);
+    Ok(sessions)
+}
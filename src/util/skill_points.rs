@@ -0,0 +1,195 @@
+use anyhow::anyhow;
+use fred::{clients::Pool as RedisPool, prelude::*};
+use serde::Deserialize;
+use tracing::{info, warn};
+
+use super::game_types::League;
+
+/// Version of the policy the leaderboards were last rebuilt with
+const POLICY_VERSION_KEY: &str = "leaderboard:policy_version";
+
+/// How skill points are calculated from a score.
+///
+/// A score is worth `score / gold_threshold * league_weight`, rounded.
+/// With `reference_song_length_secs`, songs shorter than that are worth proportionally less.
+///
+/// The leaderboards in Redis are only correct for the policy they were built with,
+/// so after changing it, the version has to be raised and `refresh-all-skill-points` run.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct SkillPointPolicy {
+    /// Raised whenever any of the parameters change
+    pub version: u32,
+    /// What a score at exactly the gold threshold is worth in Casual, Pro and Elite
+    pub league_weights: [f64; 3],
+    pub reference_song_length_secs: Option<u32>,
+}
+
+impl SkillPointPolicy {
+    /// The formula before it was configurable
+    pub const ORIGINAL: Self = Self {
+        version: 1,
+        league_weights: [100.0, 200.0, 300.0],
+        reference_song_length_secs: None,
+    };
+
+    /// Checks that the parameters make sense.
+    ///
+    /// # Errors
+    /// Fails if a weight is negative or not a number, or the reference song length is 0.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if let Some(weight) = self
+            .league_weights
+            .iter()
+            .find(|weight| !weight.is_finite() || **weight < 0.0)
+        {
+            return Err(anyhow!("League weight {weight} has to be 0 or more"));
+        }
+        if self.reference_song_length_secs == Some(0) {
+            return Err(anyhow!("Reference song length can't be 0"));
+        }
+
+        Ok(())
+    }
+
+    const fn league_weight(&self, league: League) -> f64 {
+        self.league_weights[league as usize]
+    }
+
+    /// Calculates the skill points of a score. `song_length` is in centiseconds, like the game sends it.
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn points(&self, score: i32, gold_threshold: i32, league: League, song_length: i32) -> i32 {
+        let mut points =
+            (f64::from(score) / f64::from(gold_threshold)) * self.league_weight(league);
+        if let Some(reference) = self.reference_song_length_secs {
+            points *= (f64::from(song_length) / (f64::from(reference) * 100.0)).min(1.0);
+        }
+        points.round() as i32
+    }
+
+    /// The same as `points` in SQL, for a row of `scores`, without rounding.
+    /// NULL if the gold threshold is 0.
+    #[must_use]
+    pub fn sql_points(&self) -> String {
+        let [casual, pro, elite] = self.league_weights;
+        let mut expression = format!(
+            "(scores.score::numeric / NULLIF(scores.gold_threshold, 0)) \
+            * (CASE scores.league WHEN 0 THEN {casual} WHEN 1 THEN {pro} ELSE {elite} END)::numeric"
+        );
+        if let Some(reference) = self.reference_song_length_secs {
+            expression.push_str(&format!(
+                " * LEAST(scores.song_length::numeric / {}, 1)",
+                u64::from(reference) * 100
+            ));
+        }
+        expression
+    }
+
+    /// Sum of the rounded skill points of all rows, as a BIGINT that is 0 if there are none.
+    #[must_use]
+    pub fn sql_sum(&self) -> String {
+        format!("COALESCE(SUM(ROUND({})), 0)::int8", self.sql_points())
+    }
+}
+
+impl Default for SkillPointPolicy {
+    fn default() -> Self {
+        Self::ORIGINAL
+    }
+}
+
+/// The policy version the leaderboards were last rebuilt with.
+/// Leaderboards from before there were versions count as version 1.
+///
+/// # Errors
+/// Fails if something goes wrong with Redis.
+pub async fn stored_version(redis_conn: &RedisPool) -> anyhow::Result<u32> {
+    let version: Option<u32> = redis_conn.get(POLICY_VERSION_KEY).await?;
+    Ok(version.unwrap_or(SkillPointPolicy::ORIGINAL.version))
+}
+
+/// Remembers that the leaderboards were just rebuilt with `policy`.
+///
+/// # Errors
+/// Fails if something goes wrong with Redis.
+pub async fn store_version(
+    policy: &SkillPointPolicy,
+    redis_conn: &RedisPool,
+) -> anyhow::Result<()> {
+    let _: () = redis_conn
+        .set(POLICY_VERSION_KEY, policy.version, None, None, false)
+        .await?;
+    Ok(())
+}
+
+/// Warns if the leaderboards were built with another policy version than the configured one.
+///
+/// # Errors
+/// Fails if something goes wrong with Redis.
+pub async fn check_version(
+    policy: &SkillPointPolicy,
+    redis_conn: &RedisPool,
+) -> anyhow::Result<()> {
+    let stored = stored_version(redis_conn).await?;
+    let configured = policy.version;
+    if stored == configured {
+        info!("Leaderboards match skill point policy version {configured}");
+    } else {
+        warn!(
+            "Skill point policy is version {configured}, but the leaderboards were built with version {stored}! \
+            Run refresh-all-skill-points to rebuild them"
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn original_policy_is_unchanged() {
+        let policy = SkillPointPolicy::default();
+        assert_eq!(policy.points(1000, 1000, League::Casual, 12_000), 100);
+        assert_eq!(policy.points(1500, 1000, League::Pro, 12_000), 300);
+        assert_eq!(policy.points(1000, 3000, League::Elite, 12_000), 100);
+        assert_eq!(
+            policy.sql_sum(),
+            "COALESCE(SUM(ROUND((scores.score::numeric / NULLIF(scores.gold_threshold, 0)) \
+            * (CASE scores.league WHEN 0 THEN 100 WHEN 1 THEN 200 ELSE 300 END)::numeric)), 0)::int8"
+        );
+    }
+
+    #[test]
+    fn short_songs_are_worth_less() {
+        let policy = SkillPointPolicy {
+            version: 2,
+            league_weights: [100.0, 200.0, 400.0],
+            reference_song_length_secs: Some(120),
+        };
+        // A one minute song is worth half, longer ones aren't worth more
+        assert_eq!(policy.points(1000, 1000, League::Elite, 6000), 200);
+        assert_eq!(policy.points(1000, 1000, League::Elite, 60_000), 400);
+        assert!(policy
+            .sql_points()
+            .ends_with("LEAST(scores.song_length::numeric / 12000, 1)"));
+    }
+
+    #[test]
+    fn invalid_policies_are_refused() {
+        let negative = SkillPointPolicy {
+            league_weights: [100.0, -1.0, 300.0],
+            ..SkillPointPolicy::default()
+        };
+        assert!(negative.validate().is_err());
+
+        let zero_length = SkillPointPolicy {
+            reference_song_length_secs: Some(0),
+            ..SkillPointPolicy::default()
+        };
+        assert!(zero_length.validate().is_err());
+        assert!(SkillPointPolicy::default().validate().is_ok());
+    }
+}
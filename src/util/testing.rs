@@ -0,0 +1,418 @@
+//! Helpers for tests that need a database or Redis.
+//!
+//! Database tests run against the Postgres database in `WAVEBREAKER_TEST_DATABASE` and are skipped if it isn't set.
+//! Everything they do happens in a transaction that is never committed, so the database can be shared.
+
+use std::{
+    cell::Cell,
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::Context;
+use async_trait::async_trait;
+use diesel::{
+    connection::{Instrumentation, InstrumentationEvent},
+    Connection, ConnectionError,
+};
+use diesel_async::{
+    async_connection_wrapper::AsyncConnectionWrapper,
+    pooled_connection::{
+        deadpool::Pool, AsyncDieselConnectionManager, ManagerConfig, RecyclingMethod,
+    },
+    AsyncConnection, AsyncPgConnection, RunQueryDsl,
+};
+use fred::{
+    clients::Pool as RedisPool,
+    mocks::{MockCommand, Mocks},
+    prelude::*,
+    types::config::Config as RedisConfig,
+};
+use futures::FutureExt;
+use steam_openid::SteamOpenId;
+use steam_rs::Steam;
+
+use crate::{
+    models::{
+        scores::{NewScore, Score},
+        songs::NewSong,
+    },
+    util::{
+        game_types::{Character, League},
+        meilisearch::{SongDocument, SongIndex},
+        skill_points::SkillPointPolicy,
+    },
+    AppState, Cache, Config, Db, Metadata, SteamAuth,
+};
+
+/// Where individual accounts start in 64-bit Steam IDs
+const STEAM_ID_BASE: u64 = 76_561_197_960_265_728;
+/// How long tests wait for another test to finish migrating
+const MIGRATION_TIMEOUT: Duration = Duration::from_secs(60);
+
+thread_local! {
+    /// Queries finished on connections from [`state`]'s pool on this thread
+    static QUERIES_RUN: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Counts the queries of a connection in [`QUERIES_RUN`]
+struct QueryCounter;
+
+impl Instrumentation for QueryCounter {
+    fn on_connection_event(&mut self, event: InstrumentationEvent<'_>) {
+        if let InstrumentationEvent::FinishQuery { .. } = event {
+            QUERIES_RUN.with(|queries| queries.set(queries.get() + 1));
+        }
+    }
+}
+
+/// How many queries went through `state.db` on this thread so far, from the test and the handlers it called.
+/// `#[tokio::test]`s run on a single thread, so compare two of these to count the queries in between.
+#[must_use]
+pub fn queries_run() -> usize {
+    QUERIES_RUN.with(Cell::get)
+}
+
+/// URL of the migrated test database, or `None` if `WAVEBREAKER_TEST_DATABASE` isn't set.
+///
+/// # Panics
+/// Panics if the database can't be reached or migrated.
+async fn database_url() -> Option<String> {
+    let Ok(url) = std::env::var("WAVEBREAKER_TEST_DATABASE") else {
+        eprintln!("WAVEBREAKER_TEST_DATABASE isn't set, skipping");
+        return None;
+    };
+
+    // Cheap once nothing is pending, and the migration lock keeps concurrent tests from migrating twice
+    let migration_url = url.clone();
+    tokio::task::spawn_blocking(move || {
+        let mut conn = AsyncConnectionWrapper::<AsyncPgConnection>::establish(&migration_url)
+            .context("Failed to connect to the test database")?;
+        crate::run_migrations(&mut conn, MIGRATION_TIMEOUT)
+    })
+    .await
+    .unwrap()
+    .unwrap();
+
+    Some(url)
+}
+
+/// Connects to the test database, migrated and inside a test transaction.
+/// Returns `None` if `WAVEBREAKER_TEST_DATABASE` isn't set, so the test can be skipped.
+///
+/// # Panics
+/// Panics if the database can't be reached or migrated.
+pub async fn db() -> Option<AsyncPgConnection> {
+    let url = database_url().await?;
+    let mut conn = AsyncPgConnection::establish(&url).await.unwrap();
+    conn.begin_test_transaction().await.unwrap();
+    Some(conn)
+}
+
+/// A config with only the required settings, pointing nowhere.
+///
+/// # Panics
+/// Panics if the required settings changed and this wasn't updated.
+pub(crate) fn config() -> Config {
+    toml::from_str(
+        r#"
+        [main]
+        address = "localhost:1337"
+        database = "postgres://localhost/wavebreaker"
+        redis = "redis://localhost:6379"
+        jwt_secret = "secret"
+
+        [radio]
+        cgr_location = "./radio"
+
+        [external]
+        steam_key = "key"
+        steam_realm = "http://localhost:1337"
+        steam_return_path = "/api/auth/return"
+        "#,
+    )
+    .unwrap()
+}
+
+/// State for running requests through the router, with the test database and `mocks` as Redis.
+/// Returns `None` if `WAVEBREAKER_TEST_DATABASE` isn't set, so the test can be skipped.
+///
+/// The pool only has one connection, so the test and the handlers share its test transaction.
+/// Don't hold on to a connection from `state.db` while sending a request!
+///
+/// # Panics
+/// Panics if the database can't be reached or migrated.
+pub async fn state(mocks: Arc<dyn Mocks>) -> Option<AppState> {
+    let url = database_url().await?;
+    let mut manager_config = ManagerConfig::default();
+    // Checking connections with a query would throw off `queries_run`
+    manager_config.recycling_method = RecyclingMethod::Fast;
+    manager_config.custom_setup = Box::new(|url| {
+        async move {
+            let mut conn = AsyncPgConnection::establish(url).await?;
+            conn.begin_test_transaction()
+                .await
+                .map_err(ConnectionError::CouldntSetupConfiguration)?;
+            conn.set_instrumentation(QueryCounter);
+            Ok(conn)
+        }
+        .boxed()
+    });
+    let manager =
+        AsyncDieselConnectionManager::<AsyncPgConnection>::new_with_config(url, manager_config);
+    let pool = Pool::builder(manager).max_size(1).build().unwrap();
+
+    let config = config();
+    Some(AppState {
+        db: Db {
+            primary: pool,
+            replica: None,
+        },
+        redis: Cache(Arc::new(redis(mocks).await)),
+        steam: SteamAuth {
+            api: Arc::new(Steam::new(&config.external.steam_key)),
+            openid: Arc::new(
+                SteamOpenId::new(
+                    &config.external.steam_realm,
+                    &config.external.steam_return_path,
+                )
+                .unwrap(),
+            ),
+        },
+        metadata: Metadata { meili: None },
+        jwt_keys: crate::util::jwt::Keys::new(config.main.jwt_secret.as_bytes()),
+        usage: crate::util::usage::UsageCounter::default(),
+        radio: crate::util::radio::RadioLineup::default(),
+        config: Arc::new(config),
+    })
+}
+
+/// The 64-bit Steam ID (as stored in `players.steam_id`) of a Steam account number
+#[must_use]
+pub fn steam_id(steam_account_num: i32) -> String {
+    (STEAM_ID_BASE + u64::try_from(steam_account_num).unwrap()).to_string()
+}
+
+/// Inserts a player with the Steam account number `steam_account_num` and returns their ID.
+///
+/// # Panics
+/// Panics if the insert fails, e.g. because the number is taken.
+pub async fn insert_player(steam_account_num: i32, conn: &mut AsyncPgConnection) -> i32 {
+    use diesel::prelude::*;
+
+    use crate::schema::players;
+
+    diesel::insert_into(players::table)
+        .values((
+            players::username.eq(format!("Player {steam_account_num}")),
+            players::steam_id.eq(steam_id(steam_account_num)),
+            players::steam_account_num.eq(steam_account_num),
+            players::avatar_url.eq(""),
+        ))
+        .returning(players::id)
+        .get_result(conn)
+        .await
+        .unwrap()
+}
+
+/// Inserts a song and returns its ID.
+///
+/// # Panics
+/// Panics if the insert fails.
+pub async fn insert_song(title: &str, artist: &str, conn: &mut AsyncPgConnection) -> i32 {
+    NewSong::new(title, artist, None)
+        .find_or_create(conn)
+        .await
+        .unwrap()
+        .id
+}
+
+/// Submits a score for `player_id` on `song_id`, keeping the better one like a game submission does.
+///
+/// # Panics
+/// Panics if the submission fails.
+pub async fn insert_score(
+    player_id: i32,
+    song_id: i32,
+    league: League,
+    score: i32,
+    conn: &mut AsyncPgConnection,
+    redis_conn: &RedisPool,
+) -> Score {
+    NewScore::new(
+        player_id,
+        song_id,
+        league,
+        score,
+        &[],
+        &[],
+        0,
+        Character::PointmanPro,
+        &[],
+        180,
+        score,
+        0,
+        0,
+    )
+    .create_or_update(&SkillPointPolicy::ORIGINAL, conn, redis_conn)
+    .await
+    .unwrap()
+}
+
+/// Redis stand-in that answers every command with nil and remembers them
+#[derive(Debug, Default)]
+pub struct RecordedRedis {
+    commands: Mutex<Vec<MockCommand>>,
+}
+
+impl RecordedRedis {
+    /// Names and first arguments of the commands that were sent, e.g. `("ZINCRBY", "leaderboard")`
+    pub fn commands(&self) -> Vec<(String, String)> {
+        self.commands
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|command| {
+                (
+                    command.cmd.to_string(),
+                    command
+                        .args
+                        .first()
+                        .and_then(|arg| arg.as_string())
+                        .unwrap_or_default(),
+                )
+            })
+            .collect()
+    }
+}
+
+impl Mocks for RecordedRedis {
+    fn process_command(&self, command: MockCommand) -> Result<Value, Error> {
+        self.commands.lock().unwrap().push(command);
+        Ok(Value::Null)
+    }
+}
+
+/// Redis stand-in that keeps plain keys and hashes,
+/// for code that only uses `SET` (with `NX`), `GET`, `DEL`, `EXPIRE`, `HSET` and `HGET`
+#[derive(Debug, Default)]
+pub struct KeyStore {
+    keys: Mutex<HashMap<String, Value>>,
+    hashes: Mutex<HashMap<(String, String), Value>>,
+}
+
+impl Mocks for KeyStore {
+    fn process_command(&self, command: MockCommand) -> Result<Value, Error> {
+        let mut args = command.args.into_iter();
+        let key = args
+            .next()
+            .and_then(|key| key.as_string())
+            .unwrap_or_default();
+        let mut keys = self.keys.lock().unwrap();
+        match &*command.cmd {
+            "SET" => {
+                let value = args.next().unwrap_or(Value::Null);
+                let only_new = args.any(|arg| arg.as_string().as_deref() == Some("NX"));
+                if only_new && keys.contains_key(&key) {
+                    return Ok(Value::Null);
+                }
+                keys.insert(key, value);
+                Ok(Value::from_static_str("OK"))
+            }
+            "GET" => Ok(keys.get(&key).cloned().unwrap_or(Value::Null)),
+            "DEL" => {
+                let mut hashes = self.hashes.lock().unwrap();
+                let had_hash = hashes.keys().any(|(hash_key, _)| *hash_key == key);
+                hashes.retain(|(hash_key, _), _| *hash_key != key);
+                Ok(Value::Integer(i64::from(
+                    keys.remove(&key).is_some() || had_hash,
+                )))
+            }
+            // Keys never expire during a test
+            "EXPIRE" => Ok(Value::Integer(1)),
+            "HSET" => {
+                let mut hashes = self.hashes.lock().unwrap();
+                let mut added = 0;
+                while let (Some(field), Some(value)) = (args.next(), args.next()) {
+                    let field = field.as_string().unwrap_or_default();
+                    if hashes.insert((key.clone(), field), value).is_none() {
+                        added += 1;
+                    }
+                }
+                Ok(Value::Integer(added))
+            }
+            "HGET" => {
+                let field = args
+                    .next()
+                    .and_then(|field| field.as_string())
+                    .unwrap_or_default();
+                let hashes = self.hashes.lock().unwrap();
+                Ok(hashes.get(&(key, field)).cloned().unwrap_or(Value::Null))
+            }
+            other => Err(Error::new(
+                ErrorKind::Unknown,
+                format!("KeyStore doesn't know {other}"),
+            )),
+        }
+    }
+}
+
+/// Redis that fails every command, like it does while it's unreachable
+#[derive(Debug)]
+pub struct Outage;
+
+impl Mocks for Outage {
+    fn process_command(&self, _command: MockCommand) -> Result<Value, Error> {
+        Err(Error::new(ErrorKind::IO, "Connection refused"))
+    }
+}
+
+/// Builds a Redis pool that sends everything to `mocks` instead of a server.
+///
+/// # Panics
+/// Panics if the pool can't be built.
+pub async fn redis(mocks: Arc<dyn Mocks>) -> RedisPool {
+    let config = RedisConfig {
+        mocks: Some(mocks),
+        ..RedisConfig::default()
+    };
+    let pool = Builder::from_config(config).build_pool(1).unwrap();
+    pool.init().await.unwrap();
+    pool
+}
+
+/// Search index stand-in that remembers which songs it was told to remove and index
+#[derive(Debug, Default)]
+pub struct RecordedIndex {
+    removed: Mutex<Vec<i32>>,
+    indexed: Mutex<Vec<i32>>,
+}
+
+impl RecordedIndex {
+    /// IDs of the songs that were removed
+    pub fn removed(&self) -> Vec<i32> {
+        self.removed.lock().unwrap().clone()
+    }
+
+    /// IDs of the songs whose documents were sent
+    pub fn indexed(&self) -> Vec<i32> {
+        self.indexed.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl SongIndex for RecordedIndex {
+    async fn remove_songs(&self, song_ids: &[i32]) -> anyhow::Result<()> {
+        self.removed.lock().unwrap().extend_from_slice(song_ids);
+        Ok(())
+    }
+
+    async fn index_songs(&self, documents: &[SongDocument]) -> anyhow::Result<()> {
+        self.indexed
+            .lock()
+            .unwrap()
+            .extend(documents.iter().map(|document| document.id));
+        Ok(())
+    }
+}
@@ -0,0 +1,168 @@
+use std::{
+    collections::HashMap,
+    mem,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    middleware::Next,
+    response::Response,
+};
+use axum_extra::headers::{authorization::Bearer, Authorization, HeaderMapExt};
+use fred::{clients::Pool as RedisPool, prelude::*};
+use jsonwebtoken::{decode, Validation};
+use time::{Date, OffsetDateTime};
+
+use super::jwt::{Claims, Keys};
+
+/// Usage counts are kept for this many days
+pub const USAGE_RETENTION_DAYS: i64 = 30;
+
+/// Hash of request counts per route group, for one player on one day
+pub fn usage_key(date: Date, player_id: i32) -> String {
+    format!("api_usage:{date}:{player_id}")
+}
+
+/// Sorted set of players by their total request count on one day
+pub fn usage_top_key(date: Date) -> String {
+    format!("api_usage_top:{date}")
+}
+
+/// Counts authenticated API requests in memory until they're flushed to Redis.
+///
+/// Counting happens on every request, so it must not wait for Redis.
+/// `tasks` flushes the buffer every few seconds instead.
+#[derive(Clone, Default)]
+pub struct UsageCounter(Arc<Mutex<HashMap<(i32, String), u64>>>);
+
+impl UsageCounter {
+    fn lock(&self) -> MutexGuard<'_, HashMap<(i32, String), u64>> {
+        // The counts are still fine if a thread panicked while holding the lock
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn record(&self, player_id: i32, group: &str) {
+        let mut counts = self.lock();
+        *counts.entry((player_id, group.to_owned())).or_default() += 1;
+    }
+
+    fn take(&self) -> HashMap<(i32, String), u64> {
+        mem::take(&mut *self.lock())
+    }
+
+    fn restore(&self, pending: HashMap<(i32, String), u64>) {
+        let mut counts = self.lock();
+        for (key, count) in pending {
+            *counts.entry(key).or_default() += count;
+        }
+    }
+
+    /// Writes all buffered counts to today's usage hashes.
+    /// If that fails, the counts are kept for the next flush.
+    ///
+    /// # Errors
+    /// Fails if something goes wrong with Redis.
+    pub async fn flush(&self, redis_conn: &RedisPool) -> anyhow::Result<()> {
+        let pending = self.take();
+        if pending.is_empty() {
+            return Ok(());
+        }
+
+        if let Err(e) = write_counts(&pending, redis_conn).await {
+            self.restore(pending);
+            return Err(e);
+        }
+
+        Ok(())
+    }
+}
+
+async fn write_counts(
+    counts: &HashMap<(i32, String), u64>,
+    redis_conn: &RedisPool,
+) -> anyhow::Result<()> {
+    let today = OffsetDateTime::now_utc().date();
+    let ttl = USAGE_RETENTION_DAYS * 60 * 60 * 24;
+
+    let pipeline = redis_conn.next().pipeline();
+    for ((player_id, group), count) in counts {
+        let count = i64::try_from(*count)?;
+        let _: () = pipeline
+            .hincrby(usage_key(today, *player_id), group.as_str(), count)
+            .await?;
+        let _: () = pipeline
+            .expire(usage_key(today, *player_id), ttl, None)
+            .await?;
+        #[allow(clippy::cast_precision_loss)]
+        let _: () = pipeline
+            .zincrby(usage_top_key(today), count as f64, *player_id)
+            .await?;
+    }
+    let _: () = pipeline.expire(usage_top_key(today), ttl, None).await?;
+    let _: () = pipeline.all().await?;
+
+    Ok(())
+}
+
+/// The first segment of a route's path (e.g. `songs` for `/songs/{id}`), which counts are grouped by.
+fn route_group(matched_path: &str) -> Option<&str> {
+    matched_path
+        .strip_prefix("/api")
+        .unwrap_or(matched_path)
+        .split('/')
+        .find(|segment| !segment.is_empty())
+}
+
+/// Middleware counting API requests made with a valid token, by player and route group.
+///
+/// Only the token's signature is checked here. Whether its session is still active is up to the route.
+pub async fn count_usage(
+    State(jwt_keys): State<Keys>,
+    State(usage): State<UsageCounter>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let group = request
+        .extensions()
+        .get::<MatchedPath>()
+        .and_then(|path| route_group(path.as_str()));
+    let bearer = request.headers().typed_get::<Authorization<Bearer>>();
+
+    if let (Some(group), Some(bearer)) = (group, bearer) {
+        if let Ok(token_data) =
+            decode::<Claims>(bearer.token(), &jwt_keys.decoding, &Validation::default())
+        {
+            usage.record(token_data.claims.profile.id, group);
+        }
+    }
+
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn route_groups() {
+        assert_eq!(route_group("/songs/{id}"), Some("songs"));
+        assert_eq!(route_group("/api/players/{id}/favorites"), Some("players"));
+        assert_eq!(route_group("/stats"), Some("stats"));
+        assert_eq!(route_group("/"), None);
+    }
+
+    #[test]
+    fn restored_counts_are_merged() {
+        let usage = UsageCounter::default();
+        usage.record(1, "songs");
+        let pending = usage.take();
+        usage.record(1, "songs");
+        usage.record(2, "scores");
+        usage.restore(pending);
+
+        let counts = usage.take();
+        assert_eq!(counts.get(&(1, "songs".to_owned())), Some(&2));
+        assert_eq!(counts.get(&(2, "scores".to_owned())), Some(&1));
+    }
+}
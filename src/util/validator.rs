@@ -1,7 +1,7 @@
 use axum::{
     extract::{
-        rejection::{FormRejection, QueryRejection},
-        Form, FromRequest, FromRequestParts, Query, Request,
+        rejection::{FormRejection, JsonRejection, QueryRejection},
+        Form, FromRequest, FromRequestParts, Json, Query, Request,
     },
     http::request::Parts,
 };
@@ -53,3 +53,24 @@ where
         Ok(Self(value))
     }
 }
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ValidatedJson<T>(pub T);
+
+impl<T, S> FromRequest<S> for ValidatedJson<T>
+where
+    T: DeserializeOwned + Validate,
+    S: Send + Sync,
+    Json<T>: FromRequest<S, Rejection = JsonRejection>,
+{
+    type Rejection = RouteError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let Json(value) = Json::<T>::from_request(req, state).await?;
+        value.validate().map_err(|e| {
+            let message = format!("JSON validation error: [{e}]").replace('\n', ", ");
+            RouteError::new_bad_request().set_public_error_message(&message)
+        })?;
+        Ok(Self(value))
+    }
+}
@@ -0,0 +1,113 @@
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use utoipa::ToSchema;
+
+/// Version of the Wavebreaker client mod.
+///
+/// Parsing is tolerant of how the mod (and people) write versions:
+/// a leading `v` is ignored, missing minor/patch parts count as 0, and pre-release/build suffixes are ignored.
+///
+/// **Example:** "v1.2" -> 1.2.0, "1.2.3-beta+abc" -> 1.2.3
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ToSchema)]
+#[schema(value_type = String, example = "1.2.3")]
+pub struct ClientVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Invalid client version: {0}")]
+pub struct InvalidClientVersion(String);
+
+impl FromStr for ClientVersion {
+    type Err = InvalidClientVersion;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidClientVersion(s.to_owned());
+
+        let trimmed = s.trim();
+        let trimmed = trimmed.strip_prefix(['v', 'V']).unwrap_or(trimmed);
+        let core = trimmed.split(['-', '+']).next().unwrap_or_default();
+
+        let mut parts = core.split('.');
+        let mut next_part = |required: bool| match parts.next() {
+            Some(part) => part.parse::<u32>().map_err(|_| invalid()),
+            None if required => Err(invalid()),
+            None => Ok(0),
+        };
+
+        let version = Self {
+            major: next_part(true)?,
+            minor: next_part(false)?,
+            patch: next_part(false)?,
+        };
+        if parts.next().is_some() {
+            return Err(invalid());
+        }
+
+        Ok(version)
+    }
+}
+
+impl fmt::Display for ClientVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+impl Serialize for ClientVersion {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for ClientVersion {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let version = String::deserialize(deserializer)?;
+        version.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(major: u32, minor: u32, patch: u32) -> ClientVersion {
+        ClientVersion {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    #[test]
+    fn parse_full_version() {
+        assert_eq!("1.2.3".parse::<ClientVersion>().unwrap(), version(1, 2, 3));
+    }
+
+    #[test]
+    fn parse_tolerant_versions() {
+        assert_eq!("v1.2".parse::<ClientVersion>().unwrap(), version(1, 2, 0));
+        assert_eq!("2".parse::<ClientVersion>().unwrap(), version(2, 0, 0));
+        assert_eq!(
+            " 1.2.3-beta+abc ".parse::<ClientVersion>().unwrap(),
+            version(1, 2, 3)
+        );
+    }
+
+    #[test]
+    fn reject_invalid_versions() {
+        assert!("".parse::<ClientVersion>().is_err());
+        assert!("one.two".parse::<ClientVersion>().is_err());
+        assert!("1.2.3.4".parse::<ClientVersion>().is_err());
+        assert!("1..3".parse::<ClientVersion>().is_err());
+    }
+
+    #[test]
+    fn versions_are_ordered() {
+        assert!(version(1, 2, 3) < version(1, 10, 0));
+        assert!(version(2, 0, 0) > version(1, 99, 99));
+    }
+}
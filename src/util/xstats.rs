@@ -0,0 +1,49 @@
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use super::game_types::Character;
+
+/// The extended stats of a score, decoded according to the character they were played with.
+///
+/// No character's layout has been confirmed against `xstats` captured from a real client yet,
+/// so every array is kept as it is in `Raw` for now. A named variant for a character is only added
+/// once a capture confirms its layout, since clients will build on its field names.
+#[derive(Serialize, ToSchema, Debug, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum DecodedXstats {
+    /// Characters without a confirmed layout, or arrays that don't match it
+    Raw { values: Vec<Option<i32>> },
+}
+
+impl DecodedXstats {
+    /// Decodes the `xstats` of a score played with `character`. Never fails, see `Raw`.
+    #[must_use]
+    pub fn decode(_character: Character, xstats: &[Option<i32>]) -> Self {
+        Self::Raw {
+            values: xstats.to_vec(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unconfirmed_layouts_stay_raw() {
+        assert_eq!(
+            DecodedXstats::decode(Character::NinjaMono, &[Some(120), Some(3)]),
+            DecodedXstats::Raw {
+                values: vec![Some(120), Some(3)]
+            }
+        );
+        assert_eq!(
+            serde_json::to_value(DecodedXstats::decode(
+                Character::PointmanElite,
+                &[Some(80), None]
+            ))
+            .unwrap(),
+            serde_json::json!({ "kind": "raw", "values": [80, null] })
+        );
+    }
+}